@@ -0,0 +1,13 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Samples the input of a FullscreenPass, see FullscreenPass::blit.
+
+layout (location = 0) in vec2 inUv;
+layout (location = 0) out vec4 outColor;
+
+layout (set = 0, binding = 0) uniform sampler2D inputTexture;
+
+void main() {
+   outColor = texture(inputTexture, inUv);
+}
@@ -0,0 +1,11 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Fullscreen triangle, drawn with 3 vertices and no vertex buffer, see PipelineInfo::fullscreen.
+
+layout (location = 0) out vec2 outUv;
+
+void main() {
+   outUv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+   gl_Position = vec4(outUv * 2.0 - 1.0, 0.0, 1.0);
+}
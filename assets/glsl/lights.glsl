@@ -0,0 +1,56 @@
+#ifndef LIGHTS_GLSL
+#define LIGHTS_GLSL
+
+// Lights of sol::scene::LightBuffer. Define LIGHTS_SET (and optionally LIGHTS_BINDING) before
+// including.
+#ifndef LIGHTS_BINDING
+#define LIGHTS_BINDING 0
+#endif
+
+#define LIGHT_DIRECTIONAL 0u
+#define LIGHT_POINT 1u
+#define LIGHT_SPOT 2u
+
+struct Light
+{
+    vec4 position; // xyz, range (0 for infinite)
+    vec4 direction;
+    vec4 color; // premultiplied by the intensity
+    uint kind;
+    float cos_inner_angle;
+    float cos_outer_angle;
+    uint padding;
+};
+
+layout(set = LIGHTS_SET, binding = LIGHTS_BINDING, std430) readonly buffer Lights {
+    uint light_count;
+    uint light_padding0;
+    uint light_padding1;
+    uint light_padding2;
+    Light lights[];
+};
+
+// Direction from `position` to the light, and the light radiance arriving there (without
+// shadowing), following the KHR_lights_punctual falloff.
+vec3 light_incoming(Light light, vec3 position, out vec3 to_light)
+{
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        to_light = -light.direction.xyz;
+        return light.color.rgb;
+    }
+    vec3 offset = light.position.xyz - position;
+    float distance2 = max(dot(offset, offset), 1e-8);
+    to_light = offset * inversesqrt(distance2);
+    float attenuation = 1.0 / distance2;
+    float range = light.position.w;
+    if (range > 0.0) {
+        float ratio = distance2 / (range * range);
+        attenuation *= clamp(1.0 - ratio * ratio, 0.0, 1.0);
+    }
+    if (light.kind == LIGHT_SPOT) {
+        float cos_angle = dot(light.direction.xyz, -to_light);
+        attenuation *= smoothstep(light.cos_outer_angle, light.cos_inner_angle, cos_angle);
+    }
+    return light.color.rgb * attenuation;
+}
+#endif
@@ -0,0 +1,29 @@
+#ifndef OBJECT_TABLE_GLSL
+#define OBJECT_TABLE_GLSL
+
+// Object index convention, see sol::scene::ObjectTable. Define OBJECT_TABLE_SET (and optionally
+// OBJECT_TABLE_BINDING) before including.
+#ifndef OBJECT_TABLE_BINDING
+#define OBJECT_TABLE_BINDING 0
+#endif
+
+#define NO_MATERIAL 0xffffffffu
+
+struct ObjectData
+{
+    mat4 transform;
+    mat4 normal_transform;
+    uint material_index;
+    uint padding0;
+    uint padding1;
+    uint padding2;
+};
+
+layout(push_constant) uniform ObjectIndex { uint object_index; };
+
+layout(set = OBJECT_TABLE_SET, binding = OBJECT_TABLE_BINDING, std430) readonly buffer ObjectTable {
+    ObjectData objects[];
+};
+
+ObjectData current_object() { return objects[object_index]; }
+#endif
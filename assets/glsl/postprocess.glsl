@@ -31,6 +31,11 @@ vec3 ACESFilm( vec3 x ) {
     return clamp( ( x * ( a * x + b ) ) / ( x * ( c * x + d ) + e ), vec3(0), vec3(1) );
 }
 
+vec3 tonemapReinhard( in vec3 color )
+{
+	return color / (1.0 + color);
+}
+
 vec3 exposure(vec3 color, float fstop) {
    return color * pow(2.0,fstop);
 }
@@ -0,0 +1,42 @@
+#ifndef SKINNING_GLSL
+#define SKINNING_GLSL
+
+// Skinning with sol::scene::JointBuffer matrices and MeshSkin vertices. Define SKINNING_SET (and
+// optionally SKINNING_JOINTS_BINDING and SKINNING_VERTICES_BINDING) before including.
+#ifndef SKINNING_JOINTS_BINDING
+#define SKINNING_JOINTS_BINDING 0
+#endif
+#ifndef SKINNING_VERTICES_BINDING
+#define SKINNING_VERTICES_BINDING 1
+#endif
+
+struct SkinVertex
+{
+    uvec4 joints;
+    vec4 weights;
+};
+
+layout(set = SKINNING_SET, binding = SKINNING_JOINTS_BINDING, std430) readonly buffer JointMatrices {
+    mat4 joint_matrices[];
+};
+
+layout(set = SKINNING_SET, binding = SKINNING_VERTICES_BINDING, std430) readonly buffer SkinVertices {
+    SkinVertex skin_vertices[];
+};
+
+// joint_offset: returned by JointBuffer::push for the mesh skeleton. vertex_index: index in the
+// mesh vertices, i.e. gl_VertexIndex plus PrimitiveSection::get_vertex_offset.
+mat4 skin_matrix(uint joint_offset, uint vertex_index)
+{
+    SkinVertex skin = skin_vertices[vertex_index];
+    float total = dot(skin.weights, vec4(1.0));
+    if (total <= 0.0) {
+        return mat4(1.0);
+    }
+    mat4 matrix = mat4(0.0);
+    for (int i = 0; i < 4; ++i) {
+        matrix += joint_matrices[joint_offset + skin.joints[i]] * (skin.weights[i] / total);
+    }
+    return matrix;
+}
+#endif
@@ -14,6 +14,8 @@ pub struct AppData {
     pub pipeline: sol::Pipeline,
     pub per_frame: Vec<PerFrameData>,
     pub manip: scene::CameraManip,
+    // The cube is drawn in an HDR target, blitted to the present image.
+    pub hdr_target: sol::RenderTarget,
 }
 
 pub fn setup(app: &mut sol::App) -> AppData {
@@ -25,8 +27,11 @@ pub fn setup(app: &mut sol::App) -> AppData {
             .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
             .gpu_only(),
         &util::colored_cube_vertices(),
-    );
-    let texture = sol::Texture2d::new(context.clone(), util::find_asset("textures/face.png").unwrap());
+    )
+    .unwrap();
+    let texture =
+        sol::Texture2d::new(context.clone(), util::find_asset("textures/face.png").unwrap())
+            .unwrap();
 
     let mut desc_set_layout = sol::DescriptorSetLayout::new(
         context.clone(),
@@ -46,15 +51,28 @@ pub fn setup(app: &mut sol::App) -> AppData {
         context.clone(),
         sol::PipelineLayoutInfo::default().desc_set_layout(desc_set_layout.handle()),
     );
+    let extent = app.window.get_extent();
+    let mut hdr_target = sol::RenderTarget::new(
+        context.clone(),
+        sol::RenderTargetInfo::default()
+            .name("HdrColor")
+            .extent(extent.width, extent.height)
+            .color_format(context.find_hdr_color_format())
+            .samples(4)
+            .frames_count(app.renderer.get_frames_count()),
+    )
+    .unwrap();
+    hdr_target.set_clear_color([13.0 / 255.0, 17.0 / 255.0, 23.0 / 255.0, 1.0]);
     let pipeline = sol::Pipeline::new(
         context.clone(),
         sol::PipelineInfo::default()
             .layout(pipeline_layout.handle())
-            .render_pass_info(app.renderer.swapchain.get_transient_render_pass_info())
+            .render_pass_info(hdr_target.get_transient_render_pass_info())
             .vert(util::find_asset("glsl/cube.vert").unwrap())
             .frag(util::find_asset("glsl/cube.frag").unwrap())
             .vertex_type::<util::BasicVertex>(),
-    );
+    )
+    .unwrap();
 
     let mut camera = scene::Camera::new(app.window.get_size());
     camera.look_at(Vec3::splat(5.0), Vec3::ZERO, -Vec3::Y);
@@ -68,7 +86,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
                 .cpu_to_gpu(),
             &vp.to_cols_array(),
-        );
+        )
+        .unwrap();
         let desc_set = desc_set_layout.get_or_create(
             sol::DescriptorSetInfo::default()
                 .buffer(0, ubo.get_descriptor_info())
@@ -88,15 +107,24 @@ pub fn setup(app: &mut sol::App) -> AppData {
             camera,
             input: scene::CameraInput::default(),
         },
+        hdr_target,
     }
 }
 
-pub fn window_event(_: &mut sol::App, data: &mut AppData, event: &winit::event::WindowEvent) {
+pub fn window_event(app: &mut sol::App, data: &mut AppData, event: &winit::event::WindowEvent) {
     data.manip.update(&event);
+    if let winit::event::WindowEvent::Resized(_) = event {
+        data.hdr_target.resize(app.window.get_extent()).unwrap();
+    }
 }
 
 pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRenderError> {
-    let (image_aquired_semaphore, cmd) = app.renderer.begin_frame_default()?;
+    let (image_aquired_semaphore, frame_index) = app.renderer.acquire_next_image()?;
+    let cmd = app.renderer.begin_command_buffer();
+    data.hdr_target.cmd_begin(cmd, frame_index);
+    app.renderer
+        .context
+        .cmd_set_full_viewport(cmd, &data.hdr_target);
     let ref camera = data.manip.camera;
     let vp = camera.perspective_matrix() * camera.view_matrix();
     data.per_frame[app.renderer.active_frame_index]
@@ -105,8 +133,6 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
     let descriptor_sets = [data.per_frame[app.renderer.active_frame_index].desc_set.handle()];
     let device = app.renderer.context.device();
     unsafe {
-        device.cmd_set_scissor(cmd, 0, &[app.window.get_rect()]);
-        device.cmd_set_viewport(cmd, 0, &[app.window.get_viewport()]);
         device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, data.pipeline.handle());
         device.cmd_bind_descriptor_sets(
             cmd,
@@ -119,18 +145,19 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
         device.cmd_bind_vertex_buffers(cmd, 0, &[data.vertex_buffer.handle()], &[0]);
         device.cmd_draw(cmd, data.vertex_buffer.get_element_count(), 1, 0, 1);
     }
-    app.renderer.end_frame_default(image_aquired_semaphore, cmd)
+    data.hdr_target.cmd_end(cmd, frame_index);
+    app.renderer
+        .cmd_blit_to_present_image(cmd, data.hdr_target.get_color_mut(frame_index));
+    app.renderer.end_command_buffer(cmd);
+    app.renderer
+        .submit_and_present(cmd, image_aquired_semaphore)
 }
 
 pub fn prepare() -> sol::AppSettings {
     sol::AppSettings {
         name: "Cube App".to_string(),
         resolution: [900, 600],
-        render: sol::RendererSettings {
-            samples: 8,
-            clear_color: vec4(13.0 / 255.0, 17.0 / 255.0, 23.0 / 255.0, 1.0),
-            ..Default::default()
-        },
+        ..Default::default()
     }
 }
 
@@ -1,6 +1,7 @@
 //#![windows_subsystem = "windows"]
 use sol::prelude::*;
 use sol::scene;
+use winit::event::VirtualKeyCode;
 
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
@@ -16,11 +17,15 @@ pub struct PerFrameData {
 
 pub struct AppData {
     pub scene: scene::Scene,
+    pub indirect_buffers: Vec<sol::Buffer>,
     pub pipeline: sol::Pipeline,
     pub desc_set_layout: sol::DescriptorSetLayout,
     pub pipeline_layout: sol::PipelineLayout,
     pub per_frame: Vec<PerFrameData>,
     pub manip: scene::CameraManip,
+    pub recorder: sol::StaticSceneRecorder,
+    pub debug_pass: scene::DebugViewPass,
+    pub debug_view: Option<scene::DebugView>,
 }
 
 pub fn setup(app: &mut sol::App) -> AppData {
@@ -28,7 +33,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
     let scene = scene::load_scene(
         context.clone(),
         &sol::util::find_asset("models/Duck.gltf").unwrap(),
-    );
+    )
+    .unwrap();
 
     let mut desc_set_layout = sol::DescriptorSetLayout::new(
         context.clone(),
@@ -51,6 +57,12 @@ pub fn setup(app: &mut sol::App) -> AppData {
             .frag(sol::util::find_asset("glsl/model.frag").unwrap())
             .front_face(vk::FrontFace::CLOCKWISE)
             .vertex_type::<scene::ModelVertex>(),
+    )
+    .unwrap();
+    let debug_pass = scene::DebugViewPass::new(
+        context.clone(),
+        app.renderer.get_renderpass(),
+        app.renderer.swapchain.get_sample_count(),
     );
 
     let mut camera = scene::Camera::new(app.window.get_size());
@@ -71,13 +83,19 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
                 .cpu_to_gpu(),
             &[scene_data],
-        );
+        )
+        .unwrap();
         let desc_set = desc_set_layout
             .get_or_create(sol::DescriptorSetInfo::default().buffer(0, ubo.get_descriptor_info()));
         per_frame.push(PerFrameData { ubo, desc_set });
     }
+    let indirect_buffers = scene.create_indirect_buffers().unwrap();
+    app.picking = Some(
+        scene::PickingPass::new(context.clone(), app.renderer.swapchain.get_extent()).unwrap(),
+    );
     AppData {
         scene,
+        indirect_buffers,
         pipeline,
         desc_set_layout,
         pipeline_layout,
@@ -86,16 +104,77 @@ pub fn setup(app: &mut sol::App) -> AppData {
             camera,
             input: scene::CameraInput::default(),
         },
+        recorder: sol::StaticSceneRecorder::new(context.clone(), app.renderer.get_frames_count()),
+        debug_pass,
+        debug_view: None,
     }
 }
 
 pub fn window_event(_: &mut sol::App, data: &mut AppData, event: &winit::event::WindowEvent) {
-    data.manip.update(&event);
+    data.manip.update(event);
+    if let winit::event::WindowEvent::Resized(_) = event {
+        // Recorded viewport and scissor depend on the swapchain extent.
+        data.recorder.invalidate();
+    }
+}
+
+pub fn update(app: &mut sol::App, data: &mut AppData, time: sol::FrameTime) {
+    // V cycles through the debug views, then back to the regular shading.
+    if app.input.is_key_pressed(VirtualKeyCode::V) {
+        data.debug_view = match data.debug_view {
+            None => Some(scene::DebugView::ObjectNormal),
+            Some(scene::DebugView::Albedo) => None,
+            Some(view) => Some(view.next()),
+        };
+        data.recorder.invalidate();
+    }
+    // F toggles the fly camera, moved with WASD, E and Q.
+    if app.input.is_key_pressed(VirtualKeyCode::F) {
+        let camera = &mut data.manip.camera;
+        camera.set_mode(match camera.mode() {
+            scene::CameraMode::Examine => scene::CameraMode::Fly,
+            _ => scene::CameraMode::Examine,
+        });
+    }
+    // P prints the scene instance under the cursor.
+    if app.input.is_key_pressed(VirtualKeyCode::P) {
+        if let Some(pos) = app.input.mouse_pos() {
+            match app.pick(pos.x, pos.y) {
+                Some(instance) => println!("Picked instance {}.", instance.0),
+                None => println!("Nothing picked."),
+            }
+        }
+    }
+    // C moves to the next camera of the glTF file.
+    if app.input.is_key_pressed(VirtualKeyCode::C) && !data.scene.cameras.is_empty() {
+        let next = data
+            .scene
+            .selected_camera()
+            .map_or(0, |index| (index + 1) % data.scene.cameras.len());
+        data.scene.select_camera(next);
+        let target = data.scene.cameras[next].camera;
+        data.manip.camera.set_target_camera(&target, 1.0);
+    }
+
+    #[cfg(feature = "gamepad")]
+    data.manip.update_gamepad(app.gamepad.state());
+    data.manip.tick(time.delta_seconds());
 }
 
 pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRenderError> {
-    let (image_aquired_semaphore, cmd) = app.renderer.begin_frame_default()?;
-    let ref camera = data.manip.camera;
+    let (image_aquired_semaphore, frame_index) = app.renderer.acquire_next_image()?;
+    let cmd = app.renderer.begin_command_buffer();
+    let camera = &data.manip.camera;
+    let view_projection = camera.perspective_matrix() * camera.view_matrix();
+    // Instance ids under the cursor for App::pick, drawn outside of the main pass.
+    if let Some(picking) = &mut app.picking {
+        picking.cmd_draw(cmd, &data.scene, view_projection);
+    }
+    app.renderer.begin_renderpass_with_contents(
+        cmd,
+        app.renderer.swapchain.get_extent(),
+        vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+    );
     //TODO: move mesh transform in push constant?
     let scene_data = SceneData {
         mvp: camera.perspective_matrix() * camera.view_matrix() * data.scene.meshes[0].transform,
@@ -103,16 +182,31 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
             .inverse()
             .transpose(),
     };
-    data.per_frame[app.renderer.active_frame_index]
-        .ubo
-        .update(&[scene_data]);
+    data.per_frame[frame_index].ubo.update(&[scene_data]);
+
+    // Draws never change: they are recorded once per frame and replayed afterwards.
+    // The debug views push the camera matrices, so they are re-recorded every frame.
+    if data.debug_view.is_some() {
+        data.recorder.invalidate();
+    }
+    let scene = &data.scene;
+    let indirect_buffers = &data.indirect_buffers;
+    let pipeline = data.pipeline.handle();
     let pipeline_layout = data.pipeline_layout.handle();
-    let descriptor_sets = [data.per_frame[app.renderer.active_frame_index].desc_set.handle()];
-    let device = app.renderer.context.device();
-    unsafe {
-        device.cmd_set_scissor(cmd, 0, &[app.window.get_rect()]);
-        device.cmd_set_viewport(cmd, 0, &[app.window.get_viewport()]);
-        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, data.pipeline.handle());
+    let descriptor_sets = [data.per_frame[frame_index].desc_set.handle()];
+    let renderer = &app.renderer;
+    let debug_pass = &data.debug_pass;
+    let debug_view = data.debug_view;
+    let device = renderer.context.device();
+    data.recorder.cmd_execute(cmd, frame_index, renderer.get_renderpass(), |cmd| unsafe {
+        // Secondary command buffers draw the background themselves.
+        renderer.cmd_draw_background(cmd, renderer.swapchain.get_extent());
+        renderer.context.cmd_set_full_viewport(cmd, &*renderer.swapchain);
+        if let Some(view) = debug_view {
+            debug_pass.cmd_draw(cmd, scene, view, view_projection);
+            return;
+        }
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
         device.cmd_bind_descriptor_sets(
             cmd,
             vk::PipelineBindPoint::GRAPHICS,
@@ -121,8 +215,12 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
             descriptor_sets.as_slice(),
             &[],
         );
-    }
-    data.scene.meshes.iter().for_each(|mesh| mesh.cmd_draw(cmd));
+        scene
+            .meshes
+            .iter()
+            .zip(indirect_buffers)
+            .for_each(|(mesh, indirect_buffer)| mesh.cmd_draw_indirect(cmd, indirect_buffer));
+    });
     app.renderer.end_frame_default(image_aquired_semaphore, cmd)
 }
 
@@ -133,14 +231,20 @@ pub fn prepare() -> sol::AppSettings {
         render: sol::RendererSettings {
             samples: 8,
             clear_color: Vec4::splat(0.15),
+            background: sol::Background::VerticalGradient {
+                top: vec4(0.35, 0.38, 0.42, 1.0),
+                bottom: vec4(0.08, 0.08, 0.1, 1.0),
+            },
             ..Default::default()
         },
+        ..Default::default()
     }
 }
 
 pub fn main() {
     sol::App::build(setup)
         .prepare(prepare)
+        .update(update)
         .render(render)
         .window_event(window_event)
         .run();
@@ -4,6 +4,16 @@ use sol::ray;
 use sol::scene;
 use winit::event::WindowEvent;
 
+// Scene descriptors of the pass set.
+fn scene_bindings() -> ray::SceneBindings {
+    ray::SceneBindings::default()
+        .tlas(0)
+        .instances(2)
+        .vertices(3)
+        .indices(4)
+        .materials(5)
+}
+
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
 struct SceneUniforms {
@@ -46,6 +56,7 @@ pub struct AppData {
     pub image_target: sol::Image2d,
     pub sbt: ray::ShaderBindingTable,
     pub scene_description: ray::SceneDescription,
+    pub debug_view: scene::DebugView,
 }
 
 fn create_image_target(context: &Arc<sol::Context>, window: &sol::Window) -> sol::Image2d {
@@ -67,6 +78,7 @@ fn create_image_target(context: &Arc<sol::Context>, window: &sol::Window) -> sol
         1,
         "TargetRT"
     )
+    .unwrap()
 }
 
 pub fn setup(app: &mut sol::App) -> AppData {
@@ -74,7 +86,9 @@ pub fn setup(app: &mut sol::App) -> AppData {
     let scene = scene::load_scene(
         context.clone(),
         &sol::util::find_asset("models/Duck.gltf").unwrap(),
-    );
+    )
+    .unwrap();
+    let scene_description = ray::SceneDescription::from_scene(context.clone(), &scene).unwrap();
     let mut camera = scene::Camera::new(app.window.get_size());
     camera.look_at(Vec3::splat(5.0), Vec3::ZERO, -Vec3::Y);
 
@@ -90,12 +104,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
     );
     let layout_pass = sol::DescriptorSetLayout::new(
         context.clone(),
-        sol::DescriptorSetLayoutInfo::default()
-            .binding(
-                0,
-                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
-                vk::ShaderStageFlags::RAYGEN_KHR,
-            )
+        scene_description
+            .descriptor_set_layout_info(&scene_bindings())
             .binding(
                 1,
                 vk::DescriptorType::STORAGE_IMAGE,
@@ -106,7 +116,13 @@ pub fn setup(app: &mut sol::App) -> AppData {
     let pipeline_layout = sol::PipelineLayout::new(
         context.clone(),
         sol::PipelineLayoutInfo::default()
-            .desc_set_layouts(&[layout_scene.handle(), layout_pass.handle()]),
+            .desc_set_layouts(&[layout_scene.handle(), layout_pass.handle()])
+            .push_constant_range(
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
+                    .size(size_of::<u32>() as u32)
+                    .build(),
+            ),
     );
 
     let pipeline = ray::Pipeline::new(
@@ -126,7 +142,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 vk::ShaderStageFlags::CLOSEST_HIT_KHR,
             )
             .name("debug_mat".to_string()),
-    );
+    )
+    .unwrap();
 
     for _ in 0..app.renderer.get_frames_count() {
         let uniforms = SceneUniforms::from(
@@ -143,14 +160,13 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
                 .cpu_to_gpu(),
             &[uniforms],
-        );
+        )
+        .unwrap();
         let desc_set = layout_scene
             .get_or_create(sol::DescriptorSetInfo::default().buffer(0, ubo.get_descriptor_info()));
         per_frame.push(PerFrameData { ubo, desc_set });
     }
 
-    let scene_description = ray::SceneDescription::from_scene(context.clone(), &scene);
-
     let sbt = ray::ShaderBindingTable::new(
         context.clone(),
         pipeline.handle(),
@@ -158,7 +174,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
             .raygen(0)
             .miss(1)
             .hitgroup(2),
-    );
+    )
+    .unwrap();
 
     let image_target = create_image_target(&context, &app.window);
 
@@ -176,15 +193,22 @@ pub fn setup(app: &mut sol::App) -> AppData {
         image_target,
         sbt,
         scene_description,
+        debug_view: scene::DebugView::ObjectNormal,
     }
 }
 
 pub fn window_event(app: &mut sol::App, data: &mut AppData, event: &WindowEvent) {
-    data.manip.update(&event);
+    data.manip.update(event);
     match event {
         WindowEvent::Resized(_) => {
             data.image_target = create_image_target(&app.renderer.context, &app.window);
-            data.layout_pass.reset_pool();
+        }
+        WindowEvent::KeyboardInput { input, .. }
+            if input.state == winit::event::ElementState::Pressed
+                && input.virtual_keycode == Some(winit::event::VirtualKeyCode::V) =>
+        {
+            data.debug_view = data.debug_view.next();
+            println!("Debug view: {}", data.debug_view.name());
         }
         _ => {}
     }
@@ -218,15 +242,13 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
         .image_layout(vk::ImageLayout::GENERAL)
         .build();
     let desc_pass = data.layout_pass.get_or_create(
-        sol::DescriptorSetInfo::default()
-            .accel_struct(0, data.scene_description.tlas().handle())
+        data.scene_description
+            .descriptor_set_info(&scene_bindings())
             .image(1, image_info),
     );
 
     let device = app.renderer.context.device();
     unsafe {
-        device.cmd_set_scissor(cmd, 0, &[app.window.get_rect()]);
-        device.cmd_set_viewport(cmd, 0, &[app.window.get_viewport()]);
         device.cmd_bind_pipeline(
             cmd,
             vk::PipelineBindPoint::RAY_TRACING_KHR,
@@ -240,6 +262,13 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
             &[desc_scene, desc_pass.handle()],
             &[],
         );
+        device.cmd_push_constants(
+            cmd,
+            data.pipeline_layout.handle(),
+            vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+            0,
+            &data.debug_view.index().to_ne_bytes(),
+        );
     }
     data.sbt.cmd_trace_rays(cmd, app.window.get_extent_3d());
 
@@ -262,6 +291,7 @@ pub fn prepare() -> sol::AppSettings {
             extensions: vec![vk::KhrGetPhysicalDeviceProperties2Fn::name()],
             ..Default::default()
         },
+        ..Default::default()
     }
 }
 
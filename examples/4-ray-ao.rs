@@ -2,7 +2,16 @@
 use sol::prelude::*;
 use sol::ray;
 use sol::scene;
-use winit::event::WindowEvent;
+use winit::event::{ElementState, MouseButton, WindowEvent};
+
+// Scene descriptors of the pass set.
+fn scene_bindings() -> ray::SceneBindings {
+    ray::SceneBindings::default()
+        .tlas(0)
+        .instances(3)
+        .vertices(4)
+        .indices(5)
+}
 
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
@@ -50,12 +59,19 @@ pub struct AppData {
     pub accumulation_start_frame: u32,
     pub render_target: sol::Image2d,
     pub tex_blue_noise: sol::Texture2d,
+    // Primary hit distances resolved to a depth buffer, for compositing rasterized elements.
+    pub hit_distance: sol::Image2d,
+    pub resolved_depth: sol::Image2d,
+    pub depth_resolve: ray::DepthResolvePass,
+    // Moves the first mesh, drawn over the frame.
+    pub gizmo: scene::Gizmo,
+    pub debug_draw: sol::DebugDraw,
 }
 
 fn create_image_target(context: &Arc<sol::Context>, window: &sol::Window) -> sol::Image2d {
     let image_info = vk::ImageCreateInfo::builder()
         .image_type(vk::ImageType::TYPE_2D)
-        .format(vk::Format::R32G32B32A32_SFLOAT)
+        .format(context.find_hdr_storage_format())
         .extent(window.get_extent_3d())
         .mip_levels(1)
         .array_layers(1)
@@ -71,6 +87,7 @@ fn create_image_target(context: &Arc<sol::Context>, window: &sol::Window) -> sol
         1,
         "TargetRT"
     )
+    .unwrap()
 }
 
 pub fn setup(app: &mut sol::App) -> AppData {
@@ -78,13 +95,19 @@ pub fn setup(app: &mut sol::App) -> AppData {
     let mut scene = scene::load_scene(
         context.clone(),
         &sol::util::find_asset("models/ToyCar.glb").unwrap(),
-    );
+    )
+    .unwrap();
     // Override transforms...
     for mesh in &mut scene.meshes {
-        mesh.transform = glam::Mat4::from_scale(Vec3::splat(0.01))
+        mesh.transform = Mat4::from_scale(Vec3::splat(0.01))
             * Mat4::from_rotation_x(std::f32::consts::FRAC_PI_2);
     }
-    let scene_description = ray::SceneDescription::from_scene(context.clone(), &scene);
+    let scene_description = ray::SceneDescription::from_scene_with_info(
+        context.clone(),
+        &scene,
+        ray::AccelerationBuildInfo::default().allow_update(),
+    )
+    .unwrap();
 
     let mut camera = scene::Camera::new(app.window.get_size());
     camera.look_at(vec3(4.0, 1.0, 4.0), vec3(0.0, 0.5, 0.0), -Vec3::Y);
@@ -92,22 +115,19 @@ pub fn setup(app: &mut sol::App) -> AppData {
     let mut per_frame = Vec::<PerFrameData>::new();
 
     let mut layout_scene = sol::DescriptorSetLayout::new(
-        context.clone(),
-        sol::DescriptorSetLayoutInfo::default().binding(
-            0,
-            vk::DescriptorType::UNIFORM_BUFFER,
-            vk::ShaderStageFlags::ALL,
-        ),
-    );
-    let instance_count = scene_description.get_instances_buffer().get_element_count();
-    let layout_pass = sol::DescriptorSetLayout::new(
         context.clone(),
         sol::DescriptorSetLayoutInfo::default()
             .binding(
                 0,
-                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
-                vk::ShaderStageFlags::RAYGEN_KHR,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::ShaderStageFlags::ALL,
             )
+            .name("Scene".to_string()),
+    );
+    let layout_pass = sol::DescriptorSetLayout::new(
+        context.clone(),
+        scene_description
+            .descriptor_set_layout_info(&scene_bindings())
             .binding(
                 1,
                 vk::DescriptorType::STORAGE_IMAGE,
@@ -119,22 +139,11 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 vk::ShaderStageFlags::ALL,
             )
             .binding(
-                3,
-                vk::DescriptorType::STORAGE_BUFFER,
-                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-            )
-            .bindings(
-                4,
-                vk::DescriptorType::STORAGE_BUFFER,
-                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                instance_count,
+                6,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::RAYGEN_KHR,
             )
-            .bindings(
-                5,
-                vk::DescriptorType::STORAGE_BUFFER,
-                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                instance_count,
-            ),
+            .name("AO pass".to_string()),
     );
 
     let pipeline_layout = sol::PipelineLayout::new(
@@ -166,7 +175,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
             )
             //.specialization(&0u32, 0)
             .name("AO_mat".to_string()),
-    );
+    )
+    .unwrap();
 
     for _ in 0..app.renderer.get_frames_count() {
         let uniforms = SceneUniforms::from(
@@ -179,7 +189,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
                 .cpu_to_gpu(),
             &[uniforms],
-        );
+        )
+        .unwrap();
         let desc_set = layout_scene
             .get_or_create(sol::DescriptorSetInfo::default().buffer(0, ubo.get_descriptor_info()));
         per_frame.push(PerFrameData { ubo, desc_set });
@@ -192,13 +203,23 @@ pub fn setup(app: &mut sol::App) -> AppData {
             .raygen(0)
             .miss(1)
             .hitgroup(2),
-    );
+    )
+    .unwrap();
 
     let render_target = create_image_target(&context, &app.window);
+    let hit_distance =
+        ray::create_hit_distance_image(context, app.window.get_extent_3d()).unwrap();
+    let resolved_depth =
+        ray::create_resolved_depth_image(context, app.window.get_extent_3d()).unwrap();
+    let depth_resolve = ray::DepthResolvePass::new(context.clone(), &resolved_depth);
     let tex_blue_noise = sol::Texture2d::new(
         context.clone(),
         sol::util::find_asset("textures/HDR_RGBA_0.png").unwrap(),
-    );
+    )
+    .unwrap();
+    let mut gizmo = scene::Gizmo::default();
+    gizmo.attach(0, scene.meshes[0].transform);
+    let debug_draw = sol::DebugDraw::new(&app.renderer).unwrap();
 
     AppData {
         scene,
@@ -216,28 +237,53 @@ pub fn setup(app: &mut sol::App) -> AppData {
         accumulation_start_frame: 0,
         render_target,
         tex_blue_noise,
+        hit_distance,
+        resolved_depth,
+        depth_resolve,
+        gizmo,
+        debug_draw,
     }
 }
 
 pub fn window_event(app: &mut sol::App, data: &mut AppData, event: &WindowEvent) {
-    if data.manip.update(&event) {
+    // A click on the gizmo starts a drag instead of moving the camera.
+    let gizmo_pressed = data.gizmo.is_hovered()
+        && matches!(
+            event,
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            }
+        );
+    if data.gizmo.update(event, &data.manip.camera).is_some() {
+        data.gizmo.apply(&mut data.scene_description);
+        data.scene_description.update();
+        data.accumulation_start_frame = app.elapsed_ticks as u32;
+    }
+    if !gizmo_pressed && data.manip.update(event) {
         data.accumulation_start_frame = app.elapsed_ticks as u32;
     }
     match event {
         WindowEvent::Resized(_) => {
-            data.render_target = create_image_target(&app.renderer.context, &app.window);
+            let context = &app.renderer.context;
+            data.render_target = create_image_target(context, &app.window);
+            data.hit_distance =
+                ray::create_hit_distance_image(context, app.window.get_extent_3d()).unwrap();
+            data.resolved_depth =
+                ray::create_resolved_depth_image(context, app.window.get_extent_3d()).unwrap();
+            data.depth_resolve = ray::DepthResolvePass::new(context.clone(), &data.resolved_depth);
             data.accumulation_start_frame = app.elapsed_ticks as u32;
-            data.layout_pass.reset_pool();
         }
         _ => {}
     }
 }
 
-// pub fn update(app: &mut sol::App, data: &mut AppData) {
-//     let t = app.elapsed_time.as_secs_f32();
-//     let transform = glam::Mat4::from_scale(Vec3::splat(0.01))
+// pub fn update(app: &mut sol::App, data: &mut AppData, time: sol::FrameTime) {
+//     let t = time.elapsed_seconds();
+//     let transform = Mat4::from_scale(Vec3::splat(0.01))
 //         * Mat4::from_rotation_x(std::f32::consts::FRAC_PI_2)
-//         * glam::Mat4::from_translation(vec3(0.0, 40.0 * t.cos(), 0f32));
+//         * Mat4::from_translation(vec3(0.0, 40.0 * t.cos(), 0f32));
 //     data.scene_description.blas_transform(transform, 0);
 //     data.scene_description.blas_transform(transform, 2);
 //     data.scene_description.update();
@@ -253,7 +299,8 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
     )]);
 
     let cmd = app.renderer.begin_command_buffer();
-    let device = app.renderer.context.device();
+    let context = app.renderer.context.clone();
+    let device = context.device();
 
     if data.accumulation_start_frame == app.elapsed_ticks as u32 {
         unsafe {
@@ -267,33 +314,31 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
         }
     }
 
-    data.scene_description.tlas_regenerate(cmd);
+    app.renderer.begin_gpu_scope(cmd, "tlas refit");
+    data.scene_description.tlas_refit(cmd);
+    app.renderer.end_gpu_scope(cmd);
 
     data.render_target.transition_image_layout(
         cmd,
         vk::ImageLayout::UNDEFINED,
         vk::ImageLayout::GENERAL,
     );
+    data.hit_distance.transition_image_layout(
+        cmd,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::GENERAL,
+    );
 
     let desc_pass = data.layout_pass.get_or_create(
-        sol::DescriptorSetInfo::default()
-            .accel_struct(0, data.scene_description.tlas().handle())
+        data.scene_description
+            .descriptor_set_info(&scene_bindings())
             .image(1, data.render_target.get_descriptor_info())
             .image(2, data.tex_blue_noise.get_descriptor_info())
-            .buffer(
-                3,
-                data.scene_description
-                    .get_instances_buffer()
-                    .get_descriptor_info(),
-            )
-            .buffers(4, data.scene_description.get_vertex_descriptors().clone())
-            .buffers(5, data.scene_description.get_index_descriptors().clone()),
+            .image(6, data.hit_distance.get_descriptor_info()),
     );
 
     let descriptor_sets = vec!(data.per_frame[frame_index].desc_set.handle(), desc_pass.handle());
     unsafe {
-        device.cmd_set_scissor(cmd, 0, &[app.window.get_rect()]);
-        device.cmd_set_viewport(cmd, 0, &[app.window.get_viewport()]);
         device.cmd_bind_pipeline(
             cmd,
             vk::PipelineBindPoint::RAY_TRACING_KHR,
@@ -308,15 +353,40 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
             &[],
         );
     }
+    app.renderer.begin_gpu_scope(cmd, "trace rays");
     data.sbt.cmd_trace_rays(cmd, app.window.get_extent_3d());
+    app.renderer.end_gpu_scope(cmd);
+    // Storage image writes aren't seen by the crate wrappers, report them to the hazard tracker.
+    context.track_hazards(|tracker| {
+        for image in [&data.render_target, &data.hit_distance] {
+            tracker.write(
+                image.handle(),
+                image.get_name(),
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::AccessFlags::SHADER_WRITE,
+            );
+        }
+    });
+    data.depth_resolve.cmd_resolve(
+        cmd,
+        &data.hit_distance,
+        &mut data.resolved_depth,
+        data.manip.camera.perspective_matrix(),
+    );
 
     let present_image = app.renderer.swapchain.get_present_image(frame_index);
     data.render_target.cmd_blit_to(cmd, present_image, true);
-    present_image.transition_image_layout(
+    // Overlays (UI, stats) are drawn over the blitted image, the pass leaves it ready to present.
+    app.renderer.begin_overlay_pass(cmd);
+    let camera = &data.manip.camera;
+    data.debug_draw.gizmo(&data.gizmo, camera);
+    data.debug_draw.cmd_draw(
         cmd,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        vk::ImageLayout::PRESENT_SRC_KHR,
+        camera.perspective_matrix() * camera.view_matrix(),
+        &*app.renderer.swapchain,
+        &mut app.renderer.transient,
     );
+    app.renderer.end_overlay_pass(cmd);
     app.renderer.end_command_buffer(cmd);
     app.renderer.submit_and_present(cmd, semaphore)
 }
@@ -329,6 +399,7 @@ pub fn prepare() -> sol::AppSettings {
             extensions: vec![vk::KhrGetPhysicalDeviceProperties2Fn::name()],
             ..Default::default()
         },
+        ..Default::default()
     }
 }
 
@@ -4,6 +4,16 @@ use sol::ray;
 use sol::scene;
 use winit::event::WindowEvent;
 
+// Scene descriptors of the pass set.
+fn scene_bindings() -> ray::SceneBindings {
+    ray::SceneBindings::default()
+        .tlas(0)
+        .instances(3)
+        .vertices(4)
+        .indices(5)
+        .materials(6)
+}
+
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
 struct SceneUniforms {
@@ -31,6 +41,14 @@ impl SceneUniforms {
     }
 }
 
+// Push constants of pathtrace.rgen.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct TracePushConstants {
+    launch_offset: [u32; 2],
+    accumulation_start_frame: u32,
+}
+
 pub struct PerFrameData {
     pub ubo: sol::Buffer,
     pub desc_set: sol::DescriptorSet,
@@ -50,8 +68,12 @@ pub struct AppData {
     pub accumulation_start_frame: u32,
     pub accum_target: sol::Image2d,
     pub render_target: sol::Image2d,
+    // Tonemaps the accumulation, replacing the gamma corrected render_target.
+    pub post_process: sol::PostProcessChain,
 
     pub enable_sky: bool, //Temporary shader hack for sky/sun light
+    #[cfg(feature = "ui")]
+    pub ui: sol::ui::UiPass,
 }
 
 fn create_image_target(
@@ -77,13 +99,14 @@ fn create_image_target(
         1,
         "TargetRT"
     )
+    .unwrap()
 }
 
 fn build_pipeline_sbt(
     context: &Arc<sol::Context>,
     pipeline_layout: &sol::PipelineLayout,
     enable_sky: bool,
-) -> (ray::Pipeline, ray::ShaderBindingTable) {
+) -> sol::Result<(ray::Pipeline, ray::ShaderBindingTable)> {
     let pipeline = ray::Pipeline::new(
         context.clone(),
         ray::PipelineInfo::default()
@@ -102,7 +125,7 @@ fn build_pipeline_sbt(
             )
             .specialization(&[enable_sky as u32], 0)
             .name("AO_mat".to_string()),
-    );
+    )?;
     let sbt = ray::ShaderBindingTable::new(
         context.clone(),
         pipeline.handle(),
@@ -110,9 +133,9 @@ fn build_pipeline_sbt(
             .raygen(0)
             .miss(1)
             .hitgroup(2),
-    );
+    )?;
 
-    (pipeline, sbt)
+    Ok((pipeline, sbt))
 }
 
 pub fn setup(app: &mut sol::App) -> AppData {
@@ -122,8 +145,9 @@ pub fn setup(app: &mut sol::App) -> AppData {
         context.clone(),
         &sol::util::find_asset(&std::env::args().nth(index + 1).expect("no gltf file given"))
             .unwrap(),
-    );
-    let scene_description = ray::SceneDescription::from_scene(context.clone(), &scene);
+    )
+    .unwrap();
+    let scene_description = ray::SceneDescription::from_scene(context.clone(), &scene).unwrap();
 
     let camera = match scene.camera {
         Some(scene_camera) => {
@@ -144,15 +168,10 @@ pub fn setup(app: &mut sol::App) -> AppData {
             vk::ShaderStageFlags::ALL,
         ),
     );
-    let instance_count = scene_description.get_instances_buffer().get_element_count();
     let layout_pass = sol::DescriptorSetLayout::new(
         context.clone(),
-        sol::DescriptorSetLayoutInfo::default()
-            .binding(
-                0,
-                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
-                vk::ShaderStageFlags::RAYGEN_KHR,
-            )
+        scene_description
+            .descriptor_set_layout_info(&scene_bindings())
             .binding(
                 1,
                 vk::DescriptorType::STORAGE_IMAGE,
@@ -162,29 +181,6 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 2,
                 vk::DescriptorType::STORAGE_IMAGE,
                 vk::ShaderStageFlags::RAYGEN_KHR,
-            )
-            .binding(
-                3,
-                vk::DescriptorType::STORAGE_BUFFER,
-                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-            )
-            .bindings(
-                4,
-                vk::DescriptorType::STORAGE_BUFFER,
-                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                instance_count,
-            )
-            .bindings(
-                5,
-                vk::DescriptorType::STORAGE_BUFFER,
-                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                instance_count,
-            )
-            .bindings(
-                6,
-                vk::DescriptorType::STORAGE_BUFFER,
-                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                instance_count,
             ),
     );
 
@@ -199,7 +195,8 @@ pub fn setup(app: &mut sol::App) -> AppData {
                 .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
                 .cpu_to_gpu(),
             &[uniforms],
-        );
+        )
+        .unwrap();
         let desc_set = layout_scene
             .get_or_create(sol::DescriptorSetInfo::default().buffer(0, ubo.get_descriptor_info()));
         per_frame.push(PerFrameData { ubo, desc_set });
@@ -212,21 +209,36 @@ pub fn setup(app: &mut sol::App) -> AppData {
             .push_constant_range(
                 vk::PushConstantRange::builder()
                     .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
-                    .size(size_of::<u32>() as u32)
+                    .size(size_of::<TracePushConstants>() as u32)
                     .build(),
             ),
     );
 
     let enable_sky = std::env::args().any(|arg| arg == "--sky");
-    let (pipeline, sbt) = build_pipeline_sbt(&context, &pipeline_layout, enable_sky);
-    let mut accum_target =
-        create_image_target(&context, &app.window, vk::Format::R32G32B32A32_SFLOAT);
+    let (pipeline, sbt) = build_pipeline_sbt(context, &pipeline_layout, enable_sky).unwrap();
+    // rgba32f in pathtrace.rgen and the post-processing, without fallback: Image2d::new fails
+    // with Error::UnsupportedFormat when the device can't store to it.
+    let mut accum_target = create_image_target(context, &app.window, sol::POST_PROCESS_FORMAT);
 
     let cmd = context.begin_single_time_cmd();
     accum_target.transition_image_layout(cmd, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
     context.end_single_time_cmd(cmd);
 
     let render_target = create_image_target(&context, &app.window, vk::Format::R8G8B8A8_UNORM);
+    let mut post_process =
+        sol::PostProcessChain::new(context.clone(), app.window.get_extent()).unwrap();
+    post_process.add_exposure(0.0).unwrap();
+    post_process.add_tonemap(sol::Tonemap::Aces).unwrap();
+    post_process.add_gamma(2.2).unwrap();
+    #[cfg(feature = "ui")]
+    let ui = sol::ui::UiPass::new(
+        context.clone(),
+        app.renderer.get_overlay_renderpass(),
+        app.renderer.swapchain.get_format(),
+        app.window.get_size(),
+        app.window.handle().scale_factor() as f32,
+        app.renderer.get_frames_count(),
+    );
     AppData {
         scene,
         pipeline_layout,
@@ -243,47 +255,38 @@ pub fn setup(app: &mut sol::App) -> AppData {
         accumulation_start_frame: 0,
         accum_target,
         render_target,
+        post_process,
         enable_sky,
+        #[cfg(feature = "ui")]
+        ui,
     }
 }
 
 pub fn window_event(app: &mut sol::App, data: &mut AppData, event: &WindowEvent) {
+    // Clicks and keys used by the UI don't reach the camera.
+    #[cfg(feature = "ui")]
+    if data.ui.handle_event(event) {
+        return;
+    }
     if data.manip.update(&event) {
         data.accumulation_start_frame = app.elapsed_ticks as u32;
     }
     match event {
         WindowEvent::Resized(_) => {
-            data.accum_target = create_image_target(
-                &app.renderer.context,
-                &app.window,
-                vk::Format::R32G32B32A32_SFLOAT,
-            );
+            data.accum_target =
+                create_image_target(&app.renderer.context, &app.window, sol::POST_PROCESS_FORMAT);
             data.render_target = create_image_target(
                 &app.renderer.context,
                 &app.window,
                 vk::Format::R8G8B8A8_UNORM,
             );
+            data.post_process.resize(app.window.get_extent()).unwrap();
             data.accumulation_start_frame = app.elapsed_ticks as u32;
-            data.layout_pass.reset_pool();
         }
         WindowEvent::KeyboardInput { input, .. } => {
             if input.state == winit::event::ElementState::Pressed {
                 if input.virtual_keycode == Some(winit::event::VirtualKeyCode::R) {
-                    unsafe {
-                        app.renderer
-                            .context
-                            .device()
-                            .queue_wait_idle(app.renderer.context.graphics_queue())
-                            .unwrap();
-                    }
-                    let (pipeline, sbt) = build_pipeline_sbt(
-                        &app.renderer.context,
-                        &data.pipeline_layout,
-                        data.enable_sky,
-                    );
-                    data.pipeline = pipeline;
-                    data.sbt = sbt;
-                    data.accumulation_start_frame = app.elapsed_ticks as u32;
+                    reload_pipeline(app, data);
                 }
             }
         }
@@ -291,6 +294,70 @@ pub fn window_event(app: &mut sol::App, data: &mut AppData, event: &WindowEvent)
     }
 }
 
+fn reload_pipeline(app: &mut sol::App, data: &mut AppData) {
+    app.renderer.context.device_wait_idle().unwrap();
+    // On a compile error, keep rendering with the previous pipeline until the shader is fixed.
+    match build_pipeline_sbt(&app.renderer.context, &data.pipeline_layout, data.enable_sky) {
+        Ok((pipeline, sbt)) => {
+            data.pipeline = pipeline;
+            data.sbt = sbt;
+            data.accumulation_start_frame = app.elapsed_ticks as u32;
+        }
+        Err(error) => eprintln!("{}", error),
+    }
+    if let Err(error) = data.post_process.reload() {
+        eprintln!("{}", error);
+    }
+}
+
+pub fn update(app: &mut sol::App, data: &mut AppData, time: sol::FrameTime) {
+    // Hot reload: any shader or shared include touched on disk rebuilds the pipeline. The files
+    // are checked once per second.
+    let new_second = time.elapsed.as_secs() != time.elapsed.saturating_sub(time.delta).as_secs();
+    if new_second && (data.pipeline.is_outdated() || data.post_process.is_outdated()) {
+        reload_pipeline(app, data);
+    }
+    #[cfg(feature = "ui")]
+    update_ui(app, data);
+}
+
+// Index of the exposure pass in AppData::post_process.
+#[cfg(feature = "ui")]
+const EXPOSURE_PASS: usize = 0;
+
+// Parameters tuned at runtime, drawn in render.
+#[cfg(feature = "ui")]
+fn update_ui(app: &mut sol::App, data: &mut AppData) {
+    let accumulated = (app.elapsed_ticks as u32).saturating_sub(data.accumulation_start_frame);
+    let gpu_frame_time = app.renderer.gpu_frame_time;
+    let mut enable_sky = data.enable_sky;
+    let mut reset = false;
+    let post_process = &mut data.post_process;
+    data.ui.run(app.elapsed_time, |ctx| {
+        sol::ui::egui::Window::new("Path tracer").show(ctx, |ui| {
+            ui.label(format!("{} accumulated frames", accumulated));
+            ui.label(format!("gpu: {:.1} ms", gpu_frame_time));
+            ui.checkbox(&mut enable_sky, "Sky");
+            reset = ui.button("Reset accumulation").clicked();
+            ui.separator();
+            for index in 0..post_process.passes().len() {
+                let pass = post_process.pass_mut(index);
+                let name = pass.name().to_string();
+                ui.checkbox(&mut pass.enabled, name);
+            }
+            let exposure = &mut post_process.pass_mut(EXPOSURE_PASS).params.x;
+            ui.add(sol::ui::egui::Slider::new(exposure, -4.0..=4.0).text("Exposure"));
+        });
+    });
+    if enable_sky != data.enable_sky {
+        data.enable_sky = enable_sky;
+        reload_pipeline(app, data);
+    }
+    if reset {
+        data.accumulation_start_frame = app.elapsed_ticks as u32;
+    }
+}
+
 pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRenderError> {
     let (semaphore, frame_index) = app.renderer.acquire_next_image()?;
 
@@ -303,16 +370,6 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
     let cmd = app.renderer.begin_command_buffer();
     let device = app.renderer.context.device();
 
-    unsafe {
-        device.cmd_push_constants(
-            cmd,
-            data.pipeline_layout.handle(),
-            vk::ShaderStageFlags::RAYGEN_KHR,
-            0,
-            &data.accumulation_start_frame.to_ne_bytes(),
-        )
-    }
-
     data.scene_description.tlas_regenerate(cmd);
 
     data.render_target.transition_image_layout(
@@ -322,25 +379,14 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
     );
 
     let desc_pass = data.layout_pass.get_or_create(
-        sol::DescriptorSetInfo::default()
-            .accel_struct(0, data.scene_description.tlas().handle())
+        data.scene_description
+            .descriptor_set_info(&scene_bindings())
             .image(1, data.accum_target.get_descriptor_info())
-            .image(2, data.render_target.get_descriptor_info())
-            .buffer(
-                3,
-                data.scene_description
-                    .get_instances_buffer()
-                    .get_descriptor_info(),
-            )
-            .buffers(4, data.scene_description.get_vertex_descriptors().clone())
-            .buffers(5, data.scene_description.get_index_descriptors().clone())
-            .buffers(6, data.scene_description.get_material_descriptors().clone()),
+            .image(2, data.render_target.get_descriptor_info()),
     );
 
     let descriptor_sets = [data.per_frame[frame_index].desc_set.handle(), desc_pass.handle()];
     unsafe {
-        device.cmd_set_scissor(cmd, 0, &[app.window.get_rect()]);
-        device.cmd_set_viewport(cmd, 0, &[app.window.get_viewport()]);
         device.cmd_bind_pipeline(
             cmd,
             vk::PipelineBindPoint::RAY_TRACING_KHR,
@@ -355,15 +401,57 @@ pub fn render(app: &mut sol::App, data: &mut AppData) -> Result<(), sol::AppRend
             &[],
         );
     }
-    data.sbt.cmd_trace_rays(cmd, app.window.get_extent_3d());
+    // With split frame rendering, each device of the group only traces its own band.
+    for region in app.renderer.get_device_regions(app.window.get_extent()) {
+        app.renderer.cmd_set_device_mask(cmd, region.device_mask);
+        let push_constants = TracePushConstants {
+            launch_offset: [region.area.offset.x as u32, region.area.offset.y as u32],
+            accumulation_start_frame: data.accumulation_start_frame,
+        };
+        unsafe {
+            device.cmd_push_constants(
+                cmd,
+                data.pipeline_layout.handle(),
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const TracePushConstants as *const u8,
+                    size_of::<TracePushConstants>(),
+                ),
+            )
+        }
+        data.sbt.cmd_trace_rays(
+            cmd,
+            vk::Extent3D {
+                width: region.area.extent.width,
+                height: region.area.extent.height,
+                depth: 1,
+            },
+        );
+    }
+    app.renderer
+        .cmd_set_device_mask(cmd, app.renderer.get_device_mask());
+    // F11 dumps both targets to dumps/.
+    let context = &app.renderer.context;
+    app.image_dump
+        .cmd_dump(context, cmd, "pathtrace", "accum", &mut data.accum_target);
+    app.image_dump
+        .cmd_dump(context, cmd, "pathtrace", "output", &mut data.render_target);
 
-    let present_image = app.renderer.swapchain.get_present_image(frame_index);
-    data.render_target.cmd_blit_to(cmd, present_image, true);
-    present_image.transition_image_layout(
-        cmd,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        vk::ImageLayout::PRESENT_SRC_KHR,
-    );
+    match data.post_process.cmd_run(cmd, &data.accum_target) {
+        Some(output) => app.renderer.cmd_blit_to_present_image(cmd, output),
+        None => app
+            .renderer
+            .cmd_blit_to_present_image(cmd, &mut data.render_target),
+    }
+    #[cfg(feature = "ui")]
+    {
+        app.renderer.begin_overlay_pass(cmd);
+        let extent = app.renderer.swapchain.get_extent();
+        data.ui
+            .cmd_draw(cmd, frame_index, extent, &mut app.renderer.transient);
+        app.renderer.end_overlay_pass(cmd);
+    }
     app.renderer.end_command_buffer(cmd);
     app.renderer.submit_and_present(cmd, semaphore)
 }
@@ -374,14 +462,24 @@ pub fn prepare() -> sol::AppSettings {
         resolution: [1280, 720],
         render: sol::RendererSettings {
             extensions: vec![vk::KhrGetPhysicalDeviceProperties2Fn::name()],
+            // Multi-GPU rendering when a device group is found.
+            device_group: if std::env::args().any(|arg| arg == "--sfr") {
+                sol::DeviceGroupMode::SplitFrame
+            } else if std::env::args().any(|arg| arg == "--afr") {
+                sol::DeviceGroupMode::AlternateFrame
+            } else {
+                sol::DeviceGroupMode::Disabled
+            },
             ..Default::default()
         },
+        ..Default::default()
     }
 }
 
 pub fn main() {
     sol::App::build(setup)
         .prepare(prepare)
+        .update(update)
         .render(render)
         .window_event(window_event)
         .run();
@@ -0,0 +1,79 @@
+use crate::{AppRenderer, RendererSettings, Result, SharedContext, Window};
+use std::sync::Arc;
+use winit::{event_loop::EventLoopWindowTarget, window::WindowId};
+
+#[derive(Clone, Debug)]
+pub struct AppWindowSettings {
+    pub name: String,
+    pub resolution: [u32; 2],
+    // Only the swapchain and frame settings apply, see AppRenderer::new_shared.
+    pub render: RendererSettings,
+}
+
+impl Default for AppWindowSettings {
+    fn default() -> Self {
+        AppWindowSettings {
+            name: "Window".to_string(),
+            resolution: [640, 480],
+            render: RendererSettings::default(),
+        }
+    }
+}
+
+// Additional window of an App, with its own surface, swapchain and frames on the device of the
+// main window. Closing it hides it, see AppBuilder::window.
+pub struct AppWindow {
+    // Declared first to destroy the swapchain before the surface.
+    pub renderer: AppRenderer,
+    pub window: Window,
+    pub settings: AppWindowSettings,
+    visible: bool,
+    // Set when the last frame found the swapchain out of date.
+    pub(crate) dirty_swapchain: bool,
+}
+
+impl AppWindow {
+    pub fn new(
+        shared_context: Arc<SharedContext>,
+        settings: AppWindowSettings,
+        event_loop: &EventLoopWindowTarget<()>,
+    ) -> Result<Self> {
+        let mut window = Window::new(
+            settings.resolution[0],
+            settings.resolution[1],
+            settings.name.clone(),
+            event_loop,
+        );
+        let renderer =
+            AppRenderer::new_shared(shared_context, &mut window, settings.render.clone())?;
+        Ok(AppWindow {
+            renderer,
+            window,
+            settings,
+            visible: true,
+            dirty_swapchain: false,
+        })
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.handle().id()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.window.handle().set_visible(visible);
+        self.visible = visible;
+    }
+
+    // Hidden and minimized windows aren't rendered.
+    pub fn should_render(&self) -> bool {
+        self.visible && !self.window.is_minimized()
+    }
+
+    pub fn recreate_swapchain(&mut self) {
+        self.renderer.recreate_swapchain(&self.window);
+    }
+}
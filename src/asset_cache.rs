@@ -0,0 +1,132 @@
+use crate::scene::{load_scene_with_info, Scene, SceneImportInfo};
+use crate::{Context, Result, SamplerInfo, Shader, Texture2d};
+use ash::vk;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Canonical path and load parameters. Parameters without Hash (SamplerInfo, SceneImportInfo)
+// are keyed by their Debug output.
+type AssetKey = (PathBuf, String);
+
+#[derive(Default)]
+struct CachedAssets {
+    textures: HashMap<AssetKey, Arc<Texture2d>>,
+    shaders: HashMap<AssetKey, Arc<Shader>>,
+    scenes: HashMap<AssetKey, Arc<Scene>>,
+}
+
+// Shared handles of the assets loaded from files, see Context::asset_cache. Loading the same
+// file with the same parameters again returns the cached handle instead of new GPU resources.
+// Assets stay cached until purge_unused is called once the last handle outside of the cache is
+// dropped. The cached assets hold the context, the renderer clears the cache on exit.
+#[derive(Default)]
+pub struct AssetCache {
+    assets: Mutex<CachedAssets>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AssetCacheStats {
+    pub textures: usize,
+    pub shaders: usize,
+    pub scenes: usize,
+}
+
+fn asset_key(path: &Path, parameters: String) -> AssetKey {
+    // Load errors of missing files are reported by the loaders.
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    (path, parameters)
+}
+
+fn get_or_load<T>(
+    assets: &mut HashMap<AssetKey, Arc<T>>,
+    key: AssetKey,
+    load: impl FnOnce(&Path) -> Result<T>,
+) -> Result<Arc<T>> {
+    if let Some(asset) = assets.get(&key) {
+        return Ok(asset.clone());
+    }
+    let asset = Arc::new(load(&key.0)?);
+    assets.insert(key, asset.clone());
+    Ok(asset)
+}
+
+impl AssetCache {
+    pub fn texture(
+        &self,
+        context: &Arc<Context>,
+        path: &Path,
+        sampler_info: SamplerInfo,
+    ) -> Result<Arc<Texture2d>> {
+        let key = asset_key(path, format!("{:?}", sampler_info));
+        let mut assets = self.assets.lock().unwrap();
+        get_or_load(&mut assets.textures, key, |path| {
+            Texture2d::with_sampler(context.clone(), path.to_path_buf(), sampler_info)
+        })
+    }
+
+    pub fn shader(
+        &self,
+        context: &Arc<Context>,
+        path: &Path,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Result<Arc<Shader>> {
+        let key = asset_key(path, format!("{:?}", stage_flags));
+        let mut assets = self.assets.lock().unwrap();
+        get_or_load(&mut assets.shaders, key, |path| {
+            Shader::new(context.clone(), path.to_path_buf(), stage_flags)
+        })
+    }
+
+    // Scenes without textures, see load_scene_with_info. Scenes sharing a TextureArena are
+    // loaded directly instead.
+    pub fn scene(
+        &self,
+        context: &Arc<Context>,
+        path: &Path,
+        info: SceneImportInfo,
+    ) -> Result<Arc<Scene>> {
+        let key = asset_key(path, format!("{:?}", info));
+        let mut assets = self.assets.lock().unwrap();
+        get_or_load(&mut assets.scenes, key, |path| {
+            load_scene_with_info(context.clone(), path, info, None)
+        })
+    }
+
+    // Drops the assets only referenced by the cache and returns how many were dropped. Their
+    // GPU resources are destroyed immediately, frames in flight must not use them.
+    pub fn purge_unused(&self) -> usize {
+        let mut assets = self.assets.lock().unwrap();
+        let count = assets.len();
+        assets
+            .textures
+            .retain(|_, asset| Arc::strong_count(asset) > 1);
+        assets
+            .shaders
+            .retain(|_, asset| Arc::strong_count(asset) > 1);
+        assets
+            .scenes
+            .retain(|_, asset| Arc::strong_count(asset) > 1);
+        count - assets.len()
+    }
+
+    // Drops every cached handle, assets still referenced elsewhere stay alive.
+    pub fn clear(&self) {
+        *self.assets.lock().unwrap() = CachedAssets::default();
+    }
+
+    pub fn stats(&self) -> AssetCacheStats {
+        let assets = self.assets.lock().unwrap();
+        AssetCacheStats {
+            textures: assets.textures.len(),
+            shaders: assets.shaders.len(),
+            scenes: assets.scenes.len(),
+        }
+    }
+}
+
+impl CachedAssets {
+    fn len(&self) -> usize {
+        self.textures.len() + self.shaders.len() + self.scenes.len()
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{Context, Error, Result};
+use ash::vk;
+use std::sync::Arc;
+
+// Identifies an AsyncCompute submission: the value its timeline semaphore reaches on completion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ComputeTicket(pub u64);
+
+// Submits compute work to Context::compute_queue, running concurrently with the graphics queue,
+// each submission signaling the next value of a timeline semaphore. Graphics work waits on it
+// with AppRenderer::wait_for_compute, and compute work waits on the frames of
+// AppRenderer::submit_frame with wait_for_graphics. With a dedicated compute family, EXCLUSIVE
+// resources shared with the graphics queue need queue family ownership transfers, see
+// queue_family_index.
+pub struct AsyncCompute {
+    context: Arc<Context>,
+    pool: vk::CommandPool,
+    timeline: vk::Semaphore,
+    last_value: u64,
+    // Signaled by AppRenderer::submit_frame.
+    graphics_timeline: vk::Semaphore,
+    graphics_value: u64,
+    in_flight: Vec<(u64, vk::CommandBuffer)>,
+    free: Vec<vk::CommandBuffer>,
+}
+
+impl AsyncCompute {
+    // Requires timeline semaphores, core in Vulkan 1.2.
+    pub fn new(context: Arc<Context>) -> Result<Self> {
+        if !context.supports_timeline_semaphore() {
+            eprintln!("Async compute requires timeline semaphores.");
+            return Err(Error::Vulkan(vk::Result::ERROR_FEATURE_NOT_PRESENT));
+        }
+        let pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(context.shared().queue_family_indices.compute);
+        let mut timeline_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().push_next(&mut timeline_info);
+        unsafe {
+            let device = context.device();
+            let pool = device.create_command_pool(&pool_create_info, None)?;
+            let timeline = device.create_semaphore(&semaphore_info, None)?;
+            let graphics_timeline = device.create_semaphore(&semaphore_info, None)?;
+            Ok(AsyncCompute {
+                context,
+                pool,
+                timeline,
+                last_value: 0,
+                graphics_timeline,
+                graphics_value: 0,
+                in_flight: Vec::new(),
+                free: Vec::new(),
+            })
+        }
+    }
+
+    pub fn queue_family_index(&self) -> u32 {
+        self.context.shared().queue_family_indices.compute
+    }
+
+    pub fn timeline_semaphore(&self) -> vk::Semaphore {
+        self.timeline
+    }
+
+    pub fn graphics_timeline_semaphore(&self) -> vk::Semaphore {
+        self.graphics_timeline
+    }
+
+    // Value of the graphics timeline signaled by the last AppRenderer::submit_frame.
+    pub fn graphics_value(&self) -> u64 {
+        self.graphics_value
+    }
+
+    // Wait of submit for the frames submitted so far to complete, e.g. before reading their
+    // results at `stage`.
+    pub fn wait_for_graphics(
+        &self,
+        stage: vk::PipelineStageFlags,
+    ) -> (vk::Semaphore, u64, vk::PipelineStageFlags) {
+        (self.graphics_timeline, self.graphics_value, stage)
+    }
+
+    // Value the next frame signals on the graphics timeline.
+    pub(crate) fn next_graphics_value(&mut self) -> u64 {
+        self.graphics_value += 1;
+        self.graphics_value
+    }
+
+    // Begins a command buffer of the compute queue family, to record and pass to submit.
+    pub fn begin_cmd(&mut self) -> vk::CommandBuffer {
+        self.recycle();
+        let device = self.context.device();
+        let cmd = match self.free.pop() {
+            Some(cmd) => cmd,
+            None => {
+                let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_buffer_count(1)
+                    .command_pool(self.pool)
+                    .level(vk::CommandBufferLevel::PRIMARY);
+                unsafe { device.allocate_command_buffers(&allocate_info).unwrap()[0] }
+            }
+        };
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            device
+                .begin_command_buffer(cmd, &begin_info)
+                .expect("Begin compute commands.");
+        }
+        cmd
+    }
+
+    // Ends and submits `cmd`. Its commands start once the timeline semaphores of `waits` reach
+    // their values, e.g. those of earlier tickets.
+    pub fn submit(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        waits: &[(vk::Semaphore, u64, vk::PipelineStageFlags)],
+    ) -> ComputeTicket {
+        self.last_value += 1;
+        let wait_semaphores = waits.iter().map(|wait| wait.0).collect::<Vec<_>>();
+        let wait_values = waits.iter().map(|wait| wait.1).collect::<Vec<_>>();
+        let wait_stages = waits.iter().map(|wait| wait.2).collect::<Vec<_>>();
+        let signal_values = [self.last_value];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(std::slice::from_ref(&cmd))
+            .signal_semaphores(std::slice::from_ref(&self.timeline))
+            .push_next(&mut timeline_info);
+        unsafe {
+            self.context
+                .device()
+                .end_command_buffer(cmd)
+                .expect("End compute commands.");
+        }
+        self.context
+            .queue_submit(
+                self.context.compute_queue(),
+                std::slice::from_ref(&submit_info),
+                vk::Fence::null(),
+            )
+            .expect("Compute submit failed.");
+        self.in_flight.push((self.last_value, cmd));
+        ComputeTicket(self.last_value)
+    }
+
+    pub fn completed_value(&self) -> u64 {
+        unsafe {
+            self.context
+                .device()
+                .get_semaphore_counter_value(self.timeline)
+                .unwrap_or(0)
+        }
+    }
+
+    pub fn is_complete(&self, ticket: ComputeTicket) -> bool {
+        self.completed_value() >= ticket.0
+    }
+
+    pub fn wait(&mut self, ticket: ComputeTicket) {
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(std::slice::from_ref(&self.timeline))
+            .values(std::slice::from_ref(&ticket.0));
+        unsafe {
+            self.context
+                .device()
+                .wait_semaphores(&wait_info, u64::MAX)
+                .expect("Wait for compute failed.");
+        }
+        self.recycle();
+    }
+
+    // Returns the command buffers of completed submissions to the free list.
+    fn recycle(&mut self) {
+        let completed = self.completed_value();
+        let (done, pending): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|(value, _)| *value <= completed);
+        self.in_flight = pending;
+        self.free.extend(done.into_iter().map(|(_, cmd)| cmd));
+    }
+}
+
+impl Drop for AsyncCompute {
+    fn drop(&mut self) {
+        self.wait(ComputeTicket(self.last_value));
+        unsafe {
+            let device = self.context.device();
+            device.destroy_command_pool(self.pool, None);
+            device.destroy_semaphore(self.timeline, None);
+            device.destroy_semaphore(self.graphics_timeline, None);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{Context, RenderingFormats, Resource, Shader};
+use ash::vk;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// Procedural background drawn by the renderer at the start of its render pass, before any
+// user content.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Background {
+    // Only the clear color.
+    #[default]
+    None,
+    VerticalGradient {
+        top: glam::Vec4,
+        bottom: glam::Vec4,
+    },
+    Checkerboard {
+        color_a: glam::Vec4,
+        color_b: glam::Vec4,
+        // Cell size in pixels.
+        cell_size: f32,
+    },
+}
+
+const BACKGROUND_VERT: &str = "
+#version 450
+layout(location = 0) out vec2 uv;
+void main() {
+    uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+}
+";
+
+const BACKGROUND_FRAG: &str = "
+#version 450
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 out_color;
+layout(push_constant) uniform Params {
+    vec4 color_a;
+    vec4 color_b;
+    vec4 params; // x: mode (0 gradient, 1 checkerboard), y: cell size
+} p;
+void main() {
+    if (p.params.x < 0.5) {
+        out_color = mix(p.color_a, p.color_b, uv.y);
+    } else {
+        ivec2 cell = ivec2(floor(gl_FragCoord.xy / p.params.y));
+        out_color = ((cell.x + cell.y) & 1) == 0 ? p.color_a : p.color_b;
+    }
+}
+";
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BackgroundParams {
+    color_a: glam::Vec4,
+    color_b: glam::Vec4,
+    params: glam::Vec4,
+}
+
+// Fullscreen triangle pipeline drawing a Background, without depth test or write.
+pub struct BackgroundPass {
+    context: Arc<Context>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl BackgroundPass {
+    pub fn new(
+        context: Arc<Context>,
+        render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        Self::create(context, render_pass, None, samples)
+    }
+
+    // For passes begun with dynamic rendering, see RenderingInfo.
+    pub fn new_dynamic(context: Arc<Context>, formats: &RenderingFormats) -> Self {
+        Self::create(
+            context,
+            vk::RenderPass::null(),
+            Some(formats),
+            formats.samples,
+        )
+    }
+
+    fn create(
+        context: Arc<Context>,
+        render_pass: vk::RenderPass,
+        rendering_formats: Option<&RenderingFormats>,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        let vert = Shader::from_source(
+            context.clone(),
+            BACKGROUND_VERT,
+            "background.vert",
+            vk::ShaderStageFlags::VERTEX,
+        )
+        .expect("Unable to compile background shader");
+        let frag = Shader::from_source(
+            context.clone(),
+            BACKGROUND_FRAG,
+            "background.frag",
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+        .expect("Unable to compile background shader");
+        let entry_name = CString::new("main").unwrap();
+        let stages = [
+            vert.get_create_info(&entry_name),
+            frag.get_create_info(&entry_name),
+        ];
+
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(std::mem::size_of::<BackgroundParams>() as u32)
+            .build()];
+        let layout_info =
+            vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            context
+                .device()
+                .create_pipeline_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default();
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+            scissor_count: 1,
+            viewport_count: 1,
+            ..Default::default()
+        };
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            ..Default::default()
+        };
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: samples,
+            ..Default::default()
+        };
+        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo::default();
+        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            ..Default::default()
+        }];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachment_states);
+        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+
+        let mut rendering_create_info =
+            rendering_formats.map(|formats| formats.pipeline_create_info());
+        let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state_info)
+            .input_assembly_state(&vertex_input_assembly_state_info)
+            .viewport_state(&viewport_state_info)
+            .rasterization_state(&rasterization_info)
+            .multisample_state(&multisample_state_info)
+            .depth_stencil_state(&depth_state_info)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state_info)
+            .layout(pipeline_layout)
+            .render_pass(render_pass);
+        if let Some(rendering_create_info) = &mut rendering_create_info {
+            create_info = create_info.push_next(rendering_create_info);
+        }
+        let create_infos = [create_info.build()];
+        let pipeline = unsafe {
+            context
+                .device()
+                .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                .expect("Unable to create background pipeline")[0]
+        };
+
+        BackgroundPass {
+            context,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    // Must be recorded inside the render pass, before any other draw.
+    pub fn cmd_draw(&self, cmd: vk::CommandBuffer, extent: vk::Extent2D, background: &Background) {
+        let params = match *background {
+            Background::None => return,
+            Background::VerticalGradient { top, bottom } => BackgroundParams {
+                color_a: top,
+                color_b: bottom,
+                params: glam::Vec4::ZERO,
+            },
+            Background::Checkerboard {
+                color_a,
+                color_b,
+                cell_size,
+            } => BackgroundParams {
+                color_a,
+                color_b,
+                params: glam::vec4(1.0, cell_size.max(1.0), 0.0, 0.0),
+            },
+        };
+        let device = self.context.device();
+        unsafe {
+            let bytes = std::slice::from_raw_parts(
+                &params as *const BackgroundParams as *const u8,
+                std::mem::size_of::<BackgroundParams>(),
+            );
+            device.cmd_set_viewport(
+                cmd,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(
+                cmd,
+                0,
+                &[vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                }],
+            );
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytes,
+            );
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+        }
+    }
+}
+
+impl Resource<vk::Pipeline> for BackgroundPass {
+    fn handle(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Drop for BackgroundPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().destroy_pipeline(self.pipeline, None);
+            self.context
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
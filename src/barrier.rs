@@ -0,0 +1,345 @@
+use ash::vk;
+
+// Global memory dependency, e.g. between acceleration structure builds. Recorded with
+// SharedContext::cmd_pipeline_barrier2.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryBarrier2 {
+    pub src_stage: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stage: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+}
+
+impl MemoryBarrier2 {
+    pub fn src(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.src_stage = stage;
+        self.src_access = access;
+        self
+    }
+
+    pub fn dst(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.dst_stage = stage;
+        self.dst_access = access;
+        self
+    }
+
+    pub(crate) fn to_vk(self) -> vk::MemoryBarrier2 {
+        vk::MemoryBarrier2::builder()
+            .src_stage_mask(self.src_stage)
+            .src_access_mask(self.src_access)
+            .dst_stage_mask(self.dst_stage)
+            .dst_access_mask(self.dst_access)
+            .build()
+    }
+}
+
+// Dependency on a buffer range, the whole buffer by default.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferBarrier2 {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub src_stage: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stage: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+}
+
+impl BufferBarrier2 {
+    pub fn new(buffer: vk::Buffer) -> Self {
+        BufferBarrier2 {
+            buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            src_stage: vk::PipelineStageFlags2::NONE,
+            src_access: vk::AccessFlags2::NONE,
+            dst_stage: vk::PipelineStageFlags2::NONE,
+            dst_access: vk::AccessFlags2::NONE,
+        }
+    }
+
+    pub fn range(mut self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Self {
+        self.offset = offset;
+        self.size = size;
+        self
+    }
+
+    pub fn src(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.src_stage = stage;
+        self.src_access = access;
+        self
+    }
+
+    pub fn dst(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.dst_stage = stage;
+        self.dst_access = access;
+        self
+    }
+
+    pub(crate) fn to_vk(self) -> vk::BufferMemoryBarrier2 {
+        vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(self.src_stage)
+            .src_access_mask(self.src_access)
+            .dst_stage_mask(self.dst_stage)
+            .dst_access_mask(self.dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer)
+            .offset(self.offset)
+            .size(self.size)
+            .build()
+    }
+}
+
+// Dependency on, or layout transition of, image subresources: all of them by default.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageBarrier2 {
+    pub image: vk::Image,
+    pub subresource_range: vk::ImageSubresourceRange,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub src_stage: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stage: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+}
+
+impl ImageBarrier2 {
+    pub fn new(image: vk::Image, aspect_mask: vk::ImageAspectFlags) -> Self {
+        ImageBarrier2 {
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: vk::REMAINING_MIP_LEVELS,
+                base_array_layer: 0,
+                layer_count: vk::REMAINING_ARRAY_LAYERS,
+            },
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::UNDEFINED,
+            src_stage: vk::PipelineStageFlags2::NONE,
+            src_access: vk::AccessFlags2::NONE,
+            dst_stage: vk::PipelineStageFlags2::NONE,
+            dst_access: vk::AccessFlags2::NONE,
+        }
+    }
+
+    // Transition waiting for the accesses images in `old` are used for, and blocking those of
+    // `new`, see layout_scope. Override them with src and dst when the uses are known.
+    pub fn layouts(mut self, old: vk::ImageLayout, new: vk::ImageLayout) -> Self {
+        self.old_layout = old;
+        self.new_layout = new;
+        let (src_stage, src_access) = layout_scope(old);
+        let (dst_stage, dst_access) = layout_scope(new);
+        self.src(src_stage, src_access).dst(dst_stage, dst_access)
+    }
+
+    // Adds the ray tracing shader stages to the SHADER_READ_ONLY_OPTIMAL scopes of layout_scope,
+    // left to the barrier recording as they are only valid with ray tracing enabled.
+    pub(crate) fn with_ray_tracing_reads(mut self) -> Self {
+        let layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        let (read_stage, _) = layout_scope(layout);
+        if self.old_layout == layout && self.src_stage == read_stage {
+            self.src_stage |= vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR;
+        }
+        if self.new_layout == layout && self.dst_stage == read_stage {
+            self.dst_stage |= vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR;
+        }
+        self
+    }
+
+    pub fn src(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.src_stage = stage;
+        self.src_access = access;
+        self
+    }
+
+    pub fn dst(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.dst_stage = stage;
+        self.dst_access = access;
+        self
+    }
+
+    pub fn mips(mut self, base_mip_level: u32, level_count: u32) -> Self {
+        self.subresource_range.base_mip_level = base_mip_level;
+        self.subresource_range.level_count = level_count;
+        self
+    }
+
+    pub fn layers(mut self, base_array_layer: u32, layer_count: u32) -> Self {
+        self.subresource_range.base_array_layer = base_array_layer;
+        self.subresource_range.layer_count = layer_count;
+        self
+    }
+
+    pub(crate) fn to_vk(self) -> vk::ImageMemoryBarrier2 {
+        vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(self.src_stage)
+            .src_access_mask(self.src_access)
+            .dst_stage_mask(self.dst_stage)
+            .dst_access_mask(self.dst_access)
+            .old_layout(self.old_layout)
+            .new_layout(self.new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(self.subresource_range)
+            .build()
+    }
+
+    fn to_legacy(self) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier::builder()
+            .src_access_mask(legacy_access(self.src_access))
+            .dst_access_mask(legacy_access(self.dst_access))
+            .old_layout(self.old_layout)
+            .new_layout(self.new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(self.subresource_range)
+            .build()
+    }
+}
+
+// Stages and accesses of the commands using images in `layout`. Sampled images may be read by
+// vertex, fragment and compute shaders, and by ray tracing shaders when enabled (added by
+// SharedContext::cmd_pipeline_barrier2).
+pub fn layout_scope(layout: vk::ImageLayout) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+    type Stage = vk::PipelineStageFlags2;
+    type Access = vk::AccessFlags2;
+    match layout {
+        vk::ImageLayout::UNDEFINED => (Stage::NONE, Access::NONE),
+        vk::ImageLayout::PREINITIALIZED => (Stage::HOST, Access::HOST_WRITE),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            Stage::COLOR_ATTACHMENT_OUTPUT,
+            Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            Stage::EARLY_FRAGMENT_TESTS | Stage::LATE_FRAGMENT_TESTS,
+            Access::DEPTH_STENCIL_ATTACHMENT_READ | Access::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => (
+            Stage::EARLY_FRAGMENT_TESTS | Stage::LATE_FRAGMENT_TESTS | Stage::FRAGMENT_SHADER,
+            Access::DEPTH_STENCIL_ATTACHMENT_READ | Access::SHADER_SAMPLED_READ,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            Stage::VERTEX_SHADER | Stage::FRAGMENT_SHADER | Stage::COMPUTE_SHADER,
+            Access::SHADER_SAMPLED_READ,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (Stage::TRANSFER, Access::TRANSFER_READ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (Stage::TRANSFER, Access::TRANSFER_WRITE),
+        // Presentation needs no access, but the image may have been rendered to earlier.
+        vk::ImageLayout::PRESENT_SRC_KHR => (Stage::ALL_COMMANDS, Access::NONE),
+        // Storage images and anything else.
+        _ => (
+            Stage::ALL_COMMANDS,
+            Access::MEMORY_READ | Access::MEMORY_WRITE,
+        ),
+    }
+}
+
+// Without synchronization2 the barriers are recorded with a single vkCmdPipelineBarrier, their
+// stages merged and their flags narrowed to the legacy ones.
+pub(crate) fn cmd_legacy_pipeline_barrier(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    memory_barriers: &[MemoryBarrier2],
+    buffer_barriers: &[BufferBarrier2],
+    image_barriers: &[ImageBarrier2],
+) {
+    let scopes = memory_barriers
+        .iter()
+        .map(|barrier| (barrier.src_stage, barrier.dst_stage))
+        .chain(
+            buffer_barriers
+                .iter()
+                .map(|barrier| (barrier.src_stage, barrier.dst_stage)),
+        )
+        .chain(
+            image_barriers
+                .iter()
+                .map(|barrier| (barrier.src_stage, barrier.dst_stage)),
+        );
+    let (mut src_stage, mut dst_stage) = (
+        vk::PipelineStageFlags::empty(),
+        vk::PipelineStageFlags::empty(),
+    );
+    for (src, dst) in scopes {
+        src_stage |= legacy_stage(src);
+        dst_stage |= legacy_stage(dst);
+    }
+    if src_stage.is_empty() {
+        src_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
+    }
+    if dst_stage.is_empty() {
+        dst_stage = vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+    }
+    let memory_barriers = memory_barriers
+        .iter()
+        .map(|barrier| {
+            vk::MemoryBarrier::builder()
+                .src_access_mask(legacy_access(barrier.src_access))
+                .dst_access_mask(legacy_access(barrier.dst_access))
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let buffer_barriers = buffer_barriers
+        .iter()
+        .map(|barrier| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(legacy_access(barrier.src_access))
+                .dst_access_mask(legacy_access(barrier.dst_access))
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(barrier.buffer)
+                .offset(barrier.offset)
+                .size(barrier.size)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let image_barriers = image_barriers
+        .iter()
+        .map(|barrier| barrier.to_legacy())
+        .collect::<Vec<_>>();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            cmd,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &memory_barriers,
+            &buffer_barriers,
+            &image_barriers,
+        );
+    }
+}
+
+// The legacy stages share their bits with synchronization2, whose finer stages map to the
+// legacy stage including them. NONE maps to no stage.
+pub fn legacy_stage(stage: vk::PipelineStageFlags2) -> vk::PipelineStageFlags {
+    type Stage = vk::PipelineStageFlags2;
+    let mut legacy = vk::PipelineStageFlags::from_raw(stage.as_raw() as u32);
+    if stage.intersects(Stage::COPY | Stage::RESOLVE | Stage::BLIT | Stage::CLEAR) {
+        legacy |= vk::PipelineStageFlags::TRANSFER;
+    }
+    if stage.intersects(Stage::INDEX_INPUT | Stage::VERTEX_ATTRIBUTE_INPUT) {
+        legacy |= vk::PipelineStageFlags::VERTEX_INPUT;
+    }
+    // Tessellation and geometry stages may not be enabled.
+    if stage.intersects(Stage::PRE_RASTERIZATION_SHADERS) {
+        legacy |= vk::PipelineStageFlags::VERTEX_SHADER;
+    }
+    legacy
+}
+
+pub fn legacy_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    type Access = vk::AccessFlags2;
+    let mut legacy = vk::AccessFlags::from_raw(access.as_raw() as u32);
+    if access.intersects(Access::SHADER_SAMPLED_READ | Access::SHADER_STORAGE_READ) {
+        legacy |= vk::AccessFlags::SHADER_READ;
+    }
+    if access.intersects(Access::SHADER_STORAGE_WRITE) {
+        legacy |= vk::AccessFlags::SHADER_WRITE;
+    }
+    legacy
+}
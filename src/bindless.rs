@@ -0,0 +1,110 @@
+use crate::{
+    Context, DescriptorSet, DescriptorSetLayout, DescriptorSetLayoutInfo, Resource, Texture2d,
+};
+use ash::vk;
+use std::sync::Arc;
+
+// Texture index of materials without texture, see MaterialInfo.
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+// Bindless textures: owns Texture2d objects at stable indices of a single combined image sampler
+// array (set binding 0), so shaders select textures by index, e.g. from MaterialInfo. Declared in
+// GLSL as `layout(set = N, binding = 0) uniform sampler2D textures[];` with
+// GL_EXT_nonuniform_qualifier. Needs Context::supports_bindless.
+pub struct TextureArena {
+    context: Arc<Context>,
+    layout: DescriptorSetLayout,
+    set: DescriptorSet,
+    textures: Vec<Option<Texture2d>>,
+    free: Vec<u32>,
+    capacity: u32,
+}
+
+impl TextureArena {
+    pub fn new(context: Arc<Context>, capacity: u32, stages: vk::ShaderStageFlags) -> Self {
+        let mut layout = DescriptorSetLayout::new(
+            context.clone(),
+            DescriptorSetLayoutInfo::default()
+                .bindless(
+                    0,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    stages,
+                    capacity,
+                )
+                .min_max_sets(1)
+                .name("TextureArena".to_string()),
+        );
+        let set = layout.create_set();
+        TextureArena {
+            context,
+            layout,
+            set,
+            textures: Vec::new(),
+            free: Vec::new(),
+            capacity,
+        }
+    }
+
+    // Returns the index of the texture, stable until it is removed.
+    pub fn insert(&mut self, texture: Texture2d) -> u32 {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                assert!(
+                    (self.textures.len() as u32) < self.capacity,
+                    "TextureArena is full."
+                );
+                self.textures.push(None);
+                self.textures.len() as u32 - 1
+            }
+        };
+        let image_info = [texture.get_descriptor_info()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set.handle())
+            .dst_binding(0)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)
+            .build();
+        unsafe {
+            self.context.device().update_descriptor_sets(&[write], &[]);
+        }
+        self.textures[index as usize] = Some(texture);
+        index
+    }
+
+    // The index can be reused by the next insert. Shaders must not access it anymore, and
+    // commands using the texture must have completed before the returned texture is dropped.
+    pub fn remove(&mut self, index: u32) -> Option<Texture2d> {
+        let texture = self.textures.get_mut(index as usize)?.take();
+        if texture.is_some() {
+            self.free.push(index);
+        }
+        texture
+    }
+
+    pub fn get(&self, index: u32) -> Option<&Texture2d> {
+        self.textures.get(index as usize)?.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    // Layout to include in the pipeline layouts of shaders sampling the arena.
+    pub fn layout(&self) -> &DescriptorSetLayout {
+        &self.layout
+    }
+
+    pub fn descriptor_set(&self) -> DescriptorSet {
+        self.set
+    }
+}
@@ -1,7 +1,8 @@
-use crate::{Context, Resource};
+use crate::{BufferBarrier2, Context, Error, PendingBatch, Resource, Result};
 use ash::{util::Align, vk};
+use bytemuck::Pod;
 use std::sync::Arc;
-use std::{ffi::c_void, mem::align_of};
+use std::{ffi::c_void, fmt, marker::PhantomData, mem::align_of};
 use gpu_allocator::{MemoryLocation, vulkan::{Allocation, AllocationCreateDesc}};
 
 #[derive(Clone, Copy)]
@@ -70,6 +71,11 @@ impl<'a> BufferInfo<'a> {
         self.usage |= vk::BufferUsageFlags::INDIRECT_BUFFER;
         self
     }
+    // See Buffer::get_address, needs Context::supports_buffer_device_address.
+    pub fn usage_device_address(mut self) -> Self {
+        self.usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        self
+    }
     pub fn gpu_only(mut self) -> Self {
         self.mem_usage = MemoryLocation::GpuOnly;
         self
@@ -92,11 +98,124 @@ impl<'a> BufferInfo<'a> {
     }
 }
 
+// GPU pointer to T values, e.g. pushed as a push constant or stored in other buffers to build
+// pointer-based data structures. Matches a uint64_t or a `buffer_reference` block in shaders
+// (GL_EXT_buffer_reference), whose members should use the scalar layout
+// (GL_EXT_scalar_block_layout) to match the #[repr(C)] T.
+#[repr(transparent)]
+pub struct DeviceAddress<T> {
+    address: vk::DeviceAddress,
+    _marker: PhantomData<*const T>,
+}
+
+impl<T> DeviceAddress<T> {
+    pub fn new(address: vk::DeviceAddress) -> Self {
+        DeviceAddress {
+            address,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn null() -> Self {
+        Self::new(0)
+    }
+
+    pub fn is_null(self) -> bool {
+        self.address == 0
+    }
+
+    pub fn raw(self) -> vk::DeviceAddress {
+        self.address
+    }
+
+    // Address of the element `count` T values further.
+    pub fn offset(self, count: usize) -> Self {
+        Self::new(self.address + (count * std::mem::size_of::<T>()) as vk::DeviceAddress)
+    }
+
+    // Address `offset` bytes further, e.g. of a field to `cast` to its type.
+    pub fn byte_add(self, offset: vk::DeviceSize) -> Self {
+        Self::new(self.address + offset)
+    }
+
+    pub fn cast<U>(self) -> DeviceAddress<U> {
+        DeviceAddress::new(self.address)
+    }
+}
+
+// Not derived, which would require T to implement them.
+impl<T> Clone for DeviceAddress<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for DeviceAddress<T> {}
+
+impl<T> PartialEq for DeviceAddress<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+}
+
+impl<T> Eq for DeviceAddress<T> {}
+
+impl<T> Default for DeviceAddress<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> fmt::Debug for DeviceAddress<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeviceAddress({:#x})", self.address)
+    }
+}
+
+// Device addresses need the bufferDeviceAddress feature, else buffer creation would be invalid.
+fn check_usage(context: &Context, info: &BufferInfo) -> Result<()> {
+    let device_address = info
+        .usage
+        .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS);
+    if device_address && !context.supports_buffer_device_address() {
+        eprintln!(
+            "Buffer {}: device addresses are unsupported by the device.",
+            info.name
+        );
+        return Err(Error::Vulkan(vk::Result::ERROR_FEATURE_NOT_PRESENT));
+    }
+    Ok(())
+}
+
+// Allocates and binds the memory of `buffer`, which is destroyed on failure.
+fn bind_memory(
+    context: &Context,
+    buffer: vk::Buffer,
+    desc: &AllocationCreateDesc,
+) -> Result<Allocation> {
+    let device = context.device();
+    let allocation = context.allocate_memory(desc).map_err(|error| {
+        unsafe { device.destroy_buffer(buffer, None) };
+        Error::from(error)
+    })?;
+    let bound =
+        unsafe { device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset()) };
+    if let Err(error) = bound {
+        unsafe { device.destroy_buffer(buffer, None) };
+        let _ = context.free_memory(allocation);
+        return Err(error.into());
+    }
+    Ok(allocation)
+}
+
 pub struct Buffer {
     context: Arc<Context>,
     handle: vk::Buffer,
+    // Of the buffer, its allocation may be larger.
+    size: vk::DeviceSize,
     element_count: u32,
     allocation: Allocation,
+    usage: vk::BufferUsageFlags,
     index_type: Option<vk::IndexType>,
 }
 
@@ -106,47 +225,50 @@ impl Buffer {
         info: BufferInfo,
         device_size: vk::DeviceSize,
         element_count: u32,
-    ) -> Self {
+    ) -> Result<Self> {
         assert_ne!(device_size, 0);
+        check_usage(&context, &info)?;
 
         let create_info = vk::BufferCreateInfo::builder()
             .size(device_size)
             .usage(info.usage);
 
-        let buffer = unsafe { context.device().create_buffer(&create_info, None) }.unwrap();
+        let buffer = unsafe { context.device().create_buffer(&create_info, None) }?;
         let mut requirements = unsafe { context.device().get_buffer_memory_requirements(buffer) };
         if info.memory_type_bits.is_some() {
             requirements.memory_type_bits |= info.memory_type_bits.unwrap();
         }
 
-        let allocation = context.allocator()
-            .lock()
-            .unwrap()
-            .allocate(&AllocationCreateDesc {
+        let allocation = bind_memory(
+            &context,
+            buffer,
+            &AllocationCreateDesc {
                 name: info.name,
                 requirements,
                 location: info.mem_usage,
                 linear: true, // Buffers are always linear
-            }).unwrap();
-        
-        // Bind memory to the buffer
-        unsafe { context.device().bind_buffer_memory(buffer, allocation.memory(), allocation.offset()).unwrap() };
+            },
+        )?;
+        context.debug_marker().set_name(buffer, info.name);
 
-        Buffer {
+        Ok(Buffer {
             context: context.clone(),
             handle: buffer,
+            size: device_size,
             element_count,
             allocation,
+            usage: create_info.usage,
             index_type: info.index_type,
-        }
+        })
     }
 
     pub fn from_data<T: Copy>(
         context: Arc<Context>,
         info: BufferInfo,
         data: &[T],
-    ) -> Self {
+    ) -> Result<Self> {
         assert!(!data.is_empty());
+        check_usage(&context, &info)?;
 
         let device_size = std::mem::size_of_val(data) as u64;
         let mut create_info = vk::BufferCreateInfo::builder()
@@ -156,30 +278,31 @@ impl Buffer {
             create_info.usage |= vk::BufferUsageFlags::TRANSFER_DST;
         }
 
-        let buffer = unsafe { context.device().create_buffer(&create_info, None) }.unwrap();
+        let buffer = unsafe { context.device().create_buffer(&create_info, None) }?;
         let mut requirements = unsafe { context.device().get_buffer_memory_requirements(buffer) };
         if info.memory_type_bits.is_some() {
             requirements.memory_type_bits |= info.memory_type_bits.unwrap();
         }
 
-        let allocation = context.allocator()
-            .lock()
-            .unwrap()
-            .allocate(&AllocationCreateDesc {
+        let allocation = bind_memory(
+            &context,
+            buffer,
+            &AllocationCreateDesc {
                 name: info.name,
                 requirements,
                 location: info.mem_usage,
                 linear: true, // Buffers are always linear
-            }).unwrap();
-
-        // Bind memory to the buffer
-        unsafe { context.device().bind_buffer_memory(buffer, allocation.memory(), allocation.offset()).unwrap() };
+            },
+        )?;
+        context.debug_marker().set_name(buffer, info.name);
 
         let result  = Buffer {
             context: context.clone(),
             handle: buffer,
+            size: device_size,
             element_count: data.len() as u32,
             allocation,
+            usage: create_info.usage,
             index_type: info.index_type,
         };
 
@@ -192,7 +315,7 @@ impl Buffer {
                         .usage(vk::BufferUsageFlags::TRANSFER_SRC),
                     device_size,
                     1,
-                );
+                )?;
                 staging_buffer.update(data);
 
                 let cmd = context.begin_single_time_cmd();
@@ -208,7 +331,7 @@ impl Buffer {
                 result.update(data);
             }
         }
-        result
+        Ok(result)
     }
 
     pub fn update<T: Copy>(&self, data: &[T]) {
@@ -280,6 +403,130 @@ impl Buffer {
             )
         }
     }
+
+    // Typed address of the first element, the buffer needs SHADER_DEVICE_ADDRESS usage.
+    pub fn get_address<T>(&self) -> DeviceAddress<T> {
+        DeviceAddress::new(self.get_device_address())
+    }
+
+    pub fn get_usage(&self) -> vk::BufferUsageFlags {
+        self.usage
+    }
+
+    // Blocking copy of the buffer contents as `T` elements, e.g. counters written by a compute
+    // shader to GpuOnly memory. The buffer needs TRANSFER_SRC usage.
+    pub fn read_to_vec<T: Pod>(&self) -> Result<Vec<T>> {
+        self.check_readback()?;
+        let cmd = self.context.begin_single_time_cmd();
+        let readback = self.cmd_readback(cmd)?;
+        self.context.end_single_time_cmd(cmd);
+        Ok(readback.finish())
+    }
+
+    // Submits the copy of read_to_vec with a fence, without waiting for it.
+    pub fn read_to_vec_async<T: Pod>(&self) -> Result<BufferReadback<'_, T>> {
+        self.check_readback()?;
+        let mut batch = self.context.begin_batch();
+        let readback = self.cmd_readback(batch.begin_cmd())?;
+        Ok(BufferReadback {
+            batch: batch.submit(),
+            readback,
+        })
+    }
+
+    // Records the copy of read_to_vec in `cmd`, after the writes of any previous command. The
+    // readback can be finished once `cmd` has completed execution. Nothing is recorded on error.
+    pub fn cmd_readback<T: Pod>(&self, cmd: vk::CommandBuffer) -> Result<PendingBufferReadback<T>> {
+        self.check_readback()?;
+        let count = self.size as usize / std::mem::size_of::<T>();
+        assert!(count > 0, "Buffer smaller than a single readback element.");
+        let size = (count * std::mem::size_of::<T>()) as vk::DeviceSize;
+        let buffer = Buffer::new(
+            self.context.clone(),
+            BufferInfo::default()
+                .name("BufferReadback")
+                .gpu_to_cpu()
+                .usage_transfer_dst(),
+            size,
+            count as u32,
+        )
+        .expect("Unable to create readback buffer");
+        let read_barrier = BufferBarrier2::new(self.handle)
+            .src(
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                vk::AccessFlags2::MEMORY_WRITE,
+            )
+            .dst(
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_READ,
+            );
+        let host_barrier = BufferBarrier2::new(buffer.handle)
+            .src(
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            )
+            .dst(vk::PipelineStageFlags2::HOST, vk::AccessFlags2::HOST_READ);
+        let region = vk::BufferCopy::builder().size(size).build();
+        self.context
+            .cmd_pipeline_barrier2(cmd, &[], &[read_barrier], &[]);
+        unsafe {
+            self.context
+                .device()
+                .cmd_copy_buffer(cmd, self.handle, buffer.handle, &[region]);
+        }
+        self.context
+            .cmd_pipeline_barrier2(cmd, &[], &[host_barrier], &[]);
+        Ok(PendingBufferReadback {
+            buffer,
+            count,
+            _element: PhantomData,
+        })
+    }
+
+    fn check_readback(&self) -> Result<()> {
+        if !self.usage.contains(vk::BufferUsageFlags::TRANSFER_SRC) {
+            eprintln!("Buffer readbacks require TRANSFER_SRC usage.");
+            return Err(Error::Vulkan(vk::Result::ERROR_VALIDATION_FAILED_EXT));
+        }
+        Ok(())
+    }
+}
+
+// Buffer copy recorded by Buffer::cmd_readback, not yet executed.
+pub struct PendingBufferReadback<T> {
+    buffer: Buffer,
+    count: usize,
+    _element: PhantomData<T>,
+}
+
+impl<T: Pod> PendingBufferReadback<T> {
+    // The command buffer of the copy must have completed execution.
+    pub fn finish(self) -> Vec<T> {
+        let mut elements = vec![T::zeroed(); self.count];
+        let bytes = bytemuck::cast_slice_mut::<T, u8>(&mut elements);
+        // The mapped memory may be less aligned than `T`.
+        let mapped = unsafe { std::slice::from_raw_parts(self.buffer.map(), bytes.len()) };
+        bytes.copy_from_slice(mapped);
+        elements
+    }
+}
+
+// Submitted Buffer::read_to_vec_async. Not Send, as the PendingBatch it waits on.
+pub struct BufferReadback<'a, T> {
+    batch: PendingBatch<'a>,
+    readback: PendingBufferReadback<T>,
+}
+
+impl<T: Pod> BufferReadback<'_, T> {
+    pub fn is_complete(&self) -> bool {
+        self.batch.is_complete()
+    }
+
+    // Waits for the copy when it isn't complete yet.
+    pub fn wait(self) -> Vec<T> {
+        self.batch.wait();
+        self.readback.finish()
+    }
 }
 
 impl Resource<vk::Buffer> for Buffer {
@@ -293,11 +540,9 @@ impl Drop for Buffer {
         unsafe {
             self.context.device().destroy_buffer(self.handle, None);
         }
+        self.context.shared().retire_handle(self.handle);
         
         let to_drop = std::mem::replace(&mut self.allocation, Allocation::default());
-        self.context.allocator()
-            .lock()
-            .unwrap()
-            .free(to_drop).unwrap();
+        self.context.free_memory(to_drop).unwrap();
     }
 }
@@ -0,0 +1,236 @@
+use ash::{vk, Instance};
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fmt;
+
+// Not in the ash bindings yet (Vulkan 1.3.216).
+pub const KHR_PORTABILITY_ENUMERATION_NAME: &[u8] = b"VK_KHR_portability_enumeration\0";
+pub const INSTANCE_CREATE_ENUMERATE_PORTABILITY_KHR: vk::InstanceCreateFlags =
+    vk::InstanceCreateFlags::from_raw(0x1);
+
+// What the physical device supports of the features sol uses, read before device creation.
+// Unsupported optional features are skipped instead of failing device creation, e.g. on
+// portability implementations such as MoltenVK, which have no ray tracing nor shaderInt64.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCapabilities {
+    pub device_name: String,
+    pub api_version: u32,
+    // VK_KHR_portability_subset: a non-conformant implementation layered over another API.
+    pub portability_subset: bool,
+    pub ray_tracing: bool,
+    // Extensions and features ray tracing lacks, see SharedContext::require_ray_tracing.
+    pub missing_ray_tracing_support: Vec<String>,
+    // Only checked when requested, see RendererSettings::ray_query.
+    pub ray_query: bool,
+    // Only checked when requested, see RendererSettings::mesh_shader.
+    pub mesh_shader: bool,
+    pub bindless: bool,
+    pub shader_int64: bool,
+    pub sampler_anisotropy: bool,
+    pub multi_draw_indirect: bool,
+    pub draw_indirect_count: bool,
+    pub push_descriptor: bool,
+    // Core in Vulkan 1.2, see BufferInfo::usage_device_address and DeviceAddress.
+    pub buffer_device_address: bool,
+    pub scalar_block_layout: bool,
+    // Core in Vulkan 1.2, see AsyncCompute.
+    pub timeline_semaphore: bool,
+    // VK_EXT_memory_budget on Vulkan 1.1 and later, see SharedContext::memory_report.
+    pub memory_budget: bool,
+    pub core_features: CoreFeatures,
+    // Device extensions sol enables when available which aren't.
+    pub missing_extensions: Vec<String>,
+}
+
+impl DeviceCapabilities {
+    pub fn new(
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+        supported_extensions: &HashSet<String>,
+        wanted_extensions: &[&CStr],
+    ) -> Self {
+        let supports = |name: &CStr| supported_extensions.contains(name.to_str().unwrap());
+        let (properties, features) = unsafe {
+            (
+                instance.get_physical_device_properties(pdevice),
+                instance.get_physical_device_features(pdevice),
+            )
+        };
+        let device_name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+        DeviceCapabilities {
+            device_name: device_name.to_string_lossy().into_owned(),
+            api_version: properties.api_version,
+            portability_subset: supports(vk::KhrPortabilitySubsetFn::name()),
+            ray_tracing: false,
+            missing_ray_tracing_support: Vec::new(),
+            ray_query: false,
+            mesh_shader: false,
+            bindless: false,
+            shader_int64: features.shader_int64 == vk::TRUE,
+            sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+            multi_draw_indirect: features.multi_draw_indirect == vk::TRUE,
+            draw_indirect_count: supports(ash::extensions::khr::DrawIndirectCount::name()),
+            push_descriptor: supports(ash::extensions::khr::PushDescriptor::name()),
+            buffer_device_address: false,
+            scalar_block_layout: false,
+            timeline_semaphore: false,
+            memory_budget: false,
+            core_features: CoreFeatures::default(),
+            missing_extensions: wanted_extensions
+                .iter()
+                .filter(|name| !supports(name))
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for DeviceCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} (Vulkan {}.{}.{}){}",
+            self.device_name,
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version),
+            if self.portability_subset {
+                ", portability subset"
+            } else {
+                ""
+            }
+        )?;
+        let features = [
+            ("ray tracing", self.ray_tracing),
+            ("bindless", self.bindless),
+            ("shaderInt64", self.shader_int64),
+            ("samplerAnisotropy", self.sampler_anisotropy),
+            ("multiDrawIndirect", self.multi_draw_indirect),
+            ("draw indirect count", self.draw_indirect_count),
+            ("push descriptors", self.push_descriptor),
+            ("bufferDeviceAddress", self.buffer_device_address),
+            ("scalarBlockLayout", self.scalar_block_layout),
+            ("timelineSemaphore", self.timeline_semaphore),
+            ("memory budget", self.memory_budget),
+        ];
+        let unsupported = features
+            .iter()
+            .filter(|(_, supported)| !supported)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        if !unsupported.is_empty() {
+            writeln!(f, "  Unsupported: {}", unsupported.join(", "))?;
+        }
+        if !self.ray_tracing {
+            writeln!(
+                f,
+                "  Ray tracing lacks: {}",
+                self.missing_ray_tracing_support.join(", ")
+            )?;
+        }
+        if !self.missing_extensions.is_empty() {
+            writeln!(
+                f,
+                "  Missing extensions: {}",
+                self.missing_extensions.join(", ")
+            )?;
+        }
+        writeln!(f, "  {}", self.core_features)
+    }
+}
+
+// How a Vulkan 1.3 feature is provided, core features being preferred over their extension.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FeatureSource {
+    #[default]
+    Unsupported,
+    Extension,
+    Core,
+}
+
+impl FeatureSource {
+    pub fn is_supported(self) -> bool {
+        self != FeatureSource::Unsupported
+    }
+}
+
+// Vulkan 1.3 features enabled on the device, from the core API when both the instance and the
+// device support 1.3 (see RendererSettings::api_version), else from their extensions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CoreFeatures {
+    // Version used with the device, the lowest of the instance and device versions.
+    pub api_version: u32,
+    pub dynamic_rendering: FeatureSource,
+    pub synchronization2: FeatureSource,
+    pub maintenance4: FeatureSource,
+}
+
+impl CoreFeatures {
+    pub fn new(
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+        instance_api_version: u32,
+        supported_extensions: &HashSet<String>,
+    ) -> Self {
+        let device_api_version =
+            unsafe { instance.get_physical_device_properties(pdevice).api_version };
+        let api_version = instance_api_version.min(device_api_version);
+        let core = api_version >= vk::API_VERSION_1_3;
+        let available = |name: &CStr| core || supported_extensions.contains(name.to_str().unwrap());
+
+        // Only structures of available features may be chained.
+        let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut synchronization2 = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut maintenance4 = vk::PhysicalDeviceMaintenance4Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder();
+        if available(vk::KhrDynamicRenderingFn::name()) {
+            features = features.push_next(&mut dynamic_rendering);
+        }
+        if available(vk::KhrSynchronization2Fn::name()) {
+            features = features.push_next(&mut synchronization2);
+        }
+        if available(vk::KhrMaintenance4Fn::name()) {
+            features = features.push_next(&mut maintenance4);
+        }
+        unsafe { instance.get_physical_device_features2(pdevice, &mut features) };
+
+        let source = |supported: vk::Bool32| match (supported == vk::TRUE, core) {
+            (false, _) => FeatureSource::Unsupported,
+            (true, false) => FeatureSource::Extension,
+            (true, true) => FeatureSource::Core,
+        };
+        CoreFeatures {
+            api_version,
+            dynamic_rendering: source(dynamic_rendering.dynamic_rendering),
+            synchronization2: source(synchronization2.synchronization2),
+            maintenance4: source(maintenance4.maintenance4),
+        }
+    }
+
+    // Extensions providing the features which aren't core.
+    pub fn extensions(&self) -> Vec<&'static CStr> {
+        [
+            (self.dynamic_rendering, vk::KhrDynamicRenderingFn::name()),
+            (self.synchronization2, vk::KhrSynchronization2Fn::name()),
+            (self.maintenance4, vk::KhrMaintenance4Fn::name()),
+        ]
+        .iter()
+        .filter(|(source, _)| *source == FeatureSource::Extension)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
+impl fmt::Display for CoreFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Vulkan {}.{}: dynamic rendering {:?}, synchronization2 {:?}, maintenance4 {:?}",
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            self.dynamic_rendering,
+            self.synchronization2,
+            self.maintenance4
+        )
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static VALIDATION_ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
+
+// Called from the debug-utils messenger for every ERROR severity message.
+pub(crate) fn notify_validation_error() {
+    VALIDATION_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn validation_error_count() -> u32 {
+    VALIDATION_ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+// Programmatic frame captures through the RenderDoc in-application API. The API is only
+// available when the application was launched from (or injected by) RenderDoc, otherwise every
+// call is a no-op. Without the `renderdoc` feature, this is always a no-op.
+pub struct FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<renderdoc::RenderDoc<renderdoc::V141>>,
+    capture_on_validation_error: bool,
+    seen_validation_errors: u32,
+}
+
+impl FrameCapture {
+    pub fn new(name: &str, capture_on_validation_error: bool) -> Self {
+        #[cfg(feature = "renderdoc")]
+        let renderdoc = match renderdoc::RenderDoc::<renderdoc::V141>::new() {
+            Ok(mut rd) => {
+                rd.set_capture_file_path_template(format!("captures/{}", name));
+                Some(rd)
+            }
+            Err(_) => None,
+        };
+        #[cfg(not(feature = "renderdoc"))]
+        let _ = name;
+        FrameCapture {
+            #[cfg(feature = "renderdoc")]
+            renderdoc,
+            capture_on_validation_error,
+            seen_validation_errors: validation_error_count(),
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        return self.renderdoc.is_some();
+        #[cfg(not(feature = "renderdoc"))]
+        return false;
+    }
+
+    // Captures the next presented frame.
+    pub fn trigger_capture(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(rd) = self.renderdoc.as_mut() {
+            rd.trigger_capture();
+            return;
+        }
+        println!("RenderDoc is not attached, ignoring capture request.");
+    }
+
+    // Attaches a comment to the most recent capture, e.g. the validation message that caused it.
+    pub fn set_capture_comments(&mut self, comments: &str) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(rd) = self.renderdoc.as_mut() {
+            rd.set_capture_file_comments(None, comments);
+        }
+        #[cfg(not(feature = "renderdoc"))]
+        let _ = comments;
+    }
+
+    // Called once per frame by the main loop. Triggers a single capture of the frame following
+    // the first new validation error.
+    pub(crate) fn end_frame(&mut self) {
+        let errors = validation_error_count();
+        if errors > self.seen_validation_errors && self.capture_on_validation_error {
+            if self.is_available() {
+                println!("Validation error detected, capturing next frame.");
+                self.trigger_capture();
+            }
+            self.capture_on_validation_error = false;
+        }
+        self.seen_validation_errors = errors;
+    }
+}
@@ -0,0 +1,121 @@
+use crate::Context;
+use ash::vk;
+use std::marker::PhantomData;
+
+// One-time command buffers submitted together to the graphics queue with a single fence, instead
+// of a submit and a queue wait per Context::end_single_time_cmd. Command buffers execute in
+// recording order, commands depending on earlier ones still need barriers. Not Send: the command
+// buffers belong to the transient command pool of the recording thread.
+pub struct CommandBatch<'a> {
+    context: &'a Context,
+    command_buffers: Vec<vk::CommandBuffer>,
+    _thread: PhantomData<*const ()>,
+}
+
+impl<'a> CommandBatch<'a> {
+    pub fn new(context: &'a Context) -> Self {
+        CommandBatch {
+            context,
+            command_buffers: Vec::new(),
+            _thread: PhantomData,
+        }
+    }
+
+    // Begins a new command buffer, ended on submission.
+    pub fn begin_cmd(&mut self) -> vk::CommandBuffer {
+        let cmd = self.context.begin_single_time_cmd();
+        self.command_buffers.push(cmd);
+        cmd
+    }
+
+    pub fn record(&mut self, f: impl FnOnce(vk::CommandBuffer)) {
+        f(self.begin_cmd());
+    }
+
+    pub fn len(&self) -> usize {
+        self.command_buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.command_buffers.is_empty()
+    }
+
+    // Submits the command buffers without waiting, see PendingBatch.
+    pub fn submit(mut self) -> PendingBatch<'a> {
+        let device = self.context.device();
+        let command_buffers = std::mem::take(&mut self.command_buffers);
+        unsafe {
+            for cmd in &command_buffers {
+                device.end_command_buffer(*cmd).unwrap();
+            }
+            let fence = device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .unwrap();
+            let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+            self.context
+                .queue_submit(
+                    self.context.graphics_queue(),
+                    std::slice::from_ref(&submit_info),
+                    fence,
+                )
+                .expect("Batch submit failed.");
+            PendingBatch {
+                context: self.context,
+                command_buffers,
+                fence,
+                _thread: PhantomData,
+            }
+        }
+    }
+
+    pub fn submit_and_wait(self) {
+        self.submit().wait();
+    }
+}
+
+impl Drop for CommandBatch<'_> {
+    // Discards the command buffers which were not submitted.
+    fn drop(&mut self) {
+        if !self.command_buffers.is_empty() {
+            self.context
+                .free_transient_command_buffers(&self.command_buffers);
+        }
+    }
+}
+
+// Submitted CommandBatch. Dropping it waits for the commands to complete before freeing them.
+pub struct PendingBatch<'a> {
+    context: &'a Context,
+    command_buffers: Vec<vk::CommandBuffer>,
+    fence: vk::Fence,
+    _thread: PhantomData<*const ()>,
+}
+
+impl PendingBatch<'_> {
+    pub fn is_complete(&self) -> bool {
+        unsafe {
+            self.context
+                .device()
+                .get_fence_status(self.fence)
+                .unwrap_or(false)
+        }
+    }
+
+    pub fn wait(self) {
+        drop(self);
+    }
+}
+
+impl Drop for PendingBatch<'_> {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .expect("Wait for batch failed.");
+            device.destroy_fence(self.fence, None);
+        }
+        self.context
+            .free_transient_command_buffers(&self.command_buffers);
+    }
+}
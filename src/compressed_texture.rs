@@ -0,0 +1,473 @@
+use crate::{Error, Result};
+use ash::vk;
+use std::convert::TryInto;
+use std::path::Path;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const KTX2_HEADER_SIZE: usize = 80;
+const KTX2_LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const DDS_HEADER_SIZE: usize = 128;
+const DDS_DX10_HEADER_SIZE: usize = 20;
+const DDS_PIXEL_FORMAT_FOURCC: u32 = 0x4;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+const DDS_CAPS2_CUBEMAP: u32 = 0x200;
+const DDS_CAPS2_VOLUME: u32 = 0x20_0000;
+
+// Mip level of a CompressedImage, with its byte range in CompressedImage::data.
+#[derive(Clone, Copy, Debug)]
+pub struct CompressedLevel {
+    pub offset: usize,
+    pub size: usize,
+    pub extent: vk::Extent2D,
+}
+
+// Pre-compressed (BCn, ASTC, ETC2) or raw texels of a KTX2 or DDS file, uploaded as is with
+// all of their mips, see Texture2d::from_compressed. Only single layer 2D images without
+// supercompression are supported.
+#[derive(Clone, Debug)]
+pub struct CompressedImage {
+    pub name: String,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    // Largest level first.
+    pub levels: Vec<CompressedLevel>,
+    pub data: Vec<u8>,
+}
+
+impl CompressedImage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).map_err(|source| Error::io(path, source))?;
+        Self::from_bytes(&path.to_string_lossy(), data)
+    }
+
+    // Detects the container from its magic number.
+    pub fn from_bytes(name: &str, data: Vec<u8>) -> Result<Self> {
+        if data.starts_with(&KTX2_IDENTIFIER) {
+            Self::from_ktx2(name, data)
+        } else if data.starts_with(DDS_MAGIC) {
+            Self::from_dds(name, data)
+        } else {
+            Err(invalid(name, "not a KTX2 nor a DDS file"))
+        }
+    }
+
+    pub fn from_ktx2(name: &str, data: Vec<u8>) -> Result<Self> {
+        let reader = Reader { name, data: &data };
+        reader.check_size(KTX2_HEADER_SIZE)?;
+        let format = vk::Format::from_raw(reader.u32(12) as i32);
+        let extent = vk::Extent2D {
+            width: reader.u32(20),
+            height: reader.u32(24),
+        };
+        let (depth, layers, faces) = (reader.u32(28), reader.u32(32), reader.u32(36));
+        // 0 asks the loader to generate the mips.
+        let level_count = level_count(extent, reader.u32(40)) as usize;
+        let supercompression = reader.u32(44);
+        if format == vk::Format::UNDEFINED {
+            return Err(invalid(
+                name,
+                "Basis Universal textures must be transcoded first",
+            ));
+        }
+        if supercompression != 0 {
+            return Err(invalid(name, "supercompressed KTX2 files are unsupported"));
+        }
+        if depth > 1 || layers > 1 || faces != 1 {
+            return Err(invalid(
+                name,
+                "only 2D textures without layers are supported",
+            ));
+        }
+        check_extent(name, extent)?;
+        let (_, _, block_size) =
+            block_info(format).ok_or_else(|| invalid(name, "unsupported format"))?;
+        reader.check_size(KTX2_HEADER_SIZE + level_count * KTX2_LEVEL_INDEX_ENTRY_SIZE)?;
+        let levels = (0..level_count)
+            .map(|level| {
+                let entry = KTX2_HEADER_SIZE + level * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+                let offset = reader.usize(entry)?;
+                let size = reader.usize(entry + 8)?;
+                let extent = mip_extent(extent, level as u32);
+                if Some(size) != level_size(format, extent) {
+                    return Err(invalid(name, "level size does not match its format"));
+                }
+                // Copies from buffers need offsets aligned to the block size and 4.
+                if offset % block_size as usize != 0 || offset % 4 != 0 {
+                    return Err(invalid(name, "misaligned level"));
+                }
+                reader.check_range(offset, size)?;
+                Ok(CompressedLevel {
+                    offset,
+                    size,
+                    extent,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CompressedImage {
+            name: name.to_string(),
+            format,
+            extent,
+            levels,
+            data,
+        })
+    }
+
+    pub fn from_dds(name: &str, data: Vec<u8>) -> Result<Self> {
+        let reader = Reader { name, data: &data };
+        reader.check_size(DDS_HEADER_SIZE)?;
+        let extent = vk::Extent2D {
+            width: reader.u32(16),
+            height: reader.u32(12),
+        };
+        let level_count = level_count(extent, reader.u32(28));
+        let pixel_format_flags = reader.u32(80);
+        let four_cc = &data[84..88];
+        if reader.u32(112) & (DDS_CAPS2_CUBEMAP | DDS_CAPS2_VOLUME) != 0 {
+            return Err(invalid(
+                name,
+                "only 2D textures without layers are supported",
+            ));
+        }
+        let mut offset = DDS_HEADER_SIZE;
+        let format = if pixel_format_flags & DDS_PIXEL_FORMAT_FOURCC == 0 {
+            // Uncompressed formats are described by bit masks.
+            match (reader.u32(88), reader.u32(92), reader.u32(100)) {
+                (32, 0xff, 0xff_0000) => vk::Format::R8G8B8A8_UNORM,
+                (32, 0xff_0000, 0xff) => vk::Format::B8G8R8A8_UNORM,
+                _ => return Err(invalid(name, "unsupported uncompressed pixel format")),
+            }
+        } else if four_cc == b"DX10" {
+            reader.check_size(DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE)?;
+            let array_size = reader.u32(DDS_HEADER_SIZE + 12);
+            let misc_flags = reader.u32(DDS_HEADER_SIZE + 8);
+            if array_size > 1 || misc_flags & DDS_RESOURCE_MISC_TEXTURECUBE != 0 {
+                return Err(invalid(
+                    name,
+                    "only 2D textures without layers are supported",
+                ));
+            }
+            offset += DDS_DX10_HEADER_SIZE;
+            dxgi_format(reader.u32(DDS_HEADER_SIZE))
+                .ok_or_else(|| invalid(name, "unsupported DXGI format"))?
+        } else {
+            match four_cc {
+                b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+                b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+                b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+                b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+                b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+                b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+                b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+                _ => return Err(invalid(name, "unsupported FourCC format")),
+            }
+        };
+        check_extent(name, extent)?;
+        // Levels are tightly packed, largest first.
+        let mut levels = Vec::new();
+        for level in 0..level_count {
+            let extent = mip_extent(extent, level);
+            let size =
+                level_size(format, extent).ok_or_else(|| invalid(name, "level size overflows"))?;
+            reader.check_range(offset, size)?;
+            levels.push(CompressedLevel {
+                offset,
+                size,
+                extent,
+            });
+            offset += size;
+        }
+        Ok(CompressedImage {
+            name: name.to_string(),
+            format,
+            extent,
+            levels,
+            data,
+        })
+    }
+
+    pub fn is_compressed_path(path: &Path) -> bool {
+        matches!(
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(str::to_lowercase)
+                .as_deref(),
+            Some("ktx2") | Some("dds")
+        )
+    }
+}
+
+struct Reader<'a> {
+    name: &'a str,
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn check_size(&self, size: usize) -> Result<()> {
+        match self.data.len() >= size {
+            true => Ok(()),
+            false => Err(invalid(self.name, "truncated file")),
+        }
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    // Byte range of the file, with `offset` and `size` read from it.
+    fn check_range(&self, offset: usize, size: usize) -> Result<()> {
+        let end = offset
+            .checked_add(size)
+            .ok_or_else(|| invalid(self.name, "level out of the file"))?;
+        self.check_size(end)
+    }
+
+    fn u64(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.data[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn usize(&self, offset: usize) -> Result<usize> {
+        self.u64(offset)
+            .try_into()
+            .map_err(|_| invalid(self.name, "level out of the file"))
+    }
+}
+
+fn invalid(name: &str, reason: &str) -> Error {
+    Error::InvalidTexture {
+        name: name.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn check_extent(name: &str, extent: vk::Extent2D) -> Result<()> {
+    match extent.width == 0 || extent.height == 0 {
+        true => Err(invalid(name, "empty image")),
+        false => Ok(()),
+    }
+}
+
+// Level count of the header, clamped to the full mip chain of `extent`.
+fn level_count(extent: vk::Extent2D, level_count: u32) -> u32 {
+    let full_chain = 32 - extent.width.max(extent.height).leading_zeros();
+    level_count.max(1).min(full_chain.max(1))
+}
+
+// Level extents are only valid below 32 levels, see level_count.
+fn mip_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+    }
+}
+
+// Bytes of a level of `extent`, None when it overflows or the format is unsupported.
+fn level_size(format: vk::Format, extent: vk::Extent2D) -> Option<usize> {
+    let (block_width, block_height, block_size) = block_info(format)?;
+    // Extents are at least 1.
+    let blocks = |texels: u32, block: u32| ((texels - 1) / block + 1) as usize;
+    blocks(extent.width, block_width)
+        .checked_mul(blocks(extent.height, block_height))?
+        .checked_mul(block_size as usize)
+}
+
+// Block width and height in texels and block size in bytes of the supported formats.
+fn block_info(format: vk::Format) -> Option<(u32, u32, u32)> {
+    Some(match format {
+        vk::Format::ASTC_4X4_UNORM_BLOCK | vk::Format::ASTC_4X4_SRGB_BLOCK => (4, 4, 16),
+        vk::Format::ASTC_5X4_UNORM_BLOCK | vk::Format::ASTC_5X4_SRGB_BLOCK => (5, 4, 16),
+        vk::Format::ASTC_5X5_UNORM_BLOCK | vk::Format::ASTC_5X5_SRGB_BLOCK => (5, 5, 16),
+        vk::Format::ASTC_6X5_UNORM_BLOCK | vk::Format::ASTC_6X5_SRGB_BLOCK => (6, 5, 16),
+        vk::Format::ASTC_6X6_UNORM_BLOCK | vk::Format::ASTC_6X6_SRGB_BLOCK => (6, 6, 16),
+        vk::Format::ASTC_8X5_UNORM_BLOCK | vk::Format::ASTC_8X5_SRGB_BLOCK => (8, 5, 16),
+        vk::Format::ASTC_8X6_UNORM_BLOCK | vk::Format::ASTC_8X6_SRGB_BLOCK => (8, 6, 16),
+        vk::Format::ASTC_8X8_UNORM_BLOCK | vk::Format::ASTC_8X8_SRGB_BLOCK => (8, 8, 16),
+        vk::Format::ASTC_10X5_UNORM_BLOCK | vk::Format::ASTC_10X5_SRGB_BLOCK => (10, 5, 16),
+        vk::Format::ASTC_10X6_UNORM_BLOCK | vk::Format::ASTC_10X6_SRGB_BLOCK => (10, 6, 16),
+        vk::Format::ASTC_10X8_UNORM_BLOCK | vk::Format::ASTC_10X8_SRGB_BLOCK => (10, 8, 16),
+        vk::Format::ASTC_10X10_UNORM_BLOCK | vk::Format::ASTC_10X10_SRGB_BLOCK => (10, 10, 16),
+        vk::Format::ASTC_12X10_UNORM_BLOCK | vk::Format::ASTC_12X10_SRGB_BLOCK => (12, 10, 16),
+        vk::Format::ASTC_12X12_UNORM_BLOCK | vk::Format::ASTC_12X12_SRGB_BLOCK => (12, 12, 16),
+        vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK
+        | vk::Format::EAC_R11_UNORM_BLOCK
+        | vk::Format::EAC_R11_SNORM_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => (4, 4, 8),
+        vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK
+        | vk::Format::EAC_R11G11_UNORM_BLOCK
+        | vk::Format::EAC_R11G11_SNORM_BLOCK
+        | vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => (4, 4, 16),
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => (1, 1, 1),
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB | vk::Format::R16_SFLOAT => (1, 1, 2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT => (1, 1, 4),
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT => (1, 1, 8),
+        vk::Format::R32G32B32A32_SFLOAT => (1, 1, 16),
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: u32) -> Option<vk::Format> {
+    Some(match format {
+        2 => vk::Format::R32G32B32A32_SFLOAT,
+        10 => vk::Format::R16G16B16A16_SFLOAT,
+        28 => vk::Format::R8G8B8A8_UNORM,
+        29 => vk::Format::R8G8B8A8_SRGB,
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        87 => vk::Format::B8G8R8A8_UNORM,
+        91 => vk::Format::B8G8R8A8_SRGB,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // KTX2 file of a single 2D image, the level index entries being (offset, size) followed by
+    // `texels` bytes of level data.
+    fn ktx2(
+        format: vk::Format,
+        supercompression: u32,
+        levels: &[(u64, u64)],
+        texels: usize,
+    ) -> Vec<u8> {
+        let mut data = vec![0; KTX2_HEADER_SIZE];
+        data[..12].copy_from_slice(&KTX2_IDENTIFIER);
+        let mut set = |offset: usize, value: u32| {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        set(12, format.as_raw() as u32);
+        set(20, 4);
+        set(24, 4);
+        set(36, 1);
+        set(40, levels.len() as u32);
+        set(44, supercompression);
+        for (offset, size) in levels {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+        }
+        data.resize(data.len() + texels, 0);
+        data
+    }
+
+    // DDS file of a 4x4 image with a FourCC pixel format, followed by `texels` bytes.
+    fn dds(four_cc: &[u8; 4], texels: usize) -> Vec<u8> {
+        let mut data = vec![0; DDS_HEADER_SIZE];
+        data[..4].copy_from_slice(DDS_MAGIC);
+        data[12..16].copy_from_slice(&4u32.to_le_bytes());
+        data[16..20].copy_from_slice(&4u32.to_le_bytes());
+        data[28..32].copy_from_slice(&1u32.to_le_bytes());
+        data[80..84].copy_from_slice(&DDS_PIXEL_FORMAT_FOURCC.to_le_bytes());
+        data[84..88].copy_from_slice(four_cc);
+        data.resize(data.len() + texels, 0);
+        data
+    }
+
+    fn assert_invalid(result: Result<CompressedImage>) {
+        match result {
+            Err(Error::InvalidTexture { .. }) => {}
+            Err(error) => panic!("expected an invalid texture, got {}", error),
+            Ok(_) => panic!("expected an invalid texture"),
+        }
+    }
+
+    const BC1: vk::Format = vk::Format::BC1_RGBA_UNORM_BLOCK;
+    // First byte after the header and the index of a single level.
+    const KTX2_LEVEL_OFFSET: u64 = (KTX2_HEADER_SIZE + KTX2_LEVEL_INDEX_ENTRY_SIZE) as u64;
+
+    #[test]
+    fn valid_files() {
+        let image = CompressedImage::from_bytes("ktx2", ktx2(BC1, 0, &[(KTX2_LEVEL_OFFSET, 8)], 8));
+        assert_eq!(image.unwrap().levels[0].size, 8);
+        let image = CompressedImage::from_bytes("dds", dds(b"DXT1", 8)).unwrap();
+        assert_eq!(image.format, BC1);
+        assert_eq!(image.levels[0].offset, DDS_HEADER_SIZE);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let mut data = ktx2(BC1, 0, &[(KTX2_LEVEL_OFFSET, 8)], 8);
+        data.truncate(KTX2_HEADER_SIZE - 1);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+        let mut data = dds(b"DXT1", 8);
+        data.truncate(DDS_HEADER_SIZE - 1);
+        assert_invalid(CompressedImage::from_dds("dds", data));
+        // The level index is missing.
+        let mut data = ktx2(BC1, 0, &[(KTX2_LEVEL_OFFSET, 8)], 8);
+        data.truncate(KTX2_HEADER_SIZE + 8);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+    }
+
+    #[test]
+    fn level_out_of_the_file() {
+        let data = ktx2(BC1, 0, &[(KTX2_LEVEL_OFFSET + 8, 8)], 8);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+        let data = ktx2(BC1, 0, &[(u64::MAX - 7, 8)], 8);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+        assert_invalid(CompressedImage::from_dds("dds", dds(b"DXT1", 7)));
+    }
+
+    #[test]
+    fn level_size_mismatch() {
+        let data = ktx2(BC1, 0, &[(KTX2_LEVEL_OFFSET, 16)], 16);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+        let data = ktx2(BC1, 0, &[(KTX2_LEVEL_OFFSET, 4)], 8);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+    }
+
+    #[test]
+    fn unsupported_format() {
+        let data = ktx2(vk::Format::D32_SFLOAT, 0, &[(KTX2_LEVEL_OFFSET, 64)], 64);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+        let data = ktx2(vk::Format::UNDEFINED, 0, &[(KTX2_LEVEL_OFFSET, 8)], 8);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+        assert_invalid(CompressedImage::from_dds("dds", dds(b"ABCD", 8)));
+        assert_invalid(CompressedImage::from_bytes(
+            "unknown",
+            vec![0; DDS_HEADER_SIZE],
+        ));
+    }
+
+    #[test]
+    fn supercompression() {
+        let data = ktx2(BC1, 1, &[(KTX2_LEVEL_OFFSET, 8)], 8);
+        assert_invalid(CompressedImage::from_ktx2("ktx2", data));
+    }
+}
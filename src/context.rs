@@ -1,17 +1,26 @@
 use crate::*;
+use ash::prelude::VkResult;
 use ash::{
     extensions::{ext::DebugUtils, khr},
     vk, Device, Entry, Instance,
 };
-use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, Allocator, AllocatorCreateDesc};
+use gpu_allocator::Result as AllocationResult;
 use std::borrow::Cow;
 use std::mem::ManuallyDrop;
+use std::path::PathBuf;
 use std::ffi::{CStr, CString};
 use std::{
-    collections::{HashSet},
+    collections::{HashMap, HashSet},
     os::raw::c_char
 };
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread::ThreadId;
+
+// Runtime filters of the debug messenger, see SharedContext::set_debug_message_filter.
+static DEBUG_MESSAGE_SEVERITY: AtomicU32 = AtomicU32::new(0);
+static DEBUG_MESSAGE_TYPE: AtomicU32 = AtomicU32::new(0);
 
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
@@ -19,6 +28,11 @@ unsafe extern "system" fn vulkan_debug_callback(
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
     _user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
+    if message_severity.as_raw() & DEBUG_MESSAGE_SEVERITY.load(Ordering::Relaxed) == 0
+        || message_type.as_raw() & DEBUG_MESSAGE_TYPE.load(Ordering::Relaxed) == 0
+    {
+        return vk::FALSE;
+    }
     let callback_data = *p_callback_data;
     let message_id_number: i32 = callback_data.message_id_number as i32;
 
@@ -34,6 +48,10 @@ unsafe extern "system" fn vulkan_debug_callback(
         CStr::from_ptr(callback_data.p_message).to_string_lossy()
     };
 
+    if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        notify_validation_error();
+    }
+
     println!(
         "{:?}:\n{:?} [{} ({})] : {}\n",
         message_severity,
@@ -46,14 +64,24 @@ unsafe extern "system" fn vulkan_debug_callback(
     vk::FALSE
 }
 
+fn set_debug_message_filter(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+) {
+    DEBUG_MESSAGE_SEVERITY.store(severity.as_raw(), Ordering::Relaxed);
+    DEBUG_MESSAGE_TYPE.store(message_type.as_raw(), Ordering::Relaxed);
+}
+
+// Without a window, the graphics family is also used as the present family. The compute family
+// is a dedicated one, without graphics, if the device has any.
 fn find_queue_families(
     instance: &Instance,
-    surface: &khr::Surface,
-    surface_khr: vk::SurfaceKHR,
+    window: Option<&Window>,
     device: vk::PhysicalDevice,
-) -> (Option<u32>, Option<u32>) {
+) -> (Option<u32>, Option<u32>, Option<u32>) {
     let mut graphics = None;
     let mut present = None;
+    let mut compute = None;
 
     let props = unsafe { instance.get_physical_device_queue_family_properties(device) };
     for (index, family) in props.iter().filter(|f| f.queue_count > 0).enumerate() {
@@ -66,38 +94,438 @@ fn find_queue_families(
             graphics = Some(index);
         }
 
-        let present_support = unsafe {
-            surface
-                .get_physical_device_surface_support(device, index, surface_khr)
-                .expect("Failed to get surface support")
+        let present_support = match window {
+            Some(window) => unsafe { window.get_surface_support(device, index) },
+            None => true,
         };
         if present_support && present.is_none() {
             present = Some(index);
         }
 
-        if graphics.is_some() && present.is_some() {
-            break;
+        if family.queue_flags.contains(vk::QueueFlags::COMPUTE)
+            && !family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            && compute.is_none()
+        {
+            compute = Some(index);
         }
     }
 
-    (graphics, present)
+    if window.is_none() {
+        present = graphics;
+    }
+    (graphics, present, compute)
+}
+
+fn supported_device_extensions(instance: &Instance, device: vk::PhysicalDevice) -> HashSet<String> {
+    unsafe {
+        let extension_properties = instance
+            .enumerate_device_extension_properties(device).unwrap();
+        //dbg!("Extension properties:\n{:#?}", &extension_properties);
+        extension_properties
+            .iter()
+            .map(|ext| {
+                CStr::from_ptr(ext.extension_name.as_ptr() as *const c_char)
+                    .to_string_lossy()
+                    .as_ref()
+                    .to_owned()
+            })
+            .collect()
+    }
+}
+
+fn supported_indexing_features(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+) -> vk::PhysicalDeviceDescriptorIndexingFeatures {
+    let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut indexing_features);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+    indexing_features.p_next = std::ptr::null_mut();
+    indexing_features
+}
+
+// bufferDeviceAddress and scalarBlockLayout support, from the core API in Vulkan 1.2.
+fn supported_address_features(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    api_version: u32,
+    supported_extensions: &HashSet<String>,
+) -> (bool, bool) {
+    let core = api_version >= vk::API_VERSION_1_2;
+    let available = |name: &CStr| core || supported_extensions.contains(name.to_str().unwrap());
+    // Only structures of available features may be chained.
+    let mut address_features = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+    let mut scalar_features = vk::PhysicalDeviceScalarBlockLayoutFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder();
+    if available(vk::KhrBufferDeviceAddressFn::name()) {
+        features = features.push_next(&mut address_features);
+    }
+    if available(vk::ExtScalarBlockLayoutFn::name()) {
+        features = features.push_next(&mut scalar_features);
+    }
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+    (
+        address_features.buffer_device_address == vk::TRUE,
+        scalar_features.scalar_block_layout == vk::TRUE,
+    )
+}
+
+// Core in Vulkan 1.2. VK_KHR_timeline_semaphore isn't used, its functions aren't those called
+// through ash::Device.
+fn supports_timeline_semaphore(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    api_version: u32,
+) -> bool {
+    if api_version < vk::API_VERSION_1_2 {
+        return false;
+    }
+    let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut timeline_features);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+    timeline_features.timeline_semaphore == vk::TRUE
+}
+
+// Extensions and features of ray tracing pipelines the device lacks, none when supported.
+fn missing_ray_tracing_support(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    supported_extensions: &HashSet<String>,
+    buffer_device_address: bool,
+) -> Vec<String> {
+    let mut missing = ray_tracing_extensions()
+        .iter()
+        .filter(|ext| !supported_extensions.contains(ext.to_str().unwrap()))
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return missing;
+    }
+    let mut acceleration_structure_features =
+        vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+    let mut pipeline_features = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut acceleration_structure_features)
+        .push_next(&mut pipeline_features);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+    if acceleration_structure_features.acceleration_structure != vk::TRUE {
+        missing.push("accelerationStructure".to_string());
+    }
+    if pipeline_features.ray_tracing_pipeline != vk::TRUE {
+        missing.push("rayTracingPipeline".to_string());
+    }
+    if !buffer_device_address {
+        missing.push("bufferDeviceAddress".to_string());
+    }
+    missing
+}
+
+// Inline ray queries, along with the ray tracing pipeline extensions they share acceleration
+// structures with.
+fn supports_ray_query(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let supported_extensions = supported_device_extensions(instance, device);
+    [
+        vk::KhrRayQueryFn::name(),
+        vk::KhrAccelerationStructureFn::name(),
+        vk::KhrRayTracingPipelineFn::name(),
+        vk::KhrDeferredHostOperationsFn::name(),
+        vk::KhrPipelineLibraryFn::name(),
+        vk::KhrBufferDeviceAddressFn::name(),
+        vk::KhrVulkanMemoryModelFn::name(),
+    ]
+    .iter()
+    .all(|ext| supported_extensions.contains(ext.to_str().unwrap()))
+}
+
+// Task and mesh shaders of VK_EXT_mesh_shader, whose SPIR-V requires version 1.4.
+fn supports_mesh_shader(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    api_version: u32,
+    supported_extensions: &HashSet<String>,
+) -> bool {
+    if api_version < vk::API_VERSION_1_2
+        || !supported_extensions.contains(MeshShader::name().to_str().unwrap())
+    {
+        return false;
+    }
+    let mut mesh_shader_features = PhysicalDeviceMeshShaderFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut mesh_shader_features);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+    mesh_shader_features.task_shader == vk::TRUE && mesh_shader_features.mesh_shader == vk::TRUE
+}
+
+// Features needed by bindless descriptor arrays, see DescriptorSetLayoutInfo::bindless.
+fn supports_bindless(features: &vk::PhysicalDeviceDescriptorIndexingFeatures) -> bool {
+    features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+        && features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+        && features.descriptor_binding_update_unused_while_pending == vk::TRUE
+        && features.descriptor_binding_variable_descriptor_count == vk::TRUE
 }
 
+// Extensions required by ray tracing pipelines, all enabled when supported.
+fn ray_tracing_extensions() -> [&'static CStr; 6] {
+    [
+        vk::KhrVulkanMemoryModelFn::name(), // used in ray tracing shaders
+        vk::KhrPipelineLibraryFn::name(),   // rt dep
+        vk::KhrDeferredHostOperationsFn::name(), // rt dep
+        vk::KhrBufferDeviceAddressFn::name(), // rt dep
+        vk::KhrAccelerationStructureFn::name(),
+        vk::KhrRayTracingPipelineFn::name(),
+    ]
+}
+
+// Extensions enabled on every device which supports them. Most are core in Vulkan 1.2.
+fn optional_device_extensions() -> [&'static CStr; 11] {
+    [
+        vk::ExtDescriptorIndexingFn::name(),
+        vk::ExtScalarBlockLayoutFn::name(),
+        vk::KhrBufferDeviceAddressFn::name(),
+        vk::KhrMaintenance1Fn::name(),
+        vk::KhrMaintenance2Fn::name(),
+        vk::KhrMaintenance3Fn::name(),
+        vk::KhrGetMemoryRequirements2Fn::name(),
+        vk::KhrImagelessFramebufferFn::name(),
+        vk::KhrImageFormatListFn::name(),
+        vk::KhrDescriptorUpdateTemplateFn::name(),
+        // Rust-GPU
+        vk::KhrShaderFloat16Int8Fn::name(),
+    ]
+}
+
+fn query_capabilities(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    api_version: u32,
+    ray_query: bool,
+    mesh_shader: bool,
+) -> DeviceCapabilities {
+    let supported_extensions = supported_device_extensions(instance, pdevice);
+    let mut capabilities = DeviceCapabilities::new(
+        instance,
+        pdevice,
+        &supported_extensions,
+        &optional_device_extensions(),
+    );
+    capabilities.bindless = supports_bindless(&supported_indexing_features(instance, pdevice));
+    capabilities.core_features =
+        CoreFeatures::new(instance, pdevice, api_version, &supported_extensions);
+    let (buffer_device_address, scalar_block_layout) = supported_address_features(
+        instance,
+        pdevice,
+        capabilities.core_features.api_version,
+        &supported_extensions,
+    );
+    capabilities.buffer_device_address = buffer_device_address;
+    capabilities.scalar_block_layout = scalar_block_layout;
+    capabilities.timeline_semaphore =
+        supports_timeline_semaphore(instance, pdevice, capabilities.core_features.api_version);
+    // Queried with vkGetPhysicalDeviceMemoryProperties2.
+    capabilities.memory_budget = capabilities.core_features.api_version >= vk::API_VERSION_1_1
+        && supported_extensions.contains(vk::ExtMemoryBudgetFn::name().to_str().unwrap());
+    capabilities.missing_ray_tracing_support = missing_ray_tracing_support(
+        instance,
+        pdevice,
+        &supported_extensions,
+        buffer_device_address,
+    );
+    capabilities.ray_tracing = capabilities.missing_ray_tracing_support.is_empty();
+    capabilities.ray_query =
+        ray_query && capabilities.ray_tracing && supports_ray_query(instance, pdevice);
+    capabilities.mesh_shader = mesh_shader
+        && supports_mesh_shader(
+            instance,
+            pdevice,
+            capabilities.core_features.api_version,
+            &supported_extensions,
+        );
+    capabilities
+}
+
+// Features sol enables when supported, merged with those the application requires. Through the
+// Vulkan 1.2 and 1.3 structures when available, their extension structures may not be chained
+// along with them.
+fn enabled_device_features(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    capabilities: &DeviceCapabilities,
+    request: &DeviceFeatureRequest,
+) -> DeviceFeatureRequest {
+    let supported_extensions = supported_device_extensions(instance, device);
+    let supports = |name: &CStr| supported_extensions.contains(name.to_str().unwrap());
+    let supported_features = unsafe { instance.get_physical_device_features(device) };
+    let device_features = vk::PhysicalDeviceFeatures::builder()
+        .sampler_anisotropy(capabilities.sampler_anisotropy)
+        .shader_int64(capabilities.shader_int64)
+        .multi_draw_indirect(supported_features.multi_draw_indirect == vk::TRUE)
+        .draw_indirect_first_instance(supported_features.draw_indirect_first_instance == vk::TRUE)
+        .fill_mode_non_solid(supported_features.fill_mode_non_solid == vk::TRUE)
+        .wide_lines(supported_features.wide_lines == vk::TRUE)
+        .sample_rate_shading(supported_features.sample_rate_shading == vk::TRUE)
+        .shader_storage_image_multisample(
+            supported_features.shader_storage_image_multisample == vk::TRUE,
+        )
+        // Block compressed formats of CompressedImage, see Context::format_supports.
+        .texture_compression_bc(supported_features.texture_compression_bc == vk::TRUE)
+        .texture_compression_astc_ldr(supported_features.texture_compression_astc_ldr == vk::TRUE)
+        .texture_compression_etc2(supported_features.texture_compression_etc2 == vk::TRUE)
+        .build();
+    let mut features = request.clone().features(device_features);
+
+    let api_version = capabilities.core_features.api_version;
+    let bindless = capabilities.bindless;
+    if api_version >= vk::API_VERSION_1_2 {
+        features = features.vulkan12(
+            vk::PhysicalDeviceVulkan12Features::builder()
+                .descriptor_binding_partially_bound(true)
+                .runtime_descriptor_array(true)
+                .shader_sampled_image_array_non_uniform_indexing(bindless)
+                .descriptor_binding_sampled_image_update_after_bind(bindless)
+                .descriptor_binding_update_unused_while_pending(bindless)
+                .descriptor_binding_variable_descriptor_count(bindless)
+                .buffer_device_address(capabilities.buffer_device_address)
+                .scalar_block_layout(capabilities.scalar_block_layout)
+                .timeline_semaphore(capabilities.timeline_semaphore)
+                .build(),
+        );
+    } else {
+        if supports(vk::ExtDescriptorIndexingFn::name()) {
+            features = features.extension(
+                vk::ExtDescriptorIndexingFn::name(),
+                vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+                    .descriptor_binding_partially_bound(true)
+                    .runtime_descriptor_array(true)
+                    .shader_sampled_image_array_non_uniform_indexing(bindless)
+                    .descriptor_binding_sampled_image_update_after_bind(bindless)
+                    .descriptor_binding_update_unused_while_pending(bindless)
+                    .descriptor_binding_variable_descriptor_count(bindless)
+                    .build(),
+            );
+        }
+        if capabilities.buffer_device_address {
+            features = features.extension(
+                vk::KhrBufferDeviceAddressFn::name(),
+                vk::PhysicalDeviceBufferDeviceAddressFeatures::builder()
+                    .buffer_device_address(true)
+                    .build(),
+            );
+        }
+        if capabilities.scalar_block_layout {
+            features = features.extension(
+                vk::ExtScalarBlockLayoutFn::name(),
+                vk::PhysicalDeviceScalarBlockLayoutFeatures::builder()
+                    .scalar_block_layout(true)
+                    .build(),
+            );
+        }
+    }
+
+    let core_features = &capabilities.core_features;
+    if api_version >= vk::API_VERSION_1_3 {
+        features = features.vulkan13(
+            vk::PhysicalDeviceVulkan13Features::builder()
+                .dynamic_rendering(core_features.dynamic_rendering.is_supported())
+                .synchronization2(core_features.synchronization2.is_supported())
+                .maintenance4(core_features.maintenance4.is_supported())
+                .build(),
+        );
+    } else {
+        if core_features.dynamic_rendering.is_supported() {
+            features = features.extension(
+                vk::KhrDynamicRenderingFn::name(),
+                vk::PhysicalDeviceDynamicRenderingFeatures::builder()
+                    .dynamic_rendering(true)
+                    .build(),
+            );
+        }
+        if core_features.synchronization2.is_supported() {
+            features = features.extension(
+                vk::KhrSynchronization2Fn::name(),
+                vk::PhysicalDeviceSynchronization2Features::builder()
+                    .synchronization2(true)
+                    .build(),
+            );
+        }
+        if core_features.maintenance4.is_supported() {
+            features = features.extension(
+                vk::KhrMaintenance4Fn::name(),
+                vk::PhysicalDeviceMaintenance4Features::builder()
+                    .maintenance4(true)
+                    .build(),
+            );
+        }
+    }
+
+    if capabilities.ray_tracing {
+        features = features
+            .extension(
+                vk::KhrAccelerationStructureFn::name(),
+                vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
+                    .acceleration_structure(true)
+                    .build(),
+            )
+            .extension(
+                vk::KhrRayTracingPipelineFn::name(),
+                vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::builder()
+                    .ray_tracing_pipeline(true)
+                    .build(),
+            );
+    }
+    if capabilities.ray_query {
+        features = features.extension(
+            vk::KhrRayQueryFn::name(),
+            vk::PhysicalDeviceRayQueryFeaturesKHR::builder()
+                .ray_query(true)
+                .build(),
+        );
+    }
+    if capabilities.mesh_shader {
+        features = features.extension(
+            MeshShader::name(),
+            PhysicalDeviceMeshShaderFeaturesEXT {
+                task_shader: vk::TRUE,
+                mesh_shader: vk::TRUE,
+                ..Default::default()
+            },
+        );
+    }
+    features
+}
+
+#[allow(clippy::too_many_arguments)]
 fn create_logical_device_with_graphics_queue(
     instance: &Instance,
     device: vk::PhysicalDevice,
     queue_families_indices: QueueFamiliesIndices,
     device_extensions: &Vec<&'static CStr>,
-) -> (Device, vk::Queue, vk::Queue) {
+    device_group: &[vk::PhysicalDevice],
+    headless: bool,
+    capabilities: &DeviceCapabilities,
+    features: &DeviceFeatureRequest,
+) -> (Device, vk::Queue, vk::Queue, vk::Queue, vk::Queue) {
     let graphics_family_index = queue_families_indices.graphics;
     let present_family_index = queue_families_indices.present;
-    let queue_priorities = [1.0f32];
+    let compute_family_index = queue_families_indices.compute;
+    let queue_priorities = [1.0f32, 0.5f32];
+
+    // A second, lower priority graphics queue is used for background work (e.g. acceleration
+    // structure builds) when the family exposes more than one queue.
+    let graphics_queue_count = unsafe {
+        instance.get_physical_device_queue_family_properties(device)[graphics_family_index as usize]
+            .queue_count
+            .min(2)
+    };
 
     let queue_create_infos = {
         // Vulkan specs does not allow passing an array containing duplicated family indices.
         // And since the family for graphics and presentation could be the same we need to
         // deduplicate it.
-        let mut indices = vec![graphics_family_index, present_family_index];
+        let mut indices = vec![
+            graphics_family_index,
+            present_family_index,
+            compute_family_index,
+        ];
+        indices.sort_unstable();
         indices.dedup();
 
         // Now we build an array of `DeviceQueueCreateInfo`.
@@ -105,101 +533,99 @@ fn create_logical_device_with_graphics_queue(
         indices
             .iter()
             .map(|index| {
+                let count = if *index == graphics_family_index {
+                    graphics_queue_count as usize
+                } else {
+                    1
+                };
                 vk::DeviceQueueCreateInfo::builder()
                     .queue_family_index(*index)
-                    .queue_priorities(&queue_priorities)
+                    .queue_priorities(&queue_priorities[..count])
                     .build()
             })
             .collect::<Vec<_>>()
     };
 
-    let supported_extensions: HashSet<String> = unsafe {
-        let extension_properties = instance
-            .enumerate_device_extension_properties(device).unwrap();
-        //dbg!("Extension properties:\n{:#?}", &extension_properties);
-        extension_properties
-            .iter()
-            .map(|ext| {
-                CStr::from_ptr(ext.extension_name.as_ptr() as *const c_char)
-                    .to_string_lossy()
-                    .as_ref()
-                    .to_owned()
-            })
-            .collect()
-    };
-
-    let mut device_extensions_ptrs = vec![
-        vk::ExtDescriptorIndexingFn::name().as_ptr(),
-        vk::ExtScalarBlockLayoutFn::name().as_ptr(),
-        vk::KhrMaintenance1Fn::name().as_ptr(),
-        vk::KhrMaintenance2Fn::name().as_ptr(),
-        vk::KhrMaintenance3Fn::name().as_ptr(),
-        vk::KhrGetMemoryRequirements2Fn::name().as_ptr(),
-        vk::KhrImagelessFramebufferFn::name().as_ptr(),
-        vk::KhrImageFormatListFn::name().as_ptr(),
-        vk::KhrDescriptorUpdateTemplateFn::name().as_ptr(),
-        // Rust-GPU
-        vk::KhrShaderFloat16Int8Fn::name().as_ptr(),
-        // DLSS
-        #[cfg(feature = "dlss")]
-        {
-            b"VK_NVX_binary_import\0".as_ptr() as *const i8
-        },
-        #[cfg(feature = "dlss")]
-        {
-            b"VK_KHR_push_descriptor\0".as_ptr() as *const i8
-        },
-        #[cfg(feature = "dlss")]
+    let supported_extensions = supported_device_extensions(instance, device);
+
+    // Unsupported ones are listed in DeviceCapabilities::missing_extensions.
+    let mut device_extensions_ptrs = optional_device_extensions()
+        .iter()
+        .filter(|ext| supported_extensions.contains(ext.to_str().unwrap()))
+        .map(|ext| ext.as_ptr())
+        .collect::<Vec<_>>();
+    // DLSS
+    #[cfg(feature = "dlss")]
+    device_extensions_ptrs.extend([
+        b"VK_NVX_binary_import\0".as_ptr() as *const i8,
         vk::NvxImageViewHandleFn::name().as_ptr(),
-    ];
-
-    device_extensions_ptrs.push(ash::extensions::khr::Swapchain::name().as_ptr());
+    ]);
 
-    let ray_tracing_extensions = [
-        vk::KhrVulkanMemoryModelFn::name().as_ptr(), // used in ray tracing shaders
-        vk::KhrPipelineLibraryFn::name().as_ptr(),   // rt dep
-        vk::KhrDeferredHostOperationsFn::name().as_ptr(), // rt dep
-        vk::KhrBufferDeviceAddressFn::name().as_ptr(), // rt dep
-        vk::KhrAccelerationStructureFn::name().as_ptr(),
-        vk::KhrRayTracingPipelineFn::name().as_ptr(),
-    ];
-
-    let ray_tracing_enabled = unsafe {
-        ray_tracing_extensions.iter().all(|ext| {
-            let ext = CStr::from_ptr(*ext).to_string_lossy();
+    // Must be enabled when supported, e.g. on MoltenVK.
+    if capabilities.portability_subset {
+        device_extensions_ptrs.push(vk::KhrPortabilitySubsetFn::name().as_ptr());
+    }
 
-            let supported = supported_extensions.contains(ext.as_ref());
+    if !headless {
+        device_extensions_ptrs.push(ash::extensions::khr::Swapchain::name().as_ptr());
+    }
 
-            if !supported {
-                dbg!("Ray tracing extension not supported: {}", ext);
-            }
+    // Optional, used by the multi-draw-indirect helpers when available.
+    if supported_extensions.contains(khr::DrawIndirectCount::name().to_str().unwrap()) {
+        device_extensions_ptrs.push(khr::DrawIndirectCount::name().as_ptr());
+    }
 
-            supported
-        })
-    };
+    // Optional, used by push descriptor set layouts. Required by DLSS.
+    if cfg!(feature = "dlss")
+        || supported_extensions.contains(khr::PushDescriptor::name().to_str().unwrap())
+    {
+        device_extensions_ptrs.push(khr::PushDescriptor::name().as_ptr());
+    }
 
-    if ray_tracing_enabled {
-        dbg!("All ray tracing extensions are supported");
-        device_extensions_ptrs.extend(ray_tracing_extensions.iter());
+    if capabilities.ray_tracing {
+        // Without the optional extensions already enabled, e.g. VK_KHR_buffer_device_address.
+        device_extensions_ptrs.extend(
+            ray_tracing_extensions()
+                .iter()
+                .filter(|ext| !optional_device_extensions().contains(ext))
+                .map(|ext| ext.as_ptr()),
+        );
+    }
+    // Checked by supports_ray_query, which requires the ray tracing extensions.
+    if capabilities.ray_query {
+        device_extensions_ptrs.push(vk::KhrRayQueryFn::name().as_ptr());
+    }
+    if capabilities.memory_budget {
+        device_extensions_ptrs.push(vk::ExtMemoryBudgetFn::name().as_ptr());
     }
+    let core_features = &capabilities.core_features;
+    device_extensions_ptrs.extend(core_features.extensions().iter().map(|ext| ext.as_ptr()));
 
     for ext in device_extensions {
         device_extensions_ptrs.push((*ext).as_ptr());
     }
+    // Those of the feature structures, most already enabled.
+    for ext in features.extension_names() {
+        let enabled = device_extensions_ptrs
+            .iter()
+            .any(|ptr| unsafe { CStr::from_ptr(*ptr) } == ext);
+        if !enabled {
+            device_extensions_ptrs.push(ext.as_ptr());
+        }
+    }
 
-    let device_features = vk::PhysicalDeviceFeatures::builder()
-        .sampler_anisotropy(true)
-        .shader_int64(true);
-
-    let mut indexing_info = vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
-        .descriptor_binding_partially_bound(true)
-        .runtime_descriptor_array(true)
-        .build();
-    let device_create_info = vk::DeviceCreateInfo::builder()
+    let mut device_group_info = vk::DeviceGroupDeviceCreateInfo::builder()
+        .physical_devices(device_group);
+    let mut device_create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_create_infos)
-        .enabled_extension_names(&device_extensions_ptrs)
-        .enabled_features(&device_features)
-        .push_next(&mut indexing_info);
+        .enabled_extension_names(&device_extensions_ptrs);
+    if device_group.len() > 1 {
+        device_create_info = device_create_info.push_next(&mut device_group_info);
+    }
+    // Owns the feature structures chained to the create info.
+    let mut feature_chain = features.feature_chain();
+    let mut device_create_info = device_create_info.build();
+    unsafe { feature_chain.chain_to(&mut device_create_info) };
 
     // Build device and queues
     let device = unsafe {
@@ -209,14 +635,89 @@ fn create_logical_device_with_graphics_queue(
     };
     let graphics_queue = unsafe { device.get_device_queue(graphics_family_index, 0) };
     let present_queue = unsafe { device.get_device_queue(present_family_index, 0) };
+    let async_queue =
+        unsafe { device.get_device_queue(graphics_family_index, graphics_queue_count - 1) };
+    let compute_queue = match compute_family_index == graphics_family_index {
+        true => async_queue,
+        false => unsafe { device.get_device_queue(compute_family_index, 0) },
+    };
 
-    (device, graphics_queue, present_queue)
+    (
+        device,
+        graphics_queue,
+        present_queue,
+        async_queue,
+        compute_queue,
+    )
+}
+
+// Returns the physical devices of the first group with more than one member containing `pdevice`.
+fn find_device_group(instance: &Instance, pdevice: vk::PhysicalDevice) -> Vec<vk::PhysicalDevice> {
+    unsafe {
+        let count = instance
+            .enumerate_physical_device_groups_len()
+            .expect("Physical device group error");
+        let mut groups = vec![vk::PhysicalDeviceGroupProperties::default(); count];
+        instance
+            .enumerate_physical_device_groups(&mut groups)
+            .expect("Physical device group error");
+        groups
+            .iter()
+            .map(|group| group.physical_devices[..group.physical_device_count as usize].to_vec())
+            .find(|devices| devices.len() > 1 && devices.contains(&pdevice))
+            .unwrap_or_default()
+    }
+}
+
+// Candidate formats in order of preference for find_supported_format.
+// D16_UNORM depth attachments are always supported, so depth lookups never fail.
+pub const DEPTH_FORMATS: [vk::Format; 4] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
+// Render targets that are sampled later (post-processing, tonemapping).
+pub const HDR_COLOR_FORMATS: [vk::Format; 3] = [
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::B10G11R11_UFLOAT_PACK32,
+    vk::Format::R32G32B32A32_SFLOAT,
+];
+// Storage images written by compute/raytracing shaders. Note that the fallback changes the
+// precision, shaders declaring an explicit format qualifier (e.g. rgba32f) need to match it.
+pub const HDR_STORAGE_FORMATS: [vk::Format; 2] = [
+    vk::Format::R32G32B32A32_SFLOAT,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+// Format features required to create an image with `usage`.
+pub fn format_features_for_usage(usage: vk::ImageUsageFlags) -> vk::FormatFeatureFlags {
+    let mut features = vk::FormatFeatureFlags::empty();
+    let pairs = [
+        (vk::ImageUsageFlags::SAMPLED, vk::FormatFeatureFlags::SAMPLED_IMAGE),
+        (vk::ImageUsageFlags::STORAGE, vk::FormatFeatureFlags::STORAGE_IMAGE),
+        (vk::ImageUsageFlags::COLOR_ATTACHMENT, vk::FormatFeatureFlags::COLOR_ATTACHMENT),
+        (
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        ),
+        (vk::ImageUsageFlags::TRANSFER_SRC, vk::FormatFeatureFlags::TRANSFER_SRC),
+        (vk::ImageUsageFlags::TRANSFER_DST, vk::FormatFeatureFlags::TRANSFER_DST),
+    ];
+    for (usage_flag, feature) in pairs {
+        if usage.contains(usage_flag) {
+            features |= feature;
+        }
+    }
+    features
 }
 
 #[derive(Clone, Copy)]
 pub struct QueueFamiliesIndices {
     pub graphics: u32,
     pub present: u32,
+    // Dedicated compute family, the graphics one if the device has none.
+    pub compute: u32,
 }
 
 pub struct SharedContext {
@@ -224,35 +725,154 @@ pub struct SharedContext {
     instance: Instance,
     debug_utils_loader: DebugUtils,
     debug_call_back: vk::DebugUtilsMessengerEXT,
+    validation_enabled: bool,
+    hazard_tracker: Option<Mutex<HazardTracker>>,
+    debug_marker: DebugMarker,
+    retired_handles: Mutex<RetiredHandles>,
+    gpu_phases: Mutex<GpuPhaseRecorder>,
     device: Device,
     pdevice: vk::PhysicalDevice,
+    device_group: Vec<vk::PhysicalDevice>,
     allocator: ManuallyDrop<Arc<Mutex<Allocator>>>,
+    // Allocations made with allocate_memory, see memory_report.
+    memory_tracker: Mutex<MemoryTracker>,
     pub queue_family_indices: QueueFamiliesIndices,
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
-    pub acceleration_structure: khr::AccelerationStructure,
-    pub ray_tracing: khr::RayTracingPipeline,
-    pub ray_tracing_properties: vk::PhysicalDeviceRayTracingPipelinePropertiesKHR,
+    async_queue: vk::Queue,
+    compute_queue: vk::Queue,
+    // Serializes submissions, queues being externally synchronized, see queue_submit.
+    queue_lock: Mutex<()>,
+    multi_draw_indirect: bool,
+    fill_mode_non_solid: bool,
+    wide_lines: bool,
+    sample_rate_shading: bool,
+    storage_image_multisample: bool,
+    limits: DeviceLimits,
+    capabilities: DeviceCapabilities,
+    // Enabled device features, see RendererSettings::device_features.
+    device_features: DeviceFeatureRequest,
+    ray_query: bool,
+    bindless: bool,
+    shader_include_dirs: Vec<PathBuf>,
+    compiler_pool: CompilerPool,
+    draw_indirect_count: Option<khr::DrawIndirectCount>,
+    push_descriptor: Option<khr::PushDescriptor>,
+    // Only loaded when dynamic rendering isn't core, see cmd_begin_rendering.
+    dynamic_rendering: Option<khr::DynamicRendering>,
+    // Only loaded when synchronization2 isn't core, see cmd_pipeline_barrier2.
+    synchronization2: Option<khr::Synchronization2>,
+    // Only loaded when requested and supported, see RendererSettings::mesh_shader.
+    mesh_shader: Option<MeshShader>,
+    // None when the device lacks ray tracing support, see require_ray_tracing.
+    ray_tracing: Option<RayTracing>,
+}
+
+// Loaders and properties of the ray tracing pipeline extensions.
+struct RayTracing {
+    acceleration_structure: khr::AccelerationStructure,
+    pipeline: khr::RayTracingPipeline,
+    properties: RayTracingProperties,
+}
+
+// VkPhysicalDeviceRayTracingPipelinePropertiesKHR without its p_next pointer, so that the
+// context is Send and Sync.
+#[derive(Clone, Copy, Debug)]
+pub struct RayTracingProperties {
+    pub shader_group_handle_size: u32,
+    pub max_ray_recursion_depth: u32,
+    pub max_shader_group_stride: u32,
+    pub shader_group_base_alignment: u32,
+    pub shader_group_handle_capture_replay_size: u32,
+    pub max_ray_dispatch_invocation_count: u32,
+    pub shader_group_handle_alignment: u32,
+    pub max_ray_hit_attribute_size: u32,
+}
+
+impl From<vk::PhysicalDeviceRayTracingPipelinePropertiesKHR> for RayTracingProperties {
+    fn from(properties: vk::PhysicalDeviceRayTracingPipelinePropertiesKHR) -> Self {
+        RayTracingProperties {
+            shader_group_handle_size: properties.shader_group_handle_size,
+            max_ray_recursion_depth: properties.max_ray_recursion_depth,
+            max_shader_group_stride: properties.max_shader_group_stride,
+            shader_group_base_alignment: properties.shader_group_base_alignment,
+            shader_group_handle_capture_replay_size: properties
+                .shader_group_handle_capture_replay_size,
+            max_ray_dispatch_invocation_count: properties.max_ray_dispatch_invocation_count,
+            shader_group_handle_alignment: properties.shader_group_handle_alignment,
+            max_ray_hit_attribute_size: properties.max_ray_hit_attribute_size,
+        }
+    }
 }
 
+// The context is shared across threads (e.g. per-thread transient command pools), checked here
+// rather than asserted with unsafe impls.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedContext>();
+};
+
 impl SharedContext {
     pub fn new(window: &mut Window, settings: &RendererSettings) -> Self {
+        Self::create(Some(window), settings)
+    }
+
+    // Context without surface nor swapchain support, for compute and offscreen rendering. The
+    // present queue is the graphics queue.
+    pub fn new_headless(settings: &RendererSettings) -> Self {
+        Self::create(None, settings)
+    }
+
+    fn create(mut window: Option<&mut Window>, settings: &RendererSettings) -> Self {
         unsafe {
             let entry = Entry::load().unwrap();
             let app_name = CString::new("VulkanTriangle").unwrap();
+            // Vulkan 1.0 loaders can't create instances of a later version.
+            let loader_version = match entry.try_enumerate_instance_version() {
+                Ok(Some(version)) => version,
+                _ => vk::API_VERSION_1_0,
+            };
+            let api_version = settings.api_version.min(loader_version);
+            if api_version < settings.api_version {
+                println!(
+                    "Vulkan {}.{} requested, the loader supports {}.{}.",
+                    vk::api_version_major(settings.api_version),
+                    vk::api_version_minor(settings.api_version),
+                    vk::api_version_major(api_version),
+                    vk::api_version_minor(api_version)
+                );
+            }
 
+            let validation = &settings.validation;
+            let validation_layer = CString::new("VK_LAYER_KHRONOS_validation").unwrap();
             let mut layer_names = Vec::<CString>::new();
-            if cfg!(debug_assertions) {
-                layer_names.push(CString::new("VK_LAYER_KHRONOS_validation").unwrap());
+            if validation.layers.is_enabled() {
+                let available = entry
+                    .enumerate_instance_layer_properties()
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|layer| {
+                        CStr::from_ptr(layer.layer_name.as_ptr()) == validation_layer.as_c_str()
+                    });
+                if available {
+                    layer_names.push(validation_layer);
+                } else {
+                    println!(
+                        "Validation requested but VK_LAYER_KHRONOS_validation isn't installed."
+                    );
+                }
                 //layer_names.push(CString::new("VK_LAYER_LUNARG_api_dump").unwrap());
             }
+            let validation_enabled = !layer_names.is_empty();
             let layers_names_raw: Vec<*const i8> = layer_names
                 .iter()
                 .map(|raw_name| raw_name.as_ptr())
                 .collect();
 
-            let surface_extensions =
-                ash_window::enumerate_required_extensions(window.handle()).unwrap();
+            let surface_extensions = match &window {
+                Some(window) => ash_window::enumerate_required_extensions(window.handle()).unwrap(),
+                None => Vec::new(),
+            };
             let mut extension_names_raw = surface_extensions
                 .iter()
                 .map(|ext| ext.as_ptr())
@@ -263,35 +883,105 @@ impl SharedContext {
                 extension_names_raw.push(ext.as_ptr());
             }
 
+            let instance_extensions = entry
+                .enumerate_instance_extension_properties(None)
+                .unwrap_or_default();
+            let supports_extension = |name: &CStr| {
+                instance_extensions
+                    .iter()
+                    .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == name)
+            };
+
+            // Lists portability implementations such as MoltenVK along with conformant devices.
+            let portability_enumeration =
+                CStr::from_bytes_with_nul(KHR_PORTABILITY_ENUMERATION_NAME).unwrap();
+            let enumerate_portability = supports_extension(portability_enumeration);
+            if enumerate_portability {
+                extension_names_raw.push(portability_enumeration.as_ptr());
+            }
+
+            // Surfaces only report HDR color spaces with VK_EXT_swapchain_colorspace.
+            let swapchain_colorspace = vk::ExtSwapchainColorspaceFn::name();
+            if window.is_some()
+                && settings
+                    .preferred_surface_formats
+                    .iter()
+                    .any(|format| format.color_space != vk::ColorSpaceKHR::SRGB_NONLINEAR)
+                && !settings.extensions.contains(&swapchain_colorspace)
+                && supports_extension(swapchain_colorspace)
+            {
+                extension_names_raw.push(swapchain_colorspace.as_ptr());
+            }
+
+            let mut enabled_validation_features = Vec::new();
+            if validation_enabled {
+                if validation.gpu_assisted {
+                    enabled_validation_features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+                    enabled_validation_features
+                        .push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+                }
+                if validation.synchronization {
+                    enabled_validation_features
+                        .push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+                }
+                if validation.best_practices {
+                    enabled_validation_features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+                }
+            }
+            // Provided by the validation layer itself.
+            if !enabled_validation_features.is_empty() {
+                extension_names_raw.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+            }
+            let mut validation_features = vk::ValidationFeaturesEXT::builder()
+                .enabled_validation_features(&enabled_validation_features);
+
             let appinfo = vk::ApplicationInfo::builder()
                 .application_name(&app_name)
                 .application_version(0)
                 .engine_name(&app_name)
                 .engine_version(0)
-                .api_version(vk::API_VERSION_1_2);
+                .api_version(api_version);
 
-            let create_info = vk::InstanceCreateInfo::builder()
+            let mut create_info = vk::InstanceCreateInfo::builder()
+                .flags(match enumerate_portability {
+                    true => INSTANCE_CREATE_ENUMERATE_PORTABILITY_KHR,
+                    false => vk::InstanceCreateFlags::empty(),
+                })
                 .application_info(&appinfo)
                 .enabled_layer_names(&layers_names_raw)
                 .enabled_extension_names(&extension_names_raw);
+            if !enabled_validation_features.is_empty() {
+                create_info = create_info.push_next(&mut validation_features);
+            }
 
             let instance: Instance = entry
                 .create_instance(&create_info, None)
                 .expect("Instance creation error");
 
+            // Everything is requested from the messenger so the filters can be widened at runtime.
+            set_debug_message_filter(validation.message_severity, validation.message_type);
             let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
                 .message_severity(
                     vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING, //| vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+                        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                        | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                        | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+                )
+                .message_type(
+                    vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                        | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                        | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
                 )
-                .message_type(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL)
                 .pfn_user_callback(Some(vulkan_debug_callback));
             let debug_utils_loader = DebugUtils::new(&entry, &instance);
             let debug_call_back = debug_utils_loader
                 .create_debug_utils_messenger(&debug_info, None)
                 .unwrap();
 
-            window.create_surface(&entry, &instance);
+            if let Some(window) = window.as_mut() {
+                window.create_surface(&entry, &instance);
+            }
+            let window = window.as_deref();
 
             let pdevices = instance
                 .enumerate_physical_devices()
@@ -307,7 +997,12 @@ impl SharedContext {
                         .filter_map(|(index, ref info)| {
                             let supports_graphic_and_surface =
                                 info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                                    && window.get_surface_support(*pdevice, index as u32);
+                                    && match window {
+                                        Some(window) => {
+                                            window.get_surface_support(*pdevice, index as u32)
+                                        }
+                                        None => true,
+                                    };
                             if supports_graphic_and_surface {
                                 Some(*pdevice)
                             } else {
@@ -322,21 +1017,67 @@ impl SharedContext {
 
             //println!("{:?}", instance.get_physical_device_properties(pdevice));
 
-            let (graphics, present) = find_queue_families(
-                &instance,
-                window.surface_loader(),
-                window.surface(),
-                pdevice,
-            );
+            let (graphics, present, compute) = find_queue_families(&instance, window, pdevice);
             let queue_family_indices = QueueFamiliesIndices {
                 graphics: graphics.unwrap(),
                 present: present.unwrap(),
+                compute: compute.or(graphics).unwrap(),
+            };
+            let device_group = if settings.device_group != DeviceGroupMode::Disabled {
+                find_device_group(&instance, pdevice)
+            } else {
+                Vec::new()
             };
-            let (device, graphics_queue, present_queue) = create_logical_device_with_graphics_queue(
+            if settings.device_group != DeviceGroupMode::Disabled && device_group.is_empty() {
+                println!("No multi-GPU device group found, rendering on a single device.");
+            }
+            let capabilities = query_capabilities(
+                &instance,
+                pdevice,
+                api_version,
+                settings.ray_query,
+                settings.mesh_shader,
+            );
+            if capabilities.portability_subset
+                || !capabilities.ray_tracing
+                || !capabilities.missing_extensions.is_empty()
+            {
+                print!("{}", capabilities);
+            }
+            let ray_query = capabilities.ray_query;
+            if settings.ray_query && !ray_query {
+                println!("Ray queries unsupported, only the ray tracing pipeline is available.");
+            }
+            if settings.mesh_shader && !capabilities.mesh_shader {
+                println!("Mesh shaders unsupported.");
+            }
+            let unsupported_features = settings.device_features.unsupported(
+                &instance,
+                pdevice,
+                capabilities.core_features.api_version,
+                &supported_device_extensions(&instance, pdevice),
+            );
+            if !unsupported_features.is_empty() {
+                panic!(
+                    "Required device features unsupported: {}",
+                    unsupported_features.join(", ")
+                );
+            }
+            let device_features = enabled_device_features(
+                &instance,
+                pdevice,
+                &capabilities,
+                &settings.device_features,
+            );
+            let (device, graphics_queue, present_queue, async_queue, compute_queue) = create_logical_device_with_graphics_queue(
                 &instance,
                 pdevice,
                 queue_family_indices,
                 &settings.device_extensions,
+                &device_group,
+                window.is_none(),
+                &capabilities,
+                &device_features,
             );
 
             let allocator = Allocator::new(&AllocatorCreateDesc{
@@ -344,27 +1085,96 @@ impl SharedContext {
                 device: device.clone(),
                 physical_device: pdevice,
                 debug_settings: Default::default(),
-                buffer_device_address: true,  // TODO: check the BufferDeviceAddressFeatures struct.
+                buffer_device_address: capabilities.buffer_device_address,
             }).unwrap();
 
-            let acceleration_structure = khr::AccelerationStructure::new(&instance, &device);
-            let ray_tracing = khr::RayTracingPipeline::new(&instance, &device);
-            let ray_tracing_properties = khr::RayTracingPipeline::get_properties(&instance, pdevice);
+            let memory_tracker = MemoryTracker::new(&instance, pdevice, capabilities.memory_budget);
+
+            let supported_features = instance.get_physical_device_features(pdevice);
+            let multi_draw_indirect = supported_features.multi_draw_indirect == vk::TRUE;
+            let fill_mode_non_solid = supported_features.fill_mode_non_solid == vk::TRUE;
+            let wide_lines = supported_features.wide_lines == vk::TRUE;
+            let sample_rate_shading = supported_features.sample_rate_shading == vk::TRUE;
+            let storage_image_multisample =
+                supported_features.shader_storage_image_multisample == vk::TRUE;
+            let limits =
+                DeviceLimits::new(&instance.get_physical_device_properties(pdevice).limits);
+            let bindless = capabilities.bindless;
+            let draw_indirect_count = if capabilities.draw_indirect_count {
+                Some(khr::DrawIndirectCount::new(&instance, &device))
+            } else {
+                None
+            };
+            let push_descriptor = if cfg!(feature = "dlss") || capabilities.push_descriptor {
+                Some(khr::PushDescriptor::new(&instance, &device))
+            } else {
+                None
+            };
+
+            let dynamic_rendering = match capabilities.core_features.dynamic_rendering {
+                FeatureSource::Extension => Some(khr::DynamicRendering::new(&instance, &device)),
+                _ => None,
+            };
+            let synchronization2 = match capabilities.core_features.synchronization2 {
+                FeatureSource::Extension => Some(khr::Synchronization2::new(&instance, &device)),
+                _ => None,
+            };
+
+            let mesh_shader = capabilities
+                .mesh_shader
+                .then(|| MeshShader::new(&instance, &device));
+
+            let ray_tracing = capabilities.ray_tracing.then(|| RayTracing {
+                acceleration_structure: khr::AccelerationStructure::new(&instance, &device),
+                pipeline: khr::RayTracingPipeline::new(&instance, &device),
+                properties: khr::RayTracingPipeline::get_properties(&instance, pdevice).into(),
+            });
+
+            let debug_marker = DebugMarker::new(debug_utils_loader.clone(), device.handle());
 
             SharedContext {
                 entry,
                 instance,
                 debug_utils_loader,
                 debug_call_back,
+                validation_enabled,
+                hazard_tracker: validation
+                    .hazard_tracking
+                    .then(|| Mutex::new(HazardTracker::new())),
+                debug_marker,
+                retired_handles: Mutex::new(RetiredHandles::new()),
+                gpu_phases: Mutex::new(GpuPhaseRecorder::new()),
                 device,
                 pdevice,
+                device_group,
                 allocator: ManuallyDrop::new(Arc::new(Mutex::new(allocator))),
+                memory_tracker: Mutex::new(memory_tracker),
                 queue_family_indices,
                 graphics_queue,
                 present_queue,
-                acceleration_structure,
+                async_queue,
+                compute_queue,
+                queue_lock: Mutex::new(()),
+                multi_draw_indirect,
+                fill_mode_non_solid,
+                wide_lines,
+                sample_rate_shading,
+                storage_image_multisample,
+                limits,
+                capabilities,
+                device_features,
+                ray_query,
+                bindless,
+                shader_include_dirs: std::iter::once(PathBuf::from(ENGINE_SHADER_DIR))
+                    .chain(settings.shader_include_dirs.iter().cloned())
+                    .collect(),
+                compiler_pool: CompilerPool::default(),
+                draw_indirect_count,
+                push_descriptor,
+                dynamic_rendering,
+                synchronization2,
+                mesh_shader,
                 ray_tracing,
-                ray_tracing_properties,
             }
         }
     }
@@ -373,6 +1183,54 @@ impl SharedContext {
         &self.entry
     }
 
+    // True when the validation layer was requested and found.
+    pub fn validation_enabled(&self) -> bool {
+        self.validation_enabled
+    }
+
+    // Runs `f` on the hazard tracker, no-op unless ValidationSettings::hazard_tracking is set.
+    pub fn track_hazards(&self, f: impl FnOnce(&mut HazardTracker)) {
+        if let Some(tracker) = &self.hazard_tracker {
+            f(&mut tracker.lock().unwrap());
+        }
+    }
+
+    pub fn debug_marker(&self) -> &DebugMarker {
+        &self.debug_marker
+    }
+
+    // Records the destruction of a resource which can be referenced by cached descriptor sets.
+    pub(crate) fn retire_handle(&self, handle: impl vk::Handle) {
+        self.retired_handles.lock().unwrap().retire(handle.as_raw());
+    }
+
+    pub(crate) fn retired_handles(&self) -> MutexGuard<'_, RetiredHandles> {
+        self.retired_handles.lock().unwrap()
+    }
+
+    // Times `phase` in the AppRenderer frame command buffer until end_gpu_phase, see GpuPhase.
+    // Ignored for other command buffers and while another phase is open.
+    pub fn begin_gpu_phase(&self, cmd: vk::CommandBuffer, phase: GpuPhase) {
+        self.gpu_phases.lock().unwrap().begin(&self.device, cmd, phase);
+    }
+
+    pub fn end_gpu_phase(&self, cmd: vk::CommandBuffer, phase: GpuPhase) {
+        self.gpu_phases.lock().unwrap().end(&self.device, cmd, phase);
+    }
+
+    pub(crate) fn gpu_phases(&self) -> MutexGuard<'_, GpuPhaseRecorder> {
+        self.gpu_phases.lock().unwrap()
+    }
+
+    // Process-wide: applies to the messengers of every context.
+    pub fn set_debug_message_filter(
+        &self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    ) {
+        set_debug_message_filter(severity, message_type);
+    }
+
     pub fn instance(&self) -> &Instance {
         &self.instance
     }
@@ -385,14 +1243,148 @@ impl SharedContext {
         self.pdevice
     }
 
+    // Number of physical devices backing the logical device (1 outside of a device group).
+    pub fn device_group_size(&self) -> u32 {
+        self.device_group.len().max(1) as u32
+    }
+
+    pub fn device_group(&self) -> &[vk::PhysicalDevice] {
+        &self.device_group
+    }
+
+    // How `local_device` may access memory of `heap_index` allocated on `remote_device`.
+    pub fn peer_memory_features(
+        &self,
+        heap_index: u32,
+        local_device: u32,
+        remote_device: u32,
+    ) -> vk::PeerMemoryFeatureFlags {
+        unsafe {
+            self.device.get_device_group_peer_memory_features(
+                heap_index,
+                local_device,
+                remote_device,
+            )
+        }
+    }
+
     pub fn get_physical_device_properties(&self) -> vk::PhysicalDeviceProperties {
         unsafe { self.instance.get_physical_device_properties(self.pdevice) }
     }
 
+    pub fn format_supports(
+        &self,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.pdevice, format)
+        };
+        match tiling {
+            vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+            _ => properties.optimal_tiling_features.contains(features),
+        }
+    }
+
+    // First format of `candidates` supporting `features` with `tiling`.
+    pub fn find_supported_format(
+        &self,
+        candidates: &[vk::Format],
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> Option<vk::Format> {
+        candidates
+            .iter()
+            .copied()
+            .find(|format| self.format_supports(*format, tiling, features))
+    }
+
+    pub fn find_depth_format(&self) -> vk::Format {
+        self.find_supported_format(
+            &DEPTH_FORMATS,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+        .expect("No supported depth format.")
+    }
+
+    // The `preferred` format when the device supports it as depth attachment, otherwise falls
+    // back to find_depth_format.
+    pub fn select_depth_format(&self, preferred: Option<vk::Format>) -> vk::Format {
+        let fallback = self.find_depth_format();
+        match preferred {
+            Some(format)
+                if self.format_supports(
+                    format,
+                    vk::ImageTiling::OPTIMAL,
+                    vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+                ) =>
+            {
+                format
+            }
+            Some(format) => {
+                println!(
+                    "Depth format {:?} unsupported, falling back to {:?}.",
+                    format, fallback
+                );
+                fallback
+            }
+            None => fallback,
+        }
+    }
+
+    pub fn find_hdr_color_format(&self) -> vk::Format {
+        self.find_supported_format(
+            &HDR_COLOR_FORMATS,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        )
+        .expect("No supported HDR color format.")
+    }
+
+    pub fn find_hdr_storage_format(&self) -> vk::Format {
+        self.find_supported_format(
+            &HDR_STORAGE_FORMATS,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::STORAGE_IMAGE | vk::FormatFeatureFlags::TRANSFER_SRC,
+        )
+        .expect("No supported HDR storage format.")
+    }
+
     pub fn get_physical_device_limits(&self) -> vk::PhysicalDeviceLimits {
         self.get_physical_device_properties().limits
     }
 
+    pub fn limits(&self) -> &DeviceLimits {
+        &self.limits
+    }
+
+    // Whether shaders of any stage can trace inline ray queries, see RendererSettings::ray_query.
+    pub fn supports_ray_query(&self) -> bool {
+        self.ray_query
+    }
+
+    // Optional features and extensions of the device, printed at creation when some are missing.
+    pub fn capabilities(&self) -> &DeviceCapabilities {
+        &self.capabilities
+    }
+
+    pub fn core_features(&self) -> &CoreFeatures {
+        &self.capabilities.core_features
+    }
+
+    // Features enabled on the device: the required ones along with those sol uses.
+    pub fn device_features(&self) -> &DeviceFeatureRequest {
+        &self.device_features
+    }
+
+    // Whether buffers can have SHADER_DEVICE_ADDRESS usage, see BufferInfo::usage_device_address.
+    pub fn supports_buffer_device_address(&self) -> bool {
+        self.capabilities.buffer_device_address
+    }
+
     pub fn graphics_queue(&self) -> vk::Queue {
         self.graphics_queue
     }
@@ -401,20 +1393,279 @@ impl SharedContext {
         self.present_queue
     }
 
+    // Second queue of the graphics family, or the graphics queue itself if the family only has one.
+    pub fn async_queue(&self) -> vk::Queue {
+        self.async_queue
+    }
+
+    // Queue of the dedicated compute family, else async_queue. See AsyncCompute.
+    pub fn compute_queue(&self) -> vk::Queue {
+        self.compute_queue
+    }
+
+    pub fn has_dedicated_compute_queue(&self) -> bool {
+        self.queue_family_indices.compute != self.queue_family_indices.graphics
+    }
+
+    pub fn supports_timeline_semaphore(&self) -> bool {
+        self.capabilities.timeline_semaphore
+    }
+
+    // Whether passes can render without render pass objects, see RenderingInfo.
+    pub fn supports_dynamic_rendering(&self) -> bool {
+        self.core_features().dynamic_rendering.is_supported()
+    }
+
+    // From the core API or VK_KHR_dynamic_rendering, see CoreFeatures.
+    pub fn cmd_begin_rendering(&self, cmd: vk::CommandBuffer, rendering_info: &vk::RenderingInfo) {
+        unsafe {
+            match &self.dynamic_rendering {
+                Some(dynamic_rendering) => {
+                    dynamic_rendering.cmd_begin_rendering(cmd, rendering_info)
+                }
+                None => self.device.cmd_begin_rendering(cmd, rendering_info),
+            }
+        }
+    }
+
+    pub fn cmd_end_rendering(&self, cmd: vk::CommandBuffer) {
+        unsafe {
+            match &self.dynamic_rendering {
+                Some(dynamic_rendering) => dynamic_rendering.cmd_end_rendering(cmd),
+                None => self.device.cmd_end_rendering(cmd),
+            }
+        }
+    }
+
+    // From the core API or VK_KHR_synchronization2, else recorded as a legacy barrier with
+    // coarser flags, see legacy_stage and legacy_access.
+    pub fn cmd_pipeline_barrier2(
+        &self,
+        cmd: vk::CommandBuffer,
+        memory_barriers: &[MemoryBarrier2],
+        buffer_barriers: &[BufferBarrier2],
+        image_barriers: &[ImageBarrier2],
+    ) {
+        let ray_tracing_barriers;
+        let image_barriers = match self.supports_ray_tracing() {
+            true => {
+                ray_tracing_barriers = image_barriers
+                    .iter()
+                    .map(|barrier| barrier.with_ray_tracing_reads())
+                    .collect::<Vec<_>>();
+                &ray_tracing_barriers
+            }
+            false => image_barriers,
+        };
+        if !self.core_features().synchronization2.is_supported() {
+            cmd_legacy_pipeline_barrier(
+                &self.device,
+                cmd,
+                memory_barriers,
+                buffer_barriers,
+                image_barriers,
+            );
+            return;
+        }
+        let memory_barriers = memory_barriers
+            .iter()
+            .map(|barrier| barrier.to_vk())
+            .collect::<Vec<_>>();
+        let buffer_barriers = buffer_barriers
+            .iter()
+            .map(|barrier| barrier.to_vk())
+            .collect::<Vec<_>>();
+        let image_barriers = image_barriers
+            .iter()
+            .map(|barrier| barrier.to_vk())
+            .collect::<Vec<_>>();
+        let dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(&memory_barriers)
+            .buffer_memory_barriers(&buffer_barriers)
+            .image_memory_barriers(&image_barriers);
+        unsafe {
+            match &self.synchronization2 {
+                Some(synchronization2) => {
+                    synchronization2.cmd_pipeline_barrier2(cmd, &dependency_info)
+                }
+                None => self.device.cmd_pipeline_barrier2(cmd, &dependency_info),
+            }
+        }
+    }
+
     pub fn allocator(&self) -> &Arc<Mutex<Allocator>> {
         &self.allocator
     }
 
+    // Allocates with the allocator, tracking the allocation in memory_report and
+    // named_allocations. Warns when a heap nears its budget.
+    pub fn allocate_memory(&self, desc: &AllocationCreateDesc) -> AllocationResult<Allocation> {
+        let allocation = self.allocator.lock().unwrap().allocate(desc)?;
+        self.memory_tracker
+            .lock()
+            .unwrap()
+            .track(&self.instance, self.pdevice, &allocation, desc);
+        Ok(allocation)
+    }
+
+    // Frees an allocation of allocate_memory.
+    pub fn free_memory(&self, allocation: Allocation) -> AllocationResult<()> {
+        self.memory_tracker.lock().unwrap().untrack(&allocation);
+        self.allocator.lock().unwrap().free(allocation)
+    }
+
+    // Bytes allocated per memory heap, along with the budgets of VK_EXT_memory_budget when
+    // supported.
+    pub fn memory_report(&self) -> MemoryReport {
+        self.memory_tracker
+            .lock()
+            .unwrap()
+            .report(&self.instance, self.pdevice)
+    }
+
+    // Live allocations of allocate_memory, largest first.
+    pub fn named_allocations(&self) -> Vec<NamedAllocation> {
+        self.memory_tracker.lock().unwrap().allocations()
+    }
+
+    // Prints the memory report followed by the named allocations.
+    pub fn dump_allocations(&self) {
+        print!("{}", self.memory_report());
+        for allocation in self.named_allocations() {
+            println!("  {}", allocation);
+        }
+    }
+
+    // Thread-safe vkQueueSubmit, to be used for every submission to the context queues.
+    pub fn queue_submit(
+        &self,
+        queue: vk::Queue,
+        submits: &[vk::SubmitInfo],
+        fence: vk::Fence,
+    ) -> VkResult<()> {
+        let _queues = self.lock_queues();
+        unsafe { self.device.queue_submit(queue, submits, fence) }
+    }
+
+    // Held while calling other functions taking a queue, e.g. vkQueuePresentKHR.
+    pub fn lock_queues(&self) -> MutexGuard<'_, ()> {
+        self.queue_lock.lock().unwrap()
+    }
+
+    // Thread-safe vkDeviceWaitIdle, submissions from other threads wait meanwhile.
+    pub fn device_wait_idle(&self) -> VkResult<()> {
+        let _queues = self.lock_queues();
+        unsafe { self.device.device_wait_idle() }
+    }
+
+    // Whether a single indirect call may issue more than one draw.
+    pub fn supports_multi_draw_indirect(&self) -> bool {
+        self.multi_draw_indirect
+    }
+
+    // Whether pipelines can use the LINE and POINT polygon modes (wireframe).
+    pub fn supports_wireframe(&self) -> bool {
+        self.fill_mode_non_solid
+    }
+
+    // Whether line widths other than 1.0 are supported, within the line_width_range limit.
+    pub fn supports_wide_lines(&self) -> bool {
+        self.wide_lines
+    }
+
+    // Whether pipelines can shade per sample, see PipelineInfo::sample_shading.
+    pub fn supports_sample_rate_shading(&self) -> bool {
+        self.sample_rate_shading
+    }
+
+    // Whether multisampled images can be storage images, e.g. written by a compute pass.
+    pub fn supports_storage_image_multisample(&self) -> bool {
+        self.storage_image_multisample
+    }
+
+    // Highest sample count of the color (and depth) attachments of a framebuffer, at most
+    // `requested`.
+    pub fn max_sample_count(&self, requested: u8, depth: bool) -> vk::SampleCountFlags {
+        let mut counts = self.limits.framebuffer_color_sample_counts;
+        if depth {
+            counts &= self.limits.framebuffer_depth_sample_counts;
+        }
+        let mut samples = 1 << (7 - requested.clamp(1, 64).leading_zeros());
+        while samples > 1 && !counts.contains(sample_count_flags(samples)) {
+            samples /= 2;
+        }
+        sample_count_flags(samples)
+    }
+
+    // Include search paths of the shaders, see RendererSettings::shader_include_dirs.
+    pub fn shader_include_dirs(&self) -> &[PathBuf] {
+        &self.shader_include_dirs
+    }
+
+    // Shared by the shader compilations of all threads.
+    pub fn compiler_pool(&self) -> &CompilerPool {
+        &self.compiler_pool
+    }
+
+    pub fn draw_indirect_count(&self) -> Option<&khr::DrawIndirectCount> {
+        self.draw_indirect_count.as_ref()
+    }
+
+    // Whether the descriptor indexing features of bindless arrays (TextureArena) are enabled.
+    pub fn supports_bindless(&self) -> bool {
+        self.bindless
+    }
+
+    pub fn push_descriptor(&self) -> Option<&khr::PushDescriptor> {
+        self.push_descriptor.as_ref()
+    }
+
+    // Whether task and mesh shader pipelines can be built, see RendererSettings::mesh_shader.
+    pub fn supports_mesh_shader(&self) -> bool {
+        self.mesh_shader.is_some()
+    }
+
+    pub fn mesh_shader(&self) -> Option<&MeshShader> {
+        self.mesh_shader.as_ref()
+    }
+
+    // Whether the ray tracing pipeline and acceleration structure extensions are enabled.
+    pub fn supports_ray_tracing(&self) -> bool {
+        self.ray_tracing.is_some()
+    }
+
+    // Checked by the ray module constructors, the error listing what the device lacks.
+    pub fn require_ray_tracing(&self) -> Result<()> {
+        match self.ray_tracing {
+            Some(_) => Ok(()),
+            None => Err(Error::UnsupportedFeature {
+                feature: "Ray tracing",
+                reason: format!(
+                    "{} lacks {}",
+                    self.capabilities.device_name,
+                    self.capabilities.missing_ray_tracing_support.join(", ")
+                ),
+            }),
+        }
+    }
+
+    // The ray tracing accessors panic without ray tracing support, see require_ray_tracing.
+    fn ray_tracing_loaders(&self) -> &RayTracing {
+        self.ray_tracing
+            .as_ref()
+            .expect("Ray tracing unsupported, see SharedContext::require_ray_tracing.")
+    }
+
     pub fn acceleration_structure(&self) -> &khr::AccelerationStructure {
-        &self.acceleration_structure
+        &self.ray_tracing_loaders().acceleration_structure
     }
 
     pub fn ray_tracing(&self) -> &khr::RayTracingPipeline {
-        &self.ray_tracing
+        &self.ray_tracing_loaders().pipeline
     }
 
-    pub unsafe fn ray_tracing_properties(&self) -> &vk::PhysicalDeviceRayTracingPipelinePropertiesKHR {
-        &self.ray_tracing_properties
+    pub fn ray_tracing_properties(&self) -> &RayTracingProperties {
+        &self.ray_tracing_loaders().properties
     }
 
     pub fn queue_family_indices(&self) -> &QueueFamiliesIndices {
@@ -436,31 +1687,40 @@ impl Drop for SharedContext {
 
 pub struct Context {
     shared_context: Arc<SharedContext>,
-    frame_command_pools: Vec<CommandPool>,
-    transient_command_pool: vk::CommandPool,
+    // One per swapchain image, see resize_frame_pools.
+    frame_command_pools: RwLock<Vec<CommandPool>>,
+    // Secondary command buffers of each frame, one pool per recording thread.
+    frame_secondary_pools: RwLock<Vec<Mutex<HashMap<ThreadId, CommandPool>>>>,
+    // One per thread recording single-time commands, so that resources can be created from
+    // worker threads. See TransientCommandPools.
+    transient_command_pools: Mutex<TransientCommandPools>,
+    asset_cache: AssetCache,
+}
+
+// Command pools of the threads with one-time command buffers not yet freed, with their command
+// buffer count, and pools of threads which freed them all.
+#[derive(Default)]
+struct TransientCommandPools {
+    active: HashMap<ThreadId, (vk::CommandPool, usize)>,
+    idle: Vec<vk::CommandPool>,
 }
 
 impl Context {
     pub fn new(shared_context: Arc<SharedContext>, swapchain_image_count: usize) -> Self {
-        unsafe {
-            let mut frame_command_pools = Vec::<CommandPool>::new();
-            let graphics_index = shared_context.queue_family_indices.graphics;
-            for _ in 0..swapchain_image_count {
-                frame_command_pools.push(CommandPool::new(shared_context.clone(), graphics_index));
-            }
-
-            let pool_create_info = vk::CommandPoolCreateInfo::builder()
-                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
-                .queue_family_index(graphics_index);
-            let transient_command_pool = shared_context
-                .device()
-                .create_command_pool(&pool_create_info, None)
-                .unwrap();
-            Context {
-                shared_context,
-                frame_command_pools,
-                transient_command_pool,
-            }
+        let mut frame_command_pools = Vec::<CommandPool>::new();
+        let graphics_index = shared_context.queue_family_indices.graphics;
+        for _ in 0..swapchain_image_count {
+            frame_command_pools.push(CommandPool::new(shared_context.clone(), graphics_index));
+        }
+        let frame_secondary_pools = (0..swapchain_image_count)
+            .map(|_| Mutex::new(HashMap::new()))
+            .collect();
+        Context {
+            shared_context,
+            frame_command_pools: RwLock::new(frame_command_pools),
+            frame_secondary_pools: RwLock::new(frame_secondary_pools),
+            transient_command_pools: Mutex::new(TransientCommandPools::default()),
+            asset_cache: AssetCache::default(),
         }
     }
 
@@ -468,6 +1728,38 @@ impl Context {
         self.shared_context.entry()
     }
 
+    pub fn validation_enabled(&self) -> bool {
+        self.shared_context.validation_enabled()
+    }
+
+    pub fn asset_cache(&self) -> &AssetCache {
+        &self.asset_cache
+    }
+
+    pub fn track_hazards(&self, f: impl FnOnce(&mut HazardTracker)) {
+        self.shared_context.track_hazards(f);
+    }
+
+    pub fn debug_marker(&self) -> &DebugMarker {
+        self.shared_context.debug_marker()
+    }
+
+    pub fn begin_gpu_phase(&self, cmd: vk::CommandBuffer, phase: GpuPhase) {
+        self.shared_context.begin_gpu_phase(cmd, phase);
+    }
+
+    pub fn end_gpu_phase(&self, cmd: vk::CommandBuffer, phase: GpuPhase) {
+        self.shared_context.end_gpu_phase(cmd, phase);
+    }
+
+    pub fn set_debug_message_filter(
+        &self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    ) {
+        self.shared_context.set_debug_message_filter(severity, message_type);
+    }
+
     pub fn instance(&self) -> &Instance {
         self.shared_context.instance()
     }
@@ -480,14 +1772,78 @@ impl Context {
         self.shared_context.physical_device()
     }
 
+    pub fn device_group_size(&self) -> u32 {
+        self.shared_context.device_group_size()
+    }
+
+    pub fn peer_memory_features(
+        &self,
+        heap_index: u32,
+        local_device: u32,
+        remote_device: u32,
+    ) -> vk::PeerMemoryFeatureFlags {
+        self.shared_context
+            .peer_memory_features(heap_index, local_device, remote_device)
+    }
+
     pub fn get_physical_device_properties(&self) -> vk::PhysicalDeviceProperties {
         self.shared_context.get_physical_device_properties()
     }
 
+    pub fn find_supported_format(
+        &self,
+        candidates: &[vk::Format],
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> Option<vk::Format> {
+        self.shared_context
+            .find_supported_format(candidates, tiling, features)
+    }
+
+    pub fn find_depth_format(&self) -> vk::Format {
+        self.shared_context.find_depth_format()
+    }
+
+    pub fn select_depth_format(&self, preferred: Option<vk::Format>) -> vk::Format {
+        self.shared_context.select_depth_format(preferred)
+    }
+
+    pub fn find_hdr_color_format(&self) -> vk::Format {
+        self.shared_context.find_hdr_color_format()
+    }
+
+    pub fn find_hdr_storage_format(&self) -> vk::Format {
+        self.shared_context.find_hdr_storage_format()
+    }
+
     pub fn get_physical_device_limits(&self) -> vk::PhysicalDeviceLimits {
         self.shared_context.get_physical_device_limits()
     }
 
+    pub fn limits(&self) -> &DeviceLimits {
+        self.shared_context.limits()
+    }
+
+    pub fn supports_ray_query(&self) -> bool {
+        self.shared_context.supports_ray_query()
+    }
+
+    pub fn capabilities(&self) -> &DeviceCapabilities {
+        self.shared_context.capabilities()
+    }
+
+    pub fn core_features(&self) -> &CoreFeatures {
+        self.shared_context.core_features()
+    }
+
+    pub fn device_features(&self) -> &DeviceFeatureRequest {
+        self.shared_context.device_features()
+    }
+
+    pub fn supports_buffer_device_address(&self) -> bool {
+        self.shared_context.supports_buffer_device_address()
+    }
+
     pub fn present_queue(&self) -> vk::Queue {
         self.shared_context.present_queue()
     }
@@ -496,10 +1852,303 @@ impl Context {
         self.shared_context.graphics_queue()
     }
 
+    pub fn async_queue(&self) -> vk::Queue {
+        self.shared_context.async_queue()
+    }
+
+    pub fn compute_queue(&self) -> vk::Queue {
+        self.shared_context.compute_queue()
+    }
+
+    pub fn has_dedicated_compute_queue(&self) -> bool {
+        self.shared_context.has_dedicated_compute_queue()
+    }
+
+    pub fn supports_timeline_semaphore(&self) -> bool {
+        self.shared_context.supports_timeline_semaphore()
+    }
+
+    pub fn supports_dynamic_rendering(&self) -> bool {
+        self.shared_context.supports_dynamic_rendering()
+    }
+
+    pub fn cmd_begin_rendering(&self, cmd: vk::CommandBuffer, rendering_info: &vk::RenderingInfo) {
+        self.shared_context.cmd_begin_rendering(cmd, rendering_info)
+    }
+
+    pub fn cmd_end_rendering(&self, cmd: vk::CommandBuffer) {
+        self.shared_context.cmd_end_rendering(cmd)
+    }
+
+    pub fn cmd_pipeline_barrier2(
+        &self,
+        cmd: vk::CommandBuffer,
+        memory_barriers: &[MemoryBarrier2],
+        buffer_barriers: &[BufferBarrier2],
+        image_barriers: &[ImageBarrier2],
+    ) {
+        self.shared_context.cmd_pipeline_barrier2(
+            cmd,
+            memory_barriers,
+            buffer_barriers,
+            image_barriers,
+        )
+    }
+
     pub fn allocator(&self) -> &Arc<Mutex<Allocator>> {
         self.shared_context.allocator()
     }
 
+    pub fn allocate_memory(&self, desc: &AllocationCreateDesc) -> AllocationResult<Allocation> {
+        self.shared_context.allocate_memory(desc)
+    }
+
+    pub fn free_memory(&self, allocation: Allocation) -> AllocationResult<()> {
+        self.shared_context.free_memory(allocation)
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        self.shared_context.memory_report()
+    }
+
+    pub fn named_allocations(&self) -> Vec<NamedAllocation> {
+        self.shared_context.named_allocations()
+    }
+
+    pub fn dump_allocations(&self) {
+        self.shared_context.dump_allocations()
+    }
+
+    pub fn queue_submit(
+        &self,
+        queue: vk::Queue,
+        submits: &[vk::SubmitInfo],
+        fence: vk::Fence,
+    ) -> VkResult<()> {
+        self.shared_context.queue_submit(queue, submits, fence)
+    }
+
+    pub fn lock_queues(&self) -> MutexGuard<'_, ()> {
+        self.shared_context.lock_queues()
+    }
+
+    pub fn device_wait_idle(&self) -> VkResult<()> {
+        self.shared_context.device_wait_idle()
+    }
+
+    pub fn supports_multi_draw_indirect(&self) -> bool {
+        self.shared_context.supports_multi_draw_indirect()
+    }
+
+    pub fn supports_wireframe(&self) -> bool {
+        self.shared_context.supports_wireframe()
+    }
+
+    pub fn supports_wide_lines(&self) -> bool {
+        self.shared_context.supports_wide_lines()
+    }
+
+    pub fn supports_sample_rate_shading(&self) -> bool {
+        self.shared_context.supports_sample_rate_shading()
+    }
+
+    pub fn supports_storage_image_multisample(&self) -> bool {
+        self.shared_context.supports_storage_image_multisample()
+    }
+
+    pub fn max_sample_count(&self, requested: u8, depth: bool) -> vk::SampleCountFlags {
+        self.shared_context.max_sample_count(requested, depth)
+    }
+
+    pub fn shader_include_dirs(&self) -> &[PathBuf] {
+        self.shared_context.shader_include_dirs()
+    }
+
+    pub fn compiler_pool(&self) -> &CompilerPool {
+        self.shared_context.compiler_pool()
+    }
+
+    pub fn draw_indirect_count(&self) -> Option<&khr::DrawIndirectCount> {
+        self.shared_context.draw_indirect_count()
+    }
+
+    pub fn supports_bindless(&self) -> bool {
+        self.shared_context.supports_bindless()
+    }
+
+    pub fn push_descriptor(&self) -> Option<&khr::PushDescriptor> {
+        self.shared_context.push_descriptor()
+    }
+
+    pub fn supports_mesh_shader(&self) -> bool {
+        self.shared_context.supports_mesh_shader()
+    }
+
+    pub fn mesh_shader(&self) -> Option<&MeshShader> {
+        self.shared_context.mesh_shader()
+    }
+
+    // Viewport and scissor covering the whole `target`, e.g. a Swapchain or RenderTarget, in
+    // place of sizes from the window which can lag behind after a resize.
+    pub fn cmd_set_full_viewport(&self, cmd: vk::CommandBuffer, target: &impl RenderArea) {
+        let extent = target.get_render_extent();
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        unsafe {
+            self.device().cmd_set_viewport(cmd, 0, &[viewport]);
+            self.device().cmd_set_scissor(cmd, 0, &[scissor]);
+        }
+    }
+
+    // `dynamic_offsets` holds one offset per dynamic descriptor of `sets`, in set then binding
+    // order, see DescriptorSetLayout::get_dynamic_offset_count.
+    pub fn cmd_bind_descriptor_sets(
+        &self,
+        cmd: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        first_set: u32,
+        sets: &[DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        let handles: Vec<_> = sets.iter().map(|set| set.handle()).collect();
+        unsafe {
+            self.device().cmd_bind_descriptor_sets(
+                cmd,
+                bind_point,
+                pipeline_layout,
+                first_set,
+                &handles,
+                dynamic_offsets,
+            );
+        }
+    }
+
+    // Issues `draw_count` VkDrawIndexedIndirectCommand tightly packed at `offset`, falling back to
+    // one call per command without the multiDrawIndirect feature.
+    pub fn cmd_draw_indexed_indirect(
+        &self,
+        cmd: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+    ) {
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        self.track_indirect_read(buffer);
+        unsafe {
+            if self.supports_multi_draw_indirect() {
+                self.device()
+                    .cmd_draw_indexed_indirect(cmd, buffer, offset, draw_count, stride);
+            } else {
+                for i in 0..draw_count {
+                    let offset = offset + (i * stride) as vk::DeviceSize;
+                    self.device()
+                        .cmd_draw_indexed_indirect(cmd, buffer, offset, 1, stride);
+                }
+            }
+        }
+    }
+
+    // Same as cmd_draw_indexed_indirect with the draw count read from `count_buffer` (e.g. written
+    // by a culling shader). Without VK_KHR_draw_indirect_count, all `max_draw_count` commands are
+    // issued and unused ones must have a zero index or instance count.
+    pub fn cmd_draw_indexed_indirect_count(
+        &self,
+        cmd: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        count_buffer: vk::Buffer,
+        count_offset: vk::DeviceSize,
+        max_draw_count: u32,
+    ) {
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        match self.draw_indirect_count() {
+            Some(loader) => unsafe {
+                self.track_indirect_read(buffer);
+                self.track_indirect_read(count_buffer);
+                loader.cmd_draw_indexed_indirect_count(
+                    cmd,
+                    buffer,
+                    offset,
+                    count_buffer,
+                    count_offset,
+                    max_draw_count,
+                    stride,
+                );
+            },
+            None => self.cmd_draw_indexed_indirect(cmd, buffer, offset, max_draw_count),
+        }
+    }
+
+    fn track_indirect_read(&self, buffer: vk::Buffer) {
+        self.track_hazards(|tracker| {
+            tracker.read(
+                buffer,
+                "",
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::AccessFlags::INDIRECT_COMMAND_READ,
+            )
+        });
+    }
+
+    // Non-indexed variant of cmd_draw_indexed_indirect, for VkDrawIndirectCommand.
+    pub fn cmd_draw_indirect(
+        &self,
+        cmd: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+    ) {
+        let stride = std::mem::size_of::<vk::DrawIndirectCommand>() as u32;
+        self.track_indirect_read(buffer);
+        unsafe {
+            if self.supports_multi_draw_indirect() {
+                self.device()
+                    .cmd_draw_indirect(cmd, buffer, offset, draw_count, stride);
+            } else {
+                for i in 0..draw_count {
+                    let offset = offset + (i * stride) as vk::DeviceSize;
+                    self.device().cmd_draw_indirect(cmd, buffer, offset, 1, stride);
+                }
+            }
+        }
+    }
+
+    // Launches the task shader workgroups of the bound mesh shading pipeline, or the mesh shader
+    // ones without task stage, see PipelineInfo::mesh.
+    pub fn cmd_draw_mesh_tasks(
+        &self,
+        cmd: vk::CommandBuffer,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) {
+        let mesh_shader = self
+            .mesh_shader()
+            .expect("Mesh shaders unsupported, see RendererSettings::mesh_shader.");
+        unsafe {
+            mesh_shader.cmd_draw_mesh_tasks(cmd, group_count_x, group_count_y, group_count_z);
+        }
+    }
+
+    pub fn supports_ray_tracing(&self) -> bool {
+        self.shared_context.supports_ray_tracing()
+    }
+
+    pub fn require_ray_tracing(&self) -> Result<()> {
+        self.shared_context.require_ray_tracing()
+    }
+
     pub fn acceleration_structure(&self) -> &khr::AccelerationStructure {
         self.shared_context.acceleration_structure()
     }
@@ -508,7 +2157,7 @@ impl Context {
         self.shared_context.ray_tracing()
     }
 
-    pub unsafe fn ray_tracing_properties(&self) -> &vk::PhysicalDeviceRayTracingPipelinePropertiesKHR {
+    pub fn ray_tracing_properties(&self) -> &RayTracingProperties {
         self.shared_context.ray_tracing_properties()
     }
 
@@ -516,10 +2165,11 @@ impl Context {
         &self.shared_context
     }
 
+    // Ends on the calling thread, its command pool being used by this thread only.
     pub fn begin_single_time_cmd(&self) -> vk::CommandBuffer {
         let create_info = vk::CommandBufferAllocateInfo::builder()
             .command_buffer_count(1)
-            .command_pool(self.transient_command_pool)
+            .command_pool(self.acquire_transient_command_pool())
             .level(vk::CommandBufferLevel::PRIMARY);
         unsafe {
             let command_buffer = self
@@ -540,36 +2190,127 @@ impl Context {
         unsafe {
             self.device().end_command_buffer(command_buffer).unwrap();
 
+            // Waits on a fence rather than the queue, other threads can submit meanwhile.
+            let fence = self
+                .device()
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .unwrap();
             let command_buffers = vec![command_buffer];
             let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
-            self.device()
-                .queue_submit(
-                    self.graphics_queue(),
-                    &[submit_info.build()],
-                    vk::Fence::null(),
-                )
+            self.queue_submit(self.graphics_queue(), &[submit_info.build()], fence)
                 .expect("queue submit failed.");
 
             self.device()
-                .queue_wait_idle(self.graphics_queue())
+                .wait_for_fences(&[fence], true, u64::MAX)
                 .unwrap();
-            self.device()
-                .free_command_buffers(self.transient_command_pool, &command_buffers)
+            self.device().destroy_fence(fence, None);
+            self.free_transient_command_buffers(&command_buffers);
+        }
+    }
+
+    // Batches one-time command buffers in a single submission, see CommandBatch.
+    pub fn begin_batch(&self) -> CommandBatch<'_> {
+        CommandBatch::new(self)
+    }
+
+    // Transient command pool of the calling thread, for one more command buffer. Taken from the
+    // idle pools, or created, when the thread has no command buffer yet.
+    fn acquire_transient_command_pool(&self) -> vk::CommandPool {
+        let mut pools = self.transient_command_pools.lock().unwrap();
+        let TransientCommandPools { active, idle } = &mut *pools;
+        let (pool, count) = active
+            .entry(std::thread::current().id())
+            .or_insert_with(|| {
+                let pool = idle.pop().unwrap_or_else(|| unsafe {
+                    let pool_create_info = vk::CommandPoolCreateInfo::builder()
+                        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                        .queue_family_index(self.shared_context.queue_family_indices.graphics);
+                    self.device()
+                        .create_command_pool(&pool_create_info, None)
+                        .unwrap()
+                });
+                (pool, 0)
+            });
+        *count += 1;
+        *pool
+    }
+
+    // Frees command buffers of begin_single_time_cmd, on the thread which began them. The pool
+    // becomes idle once the thread has no command buffer left, so that pools of exited threads
+    // are reused by others.
+    pub(crate) fn free_transient_command_buffers(&self, command_buffers: &[vk::CommandBuffer]) {
+        let thread = std::thread::current().id();
+        let mut pools = self.transient_command_pools.lock().unwrap();
+        let (pool, count) = pools
+            .active
+            .get_mut(&thread)
+            .expect("Command buffers freed on another thread than the one which began them.");
+        unsafe {
+            self.device().free_command_buffers(*pool, command_buffers);
+        }
+        *count = count.saturating_sub(command_buffers.len());
+        if *count == 0 {
+            let (pool, _) = pools.active.remove(&thread).unwrap();
+            pools.idle.push(pool);
         }
     }
 
     pub fn request_command_buffer(&self, frame_index: usize) -> vk::CommandBuffer {
-        self.frame_command_pools[frame_index].reset();
-        self.frame_command_pools[frame_index].request_command_buffer()
+        let frame_pools = self.frame_command_pools.read().unwrap();
+        frame_pools[frame_index].reset();
+        let secondary_pools = self.frame_secondary_pools.read().unwrap();
+        secondary_pools[frame_index]
+            .lock()
+            .unwrap()
+            .values()
+            .for_each(CommandPool::reset);
+        frame_pools[frame_index].request_command_buffer()
+    }
+
+    // Matches the frame pools to a new swapchain image count, e.g. after a swapchain
+    // recreation. None of the command buffers of the frames may be pending execution.
+    pub fn resize_frame_pools(&self, frame_count: usize) {
+        let graphics_index = self.shared_context.queue_family_indices.graphics;
+        let mut frame_pools = self.frame_command_pools.write().unwrap();
+        frame_pools.truncate(frame_count);
+        while frame_pools.len() < frame_count {
+            frame_pools.push(CommandPool::new(
+                self.shared_context.clone(),
+                graphics_index,
+            ));
+        }
+        let mut secondary_pools = self.frame_secondary_pools.write().unwrap();
+        secondary_pools.resize_with(frame_count, || Mutex::new(HashMap::new()));
+    }
+
+    // Secondary command buffer of the calling thread, valid until the next
+    // request_command_buffer of the frame. Each thread records into its own pool.
+    pub fn request_secondary_command_buffer(&self, frame_index: usize) -> vk::CommandBuffer {
+        let secondary_pools = self.frame_secondary_pools.read().unwrap();
+        let mut pools = secondary_pools[frame_index].lock().unwrap();
+        pools
+            .entry(std::thread::current().id())
+            .or_insert_with(|| {
+                CommandPool::new_secondary(
+                    self.shared_context.clone(),
+                    self.shared_context.queue_family_indices.graphics,
+                )
+            })
+            .request_command_buffer()
     }
 }
 
 impl Drop for Context {
     fn drop(&mut self) {
         unsafe {
-            self.device()
-                .destroy_command_pool(self.transient_command_pool, None);
-            self.frame_command_pools.clear();
+            let device = self.shared_context.device();
+            let pools = self.transient_command_pools.get_mut().unwrap();
+            let active = pools.active.values().map(|(pool, _)| pool);
+            for pool in active.chain(&pools.idle) {
+                device.destroy_command_pool(*pool, None);
+            }
+            self.frame_command_pools.get_mut().unwrap().clear();
+            self.frame_secondary_pools.get_mut().unwrap().clear();
         }
     }
 }
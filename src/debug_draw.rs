@@ -0,0 +1,133 @@
+use crate::scene::{Camera, Gizmo};
+use crate::util::BasicVertex;
+use crate::{
+    AppRenderer, Context, Pipeline, PipelineBlendMode, PipelineInfo, PipelineLayout,
+    PipelineLayoutInfo, RenderArea, Resource, Result, TransientBufferAllocator, ENGINE_SHADER_DIR,
+};
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use std::path::Path;
+use std::sync::Arc;
+
+// Lines and triangles in world space (gizmos, bounds, debug vectors) collected during the frame
+// and drawn over it with cmd_draw in the overlay pass (AppRenderer::begin_overlay_pass), without
+// depth test. Vertices are written to the renderer transient buffer.
+pub struct DebugDraw {
+    context: Arc<Context>,
+    pipeline_layout: PipelineLayout,
+    line_pipeline: Pipeline,
+    triangle_pipeline: Pipeline,
+    lines: Vec<BasicVertex>,
+    triangles: Vec<BasicVertex>,
+}
+
+impl DebugDraw {
+    pub fn new(renderer: &AppRenderer) -> Result<Self> {
+        let context = renderer.context.clone();
+        let pipeline_layout = PipelineLayout::new(
+            context.clone(),
+            PipelineLayoutInfo::default().push_constant_range(
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::VERTEX)
+                    .size(std::mem::size_of::<Mat4>() as u32)
+                    .build(),
+            ),
+        );
+        let create_pipeline = |topology: vk::PrimitiveTopology, name: &str| {
+            let info = PipelineInfo {
+                depth_test_enabled: false,
+                depth_write_enabled: false,
+                ..Default::default()
+            }
+            .layout(pipeline_layout.handle())
+            .vert(Path::new(ENGINE_SHADER_DIR).join("debug_draw.vert"))
+            .frag(Path::new(ENGINE_SHADER_DIR).join("triangle.frag"))
+            .vertex_type::<BasicVertex>()
+            .topology(topology)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .blend_mode(PipelineBlendMode::Alpha)
+            .name(name.to_string());
+            let info = match renderer.uses_dynamic_rendering() {
+                true => info.rendering_formats(renderer.get_overlay_rendering_formats()),
+                false => info.render_pass(renderer.get_overlay_renderpass()),
+            };
+            Pipeline::new(context.clone(), info)
+        };
+        let line_pipeline = create_pipeline(vk::PrimitiveTopology::LINE_LIST, "DebugLines")?;
+        let triangle_pipeline =
+            create_pipeline(vk::PrimitiveTopology::TRIANGLE_LIST, "DebugTriangles")?;
+        Ok(DebugDraw {
+            context,
+            pipeline_layout,
+            line_pipeline,
+            triangle_pipeline,
+            lines: Vec::new(),
+            triangles: Vec::new(),
+        })
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        for pos in [start, end] {
+            self.lines.push(BasicVertex {
+                pos: pos.extend(1.0),
+                color,
+                uv: Vec2::ZERO,
+            });
+        }
+    }
+
+    // Triangle list vertices, three per triangle.
+    pub fn triangles(&mut self, vertices: &[BasicVertex]) {
+        self.triangles.extend_from_slice(vertices);
+    }
+
+    // Manipulator of `gizmo` as seen from `camera`, nothing when it isn't attached.
+    pub fn gizmo(&mut self, gizmo: &Gizmo, camera: &Camera) {
+        self.triangles(&gizmo.vertices(camera));
+    }
+
+    // Draws and clears the primitives collected since the last call. Must be called inside the
+    // overlay pass, `target` being the swapchain it draws to.
+    pub fn cmd_draw(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        view_projection: Mat4,
+        target: &impl RenderArea,
+        transient: &mut TransientBufferAllocator,
+    ) {
+        if self.lines.is_empty() && self.triangles.is_empty() {
+            return;
+        }
+        let device = self.context.device();
+        self.context.cmd_set_full_viewport(cmd, target);
+        unsafe {
+            let bytes = std::slice::from_raw_parts(
+                &view_projection as *const Mat4 as *const u8,
+                std::mem::size_of::<Mat4>(),
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout.handle(),
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                bytes,
+            );
+        }
+        let lines = std::mem::take(&mut self.lines);
+        let triangles = std::mem::take(&mut self.triangles);
+        for (pipeline, vertices) in [
+            (&self.line_pipeline, lines),
+            (&self.triangle_pipeline, triangles),
+        ] {
+            if vertices.is_empty() {
+                continue;
+            }
+            let slice = transient.push(&vertices);
+            unsafe {
+                device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline.handle());
+                device.cmd_bind_vertex_buffers(cmd, 0, &[slice.buffer], &[slice.offset]);
+                device.cmd_draw(cmd, vertices.len() as u32, 1, 0, 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use ash::extensions::ext::DebugUtils;
+use ash::vk;
+use ash::vk::Handle;
+use std::ffi::CString;
+
+// Vulkan object names and command buffer label regions (VK_EXT_debug_utils), shown by RenderDoc,
+// Nsight and in validation messages. The extension is always enabled on the instance.
+pub struct DebugMarker {
+    loader: DebugUtils,
+    device: vk::Device,
+}
+
+fn to_cstring(name: &str) -> CString {
+    CString::new(name.replace('\0', "")).unwrap()
+}
+
+impl DebugMarker {
+    pub(crate) fn new(loader: DebugUtils, device: vk::Device) -> Self {
+        DebugMarker { loader, device }
+    }
+
+    // Empty names are ignored, so unnamed infos keep the driver default.
+    pub fn set_name<H: Handle>(&self, handle: H, name: &str) {
+        let raw = handle.as_raw();
+        if name.is_empty() || raw == 0 {
+            return;
+        }
+        let name = to_cstring(name);
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(raw)
+            .object_name(&name);
+        unsafe {
+            // Naming is best effort, a failure doesn't affect rendering.
+            let _ = self
+                .loader
+                .debug_utils_set_object_name(self.device, &name_info);
+        }
+    }
+
+    // Opens a label region, closed by the next cmd_end_label. Regions can be nested.
+    pub fn cmd_begin_label(&self, cmd: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        let name = to_cstring(name);
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name)
+            .color(color);
+        unsafe {
+            self.loader.cmd_begin_debug_utils_label(cmd, &label);
+        }
+    }
+
+    pub fn cmd_end_label(&self, cmd: vk::CommandBuffer) {
+        unsafe {
+            self.loader.cmd_end_debug_utils_label(cmd);
+        }
+    }
+
+    pub fn cmd_insert_label(&self, cmd: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        let name = to_cstring(name);
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name)
+            .color(color);
+        unsafe {
+            self.loader.cmd_insert_debug_utils_label(cmd, &label);
+        }
+    }
+}
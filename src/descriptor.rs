@@ -1,8 +1,52 @@
 use crate::Context;
-use ash::vk;
-use std::collections::HashMap;
+use ash::vk::{self, Handle};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 
+// Each added descriptor pool holds twice the sets of the previous one, up to this many times
+// DescriptorSetLayoutInfo::min_max_sets.
+const MAX_POOL_GROWTH: u32 = 16;
+
+// Number of retired handles kept for the descriptor set caches. Caches which haven't been
+// used since older handles were retired drop all their sets.
+const RETIRED_HANDLE_WINDOW: usize = 4096;
+
+// Raw handles of the destroyed buffers, image views, samplers and acceleration structures, in
+// destruction order. Handle values can be reused by new objects, so cached descriptor sets
+// referencing them must be evicted before the next lookup.
+pub(crate) struct RetiredHandles {
+    first_generation: u64,
+    handles: VecDeque<u64>,
+}
+
+impl RetiredHandles {
+    pub(crate) fn new() -> Self {
+        RetiredHandles {
+            first_generation: 0,
+            handles: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn retire(&mut self, handle: u64) {
+        if self.handles.len() == RETIRED_HANDLE_WINDOW {
+            self.handles.pop_front();
+            self.first_generation += 1;
+        }
+        self.handles.push_back(handle);
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.first_generation + self.handles.len() as u64
+    }
+
+    // Handles retired since `generation`, None if some of them were already dropped.
+    pub(crate) fn since(&self, generation: u64) -> Option<HashSet<u64>> {
+        let start = generation.checked_sub(self.first_generation)? as usize;
+        Some(self.handles.iter().skip(start).copied().collect())
+    }
+}
+
+#[derive(Clone)]
 pub struct DescriptorSetInfo {
     pub buffer_infos: HashMap<u32, Vec<vk::DescriptorBufferInfo>>,
     pub image_infos: HashMap<u32, Vec<vk::DescriptorImageInfo>>,
@@ -125,6 +169,22 @@ impl DescriptorSetInfo {
             && self.buffer_infos.is_empty()
             && self.acceleration_structures.is_empty()
     }
+
+    fn references_any(&self, handles: &HashSet<u64>) -> bool {
+        self.buffer_infos
+            .values()
+            .flatten()
+            .any(|info| handles.contains(&info.buffer.as_raw()))
+            || self.image_infos.values().flatten().any(|info| {
+                handles.contains(&info.image_view.as_raw())
+                    || handles.contains(&info.sampler.as_raw())
+            })
+            || self
+                .acceleration_structures
+                .values()
+                .flatten()
+                .any(|accel_struct| handles.contains(&accel_struct.as_raw()))
+    }
 }
 
 impl std::hash::Hash for DescriptorSetInfo {
@@ -166,16 +226,27 @@ impl crate::Resource<vk::DescriptorSet> for DescriptorSet {
 
 pub struct DescriptorSetLayoutInfo {
     pub bindings: HashMap<u32, (vk::DescriptorType, vk::ShaderStageFlags, u32)>,
+    pub binding_flags: HashMap<u32, vk::DescriptorBindingFlags>,
     pub flags: vk::DescriptorSetLayoutCreateFlags,
+    // Sets of the first descriptor pool. More pools are chained when they are full, each one
+    // larger than the last (see DescriptorSetLayout::get_pool_stats).
     pub min_max_sets: u32,
+    // Least recently used sets are freed past this count, 0 for no limit. Must be larger than
+    // the sets used by the frames in flight.
+    pub max_cached_sets: usize,
+    // Debug name of the layout, its pool and sets.
+    pub name: String,
 }
 
 impl Default for DescriptorSetLayoutInfo {
     fn default() -> Self {
         DescriptorSetLayoutInfo {
             bindings: HashMap::new(),
+            binding_flags: HashMap::new(),
             flags: vk::DescriptorSetLayoutCreateFlags::default(),
             min_max_sets: 64,
+            max_cached_sets: 0,
+            name: "".to_string(),
         }
     }
 }
@@ -202,18 +273,95 @@ impl DescriptorSetLayoutInfo {
         self
     }
 
+    // Array of up to `max_count` descriptors which can be updated after the set is bound, with
+    // unused elements left unwritten. Must be the last binding of the layout, and needs
+    // Context::supports_bindless.
+    pub fn bindless(
+        mut self,
+        binding: u32,
+        descritor_type: vk::DescriptorType,
+        stage: vk::ShaderStageFlags,
+        max_count: u32,
+    ) -> Self {
+        self.bindings
+            .insert(binding, (descritor_type, stage, max_count));
+        self.binding_flags.insert(
+            binding,
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING
+                | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+        );
+        self.flags |= vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL;
+        self
+    }
+
     pub fn min_max_sets(mut self, min_max_sets: u32) -> Self {
         self.min_max_sets = min_max_sets;
         self
     }
+
+    pub fn max_cached_sets(mut self, max_cached_sets: usize) -> Self {
+        self.max_cached_sets = max_cached_sets;
+        self
+    }
+
+    pub fn name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    // The layout's bindings are pushed in command buffers (DescriptorSetLayout::
+    // cmd_push_descriptor_set) instead of allocated from a pool. Needs VK_KHR_push_descriptor.
+    pub fn push_descriptor(mut self) -> Self {
+        self.flags |= vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR;
+        self
+    }
+
+    pub fn is_push_descriptor(&self) -> bool {
+        self.flags
+            .contains(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+    }
+}
+
+struct DescriptorPool {
+    pool: vk::DescriptorPool,
+    max_sets: u32,
+    allocated_sets: u32,
+}
+
+// Descriptor pool usage of a DescriptorSetLayout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DescriptorPoolStats {
+    pub pool_count: usize,
+    // Sets the pools can hold, and the ones currently allocated from them.
+    pub max_sets: u32,
+    pub allocated_sets: u32,
+    // Sets of the DescriptorSetInfo cache, the others come from create_set.
+    pub cached_sets: usize,
+    // Allocations which failed because a pool was full or fragmented.
+    pub failed_allocations: u64,
+}
+
+struct CachedSet {
+    set: DescriptorSet,
+    pool: usize,
+    last_used: u64,
 }
 
 pub struct DescriptorSetLayout {
     context: Arc<Context>,
     layout: vk::DescriptorSetLayout,
-    pool: vk::DescriptorPool,
+    // Descriptors per set, scaled by the sets of each pool.
+    pool_sizes: Vec<vk::DescriptorPoolSize>,
+    pools: Vec<DescriptorPool>,
+    current_pool: usize,
+    failed_allocations: u64,
     info: DescriptorSetLayoutInfo,
-    sets: HashMap<DescriptorSetInfo, DescriptorSet>,
+    sets: HashMap<DescriptorSetInfo, CachedSet>,
+    // Retired handles generation of the last eviction.
+    generation: u64,
+    tick: u64,
 }
 
 impl DescriptorSetLayout {
@@ -221,7 +369,6 @@ impl DescriptorSetLayout {
         let n = info.bindings.len() as usize;
         let mut bindings: Vec<vk::DescriptorSetLayoutBinding> = Vec::with_capacity(n);
         let mut pool_sizes: Vec<vk::DescriptorPoolSize> = Vec::with_capacity(n);
-        let max_sets = info.min_max_sets; //TODO: max with swapchain image count
         for src_binding in &info.bindings {
             bindings.push(
                 vk::DescriptorSetLayoutBinding::builder()
@@ -234,62 +381,272 @@ impl DescriptorSetLayout {
             pool_sizes.push(
                 vk::DescriptorPoolSize::builder()
                     .ty((src_binding.1).0)
-                    .descriptor_count(max_sets * (src_binding.1).2)
+                    .descriptor_count((src_binding.1).2)
                     .build(),
             );
         }
 
-        let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        let binding_flags: Vec<_> = bindings
+            .iter()
+            .map(|binding| {
+                info.binding_flags
+                    .get(&binding.binding)
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(&binding_flags);
+        let mut create_info = vk::DescriptorSetLayoutCreateInfo::builder()
             .flags(info.flags)
             .bindings(&bindings);
+        if !info.binding_flags.is_empty() {
+            create_info = create_info.push_next(&mut binding_flags_info);
+        }
         unsafe {
+            if !info.binding_flags.is_empty() {
+                assert!(
+                    context.supports_bindless(),
+                    "Descriptor indexing features are not supported."
+                );
+            }
+            if info.is_push_descriptor() {
+                assert!(
+                    context.push_descriptor().is_some(),
+                    "VK_KHR_push_descriptor is not supported."
+                );
+            }
             let layout = context
                 .device()
                 .create_descriptor_set_layout(&create_info, None)
                 .expect("Failed to create DescriptorSetLayout");
+            context.debug_marker().set_name(layout, &info.name);
+            let generation = context.shared().retired_handles().generation();
 
-            let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-                .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
-                .max_sets(max_sets)
-                .pool_sizes(&pool_sizes);
-            let pool = context
-                .device()
-                .create_descriptor_pool(&pool_create_info, None)
-                .expect("Failed to create DescriptorPool");
-
-            DescriptorSetLayout {
+            let mut result = DescriptorSetLayout {
                 context,
                 layout,
-                pool,
+                pool_sizes,
+                pools: Vec::new(),
+                current_pool: 0,
+                failed_allocations: 0,
                 info: info,
-                sets: HashMap::<DescriptorSetInfo, DescriptorSet>::new(),
+                sets: HashMap::new(),
+                generation,
+                tick: 0,
+            };
+            // Push descriptor sets aren't allocated.
+            if !result.info.is_push_descriptor() {
+                result.add_pool();
             }
+            result
+        }
+    }
+
+    fn add_pool(&mut self) {
+        let mut flags = vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET;
+        if self
+            .info
+            .flags
+            .contains(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+        {
+            flags |= vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND;
+        }
+        let growth = (1u32 << self.pools.len().min(31)).min(MAX_POOL_GROWTH);
+        let max_sets = self.info.min_max_sets.max(1) * growth;
+        let pool_sizes: Vec<_> = self
+            .pool_sizes
+            .iter()
+            .map(|size| vk::DescriptorPoolSize {
+                ty: size.ty,
+                descriptor_count: size.descriptor_count * max_sets,
+            })
+            .collect();
+        let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(flags)
+            .max_sets(max_sets)
+            .pool_sizes(&pool_sizes);
+        let pool = unsafe {
+            self.context
+                .device()
+                .create_descriptor_pool(&pool_create_info, None)
+                .expect("Failed to create DescriptorPool")
+        };
+        if !self.info.name.is_empty() {
+            let name = format!("{} pool {}", self.info.name, self.pools.len());
+            self.context.debug_marker().set_name(pool, &name);
+        }
+        self.pools.push(DescriptorPool {
+            pool,
+            max_sets,
+            allocated_sets: 0,
+        });
+    }
+
+    fn try_allocate(&self, pool: usize) -> ash::prelude::VkResult<vk::DescriptorSet> {
+        let set_layouts = [self.layout];
+        // Variable count bindings are allocated at their maximum count.
+        let variable_counts: Vec<_> = self
+            .info
+            .binding_flags
+            .iter()
+            .filter(|(_, flags)| {
+                flags.contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT)
+            })
+            .map(|(binding, _)| self.get_descriptor_count(*binding))
+            .collect();
+        let mut variable_count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                .descriptor_counts(&variable_counts);
+        let mut allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pools[pool].pool)
+            .set_layouts(&set_layouts);
+        if !variable_counts.is_empty() {
+            allocate_info = allocate_info.push_next(&mut variable_count_info);
+        }
+        unsafe {
+            self.context
+                .device()
+                .allocate_descriptor_sets(&allocate_info)
+                .map(|sets| sets[0])
+        }
+    }
+
+    // Tries every pool, starting with the last one used, before adding a pool.
+    fn allocate(&mut self) -> (vk::DescriptorSet, usize) {
+        let count = self.pools.len();
+        for attempt in 0..count {
+            let pool = (self.current_pool + attempt) % count;
+            match self.try_allocate(pool) {
+                Ok(set) => {
+                    self.current_pool = pool;
+                    self.pools[pool].allocated_sets += 1;
+                    return (set, pool);
+                }
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL) => self.failed_allocations += 1,
+                Err(err) => panic!("Failed to create descriptor sets: {}", err),
+            }
+        }
+        self.add_pool();
+        self.current_pool = self.pools.len() - 1;
+        let set = self
+            .try_allocate(self.current_pool)
+            .expect("Failed to create descriptor sets.");
+        self.pools[self.current_pool].allocated_sets += 1;
+        (set, self.current_pool)
+    }
+
+    fn free(&mut self, cached: CachedSet) {
+        let pool = &mut self.pools[cached.pool];
+        unsafe {
+            self.context
+                .device()
+                .free_descriptor_sets(pool.pool, &[cached.set.handle])
+                .expect("Failed to free descriptor set.");
+        }
+        pool.allocated_sets -= 1;
+    }
+
+    // Frees the sets referencing resources destroyed since the last call.
+    fn evict_retired(&mut self) {
+        let (generation, retired) = {
+            let retired_handles = self.context.shared().retired_handles();
+            let generation = retired_handles.generation();
+            if generation == self.generation {
+                return;
+            }
+            (generation, retired_handles.since(self.generation))
+        };
+        self.generation = generation;
+        let evicted: Vec<_> = match retired {
+            Some(retired) => self
+                .sets
+                .keys()
+                .filter(|info| info.references_any(&retired))
+                .cloned()
+                .collect(),
+            None => self.sets.keys().cloned().collect(),
+        };
+        for info in evicted {
+            let cached = self.sets.remove(&info).unwrap();
+            self.free(cached);
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let max = self.info.max_cached_sets;
+        if max == 0 || self.sets.len() <= max {
+            return;
+        }
+        let mut by_age: Vec<_> = self
+            .sets
+            .iter()
+            .map(|(info, cached)| (cached.last_used, info.clone()))
+            .collect();
+        by_age.sort_by_key(|(last_used, _)| *last_used);
+        for (_, info) in by_age.into_iter().take(self.sets.len() - max) {
+            let cached = self.sets.remove(&info).unwrap();
+            self.free(cached);
         }
     }
 
     pub fn get_or_create(&mut self, info: DescriptorSetInfo) -> DescriptorSet {
         assert!(!info.is_empty());
+        assert!(
+            !self.info.is_push_descriptor(),
+            "Push descriptor sets are bound with cmd_push_descriptor_set."
+        );
+
+        self.evict_retired();
+        self.tick += 1;
+        if let Some(cached) = self.sets.get_mut(&info) {
+            cached.last_used = self.tick;
+            return cached.set;
+        }
 
-        if self.sets.contains_key(&info) {
-            return self.sets[&info];
+        let (handle, pool) = self.allocate();
+        let result = DescriptorSet { handle };
+        if !self.info.name.is_empty() {
+            let name = format!("{} set {}", self.info.name, self.tick);
+            self.context.debug_marker().set_name(handle, &name);
         }
+        self.update_sets(handle, &info);
+        self.sets.insert(
+            info,
+            CachedSet {
+                set: result,
+                pool,
+                last_used: self.tick,
+            },
+        );
+        self.evict_least_recently_used();
+        result
+    }
+
+    // Allocates a set outside of the cache, e.g. a bindless set written element by element. The
+    // set is freed with the layout or by reset_pool.
+    pub fn create_set(&mut self) -> DescriptorSet {
+        assert!(!self.info.is_push_descriptor());
+        let (handle, _) = self.allocate();
+        if !self.info.name.is_empty() {
+            let name = format!("{} set", self.info.name);
+            self.context.debug_marker().set_name(handle, &name);
+        }
+        DescriptorSet { handle }
+    }
 
-        unsafe {
-            let result = DescriptorSet {
-                handle: self
-                    .context
-                    .device()
-                    .allocate_descriptor_sets(
-                        &vk::DescriptorSetAllocateInfo::builder()
-                            .descriptor_pool(self.pool)
-                            .set_layouts(&[self.layout])
-                            .build(),
-                    )
-                    .expect("Failed to create descriptor sets.")[0],
-            };
-            self.update_sets(result.handle, &info);
-            self.sets.insert(info, result.clone());
-            result
+    pub fn cached_set_count(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn get_pool_stats(&self) -> DescriptorPoolStats {
+        DescriptorPoolStats {
+            pool_count: self.pools.len(),
+            max_sets: self.pools.iter().map(|pool| pool.max_sets).sum(),
+            allocated_sets: self.pools.iter().map(|pool| pool.allocated_sets).sum(),
+            cached_sets: self.sets.len(),
+            failed_allocations: self.failed_allocations,
         }
     }
 
@@ -305,7 +662,54 @@ impl DescriptorSetLayout {
         self.info.bindings[&binding].2
     }
 
+    // Number of dynamic offsets to bind a set of this layout with.
+    pub fn get_dynamic_offset_count(&self) -> usize {
+        self.info
+            .bindings
+            .values()
+            .filter(|(ty, _, _)| {
+                *ty == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+                    || *ty == vk::DescriptorType::STORAGE_BUFFER_DYNAMIC
+            })
+            .map(|(_, _, count)| *count as usize)
+            .sum()
+    }
+
+    // Pushes the descriptors of `info` as set number `set` of `pipeline_layout`, for transient
+    // bindings which don't need a cached descriptor set. The layout must be a push descriptor one.
+    pub fn cmd_push_descriptor_set(
+        &self,
+        cmd: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+        info: &DescriptorSetInfo,
+    ) {
+        assert!(self.info.is_push_descriptor());
+        let push_descriptor = self
+            .context
+            .push_descriptor()
+            .expect("VK_KHR_push_descriptor is not supported.");
+        // dst_set is ignored by push descriptors.
+        self.write_descriptors(vk::DescriptorSet::null(), info, |writes| unsafe {
+            push_descriptor.cmd_push_descriptor_set(cmd, bind_point, pipeline_layout, set, writes);
+        });
+    }
+
     fn update_sets(&self, set: vk::DescriptorSet, info: &DescriptorSetInfo) {
+        self.write_descriptors(set, info, |writes| unsafe {
+            self.context.device().update_descriptor_sets(writes, &[]);
+        });
+    }
+
+    // Calls `f` with the writes of `info` to `set`.
+    fn write_descriptors(
+        &self,
+        set: vk::DescriptorSet,
+        info: &DescriptorSetInfo,
+        f: impl FnOnce(&[vk::WriteDescriptorSet]),
+    ) {
+        self.validate(info);
         let capacity =
             info.buffer_infos.len() + info.image_infos.len() + info.acceleration_structures.len();
         let mut write_descriptor_sets = Vec::<vk::WriteDescriptorSet>::with_capacity(capacity);
@@ -333,36 +737,92 @@ impl DescriptorSetLayout {
             );
         }
 
-        for (binding, accel_structs) in &info.acceleration_structures {
-            let mut accel_info = vk::WriteDescriptorSetAccelerationStructureKHR::builder()
-                .acceleration_structures(&accel_structs)
-                .build();
+        // The writes point to these, which must outlive them.
+        let mut accel_infos: Vec<_> = info
+            .acceleration_structures
+            .iter()
+            .map(|(binding, accel_structs)| {
+                let accel_info = vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+                    .acceleration_structures(accel_structs)
+                    .build();
+                (*binding, accel_info)
+            })
+            .collect();
+        for (binding, accel_info) in &mut accel_infos {
             let mut accel_write = vk::WriteDescriptorSet::builder()
                 .dst_set(set)
                 .dst_binding(*binding)
                 .dst_array_element(0)
                 .descriptor_type(self.get_descriptor_type(*binding))
-                .push_next(&mut accel_info)
+                .push_next(accel_info)
                 .build();
             // This is only set by the builder for images, buffers, or views; need to set explicitly after
-            accel_write.descriptor_count = 1;
+            // One descriptor per acceleration structure, e.g. a static and a dynamic TLAS.
+            accel_write.descriptor_count = accel_info.acceleration_structure_count;
             write_descriptor_sets.push(accel_write);
         }
 
-        unsafe {
-            self.context
-                .device()
-                .update_descriptor_sets(&write_descriptor_sets, &[]);
+        f(&write_descriptor_sets);
+    }
+
+    // Checks the descriptors of `info` against the bindings of the layout. Arrays may be written
+    // partially, but not past the declared descriptor count.
+    fn validate(&self, info: &DescriptorSetInfo) {
+        let counts = info
+            .buffer_infos
+            .iter()
+            .map(|(binding, infos)| (*binding, infos.len()))
+            .chain(
+                info.image_infos
+                    .iter()
+                    .map(|(binding, infos)| (*binding, infos.len())),
+            )
+            .chain(
+                info.acceleration_structures
+                    .iter()
+                    .map(|(binding, structs)| (*binding, structs.len())),
+            );
+        for (binding, count) in counts {
+            let (_, _, declared_count) = self.info.bindings.get(&binding).unwrap_or_else(|| {
+                panic!(
+                    "Binding {} is not declared in the descriptor set layout \"{}\".",
+                    binding, self.info.name
+                )
+            });
+            assert!(
+                count > 0 && count <= *declared_count as usize,
+                "Binding {} of \"{}\" has {} descriptors, the layout declares {}.",
+                binding,
+                self.info.name,
+                count,
+                declared_count
+            );
+        }
+        for binding in info.acceleration_structures.keys() {
+            assert_eq!(
+                self.get_descriptor_type(*binding),
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                "Binding {} of \"{}\" is not an acceleration structure.",
+                binding,
+                self.info.name
+            );
         }
     }
 
-    pub fn reset_pool(&self) {
-        unsafe {
-            let flags = vk::DescriptorPoolResetFlags::default();
-            self.context
-                .device()
-                .reset_descriptor_pool(self.pool, flags)
-                .expect("Failed to reset descriptor pool.");
+    // Frees every set. Sets referencing destroyed resources are evicted automatically, so this
+    // is only needed to release the pools' memory.
+    pub fn reset_pool(&mut self) {
+        self.sets.clear();
+        self.current_pool = 0;
+        for pool in &mut self.pools {
+            unsafe {
+                let flags = vk::DescriptorPoolResetFlags::default();
+                self.context
+                    .device()
+                    .reset_descriptor_pool(pool.pool, flags)
+                    .expect("Failed to reset descriptor pool.");
+            }
+            pool.allocated_sets = 0;
         }
     }
 }
@@ -379,9 +839,11 @@ impl Drop for DescriptorSetLayout {
             self.context
                 .device()
                 .destroy_descriptor_set_layout(self.layout, None);
-            self.context
-                .device()
-                .destroy_descriptor_pool(self.pool, None);
+            for pool in &self.pools {
+                self.context
+                    .device()
+                    .destroy_descriptor_pool(pool.pool, None);
+            }
         }
     }
 }
@@ -0,0 +1,433 @@
+use ash::{vk, Instance};
+use std::collections::HashSet;
+use std::ffi::{c_void, CStr};
+use std::fmt;
+
+// Device features required by the application in addition to those sol enables when supported,
+// see RendererSettings::device_features. Requested members are OR-ed with the previous requests,
+// e.g. several vulkan12 calls. Context creation fails listing the unsupported ones.
+#[derive(Clone, Default)]
+pub struct DeviceFeatureRequest {
+    pub features: vk::PhysicalDeviceFeatures,
+    // Members of the Vulkan 1.1, 1.2 and 1.3 structures and of the extension structures, without
+    // their p_next chains.
+    structures: Vec<RequestedStructure>,
+}
+
+impl DeviceFeatureRequest {
+    pub fn features(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        merge_members(&mut self.features, &features.members());
+        self
+    }
+
+    // Core Vulkan 1.1, 1.2 and 1.3 features, only available when the device is used with that
+    // version (see RendererSettings::api_version). Features promoted to these versions must then
+    // be requested here rather than with the structures of their extensions.
+    pub fn vulkan11(self, features: vk::PhysicalDeviceVulkan11Features) -> Self {
+        self.structure(Source::Core(vk::API_VERSION_1_2), features)
+    }
+
+    pub fn vulkan12(self, features: vk::PhysicalDeviceVulkan12Features) -> Self {
+        self.structure(Source::Core(vk::API_VERSION_1_2), features)
+    }
+
+    pub fn vulkan13(self, features: vk::PhysicalDeviceVulkan13Features) -> Self {
+        self.structure(Source::Core(vk::API_VERSION_1_3), features)
+    }
+
+    // Enables the device extension `name` along with `features`, its feature structure, e.g.
+    // vk::PhysicalDeviceMeshShaderFeaturesNV for VK_NV_mesh_shader.
+    pub fn extension<T: FeatureStructure>(self, name: &'static CStr, features: T) -> Self {
+        self.structure(Source::Extension(name), features)
+    }
+
+    pub fn extension_names(&self) -> Vec<&'static CStr> {
+        self.structures
+            .iter()
+            .filter_map(|structure| match structure.source {
+                Source::Extension(name) => Some(name),
+                Source::Core(_) => None,
+            })
+            .collect()
+    }
+
+    fn structure<T: FeatureStructure>(mut self, source: Source, features: T) -> Self {
+        let members = FeatureMembers::members(&features);
+        let requested = self
+            .structures
+            .iter_mut()
+            .find(|structure| structure.type_name == T::TYPE_NAME);
+        match requested {
+            Some(structure) => {
+                for (member, requested) in structure.members.iter_mut().zip(members) {
+                    if requested == vk::TRUE {
+                        *member = vk::TRUE;
+                    }
+                }
+            }
+            None => self.structures.push(RequestedStructure {
+                source,
+                type_name: T::TYPE_NAME,
+                member_names: T::MEMBER_NAMES,
+                members,
+                new: new_structure::<T>,
+            }),
+        }
+        self
+    }
+
+    // Requested features and extensions the device doesn't support, used with `api_version`.
+    pub(crate) fn unsupported(
+        &self,
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+        api_version: u32,
+        supported_extensions: &HashSet<String>,
+    ) -> Vec<String> {
+        let supports = |name: &CStr| supported_extensions.contains(name.to_str().unwrap());
+        let mut unsupported = self
+            .extension_names()
+            .iter()
+            .filter(|name| !supports(name))
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        let supported = unsafe { instance.get_physical_device_features(pdevice) };
+        unsupported.extend(missing_members(
+            vk::PhysicalDeviceFeatures::TYPE_NAME,
+            vk::PhysicalDeviceFeatures::MEMBER_NAMES,
+            &self.features.members(),
+            &supported.members(),
+        ));
+
+        // Only structures of available versions and extensions may be chained. The members of
+        // unavailable versions are all missing.
+        let mut queried = self
+            .structures
+            .iter()
+            .filter(|structure| match structure.source {
+                Source::Core(version) => api_version >= version,
+                Source::Extension(name) => supports(name),
+            })
+            .map(|structure| (structure, structure.new_default()))
+            .collect::<Vec<_>>();
+        let mut features = vk::PhysicalDeviceFeatures2::default();
+        unsafe {
+            for (_, supported) in &mut queried {
+                chain(&mut features.p_next, supported.base_mut());
+            }
+            instance.get_physical_device_features2(pdevice, &mut features);
+        }
+        for structure in &self.structures {
+            let supported = queried
+                .iter()
+                .find(|(queried, _)| queried.type_name == structure.type_name)
+                .map(|(_, supported)| supported.members());
+            let supported = match (supported, structure.source) {
+                (Some(supported), _) => supported,
+                // Already listed by its extension name.
+                (None, Source::Extension(_)) => continue,
+                (None, Source::Core(_)) => vec![vk::FALSE; structure.members.len()],
+            };
+            unsupported.extend(missing_members(
+                structure.type_name,
+                structure.member_names,
+                &structure.members,
+                &supported,
+            ));
+        }
+        unsupported
+    }
+
+    // Feature structures to chain to the device creation info. The core version structures are
+    // only included when they request any feature.
+    pub(crate) fn feature_chain(&self) -> FeatureChain {
+        FeatureChain {
+            features: Box::new(self.features),
+            structures: self
+                .structures
+                .iter()
+                .filter(|structure| {
+                    matches!(structure.source, Source::Extension(_))
+                        || structure.members.contains(&vk::TRUE)
+                })
+                .map(|structure| (structure.new)(&structure.members))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for DeviceFeatureRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Members requested, as missing from a device supporting none.
+        let mut requested = missing_members(
+            vk::PhysicalDeviceFeatures::TYPE_NAME,
+            vk::PhysicalDeviceFeatures::MEMBER_NAMES,
+            &self.features.members(),
+            &vk::PhysicalDeviceFeatures::default().members(),
+        );
+        for structure in &self.structures {
+            requested.extend(missing_members(
+                structure.type_name,
+                structure.member_names,
+                &structure.members,
+                &vec![vk::FALSE; structure.members.len()],
+            ));
+        }
+        f.debug_struct("DeviceFeatureRequest")
+            .field("features", &requested)
+            .field("extensions", &self.extension_names())
+            .finish()
+    }
+}
+
+// Feature structures chained to a device creation info, which must not outlive them.
+pub(crate) struct FeatureChain {
+    features: Box<vk::PhysicalDeviceFeatures>,
+    structures: Vec<Box<dyn DynFeatureStructure>>,
+}
+
+impl FeatureChain {
+    pub(crate) unsafe fn chain_to(&mut self, create_info: &mut vk::DeviceCreateInfo) {
+        create_info.p_enabled_features = &*self.features;
+        let next = &mut *(&mut create_info.p_next as *mut *const c_void as *mut *mut c_void);
+        for structure in &mut self.structures {
+            chain(next, structure.base_mut());
+        }
+    }
+}
+
+// The VkBool32 members of a Vulkan feature structure, listed explicitly as the structures may
+// end with padding.
+pub trait FeatureMembers {
+    const TYPE_NAME: &'static str;
+    const MEMBER_NAMES: &'static [&'static str];
+
+    fn members(&self) -> Vec<vk::Bool32>;
+    // `members` holds one value per member name.
+    fn set_members(&mut self, members: &[vk::Bool32]);
+}
+
+// Vulkan feature structures which may be requested, e.g. vk::PhysicalDeviceMeshShaderFeaturesNV.
+pub trait FeatureStructure:
+    FeatureMembers
+    + vk::ExtendsDeviceCreateInfo
+    + vk::ExtendsPhysicalDeviceFeatures2
+    + Default
+    + 'static
+{
+}
+
+impl<T> FeatureStructure for T where
+    T: FeatureMembers
+        + vk::ExtendsDeviceCreateInfo
+        + vk::ExtendsPhysicalDeviceFeatures2
+        + Default
+        + 'static
+{
+}
+
+macro_rules! feature_members {
+    ($($structure:ident { $($member:ident),* $(,)? })*) => {
+        $(
+            impl FeatureMembers for vk::$structure {
+                const TYPE_NAME: &'static str = stringify!($structure);
+                const MEMBER_NAMES: &'static [&'static str] = &[$(stringify!($member)),*];
+
+                fn members(&self) -> Vec<vk::Bool32> {
+                    vec![$(self.$member),*]
+                }
+
+                fn set_members(&mut self, members: &[vk::Bool32]) {
+                    let mut members = members.iter().copied();
+                    $(self.$member = members.next().unwrap();)*
+                }
+            }
+        )*
+    };
+}
+
+feature_members! {
+    PhysicalDeviceFeatures {
+        robust_buffer_access, full_draw_index_uint32, image_cube_array, independent_blend,
+        geometry_shader, tessellation_shader, sample_rate_shading, dual_src_blend, logic_op,
+        multi_draw_indirect, draw_indirect_first_instance, depth_clamp, depth_bias_clamp,
+        fill_mode_non_solid, depth_bounds, wide_lines, large_points, alpha_to_one, multi_viewport,
+        sampler_anisotropy, texture_compression_etc2, texture_compression_astc_ldr,
+        texture_compression_bc, occlusion_query_precise, pipeline_statistics_query,
+        vertex_pipeline_stores_and_atomics, fragment_stores_and_atomics,
+        shader_tessellation_and_geometry_point_size, shader_image_gather_extended,
+        shader_storage_image_extended_formats, shader_storage_image_multisample,
+        shader_storage_image_read_without_format, shader_storage_image_write_without_format,
+        shader_uniform_buffer_array_dynamic_indexing, shader_sampled_image_array_dynamic_indexing,
+        shader_storage_buffer_array_dynamic_indexing, shader_storage_image_array_dynamic_indexing,
+        shader_clip_distance, shader_cull_distance, shader_float64, shader_int64, shader_int16,
+        shader_resource_residency, shader_resource_min_lod, sparse_binding,
+        sparse_residency_buffer, sparse_residency_image2_d, sparse_residency_image3_d,
+        sparse_residency2_samples, sparse_residency4_samples, sparse_residency8_samples,
+        sparse_residency16_samples, sparse_residency_aliased, variable_multisample_rate,
+        inherited_queries,
+    }
+    PhysicalDeviceVulkan11Features {
+        storage_buffer16_bit_access, uniform_and_storage_buffer16_bit_access,
+        storage_push_constant16, storage_input_output16, multiview, multiview_geometry_shader,
+        multiview_tessellation_shader, variable_pointers_storage_buffer, variable_pointers,
+        protected_memory, sampler_ycbcr_conversion, shader_draw_parameters,
+    }
+    PhysicalDeviceVulkan12Features {
+        sampler_mirror_clamp_to_edge, draw_indirect_count, storage_buffer8_bit_access,
+        uniform_and_storage_buffer8_bit_access, storage_push_constant8,
+        shader_buffer_int64_atomics, shader_shared_int64_atomics, shader_float16, shader_int8,
+        descriptor_indexing, shader_input_attachment_array_dynamic_indexing,
+        shader_uniform_texel_buffer_array_dynamic_indexing,
+        shader_storage_texel_buffer_array_dynamic_indexing,
+        shader_uniform_buffer_array_non_uniform_indexing,
+        shader_sampled_image_array_non_uniform_indexing,
+        shader_storage_buffer_array_non_uniform_indexing,
+        shader_storage_image_array_non_uniform_indexing,
+        shader_input_attachment_array_non_uniform_indexing,
+        shader_uniform_texel_buffer_array_non_uniform_indexing,
+        shader_storage_texel_buffer_array_non_uniform_indexing,
+        descriptor_binding_uniform_buffer_update_after_bind,
+        descriptor_binding_sampled_image_update_after_bind,
+        descriptor_binding_storage_image_update_after_bind,
+        descriptor_binding_storage_buffer_update_after_bind,
+        descriptor_binding_uniform_texel_buffer_update_after_bind,
+        descriptor_binding_storage_texel_buffer_update_after_bind,
+        descriptor_binding_update_unused_while_pending, descriptor_binding_partially_bound,
+        descriptor_binding_variable_descriptor_count, runtime_descriptor_array,
+        sampler_filter_minmax, scalar_block_layout, imageless_framebuffer,
+        uniform_buffer_standard_layout, shader_subgroup_extended_types,
+        separate_depth_stencil_layouts, host_query_reset, timeline_semaphore,
+        buffer_device_address, buffer_device_address_capture_replay,
+        buffer_device_address_multi_device, vulkan_memory_model, vulkan_memory_model_device_scope,
+        vulkan_memory_model_availability_visibility_chains, shader_output_viewport_index,
+        shader_output_layer, subgroup_broadcast_dynamic_id,
+    }
+    PhysicalDeviceVulkan13Features {
+        robust_image_access, inline_uniform_block,
+        descriptor_binding_inline_uniform_block_update_after_bind, pipeline_creation_cache_control,
+        private_data, shader_demote_to_helper_invocation, shader_terminate_invocation,
+        subgroup_size_control, compute_full_subgroups, synchronization2,
+        texture_compression_astc_hdr, shader_zero_initialize_workgroup_memory, dynamic_rendering,
+        shader_integer_dot_product, maintenance4,
+    }
+    PhysicalDeviceDescriptorIndexingFeatures {
+        shader_input_attachment_array_dynamic_indexing,
+        shader_uniform_texel_buffer_array_dynamic_indexing,
+        shader_storage_texel_buffer_array_dynamic_indexing,
+        shader_uniform_buffer_array_non_uniform_indexing,
+        shader_sampled_image_array_non_uniform_indexing,
+        shader_storage_buffer_array_non_uniform_indexing,
+        shader_storage_image_array_non_uniform_indexing,
+        shader_input_attachment_array_non_uniform_indexing,
+        shader_uniform_texel_buffer_array_non_uniform_indexing,
+        shader_storage_texel_buffer_array_non_uniform_indexing,
+        descriptor_binding_uniform_buffer_update_after_bind,
+        descriptor_binding_sampled_image_update_after_bind,
+        descriptor_binding_storage_image_update_after_bind,
+        descriptor_binding_storage_buffer_update_after_bind,
+        descriptor_binding_uniform_texel_buffer_update_after_bind,
+        descriptor_binding_storage_texel_buffer_update_after_bind,
+        descriptor_binding_update_unused_while_pending, descriptor_binding_partially_bound,
+        descriptor_binding_variable_descriptor_count, runtime_descriptor_array,
+    }
+    PhysicalDeviceBufferDeviceAddressFeatures {
+        buffer_device_address, buffer_device_address_capture_replay,
+        buffer_device_address_multi_device,
+    }
+    PhysicalDeviceScalarBlockLayoutFeatures { scalar_block_layout }
+    PhysicalDeviceTimelineSemaphoreFeatures { timeline_semaphore }
+    PhysicalDeviceDynamicRenderingFeatures { dynamic_rendering }
+    PhysicalDeviceSynchronization2Features { synchronization2 }
+    PhysicalDeviceMaintenance4Features { maintenance4 }
+    PhysicalDeviceAccelerationStructureFeaturesKHR {
+        acceleration_structure, acceleration_structure_capture_replay,
+        acceleration_structure_indirect_build, acceleration_structure_host_commands,
+        descriptor_binding_acceleration_structure_update_after_bind,
+    }
+    PhysicalDeviceRayTracingPipelineFeaturesKHR {
+        ray_tracing_pipeline, ray_tracing_pipeline_shader_group_handle_capture_replay,
+        ray_tracing_pipeline_shader_group_handle_capture_replay_mixed,
+        ray_tracing_pipeline_trace_rays_indirect, ray_traversal_primitive_culling,
+    }
+    PhysicalDeviceRayQueryFeaturesKHR { ray_query }
+    PhysicalDeviceMeshShaderFeaturesNV { task_shader, mesh_shader }
+}
+
+#[derive(Clone, Copy)]
+enum Source {
+    // Core structure of the Vulkan version.
+    Core(u32),
+    Extension(&'static CStr),
+}
+
+#[derive(Clone)]
+struct RequestedStructure {
+    source: Source,
+    type_name: &'static str,
+    member_names: &'static [&'static str],
+    members: Vec<vk::Bool32>,
+    // Creates the structure with the given members.
+    new: fn(&[vk::Bool32]) -> Box<dyn DynFeatureStructure>,
+}
+
+impl RequestedStructure {
+    fn new_default(&self) -> Box<dyn DynFeatureStructure> {
+        (self.new)(&vec![vk::FALSE; self.members.len()])
+    }
+}
+
+// Object safe part of FeatureStructure.
+trait DynFeatureStructure {
+    fn members(&self) -> Vec<vk::Bool32>;
+    fn base_mut(&mut self) -> *mut vk::BaseOutStructure;
+}
+
+impl<T: FeatureStructure> DynFeatureStructure for T {
+    fn members(&self) -> Vec<vk::Bool32> {
+        FeatureMembers::members(self)
+    }
+
+    fn base_mut(&mut self) -> *mut vk::BaseOutStructure {
+        self as *mut T as *mut vk::BaseOutStructure
+    }
+}
+
+fn new_structure<T: FeatureStructure>(members: &[vk::Bool32]) -> Box<dyn DynFeatureStructure> {
+    let mut structure = T::default();
+    structure.set_members(members);
+    Box::new(structure)
+}
+
+fn merge_members<T: FeatureMembers>(features: &mut T, requested: &[vk::Bool32]) {
+    let mut members = features.members();
+    for (member, requested) in members.iter_mut().zip(requested) {
+        if *requested == vk::TRUE {
+            *member = vk::TRUE;
+        }
+    }
+    features.set_members(&members);
+}
+
+// Names of the members requested but not supported, e.g. "PhysicalDeviceVulkan12Features::
+// shader_float16".
+fn missing_members(
+    type_name: &str,
+    member_names: &[&str],
+    requested: &[vk::Bool32],
+    supported: &[vk::Bool32],
+) -> Vec<String> {
+    member_names
+        .iter()
+        .zip(requested.iter().zip(supported))
+        .filter(|(_, (requested, supported))| **requested == vk::TRUE && **supported != vk::TRUE)
+        .map(|(name, _)| format!("{}::{}", type_name, name))
+        .collect()
+}
+
+// Inserts `structure` at the front of the p_next chain starting at `next`.
+unsafe fn chain(next: &mut *mut c_void, structure: *mut vk::BaseOutStructure) {
+    (*structure).p_next = *next as *mut vk::BaseOutStructure;
+    *next = structure as *mut c_void;
+}
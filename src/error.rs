@@ -0,0 +1,107 @@
+use ash::vk;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    Vulkan(vk::Result),
+    ShaderCompilation { name: String, log: String },
+    Io { path: PathBuf, source: std::io::Error },
+    Allocation(gpu_allocator::AllocationError),
+    Image(image::ImageError),
+    Gltf(gltf::Error),
+    UnsupportedFormat {
+        name: String,
+        format: vk::Format,
+        features: vk::FormatFeatureFlags,
+    },
+    // A KTX2 or DDS file which is malformed or uses features the loader doesn't support.
+    InvalidTexture {
+        name: String,
+        reason: String,
+    },
+    // The operation was cancelled through its ProgressToken.
+    Cancelled,
+    // An optional device feature the operation requires, e.g. ray tracing.
+    UnsupportedFeature {
+        feature: &'static str,
+        reason: String,
+    },
+}
+
+// The error parameter defaults to sol::Error but can still be given, like std::result::Result.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Vulkan(result) => write!(f, "Vulkan error: {}", result),
+            Error::ShaderCompilation { name, log } => {
+                write!(f, "Failed to compile shader {}:\n{}", name, log)
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Allocation(error) => write!(f, "Allocation error: {}", error),
+            Error::Image(error) => write!(f, "Image error: {}", error),
+            Error::Gltf(error) => write!(f, "glTF error: {}", error),
+            Error::UnsupportedFormat {
+                name,
+                format,
+                features,
+            } => write!(
+                f,
+                "{}: format {:?} doesn't support {:?}, see find_supported_format.",
+                name, format, features
+            ),
+            Error::InvalidTexture { name, reason } => write!(f, "{}: {}", name, reason),
+            Error::Cancelled => write!(f, "Operation cancelled."),
+            Error::UnsupportedFeature { feature, reason } => {
+                write!(f, "{} unsupported: {}", feature, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Allocation(error) => Some(error),
+            Error::Image(error) => Some(error),
+            Error::Gltf(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<vk::Result> for Error {
+    fn from(result: vk::Result) -> Self {
+        Error::Vulkan(result)
+    }
+}
+
+impl From<gpu_allocator::AllocationError> for Error {
+    fn from(error: gpu_allocator::AllocationError) -> Self {
+        Error::Allocation(error)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(error: image::ImageError) -> Self {
+        Error::Image(error)
+    }
+}
+
+impl From<gltf::Error> for Error {
+    fn from(error: gltf::Error) -> Self {
+        Error::Gltf(error)
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+// Fixed updates run per frame at most. Time beyond is dropped so that a long stall (e.g. a
+// breakpoint or a window drag) doesn't trigger a burst of catch-up updates.
+const MAX_FIXED_UPDATES_PER_FRAME: u32 = 8;
+// Remaining wait spun on by SleepStrategy::SleepSpin.
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+// Passed to UpdateFn. With AppSettings::fixed_timestep, `delta` is the timestep and `elapsed`
+// the simulated time, advancing by exactly one timestep per update.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTime {
+    pub elapsed: Duration,
+    pub delta: Duration,
+}
+
+impl FrameTime {
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+}
+
+// How the main loop waits for the next frame when AppSettings::target_fps is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SleepStrategy {
+    // Lowest CPU use, frames can run late by the OS timer resolution (up to 15 ms on Windows).
+    #[default]
+    Sleep,
+    // Sleeps until shortly before the deadline, then spins for accurate frame pacing.
+    SleepSpin,
+}
+
+pub(crate) struct FrameTimer {
+    start: Instant,
+    last_frame: Instant,
+    accumulator: Duration,
+    simulated: Duration,
+}
+
+impl FrameTimer {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        FrameTimer {
+            start: now,
+            last_frame: now,
+            accumulator: Duration::ZERO,
+            simulated: Duration::ZERO,
+        }
+    }
+
+    // Waits until 1 / target_fps after the previous frame, then starts a new frame.
+    pub(crate) fn begin_frame(
+        &mut self,
+        target_fps: Option<f32>,
+        strategy: SleepStrategy,
+    ) -> FrameTime {
+        if let Some(fps) = target_fps.filter(|fps| *fps > 0.0) {
+            let deadline = self.last_frame + Duration::from_secs_f32(1.0 / fps);
+            wait_until(deadline, strategy);
+        }
+        let now = Instant::now();
+        let delta = now - self.last_frame;
+        self.last_frame = now;
+        self.accumulator += delta;
+        FrameTime {
+            elapsed: now - self.start,
+            delta,
+        }
+    }
+
+    // Consumes the time accumulated by begin_frame, one FrameTime per whole `step`.
+    pub(crate) fn fixed_updates(&mut self, step: Duration) -> Vec<FrameTime> {
+        assert!(!step.is_zero(), "The fixed timestep must not be zero.");
+        self.accumulator = self.accumulator.min(step * MAX_FIXED_UPDATES_PER_FRAME);
+        let mut updates = Vec::new();
+        while self.accumulator >= step {
+            self.accumulator -= step;
+            self.simulated += step;
+            updates.push(FrameTime {
+                elapsed: self.simulated,
+                delta: step,
+            });
+        }
+        updates
+    }
+
+    // Fraction of a step left in the accumulator, to interpolate between the last two updates.
+    pub(crate) fn fixed_alpha(&self, step: Duration) -> f32 {
+        self.accumulator.as_secs_f32() / step.as_secs_f32()
+    }
+
+    // Variable rate updates don't accumulate time.
+    pub(crate) fn reset_accumulator(&mut self) {
+        self.accumulator = Duration::ZERO;
+    }
+}
+
+fn wait_until(deadline: Instant, strategy: SleepStrategy) {
+    let now = Instant::now();
+    if now >= deadline {
+        return;
+    }
+    match strategy {
+        SleepStrategy::Sleep => std::thread::sleep(deadline - now),
+        SleepStrategy::SleepSpin => {
+            if deadline - now > SPIN_MARGIN {
+                std::thread::sleep(deadline - now - SPIN_MARGIN);
+            }
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use crate::{
+    Context, DescriptorSetInfo, DescriptorSetLayout, DescriptorSetLayoutInfo, GpuPhase, Image2d,
+    Pipeline, PipelineInfo, PipelineLayout, PipelineLayoutInfo, RenderPass, Resource, Result,
+    SamplerInfo, ENGINE_SHADER_DIR,
+};
+use ash::vk;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub struct FullscreenPassInfo {
+    // Fragment shader receiving the uv at location 0 (see PipelineInfo::fullscreen) and sampling
+    // the input as `sampler2D` at binding 0 of set 0.
+    pub frag: PathBuf,
+    pub color_format: vk::Format,
+    // Layout of the output after the pass, e.g. PRESENT_SRC_KHR for a swapchain image.
+    pub final_layout: vk::ImageLayout,
+    pub sampler: SamplerInfo,
+    // Size of the fragment shader push constants given to cmd_draw.
+    pub push_constants_size: u32,
+    pub name: String,
+}
+
+impl Default for FullscreenPassInfo {
+    fn default() -> Self {
+        FullscreenPassInfo {
+            frag: Path::new(ENGINE_SHADER_DIR).join("blit.frag"),
+            color_format: vk::Format::B8G8R8A8_UNORM,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            sampler: SamplerInfo::default()
+                .clamp_to_edge()
+                .no_anisotropy()
+                .max_lod(0.0),
+            push_constants_size: 0,
+            name: "FullscreenPass".to_string(),
+        }
+    }
+}
+
+impl FullscreenPassInfo {
+    pub fn frag(mut self, path: PathBuf) -> Self {
+        self.frag = path;
+        self
+    }
+    pub fn color_format(mut self, format: vk::Format) -> Self {
+        self.color_format = format;
+        self
+    }
+    pub fn final_layout(mut self, layout: vk::ImageLayout) -> Self {
+        self.final_layout = layout;
+        self
+    }
+    pub fn sampler(mut self, sampler: SamplerInfo) -> Self {
+        self.sampler = sampler;
+        self
+    }
+    pub fn push_constants_size(mut self, size: u32) -> Self {
+        self.push_constants_size = size;
+        self
+    }
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+}
+
+// Draws a fullscreen triangle with a fragment shader sampling an input image, over the whole
+// output image. Unlike Image2d::cmd_blit_to, the input is filtered by the sampler and can be
+// converted or processed in the shader, e.g. to present an HDR target.
+pub struct FullscreenPass {
+    context: Arc<Context>,
+    info: FullscreenPassInfo,
+    render_pass: RenderPass,
+    layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: Pipeline,
+    sampler: vk::Sampler,
+    // Per output image view, evicted when the view is destroyed.
+    framebuffers: HashMap<vk::ImageView, vk::Framebuffer>,
+    // Retired handles generation of the last eviction.
+    generation: u64,
+}
+
+fn create_pipeline(
+    context: &Arc<Context>,
+    info: &FullscreenPassInfo,
+    render_pass: &RenderPass,
+    pipeline_layout: &PipelineLayout,
+) -> Result<Pipeline> {
+    Pipeline::new(
+        context.clone(),
+        PipelineInfo::default()
+            .fullscreen()
+            .frag(info.frag.clone())
+            .layout(pipeline_layout.handle())
+            .render_pass(render_pass.handle())
+            .name(info.name.clone()),
+    )
+}
+
+impl FullscreenPass {
+    pub fn new(context: Arc<Context>, info: FullscreenPassInfo) -> Result<Self> {
+        // The previous content of the output is discarded.
+        let attachments = [vk::AttachmentDescription::builder()
+            .format(info.color_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(info.final_layout)
+            .build()];
+        let color_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs)
+            .build()];
+        let dependencies = [
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
+                src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
+                dst_access_mask: vk::AccessFlags::MEMORY_READ,
+                ..Default::default()
+            },
+        ];
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        let render_pass = RenderPass::new_raw(context.shared().clone(), &render_pass_info);
+
+        let layout = DescriptorSetLayout::new(
+            context.clone(),
+            DescriptorSetLayoutInfo::default().binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+        );
+        let mut layout_info = PipelineLayoutInfo::default().desc_set_layout(layout.handle());
+        if info.push_constants_size > 0 {
+            layout_info = layout_info.push_constant_range(
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .size(info.push_constants_size)
+                    .build(),
+            );
+        }
+        let pipeline_layout = PipelineLayout::new(context.clone(), layout_info);
+        let pipeline = create_pipeline(&context, &info, &render_pass, &pipeline_layout)?;
+        let sampler = info.sampler.create_sampler(&context, 1);
+        let generation = context.shared().retired_handles().generation();
+        Ok(FullscreenPass {
+            context,
+            info,
+            render_pass,
+            layout,
+            pipeline_layout,
+            pipeline,
+            sampler,
+            framebuffers: HashMap::new(),
+            generation,
+        })
+    }
+
+    // Linear filtered copy of the input into outputs of `color_format`.
+    pub fn blit(
+        context: Arc<Context>,
+        color_format: vk::Format,
+        final_layout: vk::ImageLayout,
+    ) -> Result<Self> {
+        Self::new(
+            context,
+            FullscreenPassInfo::default()
+                .color_format(color_format)
+                .final_layout(final_layout)
+                .name("Blit"),
+        )
+    }
+
+    // True when the fragment shader or its includes changed since the pipeline was built.
+    pub fn is_outdated(&self) -> bool {
+        self.pipeline.is_outdated()
+    }
+
+    // Rebuilds the pipeline, keeping the previous one on error. Frames in flight must have
+    // completed.
+    pub fn reload(&mut self) -> Result<()> {
+        self.pipeline = create_pipeline(
+            &self.context,
+            &self.info,
+            &self.render_pass,
+            &self.pipeline_layout,
+        )?;
+        Ok(())
+    }
+
+    // Destroys the framebuffers of the output views destroyed since the last call.
+    fn evict_retired(&mut self) {
+        let (generation, retired) = {
+            let retired_handles = self.context.shared().retired_handles();
+            let generation = retired_handles.generation();
+            if generation == self.generation {
+                return;
+            }
+            (generation, retired_handles.since(self.generation))
+        };
+        self.generation = generation;
+        let device = self.context.device();
+        self.framebuffers.retain(|view, framebuffer| {
+            let keep = retired
+                .as_ref()
+                .is_some_and(|retired| !retired.contains(&vk::Handle::as_raw(*view)));
+            if !keep {
+                unsafe { device.destroy_framebuffer(*framebuffer, None) };
+            }
+            keep
+        });
+    }
+
+    fn get_framebuffer(&mut self, output: &Image2d) -> vk::Framebuffer {
+        let (context, render_pass) = (&self.context, &self.render_pass);
+        *self
+            .framebuffers
+            .entry(output.get_image_view())
+            .or_insert_with(|| {
+                let extent = output.get_extent();
+                let attachments = [output.get_image_view()];
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass.handle())
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+                unsafe {
+                    context
+                        .device()
+                        .create_framebuffer(&framebuffer_info, None)
+                        .expect("Unable to create fullscreen pass framebuffer")
+                }
+            })
+    }
+
+    // Transitions the input (with SAMPLED usage) to SHADER_READ_ONLY_OPTIMAL and draws over the
+    // whole output (a color attachment of the info color_format), left in the info final_layout.
+    pub fn cmd_draw(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        input: &mut Image2d,
+        output: &mut Image2d,
+        push_constants: &[u8],
+    ) {
+        assert_eq!(output.get_format(), self.info.color_format);
+        assert_eq!(push_constants.len(), self.info.push_constants_size as usize);
+        self.evict_retired();
+        self.context.begin_gpu_phase(cmd, GpuPhase::Post);
+        input.transition_image_layout(
+            cmd,
+            input.get_layout(),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        let framebuffer = self.get_framebuffer(output);
+        let image_info = vk::DescriptorImageInfo::builder()
+            .sampler(self.sampler)
+            .image_view(input.get_image_view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build();
+        let desc_set = self
+            .layout
+            .get_or_create(DescriptorSetInfo::default().image(0, image_info));
+        let final_layout = self.info.final_layout;
+        self.context.track_hazards(|tracker| {
+            let (image, name) = (input.handle(), input.get_name());
+            tracker.read(
+                image,
+                name,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            );
+            let (image, name) = (output.handle(), output.get_name());
+            tracker.barrier(
+                image,
+                name,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                Some((
+                    output.get_layout(),
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )),
+            );
+            tracker.write(
+                image,
+                name,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            );
+            tracker.set_layout(image, name, final_layout);
+        });
+        self.render_pass.cmd_begin(
+            cmd,
+            framebuffer,
+            output.get_extent(),
+            &[],
+            vk::SubpassContents::INLINE,
+        );
+        self.context.cmd_set_full_viewport(cmd, output);
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline.handle());
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout.handle(),
+                0,
+                &[desc_set.handle()],
+                &[],
+            );
+            if !push_constants.is_empty() {
+                device.cmd_push_constants(
+                    cmd,
+                    self.pipeline_layout.handle(),
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    push_constants,
+                );
+            }
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+            device.cmd_end_render_pass(cmd);
+        }
+        output.set_layout(final_layout);
+        self.context.end_gpu_phase(cmd, GpuPhase::Post);
+    }
+}
+
+impl Drop for FullscreenPass {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            for framebuffer in self.framebuffers.values() {
+                device.destroy_framebuffer(*framebuffer, None);
+            }
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.context.shared().retire_handle(self.sampler);
+    }
+}
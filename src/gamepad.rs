@@ -0,0 +1,160 @@
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use glam::{vec2, Vec2};
+
+pub use gilrs::Button as GamepadButton;
+
+// Stick deflections below this radius read as zero, resting sticks rarely report exactly 0.
+const STICK_DEADZONE: f32 = 0.15;
+
+const BUTTONS: [Button; 17] = [
+    Button::South,
+    Button::East,
+    Button::North,
+    Button::West,
+    Button::LeftTrigger,
+    Button::LeftTrigger2,
+    Button::RightTrigger,
+    Button::RightTrigger2,
+    Button::Select,
+    Button::Start,
+    Button::Mode,
+    Button::LeftThumb,
+    Button::RightThumb,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+// Snapshot of the active gamepad, taken once per frame before update. Sticks are in [-1, 1]
+// with +Y up, triggers in [0, 1]. All zero without a connected gamepad.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GamepadState {
+    pub connected: bool,
+    pub left_stick: Vec2,
+    pub right_stick: Vec2,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+    pressed: Vec<Button>,
+    previous: Vec<Button>,
+}
+
+impl GamepadState {
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    // Pressed since the previous frame.
+    pub fn just_pressed(&self, button: Button) -> bool {
+        self.is_pressed(button) && !self.previous.contains(&button)
+    }
+
+    pub fn just_released(&self, button: Button) -> bool {
+        !self.is_pressed(button) && self.previous.contains(&button)
+    }
+}
+
+// Gamepads polled by the main loop (gamepad feature). The active gamepad is the last one used,
+// so that any connected gamepad can take over.
+pub struct GamepadInput {
+    gilrs: Option<Gilrs>,
+    active: Option<GamepadId>,
+    state: GamepadState,
+}
+
+impl GamepadInput {
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            // Unsupported platform: no gamepad will ever connect.
+            Err(gilrs::Error::NotImplemented(gilrs)) => Some(gilrs),
+            Err(error) => {
+                println!("Gamepad input unavailable: {}", error);
+                None
+            }
+        };
+        GamepadInput {
+            gilrs,
+            active: None,
+            state: GamepadState::default(),
+        }
+    }
+
+    pub fn state(&self) -> &GamepadState {
+        &self.state
+    }
+
+    // Name reported by the driver for the active gamepad.
+    pub fn name(&self) -> Option<&str> {
+        let gilrs = self.gilrs.as_ref()?;
+        self.active.map(|id| gilrs.gamepad(id).name())
+    }
+
+    // Drains the pending events and updates the state of the active gamepad.
+    pub fn poll(&mut self) {
+        let gilrs = match self.gilrs.as_mut() {
+            Some(gilrs) => gilrs,
+            None => return,
+        };
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::Disconnected if self.active == Some(event.id) => self.active = None,
+                EventType::ButtonPressed(..) | EventType::Connected => self.active = Some(event.id),
+                // Drifting sticks don't steal the active gamepad.
+                EventType::AxisChanged(_, value, _) if value.abs() > STICK_DEADZONE => {
+                    self.active = Some(event.id)
+                }
+                _ => {}
+            }
+        }
+        if self.active.is_none() {
+            self.active = gilrs.gamepads().next().map(|(id, _)| id);
+        }
+
+        let previous = std::mem::take(&mut self.state.pressed);
+        self.state = match self.active.map(|id| gilrs.gamepad(id)) {
+            Some(gamepad) if gamepad.is_connected() => {
+                let trigger = |button| gamepad.button_data(button).map_or(0.0, |data| data.value());
+                GamepadState {
+                    connected: true,
+                    left_stick: deadzone(vec2(
+                        gamepad.value(Axis::LeftStickX),
+                        gamepad.value(Axis::LeftStickY),
+                    )),
+                    right_stick: deadzone(vec2(
+                        gamepad.value(Axis::RightStickX),
+                        gamepad.value(Axis::RightStickY),
+                    )),
+                    left_trigger: trigger(Button::LeftTrigger2),
+                    right_trigger: trigger(Button::RightTrigger2),
+                    pressed: BUTTONS
+                        .iter()
+                        .copied()
+                        .filter(|button| gamepad.is_pressed(*button))
+                        .collect(),
+                    previous,
+                }
+            }
+            _ => GamepadState {
+                previous,
+                ..Default::default()
+            },
+        };
+    }
+}
+
+impl Default for GamepadInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Radial deadzone, rescaled so that the output still covers [0, 1] past the deadzone.
+fn deadzone(stick: Vec2) -> Vec2 {
+    let length = stick.length();
+    if length <= STICK_DEADZONE {
+        return Vec2::ZERO;
+    }
+    let scaled = ((length - STICK_DEADZONE) / (1.0 - STICK_DEADZONE)).min(1.0);
+    stick * (scaled / length)
+}
@@ -0,0 +1,201 @@
+use ash::{vk, Device};
+use std::fmt;
+
+// Renderer-level phases of a frame, timed automatically by the crate helpers recording them:
+// TLAS builds and updates of SceneDescription, the main render pass of AppRenderer and the
+// ShadowPass, the overlay pass, and blits to a swapchain image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GpuPhase {
+    AccelerationStructures,
+    Main,
+    Post,
+    PresentBlit,
+}
+
+impl GpuPhase {
+    pub const ALL: [GpuPhase; 4] = [
+        GpuPhase::AccelerationStructures,
+        GpuPhase::Main,
+        GpuPhase::Post,
+        GpuPhase::PresentBlit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GpuPhase::AccelerationStructures => "as",
+            GpuPhase::Main => "main",
+            GpuPhase::Post => "post",
+            GpuPhase::PresentBlit => "blit",
+        }
+    }
+}
+
+// Milliseconds per phase of the last frame, summed over the phase recordings.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GpuPhaseTimings {
+    pub acceleration_structures: f32,
+    pub main: f32,
+    pub post: f32,
+    pub present_blit: f32,
+}
+
+impl GpuPhaseTimings {
+    pub fn get(&self, phase: GpuPhase) -> f32 {
+        match phase {
+            GpuPhase::AccelerationStructures => self.acceleration_structures,
+            GpuPhase::Main => self.main,
+            GpuPhase::Post => self.post,
+            GpuPhase::PresentBlit => self.present_blit,
+        }
+    }
+
+    fn get_mut(&mut self, phase: GpuPhase) -> &mut f32 {
+        match phase {
+            GpuPhase::AccelerationStructures => &mut self.acceleration_structures,
+            GpuPhase::Main => &mut self.main,
+            GpuPhase::Post => &mut self.post,
+            GpuPhase::PresentBlit => &mut self.present_blit,
+        }
+    }
+}
+
+// Phases recorded in the last frame, e.g. "as:0.21 main:1.30 blit:0.05".
+impl fmt::Display for GpuPhaseTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        for phase in GpuPhase::ALL {
+            let milliseconds = self.get(phase);
+            if milliseconds > 0.0 {
+                write!(f, "{}{}:{:.2}", separator, phase.name(), milliseconds)?;
+                separator = " ";
+            }
+        }
+        Ok(())
+    }
+}
+
+// A timed phase recording: begin and end timestamps at `query` and `query + 1`.
+#[derive(Clone, Copy)]
+struct GpuPhaseSpan {
+    phase: GpuPhase,
+    query: u32,
+}
+
+// The phases recorded in a frame, to resolve once its queries are available.
+#[derive(Clone, Default)]
+pub(crate) struct GpuPhaseFrame {
+    spans: Vec<GpuPhaseSpan>,
+}
+
+impl GpuPhaseFrame {
+    // Queries written since GpuPhaseRecorder::begin_frame, from its `first_query`.
+    pub fn query_count(&self) -> u32 {
+        self.spans.len() as u32 * 2
+    }
+
+    // Sums the span durations, `timestamps` starting at the first query of the frame.
+    pub fn resolve(
+        &self,
+        timestamps: &[u64],
+        first_query: u32,
+        to_ms: impl Fn(u64, u64) -> f32,
+    ) -> GpuPhaseTimings {
+        let mut timings = GpuPhaseTimings::default();
+        for span in &self.spans {
+            let begin = (span.query - first_query) as usize;
+            *timings.get_mut(span.phase) += to_ms(timestamps[begin], timestamps[begin + 1]);
+        }
+        timings
+    }
+}
+
+// Writes the phase timestamps to a range of the AppRenderer query pool. Only the frame command
+// buffer is timed, from begin_command_buffer to end_command_buffer: phases recorded in other
+// command buffers (e.g. one time submits) are ignored. Phases don't nest, a phase recorded while
+// another one is open is counted in the open one.
+pub(crate) struct GpuPhaseRecorder {
+    query_pool: vk::QueryPool,
+    cmd: vk::CommandBuffer,
+    next_query: u32,
+    end_query: u32,
+    open: Option<GpuPhase>,
+    frame: GpuPhaseFrame,
+}
+
+impl GpuPhaseRecorder {
+    pub fn new() -> Self {
+        GpuPhaseRecorder {
+            query_pool: vk::QueryPool::null(),
+            cmd: vk::CommandBuffer::null(),
+            next_query: 0,
+            end_query: 0,
+            open: None,
+            frame: GpuPhaseFrame::default(),
+        }
+    }
+
+    // The queries [first_query, end_query) must have been reset in `cmd`.
+    pub fn begin_frame(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        query_pool: vk::QueryPool,
+        first_query: u32,
+        end_query: u32,
+    ) {
+        self.query_pool = query_pool;
+        self.cmd = cmd;
+        self.next_query = first_query;
+        self.end_query = end_query;
+        self.open = None;
+        self.frame.spans.clear();
+    }
+
+    // Closes a phase left open, e.g. by a render pass ended without AppRenderer::end_renderpass.
+    pub fn end_frame(&mut self, device: &Device, cmd: vk::CommandBuffer) {
+        if let Some(phase) = self.open {
+            self.end(device, cmd, phase);
+        }
+        self.cmd = vk::CommandBuffer::null();
+    }
+
+    pub fn begin(&mut self, device: &Device, cmd: vk::CommandBuffer, phase: GpuPhase) {
+        if cmd != self.cmd || self.open.is_some() || self.next_query + 2 > self.end_query {
+            return;
+        }
+        unsafe {
+            device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                self.next_query,
+            );
+        }
+        self.frame.spans.push(GpuPhaseSpan {
+            phase,
+            query: self.next_query,
+        });
+        self.next_query += 2;
+        self.open = Some(phase);
+    }
+
+    pub fn end(&mut self, device: &Device, cmd: vk::CommandBuffer, phase: GpuPhase) {
+        if cmd != self.cmd || self.open != Some(phase) {
+            return;
+        }
+        let query = self.frame.spans.last().unwrap().query + 1;
+        unsafe {
+            device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                query,
+            );
+        }
+        self.open = None;
+    }
+
+    // Phases of the current (or last ended) frame.
+    pub fn frame(&self) -> &GpuPhaseFrame {
+        &self.frame
+    }
+}
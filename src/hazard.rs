@@ -0,0 +1,281 @@
+use ash::vk;
+use ash::vk::Handle;
+use std::collections::{HashMap, HashSet};
+
+// Debug-only tracking of the accesses recorded through the crate wrappers (image transitions and
+// blits, depth resolve, indirect draws) plus the ones reported explicitly with read() and
+// write(). Reports likely hazards (missing barriers, wrong layouts) by resource name. Enabled
+// with ValidationSettings::hazard_tracking, state is reset at the start of every frame.
+//
+// Stage coverage is checked bit-wise (ALL_COMMANDS covers everything), so a barrier relying on
+// logically earlier or later stages may be reported: results are hints, not validation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HazardKind {
+    ReadAfterWrite,
+    WriteAfterWrite,
+    WriteAfterRead,
+    WrongLayout,
+}
+
+#[derive(Clone, Debug)]
+pub struct Hazard {
+    pub kind: HazardKind,
+    pub resource: String,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Access {
+    stage: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+}
+
+#[derive(Default)]
+struct ResourceState {
+    name: String,
+    layout: Option<vk::ImageLayout>,
+    // Last write and the accesses it was made visible to by barriers since.
+    last_write: Option<Access>,
+    visible_to: Option<Access>,
+    // Stages that read the resource since the last write, not yet ordered by a barrier.
+    read_stages: vk::PipelineStageFlags,
+}
+
+fn stage_covers(stages: vk::PipelineStageFlags, other: vk::PipelineStageFlags) -> bool {
+    stages.contains(vk::PipelineStageFlags::ALL_COMMANDS) || stages.contains(other)
+}
+
+fn access_covers(access: vk::AccessFlags, other: vk::AccessFlags, write: bool) -> bool {
+    let memory = if write {
+        vk::AccessFlags::MEMORY_WRITE
+    } else {
+        vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE
+    };
+    access.intersects(memory) || access.contains(other)
+}
+
+#[derive(Default)]
+pub struct HazardTracker {
+    resources: HashMap<u64, ResourceState>,
+    hazards: Vec<Hazard>,
+    reported: HashSet<(String, HazardKind)>,
+}
+
+impl HazardTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.resources.clear();
+        self.hazards.clear();
+    }
+
+    // Hazards found since the start of the frame, including already logged ones.
+    pub fn hazards(&self) -> &[Hazard] {
+        &self.hazards
+    }
+
+    fn state<H: Handle>(&mut self, handle: H, name: &str) -> &mut ResourceState {
+        let raw = handle.as_raw();
+        let state = self.resources.entry(raw).or_default();
+        if state.name.is_empty() {
+            state.name = if name.is_empty() {
+                format!("{:?} {:#x}", H::TYPE, raw)
+            } else {
+                name.to_string()
+            };
+        }
+        state
+    }
+
+    fn report(&mut self, kind: HazardKind, resource: String, message: String) {
+        // Logged once per resource and kind, they would otherwise repeat every frame.
+        if self.reported.insert((resource.clone(), kind)) {
+            println!("Hazard {:?} on {}: {}", kind, resource, message);
+        }
+        self.hazards.push(Hazard {
+            kind,
+            resource,
+            message,
+        });
+    }
+
+    pub fn read<H: Handle>(
+        &mut self,
+        handle: H,
+        name: &str,
+        stage: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    ) {
+        let state = self.state(handle, name);
+        let mut hazard = None;
+        if let Some(write) = state.last_write {
+            let visible = state.visible_to.is_some_and(|visible| {
+                stage_covers(visible.stage, stage) && access_covers(visible.access, access, false)
+            });
+            if !visible {
+                hazard = Some((
+                    state.name.clone(),
+                    format!(
+                        "{:?} read in {:?} without a barrier after the {:?} write in {:?}",
+                        access, stage, write.access, write.stage
+                    ),
+                ));
+            }
+        }
+        state.read_stages |= stage;
+        if let Some((resource, message)) = hazard {
+            self.report(HazardKind::ReadAfterWrite, resource, message);
+        }
+    }
+
+    pub fn write<H: Handle>(
+        &mut self,
+        handle: H,
+        name: &str,
+        stage: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    ) {
+        let state = self.state(handle, name);
+        let mut hazards = Vec::new();
+        if let Some(write) = state.last_write {
+            let ordered = state
+                .visible_to
+                .is_some_and(|visible| stage_covers(visible.stage, stage));
+            if !ordered {
+                hazards.push((
+                    HazardKind::WriteAfterWrite,
+                    format!(
+                        "{:?} write in {:?} without a barrier after the {:?} write in {:?}",
+                        access, stage, write.access, write.stage
+                    ),
+                ));
+            }
+        }
+        if !state.read_stages.is_empty() {
+            hazards.push((
+                HazardKind::WriteAfterRead,
+                format!(
+                    "{:?} write in {:?} without a barrier after reads in {:?}",
+                    access, stage, state.read_stages
+                ),
+            ));
+        }
+        state.last_write = Some(Access { stage, access });
+        state.visible_to = None;
+        state.read_stages = vk::PipelineStageFlags::empty();
+        let resource = state.name.clone();
+        for (kind, message) in hazards {
+            self.report(kind, resource.clone(), message);
+        }
+    }
+
+    // Use of an image that requires `layout`.
+    pub fn expect_layout<H: Handle>(&mut self, handle: H, name: &str, layout: vk::ImageLayout) {
+        let state = self.state(handle, name);
+        if let Some(current) = state.layout {
+            if current != layout {
+                let resource = state.name.clone();
+                self.report(
+                    HazardKind::WrongLayout,
+                    resource,
+                    format!("used as {:?} while in {:?}", layout, current),
+                );
+            }
+        }
+    }
+
+    // Layout change made outside of the tracked commands, e.g. by a render pass final layout.
+    pub fn set_layout<H: Handle>(&mut self, handle: H, name: &str, layout: vk::ImageLayout) {
+        self.state(handle, name).layout = Some(layout);
+    }
+
+    // Pipeline barrier on a resource. For images, `layouts` is the (old, new) layout pair; a
+    // transition counts as a write ordered between the source and destination scopes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn barrier<H: Handle>(
+        &mut self,
+        handle: H,
+        name: &str,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+        layouts: Option<(vk::ImageLayout, vk::ImageLayout)>,
+    ) {
+        let state = self.state(handle, name);
+        let mut hazards = Vec::new();
+        let write_available = match state.last_write {
+            Some(write) => {
+                stage_covers(src_stage, write.stage)
+                    && access_covers(src_access, write.access, true)
+            }
+            None => true,
+        };
+        let reads_ordered = stage_covers(src_stage, state.read_stages);
+        if let Some((old, new)) = layouts {
+            if let (Some(current), false) = (state.layout, old == vk::ImageLayout::UNDEFINED) {
+                if current != old {
+                    hazards.push((
+                        HazardKind::WrongLayout,
+                        format!("transition from {:?} while in {:?}", old, current),
+                    ));
+                }
+            }
+            if old != new {
+                // Discarding contents (UNDEFINED) still needs the previous accesses to be done.
+                if !write_available {
+                    hazards.push((
+                        HazardKind::WriteAfterWrite,
+                        format!(
+                            "layout transition doesn't wait for the {:?} write in {:?}",
+                            state.last_write.unwrap().access,
+                            state.last_write.unwrap().stage
+                        ),
+                    ));
+                }
+                if !reads_ordered {
+                    hazards.push((
+                        HazardKind::WriteAfterRead,
+                        format!(
+                            "layout transition doesn't wait for reads in {:?}",
+                            state.read_stages
+                        ),
+                    ));
+                }
+                state.last_write = Some(Access {
+                    stage: src_stage,
+                    access: vk::AccessFlags::empty(),
+                });
+                state.visible_to = Some(Access {
+                    stage: dst_stage,
+                    access: dst_access,
+                });
+                state.read_stages = vk::PipelineStageFlags::empty();
+            }
+            state.layout = Some(new);
+        }
+        let same_layout = match layouts {
+            Some((old, new)) => old == new,
+            None => true,
+        };
+        if same_layout {
+            if write_available && state.last_write.is_some() {
+                let visible = state.visible_to.get_or_insert(Access {
+                    stage: vk::PipelineStageFlags::empty(),
+                    access: vk::AccessFlags::empty(),
+                });
+                visible.stage |= dst_stage;
+                visible.access |= dst_access;
+            }
+            if reads_ordered {
+                state.read_stages = vk::PipelineStageFlags::empty();
+            }
+        }
+        let resource = state.name.clone();
+        for (kind, message) in hazards {
+            self.report(kind, resource.clone(), message);
+        }
+    }
+}
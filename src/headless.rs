@@ -0,0 +1,255 @@
+use crate::{
+    AppSettings, Context, GpuPhaseFrame, GpuPhaseTimings, PrepareFn, SharedContext,
+    TransientBufferAllocator,
+};
+use ash::vk;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Command buffers in flight, the CPU records frame N + 2 while the GPU runs frame N + 1.
+const HEADLESS_FRAMES_IN_FLIGHT: usize = 2;
+// Begin and end of the frame, then the GpuPhase timings.
+const QUERIES_PER_FRAME: u32 = 34;
+const QUERY_BEGIN_FRAME: u32 = 0;
+const QUERY_END_FRAME: u32 = 1;
+const QUERY_FIRST_PHASE: u32 = 2;
+
+pub type HeadlessSetupFn<T> = fn(&mut HeadlessApp) -> T;
+// Records the frame work in the command buffer, submitted after the call.
+pub type HeadlessUpdateFn<T> = fn(&mut HeadlessApp, &mut T, vk::CommandBuffer);
+
+// An app without window, surface nor swapchain, for compute and ray tracing workloads writing
+// images or buffers (e.g. batch processing tools). Frames are paced like AppRenderer frames: a
+// fence per frame in flight, with the transient allocator and GPU timings of the frame.
+pub struct HeadlessApp {
+    pub settings: AppSettings,
+    pub context: Arc<Context>,
+    pub active_frame_index: usize,
+    pub transient: TransientBufferAllocator,
+    pub elapsed_time: Duration,
+    pub elapsed_ticks: u64,
+    // Timings of the last completed frame.
+    pub gpu_frame_time: f32,
+    pub gpu_phase_timings: GpuPhaseTimings,
+    fences: Vec<vk::Fence>,
+    // Phases of the frame submitted with each fence, until its timings are read.
+    submitted_phases: Vec<Option<GpuPhaseFrame>>,
+    query_pool: vk::QueryPool,
+    exit_requested: bool,
+}
+
+impl HeadlessApp {
+    pub fn new(settings: AppSettings) -> Self {
+        let shared_context = Arc::new(SharedContext::new_headless(&settings.render));
+        let context = Arc::new(Context::new(shared_context, HEADLESS_FRAMES_IN_FLIGHT));
+        let device = context.device();
+        let fence_create_info =
+            vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+        let fences = (0..HEADLESS_FRAMES_IN_FLIGHT)
+            .map(|_| unsafe { device.create_fence(&fence_create_info, None) })
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Create fence failed.");
+        let query_create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(QUERIES_PER_FRAME * HEADLESS_FRAMES_IN_FLIGHT as u32);
+        let query_pool = unsafe { device.create_query_pool(&query_create_info, None) }
+            .expect("Failed to create query pool.");
+        let transient = TransientBufferAllocator::new(
+            context.clone(),
+            settings.render.transient_buffer_size,
+            HEADLESS_FRAMES_IN_FLIGHT,
+        );
+        HeadlessApp {
+            settings,
+            context,
+            active_frame_index: HEADLESS_FRAMES_IN_FLIGHT - 1,
+            transient,
+            elapsed_time: Duration::default(),
+            elapsed_ticks: 0,
+            gpu_frame_time: 0.0,
+            gpu_phase_timings: GpuPhaseTimings::default(),
+            fences,
+            submitted_phases: vec![None; HEADLESS_FRAMES_IN_FLIGHT],
+            query_pool,
+            exit_requested: false,
+        }
+    }
+
+    pub fn get_frames_count(&self) -> usize {
+        HEADLESS_FRAMES_IN_FLIGHT
+    }
+
+    // Stops the main loop after the current frame.
+    pub fn exit(&mut self) {
+        self.exit_requested = true;
+    }
+
+    // Waits for the frame slot to be free, reads its timings and begins its command buffer.
+    pub fn begin_frame(&mut self) -> vk::CommandBuffer {
+        self.active_frame_index = (self.active_frame_index + 1) % HEADLESS_FRAMES_IN_FLIGHT;
+        let frame_index = self.active_frame_index;
+        let first_query = frame_index as u32 * QUERIES_PER_FRAME;
+        let context = self.context.clone();
+        let device = context.device();
+        unsafe {
+            let fences = [self.fences[frame_index]];
+            device
+                .wait_for_fences(&fences, true, u64::MAX)
+                .expect("Wait for fence failed.");
+            device.reset_fences(&fences).unwrap();
+        }
+        if let Some(phases) = self.submitted_phases[frame_index].take() {
+            self.resolve_timestamps(first_query, &phases);
+        }
+        self.transient.begin_frame(frame_index);
+
+        let cmd = context.request_command_buffer(frame_index);
+        unsafe {
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device
+                .begin_command_buffer(cmd, &begin_info)
+                .expect("Begin frame commands.");
+            device.cmd_reset_query_pool(cmd, self.query_pool, first_query, QUERIES_PER_FRAME);
+            device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                first_query + QUERY_BEGIN_FRAME,
+            );
+        }
+        context.shared().gpu_phases().begin_frame(
+            cmd,
+            self.query_pool,
+            first_query + QUERY_FIRST_PHASE,
+            first_query + QUERIES_PER_FRAME,
+        );
+        cmd
+    }
+
+    pub fn end_frame(&mut self, cmd: vk::CommandBuffer) {
+        let frame_index = self.active_frame_index;
+        let first_query = frame_index as u32 * QUERIES_PER_FRAME;
+        let device = self.context.device();
+        let mut phases = self.context.shared().gpu_phases();
+        phases.end_frame(device, cmd);
+        self.submitted_phases[frame_index] = Some(phases.frame().clone());
+        drop(phases);
+        unsafe {
+            device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                first_query + QUERY_END_FRAME,
+            );
+            device.end_command_buffer(cmd).expect("End frame commands.");
+            let submit_info = vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&cmd));
+            self.context
+                .queue_submit(
+                    self.context.graphics_queue(),
+                    std::slice::from_ref(&submit_info),
+                    self.fences[frame_index],
+                )
+                .expect("Queue submit failed.");
+        }
+    }
+
+    // Waits for every submitted frame, e.g. before reading back results.
+    pub fn wait_idle(&self) {
+        unsafe {
+            self.context
+                .device()
+                .wait_for_fences(&self.fences, true, u64::MAX)
+                .expect("Wait for fence failed.");
+        }
+    }
+
+    fn resolve_timestamps(&mut self, first_query: u32, phases: &GpuPhaseFrame) {
+        let query_count = QUERY_FIRST_PHASE + phases.query_count();
+        let mut query_data = vec![0u64; query_count as usize];
+        unsafe {
+            self.context
+                .device()
+                .get_query_pool_results(
+                    self.query_pool,
+                    first_query,
+                    query_count,
+                    &mut query_data,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )
+                .expect("Failed to read query results");
+        }
+        let limits = *self.context.limits();
+        let to_ms = |begin: u64, end: u64| limits.timestamps_to_ms(begin, end);
+        self.gpu_phase_timings = phases.resolve(
+            &query_data[QUERY_FIRST_PHASE as usize..],
+            first_query + QUERY_FIRST_PHASE,
+            to_ms,
+        );
+        self.gpu_frame_time = to_ms(
+            query_data[QUERY_BEGIN_FRAME as usize],
+            query_data[QUERY_END_FRAME as usize],
+        );
+    }
+}
+
+impl Drop for HeadlessApp {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            self.context.device_wait_idle().unwrap();
+            self.context.asset_cache().clear();
+            device.destroy_query_pool(self.query_pool, None);
+            for fence in &self.fences {
+                device.destroy_fence(*fence, None);
+            }
+        }
+    }
+}
+
+pub struct HeadlessAppBuilder<T: 'static> {
+    pub prepare: Option<PrepareFn>,
+    pub setup: HeadlessSetupFn<T>,
+    pub update: Option<HeadlessUpdateFn<T>>,
+    pub frame_count: Option<u64>,
+}
+
+impl<T> HeadlessAppBuilder<T> {
+    pub fn prepare(mut self, prepare: PrepareFn) -> Self {
+        self.prepare = Some(prepare);
+        self
+    }
+
+    pub fn update(mut self, update: HeadlessUpdateFn<T>) -> Self {
+        self.update = Some(update);
+        self
+    }
+
+    // Runs at most `frame_count` frames, otherwise until HeadlessApp::exit.
+    pub fn frames(mut self, frame_count: u64) -> Self {
+        self.frame_count = Some(frame_count);
+        self
+    }
+
+    // Returns the app data once every frame completed, e.g. to read back results. Without
+    // update function, only the setup runs.
+    pub fn run(self) -> T {
+        let settings = self
+            .prepare
+            .map_or_else(AppSettings::default, |prepare| prepare());
+        let mut app = HeadlessApp::new(settings);
+        let mut app_data = (self.setup)(&mut app);
+        let start = Instant::now();
+        if let Some(update) = self.update {
+            while !app.exit_requested && self.frame_count != Some(app.elapsed_ticks) {
+                app.elapsed_time = start.elapsed();
+                let cmd = app.begin_frame();
+                update(&mut app, &mut app_data, cmd);
+                app.end_frame(cmd);
+                app.elapsed_ticks += 1;
+            }
+        }
+        app.wait_idle();
+        app_data
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{validation_error_count, Context, Image2d, PendingReadback};
+use ash::vk;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Dumps intermediate images (accumulation targets, AOVs, shadow maps) to disk, e.g. to find the
+// pass of a multi-pass setup producing a black frame. Passes call cmd_dump with their outputs
+// every frame, which only records copies in frames following `request` (F11 in App) or a
+// validation error. Files are written by end_frame once the frame completed, named
+// `<frame>_<pass>_<image>`: EXR for float formats, PNG otherwise.
+pub struct ImageDumper {
+    directory: PathBuf,
+    requested: bool,
+    dump_on_validation_error: bool,
+    seen_validation_errors: u32,
+    pending: Vec<(String, PendingReadback)>,
+}
+
+impl ImageDumper {
+    pub fn new(directory: impl Into<PathBuf>, dump_on_validation_error: bool) -> Self {
+        ImageDumper {
+            directory: directory.into(),
+            requested: false,
+            dump_on_validation_error,
+            seen_validation_errors: validation_error_count(),
+            pending: Vec::new(),
+        }
+    }
+
+    // Dumps the images of the next frame.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested
+    }
+
+    // Records the copy of `image` as it is at this point of `cmd`, restoring its layout. The
+    // image needs TRANSFER_SRC usage. No-op unless a dump was requested.
+    pub fn cmd_dump(
+        &mut self,
+        context: &Arc<Context>,
+        cmd: vk::CommandBuffer,
+        pass: &str,
+        name: &str,
+        image: &mut Image2d,
+    ) {
+        if !self.requested {
+            return;
+        }
+        let name = format!("{}_{}", pass, name).replace(|c: char| !c.is_alphanumeric(), "_");
+        self.pending.push((name, image.cmd_readback(context, cmd)));
+    }
+
+    // Called once per frame after submission, by the main loop for App::image_dump. Waits for
+    // the device when some images were dumped.
+    pub fn end_frame(&mut self, context: &Context, frame: u64) {
+        if !self.pending.is_empty() {
+            context.device_wait_idle().unwrap();
+            if let Err(error) = std::fs::create_dir_all(&self.directory) {
+                println!("Failed to create {}: {}", self.directory.display(), error);
+            }
+            for (name, readback) in self.pending.drain(..) {
+                let readback = readback.finish();
+                let extension = if readback.is_float() { "exr" } else { "png" };
+                let path = self
+                    .directory
+                    .join(format!("{:05}_{}.{}", frame, name, extension));
+                match readback.save(&path) {
+                    Ok(_) => println!("Dumped {}.", path.display()),
+                    Err(error) => println!("Failed to dump {}: {}", path.display(), error),
+                }
+            }
+        }
+        self.requested = false;
+
+        let errors = validation_error_count();
+        if errors > self.seen_validation_errors && self.dump_on_validation_error {
+            println!("Validation error detected, dumping the images of the next frame.");
+            self.requested = true;
+            self.dump_on_validation_error = false;
+        }
+        self.seen_validation_errors = errors;
+    }
+}
@@ -0,0 +1,143 @@
+use glam::{vec2, Vec2};
+use std::collections::HashSet;
+use winit::event::{
+    ElementState, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+// Approximate pixels per scroll line, to report pixel scroll deltas (touchpads) in lines.
+const PIXELS_PER_SCROLL_LINE: f32 = 20.0;
+
+// Keyboard and mouse state of the main window, maintained by the main loop from its window
+// events (see App::input). The per-frame queries (just pressed/released, mouse delta and
+// scroll) cover the events received since the previous update.
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    keys_pressed: HashSet<VirtualKeyCode>,
+    keys_released: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    mouse_pos: Option<Vec2>,
+    mouse_delta: Vec2,
+    scroll: Vec2,
+    modifiers: ModifiersState,
+}
+
+impl InputState {
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(key) = input.virtual_keycode {
+                    match input.state {
+                        ElementState::Pressed => {
+                            // Key repeats aren't new presses.
+                            if self.keys_down.insert(key) {
+                                self.keys_pressed.insert(key);
+                            }
+                        }
+                        ElementState::Released => {
+                            self.keys_down.remove(&key);
+                            self.keys_released.insert(key);
+                        }
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons_down.insert(*button);
+                    self.buttons_pressed.insert(*button);
+                }
+                ElementState::Released => {
+                    self.buttons_down.remove(button);
+                    self.buttons_released.insert(*button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                let pos = vec2(position.x as f32, position.y as f32);
+                if let Some(previous) = self.mouse_pos {
+                    self.mouse_delta += pos - previous;
+                }
+                self.mouse_pos = Some(pos);
+            }
+            WindowEvent::CursorLeft { .. } => self.mouse_pos = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(x, y) => vec2(*x, *y),
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        vec2(delta.x as f32, delta.y as f32) / PIXELS_PER_SCROLL_LINE
+                    }
+                };
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
+            WindowEvent::Focused(false) => {
+                // Releases are missed while the window is unfocused.
+                self.keys_released.extend(self.keys_down.drain());
+                self.buttons_released.extend(self.buttons_down.drain());
+                self.modifiers = ModifiersState::default();
+            }
+            _ => {}
+        }
+    }
+
+    // Clears the per-frame state, called by the main loop after render.
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.mouse_delta = Vec2::ZERO;
+        self.scroll = Vec2::ZERO;
+    }
+
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn is_key_released(&self, key: VirtualKeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn keys_down(&self) -> impl Iterator<Item = &VirtualKeyCode> {
+        self.keys_down.iter()
+    }
+
+    pub fn is_mouse_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn is_mouse_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn is_mouse_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    // In physical pixels from the top left corner, None while the cursor is outside the window.
+    pub fn mouse_pos(&self) -> Option<Vec2> {
+        self.mouse_pos
+    }
+
+    pub fn mouse_delta(&self) -> Vec2 {
+        self.mouse_delta
+    }
+
+    // In lines, positive y scrolls up.
+    pub fn scroll(&self) -> Vec2 {
+        self.scroll
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    // Ctrl, or Cmd on macOS.
+    pub fn command(&self) -> bool {
+        self.modifiers.ctrl() || self.modifiers.logo()
+    }
+}
@@ -1,41 +1,122 @@
 #![allow(dead_code)]
 
 use winit::{
-    event::{ElementState, Event, ModifiersState, VirtualKeyCode, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
+    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
 };
 
 use std::ops::Drop;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 
+mod app_window;
+mod asset_cache;
+mod async_compute;
+mod background;
+mod barrier;
+mod bindless;
 mod buffer;
+mod capabilities;
+mod capture;
+mod command_batch;
+mod compressed_texture;
 mod context;
+mod debug_draw;
+mod debug_marker;
 mod descriptor;
+mod device_features;
+mod error;
+mod frame_timing;
+mod fullscreen;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod gpu_phase;
+mod hazard;
+mod headless;
+mod image_dump;
+mod input;
+mod limits;
+pub mod math;
+mod memory_budget;
+mod mesh_shader;
+mod mipmap;
 mod pipeline;
+mod placeholder;
 mod pools;
+mod postprocess;
+mod progress;
 pub mod prelude;
+#[cfg(feature = "registry")]
+pub mod registry;
+mod recorder;
+mod reflect;
+mod render_target;
 mod renderer;
 mod renderpass;
+mod staging;
 pub mod scene;
+#[cfg(feature = "harness")]
+pub mod test_harness;
 mod swapchain;
 mod texture;
+mod transient;
+mod transient_image;
+#[cfg(feature = "ui")]
+pub mod ui;
 pub mod util;
 mod window;
 pub mod ray;
 
+pub use crate::app_window::*;
+pub use crate::asset_cache::*;
+pub use crate::async_compute::*;
+pub use crate::background::*;
+pub use crate::barrier::*;
+pub use crate::bindless::*;
 pub use crate::buffer::*;
+pub use crate::capabilities::*;
+pub use crate::capture::*;
+pub use crate::command_batch::*;
+pub use crate::compressed_texture::*;
 pub use crate::context::*;
+pub use crate::debug_draw::*;
+pub use crate::debug_marker::*;
 pub use crate::descriptor::*;
+pub use crate::device_features::*;
+pub use crate::error::*;
+pub use crate::frame_timing::*;
+pub use crate::fullscreen::*;
+#[cfg(feature = "gamepad")]
+pub use crate::gamepad::*;
+pub use crate::gpu_phase::*;
+pub use crate::hazard::*;
+pub use crate::headless::*;
+pub use crate::image_dump::*;
+pub use crate::input::*;
+pub use crate::limits::*;
+pub use crate::memory_budget::*;
+pub use crate::mesh_shader::*;
+pub use crate::mipmap::*;
 pub use crate::pipeline::*;
+pub use crate::placeholder::*;
 pub use crate::pools::*;
+pub use crate::postprocess::*;
+pub use crate::progress::*;
+pub use crate::recorder::*;
+pub use crate::reflect::*;
+pub use crate::render_target::*;
 pub use crate::renderer::*;
 pub use crate::renderpass::*;
+pub use crate::staging::*;
 pub use crate::swapchain::*;
 pub use crate::texture::*;
+pub use crate::transient::*;
+pub use crate::transient_image::*;
 pub use crate::window::*;
 pub use ash;
 pub use glam;
 pub use winit;
+// Stable path for the Vulkan bindings, independent of how sol depends on ash.
+pub use ash::vk;
 
 // Simple offset_of macro akin to C++ offsetof
 #[macro_export]
@@ -60,10 +141,26 @@ pub trait Vertex {
 
 pub struct App {
     pub settings: AppSettings,
+    // Additional windows, declared with AppBuilder::window. Dropped before the main renderer.
+    pub windows: Vec<AppWindow>,
     pub renderer: AppRenderer,
     pub window: Window,
     pub elapsed_time: Duration,
     pub elapsed_ticks: u64,
+    // Time since the previous frame, including the wait for AppSettings::target_fps.
+    pub delta_time: Duration,
+    // Fraction of AppSettings::fixed_timestep not yet simulated, for render to interpolate
+    // between the last two fixed updates. 0 without fixed timestep.
+    pub fixed_alpha: f32,
+    // Keyboard and mouse of the main window.
+    pub input: InputState,
+    #[cfg(feature = "gamepad")]
+    pub gamepad: GamepadInput,
+    pub capture: FrameCapture,
+    pub image_dump: ImageDumper,
+    // Created by the app when it needs App::pick, and drawn in its render function. Resized along
+    // with the swapchain.
+    pub picking: Option<scene::PickingPass>,
 }
 
 impl App {
@@ -74,10 +171,24 @@ impl App {
             update: None,
             window_event: None,
             render: None,
+            windows: Vec::new(),
+            secondary_window_event: None,
+        }
+    }
+
+    // Surface-less variant running update functions only, see HeadlessApp.
+    pub fn build_headless<T>(setup: HeadlessSetupFn<T>) -> HeadlessAppBuilder<T> {
+        HeadlessAppBuilder {
+            prepare: None,
+            setup,
+            update: None,
+            frame_count: None,
         }
     }
 
     pub fn new(settings: AppSettings, event_loop: &EventLoop<()>) -> Self {
+        // RenderDoc must be queried before the Vulkan instance is created.
+        let capture = FrameCapture::new(&settings.name, settings.capture_on_validation_error);
         let mut window = Window::new(
             settings.resolution[0],
             settings.resolution[1],
@@ -85,31 +196,99 @@ impl App {
             &event_loop,
         );
         let renderer = AppRenderer::new(&mut window, settings.clone().render);
+        let image_dump = ImageDumper::new("dumps", settings.dump_images_on_validation_error);
         App {
             settings,
+            windows: Vec::new(),
             renderer,
             window,
             elapsed_time: Duration::default(),
             elapsed_ticks: 0,
+            delta_time: Duration::default(),
+            fixed_alpha: 0.0,
+            input: InputState::default(),
+            #[cfg(feature = "gamepad")]
+            gamepad: GamepadInput::new(),
+            capture,
+            image_dump,
+            picking: None,
         }
     }
 
     pub fn recreate_swapchain(&mut self) {
         self.renderer.recreate_swapchain(&self.window);
+        if let Some(picking) = &mut self.picking {
+            picking
+                .resize(self.renderer.swapchain.get_extent())
+                .expect("Unable to resize picking target");
+        }
+    }
+
+    // Opens a window rendering on the device of the main window and returns its index in
+    // `windows`.
+    pub fn open_window(
+        &mut self,
+        settings: AppWindowSettings,
+        event_loop: &EventLoopWindowTarget<()>,
+    ) -> Result<usize> {
+        let window = AppWindow::new(self.renderer.context.shared().clone(), settings, event_loop)?;
+        self.windows.push(window);
+        Ok(self.windows.len() - 1)
+    }
+
+    pub fn window_index(&self, id: winit::window::WindowId) -> Option<usize> {
+        self.windows.iter().position(|window| window.id() == id)
+    }
+
+    // Instance under the pixel (`x`, `y`) of the main window, e.g. InputState::mouse_pos, as last
+    // drawn by `picking`. None without picking pass.
+    pub fn pick(&mut self, x: f32, y: f32) -> Option<scene::InstanceId> {
+        self.picking.as_mut()?.pick(x, y)
+    }
+
+    // Captures the next frame with RenderDoc (requires the `renderdoc` feature).
+    pub fn trigger_capture(&mut self) {
+        self.capture.trigger_capture();
+    }
+
+    // Saves a frame captured with AppRenderer::capture_next_frame (F12) to screenshots/.
+    pub fn save_screenshot(&self, frame: &ImageReadback) {
+        let path = std::path::PathBuf::from(format!(
+            "screenshots/{}_{}.png",
+            self.settings.name.replace(' ', "_"),
+            self.elapsed_ticks
+        ));
+        let result = std::fs::create_dir_all("screenshots").map_err(image::ImageError::IoError);
+        match result.and_then(|_| frame.save(&path)) {
+            Ok(_) => println!("Saved screenshot {}.", path.display()),
+            Err(error) => println!("Failed to save screenshot {}: {}", path.display(), error),
+        }
     }
 }
 
 pub type PrepareFn = fn() -> AppSettings;
 pub type SetupFn<T> = fn(&mut App) -> T; // TODO: how do we specify FnOnce here?
-pub type UpdateFn<T> = fn(&mut App, &mut T);
+pub type UpdateFn<T> = fn(&mut App, &mut T, time: FrameTime);
 pub type RenderFn<T> = fn(&mut App, &mut T) -> Result<(), AppRenderError>;
 pub type WindowEventFn<T> = fn(&mut App, &mut T, event: &WindowEvent);
+// Called with the index of the window in App::windows.
+pub type WindowRenderFn<T> = fn(&mut App, &mut T, window: usize) -> Result<(), AppRenderError>;
+pub type SecondaryWindowEventFn<T> = fn(&mut App, &mut T, window: usize, event: &WindowEvent);
 
 #[derive(Clone, Debug)]
 pub struct AppSettings {
     pub name: String,
     pub resolution: [u32; 2],
     pub render: RendererSettings,
+    pub capture_on_validation_error: bool,
+    // Dumps the images passed to App::image_dump in the frame following a validation error.
+    pub dump_images_on_validation_error: bool,
+    // Calls update at this fixed rate, zero or more times per frame, for deterministic
+    // simulations. None calls update once per frame with the frame delta.
+    pub fixed_timestep: Option<Duration>,
+    // Frame rate cap, e.g. to not spin at 100% with an immediate or mailbox present mode.
+    pub target_fps: Option<f32>,
+    pub sleep_strategy: SleepStrategy,
 }
 
 impl Default for AppSettings {
@@ -118,15 +297,29 @@ impl Default for AppSettings {
             name: "App".to_string(),
             resolution: [1280, 720],
             render: RendererSettings::default(),
+            capture_on_validation_error: true,
+            dump_images_on_validation_error: false,
+            fixed_timestep: None,
+            target_fps: None,
+            sleep_strategy: SleepStrategy::default(),
         }
     }
 }
+// Result of AppBuilder::run_frame.
+pub(crate) struct FrameOutcome {
+    pub dirty_swapchain: bool,
+    // Frame requested with AppRenderer::capture_next_frame.
+    pub captured_frame: Option<ImageReadback>,
+}
+
 pub struct AppBuilder<T: 'static> {
     pub prepare: Option<PrepareFn>,
     pub setup: SetupFn<T>,
     pub update: Option<UpdateFn<T>>,
     pub window_event: Option<WindowEventFn<T>>,
     pub render: Option<RenderFn<T>>,
+    pub windows: Vec<(AppWindowSettings, WindowRenderFn<T>)>,
+    pub secondary_window_event: Option<SecondaryWindowEventFn<T>>,
 }
 
 impl<T> AppBuilder<T> {
@@ -150,9 +343,85 @@ impl<T> AppBuilder<T> {
         self
     }
 
+    // Adds a window opened before setup, e.g. a tool palette or another view, rendered with
+    // `render` after the main window. Closing it hides it until AppWindow::set_visible.
+    pub fn window(mut self, settings: AppWindowSettings, render: WindowRenderFn<T>) -> Self {
+        self.windows.push((settings, render));
+        self
+    }
+
+    // Receives the events of the windows added with `window`, window_event only receives those
+    // of the main window.
+    pub fn secondary_window_event(mut self, window_event: SecondaryWindowEventFn<T>) -> Self {
+        self.secondary_window_event = Some(window_event);
+        self
+    }
+
     pub fn run(self) {
         main_loop(self);
     }
+
+    pub(crate) fn open_windows(&self, app: &mut App, event_loop: &EventLoopWindowTarget<()>) {
+        for (settings, _) in &self.windows {
+            // Render callbacks are matched to windows by index.
+            app.open_window(settings.clone(), event_loop)
+                .unwrap_or_else(|error| {
+                    panic!("Failed to open window {}: {}", settings.name, error)
+                });
+        }
+    }
+
+    pub(crate) fn render_windows(&self, app: &mut App, app_data: &mut T) {
+        for (index, (_, render_fn)) in self.windows.iter().enumerate() {
+            match app.windows.get_mut(index) {
+                Some(window) if window.should_render() => {
+                    if window.dirty_swapchain {
+                        window.recreate_swapchain();
+                        window.dirty_swapchain = false;
+                    }
+                }
+                _ => continue,
+            }
+            let dirty_swapchain = matches!(
+                render_fn(app, app_data, index),
+                Err(AppRenderError::DirtySwapchain)
+            );
+            app.windows[index].dirty_swapchain = dirty_swapchain;
+        }
+    }
+
+    // Calls update once per time of `updates`, renders the windows and ends the frame. Shared by
+    // main_loop and the test harness.
+    pub(crate) fn run_frame(
+        &self,
+        app: &mut App,
+        app_data: &mut T,
+        updates: Vec<FrameTime>,
+    ) -> FrameOutcome {
+        if let Some(update_fn) = self.update {
+            for time in updates {
+                update_fn(app, app_data, time);
+            }
+        }
+        let dirty_swapchain = match self.render {
+            Some(render_fn) => matches!(
+                render_fn(app, app_data),
+                Err(AppRenderError::DirtySwapchain)
+            ),
+            None => false,
+        };
+        self.render_windows(app, app_data);
+
+        let captured_frame = app.renderer.take_captured_frame();
+        app.input.end_frame();
+        app.capture.end_frame();
+        app.image_dump.end_frame(&app.renderer.context, app.elapsed_ticks);
+        app.elapsed_ticks += 1;
+        FrameOutcome {
+            dirty_swapchain,
+            captured_frame,
+        }
+    }
 }
 
 fn main_loop<T: 'static>(builder: AppBuilder<T>) {
@@ -165,11 +434,11 @@ fn main_loop<T: 'static>(builder: AppBuilder<T>) {
         None => {}
     }
     let mut app = App::new(settings, &event_loop);
+    builder.open_windows(&mut app, &event_loop);
     let mut app_data = (builder.setup)(&mut app);
     let mut dirty_swapchain = false;
 
-    let now = SystemTime::now();
-    let mut modifiers = ModifiersState::default();
+    let mut timer = FrameTimer::new();
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -182,20 +451,47 @@ fn main_loop<T: 'static>(builder: AppBuilder<T>) {
             }
 
             match event {
+                Event::WindowEvent { window_id, event }
+                    if app.window_index(window_id).is_some() =>
+                {
+                    let index = app.window_index(window_id).unwrap();
+                    match event {
+                        WindowEvent::CloseRequested => app.windows[index].set_visible(false),
+                        WindowEvent::KeyboardInput { input, .. }
+                            if input.state == ElementState::Pressed
+                                && input.virtual_keycode == Some(VirtualKeyCode::Q)
+                                && app.input.command() =>
+                        {
+                            *control_flow = ControlFlow::Exit;
+                        }
+                        // Shared with the main window, to quit from any window.
+                        WindowEvent::ModifiersChanged(_) => app.input.handle_event(&event),
+                        _ => (),
+                    }
+                    if let Some(event_fn) = builder.secondary_window_event {
+                        event_fn(&mut app, &mut app_data, index, &event);
+                    }
+                }
                 Event::WindowEvent { event, .. } => {
+                    app.input.handle_event(&event);
                     match event {
                         WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                         WindowEvent::KeyboardInput { input, .. } => {
                             if input.state == ElementState::Pressed {
                                 if input.virtual_keycode == Some(VirtualKeyCode::Q)
-                                    && (modifiers.ctrl() || modifiers.logo())
+                                    && app.input.command()
                                 {
                                     *control_flow = ControlFlow::Exit;
                                 }
+                                if input.virtual_keycode == Some(VirtualKeyCode::F12) {
+                                    app.renderer.capture_next_frame();
+                                }
+                                if input.virtual_keycode == Some(VirtualKeyCode::F11) {
+                                    app.image_dump.request();
+                                }
                             }
                         }
                         WindowEvent::MouseInput { .. } => {}
-                        WindowEvent::ModifiersChanged(m) => modifiers = m,
                         _ => (),
                     }
                     match builder.window_event {
@@ -206,38 +502,49 @@ fn main_loop<T: 'static>(builder: AppBuilder<T>) {
                     }
                 }
                 Event::MainEventsCleared => {
-                    let now = now.elapsed().unwrap();
+                    let time =
+                        timer.begin_frame(app.settings.target_fps, app.settings.sleep_strategy);
+                    app.elapsed_time = time.elapsed;
+                    app.delta_time = time.delta;
                     if app.elapsed_ticks % 10 == 0 {
-                        let cpu_time = now.as_millis() as f32 - app.elapsed_time.as_millis() as f32;
-                        let title = format!("{} | cpu:{:.1} ms, gpu:{:.1} ms", app.settings.name, cpu_time, app.renderer.gpu_frame_time);
+                        let cpu_time = time.delta.as_secs_f32() * 1000.0;
+                        let mut title = format!("{} | cpu:{:.1} ms, gpu:{:.1} ms", app.settings.name, cpu_time, app.renderer.gpu_frame_time);
+                        let phases = app.renderer.gpu_phase_timings.to_string();
+                        if !phases.is_empty() {
+                            title += &format!(" ({})", phases);
+                        }
                         app.window.set_title(&title);
                     }
-                    app.elapsed_time = now;
+                    #[cfg(feature = "gamepad")]
+                    app.gamepad.poll();
 
-                    match builder.update {
-                        Some(update_fn) => {
-                            update_fn(&mut app, &mut app_data);
+                    let updates = match (builder.update, app.settings.fixed_timestep) {
+                        (Some(_), Some(step)) => {
+                            let updates = timer.fixed_updates(step);
+                            app.fixed_alpha = timer.fixed_alpha(step);
+                            updates
                         }
-                        None => {}
-                    }
-
-                    dirty_swapchain = match builder.render {
-                        Some(render_fn) => {
-                            matches!(
-                                render_fn(&mut app, &mut app_data),
-                                Err(AppRenderError::DirtySwapchain)
-                            )
+                        (Some(_), None) => {
+                            timer.reset_accumulator();
+                            vec![time]
+                        }
+                        (None, _) => {
+                            timer.reset_accumulator();
+                            Vec::new()
                         }
-                        None => false,
                     };
 
-                    app.elapsed_ticks += 1;
+                    let outcome = builder.run_frame(&mut app, &mut app_data, updates);
+                    dirty_swapchain = outcome.dirty_swapchain;
+                    if let Some(frame) = outcome.captured_frame {
+                        app.save_screenshot(&frame);
+                    }
                 }
                 Event::Suspended => println!("Suspended."),
                 Event::Resumed => println!("Resumed."),
-                Event::LoopDestroyed => unsafe {
-                    app.renderer.context.device().device_wait_idle().unwrap();
-                },
+                Event::LoopDestroyed => {
+                    app.renderer.context.device_wait_idle().unwrap();
+                }
                 _ => {}
             }
         }
@@ -0,0 +1,80 @@
+use ash::vk;
+
+// Rounds `value` up to a multiple of `alignment`, a power of two (or 0, leaving it unchanged).
+pub fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    match alignment {
+        0 => value,
+        _ => (value + alignment - 1) & !(alignment - 1),
+    }
+}
+
+// Commonly needed device limits, read once at context creation. The full set is still available
+// through SharedContext::get_physical_device_limits.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceLimits {
+    pub min_uniform_buffer_offset_alignment: vk::DeviceSize,
+    pub min_storage_buffer_offset_alignment: vk::DeviceSize,
+    pub non_coherent_atom_size: vk::DeviceSize,
+    pub max_push_constants_size: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub max_image_dimension_2d: u32,
+    pub max_sampler_anisotropy: f32,
+    pub line_width_range: [f32; 2],
+    // Nanoseconds per timestamp query tick.
+    pub timestamp_period: f32,
+    // Highest sample count supported by both color and depth framebuffer attachments.
+    pub max_samples: vk::SampleCountFlags,
+    pub framebuffer_color_sample_counts: vk::SampleCountFlags,
+    pub framebuffer_depth_sample_counts: vk::SampleCountFlags,
+}
+
+impl DeviceLimits {
+    pub fn new(limits: &vk::PhysicalDeviceLimits) -> Self {
+        let sample_counts =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        let max_samples = (0..7)
+            .rev()
+            .map(|bit| vk::SampleCountFlags::from_raw(1 << bit))
+            .find(|samples| sample_counts.contains(*samples))
+            .unwrap_or(vk::SampleCountFlags::TYPE_1);
+        DeviceLimits {
+            min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+            min_storage_buffer_offset_alignment: limits.min_storage_buffer_offset_alignment,
+            non_coherent_atom_size: limits.non_coherent_atom_size,
+            max_push_constants_size: limits.max_push_constants_size,
+            max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+            max_image_dimension_2d: limits.max_image_dimension2_d,
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            line_width_range: limits.line_width_range,
+            timestamp_period: limits.timestamp_period,
+            max_samples,
+            framebuffer_color_sample_counts: limits.framebuffer_color_sample_counts,
+            framebuffer_depth_sample_counts: limits.framebuffer_depth_sample_counts,
+        }
+    }
+
+    // Size (or offset) of uniform data bound at dynamic offsets or sub-ranges of a buffer.
+    pub fn align_ubo(&self, size: vk::DeviceSize) -> vk::DeviceSize {
+        align_up(size, self.min_uniform_buffer_offset_alignment)
+    }
+
+    pub fn align_ssbo(&self, size: vk::DeviceSize) -> vk::DeviceSize {
+        align_up(size, self.min_storage_buffer_offset_alignment)
+    }
+
+    // Count of elements rounded up for the ones after it to start at a valid storage buffer
+    // offset. The element size must be a power of two.
+    pub fn align_ssbo_elements(&self, count: usize, element_size: usize) -> usize {
+        self.align_ssbo((count * element_size) as vk::DeviceSize) as usize / element_size
+    }
+
+    // For flushes and invalidations of host visible, non coherent memory ranges.
+    pub fn align_non_coherent(&self, size: vk::DeviceSize) -> vk::DeviceSize {
+        align_up(size, self.non_coherent_atom_size)
+    }
+
+    // Milliseconds between two timestamp query results.
+    pub fn timestamps_to_ms(&self, begin: u64, end: u64) -> f32 {
+        end.wrapping_sub(begin) as f32 * self.timestamp_period * 1e-6
+    }
+}
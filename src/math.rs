@@ -0,0 +1,8 @@
+// Math types used across the public API. Downstream code should import them from here (or from
+// the prelude) rather than from glam directly, so that a glam bump inside sol stays source
+// compatible as long as these items keep existing.
+pub use glam::{
+    ivec2, ivec3, ivec4, mat2, mat3, mat4, quat, uvec2, uvec3, uvec4, vec2, vec3, vec3a, vec4,
+    Affine2, Affine3A, EulerRot, IVec2, IVec3, IVec4, Mat2, Mat3, Mat4, Quat, UVec2, UVec3,
+    UVec4, Vec2, Vec3, Vec3A, Vec4, Vec4Swizzles,
+};
@@ -0,0 +1,339 @@
+use ash::{vk, Instance};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc};
+use gpu_allocator::MemoryLocation;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Fraction of a heap budget above which allocations print a warning, once until the usage drops
+// back below it.
+const BUDGET_WARNING_THRESHOLD: f64 = 0.9;
+// Minimum delay between the VK_EXT_memory_budget queries of allocations, whose usage is otherwise
+// estimated from the tracked allocations.
+const BUDGET_QUERY_INTERVAL: Duration = Duration::from_millis(500);
+
+// Usage of a memory heap in bytes, see SharedContext::memory_report.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+    pub heap_index: u32,
+    pub flags: vk::MemoryHeapFlags,
+    pub size: vk::DeviceSize,
+    // Sum of the live buffer and image allocations of the crate in the heap.
+    pub allocated: vk::DeviceSize,
+    pub allocation_count: usize,
+    // Usage and budget of the process from VK_EXT_memory_budget, including the unused parts of
+    // the allocator memory blocks and other APIs. Without the extension, usage is None and the
+    // budget is the heap size.
+    pub usage: Option<vk::DeviceSize>,
+    pub budget: vk::DeviceSize,
+}
+
+impl HeapBudget {
+    // The larger of both usage estimates.
+    pub fn used(&self) -> vk::DeviceSize {
+        self.usage.unwrap_or(0).max(self.allocated)
+    }
+
+    pub fn is_device_local(&self) -> bool {
+        self.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)
+    }
+
+    pub fn is_near_budget(&self) -> bool {
+        self.used() as f64 > self.budget as f64 * BUDGET_WARNING_THRESHOLD
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapBudget>,
+    // Whether the usage and budgets come from VK_EXT_memory_budget.
+    pub memory_budget: bool,
+}
+
+impl MemoryReport {
+    // Bytes allocated in the device local heaps, e.g. by render targets.
+    pub fn device_local_allocated(&self) -> vk::DeviceSize {
+        self.heaps
+            .iter()
+            .filter(|heap| heap.is_device_local())
+            .map(|heap| heap.allocated)
+            .sum()
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for heap in &self.heaps {
+            write!(
+                f,
+                "Heap {}{}: {} in {} allocations",
+                heap.heap_index,
+                if heap.is_device_local() {
+                    " (device local)"
+                } else {
+                    ""
+                },
+                Bytes(heap.allocated),
+                heap.allocation_count
+            )?;
+            if let Some(usage) = heap.usage {
+                write!(f, ", {} used", Bytes(usage))?;
+            }
+            writeln!(
+                f,
+                " of {} budget, {} heap{}",
+                Bytes(heap.budget),
+                Bytes(heap.size),
+                if heap.is_near_budget() {
+                    ", near budget"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A live buffer or image allocation, see SharedContext::named_allocations.
+#[derive(Clone, Debug)]
+pub struct NamedAllocation {
+    pub name: String,
+    pub size: vk::DeviceSize,
+    pub heap_index: u32,
+    pub location: MemoryLocation,
+}
+
+impl fmt::Display for NamedAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>10}  heap {}  {:?}  {}",
+            Bytes(self.size).to_string(),
+            self.heap_index,
+            self.location,
+            if self.name.is_empty() {
+                "<unnamed>"
+            } else {
+                &self.name
+            }
+        )
+    }
+}
+
+// Allocations made with SharedContext::allocate_memory, those made directly with the allocator
+// are not tracked.
+pub(crate) struct MemoryTracker {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    memory_budget: bool,
+    // By memory and offset.
+    allocations: HashMap<(vk::DeviceMemory, vk::DeviceSize), NamedAllocation>,
+    // Running totals of the allocations, per heap.
+    heap_allocated: Vec<vk::DeviceSize>,
+    heap_allocation_counts: Vec<usize>,
+    // Usage and budget per heap of the last VK_EXT_memory_budget query.
+    heap_budgets: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+    last_budget_query: Option<Instant>,
+    warned_heaps: Vec<bool>,
+}
+
+impl MemoryTracker {
+    // `memory_budget`: whether VK_EXT_memory_budget is enabled.
+    pub(crate) fn new(
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+        memory_budget: bool,
+    ) -> Self {
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(pdevice) };
+        let heap_count = memory_properties.memory_heap_count as usize;
+        MemoryTracker {
+            memory_properties,
+            memory_budget,
+            allocations: HashMap::new(),
+            heap_allocated: vec![0; heap_count],
+            heap_allocation_counts: vec![0; heap_count],
+            heap_budgets: vec![(0, 0); heap_count],
+            last_budget_query: None,
+            warned_heaps: vec![false; heap_count],
+        }
+    }
+
+    pub(crate) fn track(
+        &mut self,
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+        allocation: &Allocation,
+        desc: &AllocationCreateDesc,
+    ) {
+        let memory_type_index = memory_type_index(&self.memory_properties, desc);
+        let heap_index = self.memory_properties.memory_types[memory_type_index].heap_index;
+        let key = unsafe { (allocation.memory(), allocation.offset()) };
+        let previous = self.allocations.insert(
+            key,
+            NamedAllocation {
+                name: desc.name.to_string(),
+                size: allocation.size(),
+                heap_index,
+                location: desc.location,
+            },
+        );
+        if let Some(previous) = previous {
+            self.remove_from_totals(&previous);
+        }
+        self.heap_allocated[heap_index as usize] += allocation.size();
+        self.heap_allocation_counts[heap_index as usize] += 1;
+
+        let query_due = match self.last_budget_query {
+            Some(time) => time.elapsed() >= BUDGET_QUERY_INTERVAL,
+            None => true,
+        };
+        if self.memory_budget && query_due {
+            self.query_budgets(instance, pdevice);
+        }
+        let heap = self.heap_budget(heap_index as usize);
+        let warned = &mut self.warned_heaps[heap_index as usize];
+        if heap.is_near_budget() && !*warned {
+            println!(
+                "Memory heap {} nearing its budget: {} used of {}, allocating {}.",
+                heap_index,
+                Bytes(heap.used()),
+                Bytes(heap.budget),
+                if desc.name.is_empty() {
+                    "<unnamed>"
+                } else {
+                    desc.name
+                }
+            );
+        }
+        *warned = heap.is_near_budget();
+    }
+
+    pub(crate) fn untrack(&mut self, allocation: &Allocation) {
+        let key = unsafe { (allocation.memory(), allocation.offset()) };
+        if let Some(allocation) = self.allocations.remove(&key) {
+            self.remove_from_totals(&allocation);
+        }
+    }
+
+    // Queries the current budgets, unlike the throttled queries of track.
+    pub(crate) fn report(
+        &mut self,
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+    ) -> MemoryReport {
+        if self.memory_budget {
+            self.query_budgets(instance, pdevice);
+        }
+        let heap_count = self.memory_properties.memory_heap_count as usize;
+        MemoryReport {
+            heaps: (0..heap_count)
+                .map(|index| self.heap_budget(index))
+                .collect(),
+            memory_budget: self.memory_budget,
+        }
+    }
+
+    fn remove_from_totals(&mut self, allocation: &NamedAllocation) {
+        let heap_index = allocation.heap_index as usize;
+        self.heap_allocated[heap_index] -= allocation.size;
+        self.heap_allocation_counts[heap_index] -= 1;
+    }
+
+    fn query_budgets(&mut self, instance: &Instance, pdevice: vk::PhysicalDevice) {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties =
+            vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget_properties);
+        unsafe { instance.get_physical_device_memory_properties2(pdevice, &mut properties) };
+        for (index, heap_budget) in self.heap_budgets.iter_mut().enumerate() {
+            *heap_budget = (
+                budget_properties.heap_usage[index],
+                budget_properties.heap_budget[index],
+            );
+        }
+        self.last_budget_query = Some(Instant::now());
+    }
+
+    fn heap_budget(&self, index: usize) -> HeapBudget {
+        let heap = self.memory_properties.memory_heaps[index];
+        let (usage, budget) = self.heap_budgets[index];
+        HeapBudget {
+            heap_index: index as u32,
+            flags: heap.flags,
+            size: heap.size,
+            allocated: self.heap_allocated[index],
+            allocation_count: self.heap_allocation_counts[index],
+            usage: match self.memory_budget {
+                true => Some(usage),
+                false => None,
+            },
+            budget: match self.memory_budget {
+                true => budget,
+                false => heap.size,
+            },
+        }
+    }
+
+    // Largest first.
+    pub(crate) fn allocations(&self) -> Vec<NamedAllocation> {
+        let mut allocations = self.allocations.values().cloned().collect::<Vec<_>>();
+        allocations.sort_by_key(|allocation| std::cmp::Reverse(allocation.size));
+        allocations
+    }
+}
+
+// gpu-allocator doesn't expose the memory type of allocations, it is found from the request as
+// the allocator does: the first allowed type with the preferred properties of the location, else
+// with the required ones. A CpuToGpu allocation falling back from device local memory when its
+// heap is full is attributed to the device local heap.
+fn memory_type_index(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    desc: &AllocationCreateDesc,
+) -> usize {
+    let host = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+    let (preferred, required) = match desc.location {
+        MemoryLocation::GpuOnly => (
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ),
+        MemoryLocation::CpuToGpu => (host | vk::MemoryPropertyFlags::DEVICE_LOCAL, host),
+        MemoryLocation::GpuToCpu => (host | vk::MemoryPropertyFlags::HOST_CACHED, host),
+        MemoryLocation::Unknown => (
+            vk::MemoryPropertyFlags::empty(),
+            vk::MemoryPropertyFlags::empty(),
+        ),
+    };
+    let memory_types =
+        &memory_properties.memory_types[..memory_properties.memory_type_count as usize];
+    let find = |flags: vk::MemoryPropertyFlags| {
+        memory_types
+            .iter()
+            .enumerate()
+            .position(|(index, memory_type)| {
+                desc.requirements.memory_type_bits & (1 << index) != 0
+                    && memory_type.property_flags.contains(flags)
+            })
+    };
+    find(preferred)
+        .or_else(|| find(required))
+        // The first memory type allowed, as a fallback.
+        .unwrap_or(desc.requirements.memory_type_bits.trailing_zeros() as usize)
+}
+
+// Byte count printed in the largest unit keeping it above one, e.g. "1.5 GiB".
+struct Bytes(vk::DeviceSize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = ["B", "KiB", "MiB", "GiB"];
+        let mut size = self.0 as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit + 1 < units.len() {
+            size /= 1024.0;
+            unit += 1;
+        }
+        match unit {
+            0 => write!(f, "{} B", self.0),
+            _ => write!(f, "{:.1} {}", size, units[unit]),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::FeatureMembers;
+use ash::{vk, Device, Instance};
+use std::ffi::{c_void, CStr};
+use std::mem;
+
+// VK_EXT_mesh_shader, not in the ash bindings yet (Vulkan 1.3.230). Its shader stages and
+// pipeline stages share their bits with those of VK_NV_mesh_shader.
+pub const SHADER_STAGE_TASK_EXT: vk::ShaderStageFlags = vk::ShaderStageFlags::TASK_NV;
+pub const SHADER_STAGE_MESH_EXT: vk::ShaderStageFlags = vk::ShaderStageFlags::MESH_NV;
+pub const PIPELINE_STAGE_TASK_SHADER_EXT: vk::PipelineStageFlags =
+    vk::PipelineStageFlags::TASK_SHADER_NV;
+pub const PIPELINE_STAGE_MESH_SHADER_EXT: vk::PipelineStageFlags =
+    vk::PipelineStageFlags::MESH_SHADER_NV;
+
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_FEATURES_EXT: vk::StructureType =
+    vk::StructureType::from_raw(1_000_328_000);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalDeviceMeshShaderFeaturesEXT {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub task_shader: vk::Bool32,
+    pub mesh_shader: vk::Bool32,
+    pub multiview_mesh_shader: vk::Bool32,
+    pub primitive_fragment_shading_rate_mesh_shader: vk::Bool32,
+    pub mesh_shader_queries: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceMeshShaderFeaturesEXT {
+    fn default() -> Self {
+        PhysicalDeviceMeshShaderFeaturesEXT {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_FEATURES_EXT,
+            p_next: std::ptr::null_mut(),
+            task_shader: vk::FALSE,
+            mesh_shader: vk::FALSE,
+            multiview_mesh_shader: vk::FALSE,
+            primitive_fragment_shading_rate_mesh_shader: vk::FALSE,
+            mesh_shader_queries: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsPhysicalDeviceFeatures2 for PhysicalDeviceMeshShaderFeaturesEXT {}
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceMeshShaderFeaturesEXT {}
+
+impl FeatureMembers for PhysicalDeviceMeshShaderFeaturesEXT {
+    const TYPE_NAME: &'static str = "PhysicalDeviceMeshShaderFeaturesEXT";
+    const MEMBER_NAMES: &'static [&'static str] = &[
+        "task_shader",
+        "mesh_shader",
+        "multiview_mesh_shader",
+        "primitive_fragment_shading_rate_mesh_shader",
+        "mesh_shader_queries",
+    ];
+
+    fn members(&self) -> Vec<vk::Bool32> {
+        vec![
+            self.task_shader,
+            self.mesh_shader,
+            self.multiview_mesh_shader,
+            self.primitive_fragment_shading_rate_mesh_shader,
+            self.mesh_shader_queries,
+        ]
+    }
+
+    fn set_members(&mut self, members: &[vk::Bool32]) {
+        self.task_shader = members[0];
+        self.mesh_shader = members[1];
+        self.multiview_mesh_shader = members[2];
+        self.primitive_fragment_shading_rate_mesh_shader = members[3];
+        self.mesh_shader_queries = members[4];
+    }
+}
+
+// Task workgroup counts of an indirect draw, see MeshShader::cmd_draw_mesh_tasks_indirect.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawMeshTasksIndirectCommand {
+    pub group_count_x: u32,
+    pub group_count_y: u32,
+    pub group_count_z: u32,
+}
+
+type VoidFunction = unsafe extern "system" fn();
+type CmdDrawMeshTasks = unsafe extern "system" fn(vk::CommandBuffer, u32, u32, u32);
+type CmdDrawMeshTasksIndirect =
+    unsafe extern "system" fn(vk::CommandBuffer, vk::Buffer, vk::DeviceSize, u32, u32);
+type CmdDrawMeshTasksIndirectCount = unsafe extern "system" fn(
+    vk::CommandBuffer,
+    vk::Buffer,
+    vk::DeviceSize,
+    vk::Buffer,
+    vk::DeviceSize,
+    u32,
+    u32,
+);
+
+// Loader of the VK_EXT_mesh_shader draw commands, as the ash extension loaders.
+#[derive(Clone)]
+pub struct MeshShader {
+    cmd_draw_mesh_tasks: CmdDrawMeshTasks,
+    cmd_draw_mesh_tasks_indirect: CmdDrawMeshTasksIndirect,
+    // Only with drawIndirectCount support.
+    cmd_draw_mesh_tasks_indirect_count: Option<CmdDrawMeshTasksIndirectCount>,
+}
+
+impl MeshShader {
+    pub fn name() -> &'static CStr {
+        CStr::from_bytes_with_nul(b"VK_EXT_mesh_shader\0").unwrap()
+    }
+
+    // The extension must be enabled on `device`.
+    pub fn new(instance: &Instance, device: &Device) -> Self {
+        let load = |name: &[u8]| unsafe {
+            let name = CStr::from_bytes_with_nul(name).unwrap();
+            instance.get_device_proc_addr(device.handle(), name.as_ptr())
+        };
+        let expect = |name: &[u8]| {
+            load(name).unwrap_or_else(|| panic!("Unable to load {}", String::from_utf8_lossy(name)))
+        };
+        unsafe {
+            let cmd_draw_mesh_tasks: CmdDrawMeshTasks =
+                mem::transmute(expect(b"vkCmdDrawMeshTasksEXT\0"));
+            let cmd_draw_mesh_tasks_indirect: CmdDrawMeshTasksIndirect =
+                mem::transmute(expect(b"vkCmdDrawMeshTasksIndirectEXT\0"));
+            let cmd_draw_mesh_tasks_indirect_count = load(b"vkCmdDrawMeshTasksIndirectCountEXT\0")
+                .map(|function| {
+                    mem::transmute::<VoidFunction, CmdDrawMeshTasksIndirectCount>(function)
+                });
+            MeshShader {
+                cmd_draw_mesh_tasks,
+                cmd_draw_mesh_tasks_indirect,
+                cmd_draw_mesh_tasks_indirect_count,
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `cmd` must be in the recording state, inside a render pass with a task or mesh shader
+    /// pipeline bound.
+    pub unsafe fn cmd_draw_mesh_tasks(
+        &self,
+        cmd: vk::CommandBuffer,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) {
+        (self.cmd_draw_mesh_tasks)(cmd, group_count_x, group_count_y, group_count_z);
+    }
+
+    /// `draw_count` DrawMeshTasksIndirectCommand read from `buffer` at `offset`, `stride` bytes
+    /// apart.
+    ///
+    /// # Safety
+    ///
+    /// As cmd_draw_mesh_tasks, `buffer` must have INDIRECT_BUFFER usage and hold the commands.
+    pub unsafe fn cmd_draw_mesh_tasks_indirect(
+        &self,
+        cmd: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        (self.cmd_draw_mesh_tasks_indirect)(cmd, buffer, offset, draw_count, stride);
+    }
+
+    /// As cmd_draw_mesh_tasks_indirect, the draw count being read from `count_buffer`, e.g.
+    /// written by a culling compute shader. Requires drawIndirectCount.
+    ///
+    /// # Safety
+    ///
+    /// As cmd_draw_mesh_tasks_indirect, `count_buffer` must also have INDIRECT_BUFFER usage.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn cmd_draw_mesh_tasks_indirect_count(
+        &self,
+        cmd: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        count_buffer: vk::Buffer,
+        count_buffer_offset: vk::DeviceSize,
+        max_draw_count: u32,
+        stride: u32,
+    ) {
+        let cmd_draw_mesh_tasks_indirect_count = self
+            .cmd_draw_mesh_tasks_indirect_count
+            .expect("vkCmdDrawMeshTasksIndirectCountEXT unavailable.");
+        cmd_draw_mesh_tasks_indirect_count(
+            cmd,
+            buffer,
+            offset,
+            count_buffer,
+            count_buffer_offset,
+            max_draw_count,
+            stride,
+        );
+    }
+}
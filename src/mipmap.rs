@@ -0,0 +1,343 @@
+use crate::{
+    Context, DescriptorSetInfo, DescriptorSetLayout, DescriptorSetLayoutInfo, Error, Image2d,
+    Resource, Result, Shader,
+};
+use ash::vk;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// Mips written by a single dispatch, see DOWNSAMPLE_COMP.
+const MIPS_PER_DISPATCH: u32 = 4;
+const WORKGROUP_SIZE: u32 = 16;
+
+// Each invocation averages 2x2 texels of the source mip into the first destination mip, then the
+// workgroup reduces its 16x16 tile in shared memory into the next ones. FORMAT is replaced by the
+// storage format qualifier of the image.
+const DOWNSAMPLE_COMP: &str = "
+#version 460
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0, FORMAT) uniform readonly image2D sourceImage;
+layout(set = 0, binding = 1, FORMAT) uniform writeonly image2D mipImage1;
+layout(set = 0, binding = 2, FORMAT) uniform writeonly image2D mipImage2;
+layout(set = 0, binding = 3, FORMAT) uniform writeonly image2D mipImage3;
+layout(set = 0, binding = 4, FORMAT) uniform writeonly image2D mipImage4;
+layout(push_constant) uniform PushConstant {
+    uint mipCount;
+} push;
+
+shared vec4 tile[16][16];
+
+#define STORE(image, pixel, color) \\
+    if (all(lessThan(pixel, imageSize(image)))) imageStore(image, pixel, color)
+
+// Averages the 2x2 tile texels at `local`, `step` apart, and stores the result in place.
+vec4 reduce(ivec2 local, int step) {
+    vec4 color = 0.25 * (tile[local.y][local.x] + tile[local.y][local.x + step]
+        + tile[local.y + step][local.x] + tile[local.y + step][local.x + step]);
+    tile[local.y][local.x] = color;
+    return color;
+}
+
+void main() {
+    ivec2 local = ivec2(gl_LocalInvocationID.xy);
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 group = ivec2(gl_WorkGroupID.xy);
+    // Odd sizes are approximated by clamping the last row and column.
+    ivec2 last = imageSize(sourceImage) - 1;
+    ivec2 source = pixel * 2;
+    vec4 color = 0.25 * (imageLoad(sourceImage, min(source, last))
+        + imageLoad(sourceImage, min(source + ivec2(1, 0), last))
+        + imageLoad(sourceImage, min(source + ivec2(0, 1), last))
+        + imageLoad(sourceImage, min(source + ivec2(1, 1), last)));
+    STORE(mipImage1, pixel, color);
+    if (push.mipCount < 2) {
+        return;
+    }
+    tile[local.y][local.x] = color;
+    barrier();
+    if (all(equal(local & 1, ivec2(0)))) {
+        STORE(mipImage2, group * 8 + local / 2, reduce(local, 1));
+    }
+    if (push.mipCount < 3) {
+        return;
+    }
+    barrier();
+    if (all(equal(local & 3, ivec2(0)))) {
+        STORE(mipImage3, group * 4 + local / 4, reduce(local, 2));
+    }
+    if (push.mipCount < 4) {
+        return;
+    }
+    barrier();
+    if (all(equal(local & 7, ivec2(0)))) {
+        STORE(mipImage4, group * 2 + local / 8, reduce(local, 4));
+    }
+}
+";
+
+fn storage_format_qualifier(format: vk::Format) -> Option<&'static str> {
+    match format {
+        vk::Format::R8_UNORM => Some("r8"),
+        vk::Format::R8G8_UNORM => Some("rg8"),
+        vk::Format::R8G8B8A8_UNORM => Some("rgba8"),
+        vk::Format::R16G16_SFLOAT => Some("rg16f"),
+        vk::Format::R16G16B16A16_SFLOAT => Some("rgba16f"),
+        vk::Format::R32_SFLOAT => Some("r32f"),
+        vk::Format::R32G32_SFLOAT => Some("rg32f"),
+        vk::Format::R32G32B32A32_SFLOAT => Some("rgba32f"),
+        vk::Format::B10G11R11_UFLOAT_PACK32 => Some("r11f_g11f_b10f"),
+        _ => None,
+    }
+}
+
+// Compute alternative to Image2d::generate_mipmaps: records the downsampling of the whole mip
+// chain into a caller's command buffer, up to 4 mips per dispatch, without blocking nor needing a
+// graphics queue or linear filtering. The image needs STORAGE usage, hence a storage capable
+// format (no sRGB formats). 3D images are not supported.
+pub struct MipmapGenerator {
+    context: Arc<Context>,
+    layout: DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    // Compiled on first use, one per image format.
+    pipelines: HashMap<vk::Format, vk::Pipeline>,
+}
+
+impl MipmapGenerator {
+    pub fn new(context: Arc<Context>) -> Self {
+        let mut layout_info = DescriptorSetLayoutInfo::default();
+        for binding in 0..=MIPS_PER_DISPATCH {
+            layout_info = layout_info.binding(
+                binding,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            );
+        }
+        let layout = DescriptorSetLayout::new(context.clone(), layout_info);
+        let set_layouts = [layout.handle()];
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(std::mem::size_of::<u32>() as u32)
+            .build()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            context
+                .device()
+                .create_pipeline_layout(&layout_info, None)
+                .unwrap()
+        };
+        MipmapGenerator {
+            context,
+            layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    fn pipeline(&mut self, format: vk::Format, name: &str) -> Result<vk::Pipeline> {
+        if let Some(&pipeline) = self.pipelines.get(&format) {
+            return Ok(pipeline);
+        }
+        let features = vk::FormatFeatureFlags::STORAGE_IMAGE;
+        let qualifier = storage_format_qualifier(format)
+            .filter(|_| {
+                self.context
+                    .shared()
+                    .format_supports(format, vk::ImageTiling::OPTIMAL, features)
+            })
+            .ok_or_else(|| Error::UnsupportedFormat {
+                name: format!("Mipmaps of {}", name),
+                format,
+                features,
+            })?;
+        let shader = Shader::from_source(
+            self.context.clone(),
+            &DOWNSAMPLE_COMP.replace("FORMAT", qualifier),
+            "downsample.comp",
+            vk::ShaderStageFlags::COMPUTE,
+        )?;
+        let entry_name = CString::new("main").unwrap();
+        let create_infos = [vk::ComputePipelineCreateInfo::builder()
+            .stage(shader.get_create_info(&entry_name))
+            .layout(self.pipeline_layout)
+            .build()];
+        let pipeline = unsafe {
+            self.context
+                .device()
+                .create_compute_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                .map_err(|(_, result)| result)?[0]
+        };
+        self.context
+            .debug_marker()
+            .set_name(pipeline, &format!("Downsample {:?}", format));
+        self.pipelines.insert(format, pipeline);
+        Ok(pipeline)
+    }
+
+    // Records the generation of every mip of `image` from the first one, last written by any
+    // previous command in its current layout. All the layers are downsampled and the image is
+    // left in SHADER_READ_ONLY_OPTIMAL layout, visible to fragment and compute shaders.
+    pub fn cmd_generate(&mut self, cmd: vk::CommandBuffer, image: &mut Image2d) -> Result<()> {
+        assert_ne!(image.get_view_type(), vk::ImageViewType::TYPE_3D);
+        let level_count = image.get_level_count();
+        if level_count < 2 {
+            return Ok(());
+        }
+        let pipeline = self.pipeline(image.get_format(), image.get_name())?;
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(level_count)
+            .layer_count(image.get_layer_count())
+            .build();
+        let old_layout = image.get_layout();
+        self.context.track_hazards(|tracker| {
+            tracker.barrier(
+                image.handle(),
+                image.get_name(),
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                Some((old_layout, vk::ImageLayout::GENERAL)),
+            )
+        });
+        let device = self.context.device();
+        let to_general = vk::ImageMemoryBarrier::builder()
+            .image(image.handle())
+            .old_layout(old_layout)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .subresource_range(subresource_range)
+            .build();
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_general],
+            );
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pipeline);
+        }
+
+        // Each dispatch reads the last mip written by the previous one.
+        let extent = image.get_extent();
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+        for base_mip in (0..level_count - 1).step_by(MIPS_PER_DISPATCH as usize) {
+            let mip_count = MIPS_PER_DISPATCH.min(level_count - 1 - base_mip);
+            for layer in 0..image.get_layer_count() {
+                let layers = layer..layer + 1;
+                let source = image.create_view(base_mip..base_mip + 1, layers.clone());
+                let mut set_info = DescriptorSetInfo::default().image(
+                    0,
+                    image.get_view_descriptor_info(source, vk::ImageLayout::GENERAL),
+                );
+                // Unused bindings repeat the last mip, never written to.
+                for binding in 1..=MIPS_PER_DISPATCH {
+                    let mip = base_mip + binding.min(mip_count);
+                    let view = image.create_view(mip..mip + 1, layers.clone());
+                    set_info = set_info.image(
+                        binding,
+                        image.get_view_descriptor_info(view, vk::ImageLayout::GENERAL),
+                    );
+                }
+                let desc_set = self.layout.get_or_create(set_info);
+                let width = (extent.width >> (base_mip + 1)).max(1);
+                let height = (extent.height >> (base_mip + 1)).max(1);
+                unsafe {
+                    device.cmd_bind_descriptor_sets(
+                        cmd,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.pipeline_layout,
+                        0,
+                        &[desc_set.handle()],
+                        &[],
+                    );
+                    device.cmd_push_constants(
+                        cmd,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        &mip_count.to_ne_bytes(),
+                    );
+                    device.cmd_dispatch(
+                        cmd,
+                        width.div_ceil(WORKGROUP_SIZE),
+                        height.div_ceil(WORKGROUP_SIZE),
+                        1,
+                    );
+                }
+            }
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[memory_barrier],
+                    &[],
+                    &[],
+                );
+            }
+        }
+
+        let to_shader_read = vk::ImageMemoryBarrier::builder()
+            .image(image.handle())
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .subresource_range(subresource_range)
+            .build();
+        let dst_stage =
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader_read],
+            );
+        }
+        self.context.track_hazards(|tracker| {
+            tracker.barrier(
+                image.handle(),
+                image.get_name(),
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                dst_stage,
+                vk::AccessFlags::SHADER_READ,
+                Some((
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )),
+            )
+        });
+        image.set_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        Ok(())
+    }
+}
+
+impl Drop for MipmapGenerator {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            for &pipeline in self.pipelines.values() {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -1,46 +1,204 @@
-use crate::{Context, RenderPass, Resource, TransientRenderPassInfo, Vertex};
+use crate::{
+    Context, Error, RenderPass, RenderingFormats, Resource, Result, ShaderReflection,
+    TransientRenderPassInfo, Vertex, SHADER_STAGE_MESH_EXT, SHADER_STAGE_TASK_EXT,
+};
 use ash::vk;
 use shaderc::{CompileOptions, Compiler, IncludeType, ResolvedInclude, ShaderKind};
+use std::cell::RefCell;
 use std::ffi::CString;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::result::Result;
 use std::string::String;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// Shaders of the crate, e.g. for `#include <utils.glsl>`. Always the first include dir. This is
+// the directory of the sources the crate was built from: when it doesn't exist on the running
+// machine (e.g. a shipped binary), the files below are served from ENGINE_SHADERS instead.
+pub const ENGINE_SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/glsl");
+
+// Engine shaders embedded in the binary, by file name in ENGINE_SHADER_DIR: those the crate
+// compiles and the includes offered to the application shaders.
+macro_rules! engine_shader {
+    ($name:literal) => {
+        ($name, include_str!(concat!("../assets/glsl/", $name)))
+    };
+}
+const ENGINE_SHADERS: [(&str, &str); 11] = [
+    engine_shader!("blit.frag"),
+    engine_shader!("debug_draw.vert"),
+    engine_shader!("fullscreen.vert"),
+    engine_shader!("postprocess.comp"),
+    engine_shader!("triangle.frag"),
+    engine_shader!("lights.glsl"),
+    engine_shader!("object_table.glsl"),
+    engine_shader!("payload.glsl"),
+    engine_shader!("postprocess.glsl"),
+    engine_shader!("sampling.glsl"),
+    engine_shader!("skinning.glsl"),
+];
+
+// Embedded source of an ENGINE_SHADER_DIR file.
+fn engine_shader_source(path: &Path) -> Option<&'static str> {
+    let name = path.strip_prefix(ENGINE_SHADER_DIR).ok()?;
+    ENGINE_SHADERS
+        .iter()
+        .find(|(file, _)| name == Path::new(file))
+        .map(|(_, source)| *source)
+}
+
+// Shader file content, falling back to the embedded engine shaders.
+fn read_shader_source(path: &Path) -> std::io::Result<String> {
+    fs::read_to_string(path).or_else(|e| match engine_shader_source(path) {
+        Some(source) => Ok(source.to_string()),
+        None => Err(e),
+    })
+}
 
 const STORE_SPIRV: bool = false;
 const LOAD_SPIRV: bool = false;
 
+// Used by a single thread at a time, but not tied to the thread which created it.
+struct PooledCompiler(Compiler);
+unsafe impl Send for PooledCompiler {}
+
+// Reuses shaderc compilers across shader compilations, one per concurrent compilation.
+#[derive(Default)]
+pub struct CompilerPool {
+    compilers: Mutex<Vec<PooledCompiler>>,
+}
+
+impl CompilerPool {
+    fn with_compiler<T>(&self, f: impl FnOnce(&mut Compiler) -> T) -> T {
+        let pooled = self.compilers.lock().unwrap().pop();
+        let mut compiler = pooled.unwrap_or_else(|| PooledCompiler(Compiler::new().unwrap()));
+        let result = f(&mut compiler.0);
+        self.compilers.lock().unwrap().push(compiler);
+        result
+    }
+
+    // Compilers created so far, the highest number of concurrent compilations.
+    pub fn compiler_count(&self) -> usize {
+        self.compilers.lock().unwrap().len()
+    }
+}
+
+// Compiles `shaders` in parallel, one thread per shader, in the order given. The shader modules
+// are then created on the calling thread.
+pub fn compile_shaders(
+    context: &Arc<Context>,
+    shaders: &[(PathBuf, vk::ShaderStageFlags)],
+) -> Result<Vec<Shader>> {
+    let include_dirs = context.shader_include_dirs();
+    let compiler_pool = context.compiler_pool();
+    let codes = match shaders {
+        [(path, stage_flags)] => vec![load_or_compile(
+            path,
+            *stage_flags,
+            include_dirs,
+            compiler_pool,
+        )],
+        _ => std::thread::scope(|scope| {
+            let threads = shaders
+                .iter()
+                .map(|(path, stage_flags)| {
+                    scope.spawn(move || {
+                        load_or_compile(path, *stage_flags, include_dirs, compiler_pool)
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().expect("Shader compilation thread panicked."))
+                .collect()
+        }),
+    };
+    shaders
+        .iter()
+        .zip(codes)
+        .map(|((path, stage_flags), code)| {
+            Shader::from_code(context.clone(), path.clone(), *stage_flags, code?)
+        })
+        .collect()
+}
+
 pub struct Shader {
     context: Arc<Context>,
     pub module: vk::ShaderModule,
     pub stage_flags: vk::ShaderStageFlags,
     pub path: PathBuf,
+    pub includes: Vec<PathBuf>,
+    // (including file, included file) edges, empty when loaded from spir-v.
+    include_graph: Vec<(PathBuf, PathBuf)>,
+    compile_time: SystemTime,
     text: Option<String>,
+    reflection: ShaderReflection,
 }
 
-fn get_sharerc_include(
-    requested_source: &str,
-    _include_type: IncludeType,
-    _origin_source: &str,
-    _recursion_depth: usize,
-    origin_dir: &Path,
-) -> Result<ResolvedInclude, String> {
-    //TODO: finish implementation
-    let resolved_file = origin_dir.join(requested_source);
-    let resolved_name = resolved_file
-        // .file_name()
-        // .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    //println!("Including: {}", resolved_name);
-    let error_msg = format!("Failed to open {}.", resolved_file.to_str().unwrap());
-    let content = fs::read_to_string(resolved_file.as_path()).expect(&error_msg);
-    Ok(ResolvedInclude {
-        resolved_name,
-        content,
-    })
+// Resolves the includes of a compilation and records its include graph. `#include "file"` is
+// searched in the including file's directory first, then in the context's shader include dirs,
+// `#include <file>` only in the latter.
+struct IncludeResolver<'a> {
+    include_dirs: &'a [PathBuf],
+    // Including files from the main source, to detect recursive includes.
+    stack: Vec<String>,
+    // (including file, included file) edges.
+    graph: Vec<(PathBuf, PathBuf)>,
+}
+
+impl<'a> IncludeResolver<'a> {
+    fn new(main_source: &str, include_dirs: &'a [PathBuf]) -> Self {
+        IncludeResolver {
+            include_dirs,
+            stack: vec![main_source.to_string()],
+            graph: Vec::new(),
+        }
+    }
+
+    fn resolve(
+        &mut self,
+        requested_source: &str,
+        include_type: IncludeType,
+        origin_source: &str,
+        include_depth: usize,
+    ) -> Result<ResolvedInclude, String> {
+        let origin_dir = Path::new(origin_source).parent();
+        let relative_dir = match include_type {
+            IncludeType::Relative => origin_dir,
+            IncludeType::Standard => None,
+        };
+        let resolved_file = relative_dir
+            .into_iter()
+            .chain(self.include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(requested_source))
+            .find(|file| file.is_file() || engine_shader_source(file).is_some())
+            .ok_or_else(|| {
+                format!(
+                    "Cannot find {} included by {} (include dirs: {:?})",
+                    requested_source, origin_source, self.include_dirs
+                )
+            })?;
+        let resolved_file = fs::canonicalize(&resolved_file).unwrap_or(resolved_file);
+        let resolved_name = resolved_file.display().to_string();
+
+        // The stack holds the main source and the files including this one.
+        self.stack.truncate(include_depth.max(1));
+        if self.stack.contains(&resolved_name) {
+            return Err(format!(
+                "Recursive include: {} -> {}",
+                self.stack.join(" -> "),
+                resolved_name
+            ));
+        }
+        let content = read_shader_source(&resolved_file)
+            .map_err(|e| format!("Failed to open {}: {}", resolved_name, e))?;
+        self.stack.push(resolved_name.clone());
+        self.graph.push((PathBuf::from(origin_source), resolved_file));
+        Ok(ResolvedInclude {
+            resolved_name,
+            content,
+        })
+    }
 }
 
 fn get_shaderc_stage(stage: &vk::ShaderStageFlags) -> Option<ShaderKind> {
@@ -66,6 +224,12 @@ fn get_shaderc_stage(stage: &vk::ShaderStageFlags) -> Option<ShaderKind> {
         return Some(ShaderKind::Miss);
     } else if *stage == vk::ShaderStageFlags::INTERSECTION_KHR {
         return Some(ShaderKind::Intersection);
+    } else if *stage == SHADER_STAGE_TASK_EXT {
+        // The EXT stages share their bits with the NV ones, the shaders must then use
+        // GL_EXT_mesh_shader, which requires a glslang supporting it.
+        return Some(ShaderKind::Task);
+    } else if *stage == SHADER_STAGE_MESH_EXT {
+        return Some(ShaderKind::Mesh);
     }
     None
 }
@@ -83,85 +247,229 @@ fn get_spirv_filepath(path: &PathBuf) -> PathBuf {
     compiled_path
 }
 
-fn is_more_recent(path: &PathBuf, other: &PathBuf) -> bool {
-    let timestamp = fs::metadata(path.as_path()).unwrap().modified().unwrap();
-    let other_timestamp = fs::metadata(other.as_path()).unwrap().modified().unwrap();
-    timestamp > other_timestamp
+// Includes of a stored spir-v file, one path per line.
+fn get_spirv_dependencies_filepath(spirv_path: &Path) -> PathBuf {
+    let mut path = spirv_path.as_os_str().to_owned();
+    path.push(".deps");
+    PathBuf::from(path)
 }
 
-impl Shader {
-    pub fn new(context: Arc<Context>, path: PathBuf, stage_flags: vk::ShaderStageFlags) -> Self {
-        let spirv_path = get_spirv_filepath(&path);
-        // Only load spirv directly if its timestamp is more recent than the source file.
-        if spirv_path.exists() && LOAD_SPIRV && is_more_recent(&spirv_path, &path) {
-            let mut file = fs::File::open(&spirv_path).unwrap();
-            let words = ash::util::read_spv(&mut file).unwrap();
-            let shader_info = vk::ShaderModuleCreateInfo::builder().code(&words);
-            unsafe {
-                let module = context
-                    .device()
-                    .create_shader_module(&shader_info, None)
-                    .unwrap();
-                return Shader {
-                    context,
-                    module,
-                    stage_flags,
-                    path,
-                    text: None,
-                };
-            }
-        }
+// None if the spir-v must be recompiled: the file or its dependency list is missing, or the
+// source or one of its includes changed since.
+fn read_spirv_dependencies(path: &Path, spirv_path: &Path) -> Option<Vec<PathBuf>> {
+    let compile_time = fs::metadata(spirv_path).and_then(|m| m.modified()).ok()?;
+    let dependencies = fs::read_to_string(get_spirv_dependencies_filepath(spirv_path)).ok()?;
+    let includes: Vec<PathBuf> = dependencies.lines().map(PathBuf::from).collect();
+    let mut files = includes.clone();
+    files.push(path.to_path_buf());
+    match is_modified_since(&files, compile_time) {
+        true => None,
+        false => Some(includes),
+    }
+}
 
-        let error_msg = format!("Failed to open {}.", path.to_str().unwrap());
-        let source = fs::read_to_string(path.as_path()).expect(&error_msg);
+// Missing files count as modified so that a deleted include also forces a rebuild, except the
+// embedded engine shaders which never change.
+pub(crate) fn is_modified_since(paths: &[PathBuf], time: SystemTime) -> bool {
+    paths.iter().any(|path| match fs::metadata(path).and_then(|m| m.modified()) {
+        Ok(modified) => modified > time,
+        Err(_) => engine_shader_source(path).is_none(),
+    })
+}
 
-        let mut compiler = Compiler::new().unwrap();
+// Spir-v of a shader file, compiled or loaded without a device so that it can run on any thread.
+struct ShaderCode {
+    words: Vec<u32>,
+    includes: Vec<PathBuf>,
+    // (including file, included file) edges, empty when loaded from spir-v.
+    include_graph: Vec<(PathBuf, PathBuf)>,
+    compile_time: SystemTime,
+    text: Option<String>,
+}
+
+fn load_or_compile(
+    path: &Path,
+    stage_flags: vk::ShaderStageFlags,
+    include_dirs: &[PathBuf],
+    compiler_pool: &CompilerPool,
+) -> Result<ShaderCode> {
+    let path = path.to_path_buf();
+    let spirv_path = get_spirv_filepath(&path);
+    // Only load spirv directly if it is more recent than the source file and its includes.
+    let cached_includes = match LOAD_SPIRV {
+        true => read_spirv_dependencies(&path, &spirv_path),
+        false => None,
+    };
+    if let Some(includes) = cached_includes {
+        let mut file = fs::File::open(&spirv_path).map_err(|e| Error::io(&spirv_path, e))?;
+        let words = ash::util::read_spv(&mut file).map_err(|e| Error::io(&spirv_path, e))?;
+        return Ok(ShaderCode {
+            words,
+            includes,
+            include_graph: Vec::new(),
+            compile_time: SystemTime::now(),
+            text: None,
+        });
+    }
+
+    let source = read_shader_source(&path).map_err(|e| Error::io(&path, e))?;
+
+    let compile_time = SystemTime::now();
+    // Absolute, so that relative includes of nested files resolve from their directory.
+    let main_source = fs::canonicalize(&path)
+        .unwrap_or_else(|_| path.clone())
+        .display()
+        .to_string();
+    let resolver = RefCell::new(IncludeResolver::new(&main_source, include_dirs));
+    let code = compiler_pool.with_compiler(|compiler| {
         let mut options = CompileOptions::new().unwrap();
         options.set_generate_debug_info();
         options.set_target_spirv(shaderc::SpirvVersion::V1_4);
         options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2 as u32);
-        let origin_path = path.clone();
         options.set_include_callback(
-            move |requested_source, include_type, origin_source, recursion_depth| {
-                get_sharerc_include(
+            |requested_source, include_type, origin_source, include_depth| {
+                resolver.borrow_mut().resolve(
                     requested_source,
                     include_type,
                     origin_source,
-                    recursion_depth,
-                    origin_path.parent().unwrap(),
+                    include_depth,
                 )
             },
         );
         let sc_stage = get_shaderc_stage(&stage_flags).unwrap();
-        let code = compiler
-            .compile_into_spirv(
-                &source,
-                sc_stage,
-                path.file_name().unwrap().to_str().unwrap(),
-                "main",
-                Some(&options),
-            )
-            .unwrap();
-
-        if STORE_SPIRV {
-            fs::write(spirv_path, code.as_binary_u8()).expect("Failed to write spir-v.");
+        compiler
+            .compile_into_spirv(&source, sc_stage, &main_source, "main", Some(&options))
+            .map_err(|e| Error::ShaderCompilation {
+                name: path.display().to_string(),
+                log: e.to_string(),
+            })
+    })?;
+    // The include graph lets dependent pipelines be rebuilt when any included file changes.
+    let include_graph = resolver.into_inner().graph;
+    let mut includes: Vec<PathBuf> = include_graph.iter().map(|(_, file)| file.clone()).collect();
+    includes.sort();
+    includes.dedup();
+
+    if STORE_SPIRV {
+        let dependencies: Vec<_> = includes.iter().map(|p| p.display().to_string()).collect();
+        fs::write(
+            get_spirv_dependencies_filepath(&spirv_path),
+            dependencies.join("\n"),
+        )
+        .expect("Failed to write spir-v dependencies.");
+        fs::write(spirv_path, code.as_binary_u8()).expect("Failed to write spir-v.");
+    }
+    Ok(ShaderCode {
+        words: code.as_binary().to_vec(),
+        includes,
+        include_graph,
+        compile_time,
+        text: Some(source),
+    })
+}
+
+impl Shader {
+    pub fn new(
+        context: Arc<Context>,
+        path: PathBuf,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Result<Self> {
+        let code = load_or_compile(
+            &path,
+            stage_flags,
+            context.shader_include_dirs(),
+            context.compiler_pool(),
+        )?;
+        Self::from_code(context, path, stage_flags, code)
+    }
+
+    fn from_code(
+        context: Arc<Context>,
+        path: PathBuf,
+        stage_flags: vk::ShaderStageFlags,
+        code: ShaderCode,
+    ) -> Result<Self> {
+        let shader_info = vk::ShaderModuleCreateInfo::builder().code(&code.words);
+        unsafe {
+            let module = context.device().create_shader_module(&shader_info, None)?;
+            context
+                .debug_marker()
+                .set_name(module, &path.display().to_string());
+            Ok(Shader {
+                context,
+                module,
+                stage_flags,
+                path,
+                includes: code.includes,
+                include_graph: code.include_graph,
+                compile_time: code.compile_time,
+                text: code.text,
+                reflection: ShaderReflection::from_spirv(&code.words, stage_flags),
+            })
         }
+    }
+
+    // Compiles GLSL embedded in the binary (no includes). The shader has no file dependencies
+    // and its path is left empty.
+    pub fn from_source(
+        context: Arc<Context>,
+        source: &str,
+        name: &str,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Result<Self> {
+        let mut options = CompileOptions::new().unwrap();
+        options.set_target_spirv(shaderc::SpirvVersion::V1_4);
+        options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2 as u32);
+        let sc_stage = get_shaderc_stage(&stage_flags).unwrap();
+        let code = context.compiler_pool().with_compiler(|compiler| {
+            compiler
+                .compile_into_spirv(source, sc_stage, name, "main", Some(&options))
+                .map_err(|e| Error::ShaderCompilation {
+                    name: name.to_string(),
+                    log: e.to_string(),
+                })
+        })?;
         let shader_info = vk::ShaderModuleCreateInfo::builder().code(code.as_binary());
         unsafe {
-            let module = context
-                .device()
-                .create_shader_module(&shader_info, None)
-                .unwrap();
-            Shader {
+            let module = context.device().create_shader_module(&shader_info, None)?;
+            context.debug_marker().set_name(module, name);
+            Ok(Shader {
                 context,
                 module,
                 stage_flags,
-                path,
-                text: Some(source),
-            }
+                path: PathBuf::new(),
+                includes: Vec::new(),
+                include_graph: Vec::new(),
+                compile_time: SystemTime::now(),
+                text: Some(source.to_string()),
+                reflection: ShaderReflection::from_spirv(code.as_binary(), stage_flags),
+            })
         }
     }
 
+    // Source file followed by every file it (transitively) included.
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        let mut dependencies = Vec::new();
+        if !self.path.as_os_str().is_empty() {
+            dependencies.push(self.path.clone());
+        }
+        dependencies.extend(self.includes.iter().cloned());
+        dependencies
+    }
+
+    pub fn include_graph(&self) -> &[(PathBuf, PathBuf)] {
+        &self.include_graph
+    }
+
+    // Descriptor bindings and push constants of the module, see ReflectedLayout.
+    pub fn reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        is_modified_since(&self.dependencies(), self.compile_time)
+    }
+
     pub fn get_create_info(&self, name: &'_ std::ffi::CStr) -> vk::PipelineShaderStageCreateInfo {
         vk::PipelineShaderStageCreateInfo::builder()
             .module(self.module)
@@ -199,9 +507,32 @@ impl Drop for Shader {
     }
 }
 
+pub(crate) fn collect_dependencies(shaders: &[Shader]) -> Vec<PathBuf> {
+    let mut dependencies: Vec<PathBuf> = shaders.iter().flat_map(|s| s.dependencies()).collect();
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+fn clamp_line_width(context: &Context, line_width: f32) -> f32 {
+    match context.supports_wide_lines() {
+        true => {
+            let range = context.limits().line_width_range;
+            line_width.clamp(range[0], range[1])
+        }
+        false => 1.0,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PipelineBlendMode {
     Opaque,
+    // Straight alpha: the color is weighted by the source alpha.
     Alpha,
+    // The color is added as is, e.g. for particles and light accumulation.
+    Additive,
+    // The color was already multiplied by the source alpha in the shader.
+    Premultiplied,
 }
 
 impl Default for PipelineBlendMode {
@@ -209,20 +540,84 @@ impl Default for PipelineBlendMode {
         PipelineBlendMode::Opaque
     }
 }
+
+impl PipelineBlendMode {
+    pub fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            PipelineBlendMode::Opaque => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+            ),
+            PipelineBlendMode::Alpha => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            PipelineBlendMode::Additive => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE,
+            ),
+            PipelineBlendMode::Premultiplied => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+        };
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: (self != PipelineBlendMode::Opaque) as vk::Bool32,
+            src_color_blend_factor: src_color,
+            dst_color_blend_factor: dst_color,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: src_alpha,
+            dst_alpha_blend_factor: dst_alpha,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+}
+
 pub struct PipelineInfo {
     pub layout: vk::PipelineLayout,
     pub render_pass: Option<vk::RenderPass>,
     pub transient_render_pass_info: Option<TransientRenderPassInfo>,
+    // Dynamic rendering attachments, used instead of a render pass. See RenderingInfo.
+    pub rendering_formats: Option<RenderingFormats>,
     pub shaders: Vec<(PathBuf, vk::ShaderStageFlags)>,
     pub name: String,
     pub depth_test_enabled: bool,
     pub depth_write_enabled: bool,
+    // Blend mode of every color attachment, unless overridden in attachment_blend_modes.
     pub blend_mode: PipelineBlendMode,
+    // Per color attachment index overrides of blend_mode.
+    pub attachment_blend_modes: Vec<(u32, PipelineBlendMode)>,
+    // Must match the color attachments of the subpass, set by render_pass_info.
+    pub color_attachment_count: u32,
     pub cull_mode: vk::CullModeFlags,
     pub front_face: vk::FrontFace,
+    pub topology: vk::PrimitiveTopology,
+    // Falls back to FILL when the device doesn't support wireframe, see supports_wireframe.
+    pub polygon_mode: vk::PolygonMode,
+    // Ignored with dynamic_line_width, clamped to 1.0 without wide lines support.
+    pub line_width: f32,
+    // The line width is then set with Pipeline::cmd_set_line_width before drawing.
+    pub dynamic_line_width: bool,
     pub vertex_stride: u32,
     pub vertex_format_offset: Vec<(vk::Format, u32)>,
+    // Vertices generated in the vertex shader from gl_VertexIndex, without vertex buffer.
+    pub no_vertex_input: bool,
+    // Task and mesh shaders instead of the vertex stages, without vertex input nor input
+    // assembly state. Requires RendererSettings::mesh_shader, see Context::cmd_draw_mesh_tasks.
+    pub mesh_shading: bool,
     pub samples: vk::SampleCountFlags,
+    // Minimum fraction of the samples shaded individually, None to shade once per pixel.
+    // Ignored without sample rate shading support.
+    pub min_sample_shading: Option<f32>,
     pub specialization_data: Vec<u8>,
     pub specialization_entries: Vec<vk::SpecializationMapEntry>,
 }
@@ -233,16 +628,26 @@ impl Default for PipelineInfo {
             layout: vk::PipelineLayout::default(),
             render_pass: None,
             transient_render_pass_info: None,
+            rendering_formats: None,
             shaders: Vec::new(),
             name: "".to_string(),
             depth_test_enabled: true,
             depth_write_enabled: true,
             blend_mode: PipelineBlendMode::default(),
+            attachment_blend_modes: Vec::new(),
+            color_attachment_count: 1,
             cull_mode: vk::CullModeFlags::BACK,
             front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            line_width: 1.0,
+            dynamic_line_width: false,
             vertex_stride: 0,
             vertex_format_offset: Vec::new(),
+            no_vertex_input: false,
+            mesh_shading: false,
             samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
             specialization_data: Vec::new(),
             specialization_entries: Vec::new(),
         }
@@ -260,13 +665,24 @@ impl PipelineInfo {
     }
     pub fn render_pass_info(mut self, info: TransientRenderPassInfo) -> Self {
         self.samples = info.samples;
+        self.color_attachment_count = info.color_formats.len() as u32;
         self.transient_render_pass_info = Some(info);
         self
     }
+    pub fn rendering_formats(mut self, formats: RenderingFormats) -> Self {
+        self.samples = formats.samples;
+        self.color_attachment_count = formats.color_formats.len() as u32;
+        self.rendering_formats = Some(formats);
+        self
+    }
     pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
         self.samples = samples;
         self
     }
+    pub fn sample_shading(mut self, min_sample_shading: f32) -> Self {
+        self.min_sample_shading = Some(min_sample_shading);
+        self
+    }
     pub fn shader(mut self, path: PathBuf, stage_flags: vk::ShaderStageFlags) -> Self {
         self.shaders.push((path, stage_flags));
         self
@@ -279,6 +695,28 @@ impl PipelineInfo {
         self.shaders.push((path, vk::ShaderStageFlags::FRAGMENT));
         self
     }
+    // Optional task shader, e.g. culling meshlets before the mesh shader workgroups.
+    pub fn task(mut self, path: PathBuf) -> Self {
+        self.shaders.push((path, SHADER_STAGE_TASK_EXT));
+        self
+    }
+    pub fn mesh(mut self, path: PathBuf) -> Self {
+        self.mesh_shading = true;
+        self.shaders.push((path, SHADER_STAGE_MESH_EXT));
+        self.no_vertex_input()
+    }
+    pub fn blend_mode(mut self, blend_mode: PipelineBlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+    pub fn attachment_blend_mode(mut self, attachment: u32, blend_mode: PipelineBlendMode) -> Self {
+        self.attachment_blend_modes.push((attachment, blend_mode));
+        self
+    }
+    pub fn color_attachment_count(mut self, count: u32) -> Self {
+        self.color_attachment_count = count;
+        self
+    }
     pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
         self.cull_mode = cull_mode;
         self
@@ -287,6 +725,25 @@ impl PipelineInfo {
         self.front_face = front_face;
         self
     }
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+    pub fn wireframe(self) -> Self {
+        self.polygon_mode(vk::PolygonMode::LINE)
+    }
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+    pub fn dynamic_line_width(mut self) -> Self {
+        self.dynamic_line_width = true;
+        self
+    }
     pub fn name(mut self, name: String) -> Self {
         self.name = name.to_string();
         self
@@ -299,6 +756,21 @@ impl PipelineInfo {
         self.vertex_format_offset = T::format_offset();
         self
     }
+    pub fn no_vertex_input(mut self) -> Self {
+        self.no_vertex_input = true;
+        self.vertex_stride = 0;
+        self.vertex_format_offset.clear();
+        self
+    }
+    // Fullscreen pass with the engine fullscreen.vert, drawn with cmd_draw(cmd, 3, 1, 0, 0).
+    // The fragment shader receives the uv at location 0, (0, 0) being the top left.
+    pub fn fullscreen(mut self) -> Self {
+        self.depth_test_enabled = false;
+        self.depth_write_enabled = false;
+        self.no_vertex_input()
+            .cull_mode(vk::CullModeFlags::NONE)
+            .vert(Path::new(ENGINE_SHADER_DIR).join("fullscreen.vert"))
+    }
     pub fn specialization<T>(mut self, data: &T, constant_id: u32) -> Self {
         let slice = unsafe {
             std::slice::from_raw_parts(data as *const T as *const u8, std::mem::size_of_val(data))
@@ -320,38 +792,80 @@ pub struct Pipeline {
     info: PipelineInfo,
     pipeline: vk::Pipeline,
     transient_render_pass: Option<RenderPass>,
+    dependencies: Vec<PathBuf>,
+    build_time: SystemTime,
 }
 
 impl Pipeline {
-    pub fn new(context: Arc<Context>, info: PipelineInfo) -> Self {
-        assert!(info.vertex_stride > 0);
-        assert!(!info.vertex_format_offset.is_empty());
-        assert!(info.render_pass.is_some() || info.transient_render_pass_info.is_some());
+    pub fn new(context: Arc<Context>, info: PipelineInfo) -> Result<Self> {
+        let shaders = compile_shaders(&context, &info.shaders)?;
+        Self::with_shaders(context, info, shaders)
+    }
+
+    // Compiles the shaders of all the pipelines concurrently, e.g. to warm up every pipeline of
+    // an application at startup. Fails with the first error, in the order of `infos`.
+    pub fn new_batch(context: Arc<Context>, infos: Vec<PipelineInfo>) -> Result<Vec<Self>> {
+        let stages = infos
+            .iter()
+            .flat_map(|info| info.shaders.iter().cloned())
+            .collect::<Vec<_>>();
+        let mut shaders = compile_shaders(&context, &stages)?.into_iter();
+        infos
+            .into_iter()
+            .map(|info| {
+                let pipeline_shaders = shaders.by_ref().take(info.shaders.len()).collect();
+                Self::with_shaders(context.clone(), info, pipeline_shaders)
+            })
+            .collect()
+    }
 
-        let mut shaders = Vec::<Shader>::new();
+    fn with_shaders(context: Arc<Context>, info: PipelineInfo, shaders: Vec<Shader>) -> Result<Self> {
+        assert!(info.no_vertex_input || info.vertex_stride > 0);
+        assert!(info.no_vertex_input || !info.vertex_format_offset.is_empty());
+        assert!(
+            info.render_pass.is_some()
+                || info.transient_render_pass_info.is_some()
+                || info.rendering_formats.is_some()
+        );
+        if info.render_pass.is_none()
+            && info.rendering_formats.is_some()
+            && !context.supports_dynamic_rendering()
+        {
+            eprintln!("Dynamic rendering unsupported for pipeline {}.", info.name);
+            return Err(Error::Vulkan(vk::Result::ERROR_FEATURE_NOT_PRESENT));
+        }
+        if info.mesh_shading && !context.supports_mesh_shader() {
+            eprintln!("Mesh shaders unsupported for pipeline {}.", info.name);
+            return Err(Error::Vulkan(vk::Result::ERROR_FEATURE_NOT_PRESENT));
+        }
+
+        let build_time = SystemTime::now();
         let mut shader_stage_create_infos = Vec::new();
         let shader_entry_name = CString::new("main").unwrap();
-        for shader_info in &info.shaders {
-            let shader = Shader::new(context.clone(), shader_info.0.clone(), shader_info.1);
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&info.specialization_entries)
+            .data(&info.specialization_data)
+            .build();
+        for shader in &shaders {
             if info.specialization_entries.is_empty() {
                 shader_stage_create_infos.push(shader.get_create_info(&shader_entry_name));
             } else {
                 shader_stage_create_infos.push(
                     shader.get_create_info_with_specialization(
                         &shader_entry_name,
-                        &vk::SpecializationInfo::builder()
-                            .map_entries(&info.specialization_entries)
-                            .data(&info.specialization_data),
+                        &specialization_info,
                     ),
                 );
             }
-            shaders.push(shader);
         }
-        let vertex_input_binding_descriptions = [vk::VertexInputBindingDescription {
-            binding: 0,
-            stride: info.vertex_stride,
-            input_rate: vk::VertexInputRate::VERTEX,
-        }];
+        let vertex_input_binding_descriptions = match info.no_vertex_input {
+            true => Vec::new(),
+            false => vec![vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: info.vertex_stride,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }],
+        };
         let mut vertex_input_attribute_descriptions = Vec::new();
         for (i, format_pair) in info.vertex_format_offset.iter().enumerate() {
             vertex_input_attribute_descriptions.push(vk::VertexInputAttributeDescription {
@@ -369,7 +883,7 @@ impl Pipeline {
             ..Default::default()
         };
         let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            topology: info.topology,
             ..Default::default()
         };
 
@@ -379,15 +893,38 @@ impl Pipeline {
             ..Default::default()
         };
 
+        let polygon_mode = match info.polygon_mode {
+            vk::PolygonMode::FILL => vk::PolygonMode::FILL,
+            _ if !context.supports_wireframe() => {
+                println!(
+                    "Polygon mode {:?} unsupported for pipeline {}, using FILL.",
+                    info.polygon_mode, info.name
+                );
+                vk::PolygonMode::FILL
+            }
+            polygon_mode => polygon_mode,
+        };
         let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
             front_face: info.front_face,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
+            line_width: clamp_line_width(&context, info.line_width),
+            polygon_mode,
             cull_mode: info.cull_mode,
             ..Default::default()
         };
+        let min_sample_shading = match info.min_sample_shading {
+            Some(_) if !context.supports_sample_rate_shading() => {
+                println!(
+                    "Sample rate shading unsupported for pipeline {}, shading per pixel.",
+                    info.name
+                );
+                None
+            }
+            min_sample_shading => min_sample_shading,
+        };
         let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
             rasterization_samples: info.samples,
+            sample_shading_enable: min_sample_shading.is_some() as u32,
+            min_sample_shading: min_sample_shading.unwrap_or(0.0).clamp(0.0, 1.0),
             ..Default::default()
         };
         let noop_stencil_state = vk::StencilOpState {
@@ -407,40 +944,50 @@ impl Pipeline {
             ..Default::default()
         };
 
-        //TODO: Implement blending modes
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::RGBA,
-        }];
+        let color_blend_attachment_states: Vec<_> = (0..info.color_attachment_count)
+            .map(|attachment| {
+                let blend_mode = info
+                    .attachment_blend_modes
+                    .iter()
+                    .rev()
+                    .find(|(index, _)| *index == attachment)
+                    .map_or(info.blend_mode, |(_, blend_mode)| *blend_mode);
+                blend_mode.attachment_state()
+            })
+            .collect();
         let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op(vk::LogicOp::CLEAR)
             .attachments(&color_blend_attachment_states);
 
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let mut dynamic_state = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if info.dynamic_line_width {
+            dynamic_state.push(vk::DynamicState::LINE_WIDTH);
+        }
         let dynamic_state_info =
             vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
 
-        let transient_render_pass = match info.transient_render_pass_info.clone() {
-            Some(render_pass_info) => Some(RenderPass::new_transient(
+        // Dynamic rendering pipelines need no transient render pass.
+        let transient_render_pass = match (
+            info.transient_render_pass_info.clone(),
+            &info.rendering_formats,
+        ) {
+            (Some(render_pass_info), None) => Some(RenderPass::new_transient(
                 context.shared().clone(),
                 render_pass_info,
             )),
-            None => None,
+            _ => None,
         };
-        let render_pass = match info.render_pass {
-            Some(render_pass) => render_pass,
-            None => transient_render_pass.as_ref().unwrap().handle(),
+        let render_pass = match (info.render_pass, &transient_render_pass) {
+            (Some(render_pass), _) => render_pass,
+            (None, Some(transient_render_pass)) => transient_render_pass.handle(),
+            (None, None) => vk::RenderPass::null(),
         };
-        let create_infos = [vk::GraphicsPipelineCreateInfo::builder()
+        let mut rendering_create_info = info
+            .rendering_formats
+            .as_ref()
+            .map(|formats| formats.pipeline_create_info());
+        let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stage_create_infos)
-            .vertex_input_state(&vertex_input_state_info)
-            .input_assembly_state(&vertex_input_assembly_state_info)
             .viewport_state(&viewport_state_info)
             .rasterization_state(&rasterization_info)
             .multisample_state(&multisample_state_info)
@@ -448,24 +995,57 @@ impl Pipeline {
             .color_blend_state(&color_blend_state)
             .dynamic_state(&dynamic_state_info)
             .layout(info.layout)
-            .render_pass(render_pass)
-            .build()];
+            .render_pass(render_pass);
+        // Mesh shaders output the primitives, without vertex input nor input assembly.
+        if !info.mesh_shading {
+            create_info = create_info
+                .vertex_input_state(&vertex_input_state_info)
+                .input_assembly_state(&vertex_input_assembly_state_info);
+        }
+        if let Some(rendering_create_info) = &mut rendering_create_info {
+            create_info = create_info.push_next(rendering_create_info);
+        }
+        let create_infos = [create_info.build()];
 
         let graphics_pipelines = unsafe {
             context
                 .device()
                 .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
-                .expect("Unable to create graphics pipeline")
+                .map_err(|(_, result)| result)?
         };
+        context
+            .debug_marker()
+            .set_name(graphics_pipelines[0], &info.name);
 
-        Pipeline {
+        let dependencies = collect_dependencies(&shaders);
+        Ok(Pipeline {
             context,
             info,
             pipeline: graphics_pipelines[0],
             transient_render_pass,
+            dependencies,
+            build_time,
+        })
+    }
+
+    pub fn dependencies(&self) -> &[PathBuf] {
+        &self.dependencies
+    }
+
+    // Only for pipelines built with dynamic_line_width.
+    pub fn cmd_set_line_width(&self, cmd: vk::CommandBuffer, line_width: f32) {
+        unsafe {
+            self.context
+                .device()
+                .cmd_set_line_width(cmd, clamp_line_width(&self.context, line_width));
         }
     }
 
+    // True when any shader source or include changed since the pipeline was built.
+    pub fn is_outdated(&self) -> bool {
+        is_modified_since(&self.dependencies, self.build_time)
+    }
+
     pub fn update_specialization<T>(&mut self, data: &T) {
         let slice = unsafe {
             std::slice::from_raw_parts(data as *const T as *const u8, std::mem::size_of_val(data))
@@ -0,0 +1,108 @@
+use crate::{Context, Result, SamplerInfo, Texture2d};
+use std::sync::Arc;
+
+// Magenta and black, the usual "missing texture" pattern. Used for glTF textures which fail to
+// load.
+pub const MISSING_TEXTURE_COLORS: [[u8; 4]; 2] = [[255, 0, 255, 255], [0, 0, 0, 255]];
+// Cells per side of the generated checkerboards and grids.
+const PLACEHOLDER_CELLS: u32 = 8;
+
+// RGBA8 texel generators, rows in upload order (see Texture2d::from_rgba8). Useful as material
+// fallbacks and as test data which doesn't depend on files on disk.
+pub fn solid_rgba8(size: (u32, u32), color: [u8; 4]) -> Vec<u8> {
+    color.repeat((size.0 * size.1) as usize)
+}
+
+// `cells` squares per side, alternating between the two colors.
+pub fn checkerboard_rgba8(size: u32, cells: u32, colors: [[u8; 4]; 2]) -> Vec<u8> {
+    let cell_size = (size / cells.max(1)).max(1);
+    generate_rgba8((size, size), |x, y| {
+        colors[((x / cell_size + y / cell_size) % 2) as usize]
+    })
+}
+
+// Horizontal linear gradient, `from` in the first column and `to` in the last.
+pub fn gradient_rgba8(size: (u32, u32), from: [u8; 4], to: [u8; 4]) -> Vec<u8> {
+    let last = (size.0.max(2) - 1) as f32;
+    generate_rgba8(size, |x, _| {
+        let t = x as f32 / last;
+        let mut color = [0; 4];
+        for (i, value) in color.iter_mut().enumerate() {
+            *value = (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t).round() as u8;
+        }
+        color
+    })
+}
+
+// UV test grid: red and green encode u and v, with white lines every 1 / `cells`, to check
+// texture coordinates and their orientation.
+pub fn uv_grid_rgba8(size: u32, cells: u32) -> Vec<u8> {
+    let cell_size = (size / cells.max(1)).max(1);
+    let scale = 255.0 / (size.max(2) - 1) as f32;
+    generate_rgba8((size, size), |x, y| {
+        if x % cell_size == 0 || y % cell_size == 0 || x == size - 1 || y == size - 1 {
+            [255, 255, 255, 255]
+        } else {
+            [(x as f32 * scale) as u8, (y as f32 * scale) as u8, 0, 255]
+        }
+    })
+}
+
+fn generate_rgba8(size: (u32, u32), texel: impl Fn(u32, u32) -> [u8; 4]) -> Vec<u8> {
+    let mut data = Vec::with_capacity((size.0 * size.1 * 4) as usize);
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            data.extend_from_slice(&texel(x, y));
+        }
+    }
+    data
+}
+
+impl Texture2d {
+    // Single texel texture, e.g. a white base color or flat normal map fallback.
+    pub fn solid(context: Arc<Context>, color: [u8; 4]) -> Result<Self> {
+        let name = format!("Solid {:?}", color);
+        Self::from_rgba8(
+            context,
+            &name,
+            (1, 1),
+            &solid_rgba8((1, 1), color),
+            SamplerInfo::default(),
+        )
+    }
+
+    pub fn checkerboard(context: Arc<Context>, size: u32, colors: [[u8; 4]; 2]) -> Result<Self> {
+        Self::from_rgba8(
+            context,
+            "Checkerboard",
+            (size, size),
+            &checkerboard_rgba8(size, PLACEHOLDER_CELLS, colors),
+            SamplerInfo::default(),
+        )
+    }
+
+    pub fn gradient(
+        context: Arc<Context>,
+        size: (u32, u32),
+        from: [u8; 4],
+        to: [u8; 4],
+    ) -> Result<Self> {
+        Self::from_rgba8(
+            context,
+            "Gradient",
+            size,
+            &gradient_rgba8(size, from, to),
+            SamplerInfo::default(),
+        )
+    }
+
+    pub fn uv_grid(context: Arc<Context>, size: u32) -> Result<Self> {
+        Self::from_rgba8(
+            context,
+            "UV grid",
+            (size, size),
+            &uv_grid_rgba8(size, PLACEHOLDER_CELLS),
+            SamplerInfo::default(),
+        )
+    }
+}
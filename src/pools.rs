@@ -1,7 +1,6 @@
 use crate::{Resource, SharedContext};
 use ash::{vk};
-use std::cell::{Cell, RefCell};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 // Based on: https://github.com/KhronosGroup/Vulkan-Samples/blob/master/framework/semaphore_pool.h
 pub struct SemaphorePool {
@@ -59,15 +58,38 @@ impl Drop for SemaphorePool {
     }
 }
 
+struct PooledCommandBuffers {
+    command_buffers: Vec<vk::CommandBuffer>,
+    active_count: usize,
+}
+
+// The lock also guards the pool, which Vulkan requires to be externally synchronized.
 pub struct CommandPool {
     context: Arc<SharedContext>,
     pool: vk::CommandPool,
-    command_buffers: RefCell<Vec<vk::CommandBuffer>>,
-    active_count: Cell<usize>,
+    level: vk::CommandBufferLevel,
+    pooled: Mutex<PooledCommandBuffers>,
 }
 
 impl CommandPool {
     pub fn new(context: Arc<SharedContext>, queue_family_index: u32) -> Self {
+        Self::with_level(context, queue_family_index, vk::CommandBufferLevel::PRIMARY)
+    }
+
+    // Pool of SECONDARY command buffers, e.g. for draws recorded on worker threads.
+    pub fn new_secondary(context: Arc<SharedContext>, queue_family_index: u32) -> Self {
+        Self::with_level(
+            context,
+            queue_family_index,
+            vk::CommandBufferLevel::SECONDARY,
+        )
+    }
+
+    fn with_level(
+        context: Arc<SharedContext>,
+        queue_family_index: u32,
+        level: vk::CommandBufferLevel,
+    ) -> Self {
         let pool_create_info = vk::CommandPoolCreateInfo::builder()
             .flags(vk::CommandPoolCreateFlags::TRANSIENT)
             .queue_family_index(queue_family_index);
@@ -79,45 +101,47 @@ impl CommandPool {
             CommandPool {
                 context,
                 pool,
-                command_buffers: RefCell::new(Vec::new()),
-                active_count: Cell::new(0),
+                level,
+                pooled: Mutex::new(PooledCommandBuffers {
+                    command_buffers: Vec::new(),
+                    active_count: 0,
+                }),
             }
         }
     }
 
     pub fn reset(&self) {
+        let mut pooled = self.pooled.lock().unwrap();
         unsafe {
             self.context
                 .device()
                 .reset_command_pool(self.pool, vk::CommandPoolResetFlags::default())
                 .expect("Reset command buffer failed.");
 
-            self.active_count.set(0);
+            pooled.active_count = 0;
         }
     }
 
     pub fn request_command_buffer(&self) -> vk::CommandBuffer {
-        let mut buffers = self.command_buffers.try_borrow_mut().unwrap();
-        if self.active_count.get() < buffers.len() {
-            let index = self.active_count.get();
-            self.active_count.set(index + 1);
-            return buffers[index];
-        } else {
-            unsafe {
-                let create_info = vk::CommandBufferAllocateInfo::builder()
-                    .command_buffer_count(1)
-                    .command_pool(self.pool)
-                    .level(vk::CommandBufferLevel::PRIMARY);
-                let command_buffer = self
-                    .context
-                    .device()
-                    .allocate_command_buffers(&create_info)
-                    .unwrap()[0];
-
-                buffers.push(command_buffer.clone());
-                return command_buffer;
-            }
+        let mut pooled = self.pooled.lock().unwrap();
+        if pooled.active_count < pooled.command_buffers.len() {
+            let index = pooled.active_count;
+            pooled.active_count = index + 1;
+            return pooled.command_buffers[index];
         }
+        let create_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(self.pool)
+            .level(self.level);
+        let command_buffer = unsafe {
+            self.context
+                .device()
+                .allocate_command_buffers(&create_info)
+                .unwrap()[0]
+        };
+        pooled.command_buffers.push(command_buffer);
+        pooled.active_count += 1;
+        command_buffer
     }
 }
 
@@ -130,7 +154,7 @@ impl Resource<vk::CommandPool> for CommandPool {
 impl Drop for CommandPool {
     fn drop(&mut self) {
         unsafe {
-            self.command_buffers.get_mut().clear();
+            self.pooled.get_mut().unwrap().command_buffers.clear();
             self.context.device().destroy_command_pool(self.pool, None);
         }
     }
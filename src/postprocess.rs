@@ -0,0 +1,420 @@
+use crate::{
+    Context, DescriptorSetInfo, DescriptorSetLayout, DescriptorSetLayoutInfo, GpuPhase, Image2d,
+    Resource, Result, Shader, ENGINE_SHADER_DIR,
+};
+use ash::vk;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Format of the chain input and intermediate targets.
+pub const POST_PROCESS_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+// Tonemapping operators of the built-in tonemap pass, see postprocess.glsl.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemap {
+    #[default]
+    Aces,
+    Reinhard,
+    Uncharted2,
+}
+
+// OPERATION specialization constants of postprocess.comp.
+const OPERATION_EXPOSURE: u32 = 0;
+const OPERATION_GAMMA: u32 = 4;
+
+impl Tonemap {
+    fn operation(self) -> u32 {
+        match self {
+            Tonemap::Aces => 1,
+            Tonemap::Reinhard => 2,
+            Tonemap::Uncharted2 => 3,
+        }
+    }
+}
+
+// Compute pass of a PostProcessChain. The shader reads the rgba32f `inputImage` storage image at
+// binding 0 of set 0 and writes the rgba32f `outputImage` at binding 1, both in GENERAL layout,
+// with 16x16 workgroups. `params` is pushed as a vec4 push constant, see postprocess.comp.
+pub struct PostProcessPass {
+    name: String,
+    shader: Shader,
+    specialization: Option<u32>,
+    pipeline: vk::Pipeline,
+    pub params: glam::Vec4,
+    // Disabled passes are skipped, the next pass reading the previous output.
+    pub enabled: bool,
+}
+
+impl PostProcessPass {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Sequence of compute passes over full resolution images, e.g. to tonemap an HDR path tracing
+// accumulation before blitting it to the swapchain. Each pass writes to one of two ping-pong
+// targets, the last one holding the result.
+pub struct PostProcessChain {
+    context: Arc<Context>,
+    layout: DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    passes: Vec<PostProcessPass>,
+    targets: [Image2d; 2],
+}
+
+fn create_target(context: &Arc<Context>, extent: vk::Extent2D, name: &str) -> Result<Image2d> {
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(POST_PROCESS_FORMAT)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    Image2d::new(
+        context.shared().clone(),
+        &image_info,
+        vk::ImageAspectFlags::COLOR,
+        1,
+        name,
+    )
+}
+
+fn create_targets(context: &Arc<Context>, extent: vk::Extent2D) -> Result<[Image2d; 2]> {
+    Ok([
+        create_target(context, extent, "PostProcessTarget0")?,
+        create_target(context, extent, "PostProcessTarget1")?,
+    ])
+}
+
+impl PostProcessChain {
+    // Empty chain, for inputs of `extent`.
+    pub fn new(context: Arc<Context>, extent: vk::Extent2D) -> Result<Self> {
+        let layout = DescriptorSetLayout::new(
+            context.clone(),
+            DescriptorSetLayoutInfo::default()
+                .binding(
+                    0,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    vk::ShaderStageFlags::COMPUTE,
+                )
+                .binding(
+                    1,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    vk::ShaderStageFlags::COMPUTE,
+                ),
+        );
+        let set_layouts = [layout.handle()];
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(std::mem::size_of::<glam::Vec4>() as u32)
+            .build()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            context
+                .device()
+                .create_pipeline_layout(&layout_info, None)?
+        };
+        let targets = create_targets(&context, extent)?;
+        Ok(PostProcessChain {
+            context,
+            layout,
+            pipeline_layout,
+            passes: Vec::new(),
+            targets,
+        })
+    }
+
+    // Recreates the targets, e.g. along with the input after a window resize. The previous
+    // targets must not be in use by frames in flight.
+    pub fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        self.targets = create_targets(&self.context, extent)?;
+        Ok(())
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.targets[0].get_extent()
+    }
+
+    fn create_pipeline(
+        &self,
+        shader: &Shader,
+        specialization: Option<u32>,
+    ) -> Result<vk::Pipeline> {
+        let entry_name = CString::new("main").unwrap();
+        let map_entries = [vk::SpecializationMapEntry::builder()
+            .constant_id(0)
+            .size(std::mem::size_of::<u32>())
+            .build()];
+        let data = specialization.unwrap_or_default().to_ne_bytes();
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&map_entries)
+            .data(&data);
+        let stage = match specialization {
+            Some(_) => {
+                shader.get_create_info_with_specialization(&entry_name, &specialization_info)
+            }
+            None => shader.get_create_info(&entry_name),
+        };
+        let create_infos = [vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(self.pipeline_layout)
+            .build()];
+        let pipelines = unsafe {
+            self.context
+                .device()
+                .create_compute_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                .map_err(|(_, result)| result)?
+        };
+        self.context
+            .debug_marker()
+            .set_name(pipelines[0], &shader.path.display().to_string());
+        Ok(pipelines[0])
+    }
+
+    fn push_pass(
+        &mut self,
+        name: &str,
+        path: PathBuf,
+        specialization: Option<u32>,
+        params: glam::Vec4,
+    ) -> Result<usize> {
+        let shader = Shader::new(self.context.clone(), path, vk::ShaderStageFlags::COMPUTE)?;
+        let pipeline = self.create_pipeline(&shader, specialization)?;
+        self.passes.push(PostProcessPass {
+            name: name.to_string(),
+            shader,
+            specialization,
+            pipeline,
+            params,
+            enabled: true,
+        });
+        Ok(self.passes.len() - 1)
+    }
+
+    // Appends a pass running the compute shader at `path`, see PostProcessPass for its
+    // interface. Returns the pass index.
+    pub fn add_pass(&mut self, name: &str, path: PathBuf, params: glam::Vec4) -> Result<usize> {
+        self.push_pass(name, path, None, params)
+    }
+
+    fn add_builtin(&mut self, name: &str, operation: u32, params: glam::Vec4) -> Result<usize> {
+        let path = Path::new(ENGINE_SHADER_DIR).join("postprocess.comp");
+        self.push_pass(name, path, Some(operation), params)
+    }
+
+    // Scales the colors by 2^stops, stops being params.x.
+    pub fn add_exposure(&mut self, stops: f32) -> Result<usize> {
+        self.add_builtin(
+            "exposure",
+            OPERATION_EXPOSURE,
+            glam::vec4(stops, 0.0, 0.0, 0.0),
+        )
+    }
+
+    pub fn add_tonemap(&mut self, tonemap: Tonemap) -> Result<usize> {
+        self.add_builtin("tonemap", tonemap.operation(), glam::Vec4::ZERO)
+    }
+
+    // Encodes the colors with 1 / gamma, gamma being params.x. Not needed when the result is
+    // blitted to an sRGB swapchain.
+    pub fn add_gamma(&mut self, gamma: f32) -> Result<usize> {
+        self.add_builtin("gamma", OPERATION_GAMMA, glam::vec4(gamma, 0.0, 0.0, 0.0))
+    }
+
+    pub fn passes(&self) -> &[PostProcessPass] {
+        &self.passes
+    }
+
+    pub fn pass(&self, index: usize) -> &PostProcessPass {
+        &self.passes[index]
+    }
+
+    pub fn pass_mut(&mut self, index: usize) -> &mut PostProcessPass {
+        &mut self.passes[index]
+    }
+
+    // True when any pass shader or include changed since it was compiled.
+    pub fn is_outdated(&self) -> bool {
+        self.passes.iter().any(|pass| pass.shader.is_outdated())
+    }
+
+    // Recompiles every pass. On error the previous pipelines are kept. Frames in flight must
+    // have completed.
+    pub fn reload(&mut self) -> Result<()> {
+        let mut rebuilt = Vec::new();
+        for pass in &self.passes {
+            let shader = Shader::new(
+                self.context.clone(),
+                pass.shader.path.clone(),
+                vk::ShaderStageFlags::COMPUTE,
+            );
+            let pipeline = shader.and_then(|shader| {
+                let pipeline = self.create_pipeline(&shader, pass.specialization)?;
+                Ok((shader, pipeline))
+            });
+            match pipeline {
+                Ok(pipeline) => rebuilt.push(pipeline),
+                Err(error) => {
+                    for (_, pipeline) in rebuilt {
+                        unsafe { self.context.device().destroy_pipeline(pipeline, None) };
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        for (pass, (shader, pipeline)) in self.passes.iter_mut().zip(rebuilt) {
+            unsafe { self.context.device().destroy_pipeline(pass.pipeline, None) };
+            pass.shader = shader;
+            pass.pipeline = pipeline;
+        }
+        Ok(())
+    }
+
+    // Records the enabled passes over `input`, a POST_PROCESS_FORMAT storage image of the chain
+    // extent in GENERAL layout, written by previous shader commands. Returns the target holding
+    // the result in GENERAL layout, or None when no pass is enabled.
+    pub fn cmd_run(&mut self, cmd: vk::CommandBuffer, input: &Image2d) -> Option<&mut Image2d> {
+        assert_eq!(input.get_format(), POST_PROCESS_FORMAT);
+        assert_eq!(input.get_extent(), self.get_extent());
+        let enabled = self
+            .passes
+            .iter()
+            .filter(|pass| pass.enabled)
+            .collect::<Vec<_>>();
+        if enabled.is_empty() {
+            return None;
+        }
+        self.context.begin_gpu_phase(cmd, GpuPhase::Post);
+        let device = self.context.device();
+        let extent = self.get_extent();
+        let mut source = input.get_descriptor_info();
+        let mut source_image = (input.handle(), input.get_name().to_string());
+        for (index, pass) in enabled.iter().enumerate() {
+            let target = &mut self.targets[index % 2];
+            let old_layout = target.get_layout();
+            self.context.track_hazards(|tracker| {
+                let (image, name) = (source_image.0, source_image.1.as_str());
+                tracker.barrier(
+                    image,
+                    name,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                    None,
+                );
+                tracker.expect_layout(image, name, vk::ImageLayout::GENERAL);
+                tracker.read(
+                    image,
+                    name,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                );
+                let (image, name) = (target.handle(), target.get_name());
+                tracker.barrier(
+                    image,
+                    name,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::empty(),
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                    Some((old_layout, vk::ImageLayout::GENERAL)),
+                );
+                tracker.write(
+                    image,
+                    name,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                );
+            });
+            let memory_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            // The previous content of the target is overwritten.
+            let target_barrier = vk::ImageMemoryBarrier::builder()
+                .image(target.handle())
+                .old_layout(old_layout)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .level_count(1)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build();
+            target.set_layout(vk::ImageLayout::GENERAL);
+            let desc_set = self.layout.get_or_create(
+                DescriptorSetInfo::default()
+                    .image(0, source)
+                    .image(1, target.get_descriptor_info()),
+            );
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[memory_barrier],
+                    &[],
+                    &[target_barrier],
+                );
+                device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pass.pipeline);
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[desc_set.handle()],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    cmd,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        &pass.params as *const glam::Vec4 as *const u8,
+                        std::mem::size_of::<glam::Vec4>(),
+                    ),
+                );
+                device.cmd_dispatch(
+                    cmd,
+                    extent.width.div_ceil(WORKGROUP_SIZE),
+                    extent.height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                );
+            }
+            source = target.get_descriptor_info();
+            source_image = (target.handle(), target.get_name().to_string());
+        }
+        self.context.end_gpu_phase(cmd, GpuPhase::Post);
+        Some(&mut self.targets[(enabled.len() - 1) % 2])
+    }
+}
+
+impl Drop for PostProcessChain {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            for pass in &self.passes {
+                device.destroy_pipeline(pass.pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
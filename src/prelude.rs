@@ -1,6 +1,10 @@
+// Curated set of items most applications need. Only sol's own types and the `vk`/`math`
+// re-exports are exposed, never whole dependency crates.
 pub use crate::{
-    ash::{vk},
-    glam::*,
-    winit, Resource, Vertex,
+    math::*,
+    vk, App, AppRenderError, AppRenderer, AppSettings, Background, Buffer, BufferInfo, Context,
+    DescriptorSet, DescriptorSetInfo, DescriptorSetLayout, FrameTime, FullscreenPass, Image2d,
+    InputState, Pipeline, PipelineInfo, PipelineLayout, RenderPass, RenderTarget,
+    RendererSettings, Resource, Shader, TransientBufferAllocator, Vertex, Window,
 };
 pub use std::{default::Default, mem::size_of, result::Result, sync::Arc};
@@ -0,0 +1,74 @@
+use crate::{Error, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progress {
+    pub stage: String,
+    // Completion of the current stage, in [0, 1].
+    pub fraction: f32,
+}
+
+impl Progress {
+    pub fn percentage(&self) -> f32 {
+        self.fraction * 100.0
+    }
+}
+
+#[derive(Default)]
+struct ProgressState {
+    progress: Mutex<Progress>,
+    cancelled: AtomicBool,
+}
+
+// Shared between a long-running operation (load_scene_async, SceneDescription::build_async) and
+// the app: the operation reports its stage and progress, the app polls them (e.g. for a progress
+// bar) and can cancel the operation, which then fails with Error::Cancelled at its next check.
+// Clones share the same state.
+#[derive(Clone, Default)]
+pub struct ProgressToken {
+    state: Arc<ProgressState>,
+}
+
+impl ProgressToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report(&self, stage: &str, fraction: f32) {
+        let mut progress = self.state.progress.lock().unwrap();
+        if progress.stage != stage {
+            progress.stage = stage.to_string();
+        }
+        progress.fraction = fraction.clamp(0.0, 1.0);
+    }
+
+    // Reports `done` out of `total` steps of the stage.
+    pub fn report_steps(&self, stage: &str, done: usize, total: usize) {
+        let fraction = match total {
+            0 => 1.0,
+            total => done as f32 / total as f32,
+        };
+        self.report(stage, fraction);
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.state.progress.lock().unwrap().clone()
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Cancellation point of the operations, returns Error::Cancelled once cancel was called.
+    pub fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
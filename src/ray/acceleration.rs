@@ -1,4 +1,4 @@
-use crate::{Buffer, BufferInfo, Context, Resource};
+use crate::{Buffer, BufferInfo, Context, GpuPhase, MemoryBarrier2, Resource};
 use ash::{vk};
 use std::sync::Arc;
 
@@ -13,13 +13,80 @@ pub struct GeometryInstance {
     pub transform: glam::Mat4,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct AccelerationBuildInfo {
+    pub flags: vk::BuildAccelerationStructureFlagsKHR,
+    // Shrinks bottom-level structures to their compacted size once built (see BlasBuilder).
+    pub compact: bool,
+    // Submits BlasBuilder work on the context's async queue instead of the graphics queue.
+    pub async_queue: bool,
+}
+
+impl Default for AccelerationBuildInfo {
+    fn default() -> Self {
+        AccelerationBuildInfo {
+            flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+            compact: false,
+            async_queue: false,
+        }
+    }
+}
+
+impl AccelerationBuildInfo {
+    pub fn flags(mut self, flags: vk::BuildAccelerationStructureFlagsKHR) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn prefer_fast_build(mut self) -> Self {
+        self.flags &= !vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE;
+        self.flags |= vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD;
+        self
+    }
+
+    // Required to refit structures with TlasUpdateMode::Refit.
+    pub fn allow_update(mut self) -> Self {
+        self.flags |= vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE;
+        self
+    }
+
+    pub fn compact(mut self) -> Self {
+        self.compact = true;
+        self
+    }
+
+    pub fn async_queue(mut self) -> Self {
+        self.async_queue = true;
+        self
+    }
+
+    fn build_flags(&self) -> vk::BuildAccelerationStructureFlagsKHR {
+        if self.compact {
+            self.flags | vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION
+        } else {
+            self.flags
+        }
+    }
+}
+
 struct AccelerationStructure {
     context: Arc<Context>,
     accel_struct: vk::AccelerationStructureKHR,
-    scratch_buffer: Buffer,
+    scratch_buffer: Option<Buffer>,
     buffer: Buffer,
 }
 
+// Orders acceleration structure builds with the following builds and reads.
+fn cmd_build_barrier(context: &Context, cmd: vk::CommandBuffer) {
+    let stage = vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR;
+    let access = vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR
+        | vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR;
+    let barrier = MemoryBarrier2::default()
+        .src(stage, access)
+        .dst(stage, access);
+    context.cmd_pipeline_barrier2(cmd, &[barrier], &[], &[]);
+}
+
 struct MemorySpec {
     size: vk::DeviceSize,
     type_bits: u32,
@@ -38,6 +105,7 @@ impl Drop for AccelerationStructure {
                 .acceleration_structure()
                 .destroy_acceleration_structure(self.accel_struct, None);
         }
+        self.context.shared().retire_handle(self.accel_struct);
     }
 }
 
@@ -70,16 +138,19 @@ fn create_accel_struct(
             .usage(vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
         backing_buffer_size as vk::DeviceSize,
         1,
-    );
+    )
+    .expect("Unable to create acceleration structure buffer");
 
+    // Large enough for later in-place updates when the structure allows them.
     let scratch_buffer = Buffer::new(
         context.clone(),
         BufferInfo::default()
             .gpu_only()
             .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
-        mem_reqs.build_scratch_size,
+        mem_reqs.build_scratch_size.max(mem_reqs.update_scratch_size),
         1,
-    );
+    )
+    .expect("Unable to create acceleration structure scratch buffer");
 
     let create_info = vk::AccelerationStructureCreateInfoKHR::builder()
         .ty(ty)
@@ -101,25 +172,7 @@ fn create_accel_struct(
             std::slice::from_ref(&build_range_infos),
         );
 
-        let memory_barrier = vk::MemoryBarrier::builder()
-            .src_access_mask(
-                vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
-                    | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
-            )
-            .dst_access_mask(
-                vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
-                    | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
-            )
-            .build();
-        context.device().cmd_pipeline_barrier(
-            cmd,
-            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-            vk::DependencyFlags::empty(),
-            &[memory_barrier],
-            &[],
-            &[],
-        );
+        cmd_build_barrier(context, cmd);
     }
 
     (buffer, scratch_buffer, accel_structure)
@@ -128,10 +181,20 @@ fn create_accel_struct(
 pub struct BLAS {
     accel_struct: AccelerationStructure,
     geometries: Vec<vk::AccelerationStructureGeometryKHR>,
+    // Kept for in-place updates, see `update`.
+    build_range_infos: Vec<vk::AccelerationStructureBuildRangeInfoKHR>,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
     transform: glam::Mat4,
+    // TLAS instance settings, see the setters.
     hit_group_index: u32,
+    mask: u8,
+    instance_flags: vk::GeometryInstanceFlagsKHR,
 }
 
+// The geometries only reference device addresses, their p_next being null.
+unsafe impl Send for BLAS {}
+unsafe impl Sync for BLAS {}
+
 impl BLAS {
     pub fn new(
         context: Arc<Context>,
@@ -140,6 +203,26 @@ impl BLAS {
         transform: glam::Mat4,
         vertex_stride: vk::DeviceSize,
         is_opaque: bool,
+    ) -> Self {
+        BLAS::with_build_info(
+            context,
+            cmd,
+            geo_intances,
+            transform,
+            vertex_stride,
+            is_opaque,
+            &AccelerationBuildInfo::default(),
+        )
+    }
+
+    pub fn with_build_info(
+        context: Arc<Context>,
+        cmd: vk::CommandBuffer,
+        geo_intances: Vec<GeometryInstance>,
+        transform: glam::Mat4,
+        vertex_stride: vk::DeviceSize,
+        is_opaque: bool,
+        build_info: &AccelerationBuildInfo,
     ) -> Self {
         let mut geometries = Vec::<vk::AccelerationStructureGeometryKHR>::new();
         let mut max_primitive_counts = Vec::<u32>::new();
@@ -210,7 +293,7 @@ impl BLAS {
 
         let geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
             .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(build_info.build_flags())
             .geometries(geometries.as_slice())
             .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
             .build();
@@ -229,12 +312,17 @@ impl BLAS {
             accel_struct: AccelerationStructure {
                 context,
                 accel_struct,
-                scratch_buffer,
+                scratch_buffer: Some(scratch_buffer),
                 buffer,
             },
             transform,
             geometries,
+            build_range_infos,
+            flags: build_info.build_flags(),
             hit_group_index: 0,
+            mask: 0xff,
+            instance_flags: vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE
+                | vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE,
         }
     }
 
@@ -245,6 +333,185 @@ impl BLAS {
     pub fn set_transform(&mut self, transform: glam::Mat4) {
         self.transform = transform
     }
+
+    // Offset of the instance's records in the hit group region of the shader binding table,
+    // e.g. a per-material hit group.
+    pub fn get_hit_group_index(&self) -> u32 {
+        self.hit_group_index
+    }
+
+    pub fn set_hit_group_index(&mut self, hit_group_index: u32) {
+        assert!(hit_group_index <= 0x00ffffff);
+        self.hit_group_index = hit_group_index;
+    }
+
+    // Visibility mask of the instance, which is only hit by rays whose cull mask shares a bit
+    // with it. E.g. 0x01 for camera rays and 0x02 for shadow rays.
+    pub fn get_mask(&self) -> u8 {
+        self.mask
+    }
+
+    pub fn set_mask(&mut self, mask: u8) {
+        self.mask = mask;
+    }
+
+    // Defaults to FORCE_OPAQUE | TRIANGLE_FACING_CULL_DISABLE. Without FORCE_OPAQUE, any-hit
+    // shaders run for geometries which weren't built opaque.
+    pub fn get_instance_flags(&self) -> vk::GeometryInstanceFlagsKHR {
+        self.instance_flags
+    }
+
+    pub fn set_instance_flags(&mut self, flags: vk::GeometryInstanceFlagsKHR) {
+        self.instance_flags = flags;
+    }
+
+    // Frees the build scratch memory. The build command buffer must have completed execution.
+    pub fn release_scratch(&mut self) {
+        self.accel_struct.scratch_buffer = None;
+    }
+
+    pub fn get_size(&self) -> vk::DeviceSize {
+        self.accel_struct.buffer.get_size()
+    }
+
+    // Triangles over all geometries, e.g. to estimate the cost of an update.
+    pub fn get_primitive_count(&self) -> u64 {
+        self.build_range_infos
+            .iter()
+            .map(|range| range.primitive_count as u64)
+            .sum()
+    }
+
+    pub fn can_refit(&self) -> bool {
+        self.flags
+            .contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE)
+    }
+
+    // Records an in-place update after the geometry changed in its buffers, e.g. animated
+    // vertices, with the same modes as TLAS updates. Refits fall back to rebuilds without
+    // AccelerationBuildInfo::allow_update, and compacted structures too small for a rebuild are
+    // refit instead. The handle is unchanged, the TLAS only needs a refit afterwards.
+    pub fn update(&mut self, cmd: vk::CommandBuffer, mode: TlasUpdateMode) {
+        let context = self.accel_struct.context.clone();
+        let mut build_mode = match mode {
+            TlasUpdateMode::Refit if self.can_refit() => vk::BuildAccelerationStructureModeKHR::UPDATE,
+            _ => vk::BuildAccelerationStructureModeKHR::BUILD,
+        };
+        let mut geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+            .flags(self.flags)
+            .geometries(&self.geometries)
+            .build();
+        let max_primitive_counts = self
+            .build_range_infos
+            .iter()
+            .map(|range| range.primitive_count)
+            .collect::<Vec<_>>();
+        let sizes = unsafe {
+            context
+                .acceleration_structure()
+                .get_acceleration_structure_build_sizes(
+                    vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                    &geometry_info,
+                    &max_primitive_counts,
+                )
+        };
+        if build_mode == vk::BuildAccelerationStructureModeKHR::BUILD
+            && sizes.acceleration_structure_size > self.get_size()
+        {
+            if !self.can_refit() {
+                println!("Compacted BLAS can't be rebuilt in place, update skipped.");
+                return;
+            }
+            build_mode = vk::BuildAccelerationStructureModeKHR::UPDATE;
+        }
+        let scratch_size = match build_mode {
+            vk::BuildAccelerationStructureModeKHR::UPDATE => sizes.update_scratch_size,
+            _ => sizes.build_scratch_size,
+        };
+        // Released after the initial build, see `release_scratch`.
+        let scratch_too_small = match &self.accel_struct.scratch_buffer {
+            Some(buffer) => buffer.get_size() < scratch_size,
+            None => true,
+        };
+        if scratch_too_small {
+            self.accel_struct.scratch_buffer = Some(
+                Buffer::new(
+                    context.clone(),
+                    BufferInfo::default()
+                        .gpu_only()
+                        .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+                    sizes.build_scratch_size.max(sizes.update_scratch_size),
+                    1,
+                )
+                .expect("Unable to create acceleration structure scratch buffer"),
+            );
+        }
+
+        geometry_info.mode = build_mode;
+        if build_mode == vk::BuildAccelerationStructureModeKHR::UPDATE {
+            geometry_info.src_acceleration_structure = self.handle();
+        }
+        geometry_info.dst_acceleration_structure = self.handle();
+        geometry_info.scratch_data = vk::DeviceOrHostAddressKHR {
+            device_address: self
+                .accel_struct
+                .scratch_buffer
+                .as_ref()
+                .unwrap()
+                .get_device_address(),
+        };
+        context.begin_gpu_phase(cmd, GpuPhase::AccelerationStructures);
+        unsafe {
+            context
+                .acceleration_structure()
+                .cmd_build_acceleration_structures(
+                    cmd,
+                    std::slice::from_ref(&geometry_info),
+                    std::slice::from_ref(&&self.build_range_infos[..]),
+                );
+        }
+        cmd_build_barrier(&context, cmd);
+        context.end_gpu_phase(cmd, GpuPhase::AccelerationStructures);
+    }
+
+    // Records a copy of this structure into a new one of `compacted_size` bytes. The returned
+    // structure replaces this one (see `replace`) once the copy has completed.
+    fn cmd_compact(&self, cmd: vk::CommandBuffer, compacted_size: vk::DeviceSize) -> AccelerationStructure {
+        let context = &self.accel_struct.context;
+        let buffer = Buffer::new(
+            context.clone(),
+            BufferInfo::default()
+                .gpu_only()
+                .usage(vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+            compacted_size,
+            1,
+        )
+        .expect("Unable to create compacted acceleration structure buffer");
+        let create_info = vk::AccelerationStructureCreateInfoKHR::builder()
+            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+            .buffer(buffer.handle())
+            .size(compacted_size);
+        unsafe {
+            let accel_struct = context
+                .acceleration_structure()
+                .create_acceleration_structure(&create_info, None)
+                .unwrap();
+            let copy_info = vk::CopyAccelerationStructureInfoKHR::builder()
+                .src(self.handle())
+                .dst(accel_struct)
+                .mode(vk::CopyAccelerationStructureModeKHR::COMPACT);
+            context
+                .acceleration_structure()
+                .cmd_copy_acceleration_structure(cmd, &copy_info);
+            AccelerationStructure {
+                context: context.clone(),
+                accel_struct,
+                scratch_buffer: None,
+                buffer,
+            }
+        }
+    }
 }
 
 impl crate::Resource<vk::AccelerationStructureKHR> for BLAS {
@@ -253,6 +520,196 @@ impl crate::Resource<vk::AccelerationStructureKHR> for BLAS {
     }
 }
 
+// Records a batch of BLAS builds in its own command buffer and submits them with a fence, so
+// large scenes can be built in the background (optionally on the async queue) and polled with
+// `is_complete`. `finish` waits for the builds, frees the scratch memory and, if requested,
+// compacts every BLAS through a compacted-size query and a COMPACT copy.
+pub struct BlasBuilder {
+    context: Arc<Context>,
+    info: AccelerationBuildInfo,
+    queue: vk::Queue,
+    pool: vk::CommandPool,
+    cmd: vk::CommandBuffer,
+    fence: vk::Fence,
+    query_pool: vk::QueryPool,
+    blas: Vec<BLAS>,
+    submitted: bool,
+}
+
+impl BlasBuilder {
+    pub fn new(context: Arc<Context>, info: AccelerationBuildInfo) -> Self {
+        let queue = match info.async_queue {
+            true => context.async_queue(),
+            false => context.graphics_queue(),
+        };
+        unsafe {
+            let device = context.device();
+            let pool_create_info = vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(context.shared().queue_family_indices.graphics);
+            let pool = device.create_command_pool(&pool_create_info, None).unwrap();
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+            let cmd = device.allocate_command_buffers(&allocate_info).unwrap()[0];
+            let fence = device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .unwrap();
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device
+                .begin_command_buffer(cmd, &begin_info)
+                .expect("Begin acceleration structure build commands.");
+            BlasBuilder {
+                context,
+                info,
+                queue,
+                pool,
+                cmd,
+                fence,
+                query_pool: vk::QueryPool::null(),
+                blas: Vec::new(),
+                submitted: false,
+            }
+        }
+    }
+
+    // Records the build of a new BLAS and returns its index in the final list.
+    pub fn push(
+        &mut self,
+        geo_intances: Vec<GeometryInstance>,
+        transform: glam::Mat4,
+        vertex_stride: vk::DeviceSize,
+        is_opaque: bool,
+    ) -> usize {
+        assert!(!self.submitted, "BlasBuilder was already submitted.");
+        self.blas.push(BLAS::with_build_info(
+            self.context.clone(),
+            self.cmd,
+            geo_intances,
+            transform,
+            vertex_stride,
+            is_opaque,
+            &self.info,
+        ));
+        self.blas.len() - 1
+    }
+
+    pub fn submit(&mut self) {
+        if self.submitted {
+            return;
+        }
+        let device = self.context.device();
+        let accel = self.context.acceleration_structure();
+        unsafe {
+            if self.info.compact && !self.blas.is_empty() {
+                let query_info = vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR)
+                    .query_count(self.blas.len() as u32);
+                self.query_pool = device.create_query_pool(&query_info, None).unwrap();
+                device.cmd_reset_query_pool(self.cmd, self.query_pool, 0, self.blas.len() as u32);
+                let handles = self.blas.iter().map(|b| b.handle()).collect::<Vec<_>>();
+                accel.cmd_write_acceleration_structures_properties(
+                    self.cmd,
+                    &handles,
+                    vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+                    self.query_pool,
+                    0,
+                );
+            }
+            device
+                .end_command_buffer(self.cmd)
+                .expect("End acceleration structure build commands.");
+            let submit_info = vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&self.cmd));
+            self.context
+                .queue_submit(self.queue, std::slice::from_ref(&submit_info), self.fence)
+                .expect("Acceleration structure build submit failed.");
+        }
+        self.submitted = true;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.submitted && unsafe { self.context.device().get_fence_status(self.fence).unwrap_or(false) }
+    }
+
+    fn wait(&self) {
+        unsafe {
+            self.context
+                .device()
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .expect("Wait for acceleration structure build failed.");
+        }
+    }
+
+    // Submits if needed, waits for completion and returns the built (and compacted) structures.
+    pub fn finish(mut self) -> Vec<BLAS> {
+        self.submit();
+        self.wait();
+        self.blas.iter_mut().for_each(|b| b.release_scratch());
+
+        if self.query_pool != vk::QueryPool::null() {
+            let device = self.context.device();
+            let mut sizes = vec![0u64; self.blas.len()];
+            unsafe {
+                device
+                    .get_query_pool_results(
+                        self.query_pool,
+                        0,
+                        sizes.len() as u32,
+                        &mut sizes,
+                        vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                    )
+                    .expect("Compacted size query failed.");
+                device.reset_fences(&[self.fence]).unwrap();
+                device
+                    .reset_command_buffer(self.cmd, vk::CommandBufferResetFlags::empty())
+                    .unwrap();
+                let begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+                device.begin_command_buffer(self.cmd, &begin_info).unwrap();
+            }
+            let compacted = self
+                .blas
+                .iter()
+                .zip(sizes.iter())
+                .map(|(blas, size)| blas.cmd_compact(self.cmd, *size))
+                .collect::<Vec<_>>();
+            unsafe {
+                device.end_command_buffer(self.cmd).unwrap();
+                let submit_info = vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&self.cmd));
+                self.context
+                    .queue_submit(self.queue, std::slice::from_ref(&submit_info), self.fence)
+                    .expect("Acceleration structure compaction submit failed.");
+            }
+            self.wait();
+            // The original structures are destroyed here, after the copies completed.
+            self.blas
+                .iter_mut()
+                .zip(compacted)
+                .for_each(|(blas, accel_struct)| blas.accel_struct = accel_struct);
+        }
+        std::mem::take(&mut self.blas)
+    }
+}
+
+impl Drop for BlasBuilder {
+    fn drop(&mut self) {
+        unsafe {
+            if self.submitted {
+                self.wait();
+            }
+            let device = self.context.device();
+            if self.query_pool != vk::QueryPool::null() {
+                device.destroy_query_pool(self.query_pool, None);
+            }
+            device.destroy_fence(self.fence, None);
+            device.free_command_buffers(self.pool, &[self.cmd]);
+            device.destroy_command_pool(self.pool, None);
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, Copy)]
 struct InstanceDescriptor {
@@ -305,19 +762,40 @@ impl InstanceDescriptor {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlasUpdateMode {
+    // Full rebuild, best trace performance, required when instances are added or removed.
+    Rebuild,
+    // In-place update of the existing structure, much cheaper for moving instances. Falls back
+    // to a rebuild if the TLAS wasn't created with `allow_update` or the instance count changed.
+    Refit,
+}
+
 pub struct TLAS {
     context: Arc<Context>,
     instance_buffer: Buffer,
     accel_struct: AccelerationStructure,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    // Instances of the last build, at most `capacity` (the creation count).
+    instance_count: usize,
+    capacity: usize,
 }
 
 impl TLAS {
     fn create_instances(context: &Arc<Context>, blas: &[BLAS]) -> Vec<InstanceDescriptor>
     {
-        blas
-            .iter()
-            .enumerate()
-            .map(|(i, blas)| {
+        Self::create_subset_instances(context, blas, 0..blas.len())
+    }
+
+    // Instance custom indices stay the BLAS indices, so hit shaders index the same data.
+    fn create_subset_instances(
+        context: &Arc<Context>,
+        blas: &[BLAS],
+        indices: impl Iterator<Item = usize>,
+    ) -> Vec<InstanceDescriptor> {
+        indices
+            .map(|i| {
+                let blas = &blas[i];
                 let struct_handle = unsafe {
                     context
                         .acceleration_structure()
@@ -332,9 +810,9 @@ impl TLAS {
                 InstanceDescriptor::new(
                     transform,
                     i as u32,
-                    0xff,
+                    blas.mask,
                     blas.hit_group_index,
-                    vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE | vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE,
+                    blas.instance_flags,
                     struct_handle,
                 )
             })
@@ -342,7 +820,15 @@ impl TLAS {
     }
 
     pub fn new(context: Arc<Context>, cmd: vk::CommandBuffer, blas: &[BLAS]) -> Self {
+        TLAS::with_build_info(context, cmd, blas, &AccelerationBuildInfo::default())
+    }
 
+    pub fn with_build_info(
+        context: Arc<Context>,
+        cmd: vk::CommandBuffer,
+        blas: &[BLAS],
+        build_info: &AccelerationBuildInfo,
+    ) -> Self {
         let instances = Self::create_instances(&context, blas);
 
         let instance_buffer = Buffer::from_data(
@@ -351,7 +837,8 @@ impl TLAS {
                 .cpu_to_gpu()
                 .usage(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR),
             instances.as_slice(),
-        );
+        )
+        .expect("Unable to create instance buffer");
 
         let geometry = vk::AccelerationStructureGeometryKHR::builder()
             .geometry_type(vk::GeometryTypeKHR::INSTANCES)
@@ -370,7 +857,7 @@ impl TLAS {
             
         let geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
             .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(build_info.flags)
             .geometries(std::slice::from_ref(&geometry))
             .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
             .build();
@@ -392,10 +879,13 @@ impl TLAS {
             accel_struct: AccelerationStructure {
                 context,
                 accel_struct,
-                scratch_buffer,
+                scratch_buffer: Some(scratch_buffer),
                 buffer,
             },
             instance_buffer,
+            flags: build_info.flags,
+            instance_count: instances.len(),
+            capacity: instances.len(),
         }
     }
 
@@ -404,10 +894,62 @@ impl TLAS {
         cmd: vk::CommandBuffer,
         blas: &[BLAS]
     ) {
-        assert_eq!(std::mem::size_of::<InstanceDescriptor>(), 64);
+        self.update(cmd, blas, TlasUpdateMode::Rebuild);
+    }
+
+    pub fn can_refit(&self, instance_count: usize) -> bool {
+        self.flags.contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE)
+            && instance_count == self.instance_count
+    }
 
+    pub fn update(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        blas: &[BLAS],
+        mode: TlasUpdateMode,
+    ) {
         let instances = Self::create_instances(&self.context, blas);
-        self.instance_buffer.update(&instances);
+        self.build_instances(cmd, instances, mode);
+    }
+
+    // Builds the TLAS from the BLAS at `indices` only, e.g. the instances near the camera (see
+    // TlasSubset). Refits are only possible while the subset size doesn't change.
+    pub fn update_subset(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        blas: &[BLAS],
+        indices: &[usize],
+        mode: TlasUpdateMode,
+    ) {
+        let instances = Self::create_subset_instances(&self.context, blas, indices.iter().copied());
+        self.build_instances(cmd, instances, mode);
+    }
+
+    fn build_instances(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        instances: Vec<InstanceDescriptor>,
+        mode: TlasUpdateMode,
+    ) {
+        assert_eq!(std::mem::size_of::<InstanceDescriptor>(), 64);
+
+        // The instance buffer and the structure itself are sized for the creation instance count.
+        assert!(
+            instances.len() <= self.capacity,
+            "TLAS can't grow past its initial instance count."
+        );
+        self.context.begin_gpu_phase(cmd, GpuPhase::AccelerationStructures);
+        // An empty subset is valid, but Buffer::update needs at least one element.
+        if !instances.is_empty() {
+            self.instance_buffer.update(&instances);
+        }
+        let build_mode = match mode {
+            TlasUpdateMode::Refit if self.can_refit(instances.len()) => {
+                vk::BuildAccelerationStructureModeKHR::UPDATE
+            }
+            _ => vk::BuildAccelerationStructureModeKHR::BUILD,
+        };
+        self.instance_count = instances.len();
 
         let geometry = vk::AccelerationStructureGeometryKHR::builder()
             .geometry_type(vk::GeometryTypeKHR::INSTANCES)
@@ -426,15 +968,23 @@ impl TLAS {
 
         let mut geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
             .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(self.flags)
             .geometries(std::slice::from_ref(&geometry))
-            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .mode(build_mode)
             .build();
         
         unsafe {
+            if build_mode == vk::BuildAccelerationStructureModeKHR::UPDATE {
+                geometry_info.src_acceleration_structure = self.handle();
+            }
             geometry_info.dst_acceleration_structure = self.handle();
             geometry_info.scratch_data = vk::DeviceOrHostAddressKHR {
-                device_address: self.accel_struct.scratch_buffer.get_device_address()
+                device_address: self
+                    .accel_struct
+                    .scratch_buffer
+                    .as_ref()
+                    .expect("TLAS scratch buffer was released.")
+                    .get_device_address()
             };
 
             self.context.acceleration_structure()
@@ -444,26 +994,25 @@ impl TLAS {
                     std::slice::from_ref(&&build_range_infos[..]),
                 );
 
-            let memory_barrier = vk::MemoryBarrier::builder()
-                .src_access_mask(
-                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
-                        | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
-                )
-                .dst_access_mask(
-                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
-                        | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
-                )
-                .build();
-            self.context.device().cmd_pipeline_barrier(
-                cmd,
-                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-                vk::DependencyFlags::empty(),
-                &[memory_barrier],
-                &[],
-                &[],
-            );
+            cmd_build_barrier(&self.context, cmd);
         }
+        self.context.end_gpu_phase(cmd, GpuPhase::AccelerationStructures);
+    }
+
+    // Makes the last build or update visible to the shaders of `dst_stages`, e.g.
+    // FRAGMENT_SHADER | COMPUTE_SHADER for inline ray queries.
+    pub fn cmd_read_barrier(&self, cmd: vk::CommandBuffer, dst_stages: vk::PipelineStageFlags2) {
+        let barrier = MemoryBarrier2::default()
+            .src(
+                vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+            )
+            .dst(
+                dst_stages,
+                vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR,
+            );
+        self.context
+            .cmd_pipeline_barrier2(cmd, &[barrier], &[], &[]);
     }
 }
 
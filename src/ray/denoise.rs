@@ -0,0 +1,429 @@
+use super::HIT_DISTANCE_FORMAT;
+use crate::{
+    Context, DescriptorSetInfo, DescriptorSetLayout, DescriptorSetLayoutInfo, GpuPhase, Image2d,
+    Resource, Result, Shader, POST_PROCESS_FORMAT,
+};
+use ash::vk;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// Guide formats, bound as `rgba16f` albedo and world space normal in the raygen shader. The
+// depth guide follows the hit distance AOV convention, see HIT_DISTANCE_FORMAT.
+pub const DENOISE_ALBEDO_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const DENOISE_NORMAL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+fn create_guide(
+    context: &Arc<Context>,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    name: &str,
+) -> Result<Image2d> {
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    Image2d::new(
+        context.shared().clone(),
+        &image_info,
+        vk::ImageAspectFlags::COLOR,
+        1,
+        name,
+    )
+}
+
+// G-buffer of the primary hits guiding a Denoiser, written by the raygen shader along with the
+// noisy color. Missed rays must write a depth of 0.0.
+pub struct DenoiseGuides {
+    pub albedo: Image2d,
+    pub normal: Image2d,
+    pub depth: Image2d,
+}
+
+impl DenoiseGuides {
+    // Storage images in GENERAL layout.
+    pub fn new(context: &Arc<Context>, extent: vk::Extent2D) -> Result<Self> {
+        let mut guides = DenoiseGuides {
+            albedo: create_guide(context, extent, DENOISE_ALBEDO_FORMAT, "DenoiseAlbedo")?,
+            normal: create_guide(context, extent, DENOISE_NORMAL_FORMAT, "DenoiseNormal")?,
+            depth: create_guide(context, extent, HIT_DISTANCE_FORMAT, "DenoiseDepth")?,
+        };
+        let cmd = context.begin_single_time_cmd();
+        for image in [&mut guides.albedo, &mut guides.normal, &mut guides.depth] {
+            image.transition_image_layout(
+                cmd,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+            );
+        }
+        context.end_single_time_cmd(cmd);
+        Ok(guides)
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.albedo.get_extent()
+    }
+}
+
+// Denoising of a ray traced color target, implemented by AtrousDenoiser. External denoisers
+// (e.g. OIDN, DLSS ray reconstruction) implement it in the crates or features wrapping them, so
+// that applications can switch between them.
+pub trait Denoiser {
+    fn name(&self) -> &str;
+
+    // Called before denoising inputs of a new extent.
+    fn resize(&mut self, extent: vk::Extent2D) -> Result<()>;
+
+    // Records the denoising of `color` into `output`, both POST_PROCESS_FORMAT storage images of
+    // the guides extent, e.g. to feed a PostProcessChain. `color` and the guides are in GENERAL
+    // layout, written by previous shader commands, and `output` is left in GENERAL layout.
+    fn cmd_denoise(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        color: &Image2d,
+        guides: &DenoiseGuides,
+        output: &mut Image2d,
+    );
+}
+
+const ATROUS_COMP: &str = "
+#version 460
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0, rgba32f) uniform readonly image2D inputImage;
+layout(set = 0, binding = 1, rgba32f) uniform writeonly image2D outputImage;
+layout(set = 0, binding = 2, rgba16f) uniform readonly image2D albedoImage;
+layout(set = 0, binding = 3, rgba16f) uniform readonly image2D normalImage;
+layout(set = 0, binding = 4, r32f) uniform readonly image2D depthImage;
+layout(push_constant) uniform PushConstant {
+    int step;
+    float sigmaColor;
+    float sigmaAlbedo;
+    float sigmaNormal;
+    float sigmaDepth;
+} push;
+
+const float kernel[3] = float[](3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+
+void main() {
+    ivec2 size = imageSize(outputImage);
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pixel, size))) {
+        return;
+    }
+    vec4 center = imageLoad(inputImage, pixel);
+    float depth = imageLoad(depthImage, pixel).x;
+    // Missed primary rays (background) are not filtered.
+    if (depth <= 0.0) {
+        imageStore(outputImage, pixel, center);
+        return;
+    }
+    vec3 albedo = imageLoad(albedoImage, pixel).rgb;
+    vec3 normal = imageLoad(normalImage, pixel).xyz;
+
+    vec3 sum = vec3(0.0);
+    float weightSum = 0.0;
+    for (int y = -2; y <= 2; y++) {
+        for (int x = -2; x <= 2; x++) {
+            ivec2 tap = pixel + ivec2(x, y) * push.step;
+            if (any(lessThan(tap, ivec2(0))) || any(greaterThanEqual(tap, size))) {
+                continue;
+            }
+            float tapDepth = imageLoad(depthImage, tap).x;
+            if (tapDepth <= 0.0) {
+                continue;
+            }
+            vec3 color = imageLoad(inputImage, tap).rgb;
+            vec3 colorDelta = color - center.rgb;
+            vec3 albedoDelta = imageLoad(albedoImage, tap).rgb - albedo;
+            vec3 tapNormal = imageLoad(normalImage, tap).xyz;
+            float distance = max(length(vec2(x, y)) * float(push.step), 1.0);
+            float weight = kernel[abs(x)] * kernel[abs(y)]
+                * exp(-dot(colorDelta, colorDelta) / push.sigmaColor)
+                * exp(-dot(albedoDelta, albedoDelta) / push.sigmaAlbedo)
+                * pow(max(dot(normal, tapNormal), 0.0), push.sigmaNormal)
+                * exp(-abs(depth - tapDepth) / (push.sigmaDepth * depth * distance));
+            sum += color * weight;
+            weightSum += weight;
+        }
+    }
+    // The center tap always contributes, weightSum is positive.
+    imageStore(outputImage, pixel, vec4(sum / weightSum, center.a));
+}
+";
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AtrousParams {
+    step: i32,
+    sigma_color: f32,
+    sigma_albedo: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+}
+
+fn storage_image_info(image: &Image2d) -> vk::DescriptorImageInfo {
+    vk::DescriptorImageInfo::builder()
+        .image_view(image.get_image_view())
+        .image_layout(vk::ImageLayout::GENERAL)
+        .build()
+}
+
+// Edge-avoiding a-trous wavelet filter (Dammertz et al. 2010): iterations of a 5x5 kernel with
+// taps 2^i pixels apart, whose weights fall off with the color, albedo, normal and relative depth
+// differences. The color tolerance halves every iteration.
+pub struct AtrousDenoiser {
+    context: Arc<Context>,
+    layout: DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    targets: [Image2d; 2],
+    pub iterations: u32,
+    // Squared color distance of the first iteration at which the tap weight is 1/e.
+    pub sigma_color: f32,
+    // Squared albedo distance at which the tap weight is 1/e.
+    pub sigma_albedo: f32,
+    // Exponent of the cosine between the normals.
+    pub sigma_normal: f32,
+    // Depth difference per pixel, relative to the depth, at which the tap weight is 1/e.
+    pub sigma_depth: f32,
+}
+
+fn create_targets(context: &Arc<Context>, extent: vk::Extent2D) -> Result<[Image2d; 2]> {
+    Ok([
+        create_guide(context, extent, POST_PROCESS_FORMAT, "AtrousTarget0")?,
+        create_guide(context, extent, POST_PROCESS_FORMAT, "AtrousTarget1")?,
+    ])
+}
+
+impl AtrousDenoiser {
+    pub fn new(context: Arc<Context>, extent: vk::Extent2D) -> Self {
+        let device = context.device();
+        let mut layout_info = DescriptorSetLayoutInfo::default();
+        for binding in 0..5 {
+            layout_info = layout_info.binding(
+                binding,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            );
+        }
+        let layout = DescriptorSetLayout::new(context.clone(), layout_info);
+        let set_layouts = [layout.handle()];
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(std::mem::size_of::<AtrousParams>() as u32)
+            .build()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() };
+
+        let shader = Shader::from_source(
+            context.clone(),
+            ATROUS_COMP,
+            "atrous.comp",
+            vk::ShaderStageFlags::COMPUTE,
+        )
+        .expect("Unable to compile a-trous shader");
+        let entry_name = CString::new("main").unwrap();
+        let create_infos = [vk::ComputePipelineCreateInfo::builder()
+            .stage(shader.get_create_info(&entry_name))
+            .layout(pipeline_layout)
+            .build()];
+        let pipeline = unsafe {
+            device
+                .create_compute_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                .expect("Unable to create a-trous pipeline")[0]
+        };
+        let targets = create_targets(&context, extent).expect("Unable to create a-trous targets");
+
+        AtrousDenoiser {
+            context,
+            layout,
+            pipeline_layout,
+            pipeline,
+            targets,
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_albedo: 0.1,
+            sigma_normal: 128.0,
+            sigma_depth: 0.01,
+        }
+    }
+}
+
+impl Denoiser for AtrousDenoiser {
+    fn name(&self) -> &str {
+        "a-trous"
+    }
+
+    fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        self.targets = create_targets(&self.context, extent)?;
+        Ok(())
+    }
+
+    fn cmd_denoise(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        color: &Image2d,
+        guides: &DenoiseGuides,
+        output: &mut Image2d,
+    ) {
+        assert_eq!(color.get_extent(), guides.get_extent());
+        assert_eq!(output.get_extent(), guides.get_extent());
+        assert_eq!(self.targets[0].get_extent(), guides.get_extent());
+        let iterations = self.iterations.max(1);
+        self.context.begin_gpu_phase(cmd, GpuPhase::Post);
+        let device = self.context.device();
+        let extent = guides.get_extent();
+        let guide_images = [&guides.albedo, &guides.normal, &guides.depth];
+        let mut source = (
+            color.handle(),
+            color.get_name().to_string(),
+            storage_image_info(color),
+        );
+        for iteration in 0..iterations {
+            // The last iteration writes to the output, the others alternate between the targets.
+            let target = match iteration + 1 == iterations {
+                true => &mut *output,
+                false => &mut self.targets[iteration as usize % 2],
+            };
+            let old_layout = target.get_layout();
+            self.context.track_hazards(|tracker| {
+                let reads = guide_images
+                    .iter()
+                    .map(|image| (image.handle(), image.get_name()))
+                    .chain(std::iter::once((source.0, source.1.as_str())));
+                for (image, name) in reads {
+                    tracker.barrier(
+                        image,
+                        name,
+                        vk::PipelineStageFlags::ALL_COMMANDS,
+                        vk::AccessFlags::SHADER_WRITE,
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::AccessFlags::SHADER_READ,
+                        None,
+                    );
+                    tracker.expect_layout(image, name, vk::ImageLayout::GENERAL);
+                    tracker.read(
+                        image,
+                        name,
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::AccessFlags::SHADER_READ,
+                    );
+                }
+                let (image, name) = (target.handle(), target.get_name());
+                tracker.barrier(
+                    image,
+                    name,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::empty(),
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                    Some((old_layout, vk::ImageLayout::GENERAL)),
+                );
+                tracker.write(
+                    image,
+                    name,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                );
+            });
+            let memory_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            // The previous content of the target is overwritten.
+            let target_barrier = vk::ImageMemoryBarrier::builder()
+                .image(target.handle())
+                .old_layout(old_layout)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .level_count(1)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build();
+            target.set_layout(vk::ImageLayout::GENERAL);
+            let desc_set = self.layout.get_or_create(
+                DescriptorSetInfo::default()
+                    .image(0, source.2)
+                    .image(1, storage_image_info(target))
+                    .image(2, storage_image_info(&guides.albedo))
+                    .image(3, storage_image_info(&guides.normal))
+                    .image(4, storage_image_info(&guides.depth)),
+            );
+            let params = AtrousParams {
+                step: 1 << iteration,
+                sigma_color: self.sigma_color / (1 << iteration) as f32,
+                sigma_albedo: self.sigma_albedo,
+                sigma_normal: self.sigma_normal,
+                sigma_depth: self.sigma_depth,
+            };
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[memory_barrier],
+                    &[],
+                    &[target_barrier],
+                );
+                device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[desc_set.handle()],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    cmd,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        &params as *const AtrousParams as *const u8,
+                        std::mem::size_of::<AtrousParams>(),
+                    ),
+                );
+                device.cmd_dispatch(
+                    cmd,
+                    extent.width.div_ceil(16),
+                    extent.height.div_ceil(16),
+                    1,
+                );
+            }
+            source = (
+                target.handle(),
+                target.get_name().to_string(),
+                storage_image_info(target),
+            );
+        }
+        self.context.end_gpu_phase(cmd, GpuPhase::Post);
+    }
+}
+
+impl Drop for AtrousDenoiser {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{
+    Context, DescriptorSetInfo, DescriptorSetLayout, DescriptorSetLayoutInfo, Image2d, Resource,
+    Result, Shader,
+};
+use ash::vk;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// Primary hit distance AOV convention: distance along the normalized primary ray direction, as
+// computed in ao.rgen from the camera inverse matrices, or 0.0 when the ray missed.
+pub const HIT_DISTANCE_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+// Storage image receiving the primary hit distance, bound as `r32f` image2D in the raygen shader.
+pub fn create_hit_distance_image(context: &Arc<Context>, extent: vk::Extent3D) -> Result<Image2d> {
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(HIT_DISTANCE_FORMAT)
+        .extent(extent)
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    Image2d::new(
+        context.shared().clone(),
+        &image_info,
+        vk::ImageAspectFlags::COLOR,
+        1,
+        "HitDistanceAOV",
+    )
+}
+
+// Depth buffer a DepthResolvePass can write to, later loaded by raster passes.
+pub fn create_resolved_depth_image(context: &Arc<Context>, extent: vk::Extent3D) -> Result<Image2d> {
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(context.find_depth_format())
+        .extent(extent)
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    Image2d::new(
+        context.shared().clone(),
+        &image_info,
+        vk::ImageAspectFlags::DEPTH,
+        1,
+        "ResolvedDepth",
+    )
+}
+
+const RESOLVE_VERT: &str = "
+#version 450
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+";
+
+const RESOLVE_FRAG: &str = "
+#version 450
+layout(set = 0, binding = 0, r32f) uniform readonly image2D hitDistance;
+layout(push_constant) uniform Camera {
+    mat4 projection;
+    mat4 projection_inverse;
+} camera;
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    float t = imageLoad(hitDistance, pixel).x;
+    if (t <= 0.0) {
+        gl_FragDepth = 1.0;
+        return;
+    }
+    vec2 d = gl_FragCoord.xy / vec2(imageSize(hitDistance)) * 2.0 - 1.0;
+    vec3 dir = normalize((camera.projection_inverse * vec4(d, 1.0, 1.0)).xyz);
+    vec4 clip = camera.projection * vec4(dir * t, 1.0);
+    gl_FragDepth = clamp(clip.z / clip.w, 0.0, 1.0);
+}
+";
+
+// Converts a hit distance AOV to a raster depth buffer with the camera projection, so that
+// rasterized elements (gizmos, debug lines, 3D UI) can be depth tested against path-traced
+// surfaces. Render passes drawing them must load the resolved depth.
+pub struct DepthResolvePass {
+    context: Arc<Context>,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    layout: DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl DepthResolvePass {
+    pub fn new(context: Arc<Context>, depth: &Image2d) -> Self {
+        let device = context.device();
+        let depth_attachment = [vk::AttachmentDescription::builder()
+            .format(depth.get_format())
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build()];
+        let depth_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .depth_stencil_attachment(&depth_ref)
+            .build()];
+        let dependencies = [vk::SubpassDependency {
+            src_subpass: 0,
+            dst_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ..Default::default()
+        }];
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&depth_attachment)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        let render_pass = unsafe {
+            device
+                .create_render_pass(&render_pass_info, None)
+                .expect("Unable to create depth resolve render pass")
+        };
+
+        let extent = depth.get_extent();
+        let attachments = [depth.get_image_view()];
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() };
+
+        let layout = DescriptorSetLayout::new(
+            context.clone(),
+            DescriptorSetLayoutInfo::default().binding(
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+        );
+        let set_layouts = [layout.handle()];
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(2 * std::mem::size_of::<glam::Mat4>() as u32)
+            .build()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() };
+
+        let vert = Shader::from_source(
+            context.clone(),
+            RESOLVE_VERT,
+            "depth_resolve.vert",
+            vk::ShaderStageFlags::VERTEX,
+        )
+        .expect("Unable to compile depth resolve shader");
+        let frag = Shader::from_source(
+            context.clone(),
+            RESOLVE_FRAG,
+            "depth_resolve.frag",
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+        .expect("Unable to compile depth resolve shader");
+        let entry_name = CString::new("main").unwrap();
+        let stages = [
+            vert.get_create_info(&entry_name),
+            frag.get_create_info(&entry_name),
+        ];
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default();
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+            scissor_count: 1,
+            viewport_count: 1,
+            ..Default::default()
+        };
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            ..Default::default()
+        };
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: 1,
+            depth_write_enable: 1,
+            depth_compare_op: vk::CompareOp::ALWAYS,
+            ..Default::default()
+        };
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::default();
+        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+        let create_infos = [vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state_info)
+            .input_assembly_state(&vertex_input_assembly_state_info)
+            .viewport_state(&viewport_state_info)
+            .rasterization_state(&rasterization_info)
+            .multisample_state(&multisample_state_info)
+            .depth_stencil_state(&depth_state_info)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state_info)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .build()];
+        let pipeline = unsafe {
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                .expect("Unable to create depth resolve pipeline")[0]
+        };
+
+        DepthResolvePass {
+            context,
+            render_pass,
+            framebuffer,
+            extent,
+            layout,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    // Records the conversion after the trace rays command that wrote `hit_distance` (in GENERAL
+    // layout) to `depth`, the image the pass was created with, left in
+    // DEPTH_STENCIL_ATTACHMENT_OPTIMAL layout. `projection` must be the one used to generate the
+    // primary rays.
+    pub fn cmd_resolve(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        hit_distance: &Image2d,
+        depth: &mut Image2d,
+        projection: glam::Mat4,
+    ) {
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_view(hit_distance.get_image_view())
+            .image_layout(vk::ImageLayout::GENERAL)
+            .build();
+        let desc_set = self
+            .layout
+            .get_or_create(DescriptorSetInfo::default().image(0, image_info));
+        let matrices = [projection, projection.inverse()];
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+        let begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area);
+        self.context.track_hazards(|tracker| {
+            let (image, name) = (hit_distance.handle(), hit_distance.get_name());
+            tracker.barrier(
+                image,
+                name,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                None,
+            );
+            tracker.expect_layout(image, name, vk::ImageLayout::GENERAL);
+            tracker.read(
+                image,
+                name,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            );
+        });
+        let device = self.context.device();
+        unsafe {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            device.cmd_begin_render_pass(cmd, &begin_info, vk::SubpassContents::INLINE);
+            device.cmd_set_viewport(
+                cmd,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: self.extent.width as f32,
+                    height: self.extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(cmd, 0, &[render_area]);
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[desc_set.handle()],
+                &[],
+            );
+            let bytes = std::slice::from_raw_parts(
+                matrices.as_ptr() as *const u8,
+                std::mem::size_of_val(&matrices),
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytes,
+            );
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+            device.cmd_end_render_pass(cmd);
+        }
+        depth.set_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+    }
+}
+
+impl Drop for DepthResolvePass {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use super::{primitive_geometry, AccelerationBuildInfo, BlasBuilder, SceneDescription, BLAS};
+use crate::scene::{Mesh, PrimitiveSection};
+use crate::{Context, Vertex};
+use std::sync::Arc;
+
+// A lower detail version of a primitive section, e.g. from a decimated copy of its mesh, traced
+// when the camera is at least `distance` away from the instance.
+pub struct LodLevelInfo<'a> {
+    pub mesh: &'a Mesh,
+    pub primitive: &'a PrimitiveSection,
+    pub distance: f32,
+}
+
+impl<'a> LodLevelInfo<'a> {
+    pub fn new(mesh: &'a Mesh, primitive: usize, distance: f32) -> Self {
+        LodLevelInfo {
+            mesh,
+            primitive: &mesh.primitive_sections[primitive],
+            distance,
+        }
+    }
+}
+
+struct LodLevel {
+    // None for the active level, which lives in SceneDescription::blas.
+    blas: Option<BLAS>,
+    // Index of the vertex and index descriptors of the level, the SceneInstance id.
+    descriptor_index: u32,
+    distance: f32,
+}
+
+// Detail levels of a BLAS, sorted by distance, starting with the BLAS of the scene.
+pub(crate) struct BlasLods {
+    levels: Vec<LodLevel>,
+    active: usize,
+}
+
+impl BlasLods {
+    fn select(&self, distance: f32) -> usize {
+        self.levels
+            .iter()
+            .rposition(|level| level.distance <= distance)
+            .unwrap_or(0)
+    }
+}
+
+// Distance based level of detail for ray tracing. A TLAS instance keeps its index (and
+// material) whichever level it traces, only its acceleration structure handle and the vertex
+// and index descriptors its SceneInstance points to change.
+impl SceneDescription {
+    // Builds the BLAS of the detail levels of the BLAS at `index`. Their vertex and index
+    // descriptors are appended after the scene ones, so descriptor arrays sized from
+    // get_vertex_descriptors must be created after the levels are added.
+    pub fn add_lod_levels(
+        &mut self,
+        context: Arc<Context>,
+        index: usize,
+        levels: &[LodLevelInfo],
+        build_info: AccelerationBuildInfo,
+    ) {
+        let transform = self.blas[index].get_transform();
+        let mut blas_builder = BlasBuilder::new(context, build_info);
+        for level in levels {
+            blas_builder.push(
+                vec![primitive_geometry(level.mesh, level.primitive)],
+                transform,
+                crate::scene::ModelVertex::stride() as u64,
+                true,
+            );
+        }
+        let descriptor_index = self.instances[index].id;
+        let lods = self.lods.entry(index).or_insert_with(|| BlasLods {
+            levels: vec![LodLevel {
+                blas: None,
+                descriptor_index,
+                distance: 0.0,
+            }],
+            active: 0,
+        });
+        for (level, blas) in levels.iter().zip(blas_builder.finish()) {
+            lods.levels.push(LodLevel {
+                blas: Some(blas),
+                descriptor_index: self.vertex_descriptors.len() as u32,
+                distance: level.distance,
+            });
+            self.vertex_descriptors.push(
+                level
+                    .primitive
+                    .get_vertex_descriptor(&level.mesh.vertex_buffer),
+            );
+            if let Some(buffer) = &level.mesh.index_buffer {
+                self.index_descriptors
+                    .push(level.primitive.get_index_descriptor::<u32>(buffer));
+            }
+        }
+        // Stable, the scene BLAS stays first.
+        let active = lods.levels[lods.active].descriptor_index;
+        lods.levels
+            .sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        lods.active = lods
+            .levels
+            .iter()
+            .position(|level| level.descriptor_index == active)
+            .unwrap();
+    }
+
+    // Index of the traced level of the BLAS at `index`, 0 being the scene BLAS.
+    pub fn get_lod_level(&self, index: usize) -> usize {
+        self.lods.get(&index).map_or(0, |lods| lods.active)
+    }
+
+    pub fn get_lod_level_count(&self, index: usize) -> usize {
+        self.lods.get(&index).map_or(1, |lods| lods.levels.len())
+    }
+
+    // Selects the level of each BLAS with detail levels from the distance between `eye` and
+    // the center of its world space bounds. Returns true when a level changed: the instances
+    // buffer must then be updated (see `update`) along with the TLAS. The instance count is
+    // unchanged, so a refit is valid, but a rebuild keeps traversal fast after many switches.
+    pub fn select_lods(&mut self, eye: glam::Vec3) -> bool {
+        let mut changed = false;
+        let mut indices = self.lods.keys().copied().collect::<Vec<_>>();
+        indices.sort_unstable();
+        for index in indices {
+            let distance = self.blas_bounds(index).center().distance(eye);
+            let lods = self.lods.get_mut(&index).unwrap();
+            let selected = lods.select(distance);
+            if selected == lods.active {
+                continue;
+            }
+            let mut blas = lods.levels[selected].blas.take().unwrap();
+            let active = &self.blas[index];
+            blas.set_transform(active.get_transform());
+            blas.set_mask(active.get_mask());
+            blas.set_hit_group_index(active.get_hit_group_index());
+            blas.set_instance_flags(active.get_instance_flags());
+            std::mem::swap(&mut self.blas[index], &mut blas);
+            lods.levels[lods.active].blas = Some(blas);
+            lods.active = selected;
+            self.instances[index].id = lods.levels[selected].descriptor_index;
+            changed = true;
+        }
+        changed
+    }
+}
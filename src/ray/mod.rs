@@ -7,11 +7,36 @@ pub use acceleration::*;
 mod sbt;
 pub use sbt::*;
 
+mod depth;
+pub use depth::*;
+
+mod denoise;
+pub use denoise::*;
+
+mod subset;
+pub use subset::*;
+
+mod shadow;
+pub use shadow::*;
+
+mod scene_build;
+pub use scene_build::*;
+
+mod scene_bindings;
+pub use scene_bindings::*;
+
+mod lod;
+pub use lod::*;
+
+mod update_scheduler;
+pub use update_scheduler::*;
+
 use ash::vk;
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::{Context, Vertex};
+use crate::scene::InstanceId;
+use crate::{Context, ProgressToken, Result, Vertex};
 
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
@@ -32,6 +57,61 @@ impl SceneInstance {
     pub fn get_transform(&self) -> &glam::Mat4 {
         &self.transform
     }
+
+    pub fn id(&self) -> InstanceId {
+        InstanceId(self.id)
+    }
+}
+
+// CPU side of a SceneDescription, before the acceleration structures are built.
+struct SceneDescriptionParts {
+    instances: Vec<SceneInstance>,
+    vertex_descriptors: Vec<vk::DescriptorBufferInfo>,
+    index_descriptors: Vec<vk::DescriptorBufferInfo>,
+    mat_descriptors: Vec<vk::DescriptorBufferInfo>,
+    blas_to_instances: HashMap<usize, Vec<usize>>,
+    blas_bounds: Vec<crate::scene::Aabb>,
+    mesh_blas: Vec<std::ops::Range<usize>>,
+}
+
+struct BlasInput {
+    geometry: Vec<GeometryInstance>,
+    transform: glam::Mat4,
+}
+
+impl BlasInput {
+    fn push_to(self, builder: &mut BlasBuilder) -> usize {
+        builder.push(
+            self.geometry,
+            self.transform,
+            crate::scene::ModelVertex::stride() as u64,
+            true,
+        )
+    }
+}
+
+fn primitive_geometry(
+    mesh: &crate::scene::Mesh,
+    primitive: &crate::scene::PrimitiveSection,
+) -> GeometryInstance {
+    let (index_buffer, index_count, index_offset_size) = match &mesh.index_buffer {
+        Some(buffer) => (
+            Some(buffer.get_device_address()),
+            Some(primitive.get_index_count()),
+            Some(primitive.get_index_offset_size::<u32>()),
+        ),
+        None => (None, None, None),
+    };
+    GeometryInstance {
+        vertex_buffer: mesh.vertex_buffer.get_device_address(),
+        vertex_count: primitive.get_vertex_count(),
+        vertex_offset_size: primitive.get_vertex_offset_size(),
+        vertex_offset: primitive.get_vertex_offset(),
+        index_buffer,
+        index_count,
+        index_offset_size,
+        transform: glam::Mat4::IDENTITY, //TODO: Does this work??
+    }
 }
 
 // Scene description buffers used by the raytracing hit shader
@@ -44,10 +124,17 @@ pub struct SceneDescription {
     index_descriptors: Vec<vk::DescriptorBufferInfo>,
     mat_descriptors: Vec<vk::DescriptorBufferInfo>,
     blas_to_instances: HashMap<usize, Vec<usize>>,
+    // Object space bounds of each BLAS, from its mesh.
+    blas_bounds: Vec<crate::scene::Aabb>,
+    // BLAS of each mesh, one per primitive section.
+    mesh_blas: Vec<std::ops::Range<usize>>,
+    // Detail levels of the BLAS which have some, see add_lod_levels.
+    lods: HashMap<usize, BlasLods>,
 }
 
 impl SceneDescription {
-    pub fn from_scene(context: Arc<Context>, scene: &crate::scene::Scene) -> Self {
+    // Fails without ray tracing support, as the other constructors.
+    pub fn from_scene(context: Arc<Context>, scene: &crate::scene::Scene) -> Result<Self> {
         let meshes = scene.meshes.iter().collect::<Vec<_>>();
         let mut transforms = Vec::<glam::Mat4>::new();
         meshes.iter().for_each(|mesh| {
@@ -56,19 +143,88 @@ impl SceneDescription {
         Self::from_meshes(context, meshes, transforms, Some(&scene.material_buffer))
     }
 
+    pub fn from_scene_with_info(
+        context: Arc<Context>,
+        scene: &crate::scene::Scene,
+        build_info: AccelerationBuildInfo,
+    ) -> Result<Self> {
+        let meshes = scene.meshes.iter().collect::<Vec<_>>();
+        let transforms = meshes.iter().map(|mesh| mesh.transform).collect::<Vec<_>>();
+        Self::from_meshes_with_info(
+            context,
+            meshes,
+            transforms,
+            Some(&scene.material_buffer),
+            build_info,
+        )
+    }
+
     pub fn from_meshes(
         context: Arc<Context>,
         meshes: Vec<&crate::scene::Mesh>,
         mesh_transforms: Vec<glam::Mat4>,
         material_buffer: Option<&crate::Buffer>,
-    ) -> Self {
-        let cmd = context.begin_single_time_cmd();
-        let mut blas = Vec::<BLAS>::new();
+    ) -> Result<Self> {
+        Self::from_meshes_with_info(
+            context,
+            meshes,
+            mesh_transforms,
+            material_buffer,
+            AccelerationBuildInfo::default(),
+        )
+    }
+
+    pub fn from_meshes_with_info(
+        context: Arc<Context>,
+        meshes: Vec<&crate::scene::Mesh>,
+        mesh_transforms: Vec<glam::Mat4>,
+        material_buffer: Option<&crate::Buffer>,
+        build_info: AccelerationBuildInfo,
+    ) -> Result<Self> {
+        context.require_ray_tracing()?;
+        let (parts, blas_inputs) = Self::prepare(meshes, &mesh_transforms, material_buffer);
+        let mut blas_builder = BlasBuilder::new(context.clone(), build_info);
+        for input in blas_inputs {
+            input.push_to(&mut blas_builder);
+        }
+        Ok(Self::assemble(context, parts, blas_builder.finish(), &build_info))
+    }
+
+    // Records the BLAS builds in batches, one at a time, see SceneDescriptionBuild.
+    pub fn build_async(
+        context: Arc<Context>,
+        scene: &crate::scene::Scene,
+        build_info: AccelerationBuildInfo,
+        progress: ProgressToken,
+    ) -> Result<SceneDescriptionBuild> {
+        context.require_ray_tracing()?;
+        let meshes = scene.meshes.iter().collect::<Vec<_>>();
+        let transforms = meshes.iter().map(|mesh| mesh.transform).collect::<Vec<_>>();
+        let (parts, blas_inputs) =
+            Self::prepare(meshes, &transforms, Some(&scene.material_buffer));
+        Ok(SceneDescriptionBuild::new(
+            context,
+            parts,
+            blas_inputs,
+            build_info,
+            progress,
+        ))
+    }
+
+    // Gathers the instances, shader descriptors and BLAS geometries of the meshes.
+    fn prepare(
+        meshes: Vec<&crate::scene::Mesh>,
+        mesh_transforms: &[glam::Mat4],
+        material_buffer: Option<&crate::Buffer>,
+    ) -> (SceneDescriptionParts, Vec<BlasInput>) {
+        let mut blas_inputs = Vec::<BlasInput>::new();
         let mut instances = Vec::<SceneInstance>::new();
         let mut vertex_descriptors = Vec::<vk::DescriptorBufferInfo>::new();
         let mut index_descriptors = Vec::<vk::DescriptorBufferInfo>::new();
         let mut mat_descriptors = Vec::<vk::DescriptorBufferInfo>::new();
         let mut blas_to_instances = HashMap::<usize, Vec<usize>>::new();
+        let mut blas_bounds = Vec::new();
+        let mut mesh_blas = Vec::new();
 
         // let min = context
         //     .get_physical_device_limits()
@@ -76,40 +232,18 @@ impl SceneDescription {
         // println!("min storage align {:?}", min);
 
         meshes.iter().enumerate().for_each(|(i, mesh)| {
+            let first_blas = blas_inputs.len();
             for primitive in &mesh.primitive_sections {
-                let mut geo_intances = Vec::<GeometryInstance>::new();
+                let geo_intances = vec![primitive_geometry(mesh, primitive)];
                 let mut instance_indices = Vec::<usize>::new();
-                
-                let (index_buffer, index_count, index_offset_size) = match &mesh.index_buffer {
-                    Some(buffer) => (
-                        Some(buffer.get_device_address()),
-                        Some(primitive.get_index_count()),
-                        Some(primitive.get_index_offset_size::<u32>()),
-                    ),
-                    None => (None, None, None),
-                };
-                geo_intances.push(GeometryInstance {
-                    vertex_buffer: mesh.vertex_buffer.get_device_address(),
-                    vertex_count: primitive.get_vertex_count(),
-                    vertex_offset_size: primitive.get_vertex_offset_size(),
-                    vertex_offset: primitive.get_vertex_offset(),
-                    index_buffer,
-                    index_count,
-                    index_offset_size,
-                    transform: glam::Mat4::IDENTITY, //TODO: Does this work??
-                });
 
                 vertex_descriptors.push(primitive.get_vertex_descriptor(&mesh.vertex_buffer));
-                match &mesh.index_storage {
-                    Some(buffer) => {
-                        index_descriptors.push(primitive.get_index_descriptor::<u64>(buffer));
-                    }
-                    None => {}
+                if let Some(buffer) = &mesh.index_buffer {
+                    index_descriptors.push(primitive.get_index_descriptor::<u32>(buffer));
+                }
+                if let Some(buffer) = &material_buffer {
+                    mat_descriptors.push(primitive.get_material_descriptor(buffer));
                 }
-                match &material_buffer {
-                    Some(buffer) => mat_descriptors.push(primitive.get_material_descriptor(buffer)),
-                    None => {}
-                };
                 let instance = SceneInstance {
                     id: instances.len() as u32,
                     transform: mesh_transforms[i],
@@ -122,36 +256,58 @@ impl SceneDescription {
                 // TODO: support multiple instances per BLAS (move out of primitive loop here)
 
                 // Bottom-level acceleration structure
-                blas.push(BLAS::new(
-                    context.clone(),
-                    cmd,
-                    geo_intances,
-                    mesh_transforms[i],
-                    crate::scene::ModelVertex::stride() as u64,
-                    true,
-                ));
-                blas_to_instances.insert(i as usize, instance_indices);
+                blas_to_instances.insert(blas_inputs.len(), instance_indices);
+                blas_inputs.push(BlasInput {
+                    geometry: geo_intances,
+                    transform: mesh_transforms[i],
+                });
+                blas_bounds.push(mesh.bounds);
             }
+            mesh_blas.push(first_blas..blas_inputs.len());
         });
 
-        let tlas = TLAS::new(context.clone(), cmd, &blas);
+        let parts = SceneDescriptionParts {
+            instances,
+            vertex_descriptors,
+            index_descriptors,
+            mat_descriptors,
+            blas_to_instances,
+            blas_bounds,
+            mesh_blas,
+        };
+        (parts, blas_inputs)
+    }
+
+    // Builds the TLAS over the built BLAS.
+    fn assemble(
+        context: Arc<Context>,
+        parts: SceneDescriptionParts,
+        blas: Vec<BLAS>,
+        build_info: &AccelerationBuildInfo,
+    ) -> Self {
+        let cmd = context.begin_single_time_cmd();
+        let tlas = TLAS::with_build_info(context.clone(), cmd, &blas, build_info);
         context.end_single_time_cmd(cmd);
 
         let instances_buffer = crate::Buffer::from_data(
             context.clone(),
             crate::BufferInfo::default().cpu_to_gpu().usage_storage(),
-            &instances,
-        );
+            &parts.instances,
+        )
+        .expect("Unable to create instances buffer");
 
         SceneDescription {
             blas,
             tlas,
-            instances,
+            instances: parts.instances,
             instances_buffer,
-            vertex_descriptors,
-            index_descriptors,
-            mat_descriptors,
-            blas_to_instances,
+            vertex_descriptors: parts.vertex_descriptors,
+            index_descriptors: parts.index_descriptors,
+            mat_descriptors: parts.mat_descriptors,
+            blas_to_instances: parts.blas_to_instances,
+            blas_bounds: parts.blas_bounds,
+            mesh_blas: parts.mesh_blas,
+            lods: HashMap::new(),
         }
     }
 
@@ -175,15 +331,85 @@ impl SceneDescription {
             });
     }
 
+    // Moves the BLAS of the scene meshes to their Mesh::transform, e.g. with the meshes moved by
+    // Scene::update_transforms. The instances buffer (update) and the TLAS must then be updated,
+    // a refit is enough.
+    pub fn update_mesh_transforms(&mut self, scene: &crate::scene::Scene, meshes: &[usize]) {
+        for mesh in meshes {
+            for index in self.mesh_blas[*mesh].clone() {
+                self.blas_transform(scene.meshes[*mesh].transform, index);
+            }
+        }
+    }
+
+    // BLAS indices of a mesh, in primitive section order.
+    pub fn mesh_blas(&self, mesh: usize) -> std::ops::Range<usize> {
+        self.mesh_blas[mesh].clone()
+    }
+
+    // Per instance TLAS settings, see BLAS::set_mask, set_hit_group_index and
+    // set_instance_flags. They apply with the next TLAS update, a refit is enough.
+    pub fn set_mask(&mut self, index: usize, mask: u8) {
+        self.blas[index].set_mask(mask);
+    }
+
+    pub fn set_hit_group(&mut self, index: usize, hit_group_index: u32) {
+        self.blas[index].set_hit_group_index(hit_group_index);
+    }
+
+    pub fn set_flags(&mut self, index: usize, flags: vk::GeometryInstanceFlagsKHR) {
+        self.blas[index].set_instance_flags(flags);
+    }
+
+    // Without FORCE_OPAQUE, the geometry flags of the BLAS decide whether any-hit shaders run.
+    pub fn set_force_opaque(&mut self, index: usize, force_opaque: bool) {
+        let flags = self.blas[index].get_instance_flags();
+        let flags = match force_opaque {
+            true => flags | vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+            false => flags & !vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+        };
+        self.blas[index].set_instance_flags(flags);
+    }
+
     pub fn tlas_regenerate(&mut self, cmd: vk::CommandBuffer) {
         self.tlas
             .regenerate(cmd, &self.blas);
     }
 
+    // Requires the scene to be built with `AccelerationBuildInfo::allow_update`.
+    pub fn tlas_refit(&mut self, cmd: vk::CommandBuffer) {
+        self.tlas_update(cmd, TlasUpdateMode::Refit);
+    }
+
+    pub fn tlas_update(&mut self, cmd: vk::CommandBuffer, mode: TlasUpdateMode) {
+        self.tlas.update(cmd, &self.blas, mode);
+    }
+
+    // Builds the TLAS from a subset of the BLAS, see TlasSubset.
+    pub fn tlas_update_subset(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        indices: &[usize],
+        mode: TlasUpdateMode,
+    ) {
+        self.tlas.update_subset(cmd, &self.blas, indices, mode);
+    }
+
+    // Records an in-place update of a BLAS whose vertices changed, see BLAS::update. The TLAS
+    // must then be updated too.
+    pub fn blas_update(&mut self, cmd: vk::CommandBuffer, index: usize, mode: TlasUpdateMode) {
+        self.blas[index].update(cmd, mode);
+    }
+
     pub fn blas(&self) -> &Vec<BLAS> {
         &self.blas
     }
 
+    // World space bounds of a BLAS at its current transform.
+    pub fn blas_bounds(&self, index: usize) -> crate::scene::Aabb {
+        self.blas_bounds[index].transform(&self.blas[index].get_transform())
+    }
+
     pub fn get_instances_buffer(&self) -> &crate::Buffer {
         &self.instances_buffer
     }
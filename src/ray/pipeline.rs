@@ -1,10 +1,41 @@
-use crate::{pipeline::Shader, Context, Resource};
+use crate::{
+    pipeline::{collect_dependencies, compile_shaders, is_modified_since},
+    Context, Resource, Result,
+};
 use ash::{vk};
-use std::{ffi::CString, path::PathBuf, sync::Arc};
+use std::{ffi::CString, path::PathBuf, sync::Arc, time::SystemTime};
 
+// Hit group made of several shaders. Groups with an intersection shader are procedural, for
+// AABB geometry, the others are triangle hit groups (e.g. closest hit with an alpha testing any
+// hit).
+#[derive(Clone, Debug, Default)]
+pub struct HitGroupInfo {
+    pub closest_hit: Option<PathBuf>,
+    pub any_hit: Option<PathBuf>,
+    pub intersection: Option<PathBuf>,
+}
+
+impl HitGroupInfo {
+    pub fn closest_hit(mut self, path: PathBuf) -> Self {
+        self.closest_hit = Some(path);
+        self
+    }
+    pub fn any_hit(mut self, path: PathBuf) -> Self {
+        self.any_hit = Some(path);
+        self
+    }
+    pub fn intersection(mut self, path: PathBuf) -> Self {
+        self.intersection = Some(path);
+        self
+    }
+}
+
+// Each of the `shaders` is its own shader group (a hit group for hit stages), followed by the
+// `hit_groups`: the group indices of the shader binding table follow that order.
 pub struct PipelineInfo {
     pub layout: vk::PipelineLayout,
     pub shaders: Vec<(PathBuf, vk::ShaderStageFlags)>,
+    pub hit_groups: Vec<HitGroupInfo>,
     pub name: String,
     pub specialization_data: Vec<u8>,
     pub specialization_entries: Vec<vk::SpecializationMapEntry>,
@@ -15,6 +46,7 @@ impl Default for PipelineInfo {
         PipelineInfo {
             layout: vk::PipelineLayout::default(),
             shaders: Vec::new(),
+            hit_groups: Vec::new(),
             name: "".to_string(),
             specialization_data: Vec::new(),
             specialization_entries: Vec::new(),
@@ -31,6 +63,10 @@ impl PipelineInfo {
         self.shaders.push((path, stage_flags));
         self
     }
+    pub fn hit_group(mut self, hit_group: HitGroupInfo) -> Self {
+        self.hit_groups.push(hit_group);
+        self
+    }
     pub fn name(mut self, name: String) -> Self {
         self.name = name.to_string();
         self
@@ -55,45 +91,64 @@ pub struct Pipeline {
     context: Arc<Context>,
     info: PipelineInfo,
     pipeline: vk::Pipeline,
+    dependencies: Vec<PathBuf>,
+    build_time: SystemTime,
 }
 
 impl Pipeline {
-    pub fn new(context: Arc<Context>, info: PipelineInfo) -> Self {
-        let mut shaders = Vec::<Shader>::new();
-        let mut stages = Vec::new();
+    pub fn new(context: Arc<Context>, info: PipelineInfo) -> Result<Self> {
+        context.require_ray_tracing()?;
+        let build_time = SystemTime::now();
+        // Stages are listed with the groups, then compiled in parallel.
+        let mut stage_files = Vec::new();
+        let mut add_stage = |path: &PathBuf, stage_flags| {
+            stage_files.push((path.clone(), stage_flags));
+            stage_files.len() as u32 - 1
+        };
+
         let mut groups = Vec::new();
+        for (path, stage_flags) in &info.shaders {
+            let hit_group = match *stage_flags {
+                vk::ShaderStageFlags::CLOSEST_HIT_KHR => {
+                    HitGroupInfo::default().closest_hit(path.clone())
+                }
+                vk::ShaderStageFlags::ANY_HIT_KHR => HitGroupInfo::default().any_hit(path.clone()),
+                vk::ShaderStageFlags::INTERSECTION_KHR => {
+                    HitGroupInfo::default().intersection(path.clone())
+                }
+                _ => {
+                    let mut group = unused_shader_group();
+                    group.ty = vk::RayTracingShaderGroupTypeKHR::GENERAL;
+                    group.general_shader = add_stage(path, *stage_flags);
+                    groups.push(group);
+                    continue;
+                }
+            };
+            groups.push(hit_shader_group(&hit_group, &mut add_stage));
+        }
+        for hit_group in &info.hit_groups {
+            groups.push(hit_shader_group(hit_group, &mut add_stage));
+        }
+
+        let shaders = compile_shaders(&context, &stage_files)?;
         let shader_entry_name = CString::new("main").unwrap();
-        for (index, shader_info) in info.shaders.iter().enumerate() {
-            let shader = Shader::new(context.clone(), shader_info.0.clone(), shader_info.1);
-            if info.specialization_entries.is_empty() {
-                stages.push(shader.get_create_info(&shader_entry_name));
-            } else {
-                stages.push(
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&info.specialization_entries)
+            .data(&info.specialization_data)
+            .build();
+        let stages = shaders
+            .iter()
+            .map(|shader| {
+                if info.specialization_entries.is_empty() {
+                    shader.get_create_info(&shader_entry_name)
+                } else {
                     shader.get_create_info_with_specialization(
                         &shader_entry_name,
-                        &vk::SpecializationInfo::builder()
-                            .map_entries(&info.specialization_entries)
-                            .data(&info.specialization_data),
-                    ),
-                );
-            }
-            shaders.push(shader);
-
-            let mut group = vk::RayTracingShaderGroupCreateInfoKHR::builder()
-                .general_shader(vk::SHADER_UNUSED_KHR)
-                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
-                .any_hit_shader(vk::SHADER_UNUSED_KHR)
-                .intersection_shader(vk::SHADER_UNUSED_KHR)
-                .build();
-            if shader_info.1 == vk::ShaderStageFlags::CLOSEST_HIT_KHR {
-                group.ty = vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP;
-                group.closest_hit_shader = index as u32;
-            } else {
-                group.ty = vk::RayTracingShaderGroupTypeKHR::GENERAL;
-                group.general_shader = index as u32;
-            }
-            groups.push(group);
-        }
+                        &specialization_info,
+                    )
+                }
+            })
+            .collect::<Vec<_>>();
         // TODO: fetch from somewhere
         let max_recursion_depth = 8;
         let create_info = vk::RayTracingPipelineCreateInfoKHR::builder()
@@ -110,15 +165,26 @@ impl Pipeline {
                     vk::PipelineCache::null(),
                     &[create_info],
                     None
-                )
-                .expect("Unable to create graphics pipeline")[0]
+                )?[0]
         };
+        context.debug_marker().set_name(pipeline, &info.name);
 
-        Pipeline {
+        let dependencies = collect_dependencies(&shaders);
+        Ok(Pipeline {
             context,
             info,
             pipeline,
-        }
+            dependencies,
+            build_time,
+        })
+    }
+
+    pub fn dependencies(&self) -> &[PathBuf] {
+        &self.dependencies
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        is_modified_since(&self.dependencies, self.build_time)
     }
 
     pub fn update_specialization<T>(&mut self, data: &T) {
@@ -129,6 +195,36 @@ impl Pipeline {
     }
 }
 
+fn unused_shader_group() -> vk::RayTracingShaderGroupCreateInfoKHR {
+    vk::RayTracingShaderGroupCreateInfoKHR::builder()
+        .general_shader(vk::SHADER_UNUSED_KHR)
+        .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+        .any_hit_shader(vk::SHADER_UNUSED_KHR)
+        .intersection_shader(vk::SHADER_UNUSED_KHR)
+        .build()
+}
+
+fn hit_shader_group(
+    hit_group: &HitGroupInfo,
+    add_stage: &mut impl FnMut(&PathBuf, vk::ShaderStageFlags) -> u32,
+) -> vk::RayTracingShaderGroupCreateInfoKHR {
+    let mut group = unused_shader_group();
+    group.ty = match hit_group.intersection {
+        Some(_) => vk::RayTracingShaderGroupTypeKHR::PROCEDURAL_HIT_GROUP,
+        None => vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP,
+    };
+    if let Some(path) = &hit_group.closest_hit {
+        group.closest_hit_shader = add_stage(path, vk::ShaderStageFlags::CLOSEST_HIT_KHR);
+    }
+    if let Some(path) = &hit_group.any_hit {
+        group.any_hit_shader = add_stage(path, vk::ShaderStageFlags::ANY_HIT_KHR);
+    }
+    if let Some(path) = &hit_group.intersection {
+        group.intersection_shader = add_stage(path, vk::ShaderStageFlags::INTERSECTION_KHR);
+    }
+    group
+}
+
 impl Resource<vk::Pipeline> for Pipeline {
     fn handle(&self) -> vk::Pipeline {
         self.pipeline
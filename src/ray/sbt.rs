@@ -1,19 +1,29 @@
-use crate::{Buffer, BufferInfo, Context};
+use crate::{align_up, Buffer, BufferInfo, Context, Result, StagingBelt};
 use ash::vk;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 // https://developer.nvidia.com/rtx/raytracing/vkray_helpers
 // https://nvpro-samples.github.io/vk_raytracing_tutorial_KHR/#shaderbindingtable
 // This implementation is now mostly lifted from https://github.com/EmbarkStudios/kajiya/blob/main/crates/lib/kajiya-backend/src/vulkan/ray_tracing.rs
 
-pub fn align_up(x: u32, a: u32) -> u32 {
-    (x + (a - 1)) & !(a - 1)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderGroupRegion {
+    Raygen,
+    Miss,
+    HitGroup,
+    Callable,
 }
 
+// Records of each region, as pipeline shader group indices.
 pub struct ShaderBindingTableInfo {
     pub raygen_indices: Vec<u64>,
     pub miss_indices: Vec<u64>,
     pub hit_group_indices: Vec<u64>,
+    pub callable_indices: Vec<u64>,
+    // Shader record data following the group handle, by region and record index, read in
+    // shaders through a `shaderRecordEXT` buffer block.
+    pub record_data: HashMap<(ShaderGroupRegion, usize), Vec<u8>>,
 }
 
 impl Default for ShaderBindingTableInfo {
@@ -22,6 +32,8 @@ impl Default for ShaderBindingTableInfo {
             raygen_indices: Vec::new(),
             miss_indices: Vec::new(),
             hit_group_indices: Vec::new(),
+            callable_indices: Vec::new(),
+            record_data: HashMap::new(),
         }
     }
 }
@@ -39,22 +51,49 @@ impl ShaderBindingTableInfo {
         self.hit_group_indices.push(index);
         self
     }
+    pub fn callable(mut self, index: u64) -> Self {
+        self.callable_indices.push(index);
+        self
+    }
 
-    fn raygen_count(&self) -> usize {
-        self.miss_indices.len()
+    // Hit group record with its own data, e.g. the material of a per-material hit shader.
+    pub fn hitgroup_with_data<T: Copy>(mut self, index: u64, data: &T) -> Self {
+        let slice = unsafe {
+            std::slice::from_raw_parts(data as *const T as *const u8, std::mem::size_of_val(data))
+        };
+        self.hit_group_indices.push(index);
+        let record = self.hit_group_indices.len() - 1;
+        self.record_data(ShaderGroupRegion::HitGroup, record, slice)
     }
-    fn miss_count(&self) -> usize {
-        self.raygen_indices.len()
+
+    pub fn record_data(mut self, region: ShaderGroupRegion, record: usize, data: &[u8]) -> Self {
+        self.record_data.insert((region, record), data.to_vec());
+        self
     }
-    fn hitgroup_count(&self) -> usize {
-        self.hit_group_indices.len()
+
+    fn indices(&self, region: ShaderGroupRegion) -> &[u64] {
+        match region {
+            ShaderGroupRegion::Raygen => &self.raygen_indices,
+            ShaderGroupRegion::Miss => &self.miss_indices,
+            ShaderGroupRegion::HitGroup => &self.hit_group_indices,
+            ShaderGroupRegion::Callable => &self.callable_indices,
+        }
     }
+
     fn get_total_group_count(&self) -> usize {
-        self.raygen_count() + self.miss_count() + self.hitgroup_count()
+        self.raygen_indices
+            .iter()
+            .chain(&self.miss_indices)
+            .chain(&self.hit_group_indices)
+            .chain(&self.callable_indices)
+            .map(|index| *index as usize + 1)
+            .max()
+            .unwrap_or(0)
     }
 }
 
-// Always internally stores raygen -> miss -> hit groups.
+// One buffer per region. Records are the group handle followed by the record data, with a
+// stride large enough for the largest record of the region.
 pub struct ShaderBindingTable {
     context: Arc<Context>,
     pub raygen_sbt_address: vk::StridedDeviceAddressRegionKHR,
@@ -68,11 +107,16 @@ pub struct ShaderBindingTable {
 }
 
 impl ShaderBindingTable {
-    pub fn new(context: Arc<Context>,  pipeline: vk::Pipeline, info: ShaderBindingTableInfo) -> Self {
-        let shader_group_handle_size = 
-            unsafe{ context.ray_tracing_properties().shader_group_handle_size as usize };
-        let group_count = info.get_total_group_count() as usize;
-        let group_handles_size = (shader_group_handle_size * group_count) as usize;
+    pub fn new(
+        context: Arc<Context>,
+        pipeline: vk::Pipeline,
+        info: ShaderBindingTableInfo,
+    ) -> Result<Self> {
+        context.require_ray_tracing()?;
+        let properties = *context.ray_tracing_properties();
+        let shader_group_handle_size = properties.shader_group_handle_size as usize;
+        let group_count = info.get_total_group_count();
+        let group_handles_size = shader_group_handle_size * group_count;
 
         let group_handles: Vec<u8> = unsafe {
             context.ray_tracing()
@@ -84,84 +128,93 @@ impl ShaderBindingTable {
                 ).unwrap()
         };
 
-        let prog_size = shader_group_handle_size;
-
-        let create_binding_table =
-            |context: Arc<Context>, entry_offset: u32, entry_count: u32|
-             -> Option<Buffer> {
-                if 0 == entry_count {
-                    return None;
+        let create_binding_table = |region: ShaderGroupRegion|
+             -> (Option<Buffer>, vk::StridedDeviceAddressRegionKHR) {
+                let indices = info.indices(region);
+                if indices.is_empty() {
+                    return (None, vk::StridedDeviceAddressRegionKHR::default());
                 }
-
-                let mut sbt_data =
-                    vec![0u8; (entry_count as usize * prog_size) as _];
-
-                for dst in 0..(entry_count as usize) {
-                    let src = dst + entry_offset as usize;
-                    sbt_data
-                        [dst * prog_size..dst * prog_size + shader_group_handle_size]
-                        .copy_from_slice(
-                            &group_handles[src * shader_group_handle_size
-                                ..src * shader_group_handle_size + shader_group_handle_size],
-                        );
+                let data_size = (0..indices.len())
+                    .filter_map(|record| info.record_data.get(&(region, record)))
+                    .map(|data| data.len())
+                    .max()
+                    .unwrap_or(0);
+                let stride = align_up(
+                    (shader_group_handle_size + data_size) as u64,
+                    properties.shader_group_handle_alignment as u64,
+                ) as usize;
+
+                let mut sbt_data = vec![0u8; indices.len() * stride];
+                for (record, index) in indices.iter().enumerate() {
+                    let src = *index as usize * shader_group_handle_size;
+                    let dst = record * stride;
+                    sbt_data[dst..dst + shader_group_handle_size]
+                        .copy_from_slice(&group_handles[src..src + shader_group_handle_size]);
+                    if let Some(data) = info.record_data.get(&(region, record)) {
+                        let dst = dst + shader_group_handle_size;
+                        sbt_data[dst..dst + data.len()].copy_from_slice(data);
+                    }
                 }
 
-                Some(Buffer::from_data(
+                // Padded so that the region can start at shaderGroupBaseAlignment.
+                let base_alignment = properties.shader_group_base_alignment as u64;
+                let buffer = Buffer::new(
                     context.clone(),
                     BufferInfo::default().gpu_only().usage(
-                        vk::BufferUsageFlags::TRANSFER_SRC
+                        vk::BufferUsageFlags::TRANSFER_DST
                             | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                             | vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR,
                     ),
-                    &sbt_data
-                ))
+                    sbt_data.len() as u64 + base_alignment,
+                    1,
+                )
+                .expect("Unable to create shader binding table");
+                let buffer_address = buffer.get_device_address();
+                let device_address = align_up(buffer_address, base_alignment);
+                let mut staging = StagingBelt::new(context.clone(), sbt_data.len() as u64);
+                staging
+                    .upload(&buffer, device_address - buffer_address, &sbt_data)
+                    .expect("Unable to upload shader binding table");
+                staging.finish();
+
+                // The raygen region holds a single record, see `raygen_region`.
+                let size = match region {
+                    ShaderGroupRegion::Raygen => stride,
+                    _ => sbt_data.len(),
+                };
+                let address = vk::StridedDeviceAddressRegionKHR {
+                    device_address,
+                    stride: stride as u64,
+                    size: size as u64,
+                };
+                (Some(buffer), address)
             };
 
-        let raygen_sbt_buffer = create_binding_table(context.clone(), 0, info.raygen_count() as u32);
-        let miss_sbt_buffer = create_binding_table(context.clone(), 
-            info.raygen_count() as u32,
-            info.miss_count() as u32);
-        let hit_sbt_buffer = create_binding_table(context.clone(),
-            (info.raygen_count() + info.miss_count()) as u32,
-            info.hitgroup_count() as u32,
-        );
+        let (raygen_sbt_buffer, raygen_sbt_address) =
+            create_binding_table(ShaderGroupRegion::Raygen);
+        let (miss_sbt_buffer, miss_sbt_address) = create_binding_table(ShaderGroupRegion::Miss);
+        let (hit_sbt_buffer, hit_sbt_address) = create_binding_table(ShaderGroupRegion::HitGroup);
+        let (callable_sbt_buffer, callable_sbt_address) =
+            create_binding_table(ShaderGroupRegion::Callable);
 
-        ShaderBindingTable {
+        Ok(ShaderBindingTable {
             context,
-            raygen_sbt_address: vk::StridedDeviceAddressRegionKHR {
-                device_address: raygen_sbt_buffer
-                    .as_ref()
-                    .map(|b| b.get_device_address())
-                    .unwrap_or(0),
-                stride: prog_size as u64,
-                size: (prog_size * info.raygen_count() as usize) as u64,
-            },
+            raygen_sbt_address,
             raygen_sbt_buffer,
-            miss_sbt_address: vk::StridedDeviceAddressRegionKHR {
-                device_address: miss_sbt_buffer
-                    .as_ref()
-                    .map(|b| b.get_device_address())
-                    .unwrap_or(0),
-                stride: prog_size as u64,
-                size: (prog_size * info.miss_count() as usize) as u64,
-            },
+            miss_sbt_address,
             miss_sbt_buffer,
-            hit_sbt_address: vk::StridedDeviceAddressRegionKHR {
-                device_address: hit_sbt_buffer
-                    .as_ref()
-                    .map(|b| b.get_device_address())
-                    .unwrap_or(0),
-                stride: prog_size as u64,
-                size: (prog_size * info.hitgroup_count() as usize) as u64,
-            },
+            hit_sbt_address,
             hit_sbt_buffer,
-            callable_sbt_address: vk::StridedDeviceAddressRegionKHR {
-                device_address: Default::default(),
-                stride: 0,
-                size: 0,
-            },
-            callable_sbt_buffer: None,
-        }
+            callable_sbt_address,
+            callable_sbt_buffer,
+        })
+    }
+
+    // Region of the raygen record `record`, for tables with several raygen shaders.
+    pub fn raygen_region(&self, record: usize) -> vk::StridedDeviceAddressRegionKHR {
+        let mut region = self.raygen_sbt_address;
+        region.device_address += record as u64 * region.stride;
+        region
     }
 
     pub fn cmd_trace_rays(&self, cmd: vk::CommandBuffer, extent: vk::Extent3D) {
@@ -0,0 +1,126 @@
+use super::SceneDescription;
+use crate::{DescriptorSetInfo, DescriptorSetLayoutInfo, Resource};
+use ash::vk;
+
+// Binding numbers of the SceneDescription descriptors in a pass set, None for those the shaders
+// don't read. Arrays have one element per instance (vertices, materials) or per indexed instance
+// (indices), matching the scene being bound.
+#[derive(Clone, Copy, Debug)]
+pub struct SceneBindings {
+    pub tlas: Option<u32>,
+    pub instances: Option<u32>,
+    pub vertices: Option<u32>,
+    pub indices: Option<u32>,
+    pub materials: Option<u32>,
+    pub tlas_stages: vk::ShaderStageFlags,
+    pub geometry_stages: vk::ShaderStageFlags,
+}
+
+impl Default for SceneBindings {
+    fn default() -> Self {
+        SceneBindings {
+            tlas: None,
+            instances: None,
+            vertices: None,
+            indices: None,
+            materials: None,
+            tlas_stages: vk::ShaderStageFlags::RAYGEN_KHR,
+            geometry_stages: vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+        }
+    }
+}
+
+impl SceneBindings {
+    pub fn tlas(mut self, binding: u32) -> Self {
+        self.tlas = Some(binding);
+        self
+    }
+    pub fn instances(mut self, binding: u32) -> Self {
+        self.instances = Some(binding);
+        self
+    }
+    pub fn vertices(mut self, binding: u32) -> Self {
+        self.vertices = Some(binding);
+        self
+    }
+    pub fn indices(mut self, binding: u32) -> Self {
+        self.indices = Some(binding);
+        self
+    }
+    pub fn materials(mut self, binding: u32) -> Self {
+        self.materials = Some(binding);
+        self
+    }
+    pub fn tlas_stages(mut self, stages: vk::ShaderStageFlags) -> Self {
+        self.tlas_stages = stages;
+        self
+    }
+    pub fn geometry_stages(mut self, stages: vk::ShaderStageFlags) -> Self {
+        self.geometry_stages = stages;
+        self
+    }
+}
+
+impl SceneDescription {
+    // Layout bindings of the scene descriptors, with the array sizes of this scene. The other
+    // bindings of the pass are added to the returned info.
+    pub fn descriptor_set_layout_info(&self, bindings: &SceneBindings) -> DescriptorSetLayoutInfo {
+        let mut info = DescriptorSetLayoutInfo::default();
+        if let Some(binding) = bindings.tlas {
+            info = info.binding(
+                binding,
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                bindings.tlas_stages,
+            );
+        }
+        if let Some(binding) = bindings.instances {
+            info = info.binding(
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                bindings.geometry_stages,
+            );
+        }
+        for (binding, descriptors) in self.descriptor_arrays(bindings) {
+            info = info.bindings(
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                bindings.geometry_stages,
+                descriptors.len() as u32,
+            );
+        }
+        info
+    }
+
+    // Writes of the scene descriptors, for a layout from descriptor_set_layout_info with the
+    // same bindings. The other descriptors of the pass are added to the returned info.
+    pub fn descriptor_set_info(&self, bindings: &SceneBindings) -> DescriptorSetInfo {
+        let mut info = DescriptorSetInfo::default();
+        if let Some(binding) = bindings.tlas {
+            info = info.accel_struct(binding, self.tlas().handle());
+        }
+        if let Some(binding) = bindings.instances {
+            info = info.buffer(binding, self.get_instances_buffer().get_descriptor_info());
+        }
+        for (binding, descriptors) in self.descriptor_arrays(bindings) {
+            info = info.buffers(binding, descriptors.clone());
+        }
+        info
+    }
+
+    // Empty arrays (e.g. materials of scenes built without material buffer, indices of
+    // non-indexed meshes) are skipped: bindings can't have zero descriptors.
+    fn descriptor_arrays(
+        &self,
+        bindings: &SceneBindings,
+    ) -> Vec<(u32, &Vec<vk::DescriptorBufferInfo>)> {
+        vec![
+            (bindings.vertices, self.get_vertex_descriptors()),
+            (bindings.indices, self.get_index_descriptors()),
+            (bindings.materials, self.get_material_descriptors()),
+        ]
+        .into_iter()
+        .filter_map(|(binding, descriptors)| binding.map(|binding| (binding, descriptors)))
+        .filter(|(_, descriptors)| !descriptors.is_empty())
+        .collect()
+    }
+}
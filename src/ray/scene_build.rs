@@ -0,0 +1,107 @@
+use super::{
+    AccelerationBuildInfo, BlasBuilder, BlasInput, SceneDescription, SceneDescriptionParts, BLAS,
+};
+use crate::{Context, Error, ProgressToken, Result};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+// BLAS recorded per submit: cancellation and progress apply between batches.
+const BLAS_BATCH_SIZE: usize = 64;
+
+// A SceneDescription built incrementally, see SceneDescription::build_async. The BLAS are built
+// in batches on the queue of the build info: `poll` submits the next batch once the previous one
+// completed, so the app keeps rendering meanwhile, and builds the TLAS after the last one.
+pub struct SceneDescriptionBuild {
+    context: Arc<Context>,
+    parts: Option<SceneDescriptionParts>,
+    pending: VecDeque<BlasInput>,
+    builder: Option<BlasBuilder>,
+    blas: Vec<BLAS>,
+    blas_count: usize,
+    build_info: AccelerationBuildInfo,
+    progress: ProgressToken,
+}
+
+impl SceneDescriptionBuild {
+    pub(super) fn new(
+        context: Arc<Context>,
+        parts: SceneDescriptionParts,
+        blas_inputs: Vec<BlasInput>,
+        build_info: AccelerationBuildInfo,
+        progress: ProgressToken,
+    ) -> Self {
+        SceneDescriptionBuild {
+            context,
+            parts: Some(parts),
+            blas_count: blas_inputs.len(),
+            pending: blas_inputs.into(),
+            builder: None,
+            blas: Vec::new(),
+            build_info,
+            progress,
+        }
+    }
+
+    pub fn progress(&self) -> &ProgressToken {
+        &self.progress
+    }
+
+    // Returns None while BLAS builds are in flight, then the scene description, or
+    // Error::Cancelled once the progress token was cancelled (the in-flight batch is waited for).
+    pub fn poll(&mut self) -> Option<Result<SceneDescription>> {
+        self.parts.as_ref()?;
+        if self.progress.is_cancelled() {
+            self.cancel_pending();
+            return Some(Err(Error::Cancelled));
+        }
+        if let Some(builder) = &self.builder {
+            if !builder.is_complete() {
+                return None;
+            }
+            let builder = self.builder.take().unwrap();
+            self.blas.extend(builder.finish());
+        }
+        self.progress.report_steps(
+            "Building acceleration structures",
+            self.blas.len(),
+            self.blas_count,
+        );
+        if !self.pending.is_empty() {
+            let mut builder = BlasBuilder::new(self.context.clone(), self.build_info);
+            let batch_size = self.pending.len().min(BLAS_BATCH_SIZE);
+            for input in self.pending.drain(..batch_size) {
+                input.push_to(&mut builder);
+            }
+            builder.submit();
+            self.builder = Some(builder);
+            return None;
+        }
+        let parts = self.parts.take().unwrap();
+        let blas = std::mem::take(&mut self.blas);
+        let scene = SceneDescription::assemble(self.context.clone(), parts, blas, &self.build_info);
+        self.progress.report("Done", 1.0);
+        Some(Ok(scene))
+    }
+
+    // Blocks until the remaining batches are built.
+    pub fn finish(mut self) -> Result<SceneDescription> {
+        loop {
+            if let Some(builder) = self.builder.take() {
+                self.blas.extend(builder.finish());
+            }
+            match self.poll() {
+                Some(result) => return result,
+                None if self.parts.is_none() => panic!("SceneDescriptionBuild already finished."),
+                None => {}
+            }
+        }
+    }
+
+    fn cancel_pending(&mut self) {
+        self.pending.clear();
+        // Dropping the builder waits for its submitted batch.
+        self.builder = None;
+        self.blas.clear();
+        self.parts = None;
+    }
+}
@@ -0,0 +1,367 @@
+use super::{SceneDescription, ShaderBindingTable, ShaderBindingTableInfo};
+use crate::{
+    Context, DescriptorSetInfo, DescriptorSetLayout, DescriptorSetLayoutInfo, GpuPhase, Image2d,
+    Resource, Result, SamplerInfo, Shader,
+};
+use ash::vk;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// 1.0 where the light is visible, 0.0 in shadow.
+pub const SHADOW_MASK_FORMAT: vk::Format = vk::Format::R8_UNORM;
+
+// Storage image receiving the shadow mask, sampled by the lighting pass.
+pub fn create_shadow_mask_image(context: &Arc<Context>, extent: vk::Extent3D) -> Result<Image2d> {
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(SHADOW_MASK_FORMAT)
+        .extent(extent)
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    Image2d::new(
+        context.shared().clone(),
+        &image_info,
+        vk::ImageAspectFlags::COLOR,
+        1,
+        "ShadowMask",
+    )
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ShadowLight {
+    // Direction towards the light.
+    Directional(glam::Vec3),
+    Point(glam::Vec3),
+}
+
+const SHADOW_RGEN: &str = "
+#version 460
+#extension GL_EXT_ray_tracing : require
+layout(set = 0, binding = 0) uniform accelerationStructureEXT topLevelAS;
+layout(set = 0, binding = 1) uniform sampler2D depthTexture;
+layout(set = 0, binding = 2, r8) uniform writeonly image2D shadowMask;
+layout(push_constant) uniform Params {
+    mat4 view_projection_inverse;
+    vec4 light;
+    float bias;
+    float max_distance;
+} params;
+layout(location = 0) rayPayloadEXT float visibility;
+void main() {
+    ivec2 pixel = ivec2(gl_LaunchIDEXT.xy);
+    vec2 uv = (vec2(pixel) + 0.5) / vec2(gl_LaunchSizeEXT.xy);
+    float depth = textureLod(depthTexture, uv, 0.0).r;
+    if (depth >= 1.0) {
+        imageStore(shadowMask, pixel, vec4(1.0));
+        return;
+    }
+    vec4 world = params.view_projection_inverse * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    vec3 position = world.xyz / world.w;
+    vec3 direction = normalize(params.light.xyz);
+    float t_max = params.max_distance;
+    if (params.light.w != 0.0) {
+        vec3 to_light = params.light.xyz - position;
+        t_max = length(to_light);
+        direction = to_light / t_max;
+    }
+    visibility = 0.0;
+    uint flags = gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT
+        | gl_RayFlagsSkipClosestHitShaderEXT;
+    traceRayEXT(topLevelAS, flags, 0xff, 0, 0, 0, position, params.bias, direction, t_max, 0);
+    imageStore(shadowMask, pixel, vec4(visibility));
+}
+";
+
+const SHADOW_RMISS: &str = "
+#version 460
+#extension GL_EXT_ray_tracing : require
+layout(location = 0) rayPayloadInEXT float visibility;
+void main() {
+    visibility = 1.0;
+}
+";
+
+#[repr(C)]
+struct ShadowParams {
+    view_projection_inverse: glam::Mat4,
+    light: glam::Vec4,
+    bias: f32,
+    max_distance: f32,
+}
+
+// Hybrid shadows: traces a shadow ray per pixel of the raster depth buffer against the TLAS of a
+// SceneDescription, and writes a shadow mask for the lighting pass. Rays skip hit shaders, so the
+// scene hit groups aren't needed.
+pub struct ShadowPass {
+    context: Arc<Context>,
+    layout: DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    sbt: ShaderBindingTable,
+    sampler: vk::Sampler,
+    // Ray start offset, in world units, avoiding self-intersections.
+    pub bias: f32,
+    // Ray length of directional lights.
+    pub max_distance: f32,
+}
+
+impl ShadowPass {
+    pub fn new(context: Arc<Context>) -> Result<Self> {
+        context.require_ray_tracing()?;
+        let device = context.device();
+        let layout = DescriptorSetLayout::new(
+            context.clone(),
+            DescriptorSetLayoutInfo::default()
+                .binding(
+                    0,
+                    vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                    vk::ShaderStageFlags::RAYGEN_KHR,
+                )
+                .binding(
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    vk::ShaderStageFlags::RAYGEN_KHR,
+                )
+                .binding(
+                    2,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    vk::ShaderStageFlags::RAYGEN_KHR,
+                )
+                .name("Shadow pass".to_string()),
+        );
+        let set_layouts = [layout.handle()];
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
+            .size(std::mem::size_of::<ShadowParams>() as u32)
+            .build()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() };
+
+        let rgen = Shader::from_source(
+            context.clone(),
+            SHADOW_RGEN,
+            "shadow.rgen",
+            vk::ShaderStageFlags::RAYGEN_KHR,
+        )
+        .expect("Unable to compile shadow shader");
+        let rmiss = Shader::from_source(
+            context.clone(),
+            SHADOW_RMISS,
+            "shadow.rmiss",
+            vk::ShaderStageFlags::MISS_KHR,
+        )
+        .expect("Unable to compile shadow shader");
+        let entry_name = CString::new("main").unwrap();
+        let stages = [
+            rgen.get_create_info(&entry_name),
+            rmiss.get_create_info(&entry_name),
+        ];
+        let groups: Vec<_> = (0..stages.len() as u32)
+            .map(|index| {
+                vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                    .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                    .general_shader(index)
+                    .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .intersection_shader(vk::SHADER_UNUSED_KHR)
+                    .build()
+            })
+            .collect();
+        let create_info = vk::RayTracingPipelineCreateInfoKHR::builder()
+            .stages(&stages)
+            .groups(&groups)
+            .max_pipeline_ray_recursion_depth(1)
+            .layout(pipeline_layout)
+            .build();
+        let pipeline = unsafe {
+            context
+                .ray_tracing()
+                .create_ray_tracing_pipelines(
+                    vk::DeferredOperationKHR::null(),
+                    vk::PipelineCache::null(),
+                    &[create_info],
+                    None,
+                )
+                .expect("Unable to create shadow pipeline")[0]
+        };
+        context.debug_marker().set_name(pipeline, "Shadow pass");
+        let sbt = ShaderBindingTable::new(
+            context.clone(),
+            pipeline,
+            ShaderBindingTableInfo::default().raygen(0).miss(1),
+        )?;
+        let sampler = SamplerInfo::default()
+            .filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .clamp_to_edge()
+            .no_anisotropy()
+            .create_sampler(&context, 1);
+
+        Ok(ShadowPass {
+            context,
+            layout,
+            pipeline_layout,
+            pipeline,
+            sbt,
+            sampler,
+            bias: 0.01,
+            max_distance: 10000.0,
+        })
+    }
+
+    // Records the shadow rays after the raster pass that wrote `depth`, which must be sampleable
+    // in its current layout (e.g. DEPTH_STENCIL_READ_ONLY_OPTIMAL). `view_projection` is the one
+    // used to rasterize `depth`. The mask is left in GENERAL layout, readable by fragment and
+    // compute shaders.
+    pub fn cmd_trace(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        scene: &SceneDescription,
+        depth: &Image2d,
+        shadow_mask: &mut Image2d,
+        view_projection: glam::Mat4,
+        light: ShadowLight,
+    ) {
+        shadow_mask.transition_image_layout(
+            cmd,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::GENERAL,
+        );
+        let depth_info = vk::DescriptorImageInfo::builder()
+            .sampler(self.sampler)
+            .image_view(depth.get_image_view())
+            .image_layout(depth.get_layout())
+            .build();
+        let desc_set = self.layout.get_or_create(
+            DescriptorSetInfo::default()
+                .accel_struct(0, scene.tlas().handle())
+                .image(1, depth_info)
+                .image(2, shadow_mask.get_descriptor_info()),
+        );
+        let params = ShadowParams {
+            view_projection_inverse: view_projection.inverse(),
+            light: match light {
+                ShadowLight::Directional(direction) => direction.extend(0.0),
+                ShadowLight::Point(position) => position.extend(1.0),
+            },
+            bias: self.bias,
+            max_distance: self.max_distance,
+        };
+        self.context.track_hazards(|tracker| {
+            let (image, name) = (depth.handle(), depth.get_name());
+            tracker.barrier(
+                image,
+                name,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::AccessFlags::SHADER_READ,
+                None,
+            );
+            tracker.read(
+                image,
+                name,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::AccessFlags::SHADER_READ,
+            );
+        });
+        let device = self.context.device();
+        unsafe {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::RAY_TRACING_KHR, self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.pipeline_layout,
+                0,
+                &[desc_set.handle()],
+                &[],
+            );
+            let bytes = std::slice::from_raw_parts(
+                &params as *const ShadowParams as *const u8,
+                std::mem::size_of::<ShadowParams>(),
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                0,
+                bytes,
+            );
+        }
+        let extent = shadow_mask.get_extent();
+        self.context.begin_gpu_phase(cmd, GpuPhase::Main);
+        self.sbt.cmd_trace_rays(
+            cmd,
+            vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        );
+        self.context.end_gpu_phase(cmd, GpuPhase::Main);
+        self.context.track_hazards(|tracker| {
+            let (image, name) = (shadow_mask.handle(), shadow_mask.get_name());
+            tracker.write(
+                image,
+                name,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::AccessFlags::SHADER_WRITE,
+            );
+            tracker.barrier(
+                image,
+                name,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                None,
+            );
+        });
+        unsafe {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+}
+
+impl Drop for ShadowPass {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.context.shared().retire_handle(self.sampler);
+    }
+}
@@ -0,0 +1,147 @@
+use super::{SceneDescription, TlasUpdateMode};
+use crate::scene::Aabb;
+use ash::vk;
+
+// Input of a TlasInclusionPolicy for one BLAS of a SceneDescription.
+pub struct TlasCandidate {
+    pub index: usize,
+    // World space bounds at the current BLAS transform.
+    pub bounds: Aabb,
+    // Whether the instance is part of the current subset, e.g. for hysteresis.
+    pub included: bool,
+}
+
+// Decides which instances are part of the TLAS. Closures taking a &TlasCandidate can be used
+// directly.
+pub trait TlasInclusionPolicy {
+    fn include(&mut self, candidate: &TlasCandidate) -> bool;
+}
+
+impl<F: FnMut(&TlasCandidate) -> bool> TlasInclusionPolicy for F {
+    fn include(&mut self, candidate: &TlasCandidate) -> bool {
+        self(candidate)
+    }
+}
+
+// Includes the instances whose bounding sphere intersects the camera frustum expanded by
+// `margin` (world units) and within `max_distance` of the eye. The margin keeps instances
+// slightly off-screen for reflections and shadows, and avoids a rebuild per camera move.
+#[derive(Clone, Copy, Debug)]
+pub struct FrustumPolicy {
+    planes: [glam::Vec4; 6],
+    eye: glam::Vec3,
+    margin: f32,
+    max_distance: f32,
+}
+
+impl FrustumPolicy {
+    // `view_projection` with a [0, 1] depth range, like Camera::perspective_matrix.
+    pub fn new(view_projection: glam::Mat4, eye: glam::Vec3) -> Self {
+        let rows = [
+            view_projection.row(0),
+            view_projection.row(1),
+            view_projection.row(2),
+            view_projection.row(3),
+        ];
+        let planes = [
+            rows[3] + rows[0],
+            rows[3] - rows[0],
+            rows[3] + rows[1],
+            rows[3] - rows[1],
+            rows[2],
+            rows[3] - rows[2],
+        ]
+        .map(|plane| plane / plane.truncate().length());
+        FrustumPolicy {
+            planes,
+            eye,
+            margin: 0.0,
+            max_distance: f32::MAX,
+        }
+    }
+
+    pub fn margin(mut self, margin: f32) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn max_distance(mut self, max_distance: f32) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    pub fn contains_sphere(&self, center: glam::Vec3, radius: f32) -> bool {
+        let reach = radius + self.margin;
+        center.distance(self.eye) - radius <= self.max_distance
+            && self
+                .planes
+                .iter()
+                .all(|plane| plane.truncate().dot(center) + plane.w >= -reach)
+    }
+}
+
+impl TlasInclusionPolicy for FrustumPolicy {
+    fn include(&mut self, candidate: &TlasCandidate) -> bool {
+        !candidate.bounds.is_empty()
+            && self.contains_sphere(candidate.bounds.center(), candidate.bounds.radius())
+    }
+}
+
+// Builds the TLAS of a SceneDescription from the instances selected by a policy, for scenes too
+// large to trace entirely. The selection is updated incrementally as the camera moves: the TLAS
+// is only rebuilt when instances enter or leave the subset, and refit otherwise. The TLAS keeps
+// the capacity of the full scene.
+#[derive(Default)]
+pub struct TlasSubset {
+    included: Vec<bool>,
+    indices: Vec<usize>,
+}
+
+impl TlasSubset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Indices of the BLAS in the current subset, in increasing order.
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.included.get(index).copied().unwrap_or(false)
+    }
+
+    // Evaluates the policy for every BLAS and records the TLAS update. Returns true when the
+    // subset changed. `mode` applies when it didn't, e.g. Refit for moving instances.
+    pub fn update(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        scene: &mut SceneDescription,
+        policy: &mut impl TlasInclusionPolicy,
+        mode: TlasUpdateMode,
+    ) -> bool {
+        let count = scene.blas().len();
+        self.included.resize(count, false);
+        let mut changed = false;
+        for index in 0..count {
+            let candidate = TlasCandidate {
+                index,
+                bounds: scene.blas_bounds(index),
+                included: self.included[index],
+            };
+            let include = policy.include(&candidate);
+            changed |= include != candidate.included;
+            self.included[index] = include;
+        }
+        if changed {
+            self.indices = (0..count).filter(|i| self.included[*i]).collect();
+        }
+        let mode = if changed {
+            TlasUpdateMode::Rebuild
+        } else {
+            mode
+        };
+        scene.tlas_update_subset(cmd, &self.indices, mode);
+        changed
+    }
+}
@@ -0,0 +1,124 @@
+use super::{SceneDescription, TlasUpdateMode};
+use ash::vk;
+use std::collections::HashMap;
+
+// Initial GPU cost estimate of a BLAS update, until measured.
+const DEFAULT_MICROSECONDS_PER_PRIMITIVE: f32 = 0.01;
+// Weight of the last measurement in the cost estimate.
+const COST_SMOOTHING: f32 = 0.25;
+// Priority gained per frame spent waiting, so low priority updates still happen.
+const PRIORITY_PER_FRAME: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug)]
+struct DirtyBlas {
+    mode: TlasUpdateMode,
+    priority: f32,
+    marked_frame: u64,
+}
+
+// Spreads the updates of dirty BLAS (e.g. animated meshes) across frames, so that many of
+// them don't cause a hitch. Each frame, the highest priority updates are recorded until the
+// estimated GPU time reaches the budget. The estimate (microseconds per triangle) is refined
+// from the measured GpuPhase::AccelerationStructures time with `feedback`.
+pub struct BlasUpdateScheduler {
+    budget_us: f32,
+    us_per_primitive: f32,
+    dirty: HashMap<usize, DirtyBlas>,
+    frame: u64,
+    // Triangles updated by the last `record`, for the next feedback.
+    recorded_primitives: u64,
+}
+
+impl BlasUpdateScheduler {
+    pub fn new(budget_us: f32) -> Self {
+        BlasUpdateScheduler {
+            budget_us,
+            us_per_primitive: DEFAULT_MICROSECONDS_PER_PRIMITIVE,
+            dirty: HashMap::new(),
+            frame: 0,
+            recorded_primitives: 0,
+        }
+    }
+
+    pub fn set_budget(&mut self, budget_us: f32) {
+        self.budget_us = budget_us;
+    }
+
+    // Higher priorities are updated first, e.g. from the screen size of the instance. Marking
+    // a dirty BLAS again keeps the highest priority, and a rebuild over a refit.
+    pub fn mark_dirty(&mut self, index: usize, mode: TlasUpdateMode, priority: f32) {
+        let frame = self.frame;
+        self.dirty
+            .entry(index)
+            .and_modify(|dirty| {
+                dirty.priority = dirty.priority.max(priority);
+                if mode == TlasUpdateMode::Rebuild {
+                    dirty.mode = mode;
+                }
+            })
+            .or_insert(DirtyBlas {
+                mode,
+                priority,
+                marked_frame: frame,
+            });
+    }
+
+    pub fn is_dirty(&self, index: usize) -> bool {
+        self.dirty.contains_key(&index)
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.dirty.len()
+    }
+
+    // Estimated GPU time of the update of `primitive_count` triangles.
+    pub fn estimate_us(&self, primitive_count: u64) -> f32 {
+        primitive_count as f32 * self.us_per_primitive
+    }
+
+    // Records the updates of the frame and returns the updated BLAS indices, at least one while
+    // some are pending. The TLAS must then be updated (a refit is enough, the BLAS handles
+    // don't change).
+    pub fn record(&mut self, cmd: vk::CommandBuffer, scene: &mut SceneDescription) -> Vec<usize> {
+        self.frame += 1;
+        let frame = self.frame;
+        let mut queue = self
+            .dirty
+            .iter()
+            .map(|(index, dirty)| {
+                let age = (frame - dirty.marked_frame) as f32;
+                (*index, dirty.priority + age * PRIORITY_PER_FRAME)
+            })
+            .collect::<Vec<_>>();
+        queue.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut spent_us = 0.0;
+        let mut updated = Vec::new();
+        self.recorded_primitives = 0;
+        for (index, _) in queue {
+            let primitive_count = scene.blas()[index].get_primitive_count();
+            let cost_us = self.estimate_us(primitive_count);
+            if !updated.is_empty() && spent_us + cost_us > self.budget_us {
+                continue;
+            }
+            let dirty = self.dirty.remove(&index).unwrap();
+            scene.blas_update(cmd, index, dirty.mode);
+            spent_us += cost_us;
+            self.recorded_primitives += primitive_count;
+            updated.push(index);
+        }
+        updated
+    }
+
+    // GpuPhase::AccelerationStructures time of the frame of the last `record`, e.g. from
+    // AppRenderer::gpu_phase_timings once that frame completed. It includes the TLAS update,
+    // which makes the estimate slightly conservative.
+    pub fn feedback(&mut self, acceleration_structures_ms: f32) {
+        if self.recorded_primitives == 0 {
+            return;
+        }
+        let measured = acceleration_structures_ms * 1000.0 / self.recorded_primitives as f32;
+        self.us_per_primitive += (measured - self.us_per_primitive) * COST_SMOOTHING;
+        self.recorded_primitives = 0;
+    }
+}
@@ -0,0 +1,109 @@
+use crate::Context;
+use ash::vk;
+use std::sync::Arc;
+
+// Records draw commands once into per-frame secondary command buffers and replays them every
+// frame until invalidated. Per-frame data (camera, etc.) should live in buffers referenced by
+// the recorded descriptor sets, so updating their contents doesn't require re-recording.
+pub struct StaticSceneRecorder {
+    context: Arc<Context>,
+    pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    recorded: Vec<bool>,
+}
+
+impl StaticSceneRecorder {
+    pub fn new(context: Arc<Context>, frame_count: usize) -> Self {
+        let pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(context.shared().queue_family_indices.graphics);
+        unsafe {
+            let pool = context
+                .device()
+                .create_command_pool(&pool_create_info, None)
+                .unwrap();
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(frame_count as u32)
+                .command_pool(pool)
+                .level(vk::CommandBufferLevel::SECONDARY);
+            let command_buffers = context
+                .device()
+                .allocate_command_buffers(&allocate_info)
+                .unwrap();
+            StaticSceneRecorder {
+                context,
+                pool,
+                command_buffers,
+                recorded: vec![false; frame_count],
+            }
+        }
+    }
+
+    pub fn is_recorded(&self, frame_index: usize) -> bool {
+        self.recorded[frame_index]
+    }
+
+    // Forces every frame to be re-recorded on next use, e.g. after a resize or a scene edit.
+    pub fn invalidate(&mut self) {
+        self.recorded.iter_mut().for_each(|r| *r = false);
+    }
+
+    pub fn record<F: FnOnce(vk::CommandBuffer)>(
+        &mut self,
+        frame_index: usize,
+        render_pass: vk::RenderPass,
+        record_fn: F,
+    ) {
+        let cmd = self.command_buffers[frame_index];
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(render_pass)
+            .subpass(0);
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+            .inheritance_info(&inheritance_info);
+        unsafe {
+            let device = self.context.device();
+            device
+                .reset_command_buffer(cmd, vk::CommandBufferResetFlags::default())
+                .expect("Reset static command buffer failed.");
+            device
+                .begin_command_buffer(cmd, &begin_info)
+                .expect("Begin static commands.");
+            record_fn(cmd);
+            device
+                .end_command_buffer(cmd)
+                .expect("End static commands.");
+        }
+        self.recorded[frame_index] = true;
+    }
+
+    // Must be called inside a render pass begun with SubpassContents::SECONDARY_COMMAND_BUFFERS.
+    // Records the frame first if it was never recorded or has been invalidated.
+    pub fn cmd_execute<F: FnOnce(vk::CommandBuffer)>(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        frame_index: usize,
+        render_pass: vk::RenderPass,
+        record_fn: F,
+    ) {
+        if !self.recorded[frame_index] {
+            self.record(frame_index, render_pass, record_fn);
+        }
+        unsafe {
+            self.context
+                .device()
+                .cmd_execute_commands(cmd, &[self.command_buffers[frame_index]]);
+        }
+    }
+}
+
+impl Drop for StaticSceneRecorder {
+    fn drop(&mut self) {
+        unsafe {
+            self.context
+                .device()
+                .free_command_buffers(self.pool, &self.command_buffers);
+            self.context.device().destroy_command_pool(self.pool, None);
+        }
+    }
+}
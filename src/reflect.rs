@@ -0,0 +1,568 @@
+use crate::{
+    Context, DescriptorSetLayout, DescriptorSetLayoutInfo, PipelineLayout, PipelineLayoutInfo,
+    Resource, Shader,
+};
+use ash::vk;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const SPIRV_HEADER_WORDS: usize = 5;
+
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    // 0 for runtime arrays (e.g. `buffer Vertices { .. } vertices[];`), whose size is set with
+    // ReflectedLayout::descriptor_count.
+    pub count: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+// Resource interface of a SPIR-V module: its descriptor bindings and push constant block.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    pub bindings: Vec<ReflectedBinding>,
+    // Size in bytes of the push constant block, 0 without one.
+    pub push_constant_size: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+#[derive(Clone, Debug)]
+enum SpirvType {
+    Scalar(u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage(u32),
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Pointer(u32),
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct SpirvModule {
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    // (pointer type, id, storage class) of the global variables.
+    variables: Vec<(u32, u32, u32)>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+}
+
+impl SpirvModule {
+    fn parse(words: &[u32]) -> Option<Self> {
+        if words.len() < SPIRV_HEADER_WORDS || words[0] != SPIRV_MAGIC {
+            return None;
+        }
+        let mut module = SpirvModule::default();
+        let mut offset = SPIRV_HEADER_WORDS;
+        while offset < words.len() {
+            let word_count = (words[offset] >> 16) as usize;
+            let opcode = words[offset] & 0xffff;
+            if word_count == 0 || offset + word_count > words.len() {
+                return None;
+            }
+            let operands = &words[offset + 1..offset + word_count];
+            module.parse_instruction(opcode, operands);
+            offset += word_count;
+        }
+        Some(module)
+    }
+
+    fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) {
+        let operand = |i: usize| operands.get(i).copied().unwrap_or(0);
+        let ty = match opcode {
+            OP_TYPE_INT | OP_TYPE_FLOAT => SpirvType::Scalar(operand(1) / 8),
+            OP_TYPE_VECTOR => SpirvType::Vector(operand(1), operand(2)),
+            OP_TYPE_MATRIX => SpirvType::Matrix(operand(1), operand(2)),
+            OP_TYPE_IMAGE => SpirvType::Image {
+                dim: operand(2),
+                sampled: operand(6),
+            },
+            OP_TYPE_SAMPLER => SpirvType::Sampler,
+            OP_TYPE_SAMPLED_IMAGE => SpirvType::SampledImage(operand(1)),
+            OP_TYPE_ARRAY => SpirvType::Array(operand(1), operand(2)),
+            OP_TYPE_RUNTIME_ARRAY => SpirvType::RuntimeArray(operand(1)),
+            OP_TYPE_STRUCT => SpirvType::Struct(operands[1.min(operands.len())..].to_vec()),
+            OP_TYPE_POINTER => SpirvType::Pointer(operand(2)),
+            OP_TYPE_ACCELERATION_STRUCTURE => SpirvType::AccelerationStructure,
+            OP_CONSTANT => {
+                self.constants.insert(operand(1), operand(2));
+                return;
+            }
+            OP_VARIABLE => {
+                self.variables.push((operand(0), operand(1), operand(2)));
+                return;
+            }
+            OP_DECORATE => {
+                self.decorations
+                    .insert((operand(0), operand(1)), operand(2));
+                return;
+            }
+            OP_MEMBER_DECORATE => {
+                self.member_decorations
+                    .insert((operand(0), operand(1), operand(2)), operand(3));
+                return;
+            }
+            _ => return,
+        };
+        self.types.insert(operand(0), ty);
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    // Descriptor type and count of a resource variable of `storage_class`.
+    fn descriptor(&self, ty: u32, storage_class: u32) -> Option<(vk::DescriptorType, u32)> {
+        let (ty, count) = match self.types.get(&ty)? {
+            SpirvType::Array(element, length) => {
+                (*element, self.constants.get(length).copied().unwrap_or(1))
+            }
+            SpirvType::RuntimeArray(element) => (*element, 0),
+            _ => (ty, 1),
+        };
+        let descriptor_type = match (self.types.get(&ty)?, storage_class) {
+            (SpirvType::Sampler, _) => vk::DescriptorType::SAMPLER,
+            (SpirvType::SampledImage(_), _) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (SpirvType::Image { dim, sampled }, _) => match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            (SpirvType::AccelerationStructure, _) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            (SpirvType::Struct(_), STORAGE_CLASS_STORAGE_BUFFER) => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (SpirvType::Struct(_), STORAGE_CLASS_UNIFORM) => {
+                // Storage buffers were Uniform BufferBlocks before SPIR-V 1.3.
+                if self.decoration(ty, DECORATION_BUFFER_BLOCK).is_some() {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else if self.decoration(ty, DECORATION_BLOCK).is_some() {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                } else {
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+        Some((descriptor_type, count))
+    }
+
+    // Size in bytes of a type with explicit layout, from its Offset and stride decorations.
+    fn size_of(&self, ty: u32) -> u32 {
+        match self.types.get(&ty) {
+            Some(SpirvType::Scalar(size)) => *size,
+            Some(SpirvType::Vector(component, count)) => self.size_of(*component) * count,
+            Some(SpirvType::Matrix(column, count)) => self.size_of(*column) * count,
+            Some(SpirvType::Array(element, length)) => {
+                let length = self.constants.get(length).copied().unwrap_or(1);
+                match self.decoration(ty, DECORATION_ARRAY_STRIDE) {
+                    Some(stride) => stride * length,
+                    None => self.size_of(*element) * length,
+                }
+            }
+            Some(SpirvType::Struct(members)) => members
+                .iter()
+                .enumerate()
+                .map(|(i, member)| {
+                    let i = i as u32;
+                    let offset = self
+                        .member_decorations
+                        .get(&(ty, i, DECORATION_OFFSET))
+                        .copied()
+                        .unwrap_or(0);
+                    let size = match (
+                        self.types.get(member),
+                        self.member_decorations
+                            .get(&(ty, i, DECORATION_MATRIX_STRIDE)),
+                    ) {
+                        (Some(SpirvType::Matrix(_, columns)), Some(stride)) => stride * columns,
+                        _ => self.size_of(*member),
+                    };
+                    offset + size
+                })
+                .max()
+                .unwrap_or(0),
+            // Buffer references (PhysicalStorageBuffer pointers).
+            Some(SpirvType::Pointer(_)) => 8,
+            _ => 0,
+        }
+    }
+}
+
+impl ShaderReflection {
+    // Malformed modules give an empty reflection.
+    pub fn from_spirv(words: &[u32], stage_flags: vk::ShaderStageFlags) -> Self {
+        let module = match SpirvModule::parse(words) {
+            Some(module) => module,
+            None => {
+                return ShaderReflection {
+                    stage_flags,
+                    ..Default::default()
+                }
+            }
+        };
+        let mut reflection = ShaderReflection {
+            stage_flags,
+            ..Default::default()
+        };
+        for (pointer, id, storage_class) in &module.variables {
+            let ty = match module.types.get(pointer) {
+                Some(SpirvType::Pointer(ty)) => *ty,
+                _ => continue,
+            };
+            match *storage_class {
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    reflection.push_constant_size = module.size_of(ty);
+                }
+                STORAGE_CLASS_UNIFORM_CONSTANT
+                | STORAGE_CLASS_UNIFORM
+                | STORAGE_CLASS_STORAGE_BUFFER => {
+                    let set = module.decoration(*id, DECORATION_DESCRIPTOR_SET);
+                    let binding = module.decoration(*id, DECORATION_BINDING);
+                    let descriptor = module.descriptor(ty, *storage_class);
+                    if let (Some(set), Some(binding), Some((descriptor_type, count))) =
+                        (set, binding, descriptor)
+                    {
+                        reflection.bindings.push(ReflectedBinding {
+                            set,
+                            binding,
+                            descriptor_type,
+                            count,
+                            stage_flags,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        reflection
+    }
+}
+
+// Descriptor set and pipeline layouts derived from the reflection of a shader set, so bindings
+// are only declared in GLSL. Stages using the same binding are merged. Overrides cover what
+// SPIR-V doesn't tell, e.g. dynamic uniform buffers or the size of runtime arrays.
+#[derive(Clone, Debug, Default)]
+pub struct ReflectedLayout {
+    // Bindings per set, sets without bindings are left empty.
+    sets: Vec<BTreeMap<u32, ReflectedBinding>>,
+    push_constant_size: u32,
+    push_constant_stages: vk::ShaderStageFlags,
+    bindless: Vec<(u32, u32)>,
+}
+
+impl ReflectedLayout {
+    pub fn from_shaders(shaders: &[&Shader]) -> Self {
+        let mut layout = ReflectedLayout::default();
+        for shader in shaders {
+            layout.merge(shader.reflection());
+        }
+        layout
+    }
+
+    pub fn merge(&mut self, reflection: &ShaderReflection) {
+        for binding in &reflection.bindings {
+            let set = binding.set as usize;
+            if self.sets.len() <= set {
+                self.sets.resize(set + 1, BTreeMap::new());
+            }
+            self.sets[set]
+                .entry(binding.binding)
+                .and_modify(|merged| {
+                    assert_eq!(
+                        merged.descriptor_type, binding.descriptor_type,
+                        "Set {} binding {} has different types across stages.",
+                        binding.set, binding.binding
+                    );
+                    merged.stage_flags |= binding.stage_flags;
+                    merged.count = merged.count.max(binding.count);
+                })
+                .or_insert(*binding);
+        }
+        if reflection.push_constant_size > 0 {
+            self.push_constant_size = self.push_constant_size.max(reflection.push_constant_size);
+            self.push_constant_stages |= reflection.stage_flags;
+        }
+    }
+
+    // Overrides the reflected type, e.g. UNIFORM_BUFFER_DYNAMIC for a UNIFORM_BUFFER.
+    pub fn descriptor_type(
+        mut self,
+        set: u32,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+    ) -> Self {
+        self.binding_mut(set, binding).descriptor_type = descriptor_type;
+        self
+    }
+
+    pub fn descriptor_count(mut self, set: u32, binding: u32, count: u32) -> Self {
+        self.binding_mut(set, binding).count = count;
+        self
+    }
+
+    // See DescriptorSetLayoutInfo::bindless.
+    pub fn bindless(mut self, set: u32, binding: u32, max_count: u32) -> Self {
+        self.binding_mut(set, binding).count = max_count;
+        self.bindless.push((set, binding));
+        self
+    }
+
+    // Stages are also merged by the shaders, e.g. for bindings only some stages declare.
+    pub fn stage_flags(
+        mut self,
+        set: u32,
+        binding: u32,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Self {
+        self.binding_mut(set, binding).stage_flags |= stage_flags;
+        self
+    }
+
+    fn binding_mut(&mut self, set: u32, binding: u32) -> &mut ReflectedBinding {
+        self.sets
+            .get_mut(set as usize)
+            .and_then(|bindings| bindings.get_mut(&binding))
+            .unwrap_or_else(|| panic!("No reflected binding {} in set {}.", binding, set))
+    }
+
+    pub fn set_count(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn bindings(&self, set: u32) -> impl Iterator<Item = &ReflectedBinding> {
+        self.sets
+            .get(set as usize)
+            .into_iter()
+            .flat_map(|b| b.values())
+    }
+
+    pub fn set_layout_info(&self, set: u32) -> DescriptorSetLayoutInfo {
+        let mut info = DescriptorSetLayoutInfo::default().name(format!("Reflected set {}", set));
+        for binding in self.bindings(set) {
+            assert!(
+                binding.count > 0,
+                "Set {} binding {} is a runtime array, see ReflectedLayout::descriptor_count.",
+                set,
+                binding.binding
+            );
+            info = match self.bindless.contains(&(set, binding.binding)) {
+                true => info.bindless(
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.stage_flags,
+                    binding.count,
+                ),
+                false => info.bindings(
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.stage_flags,
+                    binding.count,
+                ),
+            };
+        }
+        info
+    }
+
+    pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        match self.push_constant_size {
+            0 => Vec::new(),
+            size => vec![vk::PushConstantRange::builder()
+                .stage_flags(self.push_constant_stages)
+                .offset(0)
+                .size(size)
+                .build()],
+        }
+    }
+
+    // One layout per set, including the empty ones in between.
+    pub fn create_set_layouts(&self, context: &Arc<Context>) -> Vec<DescriptorSetLayout> {
+        (0..self.sets.len() as u32)
+            .map(|set| DescriptorSetLayout::new(context.clone(), self.set_layout_info(set)))
+            .collect()
+    }
+
+    pub fn pipeline_layout_info(&self, set_layouts: &[DescriptorSetLayout]) -> PipelineLayoutInfo {
+        let handles = set_layouts.iter().map(|l| l.handle()).collect::<Vec<_>>();
+        PipelineLayoutInfo::default()
+            .desc_set_layouts(&handles)
+            .push_constant_ranges(&self.push_constant_ranges())
+    }
+
+    pub fn create_pipeline_layout(
+        &self,
+        context: &Arc<Context>,
+        set_layouts: &[DescriptorSetLayout],
+    ) -> PipelineLayout {
+        PipelineLayout::new(context.clone(), self.pipeline_layout_info(set_layouts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shaderc::{CompileOptions, Compiler, EnvVersion, ShaderKind, TargetEnv};
+
+    fn reflect_compute(source: &str, env_version: EnvVersion) -> ShaderReflection {
+        let mut compiler = Compiler::new().unwrap();
+        let mut options = CompileOptions::new().unwrap();
+        options.set_target_env(TargetEnv::Vulkan, env_version as u32);
+        let code = compiler
+            .compile_into_spirv(
+                source,
+                ShaderKind::Compute,
+                "test.comp",
+                "main",
+                Some(&options),
+            )
+            .unwrap();
+        ShaderReflection::from_spirv(code.as_binary(), vk::ShaderStageFlags::COMPUTE)
+    }
+
+    fn binding(
+        set: u32,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+    ) -> ReflectedBinding {
+        ReflectedBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+        }
+    }
+
+    const RESOURCES: &str = "
+        #version 460
+        layout(local_size_x = 1) in;
+        layout(set = 0, binding = 0) uniform Ubo { vec4 color; } ubo;
+        layout(set = 0, binding = 1) buffer Ssbo { vec4 values[4]; } ssbo;
+        layout(set = 1, binding = 0) uniform sampler2D tex;
+        layout(set = 1, binding = 2, rgba8) uniform image2D image;
+        void main() {
+            vec4 color = ubo.color + ssbo.values[0] + textureLod(tex, vec2(0.0), 0.0);
+            imageStore(image, ivec2(0), color);
+        }
+    ";
+
+    #[test]
+    fn resources() {
+        let expected = vec![
+            binding(0, 0, vk::DescriptorType::UNIFORM_BUFFER, 1),
+            binding(0, 1, vk::DescriptorType::STORAGE_BUFFER, 1),
+            binding(1, 0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+            binding(1, 2, vk::DescriptorType::STORAGE_IMAGE, 1),
+        ];
+        let reflection = reflect_compute(RESOURCES, EnvVersion::Vulkan1_2);
+        assert_eq!(reflection.bindings, expected);
+        assert_eq!(reflection.push_constant_size, 0);
+        // Storage buffers are Uniform BufferBlocks in SPIR-V 1.0.
+        let reflection = reflect_compute(RESOURCES, EnvVersion::Vulkan1_0);
+        assert_eq!(reflection.bindings, expected);
+    }
+
+    #[test]
+    fn arrays_and_acceleration_structures() {
+        let reflection = reflect_compute(
+            "
+            #version 460
+            #extension GL_EXT_nonuniform_qualifier : require
+            #extension GL_EXT_ray_query : require
+            layout(local_size_x = 1) in;
+            layout(set = 0, binding = 0) uniform accelerationStructureEXT tlas;
+            layout(set = 0, binding = 1) buffer Lights { vec4 l[]; } lights[3];
+            layout(set = 0, binding = 2) buffer Vertices { vec4 v[]; } vertices[];
+            void main() {
+                uint index = uint(lights[1].l[0].x);
+                vertices[nonuniformEXT(index)].v[0] = lights[2].l[0];
+                rayQueryEXT query;
+                rayQueryInitializeEXT(query, tlas, 0, 0xff, vec3(0.0), 0.0, vec3(1.0), 1.0);
+            }
+            ",
+            EnvVersion::Vulkan1_2,
+        );
+        assert_eq!(
+            reflection.bindings,
+            vec![
+                binding(0, 0, vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, 1),
+                binding(0, 1, vk::DescriptorType::STORAGE_BUFFER, 3),
+                binding(0, 2, vk::DescriptorType::STORAGE_BUFFER, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn push_constants() {
+        let reflection = reflect_compute(
+            "
+            #version 460
+            layout(local_size_x = 1) in;
+            layout(push_constant) uniform PushConstants { vec2 scale; mat4 transform; } push;
+            layout(set = 0, binding = 0) buffer Output { vec4 value; } result;
+            void main() {
+                result.value = push.transform * vec4(push.scale, 0.0, 1.0);
+            }
+            ",
+            EnvVersion::Vulkan1_2,
+        );
+        // The mat4 starts at offset 16, after the vec2 and its padding.
+        assert_eq!(reflection.push_constant_size, 80);
+        assert_eq!(
+            reflection.bindings,
+            vec![binding(0, 0, vk::DescriptorType::STORAGE_BUFFER, 1)]
+        );
+        assert_eq!(reflection.stage_flags, vk::ShaderStageFlags::COMPUTE);
+    }
+
+    #[test]
+    fn malformed_module() {
+        let reflection = ShaderReflection::from_spirv(&[0; 8], vk::ShaderStageFlags::COMPUTE);
+        assert!(reflection.bindings.is_empty());
+        assert_eq!(reflection.push_constant_size, 0);
+    }
+}
@@ -0,0 +1,297 @@
+// Light-weight alternative to the ad-hoc Scene struct for larger apps: meshes, materials, lights,
+// instances and cameras are stored in slot maps with stable handles, and read by systems such as
+// the render extraction (Registry::extract_draws) and the TLAS sync (TlasSync). Enabled with the
+// `registry` feature.
+use crate::ray::{AccelerationBuildInfo, SceneDescription, TlasUpdateMode};
+use crate::scene::{Camera, MaterialInfo, Mesh, ObjectData, ObjectDraw, ObjectTable, Scene};
+pub use crate::scene::{Light, LightKind};
+use crate::{Buffer, Context, Result};
+use slotmap::{new_key_type, SlotMap};
+use std::sync::Arc;
+
+new_key_type! {
+    pub struct MeshHandle;
+    pub struct MaterialHandle;
+    pub struct LightHandle;
+    pub struct InstanceHandle;
+    pub struct CameraHandle;
+}
+
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub mesh: MeshHandle,
+    pub transform: glam::Mat4,
+    // One entry per primitive section of the mesh, None for sections without material.
+    pub materials: Vec<Option<MaterialHandle>>,
+    pub visible: bool,
+    // Registry tick of the last change, for the systems' change detection.
+    changed: u64,
+}
+
+impl Instance {
+    pub fn new(mesh: MeshHandle, transform: glam::Mat4) -> Self {
+        Instance {
+            mesh,
+            transform,
+            materials: Vec::new(),
+            visible: true,
+            changed: 0,
+        }
+    }
+
+    pub fn materials(mut self, materials: Vec<Option<MaterialHandle>>) -> Self {
+        self.materials = materials;
+        self
+    }
+}
+
+// Handles of the items inserted by Registry::insert_scene, in the order of the Scene vectors.
+pub struct SceneHandles {
+    pub meshes: Vec<MeshHandle>,
+    pub materials: Vec<MaterialHandle>,
+    pub instances: Vec<InstanceHandle>,
+    pub camera: Option<CameraHandle>,
+}
+
+// Per instance input of a render pass, see Registry::extract_draws.
+pub struct DrawItem<'a> {
+    pub instance: InstanceHandle,
+    pub mesh: &'a Mesh,
+    pub transform: glam::Mat4,
+    pub materials: &'a [Option<MaterialHandle>],
+}
+
+#[derive(Default)]
+pub struct Registry {
+    pub meshes: SlotMap<MeshHandle, Mesh>,
+    pub materials: SlotMap<MaterialHandle, MaterialInfo>,
+    pub lights: SlotMap<LightHandle, Light>,
+    pub cameras: SlotMap<CameraHandle, Camera>,
+    pub active_camera: Option<CameraHandle>,
+    // Instances are only mutable through instance_mut, which records the change.
+    instances: SlotMap<InstanceHandle, Instance>,
+    // Incremented by every structural change (instance inserted or removed).
+    structure: u64,
+    tick: u64,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Moves the meshes, materials and camera of a loaded scene into the registry, with one
+    // instance per mesh at its glTF transform.
+    pub fn insert_scene(&mut self, scene: Scene) -> SceneHandles {
+        let materials: Vec<_> = scene
+            .materials
+            .into_iter()
+            .map(|material| self.materials.insert(material))
+            .collect();
+        let mut meshes = Vec::new();
+        let mut instances = Vec::new();
+        for mesh in scene.meshes {
+            let section_materials = mesh
+                .primitive_sections
+                .iter()
+                .map(|section| section.get_material_index().map(|index| materials[index]))
+                .collect();
+            let transform = mesh.transform;
+            let handle = self.meshes.insert(mesh);
+            meshes.push(handle);
+            instances.push(
+                self.insert_instance(Instance::new(handle, transform).materials(section_materials)),
+            );
+        }
+        let camera = scene.camera.map(|camera| {
+            let handle = self.cameras.insert(camera);
+            self.active_camera.get_or_insert(handle);
+            handle
+        });
+        SceneHandles {
+            meshes,
+            materials,
+            instances,
+            camera,
+        }
+    }
+
+    pub fn insert_instance(&mut self, mut instance: Instance) -> InstanceHandle {
+        assert!(self.meshes.contains_key(instance.mesh));
+        self.tick += 1;
+        self.structure += 1;
+        instance.changed = self.tick;
+        self.instances.insert(instance)
+    }
+
+    pub fn remove_instance(&mut self, handle: InstanceHandle) -> Option<Instance> {
+        let instance = self.instances.remove(handle);
+        if instance.is_some() {
+            self.structure += 1;
+        }
+        instance
+    }
+
+    // Also removes the instances of the mesh.
+    pub fn remove_mesh(&mut self, handle: MeshHandle) -> Option<Mesh> {
+        let count = self.instances.len();
+        self.instances.retain(|_, instance| instance.mesh != handle);
+        if self.instances.len() != count {
+            self.structure += 1;
+        }
+        self.meshes.remove(handle)
+    }
+
+    pub fn instance(&self, handle: InstanceHandle) -> Option<&Instance> {
+        self.instances.get(handle)
+    }
+
+    pub fn instance_mut(&mut self, handle: InstanceHandle) -> Option<&mut Instance> {
+        self.tick += 1;
+        let tick = self.tick;
+        let instance = self.instances.get_mut(handle)?;
+        instance.changed = tick;
+        Some(instance)
+    }
+
+    pub fn set_transform(&mut self, handle: InstanceHandle, transform: glam::Mat4) {
+        if let Some(instance) = self.instance_mut(handle) {
+            instance.transform = transform;
+        }
+    }
+
+    pub fn instances(&self) -> impl Iterator<Item = (InstanceHandle, &Instance)> {
+        self.instances.iter()
+    }
+
+    pub fn camera(&self) -> Option<&Camera> {
+        self.active_camera
+            .and_then(|handle| self.cameras.get(handle))
+    }
+
+    pub fn camera_mut(&mut self) -> Option<&mut Camera> {
+        self.active_camera
+            .and_then(move |handle| self.cameras.get_mut(handle))
+    }
+
+    // Render extraction: the visible instances, grouped by mesh to limit buffer binds.
+    pub fn extract_draws(&self) -> Vec<DrawItem<'_>> {
+        let mut draws: Vec<_> = self
+            .instances
+            .iter()
+            .filter(|(_, instance)| instance.visible)
+            .filter_map(|(handle, instance)| {
+                self.meshes.get(instance.mesh).map(|mesh| DrawItem {
+                    instance: handle,
+                    mesh,
+                    transform: instance.transform,
+                    materials: &instance.materials,
+                })
+            })
+            .collect();
+        draws.sort_by_key(|draw| draw.mesh as *const Mesh as usize);
+        draws
+    }
+
+    // Render extraction with the object index convention: pushes one object per primitive
+    // section of the visible instances. `material_index` maps the materials to the indices of
+    // the material buffer read by the shaders.
+    pub fn extract_objects(
+        &self,
+        table: &mut ObjectTable,
+        material_index: impl Fn(MaterialHandle) -> Option<usize>,
+    ) -> Vec<ObjectDraw<'_>> {
+        let mut objects = Vec::new();
+        for draw in self.extract_draws() {
+            for (index, section) in draw.mesh.primitive_sections.iter().enumerate() {
+                let material = draw
+                    .materials
+                    .get(index)
+                    .copied()
+                    .flatten()
+                    .and_then(&material_index);
+                objects.push(ObjectDraw {
+                    mesh: draw.mesh,
+                    section,
+                    object_index: table.push(ObjectData::new(draw.transform, material)),
+                });
+            }
+        }
+        objects
+    }
+}
+
+// Keeps a SceneDescription (BLAS, TLAS and hit shader instances) in sync with the registry
+// instances. Hidden instances are kept in the TLAS.
+pub struct TlasSync {
+    order: Vec<InstanceHandle>,
+    structure: u64,
+    tick: u64,
+}
+
+impl TlasSync {
+    // One BLAS per instance, in registry iteration order. The material buffer is indexed by the
+    // primitive section material indices, e.g. the Scene::material_buffer of an inserted scene.
+    pub fn build(
+        context: Arc<Context>,
+        registry: &Registry,
+        material_buffer: Option<&Buffer>,
+        build_info: AccelerationBuildInfo,
+    ) -> Result<(Self, SceneDescription)> {
+        let (order, instances): (Vec<_>, Vec<_>) = registry.instances.iter().unzip();
+        let meshes = instances
+            .iter()
+            .map(|instance| &registry.meshes[instance.mesh])
+            .collect();
+        let transforms = instances
+            .iter()
+            .map(|instance| instance.transform)
+            .collect();
+        let scene = SceneDescription::from_meshes_with_info(
+            context,
+            meshes,
+            transforms,
+            material_buffer,
+            build_info,
+        )?;
+        let sync = TlasSync {
+            order,
+            structure: registry.structure,
+            tick: registry.tick,
+        };
+        Ok((sync, scene))
+    }
+
+    // True when instances were added or removed since build: the SceneDescription is stale and
+    // must be built again.
+    pub fn needs_rebuild(&self, registry: &Registry) -> bool {
+        self.structure != registry.structure
+    }
+
+    // Copies the transforms changed since the last sync to the scene and its instances buffer.
+    // Returns the TLAS update to record, if any.
+    pub fn sync(
+        &mut self,
+        registry: &Registry,
+        scene: &mut SceneDescription,
+    ) -> Option<TlasUpdateMode> {
+        if self.needs_rebuild(registry) {
+            return None;
+        }
+        let mut changed = false;
+        for (index, handle) in self.order.iter().enumerate() {
+            let instance = &registry.instances[*handle];
+            if instance.changed > self.tick {
+                scene.blas_transform(instance.transform, index);
+                changed = true;
+            }
+        }
+        self.tick = registry.tick;
+        if changed {
+            scene.update();
+            Some(TlasUpdateMode::Refit)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use crate::{
+    clear_color, clear_depth_stencil, depth_aspect_mask, has_stencil_component, Context, Image2d,
+    RenderArea, RenderPass, Resource, Result, SamplerInfo, TransientRenderPassInfo,
+};
+use ash::vk;
+use std::sync::Arc;
+
+// Offscreen render target (render-to-texture): a color attachment sampled after the pass, with
+// optional depth and MSAA. Multisampled colors are resolved into the sampled color at the end of
+// the pass, or by the user with manual_resolve (e.g. a custom resolve shader, or to sample the
+// multisampled color in later passes before cmd_resolve).
+#[derive(Clone, Copy, Debug)]
+pub struct RenderTargetInfo<'a> {
+    pub name: &'a str,
+    pub extent: vk::Extent2D,
+    pub color_format: vk::Format,
+    pub depth: bool,
+    // Preferred depth format, see SharedContext::select_depth_format.
+    pub depth_format: Option<vk::Format>,
+    pub samples: vk::SampleCountFlags,
+    // One set of images per frame in flight, so the target of frame N can be sampled while frame
+    // N + 1 renders to its own.
+    pub frames_count: usize,
+    pub sampler: SamplerInfo,
+    // Keeps the multisampled color after the pass, in SHADER_READ_ONLY_OPTIMAL layout, instead
+    // of resolving it with the render pass.
+    pub manual_resolve: bool,
+    // Adds STORAGE usage to the colors, e.g. for ray tracing or compute passes writing into the
+    // target. The multisampled color needs supports_storage_image_multisample.
+    pub storage: bool,
+}
+
+impl Default for RenderTargetInfo<'_> {
+    fn default() -> Self {
+        RenderTargetInfo {
+            name: "RenderTarget",
+            extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+            color_format: vk::Format::R8G8B8A8_UNORM,
+            depth: true,
+            depth_format: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            frames_count: 1,
+            sampler: SamplerInfo::default().clamp_to_edge(),
+            manual_resolve: false,
+            storage: false,
+        }
+    }
+}
+
+impl<'a> RenderTargetInfo<'a> {
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = name;
+        self
+    }
+    pub fn extent(mut self, width: u32, height: u32) -> Self {
+        self.extent = vk::Extent2D { width, height };
+        self
+    }
+    pub fn color_format(mut self, format: vk::Format) -> Self {
+        self.color_format = format;
+        self
+    }
+    pub fn depth(mut self, depth: bool) -> Self {
+        self.depth = depth;
+        self
+    }
+    pub fn depth_format(mut self, format: vk::Format) -> Self {
+        self.depth = true;
+        self.depth_format = Some(format);
+        self
+    }
+    // Sample count, as RendererSettings::samples.
+    pub fn samples(mut self, samples: u8) -> Self {
+        self.samples = sample_count_flags(samples);
+        self
+    }
+    pub fn frames_count(mut self, frames_count: usize) -> Self {
+        self.frames_count = frames_count;
+        self
+    }
+    pub fn sampler(mut self, sampler: SamplerInfo) -> Self {
+        self.sampler = sampler;
+        self
+    }
+    pub fn manual_resolve(mut self) -> Self {
+        self.manual_resolve = true;
+        self
+    }
+    pub fn storage(mut self) -> Self {
+        self.storage = true;
+        self
+    }
+}
+
+// Unsupported counts fall back to a single sample.
+pub fn sample_count_flags(samples: u8) -> vk::SampleCountFlags {
+    match samples {
+        2 => vk::SampleCountFlags::TYPE_2,
+        4 => vk::SampleCountFlags::TYPE_4,
+        8 => vk::SampleCountFlags::TYPE_8,
+        16 => vk::SampleCountFlags::TYPE_16,
+        32 => vk::SampleCountFlags::TYPE_32,
+        64 => vk::SampleCountFlags::TYPE_64,
+        _ => vk::SampleCountFlags::TYPE_1,
+    }
+}
+
+struct RenderTargetFrame {
+    // Single sampled, in SHADER_READ_ONLY_OPTIMAL layout after the pass (or cmd_resolve).
+    color: Image2d,
+    multisampled_color: Option<Image2d>,
+    // Depth, only used by the framebuffer.
+    attachment_images: Vec<Image2d>,
+    framebuffer: vk::Framebuffer,
+}
+
+pub struct RenderTarget {
+    context: Arc<Context>,
+    name: String,
+    extent: vk::Extent2D,
+    color_format: vk::Format,
+    depth_format: Option<vk::Format>,
+    samples: vk::SampleCountFlags,
+    manual_resolve: bool,
+    storage: bool,
+    render_pass: RenderPass,
+    frames: Vec<RenderTargetFrame>,
+    sampler: vk::Sampler,
+    clear_values: Vec<vk::ClearValue>,
+}
+
+impl RenderTarget {
+    pub fn new(context: Arc<Context>, info: RenderTargetInfo) -> Result<Self> {
+        let depth_format = match info.depth {
+            true => Some(context.select_depth_format(info.depth_format)),
+            false => None,
+        };
+        let multisampled = info.samples != vk::SampleCountFlags::TYPE_1;
+        let manual_resolve = info.manual_resolve && multisampled;
+        let mut storage = info.storage;
+        if storage && multisampled && !context.supports_storage_image_multisample() {
+            println!(
+                "Multisampled storage images unsupported, {} has no storage usage.",
+                info.name
+            );
+            storage = false;
+        }
+        let render_pass = Self::create_render_pass(&context, &info, depth_format, manual_resolve);
+        // Attachment order: color, depth, then the resolved color.
+        let mut clear_values = vec![clear_color([0.0, 0.0, 0.0, 1.0])];
+        if depth_format.is_some() {
+            clear_values.push(clear_depth_stencil(1.0, 0));
+        }
+        let mut render_target = RenderTarget {
+            sampler: info.sampler.create_sampler(&context, 1),
+            context,
+            name: info.name.to_string(),
+            extent: info.extent,
+            color_format: info.color_format,
+            depth_format,
+            samples: info.samples,
+            manual_resolve,
+            storage,
+            render_pass,
+            frames: Vec::new(),
+            clear_values,
+        };
+        render_target.frames = (0..info.frames_count.max(1))
+            .map(|_| render_target.create_frame())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(render_target)
+    }
+
+    fn create_render_pass(
+        context: &Arc<Context>,
+        info: &RenderTargetInfo,
+        depth_format: Option<vk::Format>,
+        manual_resolve: bool,
+    ) -> RenderPass {
+        let resolve = info.samples != vk::SampleCountFlags::TYPE_1 && !manual_resolve;
+        let mut attachments = vec![vk::AttachmentDescription::builder()
+            .format(info.color_format)
+            .samples(info.samples)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(match resolve {
+                true => vk::AttachmentStoreOp::DONT_CARE,
+                false => vk::AttachmentStoreOp::STORE,
+            })
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(match resolve {
+                true => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                false => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .build()];
+        let color_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let depth_ref = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        if let Some(format) = depth_format {
+            attachments.push(
+                vk::AttachmentDescription::builder()
+                    .format(format)
+                    .samples(info.samples)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .stencil_load_op(match has_stencil_component(format) {
+                        true => vk::AttachmentLoadOp::CLEAR,
+                        false => vk::AttachmentLoadOp::DONT_CARE,
+                    })
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .build(),
+            );
+        }
+        let resolve_refs = [vk::AttachmentReference {
+            attachment: attachments.len() as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        if resolve {
+            attachments.push(
+                vk::AttachmentDescription::builder()
+                    .format(info.color_format)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build(),
+            );
+        }
+
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs);
+        if depth_format.is_some() {
+            subpass = subpass.depth_stencil_attachment(&depth_ref);
+        }
+        if resolve {
+            subpass = subpass.resolve_attachments(&resolve_refs);
+        }
+        let subpasses = [subpass.build()];
+        // The previous sampling of the color must complete before it is overwritten, and the
+        // pass writes must complete before the color is sampled.
+        let dependencies = [
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dependency_flags: vk::DependencyFlags::BY_REGION,
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::TRANSFER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ,
+                dependency_flags: vk::DependencyFlags::BY_REGION,
+            },
+        ];
+        let create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        RenderPass::new_raw(context.shared().clone(), &create_info)
+    }
+
+    fn create_image(
+        &self,
+        name: &str,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<Image2d> {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        Image2d::new(
+            self.context.shared().clone(),
+            &image_info,
+            aspect_mask,
+            1,
+            name,
+        )
+    }
+
+    fn create_frame(&self) -> Result<RenderTargetFrame> {
+        let storage_usage = match self.storage {
+            true => vk::ImageUsageFlags::STORAGE,
+            false => vk::ImageUsageFlags::empty(),
+        };
+        let color = self.create_image(
+            &format!("{}Color", self.name),
+            self.color_format,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | storage_usage,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        // Transient when resolved by the render pass, otherwise sampled and resolved later.
+        let multisampled_usage = match self.manual_resolve {
+            true => {
+                vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC | storage_usage
+            }
+            false => vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        };
+        let multisampled_color = match self.samples {
+            vk::SampleCountFlags::TYPE_1 => None,
+            samples => Some(self.create_image(
+                &format!("{}MultisampledColor", self.name),
+                self.color_format,
+                samples,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | multisampled_usage,
+                vk::ImageAspectFlags::COLOR,
+            )?),
+        };
+        let depth = match self.depth_format {
+            Some(format) => Some(self.create_image(
+                &format!("{}Depth", self.name),
+                format,
+                self.samples,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                depth_aspect_mask(format),
+            )?),
+            None => None,
+        };
+
+        let mut attachments = Vec::new();
+        match &multisampled_color {
+            Some(multisampled_color) => attachments.push(multisampled_color.get_image_view()),
+            None => attachments.push(color.get_image_view()),
+        }
+        if let Some(depth) = &depth {
+            attachments.push(depth.get_image_view());
+        }
+        if multisampled_color.is_some() && !self.manual_resolve {
+            attachments.push(color.get_image_view());
+        }
+        let attachment_images = depth.into_iter().collect();
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.render_pass.handle())
+            .attachments(&attachments)
+            .width(self.extent.width)
+            .height(self.extent.height)
+            .layers(1);
+        let framebuffer = unsafe {
+            self.context
+                .device()
+                .create_framebuffer(&framebuffer_info, None)?
+        };
+        Ok(RenderTargetFrame {
+            color,
+            multisampled_color,
+            attachment_images,
+            framebuffer,
+        })
+    }
+
+    fn destroy_frames(&mut self) {
+        for frame in self.frames.drain(..) {
+            unsafe {
+                self.context
+                    .device()
+                    .destroy_framebuffer(frame.framebuffer, None);
+            }
+        }
+    }
+
+    // Recreates the images at the new extent. Waits for the device to be idle, the previous
+    // images may still be in use.
+    pub fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        if extent == self.extent {
+            return Ok(());
+        }
+        self.context.device_wait_idle()?;
+        let frames_count = self.frames.len();
+        self.destroy_frames();
+        self.extent = extent;
+        self.frames = (0..frames_count)
+            .map(|_| self.create_frame())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(())
+    }
+
+    pub fn set_clear_color(&mut self, color: [f32; 4]) {
+        self.clear_values[0] = clear_color(color);
+    }
+
+    pub fn set_clear_depth_stencil(&mut self, depth: f32, stencil: u32) {
+        if self.depth_format.is_some() {
+            self.clear_values[1] = clear_depth_stencil(depth, stencil);
+        }
+    }
+
+    // Begins the render pass of the frame images. Pipelines drawing in it must be created with
+    // get_render_pass (or get_transient_render_pass_info).
+    pub fn cmd_begin(&self, cmd: vk::CommandBuffer, frame_index: usize) {
+        self.render_pass.cmd_begin(
+            cmd,
+            self.frames[frame_index].framebuffer,
+            self.extent,
+            &self.clear_values,
+            vk::SubpassContents::INLINE,
+        );
+    }
+
+    // The color of the frame can then be sampled in later passes of the command buffer. With
+    // manual_resolve, the multisampled color can be sampled instead, until cmd_resolve.
+    pub fn cmd_end(&mut self, cmd: vk::CommandBuffer, frame_index: usize) {
+        unsafe {
+            self.context.device().cmd_end_render_pass(cmd);
+        }
+        let frame = &mut self.frames[frame_index];
+        match (&mut frame.multisampled_color, self.manual_resolve) {
+            (Some(multisampled_color), true) => {
+                multisampled_color.set_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            }
+            _ => frame
+                .color
+                .set_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        }
+    }
+
+    // Resolves the multisampled color of the frame into its color, with manual_resolve. Both
+    // are then in SHADER_READ_ONLY_OPTIMAL layout. Passes writing the color at sample rate
+    // (e.g. a resolve shader) can use get_color_mut instead.
+    pub fn cmd_resolve(&mut self, cmd: vk::CommandBuffer, frame_index: usize) {
+        let frame = &mut self.frames[frame_index];
+        let multisampled_color = match &mut frame.multisampled_color {
+            Some(multisampled_color) if self.manual_resolve => multisampled_color,
+            _ => {
+                println!("Render target {} has no manual resolve.", self.name);
+                return;
+            }
+        };
+        multisampled_color.cmd_resolve_to(cmd, &mut frame.color, true);
+        for image in [multisampled_color, &mut frame.color] {
+            image.transition_image_layout(
+                cmd,
+                image.get_layout(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        }
+    }
+
+    pub fn get_render_pass(&self) -> vk::RenderPass {
+        self.render_pass.handle()
+    }
+
+    pub fn get_transient_render_pass_info(&self) -> TransientRenderPassInfo {
+        let resolve_formats = match self.samples {
+            vk::SampleCountFlags::TYPE_1 => Vec::new(),
+            _ if self.manual_resolve => Vec::new(),
+            _ => vec![self.color_format],
+        };
+        TransientRenderPassInfo {
+            color_formats: vec![self.color_format],
+            depth_stencil_format: self.depth_format,
+            resolve_formats,
+            samples: self.samples,
+        }
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn get_sample_count(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    pub fn get_frames_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn get_color(&self, frame_index: usize) -> &Image2d {
+        &self.frames[frame_index].color
+    }
+
+    pub fn get_color_mut(&mut self, frame_index: usize) -> &mut Image2d {
+        &mut self.frames[frame_index].color
+    }
+
+    // None for single sampled targets.
+    pub fn get_multisampled_color(&self, frame_index: usize) -> Option<&Image2d> {
+        self.frames[frame_index].multisampled_color.as_ref()
+    }
+
+    pub fn get_multisampled_color_mut(&mut self, frame_index: usize) -> Option<&mut Image2d> {
+        self.frames[frame_index].multisampled_color.as_mut()
+    }
+
+    pub fn get_sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    // Combined image sampler of the frame color, in SHADER_READ_ONLY_OPTIMAL layout.
+    pub fn get_descriptor_info(&self, frame_index: usize) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo::builder()
+            .sampler(self.sampler)
+            .image_view(self.frames[frame_index].color.get_image_view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    }
+}
+
+impl RenderArea for RenderTarget {
+    fn get_render_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        self.destroy_frames();
+        unsafe {
+            self.context.device().destroy_sampler(self.sampler, None);
+        }
+        self.context.shared().retire_handle(self.sampler);
+    }
+}
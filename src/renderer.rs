@@ -16,17 +16,167 @@ pub enum AppRenderError {
 static QUERY_POOL_SIZE: u32 = 128;
 static QUERY_BEGIN_FRAME: u32 = 0;
 static QUERY_END_FRAME: u32 = 1;
+static QUERY_FIRST_SCOPE: u32 = 2;
+// Queries [QUERY_FIRST_PHASE, QUERY_POOL_SIZE) are written by the GpuPhase timings.
+static QUERY_FIRST_PHASE: u32 = 96;
+
+// Debug label colors, as shown by capture tools.
+static FRAME_LABEL_COLOR: [f32; 4] = [0.4, 0.4, 0.4, 1.0];
+static PASS_LABEL_COLOR: [f32; 4] = [0.2, 0.6, 0.9, 1.0];
+
+#[derive(Clone, Debug)]
+pub struct GpuScopeTiming {
+    pub name: String,
+    // Nesting level, 0 for top level scopes.
+    pub depth: usize,
+    pub milliseconds: f32,
+}
+
+struct GpuScope {
+    name: String,
+    depth: usize,
+    query: u32,
+}
+
+// Explicit multi-GPU rendering over a VK_KHR_device_group (core in Vulkan 1.1).
+// Falls back to single-GPU rendering when no group with more than one device is found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeviceGroupMode {
+    #[default]
+    Disabled,
+    // Each frame is rendered and presented by the next device in the group.
+    AlternateFrame,
+    // Every device renders a horizontal band of each frame; bands are summed at present time.
+    SplitFrame,
+}
+
+// Area of the frame rendered by the devices of `device_mask`, see
+// AppRenderer::get_device_regions.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceRegion {
+    pub device_mask: u32,
+    pub area: vk::Rect2D,
+}
+
+// Whether VK_LAYER_KHRONOS_validation is enabled. The SOL_VALIDATION environment variable
+// (on/off, 1/0) overrides it, e.g. to run a debug build without the layer when chasing a driver
+// issue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValidationMode {
+    Off,
+    On,
+    // Enabled in debug builds only.
+    #[default]
+    Auto,
+    // Enabled regardless of SOL_VALIDATION, e.g. in the test harness.
+    Forced,
+}
+
+impl ValidationMode {
+    pub const ENV_VAR: &'static str = "SOL_VALIDATION";
+
+    pub fn is_enabled(self) -> bool {
+        if self == ValidationMode::Forced {
+            return true;
+        }
+        let mode = match std::env::var(Self::ENV_VAR) {
+            Ok(value) => match value.to_lowercase().as_str() {
+                "0" | "off" | "false" => ValidationMode::Off,
+                "1" | "on" | "true" => ValidationMode::On,
+                _ => {
+                    println!("Ignoring unknown {} value \"{}\".", Self::ENV_VAR, value);
+                    self
+                }
+            },
+            Err(_) => self,
+        };
+        match mode {
+            ValidationMode::Off => false,
+            ValidationMode::On | ValidationMode::Forced => true,
+            ValidationMode::Auto => cfg!(debug_assertions),
+        }
+    }
+}
+
+// Validation layer and debug-utils messenger configuration, applied at instance creation.
+#[derive(Clone, Debug)]
+pub struct ValidationSettings {
+    // Enables VK_LAYER_KHRONOS_validation, see ValidationMode. Ignored with a warning when the
+    // layer isn't installed.
+    pub layers: ValidationMode,
+    // Extra checks through VK_EXT_validation_features, only used when `layers` is enabled.
+    // GPU-assisted validation instruments shaders and reserves a descriptor set binding slot.
+    pub gpu_assisted: bool,
+    pub synchronization: bool,
+    pub best_practices: bool,
+    // Crate-level HazardTracker, independent from the validation layer.
+    pub hazard_tracking: bool,
+    // Messages outside of these filters are dropped. Both can be changed at runtime with
+    // SharedContext::set_debug_message_filter.
+    pub message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        ValidationSettings {
+            layers: ValidationMode::Auto,
+            gpu_assisted: false,
+            synchronization: false,
+            best_practices: false,
+            hazard_tracking: false,
+            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct RendererSettings {
     pub samples: u8,
     pub depth: bool,
+    // Preferred depth buffer format, e.g. D24_UNORM_S8_UINT for stencil. None selects the first
+    // supported of DEPTH_FORMATS (D32_SFLOAT first). Unsupported formats fall back the same way.
+    pub depth_format: Option<vk::Format>,
     pub clear_color: glam::Vec4,
     pub present_mode: vk::PresentModeKHR,
+    // Swapchain formats by order of preference, see select_surface_format. Empty selects the
+    // first format of the surface.
+    pub preferred_surface_formats: Vec<vk::SurfaceFormatKHR>,
     //TODO: Implement frames in flight number that differs from swapchain count
     //pub frames_in_flight: usize,
     pub extensions: Vec<&'static CStr>,
     pub device_extensions: Vec<&'static CStr>,
+    pub device_group: DeviceGroupMode,
+    // Size of each per-frame region of AppRenderer::transient.
+    pub transient_buffer_size: vk::DeviceSize,
+    pub background: Background,
+    pub validation: ValidationSettings,
+    // Searched by shader `#include`s after the including file's directory, following the
+    // engine shaders (ENGINE_SHADER_DIR).
+    pub shader_include_dirs: Vec<std::path::PathBuf>,
+    // Inline render passes begun by AppRenderer set a viewport and scissor covering the
+    // framebuffer. Secondary command buffers must still set their own.
+    pub auto_viewport: bool,
+    // Enables VK_KHR_ray_query when supported, for inline ray tracing in any shader stage (e.g.
+    // shadows in a fragment shader). The TLAS is bound as any acceleration structure
+    // descriptor, see TLAS::cmd_read_barrier.
+    pub ray_query: bool,
+    // Requested Vulkan version, lowered to what the loader and device support. Vulkan 1.3
+    // features are used from the core API when available, see CoreFeatures.
+    pub api_version: u32,
+    // Device features required on top of those sol enables when supported, e.g. for custom
+    // shaders. Context creation fails when the device lacks any of them.
+    pub device_features: DeviceFeatureRequest,
+    // Begins the frame passes without render pass objects nor framebuffers when dynamic
+    // rendering is supported, see RenderingInfo. Pipelines drawing in them are then built with
+    // get_rendering_formats and get_overlay_rendering_formats instead of the render passes.
+    pub dynamic_rendering: bool,
+    // Enables VK_EXT_mesh_shader when supported (Vulkan 1.2 and later), for pipelines of task and
+    // mesh shaders without vertex input, see PipelineInfo::mesh and Context::cmd_draw_mesh_tasks.
+    pub mesh_shader: bool,
 }
 
 impl Default for RendererSettings {
@@ -34,11 +184,24 @@ impl Default for RendererSettings {
         RendererSettings {
             samples: 1,
             depth: true,
+            depth_format: None,
             clear_color: glam::Vec4::ZERO,
             present_mode: vk::PresentModeKHR::FIFO,
+            preferred_surface_formats: vec![SURFACE_FORMAT_UNORM],
             //frames_in_flight: 2,
             extensions: Vec::new(),
             device_extensions: Vec::new(),
+            device_group: DeviceGroupMode::default(),
+            transient_buffer_size: 4 * 1024 * 1024,
+            background: Background::default(),
+            validation: ValidationSettings::default(),
+            shader_include_dirs: Vec::new(),
+            auto_viewport: true,
+            ray_query: false,
+            api_version: vk::API_VERSION_1_3,
+            device_features: DeviceFeatureRequest::default(),
+            dynamic_rendering: false,
+            mesh_shader: false,
         }
     }
 }
@@ -47,27 +210,89 @@ pub struct AppRenderer {
     pub context: Arc<Context>,
     pub swapchain: ManuallyDrop<Swapchain>,
     pub renderpass: RenderPass,
+    pub overlay_renderpass: RenderPass,
     pub active_frame_index: usize,
     frames: Vec<AppFrameData>,
+    // Empty with dynamic rendering.
     framebuffers: Vec<vk::Framebuffer>,
+    overlay_framebuffers: Vec<vk::Framebuffer>,
+    dynamic_rendering: bool,
     clear_values: [vk::ClearValue; 2],
     settings: RendererSettings,
     query_pool: vk::QueryPool,
     pub gpu_frame_time: f32,
+    pub gpu_scope_timings: Vec<GpuScopeTiming>,
+    // Timed automatically by the crate helpers, see GpuPhase.
+    pub gpu_phase_timings: GpuPhaseTimings,
+    gpu_scopes: Vec<GpuScope>,
+    open_gpu_scopes: Vec<usize>,
+    next_query: u32,
+    pub transient: TransientBufferAllocator,
+    background_pass: Option<BackgroundPass>,
+    // Created by the first call to async_compute.
+    async_compute: Option<AsyncCompute>,
+    // Timeline values of async_compute the next submit_frame waits for, per stage.
+    compute_waits: Vec<(u64, vk::PipelineStageFlags)>,
+    capture_requested: bool,
+    captured_frame: Option<ImageReadback>,
+    frame_counter: u64,
 }
 
 impl AppRenderer {
     pub fn new(window: &mut Window, settings: RendererSettings) -> Self {
+        let shared_context = Arc::new(SharedContext::new(window, &settings));
+        Self::create(shared_context, window, settings)
+    }
+
+    // Renderer of another window sharing the device and allocator of `shared_context`, e.g. for
+    // tool windows. Only the swapchain and frame settings apply, instance and device settings
+    // are those of the shared context. Fails when the present queue can't present to the window.
+    pub fn new_shared(
+        shared_context: Arc<SharedContext>,
+        window: &mut Window,
+        settings: RendererSettings,
+    ) -> Result<Self> {
+        window.create_surface(shared_context.entry(), shared_context.instance());
+        let supported = unsafe {
+            window.get_surface_support(
+                shared_context.physical_device(),
+                shared_context.queue_family_indices.present,
+            )
+        };
+        if !supported {
+            println!("The present queue family can't present to the window surface.");
+            return Err(Error::Vulkan(vk::Result::ERROR_FEATURE_NOT_PRESENT));
+        }
+        Ok(Self::create(shared_context, window, settings))
+    }
+
+    fn create(
+        shared_context: Arc<SharedContext>,
+        window: &mut Window,
+        settings: RendererSettings,
+    ) -> Self {
         unsafe {
-            let shared_context = Arc::new(SharedContext::new(window, &settings));
-            let mut swapchain = Swapchain::new(shared_context.clone(), &window, &settings);
+            let mut swapchain = Swapchain::new(shared_context.clone(), window, &settings)
+                .expect("Unable to create swapchain");
             let context = Arc::new(Context::new(
                 shared_context.clone(),
                 swapchain.get_image_count(),
             ));
             swapchain.transition_depth_images(&context);
+            let dynamic_rendering =
+                settings.dynamic_rendering && context.supports_dynamic_rendering();
+            if settings.dynamic_rendering && !dynamic_rendering {
+                println!("Dynamic rendering unsupported, using render passes.");
+            }
             let renderpass = swapchain.create_compatible_render_pass();
-            let framebuffers = swapchain.create_framebuffers(&renderpass, &window);
+            let overlay_renderpass = swapchain.create_overlay_render_pass();
+            let (framebuffers, overlay_framebuffers) = match dynamic_rendering {
+                true => (Vec::new(), Vec::new()),
+                false => (
+                    swapchain.create_framebuffers(&renderpass, window),
+                    swapchain.create_overlay_framebuffers(&overlay_renderpass),
+                ),
+            };
 
             let fence_create_info =
                 vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
@@ -84,17 +309,8 @@ impl AppRenderer {
                 frames.push(frame);
             }
             let clear_values = [
-                vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: settings.clear_color.into(),
-                    },
-                },
-                vk::ClearValue {
-                    depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
-                        stencil: 0,
-                    },
-                },
+                clear_color(settings.clear_color.into()),
+                clear_depth_stencil(1.0, 0),
             ];
 
             let query_create_info = vk::QueryPoolCreateInfo::builder()
@@ -105,17 +321,48 @@ impl AppRenderer {
                 .create_query_pool(&query_create_info, None)
                 .expect("Failed to create query pool.");
 
+            let transient = TransientBufferAllocator::new(
+                context.clone(),
+                settings.transient_buffer_size,
+                swapchain.get_image_count(),
+            );
+
+            let background_pass = match settings.background {
+                Background::None => None,
+                _ => Some(Self::create_background_pass(
+                    &context,
+                    &swapchain,
+                    &renderpass,
+                    dynamic_rendering,
+                )),
+            };
+
             AppRenderer {
                 swapchain: ManuallyDrop::new(swapchain),
                 frames,
                 renderpass,
+                overlay_renderpass,
                 framebuffers,
+                overlay_framebuffers,
+                dynamic_rendering,
                 clear_values,
                 context,
                 active_frame_index: 0,
                 settings,
                 query_pool,
                 gpu_frame_time: 0.0,
+                gpu_scope_timings: Vec::new(),
+                gpu_phase_timings: GpuPhaseTimings::default(),
+                gpu_scopes: Vec::new(),
+                open_gpu_scopes: Vec::new(),
+                next_query: QUERY_FIRST_SCOPE,
+                transient,
+                background_pass,
+                async_compute: None,
+                compute_waits: Vec::new(),
+                capture_requested: false,
+                captured_frame: None,
+                frame_counter: 0,
             }
         }
     }
@@ -133,11 +380,9 @@ impl AppRenderer {
     }
 
     pub fn recreate_swapchain(&mut self, window: &Window) {
-        unsafe {
-            self.context.device().device_wait_idle().unwrap();
-        }
+        self.context.device_wait_idle().unwrap();
 
-        for framebuffer in self.framebuffers.iter() {
+        for framebuffer in self.framebuffers.iter().chain(self.overlay_framebuffers.iter()) {
             unsafe {
                 self.context
                     .device()
@@ -149,16 +394,56 @@ impl AppRenderer {
             ManuallyDrop::drop(&mut self.swapchain);
         }
         
-        self.swapchain = ManuallyDrop::new(Swapchain::new(
-            self.context.shared().clone(),
-            window,
-            &self.settings,
-        ));
+        self.swapchain = ManuallyDrop::new(
+            Swapchain::new(self.context.shared().clone(), window, &self.settings)
+                .expect("Unable to recreate swapchain"),
+        );
         self.swapchain.transition_depth_images(&self.context);
 
-        self.framebuffers = self
-            .swapchain
-            .create_framebuffers(&self.renderpass, &window);
+        // The new swapchain may have another image count, which indexes the per-frame data.
+        let image_count = self.swapchain.get_image_count();
+        if image_count != self.frames.len() {
+            self.resize_frames(image_count);
+        }
+
+        if !self.dynamic_rendering {
+            self.framebuffers = self.swapchain.create_framebuffers(&self.renderpass, window);
+            self.overlay_framebuffers = self
+                .swapchain
+                .create_overlay_framebuffers(&self.overlay_renderpass);
+        }
+    }
+
+    // Recreates the fences, semaphores, command pools and transient rings of the frames for a
+    // new swapchain image count. The device must be idle. Application data sized by
+    // get_frames_count must be resized as well.
+    fn resize_frames(&mut self, frame_count: usize) {
+        let device = self.context.device();
+        for frame in self.frames.drain(frame_count.min(self.frames.len())..) {
+            unsafe {
+                device.destroy_fence(frame.in_flight_fence, None);
+            }
+        }
+        let fence_create_info =
+            vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+        for i in self.frames.len()..frame_count {
+            self.frames.push(AppFrameData {
+                index: i,
+                in_flight_fence: unsafe {
+                    device
+                        .create_fence(&fence_create_info, None)
+                        .expect("Create fence failed.")
+                },
+                semaphore_pool: SemaphorePool::new(self.context.shared().clone()),
+            });
+        }
+        self.context.resize_frame_pools(frame_count);
+        self.transient = TransientBufferAllocator::new(
+            self.context.clone(),
+            self.settings.transient_buffer_size,
+            frame_count,
+        );
+        self.active_frame_index = 0;
     }
 
     pub fn acquire_next_image(&mut self) -> Result<(vk::Semaphore, usize), AppRenderError> {
@@ -184,8 +469,11 @@ impl AppRenderer {
             };
 
             self.active_frame_index = image_index as usize;
+            self.frame_counter += 1;
+            self.context.track_hazards(|tracker| tracker.begin_frame());
             self.frames[self.active_frame_index].semaphore_pool.reset();
             self.wait_for_and_reset_fence(self.frames[self.active_frame_index].in_flight_fence);
+            self.transient.begin_frame(self.active_frame_index);
 
             Ok((aquired_semaphore, self.active_frame_index))
         }
@@ -194,8 +482,13 @@ impl AppRenderer {
     pub fn begin_command_buffer(&mut self) -> vk::CommandBuffer {
         let cmd = self.context.request_command_buffer(self.active_frame_index);
         unsafe {
-            let begin_info = vk::CommandBufferBeginInfo::builder()
+            let mut device_group_info =
+                vk::DeviceGroupCommandBufferBeginInfo::builder().device_mask(self.get_device_mask());
+            let mut begin_info = vk::CommandBufferBeginInfo::builder()
                 .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            if self.is_device_group() {
+                begin_info = begin_info.push_next(&mut device_group_info);
+            }
             self.context
                 .device()
                 .begin_command_buffer(cmd, &begin_info)
@@ -204,6 +497,15 @@ impl AppRenderer {
             self.context
                 .device()
                 .cmd_reset_query_pool(cmd, self.query_pool, 0, QUERY_POOL_SIZE);
+            self.gpu_scopes.clear();
+            self.open_gpu_scopes.clear();
+            self.next_query = QUERY_FIRST_SCOPE;
+            self.context.shared().gpu_phases().begin_frame(
+                cmd,
+                self.query_pool,
+                QUERY_FIRST_PHASE,
+                QUERY_POOL_SIZE,
+            );
 
             self.context.device().cmd_write_timestamp(
                 cmd,
@@ -212,10 +514,19 @@ impl AppRenderer {
                 QUERY_BEGIN_FRAME,
             );
         }
+        let frame_label = format!("Frame {}", self.frame_counter);
+        self.context
+            .debug_marker()
+            .cmd_begin_label(cmd, &frame_label, FRAME_LABEL_COLOR);
         cmd
     }
 
     pub fn end_command_buffer(&self, cmd: vk::CommandBuffer) {
+        self.context.debug_marker().cmd_end_label(cmd);
+        self.context
+            .shared()
+            .gpu_phases()
+            .end_frame(self.context.device(), cmd);
         unsafe {
             self.context.device().cmd_write_timestamp(
                 cmd,
@@ -230,29 +541,330 @@ impl AppRenderer {
         }
     }
 
-    pub fn begin_renderpass(&self, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
-        unsafe {
-            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+    pub fn begin_renderpass(&mut self, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+        self.begin_renderpass_with_contents(command_buffer, extent, vk::SubpassContents::INLINE);
+    }
+
+    pub fn begin_renderpass_with_contents(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        contents: vk::SubpassContents,
+    ) {
+        self.context.begin_gpu_phase(command_buffer, GpuPhase::Main);
+        let device_render_areas = self.get_device_render_areas(extent);
+        let mut device_group_info = vk::DeviceGroupRenderPassBeginInfo::builder()
+            .device_mask(self.get_device_mask())
+            .device_render_areas(&device_render_areas);
+        let split_frame = self.is_split_frame();
+        if self.dynamic_rendering {
+            self.swapchain
+                .cmd_transition_for_rendering(command_buffer, self.active_frame_index);
+            let flags = match contents {
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS => {
+                    vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+                }
+                _ => vk::RenderingFlags::empty(),
+            };
+            self.swapchain
+                .get_rendering_info(self.active_frame_index)
+                .cmd_begin_with_device_group(
+                    self.context.shared(),
+                    command_buffer,
+                    extent,
+                    &self.clear_values,
+                    flags,
+                    split_frame.then(|| &mut *device_group_info),
+                );
+        } else {
+            let mut render_pass_begin_info = vk::RenderPassBeginInfo::builder()
                 .render_pass(self.renderpass.handle())
                 .framebuffer(self.framebuffers[self.active_frame_index])
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D { x: 0, y: 0 },
                     extent,
                 })
-                .clear_values(&self.clear_values)
-                .build();
-            self.context.device().cmd_begin_render_pass(
-                command_buffer,
-                &render_pass_begin_info,
-                vk::SubpassContents::INLINE,
-            );
+                .clear_values(&self.clear_values);
+            if split_frame {
+                render_pass_begin_info = render_pass_begin_info.push_next(&mut device_group_info);
+            }
+            unsafe {
+                self.context.device().cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    contents,
+                );
+            }
+        }
+        // Secondary command buffers must draw the background themselves (cmd_draw_background).
+        if contents == vk::SubpassContents::INLINE {
+            self.cmd_draw_background(command_buffer, extent);
+            if self.settings.auto_viewport {
+                self.context.cmd_set_full_viewport(command_buffer, &extent);
+            }
         }
     }
 
-    pub fn end_renderpass(&self, command_buffer: vk::CommandBuffer) {
+    // Begins a secondary command buffer continuing the main render pass of the active frame. Can
+    // be called from several threads to record draws in parallel, each thread using its own
+    // command pool. The viewport is set when RendererSettings::auto_viewport is, the background
+    // is not drawn. See cmd_execute_secondary.
+    pub fn begin_secondary_command_buffer(&self) -> vk::CommandBuffer {
+        let cmd = self
+            .context
+            .request_secondary_command_buffer(self.active_frame_index);
+        let formats = self.swapchain.get_rendering_formats();
+        let mut rendering_inheritance_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+            .color_attachment_formats(&formats.color_formats)
+            .depth_attachment_format(formats.depth_format())
+            .stencil_attachment_format(formats.stencil_format())
+            .rasterization_samples(formats.samples);
+        let mut inheritance_info = vk::CommandBufferInheritanceInfo::builder().subpass(0);
+        if self.dynamic_rendering {
+            inheritance_info = inheritance_info.push_next(&mut rendering_inheritance_info);
+        } else {
+            inheritance_info = inheritance_info
+                .render_pass(self.renderpass.handle())
+                .framebuffer(self.framebuffers[self.active_frame_index]);
+        }
+        let mut device_group_info =
+            vk::DeviceGroupCommandBufferBeginInfo::builder().device_mask(self.get_device_mask());
+        let mut begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            )
+            .inheritance_info(&inheritance_info);
+        if self.is_device_group() {
+            begin_info = begin_info.push_next(&mut device_group_info);
+        }
         unsafe {
-            self.context.device().cmd_end_render_pass(command_buffer);
+            self.context
+                .device()
+                .begin_command_buffer(cmd, &begin_info)
+                .expect("Begin secondary commands.");
+        }
+        if self.settings.auto_viewport {
+            self.context
+                .cmd_set_full_viewport(cmd, &self.swapchain.get_extent());
+        }
+        cmd
+    }
+
+    pub fn end_secondary_command_buffer(&self, cmd: vk::CommandBuffer) {
+        unsafe {
+            self.context
+                .device()
+                .end_command_buffer(cmd)
+                .expect("End secondary commands.");
+        }
+    }
+
+    // Must be called inside the main render pass, begun with
+    // SubpassContents::SECONDARY_COMMAND_BUFFERS.
+    pub fn cmd_execute_secondary(&self, cmd: vk::CommandBuffer, secondary: &[vk::CommandBuffer]) {
+        if !secondary.is_empty() {
+            unsafe {
+                self.context.device().cmd_execute_commands(cmd, secondary);
+            }
+        }
+    }
+
+    // Begins a pass loading the present image, after the main render pass or a user blit to the
+    // present image, for UI and stats overlays. Pipelines must be created with
+    // get_overlay_renderpass. The image is transitioned from its tracked layout (present source
+    // if untracked) and is ready to present after end_overlay_pass.
+    pub fn begin_overlay_pass(&mut self, command_buffer: vk::CommandBuffer) {
+        self.context
+            .debug_marker()
+            .cmd_begin_label(command_buffer, "Overlay", PASS_LABEL_COLOR);
+        self.context.begin_gpu_phase(command_buffer, GpuPhase::Post);
+        let extent = self.swapchain.get_extent();
+        let present_image = self.swapchain.get_present_image(self.active_frame_index);
+        let layout = match present_image.get_layout() {
+            vk::ImageLayout::UNDEFINED => vk::ImageLayout::PRESENT_SRC_KHR,
+            layout => layout,
+        };
+        present_image.transition_image_layout(
+            command_buffer,
+            layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        // Each device only draws over its own band, as the main pass.
+        let device_render_areas = self.get_device_render_areas(extent);
+        let mut device_group_info = vk::DeviceGroupRenderPassBeginInfo::builder()
+            .device_mask(self.get_device_mask())
+            .device_render_areas(&device_render_areas);
+        let split_frame = self.is_split_frame();
+        if self.dynamic_rendering {
+            self.swapchain
+                .get_overlay_rendering_info(self.active_frame_index)
+                .cmd_begin_with_device_group(
+                    self.context.shared(),
+                    command_buffer,
+                    extent,
+                    &[],
+                    vk::RenderingFlags::empty(),
+                    split_frame.then(|| &mut *device_group_info),
+                );
+        } else {
+            let mut render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.overlay_renderpass.handle())
+                .framebuffer(self.overlay_framebuffers[self.active_frame_index])
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                });
+            if split_frame {
+                render_pass_begin_info = render_pass_begin_info.push_next(&mut device_group_info);
+            }
+            unsafe {
+                self.context.device().cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+            }
+        }
+        if self.settings.auto_viewport {
+            self.context.cmd_set_full_viewport(command_buffer, &extent);
+        }
+    }
+
+    pub fn end_overlay_pass(&mut self, command_buffer: vk::CommandBuffer) {
+        self.end_renderpass(command_buffer);
+        self.context.end_gpu_phase(command_buffer, GpuPhase::Post);
+        self.context.debug_marker().cmd_end_label(command_buffer);
+    }
+
+    // Blits `image`, of the swapchain extent, to the present image of the active frame and leaves
+    // it in PRESENT_SRC_KHR layout. In SplitFrame mode every device only blits its band over a
+    // cleared image, the present images of the devices being summed.
+    pub fn cmd_blit_to_present_image(&mut self, cmd: vk::CommandBuffer, image: &mut Image2d) {
+        let regions = self.get_device_regions(self.swapchain.get_extent());
+        let frame_device_mask = self.get_device_mask();
+        let context = self.context.clone();
+        let present_image = self.swapchain.get_present_image(self.active_frame_index);
+        if regions.len() == 1 {
+            image.cmd_blit_to(cmd, present_image, true);
+        } else {
+            context.begin_gpu_phase(cmd, GpuPhase::PresentBlit);
+            present_image.transition_image_layout(
+                cmd,
+                present_image.get_layout(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            image.transition_image_layout(
+                cmd,
+                image.get_layout(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+            let range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1)
+                .build();
+            // The blits of the bands wait for the clear.
+            let layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+            let clear_barrier =
+                ImageBarrier2::new(present_image.handle(), vk::ImageAspectFlags::COLOR)
+                    .layouts(layout, layout);
+            unsafe {
+                context.device().cmd_clear_color_image(
+                    cmd,
+                    present_image.handle(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &vk::ClearColorValue::default(),
+                    &[range],
+                );
+            }
+            context.cmd_pipeline_barrier2(cmd, &[], &[], &[clear_barrier]);
+            for region in &regions {
+                unsafe {
+                    context
+                        .device()
+                        .cmd_set_device_mask(cmd, region.device_mask);
+                }
+                image.cmd_blit_area_to(cmd, present_image, region.area);
+            }
+            unsafe {
+                context.device().cmd_set_device_mask(cmd, frame_device_mask);
+            }
+            context.end_gpu_phase(cmd, GpuPhase::PresentBlit);
+        }
+        present_image.transition_image_layout(
+            cmd,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+    }
+
+    pub fn get_overlay_renderpass(&self) -> vk::RenderPass {
+        self.overlay_renderpass.handle()
+    }
+
+    pub fn cmd_draw_background(&self, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+        if let Some(background_pass) = &self.background_pass {
+            let debug_marker = self.context.debug_marker();
+            debug_marker.cmd_begin_label(command_buffer, "Background", PASS_LABEL_COLOR);
+            background_pass.cmd_draw(command_buffer, extent, &self.settings.background);
+            debug_marker.cmd_end_label(command_buffer);
+        }
+    }
+
+    pub fn set_background(&mut self, background: Background) {
+        if background != Background::None && self.background_pass.is_none() {
+            self.background_pass = Some(Self::create_background_pass(
+                &self.context,
+                &self.swapchain,
+                &self.renderpass,
+                self.dynamic_rendering,
+            ));
         }
+        self.settings.background = background;
+    }
+
+    fn create_background_pass(
+        context: &Arc<Context>,
+        swapchain: &Swapchain,
+        renderpass: &RenderPass,
+        dynamic_rendering: bool,
+    ) -> BackgroundPass {
+        match dynamic_rendering {
+            true => {
+                BackgroundPass::new_dynamic(context.clone(), &swapchain.get_rendering_formats())
+            }
+            false => BackgroundPass::new(
+                context.clone(),
+                renderpass.handle(),
+                swapchain.get_sample_count(),
+            ),
+        }
+    }
+
+    pub fn set_clear_color(&mut self, color: [f32; 4]) {
+        self.clear_values[0] = clear_color(color);
+    }
+
+    pub fn set_clear_depth_stencil(&mut self, depth: f32, stencil: u32) {
+        self.clear_values[1] = clear_depth_stencil(depth, stencil);
+    }
+
+    pub fn end_renderpass(&mut self, command_buffer: vk::CommandBuffer) {
+        if self.dynamic_rendering {
+            // Done by the final layout of the render passes.
+            self.context.cmd_end_rendering(command_buffer);
+            self.swapchain
+                .cmd_transition_for_present(command_buffer, self.active_frame_index);
+        } else {
+            unsafe {
+                self.context.device().cmd_end_render_pass(command_buffer);
+            }
+            self.swapchain
+                .get_present_image(self.active_frame_index)
+                .set_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        }
+        self.context.end_gpu_phase(command_buffer, GpuPhase::Main);
     }
 
     pub fn submit_and_present(
@@ -265,29 +877,172 @@ impl AppRenderer {
             &[wait_semaphore],
             &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
         );
+        if self.capture_requested {
+            self.capture_requested = false;
+            self.captured_frame = Some(self.read_present_image());
+        }
         self.present_frame(rendering_complete_semaphore)?;
+        self.resolve_timestamps();
+        Ok(())
+    }
 
-        let mut query_data = [0u32; 2];
+    // The next presented image is read back before presentation, see take_captured_frame.
+    pub fn capture_next_frame(&mut self) {
+        if self.swapchain.supports_readback() {
+            self.capture_requested = true;
+        } else {
+            println!("The surface doesn't support swapchain image readback, ignoring capture.");
+        }
+    }
+
+    pub fn take_captured_frame(&mut self) -> Option<ImageReadback> {
+        self.captured_frame.take()
+    }
+
+    fn read_present_image(&mut self) -> ImageReadback {
+        let context = self.context.clone();
+        let image = self.swapchain.get_present_image(self.active_frame_index);
+        // Rendering ended with the render pass (or a user blit) leaving the image ready to present.
+        image.set_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        image.read_to_cpu(&context)
+    }
+
+    // Opens a named timestamp scope, closed by the next end_gpu_scope. Scopes can be nested.
+    // Timings are available in gpu_scope_timings after submit_and_present. Scopes are also
+    // debug label regions, shown in RenderDoc and Nsight captures.
+    pub fn begin_gpu_scope(&mut self, cmd: vk::CommandBuffer, name: &str) {
+        self.context
+            .debug_marker()
+            .cmd_begin_label(cmd, name, PASS_LABEL_COLOR);
+        if self.next_query + 2 > QUERY_FIRST_PHASE {
+            // Out of queries: keep begin/end balanced but don't time the scope.
+            self.open_gpu_scopes.push(usize::MAX);
+            return;
+        }
+        unsafe {
+            self.context.device().cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                self.next_query,
+            );
+        }
+        self.open_gpu_scopes.push(self.gpu_scopes.len());
+        self.gpu_scopes.push(GpuScope {
+            name: name.to_string(),
+            depth: self.open_gpu_scopes.len() - 1,
+            query: self.next_query,
+        });
+        self.next_query += 2;
+    }
+
+    pub fn end_gpu_scope(&mut self, cmd: vk::CommandBuffer) {
+        let index = self
+            .open_gpu_scopes
+            .pop()
+            .expect("end_gpu_scope called without a matching begin_gpu_scope.");
+        self.context.debug_marker().cmd_end_label(cmd);
+        if let Some(scope) = self.gpu_scopes.get(index) {
+            unsafe {
+                self.context.device().cmd_write_timestamp(
+                    cmd,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    self.query_pool,
+                    scope.query + 1,
+                );
+            }
+        }
+    }
+
+    // One line per recorded phase, then one line per scope, indented by nesting level.
+    pub fn gpu_timing_report(&self) -> String {
+        let mut report = format!("frame: {:.3} ms\n", self.gpu_frame_time);
+        for phase in GpuPhase::ALL {
+            let milliseconds = self.gpu_phase_timings.get(phase);
+            if milliseconds > 0.0 {
+                report += &format!("  [{}]: {:.3} ms\n", phase.name(), milliseconds);
+            }
+        }
+        for timing in &self.gpu_scope_timings {
+            report += &format!(
+                "{}{}: {:.3} ms\n",
+                "  ".repeat(timing.depth + 1),
+                timing.name,
+                timing.milliseconds
+            );
+        }
+        report
+    }
+
+    fn resolve_timestamps(&mut self) {
+        assert!(
+            self.open_gpu_scopes.is_empty(),
+            "Unbalanced begin_gpu_scope/end_gpu_scope."
+        );
+        let mut query_data = vec![0u64; self.next_query as usize];
         unsafe {
             self.context
                 .device()
                 .get_query_pool_results(
                     self.query_pool,
                     0,
-                    2,
+                    self.next_query,
                     &mut query_data,
-                    vk::QueryResultFlags::WAIT,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
                 )
                 .expect("Failed to read query results");
         }
-        let begin_time = query_data[0] as f32
-            * self.context.get_physical_device_limits().timestamp_period
-            * 1e-6;
-        let end_time = query_data[1] as f32
-            * self.context.get_physical_device_limits().timestamp_period
-            * 1e-6;
-        self.gpu_frame_time = end_time - begin_time;
-        Ok(())
+        let limits = *self.context.limits();
+        let to_ms = |begin: u64, end: u64| limits.timestamps_to_ms(begin, end);
+        self.gpu_frame_time = to_ms(
+            query_data[QUERY_BEGIN_FRAME as usize],
+            query_data[QUERY_END_FRAME as usize],
+        );
+        self.gpu_scope_timings = self
+            .gpu_scopes
+            .iter()
+            .map(|scope| GpuScopeTiming {
+                name: scope.name.clone(),
+                depth: scope.depth,
+                milliseconds: to_ms(
+                    query_data[scope.query as usize],
+                    query_data[scope.query as usize + 1],
+                ),
+            })
+            .collect();
+
+        let phases = self.context.shared().gpu_phases().frame().clone();
+        let mut phase_data = vec![0u64; phases.query_count() as usize];
+        if !phase_data.is_empty() {
+            unsafe {
+                self.context
+                    .device()
+                    .get_query_pool_results(
+                        self.query_pool,
+                        QUERY_FIRST_PHASE,
+                        phases.query_count(),
+                        &mut phase_data,
+                        vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                    )
+                    .expect("Failed to read query results");
+            }
+        }
+        self.gpu_phase_timings = phases.resolve(&phase_data, QUERY_FIRST_PHASE, to_ms);
+    }
+
+    // Compute queue of the app, created on first use. None without timeline semaphores. Once
+    // created, each submit_frame signals its graphics timeline, see wait_for_compute and
+    // AsyncCompute::wait_for_graphics.
+    pub fn async_compute(&mut self) -> Option<&mut AsyncCompute> {
+        if self.async_compute.is_none() && self.context.supports_timeline_semaphore() {
+            self.async_compute = AsyncCompute::new(self.context.clone()).ok();
+        }
+        self.async_compute.as_mut()
+    }
+
+    // The next submit_frame waits at `stage` for the async compute submission of `ticket`.
+    pub fn wait_for_compute(&mut self, ticket: ComputeTicket, stage: vk::PipelineStageFlags) {
+        self.compute_waits.push((ticket.0, stage));
     }
 
     pub fn submit_frame(
@@ -296,44 +1051,81 @@ impl AppRenderer {
         wait_semaphores: &[vk::Semaphore],
         stage_flags: &[vk::PipelineStageFlags],
     ) -> vk::Semaphore {
-        unsafe {
-            let rendering_complete_semaphore = self.frames[self.active_frame_index]
-                .semaphore_pool
-                .request_semaphore();
-            let signal_semaphores = [rendering_complete_semaphore];
-            let submit_info = vk::SubmitInfo::builder()
-                .wait_semaphores(wait_semaphores)
-                .wait_dst_stage_mask(stage_flags)
-                .command_buffers(command_buffers)
-                .signal_semaphores(&signal_semaphores);
+        let rendering_complete_semaphore = self.frames[self.active_frame_index]
+            .semaphore_pool
+            .request_semaphore();
+        let mut signal_semaphores = vec![rendering_complete_semaphore];
+        // Binary semaphores ignore their timeline values.
+        let mut signal_values = vec![0];
+        let mut wait_semaphores = wait_semaphores.to_vec();
+        let mut stage_flags = stage_flags.to_vec();
+        let mut wait_values = vec![0; wait_semaphores.len()];
+        if let Some(compute) = &mut self.async_compute {
+            for (value, stage) in self.compute_waits.drain(..) {
+                wait_semaphores.push(compute.timeline_semaphore());
+                stage_flags.push(stage);
+                wait_values.push(value);
+            }
+            signal_semaphores.push(compute.graphics_timeline_semaphore());
+            signal_values.push(compute.next_graphics_value());
+        }
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+        let device_index = self.get_device_index();
+        let wait_device_indices = vec![device_index; wait_semaphores.len()];
+        let command_buffer_device_masks = vec![self.get_device_mask(); command_buffers.len()];
+        let signal_device_indices = vec![device_index; signal_semaphores.len()];
+        let mut device_group_info = vk::DeviceGroupSubmitInfo::builder()
+            .wait_semaphore_device_indices(&wait_device_indices)
+            .command_buffer_device_masks(&command_buffer_device_masks)
+            .signal_semaphore_device_indices(&signal_device_indices);
+        let mut submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&stage_flags)
+            .command_buffers(command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        if self.is_device_group() {
+            submit_info = submit_info.push_next(&mut device_group_info);
+        }
+        if self.async_compute.is_some() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
 
-            self.context
-                .device()
-                .queue_submit(
-                    self.context.graphics_queue(),
-                    &[submit_info.build()],
-                    self.frames[self.active_frame_index].in_flight_fence,
-                )
-                .expect("queue submit failed.");
+        self.context
+            .queue_submit(
+                self.context.graphics_queue(),
+                &[submit_info.build()],
+                self.frames[self.active_frame_index].in_flight_fence,
+            )
+            .expect("queue submit failed.");
 
-            rendering_complete_semaphore
-        }
+        rendering_complete_semaphore
     }
 
     pub fn present_frame(&self, wait_semaphore: vk::Semaphore) -> Result<(), AppRenderError> {
         let wait_semaphores = [wait_semaphore];
         let swapchains = [self.swapchain.handle()];
         let image_indices = [self.active_frame_index as u32];
-        let present_info = vk::PresentInfoKHR::builder()
+        let device_masks = [self.get_device_mask()];
+        let mut device_group_info = vk::DeviceGroupPresentInfoKHR::builder()
+            .device_masks(&device_masks)
+            .mode(self.swapchain.get_device_group_present_mode());
+        let mut present_info = vk::PresentInfoKHR::builder()
             .wait_semaphores(&wait_semaphores)
             .swapchains(&swapchains)
             .image_indices(&image_indices);
+        if self.is_device_group() {
+            present_info = present_info.push_next(&mut device_group_info);
+        }
 
         unsafe {
+            let queues = self.context.lock_queues();
             let result = self
                 .swapchain
                 .swapchain_loader
                 .queue_present(self.context.present_queue(), &present_info);
+            drop(queues);
 
             match result {
                 Ok(_) => {}
@@ -370,13 +1162,125 @@ impl AppRenderer {
         Ok(())
     }
 
+    // Not used to render with RendererSettings::dynamic_rendering, see get_rendering_formats.
     pub fn get_renderpass(&self) -> vk::RenderPass {
         self.renderpass.handle()
     }
 
+    // Whether the frame passes are begun with dynamic rendering, see
+    // RendererSettings::dynamic_rendering.
+    pub fn uses_dynamic_rendering(&self) -> bool {
+        self.dynamic_rendering
+    }
+
+    // Attachments of the main pass, for pipelines drawing in it with dynamic rendering.
+    pub fn get_rendering_formats(&self) -> RenderingFormats {
+        self.swapchain.get_rendering_formats()
+    }
+
+    // Attachments of the overlay pass, for pipelines drawing in it with dynamic rendering.
+    pub fn get_overlay_rendering_formats(&self) -> RenderingFormats {
+        RenderingFormats {
+            color_formats: vec![self.swapchain.get_format()],
+            depth_stencil_format: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+
+    // Format of the main render pass depth buffer, None without depth.
+    pub fn get_depth_format(&self) -> Option<vk::Format> {
+        self.swapchain.get_depth_format()
+    }
+
+    // Format and color space of the present images, see RendererSettings::preferred_surface_formats.
+    pub fn get_surface_format(&self) -> vk::SurfaceFormatKHR {
+        self.swapchain.get_surface_format()
+    }
+
     pub fn get_frames_count(&self) -> usize {
         self.frames.len()
     }
+
+    // True when rendering is distributed over more than one physical device.
+    pub fn is_device_group(&self) -> bool {
+        !self.swapchain.get_device_group_present_mode().is_empty()
+    }
+
+    // Index of the device rendering the current frame (always 0 outside of AFR).
+    pub fn get_device_index(&self) -> u32 {
+        match self.settings.device_group {
+            DeviceGroupMode::AlternateFrame if self.is_device_group() => {
+                (self.frame_counter % self.context.shared().device_group_size() as u64) as u32
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn get_device_mask(&self) -> u32 {
+        if !self.is_device_group() {
+            return 1;
+        }
+        match self.settings.device_group {
+            DeviceGroupMode::SplitFrame => (1 << self.context.shared().device_group_size()) - 1,
+            _ => 1 << self.get_device_index(),
+        }
+    }
+
+    // The bands of the frame and the device rendering each in SplitFrame mode, otherwise the
+    // whole frame with get_device_mask. Work covering the whole frame outside of the main and
+    // overlay passes (e.g. tracing rays or blitting to the present image) must be split over
+    // these regions with cmd_set_device_mask, the present images of the devices being summed.
+    // The mask of the frame is then restored with cmd_set_device_mask(get_device_mask()).
+    pub fn get_device_regions(&self, extent: vk::Extent2D) -> Vec<DeviceRegion> {
+        if !self.is_split_frame() {
+            return vec![DeviceRegion {
+                device_mask: self.get_device_mask(),
+                area: vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                },
+            }];
+        }
+        self.get_device_render_areas(extent)
+            .into_iter()
+            .enumerate()
+            .map(|(index, area)| DeviceRegion {
+                device_mask: 1 << index,
+                area,
+            })
+            .collect()
+    }
+
+    // Restricts the following commands of `cmd` to the devices of `device_mask`, a subset of
+    // get_device_mask. No-op outside of device groups.
+    pub fn cmd_set_device_mask(&self, cmd: vk::CommandBuffer, device_mask: u32) {
+        if self.is_device_group() {
+            unsafe {
+                self.context.device().cmd_set_device_mask(cmd, device_mask);
+            }
+        }
+    }
+
+    fn is_split_frame(&self) -> bool {
+        self.settings.device_group == DeviceGroupMode::SplitFrame && self.is_device_group()
+    }
+
+    fn get_device_render_areas(&self, extent: vk::Extent2D) -> Vec<vk::Rect2D> {
+        let count = self.context.shared().device_group_size();
+        let band = extent.height.div_ceil(count);
+        (0..count)
+            .map(|i| {
+                let y = (i * band).min(extent.height);
+                vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: y as i32 },
+                    extent: vk::Extent2D {
+                        width: extent.width,
+                        height: band.min(extent.height - y),
+                    },
+                }
+            })
+            .collect()
+    }
 }
 
 impl Drop for AppRenderer {
@@ -387,9 +1291,11 @@ impl Drop for AppRenderer {
 
             device.destroy_query_pool(self.query_pool, None);
 
-            device.device_wait_idle().unwrap();
+            ctx.device_wait_idle().unwrap();
+            // Cached assets hold the context, which would otherwise never be dropped.
+            ctx.asset_cache().clear();
 
-            for framebuffer in self.framebuffers.iter() {
+            for framebuffer in self.framebuffers.iter().chain(self.overlay_framebuffers.iter()) {
                 device.destroy_framebuffer(*framebuffer, None);
             }
 
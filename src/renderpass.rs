@@ -1,25 +1,177 @@
-use crate::{Image2d, Resource, SharedContext};
+use crate::{has_stencil_component, Image2d, Resource, SharedContext};
 use ash::{vk};
 use std::sync::Arc;
 
 #[derive(Default)]
 pub struct RenderPassInfo<'a> {
     pub color_images: Vec<&'a Image2d>,
+    // Any depth format, the stencil is cleared with the depth for combined formats.
     pub depth_stencil_image: Option<&'a Image2d>,
     pub resolve_images: Vec<&'a Image2d>,
     pub present: bool,
     pub samples: vk::SampleCountFlags,
     pub final_layout: vk::ImageLayout,
+    // Keep the color contents instead of clearing them. Color images must then be in
+    // COLOR_ATTACHMENT_OPTIMAL layout when the pass begins.
+    pub load_color: bool,
 }
 
 #[derive(Clone, Default)]
 pub struct TransientRenderPassInfo {
     pub color_formats: Vec<vk::Format>,
+    // Must match the depth format of the render pass the pipeline is used with, e.g.
+    // AppRenderer::get_depth_format.
     pub depth_stencil_format: Option<vk::Format>,
     pub resolve_formats: Vec<vk::Format>,
     pub samples: vk::SampleCountFlags,
 }
 
+// Attachments of a pass rendered without render pass object nor framebuffer, see
+// SharedContext::supports_dynamic_rendering. Images must be in COLOR_ATTACHMENT_OPTIMAL and
+// DEPTH_STENCIL_ATTACHMENT_OPTIMAL layouts when the pass begins, and stay in them.
+#[derive(Default)]
+pub struct RenderingInfo<'a> {
+    pub color_images: Vec<&'a Image2d>,
+    // Any depth format, the stencil is cleared with the depth for combined formats.
+    pub depth_stencil_image: Option<&'a Image2d>,
+    // Single-sampled images the color images are resolved to, in the same order.
+    pub resolve_images: Vec<&'a Image2d>,
+    pub samples: vk::SampleCountFlags,
+    // Keep the color contents instead of clearing them.
+    pub load_color: bool,
+}
+
+// Attachment formats of pipelines used with dynamic rendering, see
+// PipelineInfo::rendering_formats.
+#[derive(Clone, Debug, Default)]
+pub struct RenderingFormats {
+    pub color_formats: Vec<vk::Format>,
+    pub depth_stencil_format: Option<vk::Format>,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl RenderingFormats {
+    pub fn depth_format(&self) -> vk::Format {
+        self.depth_stencil_format.unwrap_or(vk::Format::UNDEFINED)
+    }
+
+    pub fn stencil_format(&self) -> vk::Format {
+        match self.depth_stencil_format {
+            Some(format) if has_stencil_component(format) => format,
+            _ => vk::Format::UNDEFINED,
+        }
+    }
+
+    // Chained to the pipeline create info in place of a render pass.
+    pub fn pipeline_create_info(&self) -> vk::PipelineRenderingCreateInfoBuilder<'_> {
+        vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(self.depth_format())
+            .stencil_attachment_format(self.stencil_format())
+    }
+}
+
+// Resolve formats are dropped, pipelines only see the multisampled attachments.
+impl From<TransientRenderPassInfo> for RenderingFormats {
+    fn from(info: TransientRenderPassInfo) -> Self {
+        RenderingFormats {
+            color_formats: info.color_formats,
+            depth_stencil_format: info.depth_stencil_format,
+            samples: info.samples,
+        }
+    }
+}
+
+impl RenderingInfo<'_> {
+    pub fn formats(&self) -> RenderingFormats {
+        RenderingFormats {
+            color_formats: self
+                .color_images
+                .iter()
+                .map(|image| image.get_format())
+                .collect(),
+            depth_stencil_format: self.depth_stencil_image.map(|image| image.get_format()),
+            samples: self.samples,
+        }
+    }
+
+    // Clear values are given in attachment order: colors, then depth/stencil. Render with
+    // RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS `flags` to execute secondary command buffers.
+    pub fn cmd_begin(
+        &self,
+        context: &SharedContext,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        clear_values: &[vk::ClearValue],
+        flags: vk::RenderingFlags,
+    ) {
+        self.cmd_begin_with_device_group(context, cmd, extent, clear_values, flags, None);
+    }
+
+    pub(crate) fn cmd_begin_with_device_group(
+        &self,
+        context: &SharedContext,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        clear_values: &[vk::ClearValue],
+        flags: vk::RenderingFlags,
+        device_group_info: Option<&mut vk::DeviceGroupRenderPassBeginInfo>,
+    ) {
+        let clear_value = |index: usize| clear_values.get(index).copied().unwrap_or_default();
+        let load_op = match self.load_color {
+            true => vk::AttachmentLoadOp::LOAD,
+            false => vk::AttachmentLoadOp::CLEAR,
+        };
+        let color_attachments = self
+            .color_images
+            .iter()
+            .enumerate()
+            .map(|(index, image)| {
+                let mut attachment = vk::RenderingAttachmentInfo::builder()
+                    .image_view(image.get_image_view())
+                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .load_op(load_op)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .clear_value(clear_value(index));
+                if let Some(resolve_image) = self.resolve_images.get(index) {
+                    attachment = attachment
+                        .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                        .resolve_image_view(resolve_image.get_image_view())
+                        .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+                }
+                attachment.build()
+            })
+            .collect::<Vec<_>>();
+        let depth_attachment = self.depth_stencil_image.map(|image| {
+            vk::RenderingAttachmentInfo::builder()
+                .image_view(image.get_image_view())
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(clear_value(color_attachments.len()))
+                .build()
+        });
+        let mut rendering_info = vk::RenderingInfo::builder()
+            .flags(flags)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+        if let Some(depth_attachment) = &depth_attachment {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+            if has_stencil_component(self.depth_stencil_image.unwrap().get_format()) {
+                rendering_info = rendering_info.stencil_attachment(depth_attachment);
+            }
+        }
+        if let Some(device_group_info) = device_group_info {
+            rendering_info = rendering_info.push_next(device_group_info);
+        }
+        context.cmd_begin_rendering(cmd, &rendering_info);
+    }
+}
+
 pub struct RenderPass {
     context: Arc<SharedContext>,
     render_pass: vk::RenderPass,
@@ -37,12 +189,21 @@ impl RenderPass {
                 if info.present && info.resolve_images.is_empty() {
                     layout = info.final_layout;
                 }
+                let (load_op, initial_layout) = if info.load_color {
+                    (
+                        vk::AttachmentLoadOp::LOAD,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    )
+                } else {
+                    (vk::AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED)
+                };
                 attachments_desc.push(
                     vk::AttachmentDescription::builder()
                         .format(color_image.get_format())
                         .samples(info.samples)
-                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .load_op(load_op)
                         .store_op(vk::AttachmentStoreOp::STORE)
+                        .initial_layout(initial_layout)
                         .final_layout(layout)
                         .build(),
                 );
@@ -56,11 +217,16 @@ impl RenderPass {
             let mut depth_attachment_refs = Vec::<vk::AttachmentReference>::new();
             match info.depth_stencil_image {
                 Some(image) => {
+                    let stencil_load_op = match has_stencil_component(image.get_format()) {
+                        true => vk::AttachmentLoadOp::CLEAR,
+                        false => vk::AttachmentLoadOp::DONT_CARE,
+                    };
                     attachments_desc.push(
                         vk::AttachmentDescription::builder()
                             .format(image.get_format())
                             .samples(info.samples)
                             .load_op(vk::AttachmentLoadOp::CLEAR)
+                            .stencil_load_op(stencil_load_op)
                             .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
                             .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
                             .build(),
@@ -146,7 +312,6 @@ impl RenderPass {
                 layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             });
             index += 1;
-            break;
         }
 
         let mut depth_attachment_refs = Vec::<vk::AttachmentReference>::new();
@@ -183,8 +348,7 @@ impl RenderPass {
                 attachment: index,
                 layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             });
-            //index += 1;
-            break;
+            index += 1;
         }
         let mut subpass_builder = vk::SubpassDescription::builder()
             .color_attachments(&color_attachment_refs)
@@ -235,6 +399,61 @@ impl RenderPass {
     }
 }
 
+impl RenderPass {
+    // Clear values are given in attachment order: colors, depth/stencil, then resolves.
+    pub fn cmd_begin(
+        &self,
+        cmd: vk::CommandBuffer,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        clear_values: &[vk::ClearValue],
+        contents: vk::SubpassContents,
+    ) {
+        let begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            })
+            .clear_values(clear_values);
+        unsafe {
+            self.context
+                .device()
+                .cmd_begin_render_pass(cmd, &begin_info, contents);
+        }
+    }
+}
+
+// Anything rendered to with a full framebuffer extent, see Context::cmd_set_full_viewport.
+pub trait RenderArea {
+    fn get_render_extent(&self) -> vk::Extent2D;
+}
+
+impl RenderArea for vk::Extent2D {
+    fn get_render_extent(&self) -> vk::Extent2D {
+        *self
+    }
+}
+
+impl RenderArea for Image2d {
+    fn get_render_extent(&self) -> vk::Extent2D {
+        self.get_extent()
+    }
+}
+
+pub fn clear_color(color: [f32; 4]) -> vk::ClearValue {
+    vk::ClearValue {
+        color: vk::ClearColorValue { float32: color },
+    }
+}
+
+pub fn clear_depth_stencil(depth: f32, stencil: u32) -> vk::ClearValue {
+    vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+    }
+}
+
 impl Resource<vk::RenderPass> for RenderPass {
     fn handle(&self) -> vk::RenderPass {
         self.render_pass
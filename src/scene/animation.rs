@@ -0,0 +1,354 @@
+use super::ModelVertex;
+use crate::{Buffer, BufferInfo, Context, Result};
+use ash::vk;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// std430 element of the skin vertex stream, parallel to the mesh vertices. Joints index
+// Skeleton::joints.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SkinVertex {
+    pub joints: [u32; 4],
+    pub weights: glam::Vec4,
+}
+
+impl SkinVertex {
+    // Vertices without weights (e.g. of unskinned primitives of a skinned mesh) stay in place.
+    pub fn skin_matrix(&self, joint_matrices: &[glam::Mat4]) -> glam::Mat4 {
+        let total = self.weights.x + self.weights.y + self.weights.z + self.weights.w;
+        if total <= 0.0 {
+            return glam::Mat4::IDENTITY;
+        }
+        let mut matrix = glam::Mat4::ZERO;
+        for (joint, weight) in self.joints.iter().zip(self.weights.to_array()) {
+            if weight > 0.0 {
+                matrix += joint_matrices[*joint as usize] * (weight / total);
+            }
+        }
+        matrix
+    }
+
+    pub fn skin(&self, vertex: &ModelVertex, joint_matrices: &[glam::Mat4]) -> ModelVertex {
+        let matrix = self.skin_matrix(joint_matrices);
+        let normal = matrix
+            .transform_vector3(vertex.normal.truncate())
+            .normalize_or_zero();
+        ModelVertex {
+            pos: matrix * vertex.pos,
+            normal: normal.extend(vertex.normal.w),
+            ..*vertex
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeTransform {
+    pub translation: glam::Vec3,
+    pub rotation: glam::Quat,
+    pub scale: glam::Vec3,
+}
+
+impl Default for NodeTransform {
+    fn default() -> Self {
+        NodeTransform {
+            translation: glam::Vec3::ZERO,
+            rotation: glam::Quat::IDENTITY,
+            scale: glam::Vec3::ONE,
+        }
+    }
+}
+
+impl NodeTransform {
+    pub fn matrix(&self) -> glam::Mat4 {
+        glam::Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SkeletonNode {
+    // glTF node index, targeted by the animation channels.
+    pub node: usize,
+    // Index in Skeleton::nodes.
+    pub parent: Option<usize>,
+    pub rest: NodeTransform,
+}
+
+// Joint hierarchy of a glTF skin. The nodes hold the joints and their ancestors, parents
+// before children, so that global transforms are computed in a single pass.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub name: String,
+    pub nodes: Vec<SkeletonNode>,
+    // Index in `nodes` of each joint, in skin order.
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<glam::Mat4>,
+    node_slots: HashMap<usize, usize>,
+}
+
+impl Skeleton {
+    pub fn new(
+        name: String,
+        nodes: Vec<SkeletonNode>,
+        joint_nodes: &[usize],
+        inverse_bind_matrices: Vec<glam::Mat4>,
+    ) -> Self {
+        let node_slots = nodes
+            .iter()
+            .enumerate()
+            .map(|(slot, node)| (node.node, slot))
+            .collect::<HashMap<_, _>>();
+        let joints = joint_nodes.iter().map(|node| node_slots[node]).collect();
+        Skeleton {
+            name,
+            nodes,
+            joints,
+            inverse_bind_matrices,
+            node_slots,
+        }
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    // Joint matrices of the rest pose.
+    pub fn rest_pose(&self, joint_matrices: &mut Vec<glam::Mat4>) {
+        let locals = self.nodes.iter().map(|node| node.rest).collect();
+        self.joint_matrices(locals, joint_matrices);
+    }
+
+    // Joint matrices of `clip` at `time`, in the space of the skeleton root's parent (the world
+    // space for skeletons at the scene root). Nodes without channels keep their rest transform.
+    pub fn sample(&self, clip: &AnimationClip, time: f32, joint_matrices: &mut Vec<glam::Mat4>) {
+        let mut locals = self.nodes.iter().map(|node| node.rest).collect::<Vec<_>>();
+        for channel in &clip.channels {
+            if let Some(slot) = self.node_slots.get(&channel.node) {
+                channel.apply(time, &mut locals[*slot]);
+            }
+        }
+        self.joint_matrices(locals, joint_matrices);
+    }
+
+    fn joint_matrices(&self, locals: Vec<NodeTransform>, joint_matrices: &mut Vec<glam::Mat4>) {
+        let mut globals = Vec::<glam::Mat4>::with_capacity(self.nodes.len());
+        for (node, local) in self.nodes.iter().zip(locals) {
+            let global = match node.parent {
+                Some(parent) => globals[parent] * local.matrix(),
+                None => local.matrix(),
+            };
+            globals.push(global);
+        }
+        joint_matrices.clear();
+        joint_matrices.extend(
+            self.joints
+                .iter()
+                .zip(&self.inverse_bind_matrices)
+                .map(|(joint, inverse_bind)| globals[*joint] * *inverse_bind),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationProperty {
+    Translation,
+    // Quaternion values, as x, y, z, w.
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationInterpolation {
+    Step,
+    Linear,
+    // Values are (in tangent, value, out tangent) triplets.
+    CubicSpline,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationChannel {
+    // glTF node index.
+    pub node: usize,
+    pub property: AnimationProperty,
+    pub interpolation: AnimationInterpolation,
+    // Key times in seconds, ascending and not empty.
+    pub times: Vec<f32>,
+    pub values: Vec<glam::Vec4>,
+}
+
+impl AnimationChannel {
+    fn key(&self, index: usize) -> glam::Vec4 {
+        match self.interpolation {
+            AnimationInterpolation::CubicSpline => self.values[index * 3 + 1],
+            _ => self.values[index],
+        }
+    }
+
+    // Value at `time`, clamped to the first and last keys.
+    pub fn sample(&self, time: f32) -> glam::Vec4 {
+        let last = self.times.len() - 1;
+        let next = self.times.partition_point(|key_time| *key_time <= time);
+        if next == 0 {
+            return self.key(0);
+        }
+        if next > last {
+            return self.key(last);
+        }
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / delta;
+        let rotation = self.property == AnimationProperty::Rotation;
+        match self.interpolation {
+            AnimationInterpolation::Step => self.key(previous),
+            AnimationInterpolation::Linear if rotation => {
+                let from = glam::Quat::from_vec4(self.key(previous));
+                let to = glam::Quat::from_vec4(self.key(next));
+                glam::Vec4::from(from.slerp(to, t))
+            }
+            AnimationInterpolation::Linear => self.key(previous).lerp(self.key(next), t),
+            AnimationInterpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                let value = self.values[previous * 3 + 1] * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + self.values[previous * 3 + 2] * (delta * (t3 - 2.0 * t2 + t))
+                    + self.values[next * 3 + 1] * (-2.0 * t3 + 3.0 * t2)
+                    + self.values[next * 3] * (delta * (t3 - t2));
+                match rotation {
+                    true => value.normalize(),
+                    false => value,
+                }
+            }
+        }
+    }
+
+    pub fn apply(&self, time: f32, transform: &mut NodeTransform) {
+        let value = self.sample(time);
+        match self.property {
+            AnimationProperty::Translation => transform.translation = value.truncate(),
+            AnimationProperty::Rotation => {
+                transform.rotation = glam::Quat::from_vec4(value).normalize()
+            }
+            AnimationProperty::Scale => transform.scale = value.truncate(),
+        }
+    }
+}
+
+// A glTF animation. Morph target weight channels are skipped.
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    // Last key time of all channels, in seconds.
+    pub duration: f32,
+    pub channels: Vec<AnimationChannel>,
+}
+
+// Playback state of a clip, see Skeleton::sample.
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationPlayer {
+    // Index in Scene::animations.
+    pub clip: usize,
+    pub time: f32,
+    pub speed: f32,
+    pub looping: bool,
+    pub playing: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: usize) -> Self {
+        AnimationPlayer {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            playing: true,
+        }
+    }
+
+    // Returns the new time, wrapped around (looping) or clamped to the clip duration.
+    pub fn advance(&mut self, clips: &[AnimationClip], delta_seconds: f32) -> f32 {
+        if self.playing {
+            let duration = clips[self.clip].duration;
+            self.time += delta_seconds * self.speed;
+            self.time = match self.looping && duration > 0.0 {
+                true => self.time.rem_euclid(duration),
+                false => self.time.clamp(0.0, duration),
+            };
+        }
+        self.time
+    }
+
+    pub fn sample(
+        &self,
+        clips: &[AnimationClip],
+        skeleton: &Skeleton,
+        joint_matrices: &mut Vec<glam::Mat4>,
+    ) {
+        skeleton.sample(&clips[self.clip], self.time, joint_matrices);
+    }
+}
+
+// Joint matrices of the current frame, pushed per skeleton and uploaded once per frame to a
+// storage buffer per frame in flight, like ObjectTable. Raster shaders skin with the matrices
+// (see assets/glsl/skinning.glsl), ray tracing refits the BLAS of vertices skinned with
+// Mesh::upload_skinned_vertices.
+pub struct JointBuffer {
+    matrices: Vec<glam::Mat4>,
+    buffers: Vec<Buffer>,
+    capacity: usize,
+}
+
+impl JointBuffer {
+    pub fn new(context: Arc<Context>, capacity: usize, frames_count: usize) -> Result<Self> {
+        let buffers = (0..frames_count)
+            .map(|_| {
+                Buffer::new(
+                    context.clone(),
+                    BufferInfo::default()
+                        .name("JointBuffer")
+                        .usage_storage()
+                        .cpu_to_gpu(),
+                    (capacity * std::mem::size_of::<glam::Mat4>()) as vk::DeviceSize,
+                    capacity as u32,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(JointBuffer {
+            matrices: Vec::with_capacity(capacity),
+            buffers,
+            capacity,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.matrices.clear();
+    }
+
+    // Returns the joint offset of the skeleton, to pass to the skinning shaders.
+    pub fn push(&mut self, joint_matrices: &[glam::Mat4]) -> u32 {
+        assert!(
+            self.matrices.len() + joint_matrices.len() <= self.capacity,
+            "JointBuffer is full."
+        );
+        let offset = self.matrices.len() as u32;
+        self.matrices.extend_from_slice(joint_matrices);
+        offset
+    }
+
+    pub fn matrices(&self) -> &[glam::Mat4] {
+        &self.matrices
+    }
+
+    // Copies the matrices to the buffer of `frame_index`, before submitting the frame draws.
+    pub fn upload(&self, frame_index: usize) {
+        if !self.matrices.is_empty() {
+            self.buffers[frame_index].update(&self.matrices);
+        }
+    }
+
+    pub fn get_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.buffers[frame_index]
+    }
+
+    pub fn get_descriptor_info(&self, frame_index: usize) -> vk::DescriptorBufferInfo {
+        self.buffers[frame_index].get_descriptor_info()
+    }
+}
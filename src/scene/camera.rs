@@ -1,12 +1,17 @@
 use glam::*;
-use winit::event::WindowEvent;
+use winit::event::{VirtualKeyCode, WindowEvent};
 
-enum Modes {
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    // Orbit, pan and dolly around the center.
+    #[default]
     Examine,
+    // Mouse-look and keyboard movement along the view direction.
     Fly,
+    // Like Fly, with the movement kept orthogonal to the up vector.
     Walk,
-    Trackball,
 }
+
 enum Actions {
     None,
     Orbit,
@@ -22,12 +27,46 @@ pub struct CameraInput {
     pub shift: bool,
     pub ctrl: bool,
     pub alt: bool,
+    // Movement keys of the Fly and Walk modes: WASD, E and Q.
+    pub forward: bool,
+    pub backward: bool,
+    pub left: bool,
+    pub right: bool,
+    pub ascend: bool,
+    pub descend: bool,
+    // Analog movement (right, up, forward) and look rates in [-1, 1], added to the keys and
+    // mouse, e.g. from gamepad sticks (see CameraManip::update_gamepad).
+    pub move_axis: Vec3,
+    pub look_axis: Vec2,
 }
 
 impl CameraInput {
     pub fn is_mouse_down(&self) -> bool {
         self.lmb || self.mmb || self.rmb
     }
+
+    pub fn is_moving(&self) -> bool {
+        self.forward
+            || self.backward
+            || self.left
+            || self.right
+            || self.ascend
+            || self.descend
+            || self.move_axis != Vec3::ZERO
+    }
+
+    fn set_key(&mut self, key: VirtualKeyCode, is_down: bool) {
+        let flag = match key {
+            VirtualKeyCode::W => &mut self.forward,
+            VirtualKeyCode::S => &mut self.backward,
+            VirtualKeyCode::A => &mut self.left,
+            VirtualKeyCode::D => &mut self.right,
+            VirtualKeyCode::E => &mut self.ascend,
+            VirtualKeyCode::Q => &mut self.descend,
+            _ => return,
+        };
+        *flag = is_down;
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -44,12 +83,55 @@ pub struct Camera {
     mouse_pos: Vec2,
     window_size: Vec2,
     speed: f32,
+    mode: CameraMode,
+    // Units per second of the Fly and Walk movements.
+    fly_speed: f32,
+    velocity: Vec3,
+    tween: Option<CameraTween>,
+}
+
+// Transition of set_target, processed by tick.
+#[derive(Clone, Copy, Debug)]
+struct CameraTween {
+    from_position: Vec3,
+    from_rotation: Quat,
+    from_distance: f32,
+    from_vfov: f32,
+    position: Vec3,
+    center: Vec3,
+    up: Vec3,
+    rotation: Quat,
+    vfov: f32,
+    duration: f32,
+    elapsed: f32,
+}
+
+// Rotation of the eye frame, looking down its -Z axis with its Y axis along the projected up.
+fn look_rotation(position: Vec3, center: Vec3, up: Vec3) -> Quat {
+    let view = Mat4::look_at_rh(position, center, up);
+    Quat::from_mat4(&view.inverse()).normalize()
 }
 
+// Rate at which the velocity reaches the target speed, per second.
+const FLY_DAMPING: f32 = 12.0;
+// Speed factor while shift is held.
+const FLY_BOOST: f32 = 4.0;
+// Screen widths per second turned at full CameraInput::look_axis deflection.
+const LOOK_RATE: f32 = 0.5;
+
 fn is_zero(value: f32) -> bool {
     value.abs() < f32::EPSILON
 }
 
+// A camera of the glTF file, see Scene::select_camera.
+#[derive(Clone, Debug)]
+pub struct SceneCamera {
+    pub name: String,
+    // Index in Scene::graph.
+    pub node: usize,
+    pub camera: Camera,
+}
+
 impl Camera {
     pub fn new(window_size: Vec2) -> Self {
         let mut camera = Camera {
@@ -65,6 +147,10 @@ impl Camera {
             mouse_pos: Vec2::ZERO,
             window_size,
             speed: 30.0,
+            mode: CameraMode::Examine,
+            fly_speed: 5.0,
+            velocity: Vec3::ZERO,
+            tween: None,
         };
         camera.update_persp();
         camera
@@ -75,8 +161,8 @@ impl Camera {
         let position = view_inverse * vec4(0.0,0.0,0.0,1.0);
         let up = view_inverse * vec4(0.0,1.0,0.0,0.0);
         let center = position + view_inverse * vec4(0.0,0.0,-4.0,0.0);
-        
-        let camera = Camera {
+
+        let mut camera = Camera {
             input: CameraInput::default(),
             position: position.xyz(),
             center: center.xyz(),
@@ -89,7 +175,12 @@ impl Camera {
             mouse_pos: Vec2::ZERO,
             window_size: vec2(1920.0, 1080.0),
             speed: 30.0,
+            mode: CameraMode::Examine,
+            fly_speed: 5.0,
+            velocity: Vec3::ZERO,
+            tween: None,
         };
+        camera.update_persp();
         camera
     }
 }
@@ -112,6 +203,91 @@ impl Camera {
         self.update_view();
     }
 
+    // Moves to the eye, center and up over `duration` seconds, advanced by tick. The eye eases
+    // in and out with a smooth-step, the orientation is slerped. Mouse manipulations cancel the
+    // transition.
+    pub fn set_target(&mut self, eye: Vec3, center: Vec3, up: Vec3, duration: f32) {
+        self.set_target_with_vfov(eye, center, up, self.vfov, duration);
+    }
+
+    // Transition to the view and vertical field of view of another camera, e.g. of
+    // Scene::cameras.
+    pub fn set_target_camera(&mut self, target: &Camera, duration: f32) {
+        self.set_target_with_vfov(
+            target.position,
+            target.center,
+            target.up,
+            target.vfov,
+            duration,
+        );
+    }
+
+    fn set_target_with_vfov(
+        &mut self,
+        eye: Vec3,
+        center: Vec3,
+        up: Vec3,
+        vfov: f32,
+        duration: f32,
+    ) {
+        self.velocity = Vec3::ZERO;
+        if duration <= 0.0 {
+            self.tween = None;
+            self.look_at(eye, center, up);
+            self.set_vfov(vfov);
+            return;
+        }
+        self.tween = Some(CameraTween {
+            from_position: self.position,
+            from_rotation: look_rotation(self.position, self.center, self.up),
+            from_distance: (self.center - self.position).length(),
+            from_vfov: self.vfov,
+            position: eye,
+            center,
+            up,
+            rotation: look_rotation(eye, center, up),
+            vfov,
+            duration,
+            elapsed: 0.0,
+        });
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.tween.is_some()
+    }
+
+    // Stops the transition of set_target where it is.
+    pub fn stop_animation(&mut self) {
+        self.tween = None;
+    }
+
+    fn tick_tween(&mut self, delta_seconds: f32) {
+        let tween = match &mut self.tween {
+            Some(tween) => tween,
+            None => return,
+        };
+        tween.elapsed += delta_seconds;
+        let t = (tween.elapsed / tween.duration).min(1.0);
+        let tween = *tween;
+        if t >= 1.0 {
+            self.tween = None;
+            self.look_at(tween.position, tween.center, tween.up);
+            self.set_vfov(tween.vfov);
+            return;
+        }
+        let s = t * t * (3.0 - 2.0 * t);
+        let rotation = tween.from_rotation.slerp(tween.rotation, s);
+        let distance = (tween.center - tween.position).length();
+        let distance = tween.from_distance + (distance - tween.from_distance) * s;
+        self.position = tween.from_position.lerp(tween.position, s);
+        self.center = self.position + rotation * -Vec3::Z * distance;
+        // Orthogonal to the view direction until the end of the transition.
+        self.up = rotation * Vec3::Y;
+        self.update_view();
+        self.vfov = tween.from_vfov + (tween.vfov - tween.from_vfov) * s;
+        self.update_persp();
+    }
+
     pub fn set_window_size(&mut self, window_size: Vec2) {
         self.window_size = window_size;
         self.update_persp();
@@ -126,18 +302,39 @@ impl Camera {
         self.update_persp();
     }
 
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.mode = mode;
+        self.velocity = Vec3::ZERO;
+    }
+
+    pub fn set_fly_speed(&mut self, fly_speed: f32) {
+        self.fly_speed = fly_speed;
+    }
+
     pub fn mouse_move(&mut self, x: f32, y: f32, input: &CameraInput) -> bool {
         let mut moved = false;
         let mut action = Actions::None;
+        // The left button orbits in Examine mode and looks around otherwise, alt swaps both.
+        let examine = self.mode == CameraMode::Examine;
         if input.lmb {
             if ((input.ctrl) && (input.shift)) || input.alt {
-                action = Actions::Orbit;
+                action = match examine {
+                    true => Actions::LookAround,
+                    false => Actions::Orbit,
+                };
             } else if input.shift {
                 action = Actions::Dolly;
             } else if input.ctrl {
                 action = Actions::Pan;
             } else {
-                action = Actions::LookAround;
+                action = match examine {
+                    true => Actions::Orbit,
+                    false => Actions::LookAround,
+                };
             }
         } else if input.mmb {
             action = Actions::Pan;
@@ -161,9 +358,13 @@ impl Camera {
                 self.pan(dx, -dy);
                 moved = true;
             }
-            Actions::LookAround => {}
+            Actions::LookAround => {
+                self.look_around(dx, dy);
+                moved = true;
+            }
         }
         if moved {
+            self.tween = None;
             self.update_view();
         }
         self.mouse_pos = vec2(x, y);
@@ -175,6 +376,7 @@ impl Camera {
         let fval = value as f32;
         let dx = fval * fval.abs() / self.window_size.x;
         self.dolly(0.0, -dx * self.speed);
+        self.tween = None;
         self.update_view();
     }
 
@@ -236,6 +438,83 @@ impl Camera {
         self.position = origin + center_to_eye;
     }
 
+    // Turns the view direction around the eye, a full width turns by half a circle. The Vulkan
+    // clip space flips Y: the up vector points down the screen.
+    fn look_around(&mut self, dx: f32, dy: f32) {
+        let eye_to_center = self.center - self.position;
+        let distance = eye_to_center.length();
+        let up = self.up.normalize_or_zero();
+        if is_zero(distance) || up == Vec3::ZERO {
+            return;
+        }
+        let yaw = Quat::from_axis_angle(up, -dx * std::f32::consts::PI);
+        let mut forward = yaw * (eye_to_center / distance);
+        let right = forward.cross(up).normalize();
+        let pitched = Quat::from_axis_angle(right, dy * std::f32::consts::PI) * forward;
+        // Stop short of looking along the up vector.
+        if pitched.dot(up).abs() < 0.99 {
+            forward = pitched;
+        }
+        self.center = self.position + forward * distance;
+    }
+
+    // Advances the set_target transition or else moves the eye and center with the movement keys
+    // of the Fly and Walk modes, returns whether the camera moved. To call once per frame with
+    // the frame duration; the fly velocity eases in and out of the target speed.
+    pub fn tick(&mut self, delta_seconds: f32, input: &CameraInput) -> bool {
+        if self.tween.is_some() {
+            self.tick_tween(delta_seconds);
+            return true;
+        }
+        let looked = self.tick_look(delta_seconds, input.look_axis);
+        if self.mode == CameraMode::Examine {
+            return looked;
+        }
+        let up = self.up.normalize_or_zero();
+        let mut forward = (self.center - self.position).normalize_or_zero();
+        if self.mode == CameraMode::Walk {
+            forward = (forward - up * forward.dot(up)).normalize_or_zero();
+        }
+        let right = forward.cross(up).normalize_or_zero();
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let mut direction = forward * (axis(input.forward, input.backward) + input.move_axis.z)
+            + right * (axis(input.right, input.left) + input.move_axis.x);
+        // The up vector points down the screen, see look_around.
+        if self.mode == CameraMode::Fly {
+            direction -= up * (axis(input.ascend, input.descend) + input.move_axis.y);
+        }
+        // Partial analog deflection moves slower, keys move at full speed.
+        let mut target = direction.clamp_length_max(1.0) * self.fly_speed;
+        if input.shift {
+            target *= FLY_BOOST;
+        }
+        let blend = 1.0 - (-FLY_DAMPING * delta_seconds).exp();
+        self.velocity = self.velocity.lerp(target, blend);
+        if target == Vec3::ZERO && self.velocity.length() < self.fly_speed * 1e-3 {
+            self.velocity = Vec3::ZERO;
+            return looked;
+        }
+        let offset = self.velocity * delta_seconds;
+        self.position += offset;
+        self.center += offset;
+        self.update_view();
+        true
+    }
+
+    // Orbits in Examine mode and looks around otherwise, like the left mouse button.
+    fn tick_look(&mut self, delta_seconds: f32, look_axis: Vec2) -> bool {
+        if look_axis == Vec2::ZERO {
+            return false;
+        }
+        let delta = look_axis * LOOK_RATE * delta_seconds;
+        match self.mode {
+            CameraMode::Examine => self.orbit(delta.x, delta.y),
+            _ => self.look_around(delta.x, -delta.y),
+        }
+        self.update_view();
+        true
+    }
+
     fn dolly(&mut self, dx: f32, dy: f32) {
         let mut z = self.center - self.position;
         let mut length = z.length();
@@ -268,6 +547,30 @@ impl Camera {
     pub fn perspective_matrix(&self) -> Mat4 {
         self.persp_matrix
     }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn window_size(&self) -> Vec2 {
+        self.window_size
+    }
+
+    pub fn mouse_pos(&self) -> Vec2 {
+        self.mouse_pos
+    }
+
+    // World space ray (origin, normalized direction) through a window pixel.
+    pub fn screen_ray(&self, pixel: Vec2) -> (Vec3, Vec3) {
+        let ndc = vec2(
+            2.0 * pixel.x / self.window_size.x - 1.0,
+            2.0 * pixel.y / self.window_size.y - 1.0,
+        );
+        let inv_view_proj = (self.persp_matrix * self.view_matrix).inverse();
+        let near = inv_view_proj.project_point3(vec3(ndc.x, ndc.y, 0.0));
+        let far = inv_view_proj.project_point3(vec3(ndc.x, ndc.y, 1.0));
+        (near, (far - near).normalize())
+    }
 }
 
 pub struct CameraManip {
@@ -276,6 +579,23 @@ pub struct CameraManip {
 }
 
 impl CameraManip {
+    // See Camera::tick.
+    pub fn tick(&mut self, delta_seconds: f32) -> bool {
+        self.camera.tick(delta_seconds, &self.input)
+    }
+
+    // Left stick moves in Fly and Walk modes, triggers descend and ascend, the right stick looks
+    // around (orbits in Examine mode). To call before tick, e.g. with App::gamepad.
+    #[cfg(feature = "gamepad")]
+    pub fn update_gamepad(&mut self, gamepad: &crate::GamepadState) {
+        self.input.move_axis = vec3(
+            gamepad.left_stick.x,
+            gamepad.right_trigger - gamepad.left_trigger,
+            gamepad.left_stick.y,
+        );
+        self.input.look_axis = gamepad.right_stick;
+    }
+
     pub fn update(&mut self, window_event: &WindowEvent) -> bool {
         let mut moved = false;
         match window_event {
@@ -307,6 +627,19 @@ impl CameraManip {
                     }
                 };
             }
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(key) = input.virtual_keycode {
+                    let is_down = input.state == winit::event::ElementState::Pressed;
+                    // Ctrl+Q quits the app.
+                    if !(is_down && self.input.ctrl) {
+                        self.input.set_key(key, is_down);
+                    }
+                }
+            }
+            WindowEvent::Focused(false) => {
+                // Releases are missed while the window is unfocused.
+                self.input = CameraInput::default();
+            }
             WindowEvent::MouseInput { state, button, .. } => {
                 let is_down = match state {
                     winit::event::ElementState::Pressed => true,
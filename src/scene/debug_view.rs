@@ -0,0 +1,340 @@
+use super::{ModelVertex, Scene};
+use crate::{Context, Resource, Shader, Vertex};
+use ash::vk;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// Standard debug visualizations of scene data. The same modes are implemented for raytracing in
+// assets/glsl/debug.rgen, debug.rchit and debug.rmiss, selected with DebugView::index() as a
+// push constant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    ObjectNormal,
+    WorldNormal,
+    Uv,
+    // Random color per primitive section, numbered like the instances of a SceneDescription.
+    InstanceId,
+    // Material base color multiplied by the vertex color.
+    Albedo,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 5] = [
+        DebugView::ObjectNormal,
+        DebugView::WorldNormal,
+        DebugView::Uv,
+        DebugView::InstanceId,
+        DebugView::Albedo,
+    ];
+
+    pub fn index(self) -> u32 {
+        self as u32
+    }
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self.index() as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::ObjectNormal => "object normal",
+            DebugView::WorldNormal => "world normal",
+            DebugView::Uv => "uv",
+            DebugView::InstanceId => "instance id",
+            DebugView::Albedo => "albedo",
+        }
+    }
+}
+
+const DEBUG_VIEW_VERT: &str = "
+#version 450
+layout(location = 0) in vec4 pos;
+layout(location = 1) in vec4 color;
+layout(location = 2) in vec4 normal;
+layout(location = 3) in vec4 uv;
+layout(push_constant) uniform Params {
+    mat4 mvp;
+    vec4 normal_matrix[3]; // world normal matrix columns, w of the first: instance id
+    vec4 albedo;
+} p;
+layout(location = 0) out vec3 out_object_normal;
+layout(location = 1) out vec3 out_world_normal;
+layout(location = 2) out vec2 out_uv;
+layout(location = 3) out vec4 out_color;
+void main() {
+    mat3 normal_matrix = mat3(p.normal_matrix[0].xyz, p.normal_matrix[1].xyz, p.normal_matrix[2].xyz);
+    out_object_normal = normal.xyz;
+    out_world_normal = normal_matrix * normal.xyz;
+    out_uv = uv.xy;
+    out_color = color;
+    gl_Position = p.mvp * pos;
+}
+";
+
+const DEBUG_VIEW_FRAG: &str = "
+#version 450
+layout(constant_id = 0) const uint MODE = 0;
+layout(location = 0) in vec3 object_normal;
+layout(location = 1) in vec3 world_normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in vec4 color;
+layout(location = 0) out vec4 out_color;
+layout(push_constant) uniform Params {
+    mat4 mvp;
+    vec4 normal_matrix[3];
+    vec4 albedo;
+} p;
+vec3 instance_color(uint id) {
+    uint h = (id + 1u) * 2654435761u;
+    return vec3((h >> 16) & 0xffu, (h >> 8) & 0xffu, h & 0xffu) / 255.0;
+}
+void main() {
+    vec3 result;
+    if (MODE == 0) {
+        result = normalize(object_normal) * 0.5 + 0.5;
+    } else if (MODE == 1) {
+        result = normalize(world_normal) * 0.5 + 0.5;
+    } else if (MODE == 2) {
+        result = vec3(fract(uv), 0.0);
+    } else if (MODE == 3) {
+        result = instance_color(uint(p.normal_matrix[0].w));
+    } else {
+        result = p.albedo.rgb * color.rgb;
+    }
+    out_color = vec4(result, 1.0);
+}
+";
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DebugViewParams {
+    mvp: glam::Mat4,
+    normal_matrix: [glam::Vec4; 3],
+    albedo: glam::Vec4,
+}
+
+// Draws the meshes of a Scene with one of the DebugView modes, in place of the app shading.
+// Holds one pipeline per mode so switching at runtime is free.
+pub struct DebugViewPass {
+    context: Arc<Context>,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: Vec<vk::Pipeline>,
+}
+
+impl DebugViewPass {
+    pub fn new(
+        context: Arc<Context>,
+        render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        let vert = Shader::from_source(
+            context.clone(),
+            DEBUG_VIEW_VERT,
+            "debug_view.vert",
+            vk::ShaderStageFlags::VERTEX,
+        )
+        .expect("Unable to compile debug view shader");
+        let frag = Shader::from_source(
+            context.clone(),
+            DEBUG_VIEW_FRAG,
+            "debug_view.frag",
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+        .expect("Unable to compile debug view shader");
+
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .size(std::mem::size_of::<DebugViewParams>() as u32)
+            .build()];
+        let layout_info =
+            vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            context
+                .device()
+                .create_pipeline_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let vertex_input_binding_descriptions = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: ModelVertex::stride(),
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let vertex_input_attribute_descriptions: Vec<_> = ModelVertex::format_offset()
+            .iter()
+            .enumerate()
+            .map(|(i, (format, offset))| vk::VertexInputAttributeDescription {
+                location: i as u32,
+                binding: 0,
+                format: *format,
+                offset: *offset,
+            })
+            .collect();
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&vertex_input_binding_descriptions)
+            .vertex_attribute_descriptions(&vertex_input_attribute_descriptions);
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+            scissor_count: 1,
+            viewport_count: 1,
+            ..Default::default()
+        };
+        // No culling: broken winding is one of the things this is meant to reveal.
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            ..Default::default()
+        };
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: samples,
+            ..Default::default()
+        };
+        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: 1,
+            depth_write_enable: 1,
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            max_depth_bounds: 1.0,
+            ..Default::default()
+        };
+        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            ..Default::default()
+        }];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachment_states);
+        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+
+        let entry_name = CString::new("main").unwrap();
+        let mode_entries = [vk::SpecializationMapEntry {
+            constant_id: 0,
+            offset: 0,
+            size: std::mem::size_of::<u32>(),
+        }];
+        let pipelines = DebugView::ALL
+            .iter()
+            .map(|view| {
+                let mode = view.index().to_ne_bytes();
+                let specialization_info = vk::SpecializationInfo::builder()
+                    .map_entries(&mode_entries)
+                    .data(&mode);
+                let stages = [
+                    vert.get_create_info(&entry_name),
+                    frag.get_create_info_with_specialization(&entry_name, &specialization_info),
+                ];
+                let create_infos = [vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&stages)
+                    .vertex_input_state(&vertex_input_state_info)
+                    .input_assembly_state(&vertex_input_assembly_state_info)
+                    .viewport_state(&viewport_state_info)
+                    .rasterization_state(&rasterization_info)
+                    .multisample_state(&multisample_state_info)
+                    .depth_stencil_state(&depth_state_info)
+                    .color_blend_state(&color_blend_state)
+                    .dynamic_state(&dynamic_state_info)
+                    .layout(pipeline_layout)
+                    .render_pass(render_pass)
+                    .build()];
+                unsafe {
+                    context
+                        .device()
+                        .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                        .expect("Unable to create debug view pipeline")[0]
+                }
+            })
+            .collect();
+
+        DebugViewPass {
+            context,
+            pipeline_layout,
+            pipelines,
+        }
+    }
+
+    // Must be recorded inside the render pass, with viewport and scissor already set.
+    pub fn cmd_draw(
+        &self,
+        cmd: vk::CommandBuffer,
+        scene: &Scene,
+        view: DebugView,
+        view_projection: glam::Mat4,
+    ) {
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipelines[view.index() as usize],
+            );
+        }
+        let mut instance_id = 0;
+        for mesh in &scene.meshes {
+            let normal_matrix = glam::Mat3::from_mat4(mesh.transform).inverse().transpose();
+            for section in &mesh.primitive_sections {
+                let albedo = section
+                    .get_material_index()
+                    .and_then(|index| scene.materials.get(index))
+                    .map_or(glam::Vec4::ONE, |material| material.base_color);
+                let params = DebugViewParams {
+                    mvp: view_projection * mesh.transform,
+                    normal_matrix: [
+                        normal_matrix.x_axis.extend(instance_id as f32),
+                        normal_matrix.y_axis.extend(0.0),
+                        normal_matrix.z_axis.extend(0.0),
+                    ],
+                    albedo,
+                };
+                unsafe {
+                    let bytes = std::slice::from_raw_parts(
+                        &params as *const DebugViewParams as *const u8,
+                        std::mem::size_of::<DebugViewParams>(),
+                    );
+                    device.cmd_push_constants(
+                        cmd,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        bytes,
+                    );
+                    device.cmd_bind_vertex_buffers(
+                        cmd,
+                        0,
+                        &[mesh.vertex_buffer.handle()],
+                        &[section.get_vertex_offset_size()],
+                    );
+                    match &mesh.index_buffer {
+                        Some(indices) => {
+                            device.cmd_bind_index_buffer(
+                                cmd,
+                                indices.handle(),
+                                section.get_index_offset_size::<u32>(),
+                                vk::IndexType::UINT32,
+                            );
+                            device.cmd_draw_indexed(cmd, section.get_index_count(), 1, 0, 0, 0);
+                        }
+                        None => device.cmd_draw(cmd, section.get_vertex_count(), 1, 0, 0),
+                    }
+                }
+                instance_id += 1;
+            }
+        }
+    }
+}
+
+impl Drop for DebugViewPass {
+    fn drop(&mut self) {
+        unsafe {
+            for pipeline in &self.pipelines {
+                self.context.device().destroy_pipeline(*pipeline, None);
+            }
+            self.context
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
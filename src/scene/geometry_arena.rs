@@ -0,0 +1,100 @@
+use super::ModelVertex;
+use crate::{Buffer, BufferInfo, Context, Resource, Result, StagingBelt};
+use ash::vk;
+use std::sync::Arc;
+
+// Vertices and indices of many meshes packed in shared buffers, e.g. those of a Scene: a single
+// allocation per buffer and a single bind for all of them, see cmd_bind and
+// Mesh::cmd_draw_section_bound. The meshes share the buffers, their primitive sections
+// addressing their part.
+pub struct GeometryArena {
+    context: Arc<Context>,
+    pub vertex_buffer: Arc<Buffer>,
+    // None when no mesh is indexed.
+    pub index_buffer: Option<Arc<Buffer>>,
+}
+
+// First vertex and first index of a mesh in a GeometryArena.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GeometryBase {
+    pub vertex: usize,
+    pub index: usize,
+}
+
+impl GeometryArena {
+    // Records the uploads of the (vertices, indices) of each mesh, valid once `staging` is
+    // submitted. Returns the arena along with the base of each mesh. Indices stay relative to
+    // the mesh vertices. At least one vertex is required.
+    //
+    // The bases are aligned to minStorageBufferOffsetAlignment, the mesh parts being bound as
+    // storage buffers, e.g. by the vertex and index descriptors of ray::SceneDescription.
+    pub fn upload(
+        context: Arc<Context>,
+        staging: &mut StagingBelt,
+        meshes: &[(&[ModelVertex], &[u32])],
+    ) -> Result<(Self, Vec<GeometryBase>)> {
+        let limits = *context.limits();
+        let mut bases = Vec::with_capacity(meshes.len());
+        let mut base = GeometryBase::default();
+        for (vertices, indices) in meshes {
+            base.vertex =
+                limits.align_ssbo_elements(base.vertex, std::mem::size_of::<ModelVertex>());
+            base.index = limits.align_ssbo_elements(base.index, std::mem::size_of::<u32>());
+            bases.push(base);
+            base.vertex += vertices.len();
+            base.index += indices.len();
+        }
+        let buffer = |info: BufferInfo, element_size: usize, element_count: usize| {
+            Buffer::new(
+                context.clone(),
+                info.gpu_only().usage_transfer_dst(),
+                (element_size * element_count) as vk::DeviceSize,
+                element_count as u32,
+            )
+        };
+        let vertex_buffer = buffer(
+            BufferInfo::default()
+                .name("GeometryArenaVertices")
+                .usage_vertex()
+                .usage_storage(),
+            std::mem::size_of::<ModelVertex>(),
+            base.vertex,
+        )?;
+        let index_buffer = match base.index {
+            0 => None,
+            count => Some(buffer(
+                BufferInfo::default()
+                    .name("GeometryArenaIndices")
+                    .usage_index()
+                    .usage_storage(),
+                std::mem::size_of::<u32>(),
+                count,
+            )?),
+        };
+        for ((vertices, indices), base) in meshes.iter().zip(&bases) {
+            let offset = (base.vertex * std::mem::size_of::<ModelVertex>()) as vk::DeviceSize;
+            staging.upload(&vertex_buffer, offset, vertices)?;
+            if let Some(index_buffer) = &index_buffer {
+                let offset = (base.index * std::mem::size_of::<u32>()) as vk::DeviceSize;
+                staging.upload(index_buffer, offset, indices)?;
+            }
+        }
+        let arena = GeometryArena {
+            context,
+            vertex_buffer: Arc::new(vertex_buffer),
+            index_buffer: index_buffer.map(Arc::new),
+        };
+        Ok((arena, bases))
+    }
+
+    // Binds the vertex and index buffers at offset zero.
+    pub fn cmd_bind(&self, cmd: vk::CommandBuffer) {
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_vertex_buffers(cmd, 0, &[self.vertex_buffer.handle()], &[0]);
+            if let Some(indices) = &self.index_buffer {
+                device.cmd_bind_index_buffer(cmd, indices.handle(), 0, vk::IndexType::UINT32);
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use super::Camera;
+use crate::util::BasicVertex;
+use glam::*;
+use winit::event::{ElementState, MouseButton, WindowEvent};
+
+const AXES: [Vec3; 3] = [Vec3::X, Vec3::Y, Vec3::Z];
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Drag {
+    axis: usize,
+    start_transform: Mat4,
+    // Axis parameter (translate/scale) or plane direction (rotate) where the drag started.
+    start_param: f32,
+    start_dir: Vec3,
+}
+
+// Translate/rotate/scale manipulator for a single scene instance. Feed it window events with
+// `update`, apply the returned transform (e.g. with `apply` on a SceneDescription) and draw
+// it with DebugDraw::gizmo, or the triangles returned by `vertices` with a BasicVertex pipeline.
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    // Size of the gizmo relative to its distance from the camera.
+    pub size: f32,
+    target: Option<usize>,
+    transform: Mat4,
+    hovered_axis: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Gizmo {
+            mode: GizmoMode::Translate,
+            size: 0.15,
+            target: None,
+            transform: Mat4::IDENTITY,
+            hovered_axis: None,
+            drag: None,
+        }
+    }
+}
+
+fn axis_color(axis: usize, highlighted: bool) -> Vec4 {
+    if highlighted {
+        return vec4(1.0, 0.85, 0.1, 1.0);
+    }
+    match axis {
+        0 => vec4(0.9, 0.2, 0.2, 1.0),
+        1 => vec4(0.2, 0.8, 0.2, 1.0),
+        _ => vec4(0.2, 0.4, 0.95, 1.0),
+    }
+}
+
+// Parameter along the axis line (origin, dir) of the point closest to the ray.
+fn closest_axis_param(origin: Vec3, dir: Vec3, ray_origin: Vec3, ray_dir: Vec3) -> Option<f32> {
+    let w = origin - ray_origin;
+    let b = dir.dot(ray_dir);
+    let denom = 1.0 - b * b;
+    if denom.abs() < 1e-6 {
+        return None;
+    }
+    Some((b * ray_dir.dot(w) - dir.dot(w)) / denom)
+}
+
+fn ray_plane(origin: Vec3, normal: Vec3, ray_origin: Vec3, ray_dir: Vec3) -> Option<Vec3> {
+    let denom = normal.dot(ray_dir);
+    if denom.abs() < 1e-6 {
+        return None;
+    }
+    let t = normal.dot(origin - ray_origin) / denom;
+    if t < 0.0 {
+        return None;
+    }
+    Some(ray_origin + ray_dir * t)
+}
+
+fn ray_point_distance(ray_origin: Vec3, ray_dir: Vec3, point: Vec3) -> f32 {
+    let v = point - ray_origin;
+    (v - ray_dir * v.dot(ray_dir).max(0.0)).length()
+}
+
+impl Gizmo {
+    pub fn new(mode: GizmoMode) -> Self {
+        Gizmo {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    // Attaches the gizmo to the instance `index` (e.g. a BLAS index) with its current transform.
+    pub fn attach(&mut self, index: usize, transform: Mat4) {
+        self.target = Some(index);
+        self.transform = transform;
+        self.drag = None;
+    }
+
+    pub fn detach(&mut self) {
+        self.target = None;
+        self.hovered_axis = None;
+        self.drag = None;
+    }
+
+    pub fn target(&self) -> Option<usize> {
+        self.target
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        self.transform
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn is_hovered(&self) -> bool {
+        self.hovered_axis.is_some()
+    }
+
+    pub fn apply(&self, scene: &mut crate::ray::SceneDescription) {
+        if let Some(index) = self.target {
+            scene.blas_transform(self.transform, index);
+        }
+    }
+
+    fn origin(&self) -> Vec3 {
+        self.transform.w_axis.xyz()
+    }
+
+    fn scale(&self, camera: &Camera) -> f32 {
+        (camera.position() - self.origin()).length() * self.size
+    }
+
+    // Handles mouse events, returns the new target transform while dragging.
+    pub fn update(&mut self, window_event: &WindowEvent, camera: &Camera) -> Option<Mat4> {
+        self.target?;
+        match window_event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let (ray_origin, ray_dir) =
+                    camera.screen_ray(vec2(position.x as f32, position.y as f32));
+                match self.drag {
+                    Some(drag) => {
+                        self.transform = self.drag_transform(&drag, ray_origin, ray_dir);
+                        return Some(self.transform);
+                    }
+                    None => self.hovered_axis = self.pick_axis(camera, ray_origin, ray_dir),
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    if let Some(axis) = self.hovered_axis {
+                        let (ray_origin, ray_dir) = camera.screen_ray(camera.mouse_pos());
+                        self.drag = self.begin_drag(axis, ray_origin, ray_dir);
+                    }
+                }
+                ElementState::Released => self.drag = None,
+            },
+            _ => {}
+        }
+        None
+    }
+
+    fn world_axis(&self, axis: usize) -> Vec3 {
+        self.transform.transform_vector3(AXES[axis]).normalize()
+    }
+
+    fn pick_axis(&self, camera: &Camera, ray_origin: Vec3, ray_dir: Vec3) -> Option<usize> {
+        let scale = self.scale(camera);
+        let threshold = scale * 0.08;
+        let origin = self.origin();
+        (0..3)
+            .filter_map(|axis| {
+                let dir = self.world_axis(axis);
+                let distance = match self.mode {
+                    GizmoMode::Rotate => {
+                        let hit = ray_plane(origin, dir, ray_origin, ray_dir)?;
+                        ((hit - origin).length() - scale).abs()
+                    }
+                    _ => {
+                        let t = closest_axis_param(origin, dir, ray_origin, ray_dir)?
+                            .clamp(0.0, scale);
+                        ray_point_distance(ray_origin, ray_dir, origin + dir * t)
+                    }
+                };
+                Some((axis, distance))
+            })
+            .filter(|(_, distance)| *distance < threshold)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(axis, _)| axis)
+    }
+
+    fn begin_drag(&self, axis: usize, ray_origin: Vec3, ray_dir: Vec3) -> Option<Drag> {
+        let origin = self.origin();
+        let dir = self.world_axis(axis);
+        let (start_param, start_dir) = match self.mode {
+            GizmoMode::Rotate => {
+                let hit = ray_plane(origin, dir, ray_origin, ray_dir)?;
+                (0.0, (hit - origin).normalize())
+            }
+            _ => (closest_axis_param(origin, dir, ray_origin, ray_dir)?, Vec3::ZERO),
+        };
+        Some(Drag {
+            axis,
+            start_transform: self.transform,
+            start_param,
+            start_dir,
+        })
+    }
+
+    fn drag_transform(&self, drag: &Drag, ray_origin: Vec3, ray_dir: Vec3) -> Mat4 {
+        let start = drag.start_transform;
+        let origin = start.w_axis.xyz();
+        let dir = start.transform_vector3(AXES[drag.axis]).normalize();
+        match self.mode {
+            GizmoMode::Translate => {
+                match closest_axis_param(origin, dir, ray_origin, ray_dir) {
+                    Some(t) => Mat4::from_translation(dir * (t - drag.start_param)) * start,
+                    None => self.transform,
+                }
+            }
+            GizmoMode::Scale => {
+                match closest_axis_param(origin, dir, ray_origin, ray_dir) {
+                    Some(t) if drag.start_param.abs() > 1e-4 => {
+                        let mut factor = Vec3::ONE;
+                        factor[drag.axis] = (t / drag.start_param).max(1e-3);
+                        start * Mat4::from_scale(factor)
+                    }
+                    _ => self.transform,
+                }
+            }
+            GizmoMode::Rotate => match ray_plane(origin, dir, ray_origin, ray_dir) {
+                Some(hit) => {
+                    let current = (hit - origin).normalize();
+                    let angle = drag
+                        .start_dir
+                        .cross(current)
+                        .dot(dir)
+                        .atan2(drag.start_dir.dot(current));
+                    Mat4::from_translation(origin)
+                        * Mat4::from_axis_angle(dir, angle)
+                        * Mat4::from_translation(-origin)
+                        * start
+                }
+                None => self.transform,
+            },
+        }
+    }
+
+    // Colored line segments (start, end, color) of the gizmo in world space.
+    pub fn lines(&self, camera: &Camera) -> Vec<(Vec3, Vec3, Vec4)> {
+        let mut lines = Vec::new();
+        if self.target.is_none() {
+            return lines;
+        }
+        let origin = self.origin();
+        let scale = self.scale(camera);
+        let highlighted = self.drag.map(|d| d.axis).or(self.hovered_axis);
+        for axis in 0..3 {
+            let dir = self.world_axis(axis);
+            let color = axis_color(axis, highlighted == Some(axis));
+            let tip = origin + dir * scale;
+            let side = self.world_axis((axis + 1) % 3) * scale * 0.06;
+            let other = self.world_axis((axis + 2) % 3) * scale * 0.06;
+            match self.mode {
+                GizmoMode::Translate => {
+                    lines.push((origin, tip, color));
+                    let base = tip - dir * scale * 0.15;
+                    for offset in [side, -side, other, -other] {
+                        lines.push((tip, base + offset, color));
+                    }
+                }
+                GizmoMode::Scale => {
+                    lines.push((origin, tip, color));
+                    lines.push((tip - side, tip + side, color));
+                    lines.push((tip - other, tip + other, color));
+                }
+                GizmoMode::Rotate => {
+                    let u = self.world_axis((axis + 1) % 3) * scale;
+                    let v = self.world_axis((axis + 2) % 3) * scale;
+                    let point = |i: usize| {
+                        let a = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                        origin + u * a.cos() + v * a.sin()
+                    };
+                    for i in 0..CIRCLE_SEGMENTS {
+                        lines.push((point(i), point(i + 1), color));
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    // Gizmo lines expanded into camera-facing quads, drawable with a triangle list pipeline.
+    pub fn vertices(&self, camera: &Camera) -> Vec<BasicVertex> {
+        let width = self.scale(camera) * 0.012;
+        let eye = camera.position();
+        let mut vertices = Vec::new();
+        for (start, end, color) in self.lines(camera) {
+            let dir = end - start;
+            let offset = dir.cross(eye - start).normalize_or_zero() * width;
+            let corners = [start - offset, start + offset, end + offset, end - offset];
+            for i in [0, 1, 2, 0, 2, 3] {
+                vertices.push(BasicVertex {
+                    pos: corners[i].extend(1.0),
+                    color,
+                    uv: Vec2::ZERO,
+                });
+            }
+        }
+        vertices
+    }
+}
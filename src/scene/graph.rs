@@ -0,0 +1,173 @@
+use super::NodeTransform;
+
+pub struct SceneNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // Index in Scene::meshes.
+    pub mesh: Option<usize>,
+    local: NodeTransform,
+    global: glam::Mat4,
+}
+
+impl SceneNode {
+    pub fn new(name: &str, local: NodeTransform, mesh: Option<usize>) -> Self {
+        SceneNode {
+            name: name.to_owned(),
+            parent: None,
+            children: Vec::new(),
+            mesh,
+            local,
+            global: local.matrix(),
+        }
+    }
+
+    pub fn local_transform(&self) -> &NodeTransform {
+        &self.local
+    }
+
+    // As of the last SceneGraph::update_transforms.
+    pub fn global_transform(&self) -> glam::Mat4 {
+        self.global
+    }
+}
+
+// Node hierarchy of a scene, with the glTF node indices. Local transforms are set per node, the
+// global transforms of the modified nodes and their descendants are recomputed by
+// update_transforms.
+pub struct SceneGraph {
+    nodes: Vec<SceneNode>,
+    roots: Vec<usize>,
+    // Nodes whose local transform or parent changed since the last update.
+    dirty: Vec<usize>,
+}
+
+impl SceneGraph {
+    // Nodes with their children set, the parents are deduced.
+    pub fn new(mut nodes: Vec<SceneNode>) -> Self {
+        for index in 0..nodes.len() {
+            for child in nodes[index].children.clone() {
+                nodes[child].parent = Some(index);
+            }
+        }
+        let roots = (0..nodes.len())
+            .filter(|index| nodes[*index].parent.is_none())
+            .collect::<Vec<_>>();
+        let mut graph = SceneGraph {
+            nodes,
+            dirty: roots.clone(),
+            roots,
+        };
+        graph.update_transforms();
+        graph
+    }
+
+    pub fn nodes(&self) -> &[SceneNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, index: usize) -> &SceneNode {
+        &self.nodes[index]
+    }
+
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    // First node instancing the mesh, which places Mesh::transform.
+    pub fn mesh_node(&self, mesh: usize) -> Option<usize> {
+        self.nodes.iter().position(|node| node.mesh == Some(mesh))
+    }
+
+    pub fn set_local_transform(&mut self, index: usize, transform: NodeTransform) {
+        if self.nodes[index].local != transform {
+            self.nodes[index].local = transform;
+            self.dirty.push(index);
+        }
+    }
+
+    // Returns the index of the new node, its children are ignored.
+    pub fn add_node(&mut self, node: SceneNode, parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(SceneNode {
+            parent: None,
+            children: Vec::new(),
+            ..node
+        });
+        self.roots.push(index);
+        self.set_parent(index, parent);
+        index
+    }
+
+    pub fn set_parent(&mut self, index: usize, parent: Option<usize>) {
+        let mut ancestor = parent;
+        while let Some(node) = ancestor {
+            assert_ne!(node, index, "Node {} can't be its own ancestor.", index);
+            ancestor = self.nodes[node].parent;
+        }
+        match self.nodes[index].parent {
+            Some(previous) => self.nodes[previous]
+                .children
+                .retain(|child| *child != index),
+            None => self.roots.retain(|root| *root != index),
+        }
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(index),
+            None => self.roots.push(index),
+        }
+        self.nodes[index].parent = parent;
+        self.dirty.push(index);
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    // Recomputes the global transforms below the modified nodes and returns the updated nodes,
+    // parents before children.
+    pub fn update_transforms(&mut self) -> Vec<usize> {
+        let mut updated = Vec::new();
+        let mut visited = vec![false; self.nodes.len()];
+        let mut dirty = std::mem::take(&mut self.dirty);
+        // Shallower first, the subtrees of the updated nodes are skipped.
+        dirty.sort_by_key(|index| self.depth(*index));
+        for index in dirty {
+            if visited[index] {
+                continue;
+            }
+            let mut stack = vec![index];
+            while let Some(node) = stack.pop() {
+                let parent_global = self.nodes[node]
+                    .parent
+                    .map_or(glam::Mat4::IDENTITY, |parent| self.nodes[parent].global);
+                self.nodes[node].global = parent_global * self.nodes[node].local.matrix();
+                visited[node] = true;
+                updated.push(node);
+                stack.extend(self.nodes[node].children.iter().rev());
+            }
+        }
+        updated
+    }
+
+    fn depth(&self, index: usize) -> usize {
+        let mut depth = 0;
+        let mut ancestor = self.nodes[index].parent;
+        while let Some(node) = ancestor {
+            depth += 1;
+            ancestor = self.nodes[node].parent;
+        }
+        depth
+    }
+}
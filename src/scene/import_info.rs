@@ -0,0 +1,65 @@
+// How glTF COLOR_0 vertex colors are imported into ModelVertex::color.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VertexColorMode {
+    // Colors are used as stored, linear as the glTF specification requires.
+    #[default]
+    Linear,
+    // Colors were exported sRGB encoded (a common exporter mistake), decoded to linear.
+    Srgb,
+    // Every vertex gets SceneImportInfo::default_vertex_color.
+    Ignore,
+}
+
+// Options of load_scene_with_info, kept by the scene for Scene::reimport.
+#[derive(Clone, Copy, Debug)]
+pub struct SceneImportInfo {
+    pub vertex_colors: VertexColorMode,
+    // Color of the vertices of primitives without COLOR_0, multiplied in by the shaders like
+    // imported colors.
+    pub default_vertex_color: glam::Vec4,
+}
+
+impl Default for SceneImportInfo {
+    fn default() -> Self {
+        SceneImportInfo {
+            vertex_colors: VertexColorMode::default(),
+            default_vertex_color: glam::Vec4::ONE,
+        }
+    }
+}
+
+impl SceneImportInfo {
+    pub fn vertex_colors(mut self, mode: VertexColorMode) -> Self {
+        self.vertex_colors = mode;
+        self
+    }
+    pub fn ignore_vertex_colors(mut self) -> Self {
+        self.vertex_colors = VertexColorMode::Ignore;
+        self
+    }
+    pub fn default_vertex_color(mut self, color: glam::Vec4) -> Self {
+        self.default_vertex_color = color;
+        self
+    }
+
+    pub(crate) fn vertex_color(&self, color: Option<&[f32; 4]>) -> glam::Vec4 {
+        match (self.vertex_colors, color) {
+            (VertexColorMode::Linear, Some(color)) => glam::Vec4::from(*color),
+            (VertexColorMode::Srgb, Some(color)) => glam::vec4(
+                srgb_to_linear(color[0]),
+                srgb_to_linear(color[1]),
+                srgb_to_linear(color[2]),
+                color[3],
+            ),
+            _ => self.default_vertex_color,
+        }
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{Buffer, BufferInfo, Context, Result};
+use ash::vk;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,
+    // A range of 0 is infinite.
+    Point {
+        range: f32,
+    },
+    // Cone angles in radians.
+    Spot {
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+// Intensities follow KHR_lights_punctual: lux for directional lights, candela otherwise.
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: glam::Vec3,
+    pub direction: glam::Vec3,
+    pub color: glam::Vec3,
+    pub intensity: f32,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Light {
+            kind: LightKind::Directional,
+            position: glam::Vec3::ZERO,
+            direction: -glam::Vec3::Y,
+            color: glam::Vec3::ONE,
+            intensity: 1.0,
+        }
+    }
+}
+
+pub const LIGHT_DIRECTIONAL: u32 = 0;
+pub const LIGHT_POINT: u32 = 1;
+pub const LIGHT_SPOT: u32 = 2;
+
+// std430 element of the light buffer, see assets/glsl/lights.glsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuLight {
+    // Position and range.
+    pub position: glam::Vec4,
+    // Direction the light points to.
+    pub direction: glam::Vec4,
+    // Color premultiplied by the intensity.
+    pub color: glam::Vec4,
+    // LIGHT_DIRECTIONAL, LIGHT_POINT or LIGHT_SPOT.
+    pub kind: u32,
+    // Cosines of the spot cone angles.
+    pub cos_inner_angle: f32,
+    pub cos_outer_angle: f32,
+    pub padding: u32,
+}
+
+impl From<&Light> for GpuLight {
+    fn from(light: &Light) -> Self {
+        let (kind, range, inner_angle, outer_angle) = match light.kind {
+            LightKind::Directional => (LIGHT_DIRECTIONAL, 0.0, 0.0, 0.0),
+            LightKind::Point { range } => (LIGHT_POINT, range, 0.0, 0.0),
+            LightKind::Spot {
+                range,
+                inner_angle,
+                outer_angle,
+            } => (LIGHT_SPOT, range, inner_angle, outer_angle),
+        };
+        GpuLight {
+            position: light.position.extend(range),
+            direction: light.direction.extend(0.0),
+            color: (light.color * light.intensity).extend(1.0),
+            kind,
+            cos_inner_angle: inner_angle.cos(),
+            cos_outer_angle: outer_angle.cos(),
+            padding: 0,
+        }
+    }
+}
+
+// Size of the light count header, padded to the GpuLight alignment.
+const LIGHT_HEADER_SIZE: usize = 16;
+
+// Storage buffer of up to `capacity` lights, following the light count header. Lights
+// are rewritten with update, which must not happen while frames in flight read the buffer.
+pub struct LightBuffer {
+    buffer: Buffer,
+    capacity: usize,
+    count: usize,
+}
+
+impl LightBuffer {
+    pub fn new(context: Arc<Context>, capacity: usize) -> Result<Self> {
+        let buffer = Buffer::new(
+            context,
+            BufferInfo::default()
+                .name("LightBuffer")
+                .usage_storage()
+                .cpu_to_gpu(),
+            (LIGHT_HEADER_SIZE + capacity * std::mem::size_of::<GpuLight>()) as vk::DeviceSize,
+            capacity as u32,
+        )?;
+        let mut light_buffer = LightBuffer {
+            buffer,
+            capacity,
+            count: 0,
+        };
+        light_buffer.update(&[]);
+        Ok(light_buffer)
+    }
+
+    pub fn from_lights(context: Arc<Context>, lights: &[Light]) -> Result<Self> {
+        let mut light_buffer = Self::new(context, lights.len().max(1))?;
+        light_buffer.update(lights);
+        Ok(light_buffer)
+    }
+
+    pub fn update(&mut self, lights: &[Light]) {
+        assert!(lights.len() <= self.capacity, "LightBuffer is full.");
+        let header = [lights.len() as u32, 0, 0, 0];
+        let data = lights.iter().map(GpuLight::from).collect::<Vec<_>>();
+        unsafe {
+            let mapped = self.buffer.map();
+            std::ptr::copy_nonoverlapping(header.as_ptr() as *const u8, mapped, LIGHT_HEADER_SIZE);
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                mapped.add(LIGHT_HEADER_SIZE),
+                std::mem::size_of_val(data.as_slice()),
+            );
+        }
+        self.count = lights.len();
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn get_buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn get_descriptor_info(&self) -> vk::DescriptorBufferInfo {
+        self.buffer.get_descriptor_info()
+    }
+}
@@ -0,0 +1,146 @@
+use super::{MaterialInfo, Scene, NO_TEXTURE};
+use crate::{Buffer, BufferInfo, Result, StagingBelt};
+use ash::vk;
+use std::ops::Range;
+
+impl MaterialInfo {
+    // Untextured white diffuse material, e.g. a debug material for light studies.
+    pub fn white() -> Self {
+        MaterialInfo {
+            base_color: glam::Vec4::ONE,
+            metallic_factor: 0.0,
+            roughness_factor: 1.0,
+            base_color_texture: NO_TEXTURE,
+            metallic_roughness_texture: NO_TEXTURE,
+            ..Default::default()
+        }
+    }
+}
+
+// Material index of an overridden primitive section before its override, and its own slot.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MaterialOverride {
+    original: Option<usize>,
+    slot: usize,
+}
+
+// Lookdev and lighting debug helpers. Scene::materials keeps the authored materials (and the
+// overrides), the material buffer is updated in place with the debug material replacing all of
+// them while it is set.
+impl Scene {
+    // Gives the primitive section its own material slot, appended after the scene materials, so
+    // sections sharing its original material are unaffected. Overriding it again updates the
+    // slot in place. Returns the material index of the section.
+    pub fn override_material(
+        &mut self,
+        mesh: usize,
+        primitive: usize,
+        material: MaterialInfo,
+    ) -> Result<usize> {
+        let slot = match self.material_overrides.get(&(mesh, primitive)) {
+            Some(material_override) => {
+                self.materials[material_override.slot] = material;
+                material_override.slot
+            }
+            None => {
+                let section = &mut self.meshes[mesh].primitive_sections[primitive];
+                let slot = self.materials.len();
+                self.material_overrides.insert(
+                    (mesh, primitive),
+                    MaterialOverride {
+                        original: section.material_index,
+                        slot,
+                    },
+                );
+                section.material_index = Some(slot);
+                self.materials.push(material);
+                self.material_generation += 1;
+                slot
+            }
+        };
+        self.upload_materials(slot..slot + 1)?;
+        Ok(slot)
+    }
+
+    // Restores the original materials of the overridden primitive sections.
+    pub fn clear_material_overrides(&mut self) {
+        let mut first_slot = self.materials.len();
+        for ((mesh, primitive), material_override) in self.material_overrides.drain() {
+            self.meshes[mesh].primitive_sections[primitive].material_index =
+                material_override.original;
+            first_slot = first_slot.min(material_override.slot);
+        }
+        if first_slot < self.materials.len() {
+            // The buffer slots of the overrides are left unused.
+            self.materials.truncate(first_slot);
+            self.material_generation += 1;
+        }
+    }
+
+    // Replaces every material of the scene in the material buffer, e.g. MaterialInfo::white
+    // for light studies. None restores the scene materials.
+    pub fn set_debug_material(&mut self, material: Option<MaterialInfo>) -> Result<()> {
+        self.debug_material = material;
+        self.upload_materials(0..self.materials.len())
+    }
+
+    pub fn get_debug_material(&self) -> Option<&MaterialInfo> {
+        self.debug_material.as_ref()
+    }
+
+    // Incremented when primitive section material indices change or the material buffer is
+    // replaced: descriptors built from PrimitiveSection::get_material_descriptor (e.g. a
+    // SceneDescription) must then be rebuilt. DescriptorSetCache sets referencing a replaced
+    // buffer are evicted automatically.
+    pub fn get_material_generation(&self) -> u64 {
+        self.material_generation
+    }
+
+    // Waits for the device to be idle, frames in flight may read the materials.
+    fn upload_materials(&mut self, range: Range<usize>) -> Result<()> {
+        self.context.device_wait_idle()?;
+        let material_size = std::mem::size_of::<MaterialInfo>();
+        let mut staging = StagingBelt::new(self.context.clone(), 64 * 1024);
+        if self.materials.len() > self.material_buffer.get_element_count() as usize {
+            // Room for an override per primitive section, later overrides are updated in place.
+            let section_count: usize = self
+                .meshes
+                .iter()
+                .map(|mesh| mesh.primitive_sections.len())
+                .sum();
+            let capacity = self.materials.len() + section_count;
+            let material_buffer = Buffer::new(
+                self.context.clone(),
+                BufferInfo::default()
+                    .name("SceneMaterials")
+                    .usage_storage()
+                    .usage_transfer_dst()
+                    .gpu_only(),
+                (capacity * material_size) as vk::DeviceSize,
+                capacity as u32,
+            )?;
+            staging.upload(
+                &material_buffer,
+                0,
+                &self.gpu_materials(0..self.materials.len()),
+            )?;
+            self.material_buffer = material_buffer;
+            self.material_generation += 1;
+        } else {
+            staging.upload(
+                &self.material_buffer,
+                (range.start * material_size) as vk::DeviceSize,
+                &self.gpu_materials(range),
+            )?;
+        }
+        staging.finish();
+        Ok(())
+    }
+
+    fn gpu_materials(&self, range: Range<usize>) -> Vec<MaterialInfo> {
+        match self.debug_material {
+            Some(material) => vec![material; range.len()],
+            None => self.materials[range].to_vec(),
+        }
+    }
+}
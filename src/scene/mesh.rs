@@ -1,5 +1,5 @@
-use super::PrimitiveSection;
-use crate::{offset_of, Buffer, Context, Resource, Vertex};
+use super::{Aabb, BufferPart, PrimitiveSection, SkinVertex};
+use crate::{offset_of, Buffer, BufferInfo, Context, Resource, Result, StagingBelt, Vertex};
 use ash::{vk};
 use std::sync::Arc;
 
@@ -50,50 +50,256 @@ impl Vertex for ModelVertex {
     }
 }
 
+// Joint influences of a skinned mesh. The skin vertices are read from a storage buffer (at the
+// index of the mesh vertex) rather than a second vertex binding.
+pub struct MeshSkin {
+    // Index in Scene::skeletons.
+    pub skeleton: usize,
+    pub skin_buffer: Buffer,
+    pub skin_vertices: Vec<SkinVertex>,
+    // Vertices in the bind pose, the vertex buffer holding the last skinned upload.
+    pub rest_vertices: Vec<ModelVertex>,
+}
+
 pub struct Mesh {
     pub context: Arc<Context>,
     pub name: String,
-    pub vertex_buffer: Buffer,
-    pub index_buffer: Option<Buffer>,
-    pub index_storage: Option<Buffer>,
+    // Shared with the other meshes of a GeometryArena, the primitive sections addressing the
+    // part of the mesh.
+    pub vertex_buffer: Arc<Buffer>,
+    // Also bound as a storage buffer of u32 indices by the ray tracing shaders.
+    pub index_buffer: Option<Arc<Buffer>>,
+    // First vertex of the mesh in vertex_buffer.
+    pub first_vertex: usize,
     pub transform: glam::Mat4,
     pub primitive_sections: Vec<PrimitiveSection>,
+    // Object space bounds of the vertex positions. Those of the rest pose for skinned meshes.
+    pub bounds: Aabb,
+    pub skin: Option<MeshSkin>,
 }
 
 impl Mesh {
+    // Mesh of a single section of indexed triangles, without material. The buffers are uploaded
+    // synchronously, e.g. for the meshes of scene::ProceduralMesh.
+    pub fn from_data(
+        context: Arc<Context>,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+    ) -> Result<Self> {
+        let vertex_buffer = Buffer::from_data(
+            context.clone(),
+            BufferInfo::default()
+                .name(name)
+                .usage_vertex()
+                .usage_storage()
+                .gpu_only(),
+            vertices,
+        )?;
+        let index_buffer = Buffer::from_data(
+            context.clone(),
+            BufferInfo::default()
+                .name(name)
+                .usage_index()
+                .usage_storage()
+                .gpu_only(),
+            indices,
+        )?;
+        let mut bounds = Aabb::EMPTY;
+        for vertex in vertices {
+            bounds.grow(vertex.pos.truncate());
+        }
+        let section = PrimitiveSection {
+            index: 0,
+            vertices: BufferPart {
+                offset: 0,
+                element_count: vertices.len(),
+            },
+            indices: Some(BufferPart {
+                offset: 0,
+                element_count: indices.len(),
+            }),
+            material_index: None,
+        };
+        Ok(Mesh {
+            context,
+            name: name.to_owned(),
+            vertex_buffer: Arc::new(vertex_buffer),
+            index_buffer: Some(Arc::new(index_buffer)),
+            first_vertex: 0,
+            transform: glam::Mat4::IDENTITY,
+            primitive_sections: vec![section],
+            bounds,
+            skin: None,
+        })
+    }
+
+    // CPU skinned vertices, None for meshes without skin.
+    pub fn skin_vertices(&self, joint_matrices: &[glam::Mat4]) -> Option<Vec<ModelVertex>> {
+        self.skin.as_ref().map(|skin| {
+            skin.skin_vertices
+                .iter()
+                .zip(&skin.rest_vertices)
+                .map(|(skin_vertex, vertex)| skin_vertex.skin(vertex, joint_matrices))
+                .collect()
+        })
+    }
+
+    // Records the upload of the skinned vertices to the vertex buffer, e.g. before refitting the
+    // mesh BLAS with SceneDescription::blas_update. Returns false for meshes without skin.
+    pub fn upload_skinned_vertices(
+        &self,
+        staging: &mut StagingBelt,
+        joint_matrices: &[glam::Mat4],
+    ) -> Result<bool> {
+        match self.skin_vertices(joint_matrices) {
+            Some(vertices) => {
+                let offset = self.first_vertex * std::mem::size_of::<ModelVertex>();
+                staging.upload(&self.vertex_buffer, offset as vk::DeviceSize, &vertices)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     pub fn cmd_draw(&self, cmd: vk::CommandBuffer) {
+        for section in &self.primitive_sections {
+            self.cmd_draw_section(cmd, section);
+        }
+    }
+
+    // Draws one of the mesh primitive sections.
+    pub fn cmd_draw_section(&self, cmd: vk::CommandBuffer, section: &PrimitiveSection) {
         let device = self.context.device();
         unsafe {
+            device.cmd_bind_vertex_buffers(
+                cmd,
+                0,
+                &[self.vertex_buffer.handle()],
+                &[section.get_vertex_offset_size()],
+            );
             match &self.index_buffer {
                 Some(indices) => {
-                    for section in &self.primitive_sections {
-                        device.cmd_bind_vertex_buffers(
-                            cmd,
-                            0,
-                            &[self.vertex_buffer.handle()],
-                            &[section.get_vertex_offset_size()],
-                        );
-                        device.cmd_bind_index_buffer(
-                            cmd,
-                            indices.handle(),
-                            section.get_index_offset_size::<u32>(),
-                            vk::IndexType::UINT32,
-                        );
-                        device.cmd_draw_indexed(cmd, section.get_index_count(), 1, 0, 0, 1);
-                    }
+                    device.cmd_bind_index_buffer(
+                        cmd,
+                        indices.handle(),
+                        section.get_index_offset_size::<u32>(),
+                        vk::IndexType::UINT32,
+                    );
+                    device.cmd_draw_indexed(cmd, section.get_index_count(), 1, 0, 0, 1);
                 }
-                None => {
-                    for section in &self.primitive_sections {
-                        device.cmd_bind_vertex_buffers(
-                            cmd,
-                            0,
-                            &[self.vertex_buffer.handle()],
-                            &[section.get_vertex_offset_size()],
-                        );
-                        device.cmd_draw(cmd, section.get_vertex_count(), 1, 0, 1);
-                    }
+                None => device.cmd_draw(cmd, section.get_vertex_count(), 1, 0, 1),
+            }
+        }
+    }
+
+    // Draws a section with the vertex and index buffers already bound at offset zero, e.g. once
+    // for all the meshes of a scene with GeometryArena::cmd_bind.
+    pub fn cmd_draw_section_bound(&self, cmd: vk::CommandBuffer, section: &PrimitiveSection) {
+        let device = self.context.device();
+        unsafe {
+            match section.get_indices() {
+                Some(_) => {
+                    let draw = section.get_indexed_indirect_command();
+                    device.cmd_draw_indexed(
+                        cmd,
+                        draw.index_count,
+                        1,
+                        draw.first_index,
+                        draw.vertex_offset,
+                        0,
+                    );
                 }
+                None => device.cmd_draw(
+                    cmd,
+                    section.get_vertex_count(),
+                    1,
+                    section.get_vertex_offset(),
+                    0,
+                ),
             }
         }
     }
+
+    // Builds the indirect commands of all primitive sections: VkDrawIndexedIndirectCommand for
+    // indexed meshes, VkDrawIndirectCommand otherwise. Also usable as a storage buffer, e.g. for
+    // GPU culling.
+    pub fn create_indirect_buffer(&self) -> Result<Buffer> {
+        let info = BufferInfo::default()
+            .name("IndirectCommands")
+            .usage_indirect()
+            .usage_storage()
+            .gpu_only();
+        match &self.index_buffer {
+            Some(_) => {
+                let commands: Vec<vk::DrawIndexedIndirectCommand> = self
+                    .primitive_sections
+                    .iter()
+                    .map(|section| section.get_indexed_indirect_command())
+                    .collect();
+                Buffer::from_data(self.context.clone(), info, &commands)
+            }
+            None => {
+                let commands: Vec<vk::DrawIndirectCommand> = self
+                    .primitive_sections
+                    .iter()
+                    .map(|section| section.get_indirect_command())
+                    .collect();
+                Buffer::from_data(self.context.clone(), info, &commands)
+            }
+        }
+    }
+
+    // Same result as cmd_draw with a single multi-draw-indirect call.
+    pub fn cmd_draw_indirect(&self, cmd: vk::CommandBuffer, indirect_buffer: &Buffer) {
+        let device = self.context.device();
+        let draw_count = indirect_buffer.get_element_count();
+        unsafe {
+            device.cmd_bind_vertex_buffers(cmd, 0, &[self.vertex_buffer.handle()], &[0]);
+        }
+        match &self.index_buffer {
+            Some(indices) => {
+                unsafe {
+                    device.cmd_bind_index_buffer(cmd, indices.handle(), 0, vk::IndexType::UINT32);
+                }
+                self.context.cmd_draw_indexed_indirect(
+                    cmd,
+                    indirect_buffer.handle(),
+                    0,
+                    draw_count,
+                );
+            }
+            None => {
+                self.context
+                    .cmd_draw_indirect(cmd, indirect_buffer.handle(), 0, draw_count);
+            }
+        }
+    }
+
+    // Indexed draws whose count is read from `count_buffer` (a u32 at offset zero), e.g. after a
+    // culling pass compacted `indirect_buffer`.
+    pub fn cmd_draw_indirect_count(
+        &self,
+        cmd: vk::CommandBuffer,
+        indirect_buffer: &Buffer,
+        count_buffer: &Buffer,
+    ) {
+        let indices = self
+            .index_buffer
+            .as_ref()
+            .expect("Indirect count draws require an indexed mesh.");
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_vertex_buffers(cmd, 0, &[self.vertex_buffer.handle()], &[0]);
+            device.cmd_bind_index_buffer(cmd, indices.handle(), 0, vk::IndexType::UINT32);
+        }
+        self.context.cmd_draw_indexed_indirect_count(
+            cmd,
+            indirect_buffer.handle(),
+            0,
+            count_buffer.handle(),
+            0,
+            indirect_buffer.get_element_count(),
+        );
+    }
 }
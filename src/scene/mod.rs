@@ -1,31 +1,135 @@
+mod animation;
+pub use animation::*;
+
 mod camera;
 pub use camera::*;
 
+mod geometry_arena;
+pub use geometry_arena::*;
+
+mod gizmo;
+pub use gizmo::*;
+
+mod graph;
+pub use graph::*;
+
+mod debug_view;
+pub use debug_view::*;
+
 // Much of this was directly based on:
 // https://github.com/adrien-ben/gltf-viewer-rs/blob/master/model/src/mesh.rs
 
+mod import_info;
+pub use import_info::*;
+
+mod light;
+pub use light::*;
+
+mod material_override;
+use material_override::MaterialOverride;
+
 mod mesh;
 pub use mesh::*;
 
-use crate::{Buffer, BufferInfo, Context};
+mod object_table;
+pub use object_table::*;
+
+mod physics;
+pub use physics::*;
+
+mod picking;
+pub use picking::*;
+
+mod primitives;
+pub use primitives::*;
+
+use crate::{
+    Buffer, BufferInfo, Context, DeviceLimits, Error, ProgressToken, Result, SamplerInfo,
+    StagingBelt, Texture2d, TextureArena, MISSING_TEXTURE_COLORS, NO_TEXTURE,
+};
 use ash::vk;
 use gltf::{
     buffer::Buffer as GltfBuffer,
     mesh::{Reader, Semantic},
 };
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug)]
 pub struct MaterialInfo {
     pub base_color: glam::Vec4,
     pub emissive_factor: glam::Vec3,
     pub padding0: f32,
     pub metallic_factor: f32,
     pub roughness_factor: f32,
-    pub padding1: f32,
-    pub padding2: f32,
+    // TextureArena indices, NO_TEXTURE when absent or not loaded.
+    pub base_color_texture: u32,
+    pub metallic_roughness_texture: u32,
+}
+
+impl Default for MaterialInfo {
+    fn default() -> Self {
+        MaterialInfo {
+            base_color: glam::Vec4::ZERO,
+            emissive_factor: glam::Vec3::ZERO,
+            padding0: 0.0,
+            metallic_factor: 0.0,
+            roughness_factor: 0.0,
+            base_color_texture: NO_TEXTURE,
+            metallic_roughness_texture: NO_TEXTURE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    // Inverted bounds, grown by the first point.
+    pub const EMPTY: Aabb = Aabb {
+        min: glam::const_vec3!([f32::MAX, f32::MAX, f32::MAX]),
+        max: glam::const_vec3!([f32::MIN, f32::MIN, f32::MIN]),
+    };
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn grow(&mut self, point: glam::Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn center(&self) -> glam::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // Radius of the bounding sphere around center().
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).length() * 0.5
+    }
+
+    // Bounds of the transformed box corners.
+    pub fn transform(&self, transform: &glam::Mat4) -> Aabb {
+        let mut result = Aabb::EMPTY;
+        if self.is_empty() {
+            return result;
+        }
+        for i in 0..8 {
+            let corner = glam::vec3(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            result.grow(transform.transform_point3(corner));
+        }
+        result
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -82,6 +186,10 @@ impl PrimitiveSection {
         self.vertices.offset as u64 * size
     }
 
+    pub fn get_material_index(&self) -> Option<usize> {
+        self.material_index
+    }
+
     pub fn get_indices(&self) -> &Option<BufferPart> {
         &self.indices
     }
@@ -94,76 +202,566 @@ impl PrimitiveSection {
         let size = std::mem::size_of::<T>() as u64;
         self.indices.unwrap().offset as u64 * size
     }
+
+    // Moves the section to the part of a GeometryArena holding its mesh.
+    fn offset_by(&mut self, base: GeometryBase) {
+        self.vertices.offset += base.vertex;
+        if let Some(indices) = &mut self.indices {
+            indices.offset += base.index;
+        }
+    }
+
+    // Draw of this section with the mesh vertex and index buffers bound at offset zero.
+    pub fn get_indexed_indirect_command(&self) -> vk::DrawIndexedIndirectCommand {
+        vk::DrawIndexedIndirectCommand {
+            index_count: self.get_index_count(),
+            instance_count: 1,
+            first_index: self.indices.unwrap().offset as u32,
+            vertex_offset: self.vertices.offset as i32,
+            first_instance: 0,
+        }
+    }
+
+    pub fn get_indirect_command(&self) -> vk::DrawIndirectCommand {
+        vk::DrawIndirectCommand {
+            vertex_count: self.get_vertex_count(),
+            instance_count: 1,
+            first_vertex: self.get_vertex_offset(),
+            first_instance: 0,
+        }
+    }
 }
 
 pub struct Scene {
     pub meshes: Vec<Mesh>,
+    // Shared vertex and index buffers of the meshes, None without meshes.
+    pub geometry: Option<GeometryArena>,
     pub materials: Vec<MaterialInfo>,
     pub material_buffer: Buffer,
+    // The selected camera, initially the first of `cameras`.
     pub camera: Option<Camera>,
+    // Perspective cameras, in glTF camera order.
+    pub cameras: Vec<SceneCamera>,
+    // KHR_lights_punctual lights, placed by their node (see light_nodes).
+    pub lights: Vec<Light>,
+    pub skeletons: Vec<Skeleton>,
+    pub animations: Vec<AnimationClip>,
+    // Nodes instancing the meshes, see Scene::update_transforms.
+    pub graph: SceneGraph,
+    // TextureArena indices of the scene images, see load_scene_with_textures.
+    pub textures: Vec<u32>,
+    context: Arc<Context>,
+    filepath: PathBuf,
+    import_info: SceneImportInfo,
+    // By (mesh, primitive section) index, see Scene::override_material.
+    material_overrides: HashMap<(usize, usize), MaterialOverride>,
+    debug_material: Option<MaterialInfo>,
+    material_generation: u64,
+    selected_camera: Option<usize>,
+    // Graph node of each light.
+    light_nodes: Vec<usize>,
 }
 
-fn find_mesh(node: &gltf::Node, transforms: &mut Vec<glam::Mat4>, mesh_index: usize) -> bool {
-    transforms.push(glam::Mat4::from_cols_array_2d(&node.transform().matrix()));
-    let found = match node.mesh() {
-        Some(node_mesh) => node_mesh.index() == mesh_index,
-        None => false,
-    };
-    if found {
-        return true;
+impl Scene {
+    // One indirect command buffer per mesh, in mesh order, for Mesh::cmd_draw_indirect.
+    pub fn create_indirect_buffers(&self) -> Result<Vec<Buffer>> {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.create_indirect_buffer())
+            .collect()
+    }
+
+    pub fn get_filepath(&self) -> &Path {
+        &self.filepath
+    }
+
+    pub fn get_import_info(&self) -> &SceneImportInfo {
+        &self.import_info
+    }
+
+    // Reads the file again with other import options and replaces the meshes, keeping the
+    // materials and textures. Material overrides are cleared. Waits for the device to be idle,
+    // frames in flight may use the meshes.
+    pub fn reimport(&mut self, info: SceneImportInfo) -> Result<()> {
+        let progress = ProgressToken::new();
+        let data = parse_gltf(&self.filepath, &info, &progress)?;
+        self.context.device_wait_idle()?;
+        self.clear_material_overrides();
+        let mut staging = StagingBelt::new(self.context.clone(), 16 * 1024 * 1024);
+        let (meshes, geometry) =
+            upload_meshes(&self.context, &mut staging, data.meshes, &progress)?;
+        staging.finish();
+        self.meshes = meshes;
+        self.geometry = geometry;
+        self.skeletons = data.skeletons;
+        self.animations = data.animations;
+        self.graph = data.graph;
+        self.cameras = data.cameras;
+        self.lights = data.lights;
+        self.light_nodes = data.light_nodes;
+        self.selected_camera = self
+            .selected_camera
+            .filter(|index| *index < self.cameras.len());
+        self.import_info = info;
+        self.material_generation += 1;
+        Ok(())
+    }
+
+    // Applies the modified node transforms (SceneGraph::set_local_transform, apply_animation) to
+    // the meshes, each placed by its first node. Returns the moved meshes, e.g. for
+    // SceneDescription::update_mesh_transforms. Skinned meshes are placed by their joints.
+    pub fn update_transforms(&mut self) -> Vec<usize> {
+        let mut moved = Vec::new();
+        let updated = self.graph.update_transforms();
+        for (light, node) in self.light_nodes.iter().enumerate() {
+            if updated.contains(node) {
+                place_light(
+                    &mut self.lights[light],
+                    &self.graph.node(*node).global_transform(),
+                );
+            }
+        }
+        for node in updated {
+            let mesh = match self.graph.node(node).mesh {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            if self.meshes[mesh].skin.is_none() && self.graph.mesh_node(mesh) == Some(node) {
+                self.meshes[mesh].transform = self.graph.node(node).global_transform();
+                moved.push(mesh);
+            }
+        }
+        moved
+    }
+
+    // Makes one of `cameras` the scene camera, replacing any change made to the previous one.
+    pub fn select_camera(&mut self, index: usize) {
+        self.camera = Some(self.cameras[index].camera);
+        self.selected_camera = Some(index);
+    }
+
+    pub fn selected_camera(&self) -> Option<usize> {
+        self.selected_camera
+    }
+
+    pub fn find_camera(&self, name: &str) -> Option<usize> {
+        self.cameras.iter().position(|camera| camera.name == name)
     }
-    for ref child in node.children() {
-        if find_mesh(child, transforms, mesh_index) {
-            return true;
+
+    // Storage buffer of the lights, see assets/glsl/lights.glsl. Update it with
+    // LightBuffer::update after moving lights.
+    pub fn create_light_buffer(&self) -> Result<LightBuffer> {
+        LightBuffer::from_lights(self.context.clone(), &self.lights)
+    }
+
+    // Sets the transforms of the nodes animated by the clip, applied by update_transforms.
+    pub fn apply_animation(&mut self, clip: usize, time: f32) {
+        for channel in &self.animations[clip].channels {
+            let mut transform = *self.graph.node(channel.node).local_transform();
+            channel.apply(time, &mut transform);
+            self.graph.set_local_transform(channel.node, transform);
         }
     }
-    transforms.pop();
-    return false;
 }
 
-fn calc_mesh_global_transform(gltf: &gltf::Document, mesh_index: usize) -> glam::Mat4 {
-    let mut global_transform = glam::Mat4::IDENTITY;
-    let mut transforms = Vec::<glam::Mat4>::new();
+// Skin of the first node instancing the mesh.
+fn find_mesh_skin(gltf: &gltf::Document, mesh_index: usize) -> Option<usize> {
+    gltf.nodes()
+        .find(|node| node.mesh().map(|mesh| mesh.index()) == Some(mesh_index))
+        .and_then(|node| node.skin())
+        .map(|skin| skin.index())
+}
+
+fn node_transform(node: &gltf::Node) -> NodeTransform {
+    let (translation, rotation, scale) = node.transform().decomposed();
+    NodeTransform {
+        translation: glam::Vec3::from(translation),
+        rotation: glam::Quat::from_array(rotation),
+        scale: glam::Vec3::from(scale),
+    }
+}
+
+fn parse_skeleton(
+    gltf: &gltf::Document,
+    skin: &gltf::Skin,
+    buffers: &[gltf::buffer::Data],
+) -> Skeleton {
+    let mut node_parents = vec![None; gltf.nodes().len()];
     for node in gltf.nodes() {
-        if find_mesh(&node, &mut transforms, mesh_index) {
-            transforms.iter().for_each(|t| {
-                global_transform = global_transform * *t;
-            });
-            break;
+        for child in node.children() {
+            node_parents[child.index()] = Some(node.index());
+        }
+    }
+    let joint_nodes = skin.joints().map(|node| node.index()).collect::<Vec<_>>();
+    // Each joint with its ancestors not already added, root first.
+    let mut skeleton_nodes = Vec::<usize>::new();
+    for joint in &joint_nodes {
+        let mut chain = Vec::new();
+        let mut node = Some(*joint);
+        while let Some(index) = node {
+            if skeleton_nodes.contains(&index) {
+                break;
+            }
+            chain.push(index);
+            node = node_parents[index];
         }
+        skeleton_nodes.extend(chain.into_iter().rev());
     }
-    global_transform
+    let nodes = skeleton_nodes
+        .iter()
+        .map(|index| SkeletonNode {
+            node: *index,
+            parent: node_parents[*index]
+                .map(|parent| skeleton_nodes.iter().position(|n| *n == parent).unwrap()),
+            rest: node_transform(&gltf.nodes().nth(*index).unwrap()),
+        })
+        .collect();
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+    let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+        Some(matrices) => matrices
+            .map(|matrix| glam::Mat4::from_cols_array_2d(&matrix))
+            .collect(),
+        None => vec![glam::Mat4::IDENTITY; joint_nodes.len()],
+    };
+    Skeleton::new(
+        skin.name().unwrap_or_default().to_owned(),
+        nodes,
+        &joint_nodes,
+        inverse_bind_matrices,
+    )
 }
 
-pub fn load_scene(context: Arc<Context>, filepath: &PathBuf) -> Scene {
-    let mut meshes = Vec::<Mesh>::new();
-    let (gltf, buffers, _) = gltf::import(filepath).unwrap();
+fn parse_animation(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> AnimationClip {
+    use gltf::animation::{util::ReadOutputs, Interpolation};
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let times = match reader.read_inputs() {
+            Some(inputs) => inputs.collect::<Vec<_>>(),
+            None => continue,
+        };
+        let (property, values) = match reader.read_outputs() {
+            Some(ReadOutputs::Translations(translations)) => (
+                AnimationProperty::Translation,
+                translations
+                    .map(|t| glam::Vec3::from(t).extend(0.0))
+                    .collect(),
+            ),
+            Some(ReadOutputs::Rotations(rotations)) => (
+                AnimationProperty::Rotation,
+                rotations.into_f32().map(glam::Vec4::from).collect(),
+            ),
+            Some(ReadOutputs::Scales(scales)) => (
+                AnimationProperty::Scale,
+                scales.map(|s| glam::Vec3::from(s).extend(0.0)).collect(),
+            ),
+            _ => continue,
+        };
+        let interpolation = match channel.sampler().interpolation() {
+            Interpolation::Step => AnimationInterpolation::Step,
+            Interpolation::Linear => AnimationInterpolation::Linear,
+            Interpolation::CubicSpline => AnimationInterpolation::CubicSpline,
+        };
+        if times.is_empty() {
+            continue;
+        }
+        channels.push(AnimationChannel {
+            node: channel.target().node().index(),
+            property,
+            interpolation,
+            times,
+            values,
+        });
+    }
+    AnimationClip {
+        name: animation.name().unwrap_or_default().to_owned(),
+        duration: channels
+            .iter()
+            .map(|channel| *channel.times.last().unwrap())
+            .fold(0.0, f32::max),
+        channels,
+    }
+}
+
+// Lights point down their node's -Z axis.
+fn place_light(light: &mut Light, transform: &glam::Mat4) {
+    light.position = transform.transform_point3(glam::Vec3::ZERO);
+    light.direction = transform
+        .transform_vector3(-glam::Vec3::Z)
+        .normalize_or_zero();
+}
+
+fn parse_light(light: &gltf::khr_lights_punctual::Light, transform: &glam::Mat4) -> Light {
+    use gltf::khr_lights_punctual::Kind;
+    let range = light.range().unwrap_or(0.0);
+    let mut parsed = Light {
+        kind: match light.kind() {
+            Kind::Directional => LightKind::Directional,
+            Kind::Point => LightKind::Point { range },
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                range,
+                inner_angle: inner_cone_angle,
+                outer_angle: outer_cone_angle,
+            },
+        },
+        color: glam::Vec3::from(light.color()),
+        intensity: light.intensity(),
+        ..Default::default()
+    };
+    place_light(&mut parsed, transform);
+    parsed
+}
+
+// Expands glTF image data to RGBA8. 16 bit channels are truncated, single channel images are
+// replicated to gray.
+fn gltf_image_to_rgba8(image: &gltf::image::Data) -> Vec<u8> {
+    use gltf::image::Format;
+    let (channels, bytes, bgr) = match image.format {
+        Format::R8 => (1, 1, false),
+        Format::R8G8 => (2, 1, false),
+        Format::R8G8B8 => (3, 1, false),
+        Format::R8G8B8A8 => (4, 1, false),
+        Format::B8G8R8 => (3, 1, true),
+        Format::B8G8R8A8 => (4, 1, true),
+        Format::R16 => (1, 2, false),
+        Format::R16G16 => (2, 2, false),
+        Format::R16G16B16 => (3, 2, false),
+        Format::R16G16B16A16 => (4, 2, false),
+    };
+    image
+        .pixels
+        .chunks_exact(channels * bytes)
+        .flat_map(|pixel| {
+            // Most significant byte of little endian channels.
+            let mut rgba = [0, 0, 0, 255];
+            for (i, value) in rgba.iter_mut().enumerate().take(channels) {
+                *value = pixel[i * bytes + bytes - 1];
+            }
+            if channels == 1 {
+                rgba[1] = rgba[0];
+                rgba[2] = rgba[0];
+            }
+            if bgr {
+                rgba.swap(0, 2);
+            }
+            rgba
+        })
+        .collect()
+}
+
+pub fn load_scene(context: Arc<Context>, filepath: &Path) -> Result<Scene> {
+    load_scene_with_info(context, filepath, SceneImportInfo::default(), None)
+}
+
+// Also loads the images referenced by the materials into `arena`, the material texture indices
+// are arena indices.
+pub fn load_scene_with_textures(
+    context: Arc<Context>,
+    filepath: &Path,
+    arena: &mut TextureArena,
+) -> Result<Scene> {
+    load_scene_with_info(context, filepath, SceneImportInfo::default(), Some(arena))
+}
+
+// Loads the textures into `arena` when given, see load_scene_with_textures.
+pub fn load_scene_with_info(
+    context: Arc<Context>,
+    filepath: &Path,
+    info: SceneImportInfo,
+    arena: Option<&mut TextureArena>,
+) -> Result<Scene> {
+    let data = parse_gltf(filepath, &info, &ProgressToken::new())?;
+    upload_gltf(context, data, arena, &ProgressToken::new())
+}
+
+// Reads and decodes the file on a worker thread, see SceneLoad. `progress` reports the loading
+// stages and cancels the load.
+pub fn load_scene_async(filepath: &Path, progress: ProgressToken) -> SceneLoad {
+    load_scene_async_with_info(filepath, SceneImportInfo::default(), progress)
+}
+
+pub fn load_scene_async_with_info(
+    filepath: &Path,
+    info: SceneImportInfo,
+    progress: ProgressToken,
+) -> SceneLoad {
+    let filepath = filepath.to_path_buf();
+    let worker_progress = progress.clone();
+    let worker = std::thread::spawn(move || parse_gltf(&filepath, &info, &worker_progress));
+    SceneLoad {
+        worker: Some(worker),
+        progress,
+    }
+}
+
+// A scene load in progress. The GPU resources are created on the thread calling finish, which
+// blocks until the worker is done: poll is_ready first to keep rendering meanwhile.
+pub struct SceneLoad {
+    worker: Option<std::thread::JoinHandle<Result<GltfData>>>,
+    progress: ProgressToken,
+}
+
+impl SceneLoad {
+    pub fn progress(&self) -> &ProgressToken {
+        &self.progress
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.worker
+            .as_ref()
+            .is_some_and(|worker| worker.is_finished())
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    pub fn finish(self, context: Arc<Context>) -> Result<Scene> {
+        self.finish_impl(context, None)
+    }
+
+    pub fn finish_with_textures(
+        self,
+        context: Arc<Context>,
+        arena: &mut TextureArena,
+    ) -> Result<Scene> {
+        self.finish_impl(context, Some(arena))
+    }
+
+    fn finish_impl(
+        mut self,
+        context: Arc<Context>,
+        arena: Option<&mut TextureArena>,
+    ) -> Result<Scene> {
+        let data = self
+            .worker
+            .take()
+            .unwrap()
+            .join()
+            .expect("Scene loading thread panicked.")?;
+        upload_gltf(context, data, arena, &self.progress)
+    }
+}
+
+impl Drop for SceneLoad {
+    // Dropping an unfinished load cancels it.
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.progress.cancel();
+            let _ = worker.join();
+        }
+    }
+}
+
+// Size of the checkerboard replacing the glTF images which fail to load.
+const MISSING_TEXTURE_SIZE: u32 = 64;
+
+// CPU side glTF content, sent from the loading thread.
+struct GltfData {
+    filepath: PathBuf,
+    import_info: SceneImportInfo,
+    // None for the images which failed to load.
+    images: Vec<Option<image::RgbaImage>>,
+    // With the glTF image indices of the base color and metallic roughness textures.
+    materials: Vec<(MaterialInfo, Option<usize>, Option<usize>)>,
+    meshes: Vec<MeshData>,
+    cameras: Vec<SceneCamera>,
+    lights: Vec<Light>,
+    light_nodes: Vec<usize>,
+    skeletons: Vec<Skeleton>,
+    animations: Vec<AnimationClip>,
+    graph: SceneGraph,
+}
+
+struct MeshData {
+    name: String,
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    transform: glam::Mat4,
+    primitive_sections: Vec<PrimitiveSection>,
+    bounds: Aabb,
+    // Skin index, with a skin vertex per vertex.
+    skin: Option<(usize, Vec<SkinVertex>)>,
+}
+
+fn parse_gltf(
+    filepath: &Path,
+    info: &SceneImportInfo,
+    progress: &ProgressToken,
+) -> Result<GltfData> {
+    progress.report("Reading glTF", 0.0);
+    let (gltf, buffers, images) = match gltf::import(filepath) {
+        Ok((gltf, buffers, images)) => {
+            let images = images
+                .iter()
+                .map(|image| {
+                    image::RgbaImage::from_raw(
+                        image.width,
+                        image.height,
+                        gltf_image_to_rgba8(image),
+                    )
+                })
+                .collect();
+            (gltf, buffers, images)
+        }
+        // A missing or undecodable image fails the whole import, retry without the images.
+        Err(e) => import_gltf_skipping_images(filepath).map_err(|_| match e {
+            gltf::Error::Io(source) => Error::io(filepath, source),
+            e => Error::Gltf(e),
+        })?,
+    };
+    progress.check()?;
 
     //println!("{:#?}", gltf);
 
-    let mut materials = Vec::<MaterialInfo>::new();
+    let mut materials = Vec::new();
     for mat in gltf.materials() {
-        materials.push(MaterialInfo {
-            base_color: glam::Vec4::from_slice(
-                &mat.pbr_metallic_roughness().base_color_factor(),
-            ),
+        let pbr = mat.pbr_metallic_roughness();
+        let material = MaterialInfo {
+            base_color: glam::Vec4::from_slice(&pbr.base_color_factor()),
             //double_sided: mat.double_sided(),
-            metallic_factor: mat.pbr_metallic_roughness().metallic_factor(),
-            roughness_factor: mat.pbr_metallic_roughness().roughness_factor(),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
             emissive_factor: glam::Vec3::from_slice(&mat.emissive_factor()),
             ..Default::default()
-        });
+        };
+        materials.push((
+            material,
+            pbr.base_color_texture()
+                .map(|info| info.texture().source().index()),
+            pbr.metallic_roughness_texture()
+                .map(|info| info.texture().source().index()),
+        ));
     }
-    let material_buffer = Buffer::from_data(
-        context.clone(),
-        BufferInfo::default().usage_storage().gpu_only(),
-        &materials,
+
+    let graph = SceneGraph::new(
+        gltf.nodes()
+            .map(|node| {
+                let mut scene_node = SceneNode::new(
+                    node.name().unwrap_or_default(),
+                    node_transform(&node),
+                    node.mesh().map(|mesh| mesh.index()),
+                );
+                scene_node.children = node.children().map(|child| child.index()).collect();
+                scene_node
+            })
+            .collect(),
     );
 
+    let mut meshes = Vec::new();
+    let mesh_count = gltf.meshes().len();
     for mesh in gltf.meshes() {
+        progress.report_steps("Reading meshes", mesh.index(), mesh_count);
+        progress.check()?;
         let mut mesh_indices = Vec::<u32>::new();
         let mut mesh_vertices = Vec::<ModelVertex>::new();
         let mut primitive_sections = Vec::<PrimitiveSection>::new();
+        let mut bounds = Aabb::EMPTY;
+        let skin_index = find_mesh_skin(&gltf, mesh.index());
+        let mut skin_vertices = Vec::<SkinVertex>::new();
 
         // println!("Mesh #{}", mesh.index());
 
@@ -178,18 +776,29 @@ pub fn load_scene(context: Arc<Context>, filepath: &PathBuf) -> Scene {
                 let normals = read_normals(&reader);
                 let tex_coords_0 = read_tex_coords(&reader, 0);
                 let colors = read_colors(&reader);
+                let (joints, weights) = match skin_index {
+                    Some(_) => (read_joints(&reader), read_weights(&reader)),
+                    None => (vec![], vec![]),
+                };
 
                 positions.iter().enumerate().for_each(|(index, position)| {
                     let pos = *position;
+                    bounds.grow(glam::Vec3::from(pos));
                     let norm = *normals.get(index).unwrap_or(&[0.0, 1.0, 0.0]);
                     let uv = *tex_coords_0.get(index).unwrap_or(&[0.0, 0.0]);
-                    let col = *colors.get(index).unwrap_or(&[1.0, 1.0, 1.0, 1.0]);
                     mesh_vertices.push(ModelVertex {
                         pos: glam::vec4(pos[0], pos[1], pos[2], 1.0),
                         normal: glam::vec4(norm[0], norm[1], norm[2], 1.0),
-                        color: glam::vec4(col[0], col[1], col[2], col[3]),
+                        color: info.vertex_color(colors.get(index)),
                         uv: glam::vec4(uv[0], uv[1], 0.0, 0.0),
                     });
+                    if skin_index.is_some() {
+                        let joints = joints.get(index).unwrap_or(&[0; 4]);
+                        skin_vertices.push(SkinVertex {
+                            joints: joints.map(u32::from),
+                            weights: glam::Vec4::from(*weights.get(index).unwrap_or(&[0.0; 4])),
+                        });
+                    }
                 });
             };
 
@@ -215,50 +824,26 @@ pub fn load_scene(context: Arc<Context>, filepath: &PathBuf) -> Scene {
             }
         }
 
-        let mut index_buffer = None;
-        let mut index_storage = None;
-
-        if !mesh_indices.is_empty() {
-            index_buffer = Some(Buffer::from_data(
-                context.clone(),
-                BufferInfo::default().usage_index().gpu_only(),
-                &mesh_indices,
-            ));
-
-            let storage_indices: Vec<u64> = mesh_indices.iter().map(|i| *i as u64).collect();
-            index_storage = Some(Buffer::from_data(
-                context.clone(),
-                BufferInfo::default().usage_storage().gpu_only(),
-                &storage_indices,
-            ));
-        }
-        let vertex_buffer = Buffer::from_data(
-            context.clone(),
-            BufferInfo::default()
-                .usage_vertex()
-                .usage_storage()
-                .gpu_only(),
-            &mesh_vertices,
-        );
-
-        let global_transform = calc_mesh_global_transform(&gltf, mesh.index());
-
         let name = match mesh.name() {
             Some(name) => name.to_owned(),
             None => String::new(),
         };
-        meshes.push(Mesh {
-            context: context.clone(),
+        meshes.push(MeshData {
             name,
-            index_buffer,
-            index_storage,
-            vertex_buffer,
-            transform: global_transform,
+            vertices: mesh_vertices,
+            indices: mesh_indices,
+            // The transform of skinned mesh nodes is ignored, the joints place the vertices.
+            transform: match (skin_index, graph.mesh_node(mesh.index())) {
+                (None, Some(node)) => graph.node(node).global_transform(),
+                _ => glam::Mat4::IDENTITY,
+            },
             primitive_sections,
+            bounds,
+            skin: skin_index.map(|index| (index, skin_vertices)),
         });
     }
 
-    let mut camera = None;
+    let mut cameras = Vec::new();
     for gltf_camera in gltf.cameras() {
         match gltf_camera.projection() {
             gltf::camera::Projection::Orthographic(_) => {}
@@ -269,28 +854,292 @@ pub fn load_scene(context: Arc<Context>, filepath: &PathBuf) -> Scene {
                         None => false,
                     };
                     if found {
-                        let view_matrix =
-                            glam::Mat4::from_cols_array_2d(&node.transform().matrix());
-                        camera = Some(Camera::from_view(
-                            view_matrix,
-                            persp.yfov(),
-                            persp.znear(),
-                            persp.zfar().unwrap_or(100.0),
-                        ));
+                        // The node places the camera, its inverse is the view matrix.
+                        let view_matrix = graph.node(node.index()).global_transform().inverse();
+                        cameras.push(SceneCamera {
+                            name: gltf_camera.name().unwrap_or_default().to_owned(),
+                            node: node.index(),
+                            camera: Camera::from_view(
+                                view_matrix,
+                                persp.yfov().to_degrees(),
+                                persp.znear(),
+                                persp.zfar().unwrap_or(100.0),
+                            ),
+                        });
                         break;
                     }
                 }
             }
         }
-        //Support for the first (default) camera only
-        break;
     }
 
-    Scene {
+    let mut lights = Vec::new();
+    let mut light_nodes = Vec::new();
+    for node in gltf.nodes() {
+        if let Some(gltf_light) = node.light() {
+            lights.push(parse_light(
+                &gltf_light,
+                &graph.node(node.index()).global_transform(),
+            ));
+            light_nodes.push(node.index());
+        }
+    }
+
+    let skeletons = gltf
+        .skins()
+        .map(|skin| parse_skeleton(&gltf, &skin, &buffers))
+        .collect();
+    let animations = gltf
+        .animations()
+        .map(|animation| parse_animation(&animation, &buffers))
+        .collect();
+
+    Ok(GltfData {
+        filepath: filepath.to_path_buf(),
+        import_info: *info,
+        images,
+        materials,
+        meshes,
+        cameras,
+        lights,
+        light_nodes,
+        skeletons,
+        animations,
+        graph,
+    })
+}
+
+// Imports the document and buffers like gltf::import, then decodes the images one by one: those
+// failing to load are None, and replaced by a checkerboard in upload_gltf. Buffers embedded as
+// data URIs aren't supported.
+#[allow(clippy::type_complexity)]
+fn import_gltf_skipping_images(
+    filepath: &Path,
+) -> gltf::Result<(
+    gltf::Document,
+    Vec<gltf::buffer::Data>,
+    Vec<Option<image::RgbaImage>>,
+)> {
+    let base = filepath.parent().unwrap_or_else(|| Path::new("."));
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::open(filepath)?;
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let mut data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().ok_or(gltf::Error::MissingBlob)?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                return Err(gltf::Error::UnsupportedScheme)
+            }
+            gltf::buffer::Source::Uri(uri) => std::fs::read(base.join(uri))?,
+        };
+        if data.len() < buffer.length() {
+            return Err(gltf::Error::BufferLength {
+                buffer: buffer.index(),
+                expected: buffer.length(),
+                actual: data.len(),
+            });
+        }
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        buffers.push(gltf::buffer::Data(data));
+    }
+    let images = document
+        .images()
+        .map(|image| {
+            let decoded = match image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    let begin = view.offset();
+                    let end = begin + view.length();
+                    image::load_from_memory(&buffers[view.buffer().index()][begin..end])
+                }
+                gltf::image::Source::Uri { uri, .. } => image::open(base.join(uri)),
+            };
+            match decoded {
+                Ok(decoded) => Some(decoded.to_rgba8()),
+                Err(e) => {
+                    println!(
+                        "Failed to load image {} of {}: {}",
+                        image.index(),
+                        filepath.display(),
+                        e
+                    );
+                    None
+                }
+            }
+        })
+        .collect();
+    Ok((document, buffers, images))
+}
+
+fn upload_gltf(
+    context: Arc<Context>,
+    data: GltfData,
+    mut arena: Option<&mut TextureArena>,
+    progress: &ProgressToken,
+) -> Result<Scene> {
+    // Arena index of each loaded glTF image.
+    let mut image_textures = HashMap::<usize, u32>::new();
+    let mut load_texture = |image_index: Option<usize>| -> Result<u32> {
+        let (arena, image_index) = match (arena.as_mut(), image_index) {
+            (Some(arena), Some(image_index)) => (arena, image_index),
+            _ => return Ok(NO_TEXTURE),
+        };
+        if let Some(index) = image_textures.get(&image_index) {
+            return Ok(*index);
+        }
+        progress.report_steps("Uploading textures", image_textures.len(), data.images.len());
+        progress.check()?;
+        let texture = match &data.images[image_index] {
+            Some(image) => {
+                let name = format!("{} image {}", data.filepath.display(), image_index);
+                Texture2d::from_rgba8(
+                    context.clone(),
+                    &name,
+                    image.dimensions(),
+                    image.as_raw(),
+                    SamplerInfo::default(),
+                )?
+            }
+            None => Texture2d::checkerboard(
+                context.clone(),
+                MISSING_TEXTURE_SIZE,
+                MISSING_TEXTURE_COLORS,
+            )?,
+        };
+        let index = arena.insert(texture);
+        image_textures.insert(image_index, index);
+        Ok(index)
+    };
+
+    let mut materials = Vec::<MaterialInfo>::new();
+    for (material, base_color_image, metallic_roughness_image) in &data.materials {
+        materials.push(MaterialInfo {
+            base_color_texture: load_texture(*base_color_image)?,
+            metallic_roughness_texture: load_texture(*metallic_roughness_image)?,
+            ..*material
+        });
+    }
+    let mut textures: Vec<_> = image_textures.into_values().collect();
+    textures.sort_unstable();
+    // All the scene buffers are uploaded with a single submit.
+    let mut staging = StagingBelt::new(context.clone(), 16 * 1024 * 1024);
+    let material_buffer =
+        staging.create_buffer(BufferInfo::default().usage_storage(), &materials)?;
+    let (meshes, geometry) = upload_meshes(&context, &mut staging, data.meshes, progress)?;
+    staging.finish();
+    progress.report("Done", 1.0);
+
+    let selected_camera = data.cameras.first().map(|_| 0);
+    Ok(Scene {
         meshes,
+        geometry,
         materials,
         material_buffer,
-        camera,
+        camera: data.cameras.first().map(|camera| camera.camera),
+        cameras: data.cameras,
+        lights: data.lights,
+        skeletons: data.skeletons,
+        animations: data.animations,
+        graph: data.graph,
+        textures,
+        context,
+        filepath: data.filepath,
+        import_info: data.import_info,
+        material_overrides: HashMap::new(),
+        debug_material: None,
+        material_generation: 0,
+        selected_camera,
+        light_nodes: data.light_nodes,
+    })
+}
+
+// Records the mesh buffer uploads, valid once `staging` is finished. The vertices and indices of
+// all the meshes are packed in a GeometryArena.
+fn upload_meshes(
+    context: &Arc<Context>,
+    staging: &mut StagingBelt,
+    mut mesh_data: Vec<MeshData>,
+    progress: &ProgressToken,
+) -> Result<(Vec<Mesh>, Option<GeometryArena>)> {
+    progress.report("Uploading meshes", 0.0);
+    progress.check()?;
+    for mesh in &mut mesh_data {
+        align_sections(mesh, context.limits());
+    }
+    let parts = mesh_data
+        .iter()
+        .map(|mesh| (mesh.vertices.as_slice(), mesh.indices.as_slice()))
+        .collect::<Vec<_>>();
+    if parts.iter().all(|(vertices, _)| vertices.is_empty()) {
+        return Ok((Vec::new(), None));
+    }
+    let (arena, bases) = GeometryArena::upload(context.clone(), staging, &parts)?;
+
+    let mut meshes = Vec::<Mesh>::new();
+    let mesh_count = mesh_data.len();
+    for (mesh, base) in mesh_data.into_iter().zip(bases) {
+        progress.report_steps("Uploading meshes", meshes.len(), mesh_count);
+        progress.check()?;
+        let skin = match mesh.skin {
+            Some((skeleton, skin_vertices)) => Some(MeshSkin {
+                skeleton,
+                skin_buffer: staging
+                    .create_buffer(BufferInfo::default().usage_storage(), &skin_vertices)?,
+                skin_vertices,
+                rest_vertices: mesh.vertices,
+            }),
+            None => None,
+        };
+        let mut primitive_sections = mesh.primitive_sections;
+        for section in &mut primitive_sections {
+            section.offset_by(base);
+        }
+        let indexed = !mesh.indices.is_empty();
+
+        meshes.push(Mesh {
+            context: context.clone(),
+            name: mesh.name,
+            index_buffer: arena.index_buffer.clone().filter(|_| indexed),
+            vertex_buffer: arena.vertex_buffer.clone(),
+            first_vertex: base.vertex,
+            transform: mesh.transform,
+            primitive_sections,
+            bounds: mesh.bounds,
+            skin,
+        });
+    }
+    Ok((meshes, Some(arena)))
+}
+
+// Pads the vertices (and skin vertices) and indices of the mesh for each primitive section to
+// start at a storage buffer offset relative to the mesh, e.g. for the section descriptors of
+// ray::SceneDescription. The mesh base is aligned by GeometryArena::upload.
+fn align_sections(mesh: &mut MeshData, limits: &DeviceLimits) {
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    let mut skin_vertices = Vec::new();
+    let vertex_size = std::mem::size_of::<ModelVertex>();
+    for section in &mut mesh.primitive_sections {
+        let part = &mut section.vertices;
+        let range = part.offset..part.offset + part.element_count;
+        part.offset = limits.align_ssbo_elements(vertices.len(), vertex_size);
+        vertices.resize(part.offset, ModelVertex::default());
+        vertices.extend_from_slice(&mesh.vertices[range.clone()]);
+        if let Some((_, skin)) = &mesh.skin {
+            skin_vertices.resize(part.offset, SkinVertex::default());
+            skin_vertices.extend_from_slice(&skin[range]);
+        }
+        if let Some(part) = &mut section.indices {
+            let range = part.offset..part.offset + part.element_count;
+            part.offset = limits.align_ssbo_elements(indices.len(), std::mem::size_of::<u32>());
+            indices.resize(part.offset, 0);
+            indices.extend_from_slice(&mesh.indices[range]);
+        }
+    }
+    mesh.vertices = vertices;
+    mesh.indices = indices;
+    if let Some((_, skin)) = &mut mesh.skin {
+        *skin = skin_vertices;
     }
 }
 
@@ -339,3 +1188,21 @@ where
         .read_colors(0)
         .map_or(vec![], |colors| colors.into_rgba_f32().collect())
 }
+
+fn read_joints<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[u16; 4]>
+where
+    F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
+{
+    reader
+        .read_joints(0)
+        .map_or(vec![], |joints| joints.into_u16().collect())
+}
+
+fn read_weights<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[f32; 4]>
+where
+    F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
+{
+    reader
+        .read_weights(0)
+        .map_or(vec![], |weights| weights.into_f32().collect())
+}
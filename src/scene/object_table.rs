@@ -0,0 +1,173 @@
+use super::{Mesh, PrimitiveSection, Scene};
+use crate::{Buffer, BufferInfo, Context, Result};
+use ash::vk;
+use std::sync::Arc;
+
+// Material index of objects without material.
+pub const NO_MATERIAL: u32 = u32::MAX;
+
+// Object index convention: raster draws push a single u32 (the object index) at push constant
+// offset 0, and shaders fetch the transforms and material of the draw from the ObjectTable storage
+// buffer, see assets/glsl/object_table.glsl. Draws only change the pushed index, the per frame
+// data (camera) stays in a uniform buffer updated once per frame.
+pub fn object_index_push_constant_range(stages: vk::ShaderStageFlags) -> vk::PushConstantRange {
+    vk::PushConstantRange::builder()
+        .stage_flags(stages)
+        .offset(0)
+        .size(std::mem::size_of::<u32>() as u32)
+        .build()
+}
+
+pub fn cmd_push_object_index(
+    context: &Context,
+    cmd: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    stages: vk::ShaderStageFlags,
+    object_index: u32,
+) {
+    unsafe {
+        context.device().cmd_push_constants(
+            cmd,
+            pipeline_layout,
+            stages,
+            0,
+            &object_index.to_ne_bytes(),
+        );
+    }
+}
+
+// std430 element of the object table.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectData {
+    pub transform: glam::Mat4,
+    // Inverse transpose of the transform.
+    pub normal_transform: glam::Mat4,
+    // Index in Scene::materials, or NO_MATERIAL.
+    pub material_index: u32,
+    pub padding: [u32; 3],
+}
+
+impl ObjectData {
+    pub fn new(transform: glam::Mat4, material_index: Option<usize>) -> Self {
+        ObjectData {
+            transform,
+            normal_transform: transform.inverse().transpose(),
+            material_index: material_index.map_or(NO_MATERIAL, |index| index as u32),
+            padding: [0; 3],
+        }
+    }
+}
+
+// A primitive section to draw with its object index, see ObjectTable::extract_scene.
+pub struct ObjectDraw<'a> {
+    pub mesh: &'a Mesh,
+    pub section: &'a PrimitiveSection,
+    pub object_index: u32,
+}
+
+impl ObjectDraw<'_> {
+    // Pushes the object index and draws the section. The pipeline vertex input must match the
+    // mesh vertices.
+    pub fn cmd_draw(
+        &self,
+        cmd: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        stages: vk::ShaderStageFlags,
+    ) {
+        cmd_push_object_index(
+            &self.mesh.context,
+            cmd,
+            pipeline_layout,
+            stages,
+            self.object_index,
+        );
+        self.mesh.cmd_draw_section(cmd, self.section);
+    }
+}
+
+// Objects of the current frame, filled during scene extraction (clear, push or extract_scene)
+// and uploaded once per frame to a storage buffer per frame in flight.
+pub struct ObjectTable {
+    objects: Vec<ObjectData>,
+    buffers: Vec<Buffer>,
+    capacity: usize,
+}
+
+impl ObjectTable {
+    pub fn new(context: Arc<Context>, capacity: usize, frames_count: usize) -> Result<Self> {
+        let buffers = (0..frames_count)
+            .map(|_| {
+                Buffer::new(
+                    context.clone(),
+                    BufferInfo::default()
+                        .name("ObjectTable")
+                        .usage_storage()
+                        .cpu_to_gpu(),
+                    (capacity * std::mem::size_of::<ObjectData>()) as vk::DeviceSize,
+                    capacity as u32,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ObjectTable {
+            objects: Vec::with_capacity(capacity),
+            buffers,
+            capacity,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.objects.clear();
+    }
+
+    // Returns the object index to push with the draw.
+    pub fn push(&mut self, object: ObjectData) -> u32 {
+        assert!(self.objects.len() < self.capacity, "ObjectTable is full.");
+        self.objects.push(object);
+        self.objects.len() as u32 - 1
+    }
+
+    // Pushes one object per primitive section of every scene mesh, at the mesh transform and
+    // with the section material.
+    pub fn extract_scene<'a>(&mut self, scene: &'a Scene) -> Vec<ObjectDraw<'a>> {
+        let mut draws = Vec::new();
+        for mesh in &scene.meshes {
+            for section in &mesh.primitive_sections {
+                let object = ObjectData::new(mesh.transform, section.get_material_index());
+                draws.push(ObjectDraw {
+                    mesh,
+                    section,
+                    object_index: self.push(object),
+                });
+            }
+        }
+        draws
+    }
+
+    pub fn objects(&self) -> &[ObjectData] {
+        &self.objects
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // Copies the objects to the buffer of `frame_index`, before submitting the frame draws.
+    pub fn upload(&self, frame_index: usize) {
+        if !self.objects.is_empty() {
+            self.buffers[frame_index].update(&self.objects);
+        }
+    }
+
+    pub fn get_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.buffers[frame_index]
+    }
+
+    pub fn get_descriptor_info(&self, frame_index: usize) -> vk::DescriptorBufferInfo {
+        self.buffers[frame_index].get_descriptor_info()
+    }
+}
@@ -0,0 +1,434 @@
+use super::Aabb;
+use crate::ray::SceneDescription;
+use glam::*;
+
+// Bounces resolved per body and step, the remaining motion is dropped.
+const MAX_BOUNCES: usize = 4;
+// Distance kept between a body and the surface it hits, so the next sweep doesn't start inside.
+const CONTACT_OFFSET: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SweepHit {
+    // Index of the hit bounds, e.g. a BLAS index.
+    pub index: usize,
+    // Fraction of the motion before the contact, 0 when starting inside the bounds.
+    pub time: f32,
+    pub normal: Vec3,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    // Leaves have items [first, first + count), inner nodes have their two children at first
+    // and first + 1.
+    first: usize,
+    count: usize,
+}
+
+// CPU bounding volume hierarchy over world space bounds, the static colliders of the kinematic
+// helpers. Median split along the longest axis, rebuilt (cheaply) when the colliders move.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    items: Vec<(usize, Aabb)>,
+}
+
+impl Bvh {
+    const LEAF_SIZE: usize = 4;
+
+    // Empty bounds are skipped.
+    pub fn new(items: Vec<(usize, Aabb)>) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            items: items.into_iter().filter(|(_, b)| !b.is_empty()).collect(),
+        };
+        if !bvh.items.is_empty() {
+            bvh.nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first: 0,
+                count: bvh.items.len(),
+            });
+            bvh.subdivide(0);
+        }
+        bvh
+    }
+
+    // The world space bounds of the BLAS for which `is_static` returns true, e.g. every BLAS
+    // not driven by a KinematicWorld.
+    pub fn from_scene_description(
+        scene: &SceneDescription,
+        is_static: impl Fn(usize) -> bool,
+    ) -> Self {
+        Self::new(
+            (0..scene.blas().len())
+                .filter(|index| is_static(*index))
+                .map(|index| (index, scene.blas_bounds(index)))
+                .collect(),
+        )
+    }
+
+    fn subdivide(&mut self, node_index: usize) {
+        let BvhNode { first, count, .. } = self.nodes[node_index];
+        let items = &mut self.items[first..first + count];
+        let mut bounds = Aabb::EMPTY;
+        let mut centers = Aabb::EMPTY;
+        for (_, item) in items.iter() {
+            bounds.grow(item.min);
+            bounds.grow(item.max);
+            centers.grow(item.center());
+        }
+        self.nodes[node_index].bounds = bounds;
+        if count <= Self::LEAF_SIZE {
+            return;
+        }
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let half = count / 2;
+        items.select_nth_unstable_by(half, |(_, a), (_, b)| {
+            a.center()[axis].total_cmp(&b.center()[axis])
+        });
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first,
+            count: half,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first: first + half,
+            count: count - half,
+        });
+        self.nodes[node_index].first = left;
+        self.nodes[node_index].count = 0;
+        self.subdivide(left);
+        self.subdivide(left + 1);
+    }
+
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<SweepHit> {
+        self.sweep(
+            origin,
+            direction.normalize_or_zero() * max_distance,
+            Vec3::ZERO,
+        )
+    }
+
+    pub fn sweep_aabb(&self, bounds: &Aabb, motion: Vec3) -> Option<SweepHit> {
+        self.sweep(bounds.center(), motion, (bounds.max - bounds.min) * 0.5)
+    }
+
+    // The sphere is swept as its bounding box: contacts near box edges and corners are early,
+    // which is fine for demo scenes.
+    pub fn sweep_sphere(&self, center: Vec3, radius: f32, motion: Vec3) -> Option<SweepHit> {
+        self.sweep(center, motion, Vec3::splat(radius))
+    }
+
+    // Closest hit of the point `origin` moving by `motion` against the bounds grown by the
+    // half extent of the swept shape.
+    fn sweep(&self, origin: Vec3, motion: Vec3, half_extent: Vec3) -> Option<SweepHit> {
+        if self.nodes.is_empty() || motion == Vec3::ZERO {
+            return None;
+        }
+        let mut closest: Option<SweepHit> = None;
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let max_time = closest.map_or(1.0, |hit| hit.time);
+            if slab_test(origin, motion, &node.bounds, half_extent, max_time).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+                continue;
+            }
+            for (index, bounds) in &self.items[node.first..node.first + node.count] {
+                let max_time = closest.map_or(1.0, |hit| hit.time);
+                if let Some((time, normal)) =
+                    slab_test(origin, motion, bounds, half_extent, max_time)
+                {
+                    closest = Some(SweepHit {
+                        index: *index,
+                        time,
+                        normal,
+                    });
+                }
+            }
+        }
+        closest
+    }
+}
+
+// Entry time along `motion` and face normal of the bounds grown by `half_extent`.
+fn slab_test(
+    origin: Vec3,
+    motion: Vec3,
+    bounds: &Aabb,
+    half_extent: Vec3,
+    max_time: f32,
+) -> Option<(f32, Vec3)> {
+    let mut enter = f32::MIN;
+    let mut exit = f32::MAX;
+    let mut enter_axis = 0;
+    for axis in 0..3 {
+        let min = bounds.min[axis] - half_extent[axis];
+        let max = bounds.max[axis] + half_extent[axis];
+        if motion[axis].abs() < f32::EPSILON {
+            if origin[axis] < min || origin[axis] > max {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (min - origin[axis]) / motion[axis];
+        let t1 = (max - origin[axis]) / motion[axis];
+        let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+        if near > enter {
+            enter = near;
+            enter_axis = axis;
+        }
+        exit = exit.min(far);
+    }
+    if enter > exit || exit < 0.0 || enter > max_time {
+        return None;
+    }
+    let mut normal = Vec3::ZERO;
+    normal[enter_axis] = -motion[enter_axis].signum();
+    Some((enter.max(0.0), normal))
+}
+
+// A sphere falling and bouncing against the static colliders of a Bvh, driving the transform
+// of a scene instance. Bodies don't collide with each other.
+#[derive(Clone, Copy, Debug)]
+pub struct KinematicBody {
+    // Instance driven by the body, e.g. a BLAS index.
+    pub index: usize,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub radius: f32,
+    // Fraction of the normal velocity kept by a bounce.
+    pub restitution: f32,
+    // Instance transform relative to the body position.
+    local_transform: Mat4,
+}
+
+impl KinematicBody {
+    // The body starts at the translation of `transform`.
+    pub fn new(index: usize, transform: Mat4, radius: f32) -> Self {
+        let position = transform.w_axis.xyz();
+        KinematicBody {
+            index,
+            position,
+            velocity: Vec3::ZERO,
+            radius,
+            restitution: 0.5,
+            local_transform: Mat4::from_translation(-position) * transform,
+        }
+    }
+
+    pub fn velocity(mut self, velocity: Vec3) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn restitution(mut self, restitution: f32) -> Self {
+        self.restitution = restitution;
+        self
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_translation(self.position) * self.local_transform
+    }
+}
+
+// Gravity and bounce integrator for a few animated instances, enough for ray tracing demos
+// without a physics crate. Call `step` per frame, then `apply` before the TLAS update.
+pub struct KinematicWorld {
+    pub gravity: Vec3,
+    pub bodies: Vec<KinematicBody>,
+}
+
+impl Default for KinematicWorld {
+    fn default() -> Self {
+        KinematicWorld {
+            gravity: vec3(0.0, -9.81, 0.0),
+            bodies: Vec::new(),
+        }
+    }
+}
+
+impl KinematicWorld {
+    pub fn new(gravity: Vec3) -> Self {
+        KinematicWorld {
+            gravity,
+            bodies: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, body: KinematicBody) -> usize {
+        self.bodies.push(body);
+        self.bodies.len() - 1
+    }
+
+    // Whether the instance at `index` is driven by a body, e.g. to exclude it from the static
+    // colliders of Bvh::from_scene_description.
+    pub fn drives(&self, index: usize) -> bool {
+        self.bodies.iter().any(|body| body.index == index)
+    }
+
+    pub fn step(&mut self, dt: f32, colliders: &Bvh) {
+        for body in &mut self.bodies {
+            body.velocity += self.gravity * dt;
+            let mut motion = body.velocity * dt;
+            for _ in 0..MAX_BOUNCES {
+                let hit = match colliders.sweep_sphere(body.position, body.radius, motion) {
+                    Some(hit) => hit,
+                    None => {
+                        body.position += motion;
+                        break;
+                    }
+                };
+                body.position += motion * hit.time + hit.normal * CONTACT_OFFSET;
+                let bounce = 1.0 + body.restitution;
+                let normal_speed = body.velocity.dot(hit.normal);
+                if normal_speed < 0.0 {
+                    body.velocity -= hit.normal * normal_speed * bounce;
+                    // Resting contact: below the speed gained from gravity in a step, the body
+                    // would bounce forever.
+                    if -normal_speed * body.restitution <= self.gravity.length() * dt {
+                        body.velocity -= hit.normal * body.velocity.dot(hit.normal);
+                    }
+                }
+                let remaining = motion * (1.0 - hit.time);
+                motion = remaining - hit.normal * remaining.dot(hit.normal).min(0.0) * bounce;
+            }
+        }
+    }
+
+    // Writes the body transforms into `transforms`, indexed by instance, e.g. for
+    // SceneDescription::blas_transforms.
+    pub fn write_transforms(&self, transforms: &mut [Mat4]) {
+        for body in &self.bodies {
+            transforms[body.index] = body.transform();
+        }
+    }
+
+    // Updates the driven BLAS and instance transforms. The instances buffer and TLAS must then
+    // be updated (SceneDescription::update and tlas_refit).
+    pub fn apply(&self, scene: &mut SceneDescription) {
+        for body in &self.bodies {
+            scene.blas_transform(body.transform(), body.index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box(center: Vec3) -> Aabb {
+        Aabb {
+            min: center - Vec3::ONE,
+            max: center + Vec3::ONE,
+        }
+    }
+
+    #[test]
+    fn ray_hits_and_misses_box() {
+        let bounds = unit_box(Vec3::ZERO);
+        let motion = vec3(10.0, 0.0, 0.0);
+        let (time, normal) = slab_test(vec3(-5.0, 0.0, 0.0), motion, &bounds, Vec3::ZERO, 1.0)
+            .expect("the ray should hit the box");
+        assert!((time - 0.4).abs() < 1e-6);
+        assert_eq!(normal, vec3(-1.0, 0.0, 0.0));
+        // Parallel to the box, above it.
+        assert!(slab_test(vec3(-5.0, 3.0, 0.0), motion, &bounds, Vec3::ZERO, 1.0).is_none());
+        // Pointing away from it.
+        assert!(slab_test(vec3(5.0, 0.0, 0.0), motion, &bounds, Vec3::ZERO, 1.0).is_none());
+        // Too short to reach it.
+        assert!(slab_test(vec3(-5.0, 0.0, 0.0), motion, &bounds, Vec3::ZERO, 0.3).is_none());
+
+        let bvh = Bvh::new(vec![(7, bounds)]);
+        let hit = bvh.raycast(vec3(0.0, 5.0, 0.0), vec3(0.0, -1.0, 0.0), 10.0);
+        let hit = hit.expect("the ray should hit the box");
+        assert_eq!(hit.index, 7);
+        assert!((hit.time - 0.4).abs() < 1e-6);
+        assert_eq!(hit.normal, vec3(0.0, 1.0, 0.0));
+        assert!(bvh
+            .raycast(vec3(0.0, 5.0, 0.0), vec3(1.0, 0.0, 0.0), 10.0)
+            .is_none());
+    }
+
+    #[test]
+    fn sweep_starting_inside() {
+        let bvh = Bvh::new(vec![(3, unit_box(Vec3::ZERO))]);
+        let hit = bvh.sweep_sphere(vec3(0.5, 0.0, 0.0), 0.25, vec3(1.0, 0.0, 0.0));
+        let hit = hit.expect("the sweep should hit the box it starts in");
+        assert_eq!(hit.index, 3);
+        assert_eq!(hit.time, 0.0);
+    }
+
+    #[test]
+    fn closest_hit_across_leaves() {
+        // A row of boxes along x, more than a leaf holds, in shuffled order.
+        let items = [5, 2, 8, 0, 9, 3, 6, 1, 7, 4]
+            .iter()
+            .map(|&i| (i, unit_box(vec3(i as f32 * 4.0, 0.0, 0.0))))
+            .collect::<Vec<_>>();
+        let bvh = Bvh::new(items);
+        assert!(bvh.nodes.len() > 1);
+
+        let hit = bvh.raycast(vec3(-10.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 100.0);
+        let hit = hit.expect("the ray should hit the first box");
+        assert_eq!(hit.index, 0);
+        assert!((hit.time - 0.09).abs() < 1e-6);
+
+        let hit = bvh.raycast(vec3(50.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), 100.0);
+        assert_eq!(hit.map(|hit| hit.index), Some(9));
+
+        // Starting between boxes 4 and 5.
+        let hit = bvh.sweep_sphere(vec3(18.0, 0.0, 0.0), 0.5, vec3(-10.0, 0.0, 0.0));
+        assert_eq!(hit.map(|hit| hit.index), Some(4));
+    }
+
+    #[test]
+    fn body_comes_to_rest_on_floor() {
+        let floor = Aabb {
+            min: vec3(-10.0, -1.0, -10.0),
+            max: vec3(10.0, 0.0, 10.0),
+        };
+        let colliders = Bvh::new(vec![(0, floor)]);
+        let mut world = KinematicWorld::default();
+        let radius = 0.5;
+        let body = world.add(KinematicBody::new(
+            1,
+            Mat4::from_translation(Vec3::Y * 2.0),
+            radius,
+        ));
+
+        let dt = 1.0 / 60.0;
+        let rest_step = (0..600).find(|_| {
+            world.step(dt, &colliders);
+            let body = &world.bodies[body];
+            assert!(
+                body.position.y > radius - 0.01,
+                "the body went through the floor"
+            );
+            body.velocity.length() < 1e-3 && (body.position.y - radius).abs() < 0.01
+        });
+        assert!(rest_step.is_some(), "the body should come to rest");
+
+        // And stays there.
+        for _ in 0..60 {
+            world.step(dt, &colliders);
+        }
+        let body = &world.bodies[body];
+        assert!((body.position.y - radius).abs() < 0.01);
+        assert!(body.velocity.length() < 1e-3);
+        assert_eq!(body.transform().w_axis.xyz(), body.position);
+    }
+}
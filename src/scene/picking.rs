@@ -0,0 +1,335 @@
+use super::{ModelVertex, Scene};
+use crate::{
+    Buffer, BufferInfo, Context, RenderTarget, RenderTargetInfo, Resource, Result, SamplerInfo,
+    Shader, Vertex,
+};
+use ash::vk;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// Instance of a scene primitive section, numbered in mesh then section order like the
+// SceneInstance of a SceneDescription (see SceneInstance::id) and DebugView::InstanceId.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InstanceId(pub u32);
+
+const PICKING_VERT: &str = "
+#version 450
+layout(location = 0) in vec4 pos;
+layout(push_constant) uniform Params {
+    mat4 mvp;
+    uint id;
+} p;
+void main() {
+    gl_Position = p.mvp * pos;
+}
+";
+
+// Zero is left to the clear color, where nothing was drawn.
+const PICKING_FRAG: &str = "
+#version 450
+layout(location = 0) out uint out_id;
+layout(push_constant) uniform Params {
+    mat4 mvp;
+    uint id;
+} p;
+void main() {
+    out_id = p.id + 1u;
+}
+";
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PickingParams {
+    mvp: glam::Mat4,
+    id: u32,
+}
+
+// Renders the InstanceId of the scene meshes to an R32_UINT target, to find the instance under
+// a pixel, e.g. the cursor of an editor. The target is drawn with cmd_draw in the frames where
+// picking may be needed, then read back one pixel at a time with pick.
+pub struct PickingPass {
+    context: Arc<Context>,
+    target: RenderTarget,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    // Pixel copied from the target, read back to the CPU.
+    pixel: Buffer,
+}
+
+impl PickingPass {
+    pub fn new(context: Arc<Context>, extent: vk::Extent2D) -> Result<Self> {
+        let target = RenderTarget::new(
+            context.clone(),
+            RenderTargetInfo::default()
+                .name("Picking")
+                .extent(extent.width, extent.height)
+                .color_format(vk::Format::R32_UINT)
+                // Integer formats can't be linearly filtered.
+                .sampler(
+                    SamplerInfo::default()
+                        .filter(vk::Filter::NEAREST)
+                        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                        .clamp_to_edge()
+                        .no_anisotropy(),
+                ),
+        )?;
+        let vert = Shader::from_source(
+            context.clone(),
+            PICKING_VERT,
+            "picking.vert",
+            vk::ShaderStageFlags::VERTEX,
+        )?;
+        let frag = Shader::from_source(
+            context.clone(),
+            PICKING_FRAG,
+            "picking.frag",
+            vk::ShaderStageFlags::FRAGMENT,
+        )?;
+
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .size(std::mem::size_of::<PickingParams>() as u32)
+            .build()];
+        let layout_info =
+            vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            context
+                .device()
+                .create_pipeline_layout(&layout_info, None)?
+        };
+
+        // Only the positions are read.
+        let vertex_input_binding_descriptions = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: ModelVertex::stride(),
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let (position_format, position_offset) = ModelVertex::format_offset()[0];
+        let vertex_input_attribute_descriptions = [vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: position_format,
+            offset: position_offset,
+        }];
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&vertex_input_binding_descriptions)
+            .vertex_attribute_descriptions(&vertex_input_attribute_descriptions);
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+            scissor_count: 1,
+            viewport_count: 1,
+            ..Default::default()
+        };
+        // No culling, as the debug views: back faces seen through open meshes are pickable.
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            ..Default::default()
+        };
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: 1,
+            depth_write_enable: 1,
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            max_depth_bounds: 1.0,
+            ..Default::default()
+        };
+        // Integer formats can't be blended.
+        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::R,
+            ..Default::default()
+        }];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachment_states);
+        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+
+        let entry_name = CString::new("main").unwrap();
+        let stages = [
+            vert.get_create_info(&entry_name),
+            frag.get_create_info(&entry_name),
+        ];
+        let create_infos = [vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state_info)
+            .input_assembly_state(&vertex_input_assembly_state_info)
+            .viewport_state(&viewport_state_info)
+            .rasterization_state(&rasterization_info)
+            .multisample_state(&multisample_state_info)
+            .depth_stencil_state(&depth_state_info)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state_info)
+            .layout(pipeline_layout)
+            .render_pass(target.get_render_pass())
+            .build()];
+        let pipeline = unsafe {
+            context
+                .device()
+                .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                .map_err(|(_, result)| result)?[0]
+        };
+
+        let pixel = Buffer::new(
+            context.clone(),
+            BufferInfo::default()
+                .name("PickingPixel")
+                .gpu_only()
+                .usage_transfer_src()
+                .usage_transfer_dst(),
+            std::mem::size_of::<u32>() as vk::DeviceSize,
+            1,
+        )?;
+
+        Ok(PickingPass {
+            context,
+            target,
+            pipeline_layout,
+            pipeline,
+            pixel,
+        })
+    }
+
+    // Recreates the target at the new extent, e.g. that of the swapchain after a resize. Waits
+    // for the device to be idle.
+    pub fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        self.target.resize(extent)
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.target.get_extent()
+    }
+
+    // The target, with the picked ids plus one, 0 where nothing was drawn. Its color is in
+    // SHADER_READ_ONLY_OPTIMAL layout after cmd_draw, e.g. to outline the hovered instance.
+    pub fn get_target(&self) -> &RenderTarget {
+        &self.target
+    }
+
+    // Records the whole picking render pass, outside of any other render pass. `view_projection`
+    // must be that of the frame the picked pixels refer to.
+    pub fn cmd_draw(&mut self, cmd: vk::CommandBuffer, scene: &Scene, view_projection: glam::Mat4) {
+        self.target.cmd_begin(cmd, 0);
+        self.context.cmd_set_full_viewport(cmd, &self.target);
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        }
+        let mut instance_id = 0;
+        for mesh in &scene.meshes {
+            for section in &mesh.primitive_sections {
+                let params = PickingParams {
+                    mvp: view_projection * mesh.transform,
+                    id: instance_id,
+                };
+                unsafe {
+                    let bytes = std::slice::from_raw_parts(
+                        &params as *const PickingParams as *const u8,
+                        std::mem::size_of::<PickingParams>(),
+                    );
+                    device.cmd_push_constants(
+                        cmd,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        bytes,
+                    );
+                    device.cmd_bind_vertex_buffers(
+                        cmd,
+                        0,
+                        &[mesh.vertex_buffer.handle()],
+                        &[section.get_vertex_offset_size()],
+                    );
+                    match &mesh.index_buffer {
+                        Some(indices) => {
+                            device.cmd_bind_index_buffer(
+                                cmd,
+                                indices.handle(),
+                                section.get_index_offset_size::<u32>(),
+                                vk::IndexType::UINT32,
+                            );
+                            device.cmd_draw_indexed(cmd, section.get_index_count(), 1, 0, 0, 0);
+                        }
+                        None => device.cmd_draw(cmd, section.get_vertex_count(), 1, 0, 0),
+                    }
+                }
+                instance_id += 1;
+            }
+        }
+        self.target.cmd_end(cmd, 0);
+    }
+
+    // Instance drawn at pixel (`x`, `y`) from the top left corner of the target, e.g.
+    // InputState::mouse_pos, by the last submitted cmd_draw. None when nothing was drawn there,
+    // out of the target or before the first cmd_draw. Blocks until the pixel has been read back.
+    pub fn pick(&mut self, x: f32, y: f32) -> Option<InstanceId> {
+        let extent = self.target.get_extent();
+        if x < 0.0 || y < 0.0 || x >= extent.width as f32 || y >= extent.height as f32 {
+            return None;
+        }
+        let color = self.target.get_color_mut(0);
+        let previous_layout = color.get_layout();
+        if previous_layout == vk::ImageLayout::UNDEFINED {
+            return None;
+        }
+
+        let cmd = self.context.begin_single_time_cmd();
+        color.transition_image_layout(cmd, previous_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .build();
+        unsafe {
+            self.context.device().cmd_copy_image_to_buffer(
+                cmd,
+                color.handle(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.pixel.handle(),
+                &[region],
+            );
+        }
+        color.transition_image_layout(cmd, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, previous_layout);
+        let readback = self
+            .pixel
+            .cmd_readback::<u32>(cmd)
+            .expect("The picking pixel has TRANSFER_SRC usage.");
+        self.context.end_single_time_cmd(cmd);
+
+        match readback.finish()[0] {
+            0 => None,
+            id => Some(InstanceId(id - 1)),
+        }
+    }
+}
+
+impl Drop for PickingPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().destroy_pipeline(self.pipeline, None);
+            self.context
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
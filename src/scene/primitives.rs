@@ -0,0 +1,223 @@
+use super::{Mesh, ModelVertex};
+use crate::{Context, Result};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// Indexed triangles of a generated shape, centered at the origin with +Y up and counter-clockwise
+// front faces. Upload with to_mesh for rasterization or as BLAS input (see SceneDescription).
+#[derive(Clone, Debug, Default)]
+pub struct ProceduralMesh {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+fn vertex(pos: glam::Vec3, normal: glam::Vec3, uv: glam::Vec2) -> ModelVertex {
+    ModelVertex {
+        pos: pos.extend(1.0),
+        normal: normal.extend(1.0),
+        uv: uv.extend(0.0).extend(0.0),
+        ..Default::default()
+    }
+}
+
+impl ProceduralMesh {
+    pub fn to_mesh(&self, context: Arc<Context>, name: &str) -> Result<Mesh> {
+        Mesh::from_data(context, name, &self.vertices, &self.indices)
+    }
+
+    // Two triangles per cell of a (columns + 1) x (rows + 1) vertex grid, columns varying
+    // fastest. Faces are front facing when the column direction crossed by the row direction
+    // points outwards.
+    fn grid_indices(&mut self, columns: u32, rows: u32) {
+        for row in 0..rows {
+            for column in 0..columns {
+                let a = row * (columns + 1) + column;
+                let b = a + columns + 1;
+                self.indices
+                    .extend_from_slice(&[a, a + 1, b, a + 1, b + 1, b]);
+            }
+        }
+    }
+
+    // Sphere of latitude rings and longitude segments, with duplicated seam and pole vertices
+    // so that the uvs are continuous.
+    pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Self {
+        let (segments, rings) = (segments.max(3), rings.max(2));
+        let mut mesh = ProceduralMesh::default();
+        for ring in 0..=rings {
+            let v = ring as f32 / rings as f32;
+            let theta = v * PI;
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let phi = u * 2.0 * PI;
+                let normal = glam::vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                mesh.vertices
+                    .push(vertex(normal * radius, normal, glam::vec2(u, v)));
+            }
+        }
+        mesh.grid_indices(segments, rings);
+        mesh
+    }
+
+    // Subdivided icosahedron, with evenly sized triangles. Uvs are spherical and not corrected
+    // along the seam.
+    pub fn icosphere(radius: f32, subdivisions: u32) -> Self {
+        let t = (1.0 + 5f32.sqrt()) * 0.5;
+        let mut positions = vec![
+            glam::vec3(-1.0, t, 0.0),
+            glam::vec3(1.0, t, 0.0),
+            glam::vec3(-1.0, -t, 0.0),
+            glam::vec3(1.0, -t, 0.0),
+            glam::vec3(0.0, -1.0, t),
+            glam::vec3(0.0, 1.0, t),
+            glam::vec3(0.0, -1.0, -t),
+            glam::vec3(0.0, 1.0, -t),
+            glam::vec3(t, 0.0, -1.0),
+            glam::vec3(t, 0.0, 1.0),
+            glam::vec3(-t, 0.0, -1.0),
+            glam::vec3(-t, 0.0, 1.0),
+        ]
+        .into_iter()
+        .map(|position| position.normalize())
+        .collect::<Vec<_>>();
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+        for _ in 0..subdivisions {
+            // Edge midpoints are shared by the two triangles of the edge.
+            let mut midpoints = HashMap::<(u32, u32), u32>::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let position = (positions[a as usize] + positions[b as usize]).normalize();
+                    positions.push(position);
+                    positions.len() as u32 - 1
+                })
+            };
+            triangles = triangles
+                .iter()
+                .flat_map(|[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(*a, *b), midpoint(*b, *c), midpoint(*c, *a));
+                    vec![[*a, ab, ca], [*b, bc, ab], [*c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+        let vertices = positions
+            .iter()
+            .map(|normal| {
+                let uv = glam::vec2(
+                    0.5 + normal.z.atan2(normal.x) / (2.0 * PI),
+                    normal.y.clamp(-1.0, 1.0).acos() / PI,
+                );
+                vertex(*normal * radius, *normal, uv)
+            })
+            .collect();
+        ProceduralMesh {
+            vertices,
+            indices: triangles.into_iter().flatten().collect(),
+        }
+    }
+
+    // Plane in XZ facing +Y, of size.x by size.y split in subdivisions x subdivisions quads.
+    pub fn plane(size: glam::Vec2, subdivisions: u32) -> Self {
+        let cells = subdivisions.max(1);
+        let mut mesh = ProceduralMesh::default();
+        for row in 0..=cells {
+            let v = row as f32 / cells as f32;
+            for column in 0..=cells {
+                let u = column as f32 / cells as f32;
+                let pos = glam::vec3((u - 0.5) * size.x, 0.0, (v - 0.5) * size.y);
+                mesh.vertices
+                    .push(vertex(pos, glam::Vec3::Y, glam::vec2(u, v)));
+            }
+        }
+        // Rows go along +Z and columns along +X, so the grid winding is reversed to face +Y.
+        mesh.grid_indices(cells, cells);
+        for triangle in mesh.indices.chunks_mut(3) {
+            triangle.swap(1, 2);
+        }
+        mesh
+    }
+
+    // Torus around the Y axis. `radius` is the distance from the center to the tube center.
+    pub fn torus(radius: f32, tube_radius: f32, segments: u32, sides: u32) -> Self {
+        let (segments, sides) = (segments.max(3), sides.max(3));
+        let mut mesh = ProceduralMesh::default();
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let phi = u * 2.0 * PI;
+            let center = glam::vec3(phi.cos(), 0.0, phi.sin()) * radius;
+            for side in 0..=sides {
+                let v = side as f32 / sides as f32;
+                let theta = v * 2.0 * PI;
+                let normal = glam::vec3(
+                    theta.cos() * phi.cos(),
+                    theta.sin(),
+                    theta.cos() * phi.sin(),
+                );
+                mesh.vertices.push(vertex(
+                    center + normal * tube_radius,
+                    normal,
+                    glam::vec2(u, v),
+                ));
+            }
+        }
+        mesh.grid_indices(sides, segments);
+        mesh
+    }
+
+    // Box of the given extents, with split vertices per face for flat normals and full uvs.
+    pub fn cuboid(size: glam::Vec3) -> Self {
+        let half = size * 0.5;
+        let mut mesh = ProceduralMesh::default();
+        let faces = [
+            (glam::Vec3::X, -glam::Vec3::Z),
+            (-glam::Vec3::X, glam::Vec3::Z),
+            (glam::Vec3::Y, glam::Vec3::X),
+            (-glam::Vec3::Y, glam::Vec3::X),
+            (glam::Vec3::Z, glam::Vec3::X),
+            (-glam::Vec3::Z, -glam::Vec3::X),
+        ];
+        for (normal, tangent) in faces {
+            let bitangent = normal.cross(tangent);
+            let first = mesh.vertices.len() as u32;
+            for (u, v) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                let pos = normal + tangent * (u * 2.0 - 1.0) + bitangent * (v * 2.0 - 1.0);
+                mesh.vertices
+                    .push(vertex(pos * half, normal, glam::vec2(u, 1.0 - v)));
+            }
+            mesh.indices.extend_from_slice(&[
+                first,
+                first + 1,
+                first + 2,
+                first,
+                first + 2,
+                first + 3,
+            ]);
+        }
+        mesh
+    }
+}
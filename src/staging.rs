@@ -0,0 +1,280 @@
+use crate::{align_up, Buffer, BufferInfo, Context, Resource, Result};
+use ash::vk;
+use std::sync::Arc;
+
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+// Identifies a StagingBelt submission, see StagingBelt::is_complete and StagingBelt::wait.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UploadId(u64);
+
+struct Chunk {
+    buffer: Buffer,
+    // Size the buffer was created with, the allocation can be larger.
+    size: vk::DeviceSize,
+    offset: vk::DeviceSize,
+}
+
+struct Batch {
+    id: u64,
+    cmd: vk::CommandBuffer,
+    fence: vk::Fence,
+    chunks: Vec<Chunk>,
+}
+
+// Batches buffer uploads in a single command buffer, submitted with a fence instead of a
+// blocking single-time command per buffer. Data is copied into persistently mapped staging
+// chunks which are recycled once the submission that used them has completed, so repeated
+// uploads (loading, per-frame updates of gpu_only buffers) don't allocate staging memory.
+// Uploads are visible to every command submitted to the graphics queue after `submit`.
+pub struct StagingBelt {
+    context: Arc<Context>,
+    chunk_size: vk::DeviceSize,
+    pool: vk::CommandPool,
+    cmd: vk::CommandBuffer,
+    active: Vec<Chunk>,
+    free: Vec<Chunk>,
+    in_flight: Vec<Batch>,
+    next_id: u64,
+}
+
+impl StagingBelt {
+    // Uploads larger than `chunk_size` get a dedicated chunk, recycled like the others.
+    pub fn new(context: Arc<Context>, chunk_size: vk::DeviceSize) -> Self {
+        let pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(context.shared().queue_family_indices.graphics);
+        let pool = unsafe {
+            context
+                .device()
+                .create_command_pool(&pool_create_info, None)
+                .unwrap()
+        };
+        StagingBelt {
+            context,
+            chunk_size,
+            pool,
+            cmd: vk::CommandBuffer::null(),
+            active: Vec::new(),
+            free: Vec::new(),
+            in_flight: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    fn command_buffer(&mut self) -> vk::CommandBuffer {
+        if self.cmd == vk::CommandBuffer::null() {
+            let device = self.context.device();
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(self.pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe {
+                self.cmd = device.allocate_command_buffers(&allocate_info).unwrap()[0];
+                device
+                    .begin_command_buffer(self.cmd, &begin_info)
+                    .expect("Begin upload commands.");
+            }
+        }
+        self.cmd
+    }
+
+    // Returns the chunk index and offset of a staging region of `size` bytes.
+    fn allocate(&mut self, size: vk::DeviceSize) -> Result<(usize, vk::DeviceSize)> {
+        if let Some(index) = self
+            .active
+            .iter()
+            .position(|chunk| align_up(chunk.offset, STAGING_ALIGNMENT) + size <= chunk.size)
+        {
+            let chunk = &mut self.active[index];
+            let offset = align_up(chunk.offset, STAGING_ALIGNMENT);
+            chunk.offset = offset + size;
+            return Ok((index, offset));
+        }
+        self.recycle();
+        let mut chunk = match self.free.iter().position(|chunk| chunk.size >= size) {
+            Some(index) => self.free.swap_remove(index),
+            None => {
+                let chunk_size = size.max(self.chunk_size);
+                let buffer = Buffer::new(
+                    self.context.clone(),
+                    BufferInfo::default()
+                        .name("StagingBelt")
+                        .cpu_to_gpu()
+                        .usage_transfer_src(),
+                    chunk_size,
+                    1,
+                )?;
+                Chunk {
+                    buffer,
+                    size: chunk_size,
+                    offset: 0,
+                }
+            }
+        };
+        chunk.offset = size;
+        self.active.push(chunk);
+        Ok((self.active.len() - 1, 0))
+    }
+
+    // Records the copy of `data` to `dst` at byte offset `dst_offset`. `dst` needs the
+    // TRANSFER_DST usage and must stay alive until the upload has completed.
+    pub fn upload<T: Copy>(
+        &mut self,
+        dst: &Buffer,
+        dst_offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<()> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        if size == 0 {
+            return Ok(());
+        }
+        let (index, offset) = self.allocate(size)?;
+        let src = &self.active[index].buffer;
+        let src_handle = src.handle();
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                src.map().add(offset as usize),
+                size as usize,
+            );
+        }
+        let cmd = self.command_buffer();
+        let region = vk::BufferCopy::builder()
+            .src_offset(offset)
+            .dst_offset(dst_offset)
+            .size(size)
+            .build();
+        unsafe {
+            self.context
+                .device()
+                .cmd_copy_buffer(cmd, src_handle, dst.handle(), &[region]);
+        }
+        Ok(())
+    }
+
+    // Like Buffer::from_data with a gpu_only info, without waiting for the copy. The buffer
+    // contents are valid for commands submitted after `submit`.
+    pub fn create_buffer<T: Copy>(&mut self, info: BufferInfo, data: &[T]) -> Result<Buffer> {
+        assert!(!data.is_empty());
+        let buffer = Buffer::new(
+            self.context.clone(),
+            info.gpu_only().usage_transfer_dst(),
+            std::mem::size_of_val(data) as vk::DeviceSize,
+            data.len() as u32,
+        )?;
+        self.upload(&buffer, 0, data)?;
+        Ok(buffer)
+    }
+
+    // Submits the uploads recorded since the last submit. Returns immediately, use is_complete
+    // or wait to know when the staging data was consumed.
+    pub fn submit(&mut self) -> UploadId {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.cmd == vk::CommandBuffer::null() {
+            // Nothing recorded: the id is already complete.
+            return UploadId(id);
+        }
+        let device = self.context.device();
+        unsafe {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+                .build();
+            device.cmd_pipeline_barrier(
+                self.cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            device
+                .end_command_buffer(self.cmd)
+                .expect("End upload commands.");
+            let fence = device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .unwrap();
+            let submit_info =
+                vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&self.cmd));
+            self.context
+                .queue_submit(
+                    self.context.graphics_queue(),
+                    std::slice::from_ref(&submit_info),
+                    fence,
+                )
+                .expect("Upload submit failed.");
+            self.in_flight.push(Batch {
+                id,
+                cmd: self.cmd,
+                fence,
+                chunks: std::mem::take(&mut self.active),
+            });
+        }
+        self.cmd = vk::CommandBuffer::null();
+        UploadId(id)
+    }
+
+    pub fn is_complete(&self, upload: UploadId) -> bool {
+        let device = self.context.device();
+        self.in_flight
+            .iter()
+            .filter(|batch| batch.id <= upload.0)
+            .all(|batch| unsafe { device.get_fence_status(batch.fence).unwrap_or(false) })
+    }
+
+    pub fn wait(&mut self, upload: UploadId) {
+        let fences: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|batch| batch.id <= upload.0)
+            .map(|batch| batch.fence)
+            .collect();
+        if !fences.is_empty() {
+            unsafe {
+                self.context
+                    .device()
+                    .wait_for_fences(&fences, true, u64::MAX)
+                    .expect("Wait for uploads failed.");
+            }
+        }
+        self.recycle();
+    }
+
+    // Submits pending uploads and waits for all of them, e.g. at the end of a load.
+    pub fn finish(&mut self) {
+        let upload = self.submit();
+        self.wait(upload);
+    }
+
+    // Returns the chunks of completed submissions to the free list. Called by allocations and
+    // wait, so calling it explicitly is only needed to release command buffers early.
+    pub fn recycle(&mut self) {
+        let device = self.context.device();
+        let (done, pending): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|batch| unsafe { device.get_fence_status(batch.fence).unwrap_or(false) });
+        self.in_flight = pending;
+        for batch in done {
+            unsafe {
+                device.destroy_fence(batch.fence, None);
+                device.free_command_buffers(self.pool, &[batch.cmd]);
+            }
+            self.free.extend(batch.chunks);
+        }
+    }
+}
+
+impl Drop for StagingBelt {
+    fn drop(&mut self) {
+        self.finish();
+        unsafe {
+            self.context.device().destroy_command_pool(self.pool, None);
+        }
+    }
+}
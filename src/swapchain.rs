@@ -1,39 +1,96 @@
 use crate::{
-    Context, Image2d, RenderPass, RenderPassInfo, RendererSettings, Resource, SharedContext,
-    TransientRenderPassInfo, Window,
+    depth_aspect_mask, is_srgb_format, sample_count_flags, Context, DeviceGroupMode, Image2d,
+    ImageBarrier2, RenderArea, RenderPass, RenderPassInfo, RendererSettings, RenderingFormats,
+    RenderingInfo, Resource, Result, SharedContext, TransientRenderPassInfo, Window,
 };
 use ash::vk;
 use ash::{extensions::khr};
 use std::sync::Arc;
 
+// Surface formats for RendererSettings::preferred_surface_formats. Shaders writing to UNORM
+// formats encode the colors themselves, sRGB formats encode linear colors on writes.
+pub const SURFACE_FORMAT_UNORM: vk::SurfaceFormatKHR = vk::SurfaceFormatKHR {
+    format: vk::Format::B8G8R8A8_UNORM,
+    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+};
+pub const SURFACE_FORMAT_SRGB: vk::SurfaceFormatKHR = vk::SurfaceFormatKHR {
+    format: vk::Format::B8G8R8A8_SRGB,
+    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+};
+// HDR formats, only reported by surfaces of HDR displays. Colors are PQ encoded BT.2020 for HDR10
+// and linear BT.709 for scRGB, where 1.0 is 80 nits and values may exceed 1.0 or be negative.
+// The renderer enables VK_EXT_swapchain_colorspace when they are preferred.
+pub const SURFACE_FORMAT_HDR10: vk::SurfaceFormatKHR = vk::SurfaceFormatKHR {
+    format: vk::Format::A2B10G10R10_UNORM_PACK32,
+    color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+};
+pub const SURFACE_FORMAT_SCRGB: vk::SurfaceFormatKHR = vk::SurfaceFormatKHR {
+    format: vk::Format::R16G16B16A16_SFLOAT,
+    color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+};
+
+// Picks the first preferred format the surface supports. Otherwise falls back to a supported
+// format of a preferred color space with the same sRGB encoding, then of a preferred color space,
+// then to the first supported format.
+pub fn select_surface_format(
+    supported: &[vk::SurfaceFormatKHR],
+    preferred: &[vk::SurfaceFormatKHR],
+) -> vk::SurfaceFormatKHR {
+    let matches: [fn(&vk::SurfaceFormatKHR, &vk::SurfaceFormatKHR) -> bool; 3] = [
+        |preferred, supported| preferred == supported,
+        |preferred, supported| {
+            preferred.color_space == supported.color_space
+                && is_srgb_format(preferred.format) == is_srgb_format(supported.format)
+        },
+        |preferred, supported| preferred.color_space == supported.color_space,
+    ];
+    for (level, matches) in matches.iter().enumerate() {
+        for preferred_format in preferred {
+            if let Some(format) = supported
+                .iter()
+                .find(|supported| matches(preferred_format, supported))
+            {
+                if level > 0 {
+                    println!(
+                        "Surface format {:?} unsupported, using {:?}.",
+                        preferred[0], format
+                    );
+                }
+                return *format;
+            }
+        }
+    }
+    if !preferred.is_empty() {
+        println!(
+            "Surface format {:?} unsupported, using {:?}.",
+            preferred[0], supported[0]
+        );
+    }
+    supported[0]
+}
+
 pub struct Swapchain {
     context: Arc<SharedContext>,
     pub swapchain_loader: khr::Swapchain,
     swapchain: vk::SwapchainKHR,
     present_images: Vec<Image2d>,
+    surface_format: vk::SurfaceFormatKHR,
     depth_stencil_images: Vec<Image2d>,
     resolve_images: Vec<Image2d>,
     sample_count: vk::SampleCountFlags,
     extent: vk::Extent2D,
+    device_group_present_mode: vk::DeviceGroupPresentModeFlagsKHR,
+    supports_readback: bool,
 }
 
 impl Swapchain {
-    pub fn new(context: Arc<SharedContext>, window: &Window, settings: &RendererSettings) -> Self {
+    pub fn new(
+        context: Arc<SharedContext>,
+        window: &Window,
+        settings: &RendererSettings,
+    ) -> Result<Self> {
         unsafe {
-            let mut sample_count = vk::SampleCountFlags::TYPE_1;
-            if settings.samples == 2 {
-                sample_count = vk::SampleCountFlags::TYPE_2;
-            } else if settings.samples == 4 {
-                sample_count = vk::SampleCountFlags::TYPE_4;
-            } else if settings.samples == 8 {
-                sample_count = vk::SampleCountFlags::TYPE_8;
-            } else if settings.samples == 16 {
-                sample_count = vk::SampleCountFlags::TYPE_16;
-            } else if settings.samples == 32 {
-                sample_count = vk::SampleCountFlags::TYPE_32;
-            } else if settings.samples == 64 {
-                sample_count = vk::SampleCountFlags::TYPE_64;
-            }
+            let sample_count = sample_count_flags(settings.samples);
             let pdevice = context.physical_device();
             let surface_capabilities = window.get_surface_capabilities(pdevice);
             let mut desired_image_count = surface_capabilities.min_image_count + 1;
@@ -43,7 +100,10 @@ impl Swapchain {
                 desired_image_count = surface_capabilities.max_image_count;
             }
             let extent = window.get_surface_extent(pdevice);
-            let surface_format = window.get_surface_format(pdevice);
+            let surface_format = select_surface_format(
+                &window.get_surface_formats(pdevice),
+                &settings.preferred_surface_formats,
+            );
             let pre_transform = if surface_capabilities
                 .supported_transforms
                 .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
@@ -54,38 +114,51 @@ impl Swapchain {
             };
             let image_format = surface_format.format;
             let present_mode = window.get_surface_present_mode(pdevice, settings.present_mode);
+            // Present images can be read back (screenshots) when the surface allows it.
+            let supports_readback = surface_capabilities
+                .supported_usage_flags
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC);
+            let mut image_usage =
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST;
+            if supports_readback {
+                image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+            }
             let swapchain_loader = khr::Swapchain::new(context.instance(), context.device());
-            let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
+            let device_group_present_mode =
+                Self::select_device_group_present_mode(&context, &swapchain_loader, settings);
+            let mut device_group_info = vk::DeviceGroupSwapchainCreateInfoKHR::builder()
+                .modes(device_group_present_mode);
+            let mut swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
                 .surface(window.surface())
                 .min_image_count(desired_image_count)
                 .image_color_space(surface_format.color_space)
                 .image_format(image_format)
                 .image_extent(extent)
-                .image_usage(
-                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST,
-                )
+                .image_usage(image_usage)
                 .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
                 .pre_transform(pre_transform)
                 .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
                 .present_mode(present_mode)
                 .clipped(true)
                 .image_array_layers(1);
-            let swapchain = swapchain_loader
-                .create_swapchain(&swapchain_create_info, None)
-                .unwrap();
+            if !device_group_present_mode.is_empty() {
+                swapchain_create_info = swapchain_create_info.push_next(&mut device_group_info);
+            }
+            let swapchain = swapchain_loader.create_swapchain(&swapchain_create_info, None)?;
 
-            let swapchain_images = swapchain_loader.get_swapchain_images(swapchain).unwrap();
+            let swapchain_images = swapchain_loader.get_swapchain_images(swapchain)?;
             let present_images: Vec<Image2d> = swapchain_images
                 .iter()
                 .map(|image| Image2d::from_swapchain(context.clone(), *image, extent, image_format))
                 .collect();
 
             let mut depth_stencil_images = Vec::<Image2d>::new();
+            let depth_format = context.select_depth_format(settings.depth_format);
             if settings.depth {
                 for _ in 0..present_images.len() {
                     let depth_image_create_info = vk::ImageCreateInfo::builder()
                         .image_type(vk::ImageType::TYPE_2D)
-                        .format(vk::Format::D16_UNORM)
+                        .format(depth_format)
                         .extent(window.get_extent_3d())
                         .mip_levels(1)
                         .array_layers(1)
@@ -96,10 +169,10 @@ impl Swapchain {
                     depth_stencil_images.push(Image2d::new(
                         context.clone(),
                         &depth_image_create_info,
-                        vk::ImageAspectFlags::DEPTH,
+                        depth_aspect_mask(depth_format),
                         1,
                         "SwapchainDepthStencil"
-                    ));
+                    )?);
                 }
             }
 
@@ -125,20 +198,23 @@ impl Swapchain {
                         vk::ImageAspectFlags::COLOR,
                         1,
                         "SwapchainResolve"
-                    ));
+                    )?);
                 }
             }
 
-            Swapchain {
+            Ok(Swapchain {
                 context,
                 swapchain_loader,
                 swapchain,
                 present_images,
+                surface_format,
                 depth_stencil_images,
                 resolve_images,
                 sample_count,
                 extent,
-            }
+                device_group_present_mode,
+                supports_readback,
+            })
         }
     }
 
@@ -150,10 +226,70 @@ impl Swapchain {
         &mut self.present_images[index]
     }
 
+    pub fn get_format(&self) -> vk::Format {
+        self.present_images[0].get_format()
+    }
+
+    // Selected from RendererSettings::preferred_surface_formats, pipelines writing to the present
+    // images should encode their output for this format and color space.
+    pub fn get_surface_format(&self) -> vk::SurfaceFormatKHR {
+        self.surface_format
+    }
+
+    pub fn get_color_space(&self) -> vk::ColorSpaceKHR {
+        self.surface_format.color_space
+    }
+
+    pub fn supports_readback(&self) -> bool {
+        self.supports_readback
+    }
+
     pub fn get_sample_count(&self) -> vk::SampleCountFlags {
         self.sample_count
     }
 
+    pub fn get_depth_format(&self) -> Option<vk::Format> {
+        self.depth_stencil_images.first().map(|image| image.get_format())
+    }
+
+    // Empty when not rendering over a device group.
+    pub fn get_device_group_present_mode(&self) -> vk::DeviceGroupPresentModeFlagsKHR {
+        self.device_group_present_mode
+    }
+
+    fn select_device_group_present_mode(
+        context: &SharedContext,
+        swapchain_loader: &khr::Swapchain,
+        settings: &RendererSettings,
+    ) -> vk::DeviceGroupPresentModeFlagsKHR {
+        let wanted = match settings.device_group {
+            DeviceGroupMode::Disabled => return vk::DeviceGroupPresentModeFlagsKHR::empty(),
+            // Each device presents its own swapchain image.
+            DeviceGroupMode::AlternateFrame => vk::DeviceGroupPresentModeFlagsKHR::LOCAL_MULTI_DEVICE,
+            // Every device renders a disjoint band, the presentation engine sums them.
+            DeviceGroupMode::SplitFrame => vk::DeviceGroupPresentModeFlagsKHR::SUM,
+        };
+        if context.device_group_size() < 2 {
+            return vk::DeviceGroupPresentModeFlagsKHR::empty();
+        }
+        let mut capabilities = vk::DeviceGroupPresentCapabilitiesKHR::default();
+        let result = unsafe {
+            (swapchain_loader.fp().get_device_group_present_capabilities_khr)(
+                context.device().handle(),
+                &mut capabilities,
+            )
+        };
+        if result == vk::Result::SUCCESS && capabilities.modes.contains(wanted) {
+            wanted
+        } else {
+            println!(
+                "Device group present mode {:?} unsupported, rendering on a single device.",
+                wanted
+            );
+            vk::DeviceGroupPresentModeFlagsKHR::empty()
+        }
+    }
+
     pub fn create_compatible_render_pass(&self) -> RenderPass {
         let color_images = vec![&self.present_images[0]];
         let mut resolve_images = Vec::<&Image2d>::new();
@@ -174,10 +310,48 @@ impl Swapchain {
                 present: true,
                 samples: self.sample_count,
                 final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                load_color: false,
+            },
+        )
+    }
+
+    // Single-sampled pass loading the present image, drawn over whatever was rendered or blitted.
+    pub fn create_overlay_render_pass(&self) -> RenderPass {
+        RenderPass::new(
+            self.context.clone(),
+            RenderPassInfo {
+                color_images: vec![&self.present_images[0]],
+                depth_stencil_image: None,
+                resolve_images: Vec::new(),
+                present: true,
+                samples: vk::SampleCountFlags::TYPE_1,
+                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                load_color: true,
             },
         )
     }
 
+    pub fn create_overlay_framebuffers(&self, renderpass: &RenderPass) -> Vec<vk::Framebuffer> {
+        self.present_images
+            .iter()
+            .map(|image| {
+                let attachments = [image.get_image_view()];
+                let frame_buffer_create_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(renderpass.handle())
+                    .attachments(&attachments)
+                    .width(self.extent.width)
+                    .height(self.extent.height)
+                    .layers(1);
+                unsafe {
+                    self.context
+                        .device()
+                        .create_framebuffer(&frame_buffer_create_info, None)
+                        .unwrap()
+                }
+            })
+            .collect()
+    }
+
     pub fn get_transient_render_pass_info(&self) -> TransientRenderPassInfo {
         let mut resolve_formats = Vec::<vk::Format>::new();
         match self.resolve_images.iter().nth(0) {
@@ -196,6 +370,77 @@ impl Swapchain {
         }
     }
 
+    pub fn get_rendering_formats(&self) -> RenderingFormats {
+        self.get_transient_render_pass_info().into()
+    }
+
+    // Dynamic rendering counterpart of create_compatible_render_pass and its framebuffers, see
+    // cmd_transition_for_rendering.
+    pub fn get_rendering_info(&self, index: usize) -> RenderingInfo<'_> {
+        let (color_image, resolve_images) = match self.resolve_images.get(index) {
+            Some(multisampled_image) => (multisampled_image, vec![&self.present_images[index]]),
+            None => (&self.present_images[index], Vec::new()),
+        };
+        RenderingInfo {
+            color_images: vec![color_image],
+            depth_stencil_image: self.depth_stencil_images.get(index),
+            resolve_images,
+            samples: self.sample_count,
+            load_color: false,
+        }
+    }
+
+    // Dynamic rendering counterpart of create_overlay_render_pass and its framebuffers.
+    pub fn get_overlay_rendering_info(&self, index: usize) -> RenderingInfo<'_> {
+        RenderingInfo {
+            color_images: vec![&self.present_images[index]],
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_color: true,
+            ..Default::default()
+        }
+    }
+
+    // Discards the color attachments of image `index` before rendering to them without render
+    // pass, which would otherwise transition them. Once rendered, the present image must be
+    // transitioned with cmd_transition_for_present.
+    pub fn cmd_transition_for_rendering(&mut self, cmd: vk::CommandBuffer, index: usize) {
+        let images =
+            std::iter::once(&self.present_images[index]).chain(self.resolve_images.get(index));
+        // Chained to the image acquisition semaphore, waited for at this stage.
+        let barriers = images
+            .map(|image| {
+                ImageBarrier2::new(image.handle(), vk::ImageAspectFlags::COLOR)
+                    .layouts(
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    )
+                    .src(
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::NONE,
+                    )
+            })
+            .collect::<Vec<_>>();
+        self.context.cmd_pipeline_barrier2(cmd, &[], &[], &barriers);
+        let present_image = std::iter::once(&mut self.present_images[index]);
+        for image in present_image.chain(self.resolve_images.get_mut(index)) {
+            image.set_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        }
+    }
+
+    pub fn cmd_transition_for_present(&mut self, cmd: vk::CommandBuffer, index: usize) {
+        let barrier = ImageBarrier2::new(
+            self.present_images[index].handle(),
+            vk::ImageAspectFlags::COLOR,
+        )
+        .layouts(
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+        self.context
+            .cmd_pipeline_barrier2(cmd, &[], &[], &[barrier]);
+        self.present_images[index].set_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
     pub fn create_framebuffers(
         &self,
         renderpass: &RenderPass,
@@ -267,3 +512,9 @@ impl Drop for Swapchain {
         }
     }
 }
+
+impl RenderArea for Swapchain {
+    fn get_render_extent(&self) -> vk::Extent2D {
+        self.get_extent()
+    }
+}
@@ -0,0 +1,111 @@
+// Runs an app (e.g. the setup, update and render functions of an example) for a few frames and
+// reports validation errors, for smoke tests run with `cargo test --features harness`. Panics
+// in the app functions fail the calling test. Frames are run by the same function as
+// AppBuilder::run, so the app needs a window and a display: such tests are better marked
+// #[ignore] (see tests/examples.rs) to pass on machines without them. Code without rendering can
+// be tested with a HeadlessApp instead.
+use crate::{
+    validation_error_count, App, AppBuilder, AppSettings, FrameTime, ImageReadback, ValidationMode,
+};
+use std::sync::Mutex;
+use std::time::Duration;
+use winit::{
+    event::Event,
+    event_loop::{ControlFlow, EventLoop},
+    platform::run_return::EventLoopExtRunReturn,
+};
+
+// Apps are run one at a time: the validation error count is process-wide.
+static HARNESS_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct HarnessReport {
+    pub frames: u32,
+    pub validation_errors: u32,
+    // Last frame, None if the surface doesn't support swapchain image readback.
+    pub snapshot: Option<ImageReadback>,
+}
+
+// Test threads aren't the main thread, which winit only allows explicitly. On macOS the event
+// loop has to be created on the main thread: run the tests with --test-threads=1.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn create_event_loop() -> EventLoop<()> {
+    use winit::platform::unix::EventLoopExtUnix;
+    EventLoop::new_any_thread()
+}
+
+#[cfg(windows)]
+fn create_event_loop() -> EventLoop<()> {
+    use winit::platform::windows::EventLoopExtWindows;
+    EventLoop::new_any_thread()
+}
+
+#[cfg(target_os = "macos")]
+fn create_event_loop() -> EventLoop<()> {
+    EventLoop::new()
+}
+
+// Runs `frame_count` frames with the validation layer enabled, whatever SOL_VALIDATION is, and
+// reads back the last one.
+pub fn run_frames<T: 'static>(builder: AppBuilder<T>, frame_count: u32) -> HarnessReport {
+    assert!(frame_count > 0);
+    let _lock = HARNESS_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut event_loop = create_event_loop();
+    let mut settings = builder
+        .prepare
+        .map_or_else(AppSettings::default, |prepare| prepare());
+    settings.render.validation.layers = ValidationMode::Forced;
+    settings.capture_on_validation_error = false;
+
+    let errors_before = validation_error_count();
+    let mut app = App::new(settings, &event_loop);
+    builder.open_windows(&mut app, &event_loop);
+    let mut app_data = (builder.setup)(&mut app);
+    let mut frames = 0;
+    let mut snapshot = None;
+    let mut dirty_swapchain = false;
+    event_loop.run_return(|event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        if let Event::MainEventsCleared = event {
+            if dirty_swapchain {
+                app.recreate_swapchain();
+                dirty_swapchain = false;
+            }
+            if frames + 1 == frame_count {
+                app.renderer.capture_next_frame();
+            }
+            // Frames are timed as if they ran at the fixed timestep, 60 Hz by default, so that
+            // animations and simulations are deterministic.
+            let step = app
+                .settings
+                .fixed_timestep
+                .unwrap_or_else(|| Duration::from_secs_f64(1.0 / 60.0));
+            let time = FrameTime {
+                elapsed: step * (frames + 1),
+                delta: step,
+            };
+            app.elapsed_time = time.elapsed;
+            app.delta_time = time.delta;
+            let outcome = builder.run_frame(&mut app, &mut app_data, vec![time]);
+            dirty_swapchain = outcome.dirty_swapchain;
+            if outcome.captured_frame.is_some() {
+                snapshot = outcome.captured_frame;
+            }
+            frames += 1;
+            if frames == frame_count {
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+    });
+    app.renderer.context.device_wait_idle().unwrap();
+    // The app data holds resources of the app context.
+    drop(app_data);
+    drop(app);
+
+    HarnessReport {
+        frames,
+        validation_errors: validation_error_count() - errors_before,
+        snapshot,
+    }
+}
@@ -1,14 +1,217 @@
-use crate::{Buffer, BufferInfo, Context, Resource, SharedContext};
+use crate::{
+    format_features_for_usage, legacy_access, legacy_stage, Buffer, BufferInfo, CompressedImage,
+    Context, Error, GpuPhase, ImageBarrier2, Resource, Result, SharedContext,
+};
 use ash::{vk};
 use image::GenericImageView;
-use std::{cmp::max, sync::Arc};
-use std::{path::PathBuf, ptr};
+use std::{cmp::max, collections::HashMap, ops::Range, sync::Arc};
+use std::path::PathBuf;
 use gpu_allocator::{MemoryLocation, vulkan::{Allocation, AllocationCreateDesc}};
 
 //TODO: image resource trait
 
-fn has_stencil_component(format: vk::Format) -> bool {
-    format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+// Aspects of the views of depth attachments, combined formats include the stencil.
+pub fn depth_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match has_stencil_component(format) {
+        true => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        false => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+// Formats encoding the colors with the sRGB transfer function on writes, so shaders output
+// linear colors.
+pub fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+fn is_depth_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+// Size in bytes of a texel for the formats supported by read_to_cpu.
+fn texel_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::R8_UNORM => Some(1),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R32_SFLOAT
+        | vk::Format::D32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// Pixels copied back from an Image2d, rows tightly packed (row_pitch = width * texel size).
+pub struct ImageReadback {
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub row_pitch: usize,
+    pub data: Vec<u8>,
+}
+
+impl ImageReadback {
+    // Converts to 8-bit RGBA, swizzling BGRA and clamping float formats. None for other formats.
+    pub fn to_rgba_image(&self) -> Option<image::RgbaImage> {
+        let texel = texel_size(self.format)? as usize;
+        let floats = |bytes: &[u8]| -> Vec<f32> {
+            bytes
+                .chunks_exact(4)
+                .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect()
+        };
+        let halves = |bytes: &[u8]| -> Vec<f32> {
+            bytes
+                .chunks_exact(2)
+                .map(|c| f16_to_f32(u16::from_ne_bytes([c[0], c[1]])))
+                .collect()
+        };
+        let to_u8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for row in self.data.chunks_exact(self.row_pitch) {
+            for t in row[..self.width as usize * texel].chunks_exact(texel) {
+                let rgba = match self.format {
+                    vk::Format::R8_UNORM => [t[0], t[0], t[0], 255],
+                    vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => {
+                        [t[0], t[1], t[2], t[3]]
+                    }
+                    vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+                        [t[2], t[1], t[0], t[3]]
+                    }
+                    vk::Format::R32_SFLOAT | vk::Format::D32_SFLOAT => {
+                        let v = to_u8(floats(t)[0]);
+                        [v, v, v, 255]
+                    }
+                    vk::Format::R16G16B16A16_SFLOAT => {
+                        let v = halves(t);
+                        [to_u8(v[0]), to_u8(v[1]), to_u8(v[2]), to_u8(v[3])]
+                    }
+                    vk::Format::R32G32B32A32_SFLOAT => {
+                        let v = floats(t);
+                        [to_u8(v[0]), to_u8(v[1]), to_u8(v[2]), to_u8(v[3])]
+                    }
+                    _ => return None,
+                };
+                pixels.extend_from_slice(&rgba);
+            }
+        }
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self.format,
+            vk::Format::R32_SFLOAT
+                | vk::Format::D32_SFLOAT
+                | vk::Format::R16G16B16A16_SFLOAT
+                | vk::Format::R32G32B32A32_SFLOAT
+        )
+    }
+
+    // Unclamped float pixels of float formats, e.g. HDR accumulation targets. None for others.
+    pub fn to_rgba32f_image(&self) -> Option<image::Rgba32FImage> {
+        let texel = texel_size(self.format)? as usize;
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for row in self.data.chunks_exact(self.row_pitch) {
+            for t in row[..self.width as usize * texel].chunks_exact(texel) {
+                let f32s = t
+                    .chunks_exact(4)
+                    .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]));
+                match self.format {
+                    vk::Format::R32_SFLOAT | vk::Format::D32_SFLOAT => {
+                        let v = f32::from_ne_bytes([t[0], t[1], t[2], t[3]]);
+                        pixels.extend_from_slice(&[v, v, v, 1.0]);
+                    }
+                    vk::Format::R16G16B16A16_SFLOAT => pixels.extend(
+                        t.chunks_exact(2)
+                            .map(|c| f16_to_f32(u16::from_ne_bytes([c[0], c[1]]))),
+                    ),
+                    vk::Format::R32G32B32A32_SFLOAT => pixels.extend(f32s),
+                    _ => return None,
+                }
+            }
+        }
+        image::Rgba32FImage::from_raw(self.width, self.height, pixels)
+    }
+
+    // The format follows the extension of `path`: float formats can be saved to .exr.
+    pub fn save(&self, path: &std::path::Path) -> image::ImageResult<()> {
+        let is_exr = path.extension().is_some_and(|ext| ext == "exr");
+        let saved = match is_exr {
+            true => self.to_rgba32f_image().map(|image| image.save(path)),
+            false => self.to_rgba_image().map(|image| image.save(path)),
+        };
+        match saved {
+            Some(result) => result,
+            None => Err(image::ImageError::Unsupported(
+                image::error::UnsupportedError::from_format_and_kind(
+                    image::error::ImageFormatHint::Unknown,
+                    image::error::UnsupportedErrorKind::GenericFeature(format!(
+                        "readback of {:?}",
+                        self.format
+                    )),
+                ),
+            )),
+        }
+    }
+}
+
+// Image copy recorded by Image2d::cmd_readback, not yet executed.
+pub struct PendingReadback {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+    row_pitch: usize,
+}
+
+impl PendingReadback {
+    // The command buffer of the copy must have completed execution.
+    pub fn finish(self) -> ImageReadback {
+        let size = self.row_pitch * self.height as usize;
+        let data = unsafe { std::slice::from_raw_parts(self.buffer.map(), size).to_vec() };
+        ImageReadback {
+            width: self.width,
+            height: self.height,
+            format: self.format,
+            row_pitch: self.row_pitch,
+            data,
+        }
+    }
 }
 
 fn check_mipmap_support(context: &Arc<SharedContext>, image_format: vk::Format) -> bool {
@@ -23,75 +226,174 @@ fn check_mipmap_support(context: &Arc<SharedContext>, image_format: vk::Format)
         .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
 }
 
+// View type matching the image: 3D images, 2D arrays or cube maps (CUBE_COMPATIBLE images with a
+// multiple of 6 layers) and single layer 2D images.
+fn view_type_for(image_info: &vk::ImageCreateInfo) -> vk::ImageViewType {
+    let layers = image_info.array_layers;
+    let cube = image_info
+        .flags
+        .contains(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+        && layers.is_multiple_of(6);
+    match image_info.image_type {
+        vk::ImageType::TYPE_3D => vk::ImageViewType::TYPE_3D,
+        _ if cube && layers == 6 => vk::ImageViewType::CUBE,
+        _ if cube => vk::ImageViewType::CUBE_ARRAY,
+        _ if layers > 1 => vk::ImageViewType::TYPE_2D_ARRAY,
+        _ => vk::ImageViewType::TYPE_2D,
+    }
+}
+
+// Despite its name, also holds 3D images and layered 2D images (arrays and cube maps), see
+// Image2d::new. Transitions and uploads cover all the layers; blits, resolves and readbacks
+// only the first layer or depth slice.
 pub struct Image2d {
     context: Arc<SharedContext>,
     image: vk::Image,
     extent: vk::Extent3D,
+    level_count: u32,
+    layer_count: u32,
+    aspect_mask: vk::ImageAspectFlags,
+    view_type: vk::ImageViewType,
     view: vk::ImageView,
+    // Single layer views of layered attachments, e.g. a shadow map cascade to render to.
+    layer_views: Vec<vk::ImageView>,
+    // Views of create_view, keyed by (base mip, mip count, base layer, layer count).
+    subresource_views: HashMap<(u32, u32, u32, u32), vk::ImageView>,
     layout: vk::ImageLayout,
     format: vk::Format,
     allocation: Option<Allocation>,
+    name: String,
 }
 
 impl Image2d {
+    // The view type follows the image type and layer count of `image_info`, e.g. TYPE_3D for
+    // volume textures and TYPE_2D_ARRAY for layered images.
     pub fn new(
         context: Arc<SharedContext>,
         image_info: &vk::ImageCreateInfo,
         aspect_mask: vk::ImageAspectFlags,
         level_count: u32,
         name: &str
-    ) -> Self {
+    ) -> Result<Self> {
         unsafe {
             assert!(image_info.extent.width + image_info.extent.height > 2);
+            let required_features = format_features_for_usage(image_info.usage);
+            if !context.format_supports(image_info.format, image_info.tiling, required_features) {
+                return Err(Error::UnsupportedFormat {
+                    name: format!("Image {}", name),
+                    format: image_info.format,
+                    features: required_features,
+                });
+            }
 
             // Create image
-            let image = context.device().create_image(&image_info, None).unwrap();
+            let image = context.device().create_image(image_info, None)?;
 
             // Allocate and bind memory to image
             let requirements = context.device().get_image_memory_requirements(image);
-            let alloc = context.allocator()
-                .lock()
-                .unwrap()
-                .allocate(&AllocationCreateDesc {
+            let alloc = context
+                .allocate_memory(&AllocationCreateDesc {
                     name,
                     requirements,
                     location: MemoryLocation::GpuOnly,
                     linear: false,
                 })
-                .unwrap();
-            
-            context.device().bind_image_memory(image, alloc.memory(), alloc.offset())
-                .unwrap();
+                .map_err(|error| {
+                    context.device().destroy_image(image, None);
+                    Error::from(error)
+                })?;
+
+            if let Err(error) =
+                context
+                    .device()
+                    .bind_image_memory(image, alloc.memory(), alloc.offset())
+            {
+                context.device().destroy_image(image, None);
+                let _ = context.free_memory(alloc);
+                return Err(error.into());
+            }
 
+            Self::with_bound_memory(
+                context,
+                image,
+                image_info,
+                aspect_mask,
+                level_count,
+                name,
+                Some(alloc),
+            )
+        }
+    }
+
+    // Views and state of `image`, whose memory is bound. Destroyed on drop along with
+    // `allocation`, unless None: the image then belongs to the caller, e.g. a
+    // TransientImagePool.
+    pub(crate) fn with_bound_memory(
+        context: Arc<SharedContext>,
+        image: vk::Image,
+        image_info: &vk::ImageCreateInfo,
+        aspect_mask: vk::ImageAspectFlags,
+        level_count: u32,
+        name: &str,
+        allocation: Option<Allocation>,
+    ) -> Result<Self> {
+        unsafe {
+            let view_type = view_type_for(image_info);
             let subresource_range = vk::ImageSubresourceRange::builder()
                 .aspect_mask(aspect_mask)
                 .level_count(level_count)
-                .layer_count(1)
+                .layer_count(image_info.array_layers)
                 .build();
             let image_view_info = vk::ImageViewCreateInfo::builder()
-                .view_type(vk::ImageViewType::TYPE_2D)
+                .view_type(view_type)
                 .subresource_range(subresource_range)
                 .image(image)
                 .format(image_info.format);
 
             let image_view = context
                 .device()
-                .create_image_view(&image_view_info, None)
-                .unwrap();
+                .create_image_view(&image_view_info, None)?;
+            context.debug_marker().set_name(image, name);
+            context.debug_marker().set_name(image_view, name);
 
-            Image2d {
+            let attachment_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+            let mut layer_views = Vec::new();
+            if image_info.array_layers > 1 && image_info.usage.intersects(attachment_usage) {
+                for layer in 0..image_info.array_layers {
+                    let layer_view_info = vk::ImageViewCreateInfo::builder()
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            base_array_layer: layer,
+                            layer_count: 1,
+                            ..subresource_range
+                        })
+                        .image(image)
+                        .format(image_info.format);
+                    let layer_view = context.device().create_image_view(&layer_view_info, None)?;
+                    context
+                        .debug_marker()
+                        .set_name(layer_view, &format!("{}[{}]", name, layer));
+                    layer_views.push(layer_view);
+                }
+            }
+
+            Ok(Image2d {
                 context,
                 image,
-                extent: vk::Extent3D {
-                    width: image_info.extent.width,
-                    height: image_info.extent.height,
-                    depth: 1,
-                },
+                extent: image_info.extent,
+                level_count,
+                layer_count: image_info.array_layers,
+                aspect_mask,
+                view_type,
                 view: image_view,
+                layer_views,
+                subresource_views: HashMap::new(),
                 format: image_info.format,
-                allocation: Some(alloc),
+                allocation,
                 layout: vk::ImageLayout::UNDEFINED,
-            }
+                name: name.to_string(),
+            })
         }
     }
 
@@ -132,10 +434,17 @@ impl Image2d {
                     height: extent.height,
                     depth: 1,
                 },
+                level_count: 1,
+                layer_count: 1,
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                view_type: vk::ImageViewType::TYPE_2D,
                 view: image_view,
+                layer_views: Vec::new(),
+                subresource_views: HashMap::new(),
                 format: image_format,
                 allocation: None,
                 layout: vk::ImageLayout::UNDEFINED,
+                name: "SwapchainImage".to_string(),
             }
         }
     }
@@ -144,10 +453,104 @@ impl Image2d {
         self.view
     }
 
+    // View of a single layer of a layered attachment, e.g. for the framebuffer of a cascade.
+    pub fn get_layer_view(&self, layer: u32) -> vk::ImageView {
+        match self.layer_count {
+            1 => self.view,
+            _ => self.layer_views[layer as usize],
+        }
+    }
+
+    // View of a subset of the mips and layers, e.g. to write a single mip of a downsample chain
+    // from a compute pass. Views are cached and destroyed with the image. Single layer ranges of
+    // 2D images are viewed as TYPE_2D, other ranges keep the array or 3D view type.
+    pub fn create_view(&mut self, mip_range: Range<u32>, layer_range: Range<u32>) -> vk::ImageView {
+        assert!(!mip_range.is_empty() && mip_range.end <= self.level_count);
+        assert!(!layer_range.is_empty() && layer_range.end <= self.layer_count);
+        let key = (
+            mip_range.start,
+            mip_range.len() as u32,
+            layer_range.start,
+            layer_range.len() as u32,
+        );
+        if let Some(&view) = self.subresource_views.get(&key) {
+            return view;
+        }
+        let view_type = match self.view_type {
+            vk::ImageViewType::TYPE_3D => vk::ImageViewType::TYPE_3D,
+            _ if layer_range.len() == 1 => vk::ImageViewType::TYPE_2D,
+            _ if layer_range.len() == self.layer_count as usize => self.view_type,
+            _ => vk::ImageViewType::TYPE_2D_ARRAY,
+        };
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .view_type(view_type)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: self.aspect_mask,
+                base_mip_level: key.0,
+                level_count: key.1,
+                base_array_layer: key.2,
+                layer_count: key.3,
+            })
+            .image(self.image)
+            .format(self.format);
+        let view = unsafe {
+            self.context
+                .device()
+                .create_image_view(&view_info, None)
+                .expect("Unable to create image view")
+        };
+        let name = format!(
+            "{} mips {:?} layers {:?}",
+            self.name, mip_range, layer_range
+        );
+        self.context.debug_marker().set_name(view, &name);
+        self.subresource_views.insert(key, view);
+        view
+    }
+
     pub fn get_format(&self) -> vk::Format {
         self.format
     }
 
+    pub fn get_level_count(&self) -> u32 {
+        self.level_count
+    }
+
+    pub fn get_layer_count(&self) -> u32 {
+        self.layer_count
+    }
+
+    pub fn get_view_type(&self) -> vk::ImageViewType {
+        self.view_type
+    }
+
+    // Depth is 1 except for 3D images.
+    pub fn get_extent_3d(&self) -> vk::Extent3D {
+        self.extent
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.extent.width,
+            height: self.extent.height,
+        }
+    }
+
+    pub fn get_layout(&self) -> vk::ImageLayout {
+        self.layout
+    }
+
+    // Records a layout change made outside of Image2d, e.g. by a render pass final layout.
+    pub fn set_layout(&mut self, layout: vk::ImageLayout) {
+        self.layout = layout;
+        self.context
+            .track_hazards(|tracker| tracker.set_layout(self.image, &self.name, layout));
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
     pub fn transition_image_layout(
         &mut self,
         cmd: vk::CommandBuffer,
@@ -165,100 +568,59 @@ impl Image2d {
         mip_levels: u32,
     ) {
         if old == new {
+            self.context
+                .track_hazards(|tracker| tracker.expect_layout(self.image, &self.name, old));
             self.layout = new;
             return;
         }
         let mut aspect_mask = vk::ImageAspectFlags::COLOR;
-        if new == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
+        if new == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL || is_depth_format(self.format) {
             aspect_mask = vk::ImageAspectFlags::DEPTH;
             if has_stencil_component(self.format) {
                 aspect_mask |= vk::ImageAspectFlags::STENCIL;
             }
         }
-        let src_access_mask = match old {
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL => vk::AccessFlags::TRANSFER_WRITE,
-            vk::ImageLayout::PREINITIALIZED => vk::AccessFlags::HOST_WRITE,
-            vk::ImageLayout::GENERAL => {
-                vk::AccessFlags::MEMORY_WRITE | vk::AccessFlags::SHADER_WRITE
-            }
-            _ => vk::AccessFlags::default(),
-        };
-        let dst_access_mask = match new {
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => {
-                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
-            }
-            vk::ImageLayout::GENERAL => vk::AccessFlags::empty(),
-            vk::ImageLayout::PRESENT_SRC_KHR => vk::AccessFlags::empty(),
-            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => vk::AccessFlags::SHADER_READ,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => vk::AccessFlags::TRANSFER_READ,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL => vk::AccessFlags::TRANSFER_WRITE,
-            _ => panic!("Unsupported layout transition!"),
-        };
-        let src_stage = match old {
-            vk::ImageLayout::GENERAL => vk::PipelineStageFlags::ALL_COMMANDS,
-            vk::ImageLayout::PREINITIALIZED => vk::PipelineStageFlags::HOST,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL => vk::PipelineStageFlags::TRANSFER,
-            vk::ImageLayout::UNDEFINED => vk::PipelineStageFlags::TOP_OF_PIPE,
-            _ => vk::PipelineStageFlags::ALL_COMMANDS,
-        };
-        let dst_stage = match new {
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => {
-                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
-            }
-            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => {
-                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
-            }
-            vk::ImageLayout::GENERAL => vk::PipelineStageFlags::HOST,
-            vk::ImageLayout::PRESENT_SRC_KHR => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => vk::PipelineStageFlags::FRAGMENT_SHADER,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => vk::PipelineStageFlags::TRANSFER,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL => vk::PipelineStageFlags::TRANSFER,
-            _ => vk::PipelineStageFlags::ALL_COMMANDS,
-        };
-
-        let layout_transition_barriers = vk::ImageMemoryBarrier::builder()
-            .image(self.image)
-            .src_access_mask(src_access_mask)
-            .dst_access_mask(dst_access_mask)
-            .new_layout(new)
-            .old_layout(old)
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(aspect_mask)
-                    .layer_count(1)
-                    .level_count(mip_levels)
-                    .build(),
-            );
-        unsafe {
-            self.context.device().cmd_pipeline_barrier(
-                cmd,
-                src_stage,
-                dst_stage,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[layout_transition_barriers.build()],
-            );
-        }
+        let barrier = ImageBarrier2::new(self.image, aspect_mask)
+            .layouts(old, new)
+            .mips(0, mip_levels)
+            .layers(0, self.layer_count);
+        self.context
+            .cmd_pipeline_barrier2(cmd, &[], &[], &[barrier]);
+        self.context.track_hazards(|tracker| {
+            tracker.barrier(
+                self.image,
+                &self.name,
+                legacy_stage(barrier.src_stage),
+                legacy_access(barrier.src_access),
+                legacy_stage(barrier.dst_stage),
+                legacy_access(barrier.dst_access),
+                Some((old, new)),
+            )
+        });
 
         self.layout = new;
     }
 
+    // Copies the first mip level of all the layers, tightly packed one after the other in
+    // `buffer`, as are the depth slices of 3D images.
     pub fn copy_to_image(&self, context: &Arc<Context>, buffer: vk::Buffer) {
+        let cmd = context.begin_single_time_cmd();
+        self.cmd_copy_to_image(cmd, buffer);
+        context.end_single_time_cmd(cmd);
+    }
+
+    pub fn cmd_copy_to_image(&self, cmd: vk::CommandBuffer, buffer: vk::Buffer) {
         let region = vk::BufferImageCopy::builder()
             .image_subresource(
                 vk::ImageSubresourceLayers::builder()
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .layer_count(1)
+                    .layer_count(self.layer_count)
                     .build(),
             )
             .image_extent(self.extent)
             .build();
-        let cmd = context.begin_single_time_cmd();
         unsafe {
-            context.device().cmd_copy_buffer_to_image(
+            self.context.device().cmd_copy_buffer_to_image(
                 cmd,
                 buffer,
                 self.image,
@@ -266,10 +628,15 @@ impl Image2d {
                 &[region],
             );
         }
-        context.end_single_time_cmd(cmd);
     }
 
+    // Nearest filtered, see FullscreenPass::blit for a filtered copy or a shader conversion.
+    // Blits to a swapchain image are timed as GpuPhase::PresentBlit.
     pub fn cmd_blit_to(&mut self, cmd: vk::CommandBuffer, dst: &mut Image2d, do_transitions: bool) {
+        let present_blit = dst.allocation.is_none();
+        if present_blit {
+            self.context.begin_gpu_phase(cmd, GpuPhase::PresentBlit);
+        }
         if do_transitions {
             dst.transition_image_layout(cmd, dst.layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
             self.transition_image_layout(cmd, self.layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
@@ -305,6 +672,75 @@ impl Image2d {
             ])
             .build();
 
+        self.context.track_hazards(|tracker| {
+            let (stage, src_layout, dst_layout) = (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            tracker.expect_layout(self.image, &self.name, src_layout);
+            tracker.read(self.image, &self.name, stage, vk::AccessFlags::TRANSFER_READ);
+            tracker.expect_layout(dst.image, &dst.name, dst_layout);
+            tracker.write(dst.image, &dst.name, stage, vk::AccessFlags::TRANSFER_WRITE);
+        });
+        unsafe {
+            self.context.device().cmd_blit_image(
+                cmd,
+                self.handle(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst.handle(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                vk::Filter::NEAREST,
+            );
+        }
+        if present_blit {
+            self.context.end_gpu_phase(cmd, GpuPhase::PresentBlit);
+        }
+    }
+
+    // Blits `area` of this image to the same area of `dst`, e.g. the band of a device with
+    // AppRenderer::get_device_regions. The images must already be in TRANSFER_SRC_OPTIMAL and
+    // TRANSFER_DST_OPTIMAL layouts.
+    pub fn cmd_blit_area_to(&self, cmd: vk::CommandBuffer, dst: &Image2d, area: vk::Rect2D) {
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .build();
+        let offsets = [
+            vk::Offset3D {
+                x: area.offset.x,
+                y: area.offset.y,
+                z: 0,
+            },
+            vk::Offset3D {
+                x: area.offset.x + area.extent.width as i32,
+                y: area.offset.y + area.extent.height as i32,
+                z: 1,
+            },
+        ];
+        let region = vk::ImageBlit::builder()
+            .src_subresource(subresource)
+            .src_offsets(offsets)
+            .dst_subresource(subresource)
+            .dst_offsets(offsets)
+            .build();
+        self.context.track_hazards(|tracker| {
+            let (stage, src_layout, dst_layout) = (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            tracker.expect_layout(self.image, &self.name, src_layout);
+            tracker.read(
+                self.image,
+                &self.name,
+                stage,
+                vk::AccessFlags::TRANSFER_READ,
+            );
+            tracker.expect_layout(dst.image, &dst.name, dst_layout);
+            tracker.write(dst.image, &dst.name, stage, vk::AccessFlags::TRANSFER_WRITE);
+        });
         unsafe {
             self.context.device().cmd_blit_image(
                 cmd,
@@ -318,77 +754,182 @@ impl Image2d {
         }
     }
 
+    // Resolves this multisampled image into the single sampled `dst`, of the same extent and
+    // format, e.g. from a pass of its own rather than a render pass resolve attachment.
+    pub fn cmd_resolve_to(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        dst: &mut Image2d,
+        do_transitions: bool,
+    ) {
+        if do_transitions {
+            dst.transition_image_layout(cmd, dst.layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+            self.transition_image_layout(cmd, self.layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        }
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .build();
+        let region = vk::ImageResolve::builder()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(self.extent)
+            .build();
+
+        self.context.track_hazards(|tracker| {
+            let (stage, src_layout, dst_layout) = (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            tracker.expect_layout(self.image, &self.name, src_layout);
+            tracker.read(self.image, &self.name, stage, vk::AccessFlags::TRANSFER_READ);
+            tracker.expect_layout(dst.image, &dst.name, dst_layout);
+            tracker.write(dst.image, &dst.name, stage, vk::AccessFlags::TRANSFER_WRITE);
+        });
+        unsafe {
+            self.context.device().cmd_resolve_image(
+                cmd,
+                self.handle(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst.handle(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+        }
+    }
+
+    // Copies the first mip level to host memory and restores the current layout. The image needs
+    // TRANSFER_SRC usage. Blocks until the copy has completed.
+    pub fn read_to_cpu(&mut self, context: &Arc<Context>) -> ImageReadback {
+        let cmd = context.begin_single_time_cmd();
+        let readback = self.cmd_readback(context, cmd);
+        context.end_single_time_cmd(cmd);
+        readback.finish()
+    }
+
+    // Records the copy of read_to_cpu in `cmd`, e.g. a frame command buffer. The readback can be
+    // finished once `cmd` has completed execution.
+    pub fn cmd_readback(
+        &mut self,
+        context: &Arc<Context>,
+        cmd: vk::CommandBuffer,
+    ) -> PendingReadback {
+        let texel = texel_size(self.format)
+            .unwrap_or_else(|| panic!("Readback of {:?} images is not supported.", self.format));
+        let row_pitch = (self.extent.width * texel) as usize;
+        let size = row_pitch * self.extent.height as usize;
+        let buffer = Buffer::new(
+            context.clone(),
+            BufferInfo::default()
+                .name("ImageReadback")
+                .gpu_to_cpu()
+                .usage_transfer_dst(),
+            size as vk::DeviceSize,
+            1,
+        )
+        .expect("Unable to create readback buffer");
+        let aspect_mask = if is_depth_format(self.format) {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        };
+        // Zero row length and height: rows are tightly packed in the buffer.
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(aspect_mask)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_extent(vk::Extent3D {
+                depth: 1,
+                ..self.extent
+            })
+            .build();
+
+        let previous_layout = self.layout;
+        self.transition_image_layout(cmd, previous_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        unsafe {
+            context.device().cmd_copy_image_to_buffer(
+                cmd,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.handle(),
+                &[region],
+            );
+        }
+        if previous_layout != vk::ImageLayout::UNDEFINED {
+            self.transition_image_layout(cmd, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, previous_layout);
+        }
+        PendingReadback {
+            buffer,
+            width: self.extent.width,
+            height: self.extent.height,
+            format: self.format,
+            row_pitch,
+        }
+    }
+
+    // Blocking blits on the graphics queue, see MipmapGenerator for a compute alternative.
     pub fn generate_mipmaps(&self, context: &Arc<Context>, mip_levels: u32) {
         let command_buffer = context.begin_single_time_cmd();
 
-        let mut image_barrier = vk::ImageMemoryBarrier {
-            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-            p_next: ptr::null(),
-            src_access_mask: vk::AccessFlags::empty(),
-            dst_access_mask: vk::AccessFlags::empty(),
-            old_layout: vk::ImageLayout::UNDEFINED,
-            new_layout: vk::ImageLayout::UNDEFINED,
-            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            image: self.image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
+        let mip_barrier = |mip_level: u32| {
+            ImageBarrier2::new(self.image, vk::ImageAspectFlags::COLOR)
+                .mips(mip_level, 1)
+                .layers(0, self.layer_count)
         };
+        // Levels are written by the upload copy (the first one) or the previous blit.
+        let written = (
+            vk::PipelineStageFlags2::COPY | vk::PipelineStageFlags2::BLIT,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
 
         let mut mip_width = self.extent.width as i32;
         let mut mip_height = self.extent.height as i32;
+        let mut mip_depth = self.extent.depth as i32;
 
         for i in 1..mip_levels {
-            image_barrier.subresource_range.base_mip_level = i - 1;
-            image_barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-            image_barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-            image_barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-            image_barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
-
-            unsafe {
-                context.device().cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[image_barrier.clone()],
+            let barrier = mip_barrier(i - 1)
+                .layouts(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                )
+                .src(written.0, written.1)
+                .dst(
+                    vk::PipelineStageFlags2::BLIT,
+                    vk::AccessFlags2::TRANSFER_READ,
                 );
-            }
+            context.cmd_pipeline_barrier2(command_buffer, &[], &[], &[barrier]);
 
             let blits = [vk::ImageBlit {
                 src_subresource: vk::ImageSubresourceLayers {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     mip_level: i - 1,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count: self.layer_count,
                 },
                 src_offsets: [
                     vk::Offset3D { x: 0, y: 0, z: 0 },
                     vk::Offset3D {
                         x: mip_width,
                         y: mip_height,
-                        z: 1,
+                        z: mip_depth,
                     },
                 ],
                 dst_subresource: vk::ImageSubresourceLayers {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     mip_level: i,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count: self.layer_count,
                 },
                 dst_offsets: [
                     vk::Offset3D { x: 0, y: 0, z: 0 },
                     vk::Offset3D {
                         x: max(mip_width / 2, 1),
                         y: max(mip_height / 2, 1),
-                        z: 1,
+                        z: max(mip_depth / 2, 1),
                     },
                 ],
             }];
@@ -405,44 +946,29 @@ impl Image2d {
                 );
             }
 
-            image_barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-            image_barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-            image_barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
-            image_barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-            unsafe {
-                self.context.device().cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::PipelineStageFlags::FRAGMENT_SHADER,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[image_barrier.clone()],
+            let barrier = mip_barrier(i - 1)
+                .layouts(
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+                .src(
+                    vk::PipelineStageFlags2::BLIT,
+                    vk::AccessFlags2::TRANSFER_READ,
                 );
-            }
+            context.cmd_pipeline_barrier2(command_buffer, &[], &[], &[barrier]);
 
             mip_width = max(mip_width / 2, 1);
             mip_height = max(mip_height / 2, 1);
+            mip_depth = max(mip_depth / 2, 1);
         }
 
-        image_barrier.subresource_range.base_mip_level = mip_levels - 1;
-        image_barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-        image_barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-        image_barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-        image_barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-        unsafe {
-            context.device().cmd_pipeline_barrier(
-                command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[image_barrier.clone()],
-            );
-        }
+        let barrier = mip_barrier(mip_levels - 1)
+            .layouts(
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+            .src(written.0, written.1);
+        context.cmd_pipeline_barrier2(command_buffer, &[], &[], &[barrier]);
 
         context.end_single_time_cmd(command_buffer);
     }
@@ -454,6 +980,19 @@ impl Image2d {
             .image_layout(self.layout)
             .build()
     }
+
+    // Descriptor of a view of create_view, e.g. a storage image in the GENERAL layout.
+    pub fn get_view_descriptor_info(
+        &self,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo::builder()
+            .sampler(vk::Sampler::null())
+            .image_view(view)
+            .image_layout(layout)
+            .build()
+    }
 }
 
 impl Resource<vk::Image> for Image2d {
@@ -466,18 +1005,152 @@ impl Drop for Image2d {
     fn drop(&mut self) {
         unsafe {
             self.context.device().destroy_image_view(self.view, None);
+            self.context.retire_handle(self.view);
+            let views = self
+                .layer_views
+                .iter()
+                .chain(self.subresource_views.values());
+            for &view in views {
+                self.context.device().destroy_image_view(view, None);
+                self.context.retire_handle(view);
+            }
             if self.allocation.is_some() {
                 self.context.device().destroy_image(self.image, None);
                 let to_drop = self.allocation.take().unwrap();
-                self.context.allocator()
-                    .lock()
-                    .unwrap()
-                    .free(to_drop).unwrap();
+                self.context.free_memory(to_drop).unwrap();
             }
         }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerInfo {
+    pub min_filter: vk::Filter,
+    pub mag_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    pub max_anisotropy: Option<f32>,
+    pub compare_op: Option<vk::CompareOp>,
+    pub border_color: vk::BorderColor,
+    pub mip_lod_bias: f32,
+    pub max_lod: Option<f32>,
+}
+
+impl Default for SamplerInfo {
+    fn default() -> Self {
+        SamplerInfo {
+            min_filter: vk::Filter::LINEAR,
+            mag_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: Some(16.0),
+            compare_op: None,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_BLACK,
+            mip_lod_bias: 0.0,
+            max_lod: None,
+        }
+    }
+}
+
+impl SamplerInfo {
+    pub fn filter(mut self, filter: vk::Filter) -> Self {
+        self.min_filter = filter;
+        self.mag_filter = filter;
+        self
+    }
+    pub fn min_filter(mut self, filter: vk::Filter) -> Self {
+        self.min_filter = filter;
+        self
+    }
+    pub fn mag_filter(mut self, filter: vk::Filter) -> Self {
+        self.mag_filter = filter;
+        self
+    }
+    pub fn mipmap_mode(mut self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+        self.mipmap_mode = mipmap_mode;
+        self
+    }
+    pub fn address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode_u = address_mode;
+        self.address_mode_v = address_mode;
+        self.address_mode_w = address_mode;
+        self
+    }
+    pub fn address_mode_u(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode_u = address_mode;
+        self
+    }
+    pub fn address_mode_v(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode_v = address_mode;
+        self
+    }
+    pub fn address_mode_w(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode_w = address_mode;
+        self
+    }
+    pub fn clamp_to_edge(self) -> Self {
+        self.address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+    }
+    pub fn anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.max_anisotropy = Some(max_anisotropy);
+        self
+    }
+    pub fn no_anisotropy(mut self) -> Self {
+        self.max_anisotropy = None;
+        self
+    }
+    pub fn compare_op(mut self, compare_op: vk::CompareOp) -> Self {
+        self.compare_op = Some(compare_op);
+        self
+    }
+    pub fn border_color(mut self, border_color: vk::BorderColor) -> Self {
+        self.border_color = border_color;
+        self
+    }
+    pub fn mip_bias(mut self, mip_lod_bias: f32) -> Self {
+        self.mip_lod_bias = mip_lod_bias;
+        self
+    }
+    pub fn max_lod(mut self, max_lod: f32) -> Self {
+        self.max_lod = Some(max_lod);
+        self
+    }
+
+    // Anisotropy is clamped to the device limit; max_lod defaults to the full mip chain.
+    pub fn create_sampler(&self, context: &Arc<Context>, mip_levels: u32) -> vk::Sampler {
+        let device_max_anisotropy = context.limits().max_sampler_anisotropy;
+        let max_anisotropy = self.max_anisotropy.unwrap_or(1.0).min(device_max_anisotropy);
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .min_filter(self.min_filter)
+            .mag_filter(self.mag_filter)
+            .address_mode_u(self.address_mode_u)
+            .address_mode_v(self.address_mode_v)
+            .address_mode_w(self.address_mode_w)
+            .border_color(self.border_color)
+            .anisotropy_enable(
+                self.max_anisotropy.is_some() && context.capabilities().sampler_anisotropy,
+            )
+            .max_anisotropy(max_anisotropy)
+            .mipmap_mode(self.mipmap_mode)
+            .mip_lod_bias(self.mip_lod_bias)
+            .min_lod(0.0)
+            .max_lod(self.max_lod.unwrap_or(mip_levels as f32))
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .unnormalized_coordinates(false);
+        unsafe {
+            context
+                .device()
+                .create_sampler(&sampler_create_info, None)
+                .unwrap()
+        }
+    }
+}
+
 pub struct Texture2d {
     context: Arc<Context>,
     image2d: Image2d,
@@ -485,12 +1158,40 @@ pub struct Texture2d {
 }
 
 impl Texture2d {
-    pub fn new(context: Arc<Context>, filepath: PathBuf) -> Self {
-        let filename = filepath.clone().into_os_string().into_string().unwrap();
-        let mut source_image = image::open(filepath).expect("Failed to find image."); // this function is slow in debug mode.
+    pub fn new(context: Arc<Context>, filepath: PathBuf) -> Result<Self> {
+        Self::with_sampler(context, filepath, SamplerInfo::default())
+    }
+
+    // KTX2 and DDS files are uploaded as is, see from_compressed.
+    pub fn with_sampler(
+        context: Arc<Context>,
+        filepath: PathBuf,
+        sampler_info: SamplerInfo,
+    ) -> Result<Self> {
+        if CompressedImage::is_compressed_path(&filepath) {
+            let image = CompressedImage::open(&filepath)?;
+            return Self::from_compressed(context, &image, sampler_info);
+        }
+        let filename = filepath.to_string_lossy().into_owned();
+        let mut source_image = image::open(&filepath).map_err(|e| match e {
+            image::ImageError::IoError(source) => Error::io(&filepath, source),
+            e => Error::Image(e),
+        })?; // this function is slow in debug mode.
         source_image = source_image.flipv();
         let size = source_image.dimensions();
         let image_data = source_image.to_rgba8().into_raw();
+        Self::from_rgba8(context, &filename, size, &image_data, sampler_info)
+    }
+
+    // Rows of `image_data` are in upload order, unlike Texture2d::new no flip is applied.
+    pub fn from_rgba8(
+        context: Arc<Context>,
+        name: &str,
+        size: (u32, u32),
+        image_data: &[u8],
+        sampler_info: SamplerInfo,
+    ) -> Result<Self> {
+        assert_eq!(image_data.len(), (size.0 * size.1 * 4) as usize);
         let mip_levels = (max(size.0, size.1) as f32).log2().floor() as u32 + 1;
 
         let format = vk::Format::R8G8B8A8_UNORM;
@@ -517,8 +1218,8 @@ impl Texture2d {
             &image_info,
             vk::ImageAspectFlags::COLOR,
             mip_levels,
-            &filename
-        );
+            name
+        )?;
 
         {
             //Load data via temporary transfer buffer
@@ -527,18 +1228,18 @@ impl Texture2d {
                 BufferInfo::default()
                     .usage(vk::BufferUsageFlags::TRANSFER_SRC)
                     .cpu_to_gpu(),
-                &image_data,
-            );
-            let cmd = context.begin_single_time_cmd();
+                image_data,
+            )?;
+            let mut batch = context.begin_batch();
+            let cmd = batch.begin_cmd();
             image2d.transition_image_layout_mip(
                 cmd,
                 vk::ImageLayout::UNDEFINED,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 mip_levels,
             );
-            context.end_single_time_cmd(cmd);
-
-            image2d.copy_to_image(&context, transfer_buffer.handle());
+            image2d.cmd_copy_to_image(cmd, transfer_buffer.handle());
+            batch.submit_and_wait();
             if mip_levels > 1 && check_mipmap_support(&context.shared(), image2d.get_format()) {
                 image2d.generate_mipmaps(&context, mip_levels);
                 let cmd = context.begin_single_time_cmd();
@@ -561,34 +1262,180 @@ impl Texture2d {
             }
         }
 
-        let sampler_create_info = vk::SamplerCreateInfo::builder()
-            .min_filter(vk::Filter::LINEAR)
-            .mag_filter(vk::Filter::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::REPEAT)
-            .address_mode_w(vk::SamplerAddressMode::REPEAT)
-            .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK)
-            .anisotropy_enable(true)
-            .max_anisotropy(16.0)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .min_lod(0.0)
-            .max_lod(mip_levels as f32)
-            .compare_enable(false)
-            .compare_op(vk::CompareOp::ALWAYS)
-            .unnormalized_coordinates(false);
-        let sampler: vk::Sampler;
-        unsafe {
-            sampler = context
-                .device()
-                .create_sampler(&sampler_create_info, None)
-                .unwrap();
-        }
+        let sampler = sampler_info.create_sampler(&context, mip_levels);
 
-        Texture2d {
+        Ok(Texture2d {
             context: context.clone(),
             image2d,
             sampler,
+        })
+    }
+
+    // Volume textures when extent.depth > 1 (e.g. color grading LUTs), otherwise 2D textures
+    // with `layers` layers (e.g. material layers), sampled as sampler3D or sampler2DArray.
+    // `data` holds the tightly packed depth slices or layers, a single mip level is uploaded.
+    pub fn from_texels(
+        context: Arc<Context>,
+        name: &str,
+        format: vk::Format,
+        extent: vk::Extent3D,
+        layers: u32,
+        data: &[u8],
+        sampler_info: SamplerInfo,
+    ) -> Result<Self> {
+        assert!(
+            extent.depth == 1 || layers == 1,
+            "3D images can't have layers"
+        );
+        if let Some(texel) = texel_size(format) {
+            let texel_count = extent.width * extent.height * extent.depth * layers;
+            assert_eq!(data.len(), (texel_count * texel) as usize);
+        }
+        let image_type = match extent.depth {
+            1 => vk::ImageType::TYPE_2D,
+            _ => vk::ImageType::TYPE_3D,
+        };
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(image_type)
+            .format(format)
+            .extent(extent)
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let mut image2d = Image2d::new(
+            context.shared().clone(),
+            &image_info,
+            vk::ImageAspectFlags::COLOR,
+            1,
+            name,
+        )?;
+
+        let transfer_buffer = Buffer::from_data(
+            context.clone(),
+            BufferInfo::default()
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                .cpu_to_gpu(),
+            data,
+        )?;
+        let mut batch = context.begin_batch();
+        let cmd = batch.begin_cmd();
+        image2d.transition_image_layout(
+            cmd,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        image2d.cmd_copy_to_image(cmd, transfer_buffer.handle());
+        image2d.transition_image_layout(
+            cmd,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        batch.submit_and_wait();
+
+        let sampler = sampler_info.create_sampler(&context, 1);
+
+        Ok(Texture2d {
+            context,
+            image2d,
+            sampler,
+        })
+    }
+
+    // Uploads the levels of `image` without decoding, mips are never generated. Fails with
+    // Error::UnsupportedFormat when the device can't sample the format (e.g. BCn on mobile GPUs,
+    // ASTC on most desktop GPUs). Unlike Texture2d::new no flip is applied: KTX2 and DDS rows
+    // are stored top to bottom.
+    pub fn from_compressed(
+        context: Arc<Context>,
+        image: &CompressedImage,
+        sampler_info: SamplerInfo,
+    ) -> Result<Self> {
+        let mip_levels = image.levels.len() as u32;
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(image.format)
+            .extent(vk::Extent3D {
+                width: image.extent.width,
+                height: image.extent.height,
+                depth: 1,
+            })
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let mut image2d = Image2d::new(
+            context.shared().clone(),
+            &image_info,
+            vk::ImageAspectFlags::COLOR,
+            mip_levels,
+            &image.name,
+        )?;
+
+        let transfer_buffer = Buffer::from_data(
+            context.clone(),
+            BufferInfo::default()
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                .cpu_to_gpu(),
+            &image.data,
+        )?;
+        let regions = image
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(data.offset as vk::DeviceSize)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(level as u32)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .image_extent(vk::Extent3D {
+                        width: data.extent.width,
+                        height: data.extent.height,
+                        depth: 1,
+                    })
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let cmd = context.begin_single_time_cmd();
+        image2d.transition_image_layout_mip(
+            cmd,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            mip_levels,
+        );
+        unsafe {
+            context.device().cmd_copy_buffer_to_image(
+                cmd,
+                transfer_buffer.handle(),
+                image2d.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
         }
+        image2d.transition_image_layout_mip(
+            cmd,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            mip_levels,
+        );
+        context.end_single_time_cmd(cmd);
+
+        let sampler = sampler_info.create_sampler(&context, mip_levels);
+
+        Ok(Texture2d {
+            context,
+            image2d,
+            sampler,
+        })
     }
 
     pub fn get_image2d(&self) -> &Image2d {
@@ -613,5 +1460,6 @@ impl Drop for Texture2d {
         unsafe {
             self.context.device().destroy_sampler(self.sampler, None);
         }
+        self.context.shared().retire_handle(self.sampler);
     }
 }
@@ -0,0 +1,142 @@
+use crate::{align_up, Buffer, BufferInfo, Context, Resource};
+use ash::vk;
+use std::sync::Arc;
+
+// Short-lived region of a TransientBufferAllocator, valid until the frame it was allocated in
+// is recycled.
+#[derive(Clone, Copy, Debug)]
+pub struct TransientSlice {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    ptr: *mut u8,
+}
+
+impl TransientSlice {
+    pub fn get_descriptor_info(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo::builder()
+            .buffer(self.buffer)
+            .offset(self.offset)
+            .range(self.size)
+            .build()
+    }
+
+    pub fn write<T: Copy>(&self, data: &[T]) {
+        let size = std::mem::size_of_val(data);
+        assert!(size as vk::DeviceSize <= self.size);
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, self.ptr, size);
+        }
+    }
+
+    pub fn map(&self) -> *mut u8 {
+        self.ptr
+    }
+}
+
+// Persistently mapped ring split in one region per frame in flight. Allocations are bump
+// allocated in the region of the active frame and the whole region is recycled when the frame
+// fence has signaled (AppRenderer does this in acquire_next_image), so per-frame vertex, index
+// and uniform data never needs its own buffer.
+pub struct TransientBufferAllocator {
+    buffer: Buffer,
+    frame_size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+    frame_index: usize,
+    offset: vk::DeviceSize,
+}
+
+impl TransientBufferAllocator {
+    pub fn new(context: Arc<Context>, frame_size: vk::DeviceSize, frame_count: usize) -> Self {
+        let limits = context.limits();
+        let alignment = limits
+            .min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment)
+            .max(16);
+        let frame_size = align_up(frame_size, alignment);
+        let buffer = Buffer::new(
+            context,
+            BufferInfo::default()
+                .name("TransientBuffer")
+                .cpu_to_gpu()
+                .usage_vertex()
+                .usage_index()
+                .usage_uniform()
+                .usage_storage()
+                .usage_indirect()
+                .usage_transfer_src(),
+            frame_size * frame_count as vk::DeviceSize,
+            1,
+        )
+        .expect("Unable to create transient buffer");
+        TransientBufferAllocator {
+            buffer,
+            frame_size,
+            alignment,
+            frame_index: 0,
+            offset: 0,
+        }
+    }
+
+    // Recycles the region of `frame_index`. Its previous submission must have completed.
+    pub fn begin_frame(&mut self, frame_index: usize) {
+        self.frame_index = frame_index;
+        self.offset = 0;
+    }
+
+    pub fn allocate(&mut self, size: vk::DeviceSize) -> Option<TransientSlice> {
+        let offset = align_up(self.offset, self.alignment);
+        if offset + size > self.frame_size {
+            return None;
+        }
+        self.offset = offset + size;
+        let offset = self.frame_size * self.frame_index as vk::DeviceSize + offset;
+        Some(TransientSlice {
+            buffer: self.buffer.handle(),
+            offset,
+            size,
+            ptr: unsafe { self.buffer.map().add(offset as usize) },
+        })
+    }
+
+    // Allocates and fills a slice with `data`, panics if the frame region is exhausted.
+    pub fn push<T: Copy>(&mut self, data: &[T]) -> TransientSlice {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        let slice = self.allocate(size).unwrap_or_else(|| {
+            panic!(
+                "Transient buffer exhausted ({} bytes per frame), increase RendererSettings::transient_buffer_size.",
+                self.frame_size
+            )
+        });
+        slice.write(data);
+        slice
+    }
+
+    // Per-draw constants: a UNIFORM_BUFFER_DYNAMIC (or STORAGE_BUFFER_DYNAMIC) binding with this
+    // descriptor is written once, and each draw selects its constants with the dynamic offset
+    // returned by push_dynamic. `range` is the size of the constants.
+    pub fn get_dynamic_descriptor_info(&self, range: vk::DeviceSize) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo::builder()
+            .buffer(self.buffer.handle())
+            .offset(0)
+            .range(range)
+            .build()
+    }
+
+    // Allocates and fills the constants of a draw, returns their dynamic offset.
+    pub fn push_dynamic<T: Copy>(&mut self, data: &T) -> u32 {
+        self.push(std::slice::from_ref(data)).offset as u32
+    }
+
+    pub fn get_buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn get_used_size(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn get_frame_size(&self) -> vk::DeviceSize {
+        self.frame_size
+    }
+}
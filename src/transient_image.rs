@@ -0,0 +1,209 @@
+use crate::{Context, Image2d, ImageBarrier2, Resource, Result};
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc};
+use gpu_allocator::MemoryLocation;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+// Handle of an image declared to a TransientImagePool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransientImage(usize);
+
+struct Declaration {
+    name: String,
+    image_info: vk::ImageCreateInfo,
+    aspect_mask: vk::ImageAspectFlags,
+    passes: RangeInclusive<u32>,
+}
+
+// Memory shared by images whose pass ranges don't overlap.
+struct MemoryBlock {
+    requirements: vk::MemoryRequirements,
+    passes: Vec<RangeInclusive<u32>>,
+}
+
+// Frame-local images, e.g. post-processing intermediates, aliasing the same memory when their
+// lifetimes don't overlap. Each image is declared with the range of passes of the frame using
+// it, in recording order, then the pool is built. An image's contents are undefined at the start
+// of its range: its first pass begins with cmd_begin instead of a layout transition.
+pub struct TransientImagePool {
+    context: Arc<Context>,
+    declarations: Vec<Declaration>,
+    images: Vec<Image2d>,
+    handles: Vec<vk::Image>,
+    allocations: Vec<Allocation>,
+}
+
+impl TransientImagePool {
+    pub fn new(context: Arc<Context>) -> Self {
+        TransientImagePool {
+            context,
+            declarations: Vec::new(),
+            images: Vec::new(),
+            handles: Vec::new(),
+            allocations: Vec::new(),
+        }
+    }
+
+    // Image used from the first to the last pass of `passes`, both included. The pool must then
+    // be built again. Its memory is never host visible.
+    pub fn declare(
+        &mut self,
+        name: &str,
+        image_info: &vk::ImageCreateInfo,
+        aspect_mask: vk::ImageAspectFlags,
+        passes: RangeInclusive<u32>,
+    ) -> TransientImage {
+        assert!(image_info.p_next.is_null() && image_info.queue_family_index_count == 0);
+        self.declarations.push(Declaration {
+            name: name.to_string(),
+            image_info: *image_info,
+            aspect_mask,
+            passes,
+        });
+        TransientImage(self.declarations.len() - 1)
+    }
+
+    // Creates the declared images, those of disjoint pass ranges sharing memory blocks. Previous
+    // images are destroyed, the GPU must be done with them.
+    pub fn build(&mut self) -> Result<()> {
+        self.destroy();
+        let device = self.context.device();
+        for declaration in &self.declarations {
+            let image = unsafe { device.create_image(&declaration.image_info, None)? };
+            self.handles.push(image);
+        }
+        let requirements = self
+            .handles
+            .iter()
+            .map(|&image| unsafe { device.get_image_memory_requirements(image) })
+            .collect::<Vec<_>>();
+
+        // Largest first, in the first block compatible with their memory types and lifetime.
+        let mut order = (0..self.declarations.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&index| std::cmp::Reverse(requirements[index].size));
+        let mut blocks: Vec<MemoryBlock> = Vec::new();
+        let mut image_blocks = vec![0; self.declarations.len()];
+        for index in order {
+            let passes = &self.declarations[index].passes;
+            let image_requirements = requirements[index];
+            let block = blocks.iter().position(|block| {
+                block.requirements.memory_type_bits & image_requirements.memory_type_bits != 0
+                    && block
+                        .passes
+                        .iter()
+                        .all(|other| other.end() < passes.start() || passes.end() < other.start())
+            });
+            image_blocks[index] = match block {
+                Some(block_index) => {
+                    let block = &mut blocks[block_index];
+                    let block_requirements = &mut block.requirements;
+                    block_requirements.size = block_requirements.size.max(image_requirements.size);
+                    block_requirements.alignment = block_requirements
+                        .alignment
+                        .max(image_requirements.alignment);
+                    block_requirements.memory_type_bits &= image_requirements.memory_type_bits;
+                    block.passes.push(passes.clone());
+                    block_index
+                }
+                None => {
+                    blocks.push(MemoryBlock {
+                        requirements: image_requirements,
+                        passes: vec![passes.clone()],
+                    });
+                    blocks.len() - 1
+                }
+            };
+        }
+
+        for (index, block) in blocks.iter().enumerate() {
+            let allocation = self.context.allocate_memory(&AllocationCreateDesc {
+                name: &format!("TransientImagePool[{}]", index),
+                requirements: block.requirements,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+            })?;
+            self.allocations.push(allocation);
+        }
+        for (index, declaration) in self.declarations.iter().enumerate() {
+            let image = self.handles[index];
+            let allocation = &self.allocations[image_blocks[index]];
+            unsafe { device.bind_image_memory(image, allocation.memory(), allocation.offset())? };
+            self.images.push(Image2d::with_bound_memory(
+                self.context.shared().clone(),
+                image,
+                &declaration.image_info,
+                declaration.aspect_mask,
+                declaration.image_info.mip_levels,
+                &declaration.name,
+                None,
+            )?);
+        }
+        Ok(())
+    }
+
+    // Sets the extent of all the declared images and builds the pool again, e.g. on window
+    // resize.
+    pub fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        for declaration in &mut self.declarations {
+            declaration.image_info.extent.width = extent.width;
+            declaration.image_info.extent.height = extent.height;
+        }
+        self.build()
+    }
+
+    pub fn get(&self, image: TransientImage) -> &Image2d {
+        &self.images[image.0]
+    }
+
+    pub fn get_mut(&mut self, image: TransientImage) -> &mut Image2d {
+        &mut self.images[image.0]
+    }
+
+    // Begins the lifetime of `image` in its first pass: discards its contents and transitions it
+    // to `layout`, after the accesses of the images previously aliasing its memory.
+    pub fn cmd_begin(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        image: TransientImage,
+        layout: vk::ImageLayout,
+    ) {
+        let aspect_mask = self.declarations[image.0].aspect_mask;
+        let image = &mut self.images[image.0];
+        let barrier = ImageBarrier2::new(image.handle(), aspect_mask)
+            .layouts(vk::ImageLayout::UNDEFINED, layout)
+            .src(
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                vk::AccessFlags2::MEMORY_WRITE,
+            );
+        self.context
+            .cmd_pipeline_barrier2(cmd, &[], &[], &[barrier]);
+        image.set_layout(layout);
+    }
+
+    // Bytes of the memory blocks, at most those of the declared images.
+    pub fn allocated_size(&self) -> vk::DeviceSize {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.size())
+            .sum()
+    }
+
+    fn destroy(&mut self) {
+        self.images.clear();
+        unsafe {
+            for image in self.handles.drain(..) {
+                self.context.device().destroy_image(image, None);
+            }
+        }
+        for allocation in self.allocations.drain(..) {
+            self.context.free_memory(allocation).unwrap();
+        }
+    }
+}
+
+impl Drop for TransientImagePool {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
@@ -0,0 +1,689 @@
+use crate::{
+    is_srgb_format, Context, DescriptorSetInfo, DescriptorSetLayout, DescriptorSetLayoutInfo,
+    Resource, SamplerInfo, Shader, Texture2d, TransientBufferAllocator,
+};
+use ash::vk;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::Arc;
+use std::time::Duration;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+pub use egui;
+
+const UI_VERT: &str = "
+#version 450
+layout(location = 0) in vec2 in_pos;
+layout(location = 1) in vec2 in_uv;
+layout(location = 2) in vec4 in_color;
+layout(location = 0) out vec4 color;
+layout(location = 1) out vec2 uv;
+layout(push_constant) uniform Params {
+    vec2 screen_size; // In points.
+    uint srgb_target;
+} p;
+void main() {
+    color = in_color;
+    uv = in_uv;
+    gl_Position = vec4(2.0 * in_pos / p.screen_size - 1.0, 0.0, 1.0);
+}
+";
+
+const UI_FRAG: &str = "
+#version 450
+layout(location = 0) in vec4 color;
+layout(location = 1) in vec2 uv;
+layout(location = 0) out vec4 out_color;
+layout(set = 0, binding = 0) uniform sampler2D ui_texture;
+layout(push_constant) uniform Params {
+    vec2 screen_size;
+    uint srgb_target;
+} p;
+vec3 linear_from_srgb(vec3 srgb) {
+    vec3 lower = srgb / 12.92;
+    vec3 higher = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(higher, lower, lessThan(srgb, vec3(0.04045)));
+}
+void main() {
+    // Colors and textures are premultiplied sRGB, blended as is on UNORM targets.
+    out_color = color * texture(ui_texture, uv);
+    if (p.srgb_target != 0) {
+        out_color.rgb = linear_from_srgb(out_color.rgb);
+    }
+}
+";
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UiParams {
+    screen_size: glam::Vec2,
+    srgb_target: u32,
+    padding: u32,
+}
+
+// Scroll distance in points of a mouse wheel line.
+const SCROLL_LINE_HEIGHT: f32 = 50.0;
+
+enum UiTexture {
+    // Texture of egui (e.g. the font atlas), with the pixels kept for partial updates. Boxed,
+    // being much larger than the other variant.
+    Managed {
+        texture: Box<Texture2d>,
+        size: [usize; 2],
+        pixels: Vec<egui::Color32>,
+    },
+    // Image of the application, see UiPass::register_texture.
+    User(vk::DescriptorImageInfo),
+}
+
+struct UiDraw {
+    clip_rect: egui::Rect,
+    texture_id: egui::TextureId,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+fn egui_key(key: VirtualKeyCode) -> Option<egui::Key> {
+    use egui::Key;
+    Some(match key {
+        VirtualKeyCode::Down => Key::ArrowDown,
+        VirtualKeyCode::Left => Key::ArrowLeft,
+        VirtualKeyCode::Right => Key::ArrowRight,
+        VirtualKeyCode::Up => Key::ArrowUp,
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+        VirtualKeyCode::Space => Key::Space,
+        VirtualKeyCode::Insert => Key::Insert,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::Key0 | VirtualKeyCode::Numpad0 => Key::Num0,
+        VirtualKeyCode::Key1 | VirtualKeyCode::Numpad1 => Key::Num1,
+        VirtualKeyCode::Key2 | VirtualKeyCode::Numpad2 => Key::Num2,
+        VirtualKeyCode::Key3 | VirtualKeyCode::Numpad3 => Key::Num3,
+        VirtualKeyCode::Key4 | VirtualKeyCode::Numpad4 => Key::Num4,
+        VirtualKeyCode::Key5 | VirtualKeyCode::Numpad5 => Key::Num5,
+        VirtualKeyCode::Key6 | VirtualKeyCode::Numpad6 => Key::Num6,
+        VirtualKeyCode::Key7 | VirtualKeyCode::Numpad7 => Key::Num7,
+        VirtualKeyCode::Key8 | VirtualKeyCode::Numpad8 => Key::Num8,
+        VirtualKeyCode::Key9 | VirtualKeyCode::Numpad9 => Key::Num9,
+        VirtualKeyCode::A => Key::A,
+        VirtualKeyCode::B => Key::B,
+        VirtualKeyCode::C => Key::C,
+        VirtualKeyCode::D => Key::D,
+        VirtualKeyCode::E => Key::E,
+        VirtualKeyCode::F => Key::F,
+        VirtualKeyCode::G => Key::G,
+        VirtualKeyCode::H => Key::H,
+        VirtualKeyCode::I => Key::I,
+        VirtualKeyCode::J => Key::J,
+        VirtualKeyCode::K => Key::K,
+        VirtualKeyCode::L => Key::L,
+        VirtualKeyCode::M => Key::M,
+        VirtualKeyCode::N => Key::N,
+        VirtualKeyCode::O => Key::O,
+        VirtualKeyCode::P => Key::P,
+        VirtualKeyCode::Q => Key::Q,
+        VirtualKeyCode::R => Key::R,
+        VirtualKeyCode::S => Key::S,
+        VirtualKeyCode::T => Key::T,
+        VirtualKeyCode::U => Key::U,
+        VirtualKeyCode::V => Key::V,
+        VirtualKeyCode::W => Key::W,
+        VirtualKeyCode::X => Key::X,
+        VirtualKeyCode::Y => Key::Y,
+        VirtualKeyCode::Z => Key::Z,
+        _ => return None,
+    })
+}
+
+// egui drawn over the frame. Window events are forwarded from the window_event callback with
+// handle_event, the UI is built once per frame with run and drawn with cmd_draw in the overlay
+// pass (AppRenderer::begin_overlay_pass). Vertices and indices are written to the renderer
+// transient buffer, textures are uploaded during run.
+pub struct UiPass {
+    context: Arc<Context>,
+    egui: egui::Context,
+    raw_input: egui::RawInput,
+    pointer_pos: egui::Pos2,
+    modifiers: egui::Modifiers,
+    pixels_per_point: f32,
+    // Physical size of the window.
+    screen_size: glam::Vec2,
+    srgb_target: bool,
+    textures: HashMap<egui::TextureId, UiTexture>,
+    next_user_texture: u64,
+    primitives: Vec<egui::ClippedPrimitive>,
+    // Textures freed or replaced since the last draw, then kept alive until their frame is
+    // recycled.
+    pending_free: Vec<egui::TextureId>,
+    replaced: Vec<Texture2d>,
+    retired: Vec<Vec<Texture2d>>,
+    desc_set_layout: DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl UiPass {
+    // `render_pass` is the overlay render pass, of the swapchain `color_format`.
+    pub fn new(
+        context: Arc<Context>,
+        render_pass: vk::RenderPass,
+        color_format: vk::Format,
+        screen_size: glam::Vec2,
+        pixels_per_point: f32,
+        frames_count: usize,
+    ) -> Self {
+        let vert = Shader::from_source(
+            context.clone(),
+            UI_VERT,
+            "ui.vert",
+            vk::ShaderStageFlags::VERTEX,
+        )
+        .expect("Unable to compile ui shader");
+        let frag = Shader::from_source(
+            context.clone(),
+            UI_FRAG,
+            "ui.frag",
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+        .expect("Unable to compile ui shader");
+        let entry_name = CString::new("main").unwrap();
+        let stages = [
+            vert.get_create_info(&entry_name),
+            frag.get_create_info(&entry_name),
+        ];
+
+        let desc_set_layout = DescriptorSetLayout::new(
+            context.clone(),
+            DescriptorSetLayoutInfo::default()
+                .binding(
+                    0,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    vk::ShaderStageFlags::FRAGMENT,
+                )
+                .name("UiTextures".to_string()),
+        );
+        let set_layouts = [desc_set_layout.handle()];
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .size(std::mem::size_of::<UiParams>() as u32)
+            .build()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            context
+                .device()
+                .create_pipeline_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let binding_descriptions = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<egui::epaint::Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let attribute_descriptions = [
+            (vk::Format::R32G32_SFLOAT, 0),
+            (vk::Format::R32G32_SFLOAT, 8),
+            (vk::Format::R8G8B8A8_UNORM, 16),
+        ]
+        .iter()
+        .enumerate()
+        .map(
+            |(location, (format, offset))| vk::VertexInputAttributeDescription {
+                location: location as u32,
+                binding: 0,
+                format: *format,
+                offset: *offset,
+            },
+        )
+        .collect::<Vec<_>>();
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&binding_descriptions)
+            .vertex_attribute_descriptions(&attribute_descriptions);
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+            scissor_count: 1,
+            viewport_count: 1,
+            ..Default::default()
+        };
+        // egui triangles have no consistent winding.
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            ..Default::default()
+        };
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo::default();
+        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+            blend_enable: vk::TRUE,
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_DST_ALPHA,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachment_states);
+        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+
+        let create_infos = [vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state_info)
+            .input_assembly_state(&vertex_input_assembly_state_info)
+            .viewport_state(&viewport_state_info)
+            .rasterization_state(&rasterization_info)
+            .multisample_state(&multisample_state_info)
+            .depth_stencil_state(&depth_state_info)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state_info)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .build()];
+        let pipeline = unsafe {
+            context
+                .device()
+                .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
+                .expect("Unable to create ui pipeline")[0]
+        };
+
+        UiPass {
+            context,
+            egui: egui::Context::default(),
+            raw_input: egui::RawInput::default(),
+            pointer_pos: egui::Pos2::ZERO,
+            modifiers: egui::Modifiers::default(),
+            pixels_per_point,
+            screen_size,
+            srgb_target: is_srgb_format(color_format),
+            textures: HashMap::new(),
+            next_user_texture: 0,
+            primitives: Vec::new(),
+            pending_free: Vec::new(),
+            replaced: Vec::new(),
+            retired: (0..frames_count).map(|_| Vec::new()).collect(),
+            desc_set_layout,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn egui(&self) -> &egui::Context {
+        &self.egui
+    }
+
+    // Returns true when egui uses the event, e.g. a click on a window or a key typed in a text
+    // field, which the application should then ignore.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        let events = &mut self.raw_input.events;
+        match event {
+            WindowEvent::Resized(size) => {
+                self.screen_size = glam::vec2(size.width as f32, size.height as f32);
+                false
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.pixels_per_point = *scale_factor as f32;
+                false
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                let mac = cfg!(target_os = "macos");
+                self.modifiers = egui::Modifiers {
+                    alt: state.alt(),
+                    ctrl: state.ctrl(),
+                    shift: state.shift(),
+                    mac_cmd: mac && state.logo(),
+                    command: if mac { state.logo() } else { state.ctrl() },
+                };
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer_pos = egui::pos2(
+                    position.x as f32 / self.pixels_per_point,
+                    position.y as f32 / self.pixels_per_point,
+                );
+                events.push(egui::Event::PointerMoved(self.pointer_pos));
+                self.egui.is_using_pointer()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Other(_) => return false,
+                };
+                events.push(egui::Event::PointerButton {
+                    pos: self.pointer_pos,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+                self.egui.wants_pointer_input()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => egui::vec2(*x, *y) * SCROLL_LINE_HEIGHT,
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        egui::vec2(delta.x as f32, delta.y as f32) / self.pixels_per_point
+                    }
+                };
+                events.push(egui::Event::Scroll(delta));
+                self.egui.wants_pointer_input()
+            }
+            WindowEvent::ReceivedCharacter(character) => {
+                // Control characters are sent as keys.
+                if character.is_control() || (self.modifiers.command && !self.modifiers.alt) {
+                    return self.egui.wants_keyboard_input();
+                }
+                events.push(egui::Event::Text(character.to_string()));
+                self.egui.wants_keyboard_input()
+            }
+            WindowEvent::KeyboardInput { input, .. } => {
+                let key = match input.virtual_keycode.and_then(egui_key) {
+                    Some(key) => key,
+                    None => return self.egui.wants_keyboard_input(),
+                };
+                let pressed = input.state == ElementState::Pressed;
+                if pressed && self.modifiers.command {
+                    match key {
+                        egui::Key::C => events.push(egui::Event::Copy),
+                        egui::Key::X => events.push(egui::Event::Cut),
+                        _ => {}
+                    }
+                }
+                events.push(egui::Event::Key {
+                    key,
+                    pressed,
+                    modifiers: self.modifiers,
+                });
+                self.egui.wants_keyboard_input()
+            }
+            _ => false,
+        }
+    }
+
+    // Builds the UI of this frame with the accumulated events. `time` is the application time,
+    // e.g. App::elapsed_time. The returned output holds the cursor icon and the copied text.
+    pub fn run(
+        &mut self,
+        time: Duration,
+        build_ui: impl FnOnce(&egui::Context),
+    ) -> egui::PlatformOutput {
+        let mut raw_input = std::mem::take(&mut self.raw_input);
+        raw_input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(self.screen_size.x, self.screen_size.y) / self.pixels_per_point,
+        ));
+        raw_input.pixels_per_point = Some(self.pixels_per_point);
+        raw_input.max_texture_side = Some(self.context.limits().max_image_dimension_2d as usize);
+        raw_input.time = Some(time.as_secs_f64());
+        raw_input.modifiers = self.modifiers;
+        let output = self.egui.run(raw_input, build_ui);
+        for (id, delta) in output.textures_delta.set {
+            self.set_texture(id, delta);
+        }
+        self.pending_free.extend(output.textures_delta.free);
+        self.primitives = self.egui.tessellate(output.shapes);
+        output.platform_output
+    }
+
+    // Shows an image of the application in the UI (e.g. with egui::Image), until
+    // unregister_texture. The image must stay alive and in the descriptor layout while drawn.
+    pub fn register_texture(&mut self, image_info: vk::DescriptorImageInfo) -> egui::TextureId {
+        let id = egui::TextureId::User(self.next_user_texture);
+        self.next_user_texture += 1;
+        self.textures.insert(id, UiTexture::User(image_info));
+        id
+    }
+
+    pub fn unregister_texture(&mut self, id: egui::TextureId) {
+        self.textures.remove(&id);
+    }
+
+    fn set_texture(&mut self, id: egui::TextureId, delta: egui::epaint::ImageDelta) {
+        let (delta_size, delta_pixels) = match delta.image {
+            egui::ImageData::Color(image) => (image.size, image.pixels),
+            egui::ImageData::Font(image) => (image.size, image.srgba_pixels(1.0).collect()),
+        };
+        let (size, pixels) = match (delta.pos, self.textures.remove(&id)) {
+            (
+                Some(pos),
+                Some(UiTexture::Managed {
+                    texture,
+                    size,
+                    mut pixels,
+                }),
+            ) => {
+                // Partial update: the patch is copied to the kept pixels and the whole texture
+                // uploaded again.
+                for row in 0..delta_size[1] {
+                    let source = row * delta_size[0];
+                    let target = (pos[1] + row) * size[0] + pos[0];
+                    pixels[target..target + delta_size[0]]
+                        .copy_from_slice(&delta_pixels[source..source + delta_size[0]]);
+                }
+                self.replaced.push(*texture);
+                (size, pixels)
+            }
+            (_, previous) => {
+                if let Some(UiTexture::Managed { texture, .. }) = previous {
+                    self.replaced.push(*texture);
+                }
+                (delta_size, delta_pixels)
+            }
+        };
+        let data = pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_array())
+            .collect::<Vec<_>>();
+        let texture = Texture2d::from_rgba8(
+            self.context.clone(),
+            "UiTexture",
+            (size[0] as u32, size[1] as u32),
+            &data,
+            SamplerInfo::default()
+                .clamp_to_edge()
+                .no_anisotropy()
+                .max_lod(0.0),
+        );
+        match texture {
+            Ok(texture) => {
+                self.textures.insert(
+                    id,
+                    UiTexture::Managed {
+                        texture: Box::new(texture),
+                        size,
+                        pixels,
+                    },
+                );
+            }
+            Err(error) => println!("Failed to create ui texture: {}", error),
+        }
+    }
+
+    // Draws the UI built by the last run, inside the overlay pass of `frame_index`.
+    pub fn cmd_draw(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        frame_index: usize,
+        extent: vk::Extent2D,
+        transient: &mut TransientBufferAllocator,
+    ) {
+        self.record_draws(cmd, extent, transient);
+        let mut retired = std::mem::take(&mut self.replaced);
+        for id in std::mem::take(&mut self.pending_free) {
+            if let Some(UiTexture::Managed { texture, .. }) = self.textures.remove(&id) {
+                retired.push(*texture);
+            }
+        }
+        // Drops the textures retired by the previous submission of this frame, which completed.
+        self.retired[frame_index] = retired;
+    }
+
+    fn record_draws(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        transient: &mut TransientBufferAllocator,
+    ) {
+        let mut vertices = Vec::<egui::epaint::Vertex>::new();
+        let mut indices = Vec::<u32>::new();
+        let mut draws = Vec::<UiDraw>::new();
+        for primitive in &self.primitives {
+            match &primitive.primitive {
+                egui::epaint::Primitive::Mesh(mesh) => {
+                    if mesh.indices.is_empty() {
+                        continue;
+                    }
+                    draws.push(UiDraw {
+                        clip_rect: primitive.clip_rect,
+                        texture_id: mesh.texture_id,
+                        first_index: indices.len() as u32,
+                        index_count: mesh.indices.len() as u32,
+                        vertex_offset: vertices.len() as i32,
+                    });
+                    vertices.extend_from_slice(&mesh.vertices);
+                    indices.extend_from_slice(&mesh.indices);
+                }
+                egui::epaint::Primitive::Callback(_) => {}
+            }
+        }
+        if draws.is_empty() {
+            return;
+        }
+        let vertex_slice = transient.push(&vertices);
+        let index_slice = transient.push(&indices);
+
+        let screen_size = self.screen_size / self.pixels_per_point;
+        let params = UiParams {
+            screen_size,
+            srgb_target: self.srgb_target as u32,
+            padding: 0,
+        };
+        let device = self.context.device();
+        unsafe {
+            let bytes = std::slice::from_raw_parts(
+                &params as *const UiParams as *const u8,
+                std::mem::size_of::<UiParams>(),
+            );
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_set_viewport(
+                cmd,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytes,
+            );
+            device.cmd_bind_vertex_buffers(cmd, 0, &[vertex_slice.buffer], &[vertex_slice.offset]);
+            device.cmd_bind_index_buffer(
+                cmd,
+                index_slice.buffer,
+                index_slice.offset,
+                vk::IndexType::UINT32,
+            );
+        }
+        for draw in draws {
+            let image_info = match self.textures.get(&draw.texture_id) {
+                Some(UiTexture::Managed { texture, .. }) => texture.get_descriptor_info(),
+                Some(UiTexture::User(image_info)) => *image_info,
+                None => continue,
+            };
+            // Clip rectangle in pixels, clamped to the framebuffer.
+            let min =
+                glam::vec2(draw.clip_rect.min.x, draw.clip_rect.min.y) * self.pixels_per_point;
+            let max =
+                glam::vec2(draw.clip_rect.max.x, draw.clip_rect.max.y) * self.pixels_per_point;
+            let size = glam::vec2(extent.width as f32, extent.height as f32);
+            let min = min.round().clamp(glam::Vec2::ZERO, size);
+            let max = max.round().clamp(min, size);
+            if max.x <= min.x || max.y <= min.y {
+                continue;
+            }
+            let desc_set = self
+                .desc_set_layout
+                .get_or_create(DescriptorSetInfo::default().image(0, image_info));
+            unsafe {
+                device.cmd_set_scissor(
+                    cmd,
+                    0,
+                    &[vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: min.x as i32,
+                            y: min.y as i32,
+                        },
+                        extent: vk::Extent2D {
+                            width: (max.x - min.x) as u32,
+                            height: (max.y - min.y) as u32,
+                        },
+                    }],
+                );
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    0,
+                    &[desc_set.handle()],
+                    &[],
+                );
+                device.cmd_draw_indexed(
+                    cmd,
+                    draw.index_count,
+                    1,
+                    draw.first_index,
+                    draw.vertex_offset,
+                    0,
+                );
+            }
+        }
+    }
+}
+
+impl Resource<vk::Pipeline> for UiPass {
+    fn handle(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Drop for UiPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().destroy_pipeline(self.pipeline, None);
+            self.context
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
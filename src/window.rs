@@ -1,6 +1,6 @@
 use ash::{extensions::khr::Surface, vk};
 use glam::Vec2;
-use winit::{event_loop::EventLoop, window::WindowBuilder};
+use winit::{event_loop::EventLoopWindowTarget, window::WindowBuilder};
 pub struct Window {
     handle: winit::window::Window,
     surface_loader: Option<Surface>,
@@ -12,7 +12,7 @@ impl Window {
         width: u32,
         height: u32,
         title: S,
-        event_loop: &EventLoop<()>,
+        event_loop: &EventLoopWindowTarget<()>,
     ) -> Self {
         let window = WindowBuilder::new()
             .with_title(title)
@@ -74,15 +74,20 @@ impl Window {
             .unwrap()
     }
 
-    pub unsafe fn get_surface_format(
+    /// In the order of preference of the implementation, see select_surface_format.
+    ///
+    /// # Safety
+    ///
+    /// The surface must have been created and `physical_device` must support it.
+    pub unsafe fn get_surface_formats(
         &self,
         physical_device: vk::PhysicalDevice,
-    ) -> vk::SurfaceFormatKHR {
+    ) -> Vec<vk::SurfaceFormatKHR> {
         self.surface_loader
             .as_ref()
             .unwrap()
             .get_physical_device_surface_formats(physical_device, self.surface.unwrap())
-            .unwrap()[0]
+            .unwrap()
     }
 
     pub unsafe fn get_surface_present_mode(
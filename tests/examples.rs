@@ -0,0 +1,86 @@
+// Smoke tests running every example for a few frames, see sol::test_harness. The examples render
+// to a window, so these tests need a display and a Vulkan device and are ignored by default (e.g.
+// on CI machines without them). Run them with:
+// cargo test --features harness -- --ignored
+use sol::test_harness::{run_frames, HarnessReport};
+
+#[allow(dead_code)]
+#[path = "../examples/1-cube.rs"]
+mod cube;
+#[allow(dead_code)]
+#[path = "../examples/2-model.rs"]
+mod model;
+#[allow(dead_code)]
+#[path = "../examples/3-ray-debug.rs"]
+mod ray_debug;
+#[allow(dead_code)]
+#[path = "../examples/4-ray-ao.rs"]
+mod ray_ao;
+#[allow(dead_code)]
+#[path = "../examples/5-pathtrace.rs"]
+mod pathtrace;
+
+const FRAMES: u32 = 8;
+
+fn check(name: &str, report: HarnessReport) {
+    assert_eq!(report.frames, FRAMES, "{}: not every frame ran", name);
+    assert_eq!(
+        report.validation_errors, 0,
+        "{}: validation errors, see the output above",
+        name
+    );
+    if let Some(snapshot) = report.snapshot {
+        assert_eq!(
+            snapshot.data.len(),
+            snapshot.row_pitch * snapshot.height as usize,
+            "{}: incomplete snapshot",
+            name
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a display and a Vulkan device"]
+fn cube() {
+    let app = sol::App::build(cube::setup)
+        .prepare(cube::prepare)
+        .render(cube::render);
+    check("1-cube", run_frames(app, FRAMES));
+}
+
+#[test]
+#[ignore = "needs a display and a Vulkan device"]
+fn model() {
+    let app = sol::App::build(model::setup)
+        .prepare(model::prepare)
+        .render(model::render);
+    check("2-model", run_frames(app, FRAMES));
+}
+
+#[test]
+#[ignore = "needs a display and a Vulkan device"]
+fn ray_debug() {
+    let app = sol::App::build(ray_debug::setup)
+        .prepare(ray_debug::prepare)
+        .render(ray_debug::render);
+    check("3-ray-debug", run_frames(app, FRAMES));
+}
+
+#[test]
+#[ignore = "needs a display and a Vulkan device"]
+fn ray_ao() {
+    let app = sol::App::build(ray_ao::setup)
+        .prepare(ray_ao::prepare)
+        .render(ray_ao::render);
+    check("4-ray-ao", run_frames(app, FRAMES));
+}
+
+#[test]
+#[ignore = "needs a display and a Vulkan device"]
+fn pathtrace() {
+    let app = sol::App::build(pathtrace::setup)
+        .prepare(pathtrace::prepare)
+        .update(pathtrace::update)
+        .render(pathtrace::render);
+    check("5-pathtrace", run_frames(app, FRAMES));
+}